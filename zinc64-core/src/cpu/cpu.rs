@@ -2,18 +2,25 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use core::cell::{Cell, RefCell};
 use core::option::Option::{self, Some, None};
 use core::convert::From;
+use core::ptr;
 
 use core::fmt;
 use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
 use log::LogLevel;
 use log::{log, log_enabled, info, trace, debug};
 
 
-use crate::factory::{Addressable, Cpu, Register, TickFn};
-use crate::util::{IoPort, IrqLine, Pin, Shared};
+use crate::factory::{
+    Access, Addressable, Cpu, CpuState, Register, TickFn, TraceFn, TraceRecord, WatchFn,
+};
+use crate::util::{IoPort, IrqLine, Pin, Shared, Snapshot, SnapshotReader};
 
+use super::disasm::disasm;
 use super::uops::{decode_opcode, load_program, MicroOp, MicroOpPair, ProgramId};
 
 pub enum Flag {
@@ -61,7 +68,11 @@ impl Registers {
 pub struct Cpu6510 {
     // Dependencies
     mem: Shared<dyn Addressable>,
+    // Configuration
+    magic: u8,
+    io_port_enabled: bool,
     // Runtime State
+    state: CpuState,
     regs: Registers,
     opcode: u8,
     uops: &'static [MicroOpPair],
@@ -72,6 +83,15 @@ pub struct Cpu6510 {
     page_cross: bool,
     last_nmi: bool,
     last_pc: u16,
+    nmi_pending: bool,
+    pending_interrupt: Option<ProgramId>,
+    opcode_pc: Option<u16>,
+    cycles: u64,
+    // Debugging
+    watches: Vec<(u16, Access)>,
+    watch_hit: Cell<Option<(u16, Access)>>,
+    watch_handler: RefCell<Option<WatchFn>>,
+    trace: Option<TraceFn>,
     // I/O
     ba_line: Shared<Pin>,
     io_port: Shared<IoPort>,
@@ -89,6 +109,9 @@ impl Cpu6510 {
     ) -> Self {
         Self {
             mem,
+            magic: 0xee,
+            io_port_enabled: true,
+            state: CpuState::Running,
             regs: Registers::new(),
             opcode: 0,
             uops: load_program(ProgramId::Start),
@@ -99,6 +122,14 @@ impl Cpu6510 {
             page_cross: false,
             last_nmi: false,
             last_pc: 0,
+            nmi_pending: false,
+            pending_interrupt: None,
+            opcode_pc: None,
+            cycles: 0,
+            watches: Vec::new(),
+            watch_hit: Cell::new(None),
+            watch_handler: RefCell::new(None),
+            trace: None,
             ba_line,
             io_port,
             irq_line,
@@ -106,10 +137,32 @@ impl Cpu6510 {
         }
     }
 
+    /// Set the constant ORed into the accumulator by the unstable ANE and LXA opcodes.
+    /// The value varies between chips and with temperature; 0xee is a common choice.
+    pub fn set_magic_constant(&mut self, value: u8) {
+        self.magic = value;
+    }
+
+    /// Enable or disable the on-chip I/O port at $00/$01. With the port disabled these
+    /// addresses go to memory and the core behaves like a plain 6502.
+    pub fn set_io_port_enabled(&mut self, enabled: bool) {
+        self.io_port_enabled = enabled;
+    }
+
     pub fn clock(&mut self) {
-        if self.ba_line.borrow().is_low() {
+        self.cycles = self.cycles.wrapping_add(1);
+        // NMI is edge-triggered, latch the high to low transition even while stalled.
+        let nmi = self.nmi_line.borrow().is_low();
+        if nmi && !self.last_nmi {
+            self.nmi_pending = true;
+        }
+        self.last_nmi = nmi;
+        if self.ba_line.borrow().is_low() || self.state == CpuState::Jammed {
             return;
-        } 
+        }
+        if self.cycle as usize == self.uops.len() - 1 {
+            self.poll_interrupts();
+        }
         let pair = self.uops[self.cycle as usize];
         self.execute(pair.0);
         if let Some(op1) = pair.1 {
@@ -134,6 +187,7 @@ impl Cpu6510 {
             MicroOp::IndirectFetchAdh => self.indirect_fetch_adh(),
             MicroOp::ReadData => self.read_data(),
             MicroOp::ReadDataOrFixAdh => self.read_data_or_fix_adh(),
+            MicroOp::FixAdh => self.fix_adh(),
             MicroOp::WriteData => self.write_data(),
             MicroOp::OpLDA => self.lda(),
             MicroOp::OpLDX => self.ldx(),
@@ -195,44 +249,145 @@ impl Cpu6510 {
             MicroOp::OpSED => self.set_flag(Flag::Decimal),
             MicroOp::OpSEI => self.set_flag(Flag::IntDisable),
             MicroOp::OpNOP => self.nop(),
-            MicroOp::OpANE => self.ane(),
-            MicroOp::OpANX => self.anx(),
             MicroOp::OpALR => self.alr(),
-            MicroOp::OpAXS => self.axs(),
+            MicroOp::OpANC => self.anc(),
+            MicroOp::OpANE => self.ane(),
+            MicroOp::OpARR => self.arr(),
+            MicroOp::OpDCP => self.dcp(),
+            MicroOp::OpISC => self.isc(),
+            MicroOp::OpJAM => self.jam(),
+            MicroOp::OpLAS => self.las(),
             MicroOp::OpLAX => self.lax(),
-            MicroOp::OpLSE => self.lse(),
+            MicroOp::OpLXA => self.lxa(),
+            MicroOp::OpRLA => self.rla(),
+            MicroOp::OpRRA => self.rra(),
+            MicroOp::OpSAX => self.sax(),
+            MicroOp::OpSBX => self.sbx(),
+            MicroOp::OpSHA => self.sha(),
+            MicroOp::OpSHX => self.shx(),
+            MicroOp::OpSHY => self.shy(),
+            MicroOp::OpSLO => self.slo(),
+            MicroOp::OpSRE => self.sre(),
+            MicroOp::OpTAS => self.tas(),
             MicroOp::OpIRQ => self.irq(),
             MicroOp::OpNMI => self.nmi(),
             MicroOp::OpRST => self.rst(),
         }
     }
 
+    /// Interrupts are sampled before the last cycle of an instruction executes, so
+    /// flag changes made by CLI/SEI only take effect after the next instruction.
+    fn poll_interrupts(&mut self) {
+        self.pending_interrupt = if self.nmi_pending {
+            Some(ProgramId::Nmi)
+        } else if self.irq_line.borrow().is_low() && !self.test_flag(Flag::IntDisable) {
+            Some(ProgramId::Irq)
+        } else {
+            None
+        };
+    }
+
     fn load_next_program(&mut self) {
-        if self.nmi_line.borrow().is_low() {
-            if log_enabled!(LogLevel::Trace) {
-                trace!(target: "cpu::int", "IRQ");
+        match self.pending_interrupt.take() {
+            Some(ProgramId::Nmi) => {
+                if log_enabled!(LogLevel::Trace) {
+                    trace!(target: "cpu::int", "NMI");
+                }
+                self.nmi_pending = false;
+                self.uops = load_program(ProgramId::Nmi);
+                self.opcode_pc = None;
             }
-            self.uops = load_program(ProgramId::Nmi);
-        } else if self.irq_line.borrow().is_low() && !self.test_flag(Flag::IntDisable) {
-            if log_enabled!(LogLevel::Trace) {
-                trace!(target: "cpu::int", "NMI");
+            Some(program) => {
+                if log_enabled!(LogLevel::Trace) {
+                    trace!(target: "cpu::int", "IRQ");
+                }
+                self.uops = load_program(program);
+                self.opcode_pc = None;
+            }
+            None => {
+                self.fetch_opcode();
             }
-            self.uops = load_program(ProgramId::Irq);
-        } else {
-            self.fetch_opcode();
         }
         self.cycle = 0;
     }
 
+    /// Snapshots identify the running program by its id, 0 refers to the program
+    /// decoded from the current opcode.
+    fn encode_program(uops: &'static [MicroOpPair]) -> u8 {
+        ProgramId::ALL
+            .iter()
+            .position(|id| ptr::eq(uops, load_program(*id)))
+            .map_or(0, |i| i as u8 + 1)
+    }
+
+    fn decode_program(value: u8) -> Result<Option<ProgramId>, String> {
+        match value {
+            0 => Ok(None),
+            _ => match ProgramId::ALL.get(value as usize - 1) {
+                Some(id) => Ok(Some(*id)),
+                None => Err(format!("invalid cpu program {}", value)),
+            },
+        }
+    }
+
+    /// Select the vector fetched by BRK and IRQ. An NMI occurring before the vector
+    /// fetch hijacks the sequence.
+    fn fetch_vector_lo(&mut self) {
+        self.address_hi = 0xff;
+        self.address_lo = if self.nmi_pending {
+            self.nmi_pending = false;
+            0xfa
+        } else {
+            0xfe
+        };
+        let pcl = self.read_mem(make_address(self.address_hi, self.address_lo));
+        self.regs.pc = u16::from(pcl);
+    }
+
+    fn fetch_vector_hi(&mut self) {
+        let pch = self.read_mem(make_address(self.address_hi, self.address_lo + 1));
+        self.regs.pc = make_address(pch, self.regs.pc as u8);
+        self.set_flag(Flag::IntDisable);
+    }
+
     fn fetch_opcode(&mut self) {
+        self.opcode_pc = Some(self.regs.pc);
         self.opcode = self.read_mem(self.regs.pc);
         self.uops = decode_opcode(self.opcode);
         if log_enabled!(LogLevel::Trace) {
             trace!(target: "cpu::ins", "0x{:04x}: {:02x}; {}", self.regs.pc, self.opcode, &self);
         }
+        if self.trace.is_some() {
+            self.emit_trace();
+        }
         self.regs.pc = self.regs.pc.wrapping_add(1);
     }
 
+    #[inline(never)]
+    fn emit_trace(&mut self) {
+        let pc = self.regs.pc;
+        let data = [
+            self.opcode,
+            self.peek_mem(pc.wrapping_add(1)),
+            self.peek_mem(pc.wrapping_add(2)),
+        ];
+        let (disassembly, len) = disasm(&data, pc);
+        let record = TraceRecord {
+            pc,
+            bytes: data[..len].to_vec(),
+            disassembly,
+            a: self.regs.a,
+            x: self.regs.x,
+            y: self.regs.y,
+            sp: self.regs.sp,
+            p: self.regs.p,
+            cycles: self.cycles,
+        };
+        if let Some(ref mut trace) = self.trace {
+            trace(&record);
+        }
+    }
+
     fn fetch_opcode_discard(&mut self) {
         let _ = self.read_mem(self.regs.pc);
     }
@@ -286,6 +441,14 @@ impl Cpu6510 {
         }
     }
 
+    fn fix_adh(&mut self) {
+        let address = make_address(self.address_hi, self.address_lo);
+        let _ = self.read_mem(address);
+        if self.page_cross {
+            self.address_hi = self.address_hi.wrapping_add(1);
+        }
+    }
+
     fn read_data(&mut self) {
         let address = make_address(self.address_hi, self.address_lo);
         self.data = self.read_mem(address);
@@ -431,35 +594,56 @@ impl Cpu6510 {
         let ac = self.regs.a as u16;
         let value = self.data as u16;
         let carry = if self.test_flag(Flag::Carry) { 1 } else { 0 };
-        let temp = if !self.test_flag(Flag::Decimal) {
-            ac.wrapping_add(value).wrapping_add(carry)
+        let temp = ac.wrapping_add(value).wrapping_add(carry);
+        if !self.test_flag(Flag::Decimal) {
+            self.update_flag(
+                Flag::Overflow,
+                (ac ^ value) & 0x80 == 0 && (ac ^ temp) & 0x80 == 0x80,
+            );
+            self.update_flag(Flag::Carry, temp > 0xff);
+            let result = (temp & 0xff) as u8;
+            self.regs.a = result;
+            self.set_nz(result);
         } else {
+            // NMOS 6510 sets Z from the binary sum while N and V are taken from the
+            // intermediate result before the high nibble is adjusted.
             let mut t = (ac & 0x0f) + (value & 0x0f) + carry;
             if t > 0x09 {
                 t += 0x06;
             }
-            t += (ac & 0xf0) + (value & 0xf0);
+            t = if t <= 0x0f {
+                (t & 0x0f) + (ac & 0xf0) + (value & 0xf0)
+            } else {
+                (t & 0x0f) + (ac & 0xf0) + (value & 0xf0) + 0x10
+            };
+            self.update_flag(Flag::Zero, temp & 0xff == 0);
+            self.update_flag(Flag::Negative, t & 0x80 != 0);
+            self.update_flag(
+                Flag::Overflow,
+                (ac ^ value) & 0x80 == 0 && (ac ^ t) & 0x80 == 0x80,
+            );
             if t & 0x01f0 > 0x90 {
                 t += 0x60;
             }
-            t
-        };
-        self.update_flag(
-            Flag::Overflow,
-            (ac ^ value) & 0x80 == 0 && (ac ^ temp) & 0x80 == 0x80,
-        );
-        self.update_flag(Flag::Carry, temp > 0xff);
-        let result = (temp & 0xff) as u8;
-        self.regs.a = result;
-        self.set_nz(result);
+            self.update_flag(Flag::Carry, t & 0x0ff0 > 0xf0);
+            self.regs.a = (t & 0xff) as u8;
+        }
     }
 
     fn sbc(&mut self) {
         let ac = self.regs.a as u16;
         let value = self.data as u16;
         let carry = if self.test_flag(Flag::Carry) { 0 } else { 1 };
-        let temp = if !self.test_flag(Flag::Decimal) {
-            ac.wrapping_sub(value).wrapping_sub(carry)
+        let temp = ac.wrapping_sub(value).wrapping_sub(carry);
+        // NMOS 6510 sets all flags from the binary difference, even in decimal mode.
+        self.update_flag(
+            Flag::Overflow,
+            (ac ^ temp) & 0x80 != 0 && (ac ^ value) & 0x80 == 0x80,
+        );
+        self.update_flag(Flag::Carry, temp < 0x100);
+        self.set_nz((temp & 0xff) as u8);
+        let result = if !self.test_flag(Flag::Decimal) {
+            temp
         } else {
             let mut t = (ac & 0x0f).wrapping_sub(value & 0x0f).wrapping_sub(carry);
             if t & 0x10 != 0 {
@@ -469,18 +653,11 @@ impl Cpu6510 {
                 t = (t & 0x0f) | ((ac & 0xf0).wrapping_sub(value & 0xf0));
             }
             if t & 0x0100 != 0 {
-                t -= 0x60;
+                t = t.wrapping_sub(0x60);
             }
             t
         };
-        self.update_flag(
-            Flag::Overflow,
-            (ac ^ temp) & 0x80 != 0 && (ac ^ value) & 0x80 == 0x80,
-        );
-        self.update_flag(Flag::Carry, temp < 0x100);
-        let result = (temp & 0xff) as u8;
-        self.regs.a = result;
-        self.set_nz(result);
+        self.regs.a = (result & 0xff) as u8;
     }
 
     fn bit(&mut self) {
@@ -690,13 +867,10 @@ impl Cpu6510 {
                 self.write_stack(self.regs.p | (Flag::Break as u8) | (Flag::Reserved as u8));
             }
             5 => {
-                let pcl = self.read_mem(0xfffe);
-                self.regs.pc = u16::from(pcl);
+                self.fetch_vector_lo();
             }
             6 => {
-                let pch = self.read_mem(0xffff);
-                self.regs.pc = make_address(pch, self.regs.pc as u8);
-                self.set_flag(Flag::IntDisable);
+                self.fetch_vector_hi();
             }
             _ => panic!("invalid cycle {}", self.cycle),
         }
@@ -741,6 +915,7 @@ impl Cpu6510 {
                     };
                     self.regs.pc = ea;
                 } else {
+                    self.poll_interrupts();
                     self.load_next_program();
                 }
             }
@@ -750,32 +925,72 @@ impl Cpu6510 {
 
     fn nop(&mut self) {}
 
-    fn ane(&mut self) {
-        let result = self.regs.a & self.regs.x & self.data;
+    fn alr(&mut self) {
+        let value = self.regs.a & self.data;
+        self.update_flag(Flag::Carry, (value & 0x01) != 0);
+        let result = value >> 1;
         self.regs.a = result;
         self.set_nz(result);
     }
 
-    fn anx(&mut self) {
-        let result = self.regs.a & self.data;
+    fn anc(&mut self) {
+        self.and();
+        self.update_flag(Flag::Carry, self.regs.a & 0x80 != 0);
+    }
+
+    fn ane(&mut self) {
+        let result = (self.regs.a | self.magic) & self.regs.x & self.data;
         self.regs.a = result;
-        self.regs.x = result;
         self.set_nz(result);
     }
 
-    fn alr(&mut self) {
+    fn arr(&mut self) {
         let value = self.regs.a & self.data;
-        self.update_flag(Flag::Carry, (value & 0x01) != 0);
-        let result = value >> 1;
+        let carry = if self.test_flag(Flag::Carry) { 0x80 } else { 0x00 };
+        let mut result = (value >> 1) | carry;
+        if !self.test_flag(Flag::Decimal) {
+            self.set_nz(result);
+            self.update_flag(Flag::Carry, result & 0x40 != 0);
+            self.update_flag(Flag::Overflow, ((result >> 6) ^ (result >> 5)) & 0x01 != 0);
+        } else {
+            self.update_flag(Flag::Negative, carry != 0);
+            self.update_flag(Flag::Zero, result == 0);
+            self.update_flag(Flag::Overflow, (result ^ value) & 0x40 != 0);
+            if (value & 0x0f) + (value & 0x01) > 0x05 {
+                result = (result & 0xf0) | (result.wrapping_add(0x06) & 0x0f);
+            }
+            let high_carry = (u16::from(value) + u16::from(value & 0x10)) & 0x01f0 > 0x50;
+            self.update_flag(Flag::Carry, high_carry);
+            if high_carry {
+                result = result.wrapping_add(0x60);
+            }
+        }
         self.regs.a = result;
-        self.set_nz(result);
     }
 
-    fn axs(&mut self) {
-        let result = ((self.regs.a & self.regs.x) as u16).wrapping_sub(self.data as u16);
-        self.update_flag(Flag::Carry, result < 0x100);
-        self.regs.x = (result & 0xff) as u8;
-        self.set_nz((result & 0xff) as u8);
+    fn dcp(&mut self) {
+        self.data = self.data.wrapping_sub(1);
+        self.cmp();
+    }
+
+    fn isc(&mut self) {
+        self.data = self.data.wrapping_add(1);
+        self.sbc();
+    }
+
+    fn jam(&mut self) {
+        if log_enabled!(LogLevel::Trace) {
+            trace!(target: "cpu::ins", "JAM {:02x} at 0x{:04x}", self.opcode, self.get_pc());
+        }
+        self.state = CpuState::Jammed;
+    }
+
+    fn las(&mut self) {
+        let result = self.data & self.regs.sp;
+        self.regs.a = result;
+        self.regs.x = result;
+        self.regs.sp = result;
+        self.set_nz(result);
     }
 
     fn lax(&mut self) {
@@ -785,14 +1000,76 @@ impl Cpu6510 {
         self.set_nz(data);
     }
 
-    fn lse(&mut self) {
-        let value = self.data;
-        self.update_flag(Flag::Carry, (value & 0x01) != 0);
-        let result = self.regs.a ^ (value >> 1);
+    fn lxa(&mut self) {
+        let result = (self.regs.a | self.magic) & self.data;
         self.regs.a = result;
+        self.regs.x = result;
         self.set_nz(result);
-        // tick_fn();
-        // tick_fn();
+    }
+
+    fn rla(&mut self) {
+        self.rol();
+        self.and();
+    }
+
+    fn rra(&mut self) {
+        self.ror();
+        self.adc();
+    }
+
+    fn sax(&mut self) {
+        self.data = self.regs.a & self.regs.x;
+    }
+
+    fn sbx(&mut self) {
+        let result = ((self.regs.a & self.regs.x) as u16).wrapping_sub(self.data as u16);
+        self.update_flag(Flag::Carry, result < 0x100);
+        self.regs.x = (result & 0xff) as u8;
+        self.set_nz((result & 0xff) as u8);
+    }
+
+    fn sha(&mut self) {
+        self.store_and_adh(self.regs.a & self.regs.x);
+    }
+
+    fn shx(&mut self) {
+        self.store_and_adh(self.regs.x);
+    }
+
+    fn shy(&mut self) {
+        self.store_and_adh(self.regs.y);
+    }
+
+    // The unstable stores AND the value with the high byte of the base address plus one.
+    // When indexing crosses a page the result also replaces the high byte of the target
+    // address. On hardware both go away if the VIC steals the bus during the write
+    // cycle, which is not emulated.
+    fn store_and_adh(&mut self, value: u8) {
+        let base_hi = if self.page_cross {
+            self.address_hi.wrapping_sub(1)
+        } else {
+            self.address_hi
+        };
+        let result = value & base_hi.wrapping_add(1);
+        if self.page_cross {
+            self.address_hi = result;
+        }
+        self.data = result;
+    }
+
+    fn slo(&mut self) {
+        self.asl();
+        self.ora();
+    }
+
+    fn sre(&mut self) {
+        self.lsr();
+        self.eor();
+    }
+
+    fn tas(&mut self) {
+        self.regs.sp = self.regs.a & self.regs.x;
+        self.store_and_adh(self.regs.a & self.regs.x);
     }
 
     // -- Interrupts
@@ -809,13 +1086,10 @@ impl Cpu6510 {
                 self.write_stack(self.regs.p & 0xef);
             }
             5 => {
-                let pcl = self.read_mem(0xfffe);
-                self.regs.pc = u16::from(pcl);
+                self.fetch_vector_lo();
             }
             6 => {
-                let pch = self.read_mem(0xffff);
-                self.regs.pc = make_address(pch, self.regs.pc as u8);
-                self.set_flag(Flag::IntDisable);
+                self.fetch_vector_hi();
             }
             _ => panic!("invalid cycle {}", self.cycle),
         }
@@ -840,7 +1114,6 @@ impl Cpu6510 {
                 let pch = self.read_mem(0xfffb);
                 self.regs.pc = make_address(pch, self.regs.pc as u8);
                 self.set_flag(Flag::IntDisable);
-                self.nmi_line.borrow_mut().reset();
             }
             _ => panic!("invalid cycle {}", self.cycle),
         }
@@ -897,23 +1170,50 @@ impl Cpu6510 {
     // -- Memory Ops
 
     pub fn read_mem(&self, address: u16) -> u8 {
+        let value = self.peek_mem(address);
+        if !self.watches.is_empty() {
+            self.check_watch(address, Access::Read, value);
+        }
+        value
+    }
+
+    fn peek_mem(&self, address: u16) -> u8 {
         let value = match address {
-            0x0000 => self.io_port.borrow().get_direction(),
-            0x0001 => self.io_port.borrow().get_value() & 0x3f,
+            0x0000 if self.io_port_enabled => self.io_port.borrow().get_direction(),
+            0x0001 if self.io_port_enabled => self.io_port.borrow().get_value() & 0x3f,
             _ => self.mem.borrow().read(address),
         };
         value
     }
 
     pub fn write_mem(&mut self, address: u16, value: u8) {
+        if !self.watches.is_empty() {
+            self.check_watch(address, Access::Write, value);
+        }
+        self.poke_mem(address, value);
+    }
+
+    fn poke_mem(&mut self, address: u16, value: u8) {
         match address {
-            0x0000 => self.io_port.borrow_mut().set_direction(value),
-            0x0001 => self.io_port.borrow_mut().set_value(value),
+            0x0000 if self.io_port_enabled => self.io_port.borrow_mut().set_direction(value),
+            0x0001 if self.io_port_enabled => self.io_port.borrow_mut().set_value(value),
             _ => {}
         }
         self.mem.borrow_mut().write(address, value);
     }
 
+    fn check_watch(&self, address: u16, access: Access, value: u8) {
+        if self.watches.contains(&(address, access)) {
+            let halt = match *self.watch_handler.borrow_mut() {
+                Some(ref mut handler) => handler(address, access, value),
+                None => true,
+            };
+            if halt && self.watch_hit.get().is_none() {
+                self.watch_hit.set(Some((address, access)));
+            }
+        }
+    }
+
     #[inline]
     pub fn write_stack(&mut self, value: u8) {
         let address = make_address(0x01, self.regs.sp);
@@ -964,12 +1264,17 @@ impl Cpu for Cpu6510 {
     fn get_pc(&self) -> u16 {
         match self.cycle {
             1 => self.regs.pc.wrapping_sub(1),
-            _ => self.regs.pc
+            _ => self.regs.pc,
         }
     }
 
+    fn get_opcode_pc(&self) -> u16 {
+        self.opcode_pc.unwrap_or(self.regs.pc)
+    }
+
     fn set_pc(&mut self, value: u16) {
         self.regs.pc = value;
+        self.opcode_pc = None;
         self.uops = load_program(ProgramId::Start);
         self.cycle = 0;
     }
@@ -978,17 +1283,64 @@ impl Cpu for Cpu6510 {
         self.last_pc == self.get_pc()
     }
 
+    fn get_state(&self) -> CpuState {
+        self.state
+    }
+
     fn step(&mut self, tick_fn: &TickFn) {
         self.last_pc = self.get_pc();
+        self.watch_hit.set(None);
         let mut is_done = false;
         while !is_done {
+            let cycle = self.cycle;
             self.clock();
             tick_fn();
-            is_done = self.cycle == 1;
+            // A cycle stalled by BA leaves the cycle counter unchanged
+            is_done = (self.cycle == 1 && cycle != 1) || self.state == CpuState::Jammed;
+        }
+    }
+
+    fn step_cycle(&mut self, tick_fn: &TickFn) {
+        if self.cycle == 1 {
+            self.last_pc = self.get_pc();
+            self.watch_hit.set(None);
+        }
+        self.clock();
+        tick_fn();
+    }
+
+    fn remaining_cycles(&self) -> u8 {
+        (self.uops.len() - self.cycle as usize) as u8
+    }
+
+    fn add_watch(&mut self, address: u16, access: Access) {
+        if !self.watches.contains(&(address, access)) {
+            self.watches.push((address, access));
         }
     }
 
+    fn remove_watch(&mut self, address: u16, access: Access) {
+        self.watches.retain(|watch| *watch != (address, access));
+    }
+
+    fn has_watches(&self) -> bool {
+        !self.watches.is_empty()
+    }
+
+    fn get_watch_hit(&self) -> Option<(u16, Access)> {
+        self.watch_hit.get()
+    }
+
+    fn set_watch_handler(&mut self, handler: Option<WatchFn>) {
+        *self.watch_handler.borrow_mut() = handler;
+    }
+
+    fn set_trace(&mut self, trace: Option<TraceFn>) {
+        self.trace = trace;
+    }
+
     fn reset(&mut self) {
+        self.state = CpuState::Running;
         self.regs.reset();
         self.address_hi = 0;
         self.address_lo = 0;
@@ -996,24 +1348,90 @@ impl Cpu for Cpu6510 {
         self.page_cross = false;
         self.last_nmi = false;
         self.last_pc = 0;
+        self.nmi_pending = false;
+        self.pending_interrupt = None;
+        self.opcode_pc = None;
+        self.cycles = 0;
         self.io_port.borrow_mut().set_value(0xff);
         self.irq_line.borrow_mut().reset();
         self.nmi_line.borrow_mut().reset();
-        self.write(0x0000, 0b_0010_1111);
-        self.write(0x0001, 0b_0001_1111);
+        if self.io_port_enabled {
+            self.write(0x0000, 0b_0010_1111);
+            self.write(0x0001, 0b_0001_1111);
+        }
         self.opcode = 0;
         self.uops = load_program(ProgramId::Reset);
         self.cycle = 0;
     }
 
+    fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bool(self.state == CpuState::Jammed);
+        snapshot.write_u8(self.regs.a);
+        snapshot.write_u8(self.regs.x);
+        snapshot.write_u8(self.regs.y);
+        snapshot.write_u8(self.regs.sp);
+        snapshot.write_u16(self.regs.pc);
+        snapshot.write_u8(self.regs.p);
+        snapshot.write_u8(self.opcode);
+        snapshot.write_u8(Self::encode_program(self.uops));
+        snapshot.write_u8(self.cycle);
+        snapshot.write_u8(self.address_lo);
+        snapshot.write_u8(self.address_hi);
+        snapshot.write_u8(self.data);
+        snapshot.write_bool(self.page_cross);
+        snapshot.write_bool(self.last_nmi);
+        snapshot.write_u16(self.last_pc);
+        snapshot.write_bool(self.nmi_pending);
+        snapshot.write_u8(self.pending_interrupt.map_or(0, |id| id as u8 + 1));
+        snapshot.write_bool(self.opcode_pc.is_some());
+        snapshot.write_u16(self.opcode_pc.unwrap_or(0));
+        self.io_port.borrow().save_state(snapshot);
+    }
+
+    fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.state = if snapshot.read_bool()? {
+            CpuState::Jammed
+        } else {
+            CpuState::Running
+        };
+        self.regs.a = snapshot.read_u8()?;
+        self.regs.x = snapshot.read_u8()?;
+        self.regs.y = snapshot.read_u8()?;
+        self.regs.sp = snapshot.read_u8()?;
+        self.regs.pc = snapshot.read_u16()?;
+        self.regs.p = snapshot.read_u8()?;
+        self.opcode = snapshot.read_u8()?;
+        self.uops = match Self::decode_program(snapshot.read_u8()?)? {
+            Some(id) => load_program(id),
+            None => decode_opcode(self.opcode),
+        };
+        self.cycle = snapshot.read_u8()?;
+        if self.cycle as usize > self.uops.len() {
+            return Err(format!("invalid cpu cycle {}", self.cycle));
+        }
+        self.address_lo = snapshot.read_u8()?;
+        self.address_hi = snapshot.read_u8()?;
+        self.data = snapshot.read_u8()?;
+        self.page_cross = snapshot.read_bool()?;
+        self.last_nmi = snapshot.read_bool()?;
+        self.last_pc = snapshot.read_u16()?;
+        self.nmi_pending = snapshot.read_bool()?;
+        self.pending_interrupt = Self::decode_program(snapshot.read_u8()?)?;
+        let has_opcode_pc = snapshot.read_bool()?;
+        let opcode_pc = snapshot.read_u16()?;
+        self.opcode_pc = if has_opcode_pc { Some(opcode_pc) } else { None };
+        self.io_port.borrow_mut().load_state(snapshot)?;
+        Ok(())
+    }
+
     // -- I/O
 
     fn read(&self, address: u16) -> u8 {
-        self.read_mem(address)
+        self.peek_mem(address)
     }
 
     fn write(&mut self, address: u16, value: u8) {
-        self.write_mem(address, value);
+        self.poke_mem(address, value);
     }
 }
 
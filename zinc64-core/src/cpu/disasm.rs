@@ -0,0 +1,376 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::format;
+use alloc::string::String;
+
+// SPEC: http://www.6502.org/tutorials/6502opcodes.html
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl Mode {
+    fn len(self) -> usize {
+        match self {
+            Mode::Implied | Mode::Accumulator => 1,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// Disassemble the instruction at the start of `data` located at `address`. Returns
+/// the instruction text and its length in bytes. Illegal opcodes use their common
+/// mnemonics. Missing operand bytes read as zero.
+pub fn disasm(data: &[u8], address: u16) -> (String, usize) {
+    let byte = |i: usize| data.get(i).cloned().unwrap_or(0);
+    let (mnemonic, mode) = decode(byte(0));
+    let lo = byte(1);
+    let word = u16::from(lo) | (u16::from(byte(2)) << 8);
+    let text = match mode {
+        Mode::Implied => String::from(mnemonic),
+        Mode::Accumulator => format!("{} A", mnemonic),
+        Mode::Immediate => format!("{} #${:02X}", mnemonic, lo),
+        Mode::ZeroPage => format!("{} ${:02X}", mnemonic, lo),
+        Mode::ZeroPageX => format!("{} ${:02X},X", mnemonic, lo),
+        Mode::ZeroPageY => format!("{} ${:02X},Y", mnemonic, lo),
+        Mode::Absolute => format!("{} ${:04X}", mnemonic, word),
+        Mode::AbsoluteX => format!("{} ${:04X},X", mnemonic, word),
+        Mode::AbsoluteY => format!("{} ${:04X},Y", mnemonic, word),
+        Mode::Indirect => format!("{} (${:04X})", mnemonic, word),
+        Mode::IndirectX => format!("{} (${:02X},X)", mnemonic, lo),
+        Mode::IndirectY => format!("{} (${:02X}),Y", mnemonic, lo),
+        Mode::Relative => {
+            let target = address.wrapping_add(2).wrapping_add(lo as i8 as u16);
+            format!("{} ${:04X}", mnemonic, target)
+        }
+    };
+    (text, mode.len())
+}
+
+fn decode(opcode: u8) -> (&'static str, Mode) {
+    match opcode {
+        0x00 => ("BRK", Mode::Implied),
+        0x01 => ("ORA", Mode::IndirectX),
+        0x02 => ("JAM", Mode::Implied),
+        0x03 => ("SLO", Mode::IndirectX),
+        0x04 => ("NOP", Mode::ZeroPage),
+        0x05 => ("ORA", Mode::ZeroPage),
+        0x06 => ("ASL", Mode::ZeroPage),
+        0x07 => ("SLO", Mode::ZeroPage),
+        0x08 => ("PHP", Mode::Implied),
+        0x09 => ("ORA", Mode::Immediate),
+        0x0a => ("ASL", Mode::Accumulator),
+        0x0b => ("ANC", Mode::Immediate),
+        0x0c => ("NOP", Mode::Absolute),
+        0x0d => ("ORA", Mode::Absolute),
+        0x0e => ("ASL", Mode::Absolute),
+        0x0f => ("SLO", Mode::Absolute),
+        0x10 => ("BPL", Mode::Relative),
+        0x11 => ("ORA", Mode::IndirectY),
+        0x12 => ("JAM", Mode::Implied),
+        0x13 => ("SLO", Mode::IndirectY),
+        0x14 => ("NOP", Mode::ZeroPageX),
+        0x15 => ("ORA", Mode::ZeroPageX),
+        0x16 => ("ASL", Mode::ZeroPageX),
+        0x17 => ("SLO", Mode::ZeroPageX),
+        0x18 => ("CLC", Mode::Implied),
+        0x19 => ("ORA", Mode::AbsoluteY),
+        0x1a => ("NOP", Mode::Implied),
+        0x1b => ("SLO", Mode::AbsoluteY),
+        0x1c => ("NOP", Mode::AbsoluteX),
+        0x1d => ("ORA", Mode::AbsoluteX),
+        0x1e => ("ASL", Mode::AbsoluteX),
+        0x1f => ("SLO", Mode::AbsoluteX),
+        0x20 => ("JSR", Mode::Absolute),
+        0x21 => ("AND", Mode::IndirectX),
+        0x22 => ("JAM", Mode::Implied),
+        0x23 => ("RLA", Mode::IndirectX),
+        0x24 => ("BIT", Mode::ZeroPage),
+        0x25 => ("AND", Mode::ZeroPage),
+        0x26 => ("ROL", Mode::ZeroPage),
+        0x27 => ("RLA", Mode::ZeroPage),
+        0x28 => ("PLP", Mode::Implied),
+        0x29 => ("AND", Mode::Immediate),
+        0x2a => ("ROL", Mode::Accumulator),
+        0x2b => ("ANC", Mode::Immediate),
+        0x2c => ("BIT", Mode::Absolute),
+        0x2d => ("AND", Mode::Absolute),
+        0x2e => ("ROL", Mode::Absolute),
+        0x2f => ("RLA", Mode::Absolute),
+        0x30 => ("BMI", Mode::Relative),
+        0x31 => ("AND", Mode::IndirectY),
+        0x32 => ("JAM", Mode::Implied),
+        0x33 => ("RLA", Mode::IndirectY),
+        0x34 => ("NOP", Mode::ZeroPageX),
+        0x35 => ("AND", Mode::ZeroPageX),
+        0x36 => ("ROL", Mode::ZeroPageX),
+        0x37 => ("RLA", Mode::ZeroPageX),
+        0x38 => ("SEC", Mode::Implied),
+        0x39 => ("AND", Mode::AbsoluteY),
+        0x3a => ("NOP", Mode::Implied),
+        0x3b => ("RLA", Mode::AbsoluteY),
+        0x3c => ("NOP", Mode::AbsoluteX),
+        0x3d => ("AND", Mode::AbsoluteX),
+        0x3e => ("ROL", Mode::AbsoluteX),
+        0x3f => ("RLA", Mode::AbsoluteX),
+        0x40 => ("RTI", Mode::Implied),
+        0x41 => ("EOR", Mode::IndirectX),
+        0x42 => ("JAM", Mode::Implied),
+        0x43 => ("SRE", Mode::IndirectX),
+        0x44 => ("NOP", Mode::ZeroPage),
+        0x45 => ("EOR", Mode::ZeroPage),
+        0x46 => ("LSR", Mode::ZeroPage),
+        0x47 => ("SRE", Mode::ZeroPage),
+        0x48 => ("PHA", Mode::Implied),
+        0x49 => ("EOR", Mode::Immediate),
+        0x4a => ("LSR", Mode::Accumulator),
+        0x4b => ("ALR", Mode::Immediate),
+        0x4c => ("JMP", Mode::Absolute),
+        0x4d => ("EOR", Mode::Absolute),
+        0x4e => ("LSR", Mode::Absolute),
+        0x4f => ("SRE", Mode::Absolute),
+        0x50 => ("BVC", Mode::Relative),
+        0x51 => ("EOR", Mode::IndirectY),
+        0x52 => ("JAM", Mode::Implied),
+        0x53 => ("SRE", Mode::IndirectY),
+        0x54 => ("NOP", Mode::ZeroPageX),
+        0x55 => ("EOR", Mode::ZeroPageX),
+        0x56 => ("LSR", Mode::ZeroPageX),
+        0x57 => ("SRE", Mode::ZeroPageX),
+        0x58 => ("CLI", Mode::Implied),
+        0x59 => ("EOR", Mode::AbsoluteY),
+        0x5a => ("NOP", Mode::Implied),
+        0x5b => ("SRE", Mode::AbsoluteY),
+        0x5c => ("NOP", Mode::AbsoluteX),
+        0x5d => ("EOR", Mode::AbsoluteX),
+        0x5e => ("LSR", Mode::AbsoluteX),
+        0x5f => ("SRE", Mode::AbsoluteX),
+        0x60 => ("RTS", Mode::Implied),
+        0x61 => ("ADC", Mode::IndirectX),
+        0x62 => ("JAM", Mode::Implied),
+        0x63 => ("RRA", Mode::IndirectX),
+        0x64 => ("NOP", Mode::ZeroPage),
+        0x65 => ("ADC", Mode::ZeroPage),
+        0x66 => ("ROR", Mode::ZeroPage),
+        0x67 => ("RRA", Mode::ZeroPage),
+        0x68 => ("PLA", Mode::Implied),
+        0x69 => ("ADC", Mode::Immediate),
+        0x6a => ("ROR", Mode::Accumulator),
+        0x6b => ("ARR", Mode::Immediate),
+        0x6c => ("JMP", Mode::Indirect),
+        0x6d => ("ADC", Mode::Absolute),
+        0x6e => ("ROR", Mode::Absolute),
+        0x6f => ("RRA", Mode::Absolute),
+        0x70 => ("BVS", Mode::Relative),
+        0x71 => ("ADC", Mode::IndirectY),
+        0x72 => ("JAM", Mode::Implied),
+        0x73 => ("RRA", Mode::IndirectY),
+        0x74 => ("NOP", Mode::ZeroPageX),
+        0x75 => ("ADC", Mode::ZeroPageX),
+        0x76 => ("ROR", Mode::ZeroPageX),
+        0x77 => ("RRA", Mode::ZeroPageX),
+        0x78 => ("SEI", Mode::Implied),
+        0x79 => ("ADC", Mode::AbsoluteY),
+        0x7a => ("NOP", Mode::Implied),
+        0x7b => ("RRA", Mode::AbsoluteY),
+        0x7c => ("NOP", Mode::AbsoluteX),
+        0x7d => ("ADC", Mode::AbsoluteX),
+        0x7e => ("ROR", Mode::AbsoluteX),
+        0x7f => ("RRA", Mode::AbsoluteX),
+        0x80 => ("NOP", Mode::Immediate),
+        0x81 => ("STA", Mode::IndirectX),
+        0x82 => ("NOP", Mode::Immediate),
+        0x83 => ("SAX", Mode::IndirectX),
+        0x84 => ("STY", Mode::ZeroPage),
+        0x85 => ("STA", Mode::ZeroPage),
+        0x86 => ("STX", Mode::ZeroPage),
+        0x87 => ("SAX", Mode::ZeroPage),
+        0x88 => ("DEY", Mode::Implied),
+        0x89 => ("NOP", Mode::Immediate),
+        0x8a => ("TXA", Mode::Implied),
+        0x8b => ("ANE", Mode::Immediate),
+        0x8c => ("STY", Mode::Absolute),
+        0x8d => ("STA", Mode::Absolute),
+        0x8e => ("STX", Mode::Absolute),
+        0x8f => ("SAX", Mode::Absolute),
+        0x90 => ("BCC", Mode::Relative),
+        0x91 => ("STA", Mode::IndirectY),
+        0x92 => ("JAM", Mode::Implied),
+        0x93 => ("SHA", Mode::IndirectY),
+        0x94 => ("STY", Mode::ZeroPageX),
+        0x95 => ("STA", Mode::ZeroPageX),
+        0x96 => ("STX", Mode::ZeroPageY),
+        0x97 => ("SAX", Mode::ZeroPageY),
+        0x98 => ("TYA", Mode::Implied),
+        0x99 => ("STA", Mode::AbsoluteY),
+        0x9a => ("TXS", Mode::Implied),
+        0x9b => ("TAS", Mode::AbsoluteY),
+        0x9c => ("SHY", Mode::AbsoluteX),
+        0x9d => ("STA", Mode::AbsoluteX),
+        0x9e => ("SHX", Mode::AbsoluteY),
+        0x9f => ("SHA", Mode::AbsoluteY),
+        0xa0 => ("LDY", Mode::Immediate),
+        0xa1 => ("LDA", Mode::IndirectX),
+        0xa2 => ("LDX", Mode::Immediate),
+        0xa3 => ("LAX", Mode::IndirectX),
+        0xa4 => ("LDY", Mode::ZeroPage),
+        0xa5 => ("LDA", Mode::ZeroPage),
+        0xa6 => ("LDX", Mode::ZeroPage),
+        0xa7 => ("LAX", Mode::ZeroPage),
+        0xa8 => ("TAY", Mode::Implied),
+        0xa9 => ("LDA", Mode::Immediate),
+        0xaa => ("TAX", Mode::Implied),
+        0xab => ("LXA", Mode::Immediate),
+        0xac => ("LDY", Mode::Absolute),
+        0xad => ("LDA", Mode::Absolute),
+        0xae => ("LDX", Mode::Absolute),
+        0xaf => ("LAX", Mode::Absolute),
+        0xb0 => ("BCS", Mode::Relative),
+        0xb1 => ("LDA", Mode::IndirectY),
+        0xb2 => ("JAM", Mode::Implied),
+        0xb3 => ("LAX", Mode::IndirectY),
+        0xb4 => ("LDY", Mode::ZeroPageX),
+        0xb5 => ("LDA", Mode::ZeroPageX),
+        0xb6 => ("LDX", Mode::ZeroPageY),
+        0xb7 => ("LAX", Mode::ZeroPageY),
+        0xb8 => ("CLV", Mode::Implied),
+        0xb9 => ("LDA", Mode::AbsoluteY),
+        0xba => ("TSX", Mode::Implied),
+        0xbb => ("LAS", Mode::AbsoluteY),
+        0xbc => ("LDY", Mode::AbsoluteX),
+        0xbd => ("LDA", Mode::AbsoluteX),
+        0xbe => ("LDX", Mode::AbsoluteY),
+        0xbf => ("LAX", Mode::AbsoluteY),
+        0xc0 => ("CPY", Mode::Immediate),
+        0xc1 => ("CMP", Mode::IndirectX),
+        0xc2 => ("NOP", Mode::Immediate),
+        0xc3 => ("DCP", Mode::IndirectX),
+        0xc4 => ("CPY", Mode::ZeroPage),
+        0xc5 => ("CMP", Mode::ZeroPage),
+        0xc6 => ("DEC", Mode::ZeroPage),
+        0xc7 => ("DCP", Mode::ZeroPage),
+        0xc8 => ("INY", Mode::Implied),
+        0xc9 => ("CMP", Mode::Immediate),
+        0xca => ("DEX", Mode::Implied),
+        0xcb => ("SBX", Mode::Immediate),
+        0xcc => ("CPY", Mode::Absolute),
+        0xcd => ("CMP", Mode::Absolute),
+        0xce => ("DEC", Mode::Absolute),
+        0xcf => ("DCP", Mode::Absolute),
+        0xd0 => ("BNE", Mode::Relative),
+        0xd1 => ("CMP", Mode::IndirectY),
+        0xd2 => ("JAM", Mode::Implied),
+        0xd3 => ("DCP", Mode::IndirectY),
+        0xd4 => ("NOP", Mode::ZeroPageX),
+        0xd5 => ("CMP", Mode::ZeroPageX),
+        0xd6 => ("DEC", Mode::ZeroPageX),
+        0xd7 => ("DCP", Mode::ZeroPageX),
+        0xd8 => ("CLD", Mode::Implied),
+        0xd9 => ("CMP", Mode::AbsoluteY),
+        0xda => ("NOP", Mode::Implied),
+        0xdb => ("DCP", Mode::AbsoluteY),
+        0xdc => ("NOP", Mode::AbsoluteX),
+        0xdd => ("CMP", Mode::AbsoluteX),
+        0xde => ("DEC", Mode::AbsoluteX),
+        0xdf => ("DCP", Mode::AbsoluteX),
+        0xe0 => ("CPX", Mode::Immediate),
+        0xe1 => ("SBC", Mode::IndirectX),
+        0xe2 => ("NOP", Mode::Immediate),
+        0xe3 => ("ISC", Mode::IndirectX),
+        0xe4 => ("CPX", Mode::ZeroPage),
+        0xe5 => ("SBC", Mode::ZeroPage),
+        0xe6 => ("INC", Mode::ZeroPage),
+        0xe7 => ("ISC", Mode::ZeroPage),
+        0xe8 => ("INX", Mode::Implied),
+        0xe9 => ("SBC", Mode::Immediate),
+        0xea => ("NOP", Mode::Implied),
+        0xeb => ("SBC", Mode::Immediate),
+        0xec => ("CPX", Mode::Absolute),
+        0xed => ("SBC", Mode::Absolute),
+        0xee => ("INC", Mode::Absolute),
+        0xef => ("ISC", Mode::Absolute),
+        0xf0 => ("BEQ", Mode::Relative),
+        0xf1 => ("SBC", Mode::IndirectY),
+        0xf2 => ("JAM", Mode::Implied),
+        0xf3 => ("ISC", Mode::IndirectY),
+        0xf4 => ("NOP", Mode::ZeroPageX),
+        0xf5 => ("SBC", Mode::ZeroPageX),
+        0xf6 => ("INC", Mode::ZeroPageX),
+        0xf7 => ("ISC", Mode::ZeroPageX),
+        0xf8 => ("SED", Mode::Implied),
+        0xf9 => ("SBC", Mode::AbsoluteY),
+        0xfa => ("NOP", Mode::Implied),
+        0xfb => ("ISC", Mode::AbsoluteY),
+        0xfc => ("NOP", Mode::AbsoluteX),
+        0xfd => ("SBC", Mode::AbsoluteX),
+        0xfe => ("INC", Mode::AbsoluteX),
+        0xff => ("ISC", Mode::AbsoluteX),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(data: &[u8], text: &str) {
+        assert_eq!((String::from(text), data.len()), disasm(data, 0x1000));
+    }
+
+    #[test]
+    fn addressing_modes() {
+        check(&[0xea], "NOP");
+        check(&[0x0a], "ASL A");
+        check(&[0xa9, 0x01], "LDA #$01");
+        check(&[0xa5, 0x12], "LDA $12");
+        check(&[0xb5, 0x12], "LDA $12,X");
+        check(&[0xb6, 0x12], "LDX $12,Y");
+        check(&[0xad, 0x34, 0x12], "LDA $1234");
+        check(&[0xbd, 0x34, 0x12], "LDA $1234,X");
+        check(&[0xb9, 0x34, 0x12], "LDA $1234,Y");
+        check(&[0x6c, 0xfc, 0xff], "JMP ($FFFC)");
+        check(&[0xa1, 0x12], "LDA ($12,X)");
+        check(&[0xb1, 0x12], "LDA ($12),Y");
+    }
+
+    #[test]
+    fn relative_targets() {
+        check(&[0xd0, 0x05], "BNE $1007");
+        check(&[0xd0, 0xfe], "BNE $1000");
+        assert_eq!((String::from("BEQ $0001"), 2), disasm(&[0xf0, 0x01], 0xfffe));
+    }
+
+    #[test]
+    fn illegal_opcodes() {
+        check(&[0x02], "JAM");
+        check(&[0x07, 0x12], "SLO $12");
+        check(&[0xa7, 0x12], "LAX $12");
+        check(&[0x8f, 0x34, 0x12], "SAX $1234");
+        check(&[0xcb, 0x10], "SBX #$10");
+        check(&[0xdb, 0x34, 0x12], "DCP $1234,Y");
+        check(&[0xe3, 0x12], "ISC ($12,X)");
+        check(&[0x1c, 0x34, 0x12], "NOP $1234,X");
+        check(&[0x9b, 0x34, 0x12], "TAS $1234,Y");
+        check(&[0xbb, 0x34, 0x12], "LAS $1234,Y");
+    }
+
+    #[test]
+    fn truncated_operand() {
+        assert_eq!((String::from("LDA $0012"), 3), disasm(&[0xad, 0x12], 0x1000));
+    }
+}
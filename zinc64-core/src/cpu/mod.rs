@@ -3,6 +3,8 @@
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
 mod cpu;
+mod disasm;
 mod uops;
 
 pub use self::cpu::Cpu6510;
+pub use self::disasm::disasm;
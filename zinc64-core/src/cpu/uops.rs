@@ -17,6 +17,7 @@ pub enum MicroOp {
     IndirectFetchAdh,
     ReadData,
     ReadDataOrFixAdh,
+    FixAdh,
     WriteData,
     // Move (16)
     OpLDA,
@@ -82,12 +83,26 @@ pub enum MicroOp {
     OpSEI,
     OpNOP,
     // Undocumented
-    OpANE,
-    OpANX,
     OpALR,
-    OpAXS,
+    OpANC,
+    OpANE,
+    OpARR,
+    OpDCP,
+    OpISC,
+    OpJAM,
+    OpLAS,
     OpLAX,
-    OpLSE,
+    OpLXA,
+    OpRLA,
+    OpRRA,
+    OpSAX,
+    OpSBX,
+    OpSHA,
+    OpSHX,
+    OpSHY,
+    OpSLO,
+    OpSRE,
+    OpTAS,
     // Interrupts
     OpIRQ,
     OpNMI,
@@ -214,7 +229,7 @@ const fn absolutex_rmw(op: MicroOp) -> [MicroOpPair; 8] {
         MicroOpPair::from(MicroOp::FetchOpcode),
         MicroOpPair::from(MicroOp::FetchAdl),
         MicroOpPair::pair(MicroOp::FetchAdh, MicroOp::IncrementAdlX),
-        MicroOpPair::from(MicroOp::FetchOpcodeDiscard), // FIXME
+        MicroOpPair::from(MicroOp::FixAdh),
         MicroOpPair::from(MicroOp::ReadData),
         MicroOpPair::from(op),
         MicroOpPair::from(MicroOp::WriteData),
@@ -245,6 +260,19 @@ const fn absolutey_write(op: MicroOp) -> [MicroOpPair; 7] {
     ]
 }
 
+const fn absolutey_rmw(op: MicroOp) -> [MicroOpPair; 8] {
+    [
+        MicroOpPair::from(MicroOp::FetchOpcode),
+        MicroOpPair::from(MicroOp::FetchAdl),
+        MicroOpPair::pair(MicroOp::FetchAdh, MicroOp::IncrementAdlY),
+        MicroOpPair::from(MicroOp::FixAdh),
+        MicroOpPair::from(MicroOp::ReadData),
+        MicroOpPair::from(op),
+        MicroOpPair::from(MicroOp::WriteData),
+        MicroOpPair::from(MicroOp::FetchOpcode),
+    ]
+}
+
 const fn zeropage_read(op: MicroOp) -> [MicroOpPair; 4] {
     [
         MicroOpPair::from(MicroOp::FetchOpcode),
@@ -350,6 +378,20 @@ const fn indirectx_write(op: MicroOp) -> [MicroOpPair; 7] {
     ]
 }
 
+const fn indirectx_rmw(op: MicroOp) -> [MicroOpPair; 9] {
+    [
+        MicroOpPair::from(MicroOp::FetchOpcode),
+        MicroOpPair::from(MicroOp::FetchAdl),
+        MicroOpPair::from(MicroOp::IncrementAdlX),
+        MicroOpPair::from(MicroOp::IndirectFetchAdl),
+        MicroOpPair::from(MicroOp::IndirectFetchAdh),
+        MicroOpPair::from(MicroOp::ReadData),
+        MicroOpPair::from(op),
+        MicroOpPair::from(MicroOp::WriteData),
+        MicroOpPair::from(MicroOp::FetchOpcode),
+    ]
+}
+
 const fn indirecty_read(op: MicroOp) -> [MicroOpPair; 7] {
     [
         MicroOpPair::from(MicroOp::FetchOpcode),
@@ -375,6 +417,20 @@ const fn indirecty_write(op: MicroOp) -> [MicroOpPair; 8] {
     ]
 }
 
+const fn indirecty_rmw(op: MicroOp) -> [MicroOpPair; 9] {
+    [
+        MicroOpPair::from(MicroOp::FetchOpcode),
+        MicroOpPair::from(MicroOp::FetchAdl),
+        MicroOpPair::from(MicroOp::IndirectFetchAdl),
+        MicroOpPair::pair(MicroOp::IndirectFetchAdh, MicroOp::IncrementAdlY),
+        MicroOpPair::from(MicroOp::FixAdh),
+        MicroOpPair::from(MicroOp::ReadData),
+        MicroOpPair::from(op),
+        MicroOpPair::from(MicroOp::WriteData),
+        MicroOpPair::from(MicroOp::FetchOpcode),
+    ]
+}
+
 const fn indirect(op: MicroOp) -> [MicroOpPair; 6] {
     [
         MicroOpPair::from(MicroOp::FetchOpcode),
@@ -574,68 +630,153 @@ static SEI_IMPLIED: &[MicroOpPair] = &implied(MicroOp::OpSEI);
 static NOP_IMPLIED: &[MicroOpPair] = &implied(MicroOp::OpNOP);
 
 static ALR_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpALR);
+static ANC_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpANC);
 static ANE_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpANE);
-static ANX_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpANX);
-static AXS_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpAXS);
+static ARR_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpARR);
+static DCP_ABSOLUTE: &[MicroOpPair] = &absolute_rmw(MicroOp::OpDCP);
+static DCP_ABSOLUTEX: &[MicroOpPair] = &absolutex_rmw(MicroOp::OpDCP);
+static DCP_ABSOLUTEY: &[MicroOpPair] = &absolutey_rmw(MicroOp::OpDCP);
+static DCP_INDIRECTX: &[MicroOpPair] = &indirectx_rmw(MicroOp::OpDCP);
+static DCP_INDIRECTY: &[MicroOpPair] = &indirecty_rmw(MicroOp::OpDCP);
+static DCP_ZEROPAGE: &[MicroOpPair] = &zeropage_rmw(MicroOp::OpDCP);
+static DCP_ZEROPAGEX: &[MicroOpPair] = &zeropagex_rmw(MicroOp::OpDCP);
+static ISC_ABSOLUTE: &[MicroOpPair] = &absolute_rmw(MicroOp::OpISC);
+static ISC_ABSOLUTEX: &[MicroOpPair] = &absolutex_rmw(MicroOp::OpISC);
+static ISC_ABSOLUTEY: &[MicroOpPair] = &absolutey_rmw(MicroOp::OpISC);
+static ISC_INDIRECTX: &[MicroOpPair] = &indirectx_rmw(MicroOp::OpISC);
+static ISC_INDIRECTY: &[MicroOpPair] = &indirecty_rmw(MicroOp::OpISC);
+static ISC_ZEROPAGE: &[MicroOpPair] = &zeropage_rmw(MicroOp::OpISC);
+static ISC_ZEROPAGEX: &[MicroOpPair] = &zeropagex_rmw(MicroOp::OpISC);
+static JAM_IMPLIED: &[MicroOpPair] = &[
+    MicroOpPair::from(MicroOp::FetchOpcode),
+    MicroOpPair::from(MicroOp::OpJAM),
+];
+static LAS_ABSOLUTEY: &[MicroOpPair] = &absolutey_read(MicroOp::OpLAS);
 static LAX_ABSOLUTE: &[MicroOpPair] = &absolute_read(MicroOp::OpLAX);
 static LAX_ABSOLUTEY: &[MicroOpPair] = &absolutey_read(MicroOp::OpLAX);
 static LAX_INDIRECTX: &[MicroOpPair] = &indirectx_read(MicroOp::OpLAX);
 static LAX_INDIRECTY: &[MicroOpPair] = &indirecty_read(MicroOp::OpLAX);
 static LAX_ZEROPAGE: &[MicroOpPair] = &zeropage_read(MicroOp::OpLAX);
 static LAX_ZEROPAGEY: &[MicroOpPair] = &zeropagey_read(MicroOp::OpLAX);
-static LSE_ABSOLUTE: &[MicroOpPair] = &absolute_read(MicroOp::OpLSE);
-static LSE_ABSOLUTEX: &[MicroOpPair] = &absolutex_read(MicroOp::OpLSE);
-static LSE_ABSOLUTEY: &[MicroOpPair] = &absolutey_read(MicroOp::OpLSE);
-static LSE_INDIRECTX: &[MicroOpPair] = &indirectx_read(MicroOp::OpLSE);
-static LSE_INDIRECTY: &[MicroOpPair] = &indirecty_read(MicroOp::OpLSE);
-static LSE_ZEROPAGE: &[MicroOpPair] = &zeropage_read(MicroOp::OpLSE);
-static LSE_ZEROPAGEX: &[MicroOpPair] = &zeropagex_read(MicroOp::OpLSE);
+static LXA_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpLXA);
+static NOP_ABSOLUTE: &[MicroOpPair] = &absolute_read(MicroOp::OpNOP);
+static NOP_ABSOLUTEX: &[MicroOpPair] = &absolutex_read(MicroOp::OpNOP);
+static NOP_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpNOP);
+static NOP_ZEROPAGE: &[MicroOpPair] = &zeropage_read(MicroOp::OpNOP);
+static NOP_ZEROPAGEX: &[MicroOpPair] = &zeropagex_read(MicroOp::OpNOP);
+static RLA_ABSOLUTE: &[MicroOpPair] = &absolute_rmw(MicroOp::OpRLA);
+static RLA_ABSOLUTEX: &[MicroOpPair] = &absolutex_rmw(MicroOp::OpRLA);
+static RLA_ABSOLUTEY: &[MicroOpPair] = &absolutey_rmw(MicroOp::OpRLA);
+static RLA_INDIRECTX: &[MicroOpPair] = &indirectx_rmw(MicroOp::OpRLA);
+static RLA_INDIRECTY: &[MicroOpPair] = &indirecty_rmw(MicroOp::OpRLA);
+static RLA_ZEROPAGE: &[MicroOpPair] = &zeropage_rmw(MicroOp::OpRLA);
+static RLA_ZEROPAGEX: &[MicroOpPair] = &zeropagex_rmw(MicroOp::OpRLA);
+static RRA_ABSOLUTE: &[MicroOpPair] = &absolute_rmw(MicroOp::OpRRA);
+static RRA_ABSOLUTEX: &[MicroOpPair] = &absolutex_rmw(MicroOp::OpRRA);
+static RRA_ABSOLUTEY: &[MicroOpPair] = &absolutey_rmw(MicroOp::OpRRA);
+static RRA_INDIRECTX: &[MicroOpPair] = &indirectx_rmw(MicroOp::OpRRA);
+static RRA_INDIRECTY: &[MicroOpPair] = &indirecty_rmw(MicroOp::OpRRA);
+static RRA_ZEROPAGE: &[MicroOpPair] = &zeropage_rmw(MicroOp::OpRRA);
+static RRA_ZEROPAGEX: &[MicroOpPair] = &zeropagex_rmw(MicroOp::OpRRA);
+static SAX_ABSOLUTE: &[MicroOpPair] = &absolute_write(MicroOp::OpSAX);
+static SAX_INDIRECTX: &[MicroOpPair] = &indirectx_write(MicroOp::OpSAX);
+static SAX_ZEROPAGE: &[MicroOpPair] = &zeropage_write(MicroOp::OpSAX);
+static SAX_ZEROPAGEY: &[MicroOpPair] = &zeropagey_write(MicroOp::OpSAX);
+static SBX_IMMEDIATE: &[MicroOpPair] = &immediate(MicroOp::OpSBX);
+static SHA_ABSOLUTEY: &[MicroOpPair] = &absolutey_write(MicroOp::OpSHA);
+static SHA_INDIRECTY: &[MicroOpPair] = &indirecty_write(MicroOp::OpSHA);
+static SHX_ABSOLUTEY: &[MicroOpPair] = &absolutey_write(MicroOp::OpSHX);
+static SHY_ABSOLUTEX: &[MicroOpPair] = &absolutex_write(MicroOp::OpSHY);
+static SLO_ABSOLUTE: &[MicroOpPair] = &absolute_rmw(MicroOp::OpSLO);
+static SLO_ABSOLUTEX: &[MicroOpPair] = &absolutex_rmw(MicroOp::OpSLO);
+static SLO_ABSOLUTEY: &[MicroOpPair] = &absolutey_rmw(MicroOp::OpSLO);
+static SLO_INDIRECTX: &[MicroOpPair] = &indirectx_rmw(MicroOp::OpSLO);
+static SLO_INDIRECTY: &[MicroOpPair] = &indirecty_rmw(MicroOp::OpSLO);
+static SLO_ZEROPAGE: &[MicroOpPair] = &zeropage_rmw(MicroOp::OpSLO);
+static SLO_ZEROPAGEX: &[MicroOpPair] = &zeropagex_rmw(MicroOp::OpSLO);
+static SRE_ABSOLUTE: &[MicroOpPair] = &absolute_rmw(MicroOp::OpSRE);
+static SRE_ABSOLUTEX: &[MicroOpPair] = &absolutex_rmw(MicroOp::OpSRE);
+static SRE_ABSOLUTEY: &[MicroOpPair] = &absolutey_rmw(MicroOp::OpSRE);
+static SRE_INDIRECTX: &[MicroOpPair] = &indirectx_rmw(MicroOp::OpSRE);
+static SRE_INDIRECTY: &[MicroOpPair] = &indirecty_rmw(MicroOp::OpSRE);
+static SRE_ZEROPAGE: &[MicroOpPair] = &zeropage_rmw(MicroOp::OpSRE);
+static SRE_ZEROPAGEX: &[MicroOpPair] = &zeropagex_rmw(MicroOp::OpSRE);
+static TAS_ABSOLUTEY: &[MicroOpPair] = &absolutey_write(MicroOp::OpTAS);
 
 pub fn decode_opcode(opcode: u8) -> &'static [MicroOpPair] {
     match opcode {
         0x00 => &BRK_IMPLIED,
         0x01 => &ORA_INDIRECTX,
+        0x02 => &JAM_IMPLIED,
+        0x03 => &SLO_INDIRECTX,
+        0x04 => &NOP_ZEROPAGE,
         0x05 => &ORA_ZEROPAGE,
         0x06 => &ASL_ZEROPAGE,
+        0x07 => &SLO_ZEROPAGE,
         0x08 => &PHP_IMPLIED,
         0x09 => &ORA_IMMEDIATE,
         0x0a => &ASL_IMPLIED,
+        0x0b => &ANC_IMMEDIATE,
+        0x0c => &NOP_ABSOLUTE,
         0x0d => &ORA_ABSOLUTE,
         0x0e => &ASL_ABSOLUTE,
+        0x0f => &SLO_ABSOLUTE,
         0x10 => &BPL_RELATIVE,
         0x11 => &ORA_INDIRECTY,
+        0x12 => &JAM_IMPLIED,
+        0x13 => &SLO_INDIRECTY,
+        0x14 => &NOP_ZEROPAGEX,
         0x15 => &ORA_ZEROPAGEX,
         0x16 => &ASL_ZEROPAGEX,
+        0x17 => &SLO_ZEROPAGEX,
         0x18 => &CLC_IMPLIED,
         0x19 => &ORA_ABSOLUTEY,
+        0x1a => &NOP_IMPLIED,
+        0x1b => &SLO_ABSOLUTEY,
+        0x1c => &NOP_ABSOLUTEX,
         0x1d => &ORA_ABSOLUTEX,
         0x1e => &ASL_ABSOLUTEX,
+        0x1f => &SLO_ABSOLUTEX,
         0x20 => &JSR_ABSOLUTE,
         0x21 => &AND_INDIRECTX,
+        0x22 => &JAM_IMPLIED,
+        0x23 => &RLA_INDIRECTX,
         0x24 => &BIT_ZEROPAGE,
         0x25 => &AND_ZEROPAGE,
         0x26 => &ROL_ZEROPAGE,
+        0x27 => &RLA_ZEROPAGE,
         0x28 => &PLP_IMPLIED,
         0x29 => &AND_IMMEDIATE,
         0x2a => &ROL_IMPLIED,
+        0x2b => &ANC_IMMEDIATE,
         0x2c => &BIT_ABSOLUTE,
         0x2d => &AND_ABSOLUTE,
         0x2e => &ROL_ABSOLUTE,
+        0x2f => &RLA_ABSOLUTE,
         0x30 => &BMI_RELATIVE,
         0x31 => &AND_INDIRECTY,
+        0x32 => &JAM_IMPLIED,
+        0x33 => &RLA_INDIRECTY,
+        0x34 => &NOP_ZEROPAGEX,
         0x35 => &AND_ZEROPAGEX,
         0x36 => &ROL_ZEROPAGEX,
+        0x37 => &RLA_ZEROPAGEX,
         0x38 => &SEC_IMPLIED,
         0x39 => &AND_ABSOLUTEY,
         0x3a => &NOP_IMPLIED,
+        0x3b => &RLA_ABSOLUTEY,
+        0x3c => &NOP_ABSOLUTEX,
         0x3d => &AND_ABSOLUTEX,
         0x3e => &ROL_ABSOLUTEX,
+        0x3f => &RLA_ABSOLUTEX,
         0x40 => &RTI_IMPLIED,
         0x41 => &EOR_INDIRECTX,
-        0x43 => &LSE_INDIRECTX,
+        0x42 => &JAM_IMPLIED,
+        0x43 => &SRE_INDIRECTX,
+        0x44 => &NOP_ZEROPAGE,
         0x45 => &EOR_ZEROPAGE,
         0x46 => &LSR_ZEROPAGE,
-        0x47 => &LSE_ZEROPAGE,
+        0x47 => &SRE_ZEROPAGE,
         0x48 => &PHA_IMPLIED,
         0x49 => &EOR_IMMEDIATE,
         0x4a => &LSR_IMPLIED,
@@ -643,57 +784,87 @@ pub fn decode_opcode(opcode: u8) -> &'static [MicroOpPair] {
         0x4c => &JMP_ABSOLUTE,
         0x4d => &EOR_ABSOLUTE,
         0x4e => &LSR_ABSOLUTE,
-        0x4f => &LSE_ABSOLUTE,
+        0x4f => &SRE_ABSOLUTE,
         0x50 => &BVC_RELATIVE,
         0x51 => &EOR_INDIRECTY,
-        0x53 => &LSE_INDIRECTY,
+        0x52 => &JAM_IMPLIED,
+        0x53 => &SRE_INDIRECTY,
+        0x54 => &NOP_ZEROPAGEX,
         0x55 => &EOR_ZEROPAGEX,
         0x56 => &LSR_ZEROPAGEX,
-        0x57 => &LSE_ZEROPAGEX,
+        0x57 => &SRE_ZEROPAGEX,
         0x58 => &CLI_IMPLIED,
         0x59 => &EOR_ABSOLUTEY,
-        0x5b => &LSE_ABSOLUTEY,
+        0x5a => &NOP_IMPLIED,
+        0x5b => &SRE_ABSOLUTEY,
+        0x5c => &NOP_ABSOLUTEX,
         0x5d => &EOR_ABSOLUTEX,
         0x5e => &LSR_ABSOLUTEX,
-        0x5f => &LSE_ABSOLUTEX,
+        0x5f => &SRE_ABSOLUTEX,
         0x60 => &RTS_IMPLIED,
         0x61 => &ADC_INDIRECTX,
+        0x62 => &JAM_IMPLIED,
+        0x63 => &RRA_INDIRECTX,
+        0x64 => &NOP_ZEROPAGE,
         0x65 => &ADC_ZEROPAGE,
         0x66 => &ROR_ZEROPAGE,
+        0x67 => &RRA_ZEROPAGE,
         0x68 => &PLA_IMPLIED,
         0x69 => &ADC_IMMEDIATE,
         0x6a => &ROR_IMPLIED,
+        0x6b => &ARR_IMMEDIATE,
         0x6c => &JMP_INDIRECT,
         0x6d => &ADC_ABSOLUTE,
         0x6e => &ROR_ABSOLUTE,
+        0x6f => &RRA_ABSOLUTE,
         0x70 => &BVS_RELATIVE,
         0x71 => &ADC_INDIRECTY,
+        0x72 => &JAM_IMPLIED,
+        0x73 => &RRA_INDIRECTY,
+        0x74 => &NOP_ZEROPAGEX,
         0x75 => &ADC_ZEROPAGEX,
         0x76 => &ROR_ZEROPAGEX,
+        0x77 => &RRA_ZEROPAGEX,
         0x78 => &SEI_IMPLIED,
         0x79 => &ADC_ABSOLUTEY,
+        0x7a => &NOP_IMPLIED,
+        0x7b => &RRA_ABSOLUTEY,
+        0x7c => &NOP_ABSOLUTEX,
         0x7d => &ADC_ABSOLUTEX,
         0x7e => &ROR_ABSOLUTEX,
-        0x80 => &NOP_IMPLIED,
+        0x7f => &RRA_ABSOLUTEX,
+        0x80 => &NOP_IMMEDIATE,
         0x81 => &STA_INDIRECTX,
+        0x82 => &NOP_IMMEDIATE,
+        0x83 => &SAX_INDIRECTX,
         0x84 => &STY_ZEROPAGE,
         0x85 => &STA_ZEROPAGE,
         0x86 => &STX_ZEROPAGE,
+        0x87 => &SAX_ZEROPAGE,
         0x88 => &DEY_IMPLIED,
+        0x89 => &NOP_IMMEDIATE,
         0x8a => &TXA_IMPLIED,
         0x8b => &ANE_IMMEDIATE,
         0x8c => &STY_ABSOLUTE,
         0x8d => &STA_ABSOLUTE,
         0x8e => &STX_ABSOLUTE,
+        0x8f => &SAX_ABSOLUTE,
         0x90 => &BCC_RELATIVE,
         0x91 => &STA_INDIRECTY,
+        0x92 => &JAM_IMPLIED,
+        0x93 => &SHA_INDIRECTY,
         0x94 => &STY_ZEROPAGEX,
         0x95 => &STA_ZEROPAGEX,
         0x96 => &STX_ZEROPAGEY,
+        0x97 => &SAX_ZEROPAGEY,
         0x98 => &TYA_IMPLIED,
         0x99 => &STA_ABSOLUTEY,
         0x9a => &TXS_IMPLIED,
+        0x9b => &TAS_ABSOLUTEY,
+        0x9c => &SHY_ABSOLUTEX,
         0x9d => &STA_ABSOLUTEX,
+        0x9e => &SHX_ABSOLUTEY,
+        0x9f => &SHA_ABSOLUTEY,
         0xa0 => &LDY_IMMEDIATE,
         0xa1 => &LDA_INDIRECTX,
         0xa2 => &LDX_IMMEDIATE,
@@ -705,13 +876,14 @@ pub fn decode_opcode(opcode: u8) -> &'static [MicroOpPair] {
         0xa8 => &TAY_IMPLIED,
         0xa9 => &LDA_IMMEDIATE,
         0xaa => &TAX_IMPLIED,
-        0xab => &ANX_IMMEDIATE,
+        0xab => &LXA_IMMEDIATE,
         0xac => &LDY_ABSOLUTE,
         0xad => &LDA_ABSOLUTE,
         0xae => &LDX_ABSOLUTE,
         0xaf => &LAX_ABSOLUTE,
         0xb0 => &BCS_RELATIVE,
         0xb1 => &LDA_INDIRECTY,
+        0xb2 => &JAM_IMPLIED,
         0xb3 => &LAX_INDIRECTY,
         0xb4 => &LDY_ZEROPAGEX,
         0xb5 => &LDA_ZEROPAGEX,
@@ -720,51 +892,75 @@ pub fn decode_opcode(opcode: u8) -> &'static [MicroOpPair] {
         0xb8 => &CLV_IMPLIED,
         0xb9 => &LDA_ABSOLUTEY,
         0xba => &TSX_IMPLIED,
+        0xbb => &LAS_ABSOLUTEY,
         0xbc => &LDY_ABSOLUTEX,
         0xbd => &LDA_ABSOLUTEX,
         0xbe => &LDX_ABSOLUTEY,
         0xbf => &LAX_ABSOLUTEY,
         0xc0 => &CPY_IMMEDIATE,
         0xc1 => &CMP_INDIRECTX,
+        0xc2 => &NOP_IMMEDIATE,
+        0xc3 => &DCP_INDIRECTX,
         0xc4 => &CPY_ZEROPAGE,
         0xc5 => &CMP_ZEROPAGE,
         0xc6 => &DEC_ZEROPAGE,
+        0xc7 => &DCP_ZEROPAGE,
         0xc8 => &INY_IMPLIED,
         0xc9 => &CMP_IMMEDIATE,
         0xca => &DEX_IMPLIED,
-        0xcb => &AXS_IMMEDIATE,
+        0xcb => &SBX_IMMEDIATE,
         0xcc => &CPY_ABSOLUTE,
         0xcd => &CMP_ABSOLUTE,
         0xce => &DEC_ABSOLUTE,
+        0xcf => &DCP_ABSOLUTE,
         0xd0 => &BNE_RELATIVE,
         0xd1 => &CMP_INDIRECTY,
+        0xd2 => &JAM_IMPLIED,
+        0xd3 => &DCP_INDIRECTY,
+        0xd4 => &NOP_ZEROPAGEX,
         0xd5 => &CMP_ZEROPAGEX,
         0xd6 => &DEC_ZEROPAGEX,
+        0xd7 => &DCP_ZEROPAGEX,
         0xd8 => &CLD_IMPLIED,
         0xd9 => &CMP_ABSOLUTEY,
+        0xda => &NOP_IMPLIED,
+        0xdb => &DCP_ABSOLUTEY,
+        0xdc => &NOP_ABSOLUTEX,
         0xdd => &CMP_ABSOLUTEX,
         0xde => &DEC_ABSOLUTEX,
+        0xdf => &DCP_ABSOLUTEX,
         0xe0 => &CPX_IMMEDIATE,
         0xe1 => &SBC_INDIRECTX,
+        0xe2 => &NOP_IMMEDIATE,
+        0xe3 => &ISC_INDIRECTX,
         0xe4 => &CPX_ZEROPAGE,
         0xe5 => &SBC_ZEROPAGE,
         0xe6 => &INC_ZEROPAGE,
+        0xe7 => &ISC_ZEROPAGE,
         0xe8 => &INX_IMPLIED,
         0xe9 => &SBC_IMMEDIATE,
         0xea => &NOP_IMPLIED,
+        0xeb => &SBC_IMMEDIATE,
         0xec => &CPX_ABSOLUTE,
         0xed => &SBC_ABSOLUTE,
         0xee => &INC_ABSOLUTE,
+        0xef => &ISC_ABSOLUTE,
         0xf0 => &BEQ_RELATIVE,
         0xf1 => &SBC_INDIRECTY,
+        0xf2 => &JAM_IMPLIED,
+        0xf3 => &ISC_INDIRECTY,
+        0xf4 => &NOP_ZEROPAGEX,
         0xf5 => &SBC_ZEROPAGEX,
         0xf6 => &INC_ZEROPAGEX,
+        0xf7 => &ISC_ZEROPAGEX,
         0xf8 => &SED_IMPLIED,
         0xf9 => &SBC_ABSOLUTEY,
-        0xfc => &NOP_IMPLIED,
+        0xfa => &NOP_IMPLIED,
+        0xfb => &ISC_ABSOLUTEY,
+        0xfc => &NOP_ABSOLUTEX,
         0xfd => &SBC_ABSOLUTEX,
         0xfe => &INC_ABSOLUTEX,
-        _ => panic!("invalid opcode 0x{:x} at 0x{:x}", opcode, 0),
+        0xff => &ISC_ABSOLUTEX,
     }
 }
 
@@ -809,6 +1005,15 @@ pub enum ProgramId {
     Reset,
 }
 
+impl ProgramId {
+    pub const ALL: [ProgramId; 4] = [
+        ProgramId::Start,
+        ProgramId::Irq,
+        ProgramId::Nmi,
+        ProgramId::Reset,
+    ];
+}
+
 pub fn load_program(id: ProgramId) -> &'static [MicroOpPair] {
     match id {
         ProgramId::Start => &START,
@@ -5,11 +5,12 @@
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
 
 use crate::factory::{make_noop, Addressable, Cpu, Register, TickFn};
-use crate::util::{IoPort, IrqLine, Pin, Shared};
+use crate::util::{IoPort, IrqLine, Pin, Shared, Snapshot, SnapshotReader};
 use core::fmt;
 use log::LogLevel;
 use log::{log, log_enabled, info, trace, debug};
 use alloc::format;
+use alloc::string::String;
 
 use super::instruction::Instruction;
 
@@ -710,6 +711,31 @@ impl Cpu for Cpu6510 {
         self.interrupt(&Interrupt::Reset, &make_noop());
     }
 
+    fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.regs.a);
+        snapshot.write_u8(self.regs.x);
+        snapshot.write_u8(self.regs.y);
+        snapshot.write_u8(self.regs.sp);
+        snapshot.write_u16(self.regs.pc);
+        snapshot.write_u8(self.regs.p);
+        snapshot.write_bool(self.last_nmi);
+        snapshot.write_u16(self.last_pc);
+        self.io_port.borrow().save_state(snapshot);
+    }
+
+    fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.regs.a = snapshot.read_u8()?;
+        self.regs.x = snapshot.read_u8()?;
+        self.regs.y = snapshot.read_u8()?;
+        self.regs.sp = snapshot.read_u8()?;
+        self.regs.pc = snapshot.read_u16()?;
+        self.regs.p = snapshot.read_u8()?;
+        self.last_nmi = snapshot.read_bool()?;
+        self.last_pc = snapshot.read_u16()?;
+        self.io_port.borrow_mut().load_state(snapshot)?;
+        Ok(())
+    }
+
     // -- I/O
 
     fn read(&self, address: u16) -> u8 {
@@ -2,17 +2,22 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, format, rc::Rc, string::String, vec, vec::Vec};
 use bit_field::BitField;
 use log::LogLevel;
 use core::option::Option::{Some, None, self};
 use log::{log_enabled, log, info, trace, debug};
 
+use crate::util::{Event, EventBus, Snapshot, SnapshotReader};
+
+use super::flash::FlashRom;
+
 
 // SPEC: http://ist.uwaterloo.ca/~schepers/formats/CRT.TXT
 
 // DEFERRED device: cartridge test cases
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ChipType {
     Rom,
     Ram,
@@ -20,12 +25,20 @@ pub enum ChipType {
 }
 
 impl ChipType {
-    pub fn from(chip_type: u16) -> ChipType {
+    pub fn from(chip_type: u16) -> Result<ChipType, String> {
         match chip_type {
-            0x00 => ChipType::Rom,
-            0x01 => ChipType::Ram,
-            0x02 => ChipType::FlashRom,
-            _ => panic!("invalid chip type {}", chip_type),
+            0x00 => Ok(ChipType::Rom),
+            0x01 => Ok(ChipType::Ram),
+            0x02 => Ok(ChipType::FlashRom),
+            _ => Err(format!("invalid chip type {}", chip_type)),
+        }
+    }
+
+    pub fn value(&self) -> u16 {
+        match *self {
+            ChipType::Rom => 0x00,
+            ChipType::Ram => 0x01,
+            ChipType::FlashRom => 0x02,
         }
     }
 }
@@ -38,9 +51,10 @@ pub struct Chip {
     pub data: Vec<u8>,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum HwType {
     Normal,
+    ActionReplay,
     EasyFlash,
     Final3,
     GameSystem,
@@ -50,22 +64,43 @@ pub enum HwType {
 }
 
 impl HwType {
-    pub fn from(value: u8) -> HwType {
+    pub fn from(value: u16) -> Result<HwType, String> {
         match value {
-            0 => HwType::Normal,
-            3 => HwType::Final3,
-            4 => HwType::SimonsBasic,
-            5 => HwType::OceanType1,
-            15 => HwType::GameSystem,
-            19 => HwType::MagicDesk,
-            32 => HwType::EasyFlash,
-            _ => panic!("invalid hardware type {}", value),
+            0 => Ok(HwType::Normal),
+            1 => Ok(HwType::ActionReplay),
+            3 => Ok(HwType::Final3),
+            4 => Ok(HwType::SimonsBasic),
+            5 => Ok(HwType::OceanType1),
+            15 => Ok(HwType::GameSystem),
+            19 => Ok(HwType::MagicDesk),
+            32 => Ok(HwType::EasyFlash),
+            _ => Err(format!("unsupported hardware type {}", value)),
         }
     }
 
+    pub fn value(&self) -> u16 {
+        match *self {
+            HwType::Normal => 0,
+            HwType::ActionReplay => 1,
+            HwType::Final3 => 3,
+            HwType::SimonsBasic => 4,
+            HwType::OceanType1 => 5,
+            HwType::GameSystem => 15,
+            HwType::MagicDesk => 19,
+            HwType::EasyFlash => 32,
+        }
+    }
+
+    /// Check if the cartridge has a freeze button.
+    pub fn is_freezer(&self) -> bool {
+        *self == HwType::ActionReplay
+    }
+
     pub fn is_mirrowed(&self) -> bool {
         match *self {
-            HwType::OceanType1 | HwType::MagicDesk | HwType::Normal => true,
+            HwType::ActionReplay | HwType::OceanType1 | HwType::MagicDesk | HwType::Normal => {
+                true
+            }
             _ => false,
         }
     }
@@ -93,12 +128,18 @@ pub struct Cartridge {
     game: bool,
     banks: [Option<Chip>; 64],
     io_observer: Option<Box<dyn Fn(&IoConfig)>>,
+    events: Option<Rc<EventBus>>,
     is_mirrowed: bool,
+    ram: Vec<u8>,
+    flash: Vec<FlashRom>,
     // Runtime state
     bank_lo: Option<usize>,
     bank_hi: Option<usize>,
     io_config: IoConfig,
     reg_value: u8,
+    ram_enabled: bool,
+    disabled: bool,
+    led: bool,
 }
 
 impl Cartridge {
@@ -116,19 +157,87 @@ impl Cartridge {
                 None, None, None, None, None, None, None, None,
             ],
             io_observer: None,
+            events: None,
             is_mirrowed: hw_type.is_mirrowed(),
+            ram: match hw_type {
+                HwType::ActionReplay => vec![0; 0x2000],
+                HwType::EasyFlash => vec![0; 0x100],
+                _ => Vec::new(),
+            },
+            flash: match hw_type {
+                HwType::EasyFlash => vec![FlashRom::new(0x80000), FlashRom::new(0x80000)],
+                _ => Vec::new(),
+            },
             bank_lo: None,
             bank_hi: None,
             io_config: IoConfig::new(),
             reg_value: 0,
+            ram_enabled: false,
+            disabled: false,
+            led: false,
         }
     }
 
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn get_hw_type(&self) -> HwType {
+        self.hw_type
+    }
+
+    pub fn get_exrom(&self) -> bool {
+        self.exrom
+    }
+
+    pub fn get_game(&self) -> bool {
+        self.game
+    }
+
+    pub fn get_chip(&self, bank_number: u8) -> Option<&Chip> {
+        self.banks.get(bank_number as usize).and_then(|bank| bank.as_ref())
+    }
+
+    pub fn get_chip_count(&self) -> usize {
+        self.banks.iter().filter(|bank| bank.is_some()).count()
+    }
+
+    /// Flash chip for ROML (0) or ROMH (1) of flash based cartridges.
+    pub fn get_flash(&self, index: usize) -> Option<&FlashRom> {
+        self.flash.get(index)
+    }
+
+    /// Check if software changed the flash content since the cartridge was loaded.
+    pub fn is_flash_modified(&self) -> bool {
+        self.flash.iter().any(|flash| flash.is_modified())
+    }
+
+    pub fn is_led_on(&self) -> bool {
+        self.led
+    }
+
     pub fn set_io_observer(&mut self, observer: Option<Box<dyn Fn(&IoConfig)>>) {
         self.io_observer = observer;
     }
 
+    pub fn set_events(&mut self, events: Option<Rc<EventBus>>) {
+        self.events = events;
+    }
+
     pub fn add(&mut self, chip: Chip) {
+        if !self.flash.is_empty() {
+            // Flash chips are addressed as one linear array of 8K banks
+            let offset = chip.bank_number as usize * 0x2000;
+            match chip.offset {
+                0x8000 => {
+                    let (lo, hi) = chip.data.split_at(chip.data.len().min(0x2000));
+                    self.flash[0].load(offset, lo);
+                    self.flash[1].load(offset, hi);
+                }
+                _ => self.flash[1].load(offset, &chip.data),
+            }
+            return;
+        }
         let bank_num = chip.bank_number as usize;
         self.banks[bank_num] = Some(chip);
     }
@@ -136,6 +245,12 @@ impl Cartridge {
     pub fn reset(&mut self) {
         self.bank_lo = None;
         self.bank_hi = None;
+        self.ram_enabled = false;
+        self.disabled = false;
+        self.led = false;
+        for flash in self.flash.iter_mut() {
+            flash.reset();
+        }
         self.io_config = IoConfig {
             exrom: self.exrom,
             game: self.game,
@@ -146,6 +261,61 @@ impl Cartridge {
         self.notify_io_changed();
     }
 
+    /// Press the freeze button. The cartridge switches to ultimax mode with its
+    /// first bank mapped, so the NMI vector is fetched from the cartridge ROM.
+    /// Returns false if the cartridge can't be frozen.
+    pub fn freeze(&mut self) -> bool {
+        if !self.hw_type.is_freezer() {
+            return false;
+        }
+        info!(target: "cart", "Freezing cartridge");
+        self.disabled = false;
+        self.ram_enabled = false;
+        self.io_config.exrom = true;
+        self.io_config.game = false;
+        self.switch_bank(0);
+        self.notify_io_changed();
+        true
+    }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.bank_lo.map_or(0xff, |bank| bank as u8));
+        snapshot.write_u8(self.bank_hi.map_or(0xff, |bank| bank as u8));
+        snapshot.write_bool(self.io_config.exrom);
+        snapshot.write_bool(self.io_config.game);
+        snapshot.write_u8(self.reg_value);
+        snapshot.write_bool(self.ram_enabled);
+        snapshot.write_bool(self.disabled);
+        snapshot.write_bool(self.led);
+        snapshot.write_bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.bank_lo = self.read_bank(snapshot)?;
+        self.bank_hi = self.read_bank(snapshot)?;
+        self.io_config.exrom = snapshot.read_bool()?;
+        self.io_config.game = snapshot.read_bool()?;
+        self.reg_value = snapshot.read_u8()?;
+        self.ram_enabled = snapshot.read_bool()?;
+        self.disabled = snapshot.read_bool()?;
+        self.led = snapshot.read_bool()?;
+        snapshot.read_into(&mut self.ram)?;
+        self.notify_io_changed();
+        Ok(())
+    }
+
+    fn read_bank(&self, snapshot: &mut SnapshotReader) -> Result<Option<usize>, String> {
+        match snapshot.read_u8()? {
+            0xff => Ok(None),
+            bank if (bank as usize) < self.banks.len()
+                && (!self.flash.is_empty() || self.banks[bank as usize].is_some()) =>
+            {
+                Ok(Some(bank as usize))
+            }
+            bank => Err(format!("invalid cartridge bank {}", bank)),
+        }
+    }
+
     fn notify_io_changed(&self) {
         if let Some(ref observer) = self.io_observer {
             observer(&self.io_config);
@@ -156,6 +326,14 @@ impl Cartridge {
         if log_enabled!(LogLevel::Trace) {
             trace!(target: "cart::banks", "Switching to bank {} game {} exrom {}", bank_number, self.io_config.game, self.io_config.exrom);
         }
+        if let Some(ref events) = self.events {
+            events.emit(Event::CartridgeBankSwitched(bank_number));
+        }
+        if !self.flash.is_empty() {
+            self.bank_lo = Some(bank_number as usize);
+            self.bank_hi = self.bank_lo;
+            return;
+        }
         if let Some(ref bank) = self.banks[bank_number as usize] {
             match bank.offset {
                 0x8000 => {
@@ -174,7 +352,12 @@ impl Cartridge {
                         self.bank_lo = None;
                     }
                 }
-                _ => panic!("invalid load address {:04x}", bank.bank_number),
+                // Ultimax carts only drive ROMH
+                0xe000 | 0xf000 => {
+                    self.bank_lo = None;
+                    self.bank_hi = Some(bank.bank_number as usize);
+                }
+                _ => panic!("invalid load address {:04x}", bank.offset),
             }
         } else {
             panic!("invalid bank number {}", bank_number);
@@ -183,27 +366,78 @@ impl Cartridge {
 
     // -- Device I/O
 
-    fn read_io(&mut self, address: u16) -> u8 {
+    fn read_io(&mut self, address: u16) -> Option<u8> {
+        if self.hw_type == HwType::GameSystem && address <= 0xdeff {
+            self.switch_bank((address & 0x3f) as u8);
+        }
+        self.peek_io(address)
+    }
+
+    fn peek_io(&self, address: u16) -> Option<u8> {
         match self.hw_type {
-            HwType::GameSystem => match address {
-                0xde00..=0xdeff => {
-                    self.switch_bank((address & 0x3f) as u8);
-                }
-                _ => {}
+            // Bank and mode registers are write only
+            HwType::EasyFlash => match address {
+                0xdf00..=0xdfff => Some(self.ram[(address & 0xff) as usize]),
+                _ => None,
             },
-            _ => {}
+            HwType::ActionReplay if address >= 0xdf00 => {
+                Some(self.read_lo(0x1f00 | (address & 0xff)))
+            }
+            _ => Some(self.reg_value),
         }
-        self.reg_value
     }
 
     fn write_io(&mut self, address: u16, value: u8) {
+        if self.disabled {
+            return;
+        }
         self.reg_value = value;
         match self.hw_type {
-            HwType::EasyFlash => {
-                if address == 0xde00 {
-                    self.switch_bank(value & 0x3f);
+            HwType::ActionReplay => match address {
+                0xde00..=0xdeff => {
+                    /*
+                    Bit 0 asserts GAME, bit 1 releases EXROM, bit 2 disables the
+                    cartridge until reset, bits 3-4 select the bank and bit 5 maps
+                    RAM at ROML and I/O 2.
+                    */
+                    self.disabled = value.get_bit(2);
+                    self.ram_enabled = value.get_bit(5);
+                    self.switch_bank((value >> 3) & 0x03);
+                    if self.disabled {
+                        self.io_config.exrom = true;
+                        self.io_config.game = true;
+                    } else {
+                        self.io_config.exrom = value.get_bit(1);
+                        self.io_config.game = !value.get_bit(0);
+                    }
+                    self.notify_io_changed();
                 }
-            }
+                0xdf00..=0xdfff => {
+                    if self.ram_enabled {
+                        self.ram[0x1f00 | (address & 0xff) as usize] = value;
+                    }
+                }
+                _ => {}
+            },
+            HwType::EasyFlash => match address {
+                0xde00 => self.switch_bank(value & 0x3f),
+                0xde02 => {
+                    /*
+                    Bit 7 drives the LED, bit 2 selects whether GAME is controlled by
+                    bit 0 or by the boot jumper and bit 1 asserts EXROM.
+                    */
+                    self.led = value.get_bit(7);
+                    self.io_config.game = if value.get_bit(2) {
+                        !value.get_bit(0)
+                    } else {
+                        self.game
+                    };
+                    self.io_config.exrom = !value.get_bit(1);
+                    self.notify_io_changed();
+                }
+                0xdf00..=0xdfff => self.ram[(address & 0xff) as usize] = value,
+                _ => {}
+            },
             HwType::Final3 => {
                 if address == 0xde00 {
                     self.switch_bank(value - 0x40);
@@ -230,10 +464,11 @@ impl Cartridge {
             }
             HwType::OceanType1 => {
                 if address == 0xde00 {
-                    if value.get_bit(7) == true {
-                        self.switch_bank(value & 0x3f);
-                    } else {
-                        panic!("should not be here");
+                    // Bit 7 is set by Ocean software but not decoded, and the upper
+                    // bank lines are not connected on carts smaller than 512KB.
+                    let bank_count = self.get_chip_count();
+                    if bank_count != 0 {
+                        self.switch_bank(((value & 0x3f) as usize % bank_count) as u8);
                     }
                 }
             }
@@ -247,37 +482,82 @@ impl Cartridge {
         }
     }
 
+    fn read_lo(&self, offset: u16) -> u8 {
+        if self.ram_enabled {
+            self.ram[offset as usize]
+        } else if let Some(bank_num) = self.bank_lo {
+            let bank = self.banks[bank_num].as_ref().unwrap();
+            bank.data[offset as usize]
+        } else {
+            0
+        }
+    }
+
+    fn read_hi(&self, offset: u16) -> Option<u8> {
+        self.bank_hi.map(|bank_num| {
+            let bank = self.banks[bank_num].as_ref().unwrap();
+            if bank.offset == 0x8000 && bank.data.len() > 0x2000 {
+                bank.data[(offset + 0x2000) as usize]
+            } else {
+                // 4K images are mirrored across the 8K window
+                bank.data[offset as usize % bank.data.len()]
+            }
+        })
+    }
+
+    fn flash_offset(&self, address: u16) -> usize {
+        self.bank_lo.unwrap_or(0) * 0x2000 + (address & 0x1fff) as usize
+    }
+
     pub fn read(&mut self, address: u16) -> Option<u8> {
         match address {
-            0x8000..=0x9fff => {
-                if let Some(bank_num) = self.bank_lo {
-                    let bank = self.banks[bank_num].as_ref().unwrap();
-                    Some(bank.data[(address - 0x8000) as usize])
-                } else {
-                    None
-                }
+            0xde00..=0xdfff if !self.disabled => self.read_io(address),
+            _ => self.peek(address),
+        }
+    }
+
+    /// Read byte from the specified address without triggering bank switching.
+    pub fn peek(&self, address: u16) -> Option<u8> {
+        match address {
+            _ if self.disabled => None,
+            0x8000..=0x9fff if !self.flash.is_empty() => {
+                Some(self.flash[0].read(self.flash_offset(address)))
             }
-            0xa000..=0xbfff => {
-                if let Some(bank_num) = self.bank_hi {
-                    let bank = self.banks[bank_num].as_ref().unwrap();
-                    if bank.offset == 0x8000 {
-                        Some(bank.data[(address - 0x8000) as usize])
-                    } else {
-                        Some(bank.data[(address - 0xa000) as usize])
-                    }
+            0xa000..=0xbfff | 0xe000..=0xffff if !self.flash.is_empty() => {
+                Some(self.flash[1].read(self.flash_offset(address)))
+            }
+            0x8000..=0x9fff => {
+                if self.bank_lo.is_some() || self.ram_enabled {
+                    Some(self.read_lo(address - 0x8000))
                 } else {
                     None
                 }
             }
-            0xde00..=0xdfff => Some(self.read_io(address)),
+            0xa000..=0xbfff => self.read_hi(address - 0xa000),
+            0xde00..=0xdfff => self.peek_io(address),
+            // Ultimax mode
+            0xe000..=0xffff => self.read_hi(address - 0xe000),
             _ => panic!("invalid address {:04x}", address),
         }
     }
 
     pub fn write(&mut self, address: u16, value: u8) {
         match address {
+            0x8000..=0x9fff if !self.flash.is_empty() => {
+                let offset = self.flash_offset(address);
+                self.flash[0].write(offset, value);
+            }
+            0xa000..=0xbfff | 0xe000..=0xffff if !self.flash.is_empty() => {
+                let offset = self.flash_offset(address);
+                self.flash[1].write(offset, value);
+            }
+            0x8000..=0x9fff => {
+                if self.ram_enabled {
+                    self.ram[(address - 0x8000) as usize] = value;
+                }
+            }
             0xde00..=0xdfff => self.write_io(address, value),
-            _ => panic!("writes to cartridge are not supported"),
+            _ => {}
         }
     }
 }
@@ -3,11 +3,15 @@
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
 use bit_field::BitField;
 use log::{log_enabled, log, info, trace, debug};
 
 use crate::factory::Tape;
-use crate::util::{IoPort, Pin, Shared};
+use crate::util::{Event, EventBus, IoPort, Pin, Shared, Snapshot, SnapshotReader};
 
 // DEFERRED device: datassette test cases
 
@@ -28,6 +32,7 @@ const DUTY_CYCLE: u32 = 50;
 
 #[derive(Copy, Clone)]
 enum ControlPort {
+    CassetteWrite = 3,
     CassetteSwitch = 4,
     CassetteMotor = 5,
 }
@@ -70,20 +75,35 @@ pub struct Datassette {
     // Dependencies
     cia_flag_pin: Shared<Pin>,
     cpu_io_port: Shared<IoPort>,
+    events: Rc<EventBus>,
     // Runtime State
     playing: bool,
     tape: Option<Box<dyn Tape>>,
     current_pulse: Pulse,
+    // Recording
+    recording: bool,
+    record_buffer: Vec<u8>,
+    record_cycles: Option<u32>,
+    write_line: bool,
 }
 
 impl Datassette {
-    pub fn new(cia_flag_pin: Shared<Pin>, cpu_io_port: Shared<IoPort>) -> Self {
+    pub fn new(
+        cia_flag_pin: Shared<Pin>,
+        cpu_io_port: Shared<IoPort>,
+        events: Rc<EventBus>,
+    ) -> Self {
         Self {
             cia_flag_pin,
             cpu_io_port,
+            events,
             playing: false,
             tape: None,
             current_pulse: Pulse::new(0, DUTY_CYCLE),
+            recording: false,
+            record_buffer: Vec::new(),
+            record_cycles: None,
+            write_line: false,
         }
     }
 
@@ -92,7 +112,9 @@ impl Datassette {
     }
 
     pub fn clock(&mut self) {
-        if self.is_playing() && self.tape.is_some() {
+        if self.is_recording() {
+            self.clock_record();
+        } else if self.is_playing() && self.tape.is_some() {
             if self.current_pulse.is_done() {
                 let pulse_maybe = if let Some(ref mut tape) = self.tape {
                     tape.read_pulse()
@@ -100,6 +122,7 @@ impl Datassette {
                     None
                 };
                 if let Some(pulse) = pulse_maybe {
+                    self.events.emit(Event::TapePulse(pulse));
                     self.current_pulse = Pulse::new(pulse, DUTY_CYCLE);
                 } else {
                     self.stop();
@@ -113,19 +136,66 @@ impl Datassette {
         }
     }
 
+    /*
+    The tape records the write line as a sequence of pulses, each starting with a
+    rising edge. Pulse lengths are stored the same way as in a version 1 TAP image.
+    */
+    fn clock_record(&mut self) {
+        let write_line = self
+            .cpu_io_port
+            .borrow()
+            .get_value()
+            .get_bit(ControlPort::CassetteWrite.value());
+        if let Some(cycles) = self.record_cycles {
+            self.record_cycles = Some(cycles.saturating_add(1));
+        }
+        if write_line && !self.write_line {
+            if let Some(cycles) = self.record_cycles {
+                self.append_pulse(cycles);
+            }
+            self.record_cycles = Some(0);
+        }
+        self.write_line = write_line;
+    }
+
+    fn append_pulse(&mut self, cycles: u32) {
+        let cycles = cycles.min(0x00ff_ffff);
+        let value = cycles >> 3;
+        if value > 0 && value < 256 {
+            self.record_buffer.push(value as u8);
+        } else {
+            self.record_buffer.push(0);
+            self.record_buffer.push((cycles & 0xff) as u8);
+            self.record_buffer.push(((cycles >> 8) & 0xff) as u8);
+            self.record_buffer.push(((cycles >> 16) & 0xff) as u8);
+        }
+    }
+
     pub fn detach(&mut self) {
         self.stop();
         self.tape = None;
     }
 
     pub fn is_playing(&self) -> bool {
+        self.playing & self.is_motor_on()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording && self.is_motor_on()
+    }
+
+    /// RECORD is held down, whether or not the machine has started the motor.
+    pub fn is_record_pressed(&self) -> bool {
+        self.recording
+    }
+
+    fn is_motor_on(&self) -> bool {
         // Cassette motor control (0=motor spins)
-        let motor_on = !self
+        !self
             .cpu_io_port
             .borrow()
             .get_value()
-            .get_bit(ControlPort::CassetteMotor.value());
-        self.playing & motor_on
+            .get_bit(ControlPort::CassetteMotor.value())
     }
 
     pub fn play(&mut self) {
@@ -138,22 +208,153 @@ impl Datassette {
         }
     }
 
+    /// Press RECORD and PLAY. Pulses written by the machine are appended to the
+    /// recording buffer while the motor is on. Any previous recording not taken
+    /// yet is discarded.
+    pub fn record(&mut self) {
+        info!(target: "device", "Starting datassette recording");
+        self.record_buffer.clear();
+        self.cpu_io_port
+            .borrow_mut()
+            .set_input_bit(ControlPort::CassetteSwitch.value(), false);
+        self.playing = false;
+        self.recording = true;
+        self.record_cycles = None;
+        self.write_line = self
+            .cpu_io_port
+            .borrow()
+            .get_value()
+            .get_bit(ControlPort::CassetteWrite.value());
+    }
+
+    /// Take the recorded pulses as TAP version 1 data, leaving the buffer empty.
+    pub fn take_recording(&mut self) -> Vec<u8> {
+        core::mem::replace(&mut self.record_buffer, Vec::new())
+    }
+
     pub fn reset(&mut self) {
         self.cpu_io_port
             .borrow_mut()
             .set_input_bit(ControlPort::CassetteSwitch.value(), true);
         self.playing = false;
         self.current_pulse = Pulse::new(0, DUTY_CYCLE);
+        self.recording = false;
+        self.record_cycles = None;
         if let Some(ref mut tape) = self.tape {
             tape.seek(0);
         }
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bool(self.tape.is_some());
+        snapshot.write_bool(self.playing);
+        snapshot.write_u32(self.current_pulse.low_cycles);
+        snapshot.write_u32(self.current_pulse.remaining_cycles);
+        let pos = self.tape.as_ref().map_or(0, |tape| tape.get_pos());
+        snapshot.write_u64(pos as u64);
+        snapshot.write_bool(self.recording);
+        snapshot.write_bool(self.record_cycles.is_some());
+        snapshot.write_u32(self.record_cycles.unwrap_or(0));
+        snapshot.write_bool(self.write_line);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        if snapshot.read_bool()? != self.tape.is_some() {
+            return Err(String::from("snapshot tape attachment does not match datassette"));
+        }
+        self.playing = snapshot.read_bool()?;
+        self.current_pulse.low_cycles = snapshot.read_u32()?;
+        self.current_pulse.remaining_cycles = snapshot.read_u32()?;
+        let pos = snapshot.read_u64()? as usize;
+        if let Some(ref mut tape) = self.tape {
+            if !tape.seek(pos) {
+                return Err(format!("invalid tape position {}", pos));
+            }
+        }
+        self.recording = snapshot.read_bool()?;
+        let measuring = snapshot.read_bool()?;
+        let cycles = snapshot.read_u32()?;
+        self.record_cycles = if measuring { Some(cycles) } else { None };
+        self.write_line = snapshot.read_bool()?;
+        Ok(())
+    }
+
     pub fn stop(&mut self) {
         info!(target: "device", "Stopping datassette");
         self.cpu_io_port
             .borrow_mut()
             .set_input_bit(ControlPort::CassetteSwitch.value(), true);
         self.playing = false;
+        self.recording = false;
+        self.record_cycles = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{new_shared, Clock};
+    use alloc::vec;
+
+    fn write_square_wave(datassette: &mut Datassette, io_port: &Shared<IoPort>, pulses: &[u32]) {
+        for pulse in pulses {
+            io_port.borrow_mut().set_value(0x08);
+            for _i in 0..pulse / 2 {
+                datassette.clock();
+            }
+            io_port.borrow_mut().set_value(0x00);
+            for _i in 0..pulse - pulse / 2 {
+                datassette.clock();
+            }
+        }
+        // Rising edge closing the last pulse
+        io_port.borrow_mut().set_value(0x08);
+        datassette.clock();
+    }
+
+    #[test]
+    fn record_write_line_pulses() {
+        let io_port = new_shared(IoPort::new(0x28, 0xff)); // write line and motor
+        let events = Rc::new(EventBus::new(Rc::new(Clock::default())));
+        let mut datassette = Datassette::new(new_shared(Pin::new_low()), io_port.clone(), events);
+        datassette.record();
+        assert!(datassette.is_recording());
+        assert!(!io_port.borrow().get_value().get_bit(4));
+        write_square_wave(&mut datassette, &io_port, &[0x30 << 3, 0x30 << 3, 0x56 << 3, 3000]);
+        datassette.stop();
+        assert!(!datassette.is_recording());
+        assert_eq!(
+            vec![0x30, 0x30, 0x56, 0x00, 0xb8, 0x0b, 0x00],
+            datassette.take_recording()
+        );
+        assert!(datassette.take_recording().is_empty());
+    }
+
+    #[test]
+    fn record_pressed_without_motor() {
+        let io_port = new_shared(IoPort::new(0x28, 0xff));
+        io_port.borrow_mut().set_value(0x20); // motor off
+        let events = Rc::new(EventBus::new(Rc::new(Clock::default())));
+        let mut datassette = Datassette::new(new_shared(Pin::new_low()), io_port.clone(), events);
+        datassette.record();
+        assert!(datassette.is_record_pressed());
+        assert!(!datassette.is_recording());
+        datassette.stop();
+        assert!(!datassette.is_record_pressed());
+    }
+
+    #[test]
+    fn record_discards_previous_recording() {
+        let io_port = new_shared(IoPort::new(0x28, 0xff));
+        let events = Rc::new(EventBus::new(Rc::new(Clock::default())));
+        let mut datassette = Datassette::new(new_shared(Pin::new_low()), io_port.clone(), events);
+        datassette.record();
+        write_square_wave(&mut datassette, &io_port, &[0x30 << 3, 0x30 << 3]);
+        datassette.stop();
+        io_port.borrow_mut().set_value(0x00);
+        datassette.record();
+        write_square_wave(&mut datassette, &io_port, &[0x56 << 3]);
+        datassette.stop();
+        assert_eq!(vec![0x56], datassette.take_recording());
     }
 }
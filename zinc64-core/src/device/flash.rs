@@ -0,0 +1,181 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::{vec, vec::Vec};
+use log::{info, log};
+
+// SPEC: AMD Am29F040B 4 Megabit (512K x 8-Bit) CMOS 5.0 Volt-only Sector Erase Flash Memory
+
+// Design:
+//   Program and erase operations complete immediately, so the embedded algorithm status
+//   bits are never observed by software. Sector protection is not implemented.
+
+const MANUFACTURER_ID: u8 = 0x01;
+const DEVICE_ID: u8 = 0xa4;
+const SECTOR_SIZE: usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Read,
+    Unlock1,
+    Unlock2,
+    Autoselect,
+    Program,
+    EraseSetup,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+pub struct FlashRom {
+    data: Vec<u8>,
+    modified: bool,
+    state: State,
+}
+
+impl FlashRom {
+    pub fn new(size: usize) -> Self {
+        FlashRom {
+            data: vec![0xff; size],
+            modified: false,
+            state: State::Read,
+        }
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Check if the content was changed by a program or erase command.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// Initialize content from an image, this does not mark the chip as modified.
+    pub fn load(&mut self, offset: usize, data: &[u8]) {
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    pub fn reset(&mut self) {
+        self.state = State::Read;
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        match self.state {
+            State::Autoselect => match offset & 0xff {
+                0x00 => MANUFACTURER_ID,
+                0x01 => DEVICE_ID,
+                0x02 => 0x00, // sector unprotected
+                _ => self.data[offset],
+            },
+            _ => self.data[offset],
+        }
+    }
+
+    pub fn write(&mut self, offset: usize, value: u8) {
+        let command = offset & 0x7ff;
+        self.state = match (self.state, command, value) {
+            (State::Program, _, _) => {
+                // Programming can only clear bits
+                self.data[offset] &= value;
+                self.modified = true;
+                State::Read
+            }
+            (_, _, 0xf0) => State::Read,
+            (State::Read, 0x555, 0xaa) | (State::Autoselect, 0x555, 0xaa) => State::Unlock1,
+            (State::Unlock1, 0x2aa, 0x55) => State::Unlock2,
+            (State::Unlock2, 0x555, 0x90) => State::Autoselect,
+            (State::Unlock2, 0x555, 0xa0) => State::Program,
+            (State::Unlock2, 0x555, 0x80) => State::EraseSetup,
+            (State::EraseSetup, 0x555, 0xaa) => State::EraseUnlock1,
+            (State::EraseUnlock1, 0x2aa, 0x55) => State::EraseUnlock2,
+            (State::EraseUnlock2, 0x555, 0x10) => {
+                info!(target: "cart::flash", "Erasing chip");
+                self.erase(0, self.data.len());
+                State::Read
+            }
+            (State::EraseUnlock2, _, 0x30) => {
+                let sector = offset & !(SECTOR_SIZE - 1);
+                info!(target: "cart::flash", "Erasing sector 0x{:x}", sector);
+                self.erase(sector, SECTOR_SIZE.min(self.data.len() - sector));
+                State::Read
+            }
+            (State::Autoselect, _, _) => State::Autoselect,
+            _ => State::Read,
+        };
+    }
+
+    fn erase(&mut self, offset: usize, size: usize) {
+        for byte in self.data[offset..offset + size].iter_mut() {
+            *byte = 0xff;
+        }
+        self.modified = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(flash: &mut FlashRom, value: u8) {
+        flash.write(0x555, 0xaa);
+        flash.write(0x2aa, 0x55);
+        flash.write(0x555, value);
+    }
+
+    #[test]
+    fn program_byte() {
+        let mut flash = FlashRom::new(0x80000);
+        command(&mut flash, 0xa0);
+        flash.write(0x12345, 0x5a);
+        assert_eq!(0x5a, flash.read(0x12345));
+        assert!(flash.is_modified());
+        // Bits can't be set without erase
+        command(&mut flash, 0xa0);
+        flash.write(0x12345, 0xa5);
+        assert_eq!(0x00, flash.read(0x12345));
+    }
+
+    #[test]
+    fn ignore_write_without_unlock() {
+        let mut flash = FlashRom::new(0x80000);
+        flash.write(0x1000, 0x00);
+        flash.write(0x555, 0xa0);
+        flash.write(0x1000, 0x00);
+        assert_eq!(0xff, flash.read(0x1000));
+        assert!(!flash.is_modified());
+    }
+
+    #[test]
+    fn erase_sector() {
+        let mut flash = FlashRom::new(0x80000);
+        flash.load(0, &vec![0x00; 0x80000]);
+        command(&mut flash, 0x80);
+        flash.write(0x555, 0xaa);
+        flash.write(0x2aa, 0x55);
+        flash.write(0x12345, 0x30);
+        assert_eq!(0x00, flash.read(0x0ffff));
+        assert_eq!(0xff, flash.read(0x10000));
+        assert_eq!(0xff, flash.read(0x1ffff));
+        assert_eq!(0x00, flash.read(0x20000));
+    }
+
+    #[test]
+    fn erase_chip() {
+        let mut flash = FlashRom::new(0x80000);
+        flash.load(0, &vec![0x00; 0x80000]);
+        command(&mut flash, 0x80);
+        command(&mut flash, 0x10);
+        assert!(flash.get_data().iter().all(|byte| *byte == 0xff));
+    }
+
+    #[test]
+    fn autoselect() {
+        let mut flash = FlashRom::new(0x80000);
+        command(&mut flash, 0x90);
+        assert_eq!(MANUFACTURER_ID, flash.read(0x0000));
+        assert_eq!(DEVICE_ID, flash.read(0x0001));
+        flash.write(0x0000, 0xf0);
+        assert_eq!(0xff, flash.read(0x0000));
+    }
+}
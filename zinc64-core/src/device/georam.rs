@@ -0,0 +1,172 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::factory::Peripheral;
+use crate::util::{Ram, Shared, Snapshot, SnapshotReader};
+
+// SPEC: https://www.c64-wiki.com/wiki/geoRAM
+
+// Design:
+//   Expansion RAM is organized in 16KB blocks, each made of 64 pages of 256 bytes.
+//   The selected page is visible through the window at $DE00-$DEFF. Page and block
+//   registers are write only.
+
+const PAGE_SIZE: u16 = 0x100;
+const BLOCK_SIZE: usize = 0x4000;
+
+pub struct GeoRam {
+    // Dependencies
+    blocks: Vec<Shared<Ram>>,
+    // Runtime state
+    page: u8,
+    block: u8,
+}
+
+impl GeoRam {
+    /// Constructs GeoRAM from 16KB memory blocks, the number of blocks must be a power of two.
+    pub fn new(blocks: Vec<Shared<Ram>>) -> Result<Self, String> {
+        Self::check_size(blocks.len() * BLOCK_SIZE)?;
+        Ok(GeoRam {
+            blocks,
+            page: 0,
+            block: 0,
+        })
+    }
+
+    /// Check that `size` is a power of two multiple of 16KB, up to 512KB.
+    pub fn check_size(size: usize) -> Result<(), String> {
+        let blocks = size / BLOCK_SIZE;
+        if size % BLOCK_SIZE == 0 && blocks.is_power_of_two() && blocks <= 32 {
+            Ok(())
+        } else {
+            Err(format!("invalid georam size {}", size))
+        }
+    }
+
+    fn window_address(&self, address: u16) -> u16 {
+        self.page as u16 * PAGE_SIZE + (address & 0xff)
+    }
+
+    fn selected_block(&self) -> &Shared<Ram> {
+        &self.blocks[self.block as usize & (self.blocks.len() - 1)]
+    }
+}
+
+impl Peripheral for GeoRam {
+    fn clock(&mut self) {}
+
+    fn reset(&mut self) {
+        self.page = 0;
+        self.block = 0;
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        self.peek(address)
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0xde00..=0xdeff => {
+                let address = self.window_address(address);
+                Some(self.selected_block().borrow().read(address))
+            }
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xde00..=0xdeff => {
+                let address = self.window_address(address);
+                self.selected_block().borrow_mut().write(address, value);
+            }
+            0xdffe => self.page = value & 0x3f,
+            0xdfff => self.block = value,
+            _ => {}
+        }
+    }
+
+    fn save_state(&self, snapshot: &mut Snapshot) {
+        for block in self.blocks.iter() {
+            block.borrow().save_state(snapshot);
+        }
+        snapshot.write_u8(self.page);
+        snapshot.write_u8(self.block);
+    }
+
+    fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        for block in self.blocks.iter() {
+            block.borrow_mut().load_state(snapshot)?;
+        }
+        self.page = snapshot.read_u8()?;
+        self.block = snapshot.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::new_shared;
+
+    fn setup_georam(size: usize) -> GeoRam {
+        let blocks = (0..size / BLOCK_SIZE)
+            .map(|_| new_shared(Ram::new(BLOCK_SIZE)))
+            .collect();
+        GeoRam::new(blocks).unwrap()
+    }
+
+    #[test]
+    fn page_window() {
+        let mut georam = setup_georam(0x80000);
+        for block in 0..32u8 {
+            for page in [0u8, 1, 63].iter() {
+                georam.write(0xdfff, block);
+                georam.write(0xdffe, *page);
+                georam.write(0xde00, block);
+                georam.write(0xdeff, *page);
+            }
+        }
+        for block in 0..32u8 {
+            for page in [0u8, 1, 63].iter() {
+                georam.write(0xdfff, block);
+                georam.write(0xdffe, *page);
+                assert_eq!(Some(block), georam.read(0xde00));
+                assert_eq!(Some(*page), georam.read(0xdeff));
+            }
+        }
+        georam.write(0xdffe, 2);
+        assert_eq!(Some(0x00), georam.read(0xde00));
+        assert_eq!(Some(0x00), georam.peek(0xde00));
+    }
+
+    #[test]
+    fn block_wraps_to_size() {
+        let mut georam = setup_georam(0x10000);
+        georam.write(0xdfff, 0x01);
+        georam.write(0xde10, 0x55);
+        georam.write(0xdfff, 0x05);
+        assert_eq!(Some(0x55), georam.read(0xde10));
+        georam.write(0xdfff, 0x02);
+        assert_eq!(Some(0x00), georam.read(0xde10));
+    }
+
+    #[test]
+    fn registers_are_write_only() {
+        let mut georam = setup_georam(0x20000);
+        georam.write(0xdffe, 0x05);
+        assert_eq!(None, georam.read(0xdffe));
+        assert_eq!(None, georam.read(0xdfff));
+        georam.reset();
+        georam.write(0xde00, 0x11);
+        georam.write(0xdffe, 0x05);
+        assert_eq!(Some(0x00), georam.read(0xde00));
+        georam.write(0xdffe, 0x00);
+        assert_eq!(Some(0x11), georam.read(0xde00));
+    }
+}
@@ -5,7 +5,8 @@ use log::{log, info, trace, debug};
 
 use bit_field::BitField;
 
-use crate::util::SharedCell;
+use super::keyboard::{Key, Keyboard};
+use crate::util::{Shared, SharedCell};
 
 // DEFERRED device: joystick test cases
 
@@ -16,6 +17,7 @@ pub enum Button {
     Left = 2,
     Right = 3,
     Fire = 4,
+    Fire2 = 5,
 }
 
 impl Button {
@@ -30,6 +32,8 @@ pub enum Mode {
     Numpad = 0xfe,
     Joy0 = 0,
     Joy1 = 1,
+    Joy2 = 2,
+    Joy3 = 3,
 }
 
 impl Mode {
@@ -39,6 +43,8 @@ impl Mode {
             "numpad" => Mode::Numpad,
             "joy0" => Mode::Joy0,
             "joy1" => Mode::Joy1,
+            "joy2" => Mode::Joy2,
+            "joy3" => Mode::Joy3,
             _ => panic!("invalid mode {}", mode),
         }
     }
@@ -48,10 +54,35 @@ impl Mode {
     }
 }
 
+/// Keyboard keys pressed in place of the joystick lines, for games that are played
+/// from the keyboard. Unmapped lines are reported on the control port as usual.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeyMap {
+    pub up: Option<Key>,
+    pub down: Option<Key>,
+    pub left: Option<Key>,
+    pub right: Option<Key>,
+    pub fire: Option<Key>,
+}
+
+impl KeyMap {
+    fn get(&self, bit: usize) -> Option<Key> {
+        match bit {
+            0 => self.up,
+            1 => self.down,
+            2 => self.left,
+            3 => self.right,
+            4 => self.fire,
+            _ => None,
+        }
+    }
+}
+
 pub struct Joystick {
     // Configuration
     mode: Mode,
     threshold: i16,
+    key_map: Option<(KeyMap, Shared<[u8; 16]>)>,
     // State
     state: SharedCell<u8>,
 }
@@ -61,6 +92,7 @@ impl Joystick {
         Self {
             mode,
             threshold,
+            key_map: None,
             state,
         }
     }
@@ -69,15 +101,51 @@ impl Joystick {
         self.mode.index()
     }
 
+    pub fn get_key_map(&self) -> Option<KeyMap> {
+        self.key_map.as_ref().map(|(key_map, _)| *key_map)
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
     pub fn is_virtual(&self) -> bool {
         self.mode == Mode::Numpad
     }
 
+    /// Map joystick lines to keys of the keyboard with `keyboard_matrix`.
+    pub fn set_key_map(&mut self, key_map: Option<KeyMap>, keyboard_matrix: Shared<[u8; 16]>) {
+        self.key_map = key_map.map(|key_map| (key_map, keyboard_matrix));
+    }
+
+    /// Connect joystick to the control port with lines in `state`.
+    pub fn set_port(&mut self, state: SharedCell<u8>) {
+        self.reset();
+        self.state = state;
+        self.reset();
+    }
+
     pub fn reset(&mut self) {
         self.state.set(0);
     }
 
+    fn map_button(button_idx: u8) -> Button {
+        match button_idx {
+            1 => Button::Fire2,
+            _ => Button::Fire,
+        }
+    }
+
     fn set_state(&mut self, bit: usize, value: bool) {
+        if let Some((ref key_map, ref keyboard_matrix)) = self.key_map {
+            if let Some(key) = key_map.get(bit) {
+                let (row, col) = Keyboard::map_keycode(key);
+                let mut matrix = keyboard_matrix.borrow_mut();
+                matrix[row].set_bit(col, !value);
+                matrix[8 + col].set_bit(row, !value);
+                return;
+            }
+        }
         let mut new_state = self.state.get();
         new_state.set_bit(bit, value);
         self.state.set(new_state);
@@ -115,12 +183,12 @@ impl Joystick {
         }
     }
 
-    pub fn on_button_down(&mut self, _button_idx: u8) {
-        self.set_state(Button::Fire.bit(), true);
+    pub fn on_button_down(&mut self, button_idx: u8) {
+        self.set_state(Self::map_button(button_idx).bit(), true);
     }
 
-    pub fn on_button_up(&mut self, _button_idx: u8) {
-        self.set_state(Button::Fire.bit(), false);
+    pub fn on_button_up(&mut self, button_idx: u8) {
+        self.set_state(Self::map_button(button_idx).bit(), false);
     }
 
     pub fn on_key_down(&mut self, keycode: Button) {
@@ -3,7 +3,6 @@
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
 use alloc::vec::Vec;
-use alloc::string::ToString;
 use core::option::Option::{self, Some, None};
 
 use bit_field::BitField;
@@ -12,7 +11,14 @@ use crate::util::Shared;
 
 // SPEC: https://www.c64-wiki.com/index.php/Keyboard#Hardware
 
-#[derive(Copy, Clone, Debug)]
+// Queued keys are held long enough for the kernal to scan them once every 1/60 s. The
+// screen editor tokenizes a line on RETURN, so typing pauses to keep the keyboard buffer
+// from overflowing.
+const KEY_DOWN_CYCLES: u32 = 20000;
+const KEY_UP_CYCLES: u32 = 20000;
+const RETURN_CYCLES: u32 = 200_000;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Key {
     // Numerical
     Num0,
@@ -85,7 +91,7 @@ pub enum Key {
     F7,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct KeyEvent {
     keycode: Key,
     modifier: Option<Key>,
@@ -118,45 +124,97 @@ impl KeyEvent {
     }
 }
 
+/// Auto-repeat of the last held key, for software that does not repeat keys itself.
+/// The key is released for one frame and pressed again, so each repeat is read as a
+/// new key press.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyRepeat {
+    /// Frames the key is held before it starts repeating.
+    pub delay: u32,
+    /// Frames between repeats.
+    pub rate: u32,
+}
+
 pub struct Keyboard {
+    // Configuration
+    repeat: Option<KeyRepeat>,
+    // Dependencies
     matrix: Shared<[u8; 16]>,
-    queue: Vec<(KeyEvent, bool)>,
+    // Runtime State
+    queue: Vec<(KeyEvent, bool, u32)>,
     disabled_shift: u8,
+    repeat_key: Option<Key>,
+    repeat_frames: u32,
+    repeat_released: bool,
 }
 
 impl Keyboard {
     pub fn new(matrix: Shared<[u8; 16]>) -> Self {
         Self {
+            repeat: None,
             matrix,
             queue: Vec::new(),
             disabled_shift: 0,
+            repeat_key: None,
+            repeat_frames: 0,
+            repeat_released: false,
         }
     }
 
+    pub fn get_repeat(&self) -> Option<KeyRepeat> {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: Option<KeyRepeat>) {
+        self.repeat = repeat;
+        self.repeat_key = None;
+    }
+
     pub fn get_col(&self, col: u8) -> u8 {
         self.matrix.borrow()[8 + col as usize]
     }
 
+    pub fn get_matrix(&self) -> Shared<[u8; 16]> {
+        self.matrix.clone()
+    }
+
     pub fn get_row(&self, row: u8) -> u8 {
         self.matrix.borrow()[row as usize]
     }
 
-    pub fn drain_event(&mut self) {
+    /// Apply the next queued key event and return the number of cycles to wait before
+    /// draining the following one.
+    pub fn drain_event(&mut self) -> u32 {
         if !self.queue.is_empty() {
-            let (key_event, pressed) = self.queue.remove(0);
+            let (key_event, pressed, delay) = self.queue.remove(0);
             if pressed {
                 self.on_key_down(key_event)
             } else {
                 self.on_key_up(key_event)
             }
+            delay
+        } else {
+            0
         }
     }
 
     pub fn enqueue(&mut self, str: &str) {
-        for c in str.to_string().chars() {
-            let key_event = self.map_char(c);
-            self.queue.push((key_event, true));
-            self.queue.push((key_event, false));
+        self.type_str(str);
+    }
+
+    /// Queue key presses that type `str` as if entered on the keyboard. Letters are typed
+    /// unshifted regardless of case and characters without a key are skipped.
+    pub fn type_str(&mut self, str: &str) {
+        for c in str.chars() {
+            if let Some(key_event) = Self::map_char(c) {
+                let delay = if key_event.keycode == Key::Return {
+                    RETURN_CYCLES
+                } else {
+                    KEY_UP_CYCLES
+                };
+                self.queue.push((key_event, true, KEY_DOWN_CYCLES));
+                self.queue.push((key_event, false, delay));
+            }
         }
     }
 
@@ -170,10 +228,27 @@ impl Keyboard {
             matrix[i] = 0xff;
         }
         self.queue.clear();
+        self.repeat_key = None;
+    }
+
+    /// Advance key repeat by one frame.
+    pub fn process_vsync(&mut self) {
+        if let (Some(repeat), Some(key)) = (self.repeat, self.repeat_key) {
+            if self.repeat_released {
+                self.set_key(key, true);
+                self.repeat_released = false;
+                self.repeat_frames = repeat.rate;
+            } else if self.repeat_frames > 1 {
+                self.repeat_frames -= 1;
+            } else {
+                self.set_key(key, false);
+                self.repeat_released = true;
+            }
+        }
     }
 
     pub fn set_key(&mut self, keycode: Key, enabled: bool) {
-        let mapping = self.map_keycode(keycode);
+        let mapping = Self::map_keycode(keycode);
         self.matrix.borrow_mut()[mapping.0].set_bit(mapping.1, !enabled);
         self.matrix.borrow_mut()[8 + mapping.1].set_bit(mapping.0, !enabled);
     }
@@ -184,7 +259,7 @@ impl Keyboard {
     }
 
     fn is_pressed(&self, keycode: Key) -> bool {
-        let mapping = self.map_keycode(keycode);
+        let mapping = Self::map_keycode(keycode);
         !self.matrix.borrow()[mapping.0].get_bit(mapping.1)
     }
 
@@ -192,6 +267,13 @@ impl Keyboard {
 
     pub fn on_key_down(&mut self, event: KeyEvent) {
         self.set_key(event.keycode, true);
+        if let Some(repeat) = self.repeat {
+            if !Self::is_modifier(event.keycode) {
+                self.repeat_key = Some(event.keycode);
+                self.repeat_frames = repeat.delay;
+                self.repeat_released = false;
+            }
+        }
         if let Some(modifier) = event.modifier {
             self.set_key(modifier, true);
         }
@@ -209,6 +291,9 @@ impl Keyboard {
 
     pub fn on_key_up(&mut self, event: KeyEvent) {
         self.set_key(event.keycode, false);
+        if self.repeat_key == Some(event.keycode) {
+            self.repeat_key = None;
+        }
         if let Some(modifier) = event.modifier {
             self.set_key(modifier, false);
         }
@@ -223,76 +308,86 @@ impl Keyboard {
         }
     }
 
+    fn is_modifier(keycode: Key) -> bool {
+        matches!(keycode, Key::LShift | Key::RShift | Key::Ctrl | Key::LGui)
+    }
+
     // -- Mapping Ops
 
-    fn map_char(&self, c: char) -> KeyEvent {
+    fn map_char(c: char) -> Option<KeyEvent> {
+        let c = c.to_ascii_uppercase();
         match c {
-            '\n' => KeyEvent::new(Key::Return),
-            ' ' => KeyEvent::new(Key::Space),
-            '!' => KeyEvent::with_mod(Key::Num1, Key::LShift),
-            '"' => KeyEvent::with_mod(Key::Num2, Key::LShift),
-            '#' => KeyEvent::with_mod(Key::Num3, Key::LShift),
-            '$' => KeyEvent::with_mod(Key::Num4, Key::LShift),
-            '%' => KeyEvent::with_mod(Key::Num5, Key::LShift),
-            '&' => KeyEvent::with_mod(Key::Num6, Key::LShift),
-            '\'' => KeyEvent::with_mod(Key::Num7, Key::LShift),
-            '(' => KeyEvent::with_mod(Key::Num8, Key::LShift),
-            ')' => KeyEvent::with_mod(Key::Num9, Key::LShift),
-            '*' => KeyEvent::new(Key::Asterisk),
-            '+' => KeyEvent::new(Key::Plus),
-            ',' => KeyEvent::new(Key::Comma),
-            '-' => KeyEvent::new(Key::Minus),
-            '.' => KeyEvent::new(Key::Period),
-            '/' => KeyEvent::new(Key::Slash),
-            '0' => KeyEvent::new(Key::Num0),
-            '1' => KeyEvent::new(Key::Num1),
-            '2' => KeyEvent::new(Key::Num2),
-            '3' => KeyEvent::new(Key::Num3),
-            '4' => KeyEvent::new(Key::Num4),
-            '5' => KeyEvent::new(Key::Num5),
-            '6' => KeyEvent::new(Key::Num6),
-            '7' => KeyEvent::new(Key::Num7),
-            '8' => KeyEvent::new(Key::Num8),
-            '9' => KeyEvent::new(Key::Num9),
-            ':' => KeyEvent::new(Key::Colon),
-            ';' => KeyEvent::new(Key::Semicolon),
-            '<' => KeyEvent::with_mod(Key::Comma, Key::LShift),
-            '=' => KeyEvent::new(Key::Equals),
-            '>' => KeyEvent::with_mod(Key::Period, Key::LShift),
-            '?' => KeyEvent::with_mod(Key::Slash, Key::LShift),
-            '@' => KeyEvent::new(Key::At),
-            'A' => KeyEvent::new(Key::A),
-            'B' => KeyEvent::new(Key::B),
-            'C' => KeyEvent::new(Key::C),
-            'D' => KeyEvent::new(Key::D),
-            'E' => KeyEvent::new(Key::E),
-            'F' => KeyEvent::new(Key::F),
-            'G' => KeyEvent::new(Key::G),
-            'H' => KeyEvent::new(Key::H),
-            'I' => KeyEvent::new(Key::I),
-            'J' => KeyEvent::new(Key::J),
-            'K' => KeyEvent::new(Key::K),
-            'L' => KeyEvent::new(Key::L),
-            'M' => KeyEvent::new(Key::M),
-            'N' => KeyEvent::new(Key::N),
-            'O' => KeyEvent::new(Key::O),
-            'P' => KeyEvent::new(Key::P),
-            'Q' => KeyEvent::new(Key::Q),
-            'R' => KeyEvent::new(Key::R),
-            'S' => KeyEvent::new(Key::S),
-            'T' => KeyEvent::new(Key::T),
-            'U' => KeyEvent::new(Key::U),
-            'V' => KeyEvent::new(Key::V),
-            'W' => KeyEvent::new(Key::W),
-            'X' => KeyEvent::new(Key::X),
-            'Y' => KeyEvent::new(Key::Y),
-            'Z' => KeyEvent::new(Key::Z),
-            '^' => KeyEvent::new(Key::Caret),
-            _ => panic!("unsupported char {}", c),
+            '\n' => Some(KeyEvent::new(Key::Return)),
+            ' ' => Some(KeyEvent::new(Key::Space)),
+            '!' => Some(KeyEvent::with_mod(Key::Num1, Key::LShift)),
+            '"' => Some(KeyEvent::with_mod(Key::Num2, Key::LShift)),
+            '#' => Some(KeyEvent::with_mod(Key::Num3, Key::LShift)),
+            '$' => Some(KeyEvent::with_mod(Key::Num4, Key::LShift)),
+            '%' => Some(KeyEvent::with_mod(Key::Num5, Key::LShift)),
+            '&' => Some(KeyEvent::with_mod(Key::Num6, Key::LShift)),
+            '\'' => Some(KeyEvent::with_mod(Key::Num7, Key::LShift)),
+            '(' => Some(KeyEvent::with_mod(Key::Num8, Key::LShift)),
+            ')' => Some(KeyEvent::with_mod(Key::Num9, Key::LShift)),
+            '*' => Some(KeyEvent::new(Key::Asterisk)),
+            '+' => Some(KeyEvent::new(Key::Plus)),
+            ',' => Some(KeyEvent::new(Key::Comma)),
+            '-' => Some(KeyEvent::new(Key::Minus)),
+            '.' => Some(KeyEvent::new(Key::Period)),
+            '/' => Some(KeyEvent::new(Key::Slash)),
+            '0' => Some(KeyEvent::new(Key::Num0)),
+            '1' => Some(KeyEvent::new(Key::Num1)),
+            '2' => Some(KeyEvent::new(Key::Num2)),
+            '3' => Some(KeyEvent::new(Key::Num3)),
+            '4' => Some(KeyEvent::new(Key::Num4)),
+            '5' => Some(KeyEvent::new(Key::Num5)),
+            '6' => Some(KeyEvent::new(Key::Num6)),
+            '7' => Some(KeyEvent::new(Key::Num7)),
+            '8' => Some(KeyEvent::new(Key::Num8)),
+            '9' => Some(KeyEvent::new(Key::Num9)),
+            ':' => Some(KeyEvent::new(Key::Colon)),
+            ';' => Some(KeyEvent::new(Key::Semicolon)),
+            '<' => Some(KeyEvent::with_mod(Key::Comma, Key::LShift)),
+            '=' => Some(KeyEvent::new(Key::Equals)),
+            '>' => Some(KeyEvent::with_mod(Key::Period, Key::LShift)),
+            '?' => Some(KeyEvent::with_mod(Key::Slash, Key::LShift)),
+            '@' => Some(KeyEvent::new(Key::At)),
+            'A' => Some(KeyEvent::new(Key::A)),
+            'B' => Some(KeyEvent::new(Key::B)),
+            'C' => Some(KeyEvent::new(Key::C)),
+            'D' => Some(KeyEvent::new(Key::D)),
+            'E' => Some(KeyEvent::new(Key::E)),
+            'F' => Some(KeyEvent::new(Key::F)),
+            'G' => Some(KeyEvent::new(Key::G)),
+            'H' => Some(KeyEvent::new(Key::H)),
+            'I' => Some(KeyEvent::new(Key::I)),
+            'J' => Some(KeyEvent::new(Key::J)),
+            'K' => Some(KeyEvent::new(Key::K)),
+            'L' => Some(KeyEvent::new(Key::L)),
+            'M' => Some(KeyEvent::new(Key::M)),
+            'N' => Some(KeyEvent::new(Key::N)),
+            'O' => Some(KeyEvent::new(Key::O)),
+            'P' => Some(KeyEvent::new(Key::P)),
+            'Q' => Some(KeyEvent::new(Key::Q)),
+            'R' => Some(KeyEvent::new(Key::R)),
+            'S' => Some(KeyEvent::new(Key::S)),
+            'T' => Some(KeyEvent::new(Key::T)),
+            'U' => Some(KeyEvent::new(Key::U)),
+            'V' => Some(KeyEvent::new(Key::V)),
+            'W' => Some(KeyEvent::new(Key::W)),
+            'X' => Some(KeyEvent::new(Key::X)),
+            'Y' => Some(KeyEvent::new(Key::Y)),
+            'Z' => Some(KeyEvent::new(Key::Z)),
+            '[' => Some(KeyEvent::with_mod(Key::Colon, Key::LShift)),
+            ']' => Some(KeyEvent::with_mod(Key::Semicolon, Key::LShift)),
+            '^' => Some(KeyEvent::new(Key::Caret)),
+            '_' => Some(KeyEvent::new(Key::Left)),
+            '£' => Some(KeyEvent::new(Key::Dollar)),
+            _ => None,
         }
     }
 
-    fn map_keycode(&self, keycode: Key) -> (usize, usize) {
+    /// Row and column of the key in the keyboard matrix.
+    pub fn map_keycode(keycode: Key) -> (usize, usize) {
         match keycode {
             // Row 0
             Key::Backspace => (0, 0),
@@ -374,6 +469,7 @@ impl Keyboard {
 mod tests {
     use super::*;
     use crate::util::new_shared;
+    use alloc::vec;
 
     #[test]
     fn enqueue_key_event() {
@@ -397,6 +493,76 @@ mod tests {
         assert_eq!(false, keyboard.has_events());
     }
 
+    #[test]
+    fn type_str_events() {
+        let matrix = new_shared([0; 16]);
+        let mut keyboard = Keyboard::new(matrix);
+        keyboard.reset();
+        keyboard.type_str("print\"hi\"~\n");
+        let quote = KeyEvent::with_mod(Key::Num2, Key::LShift);
+        let expected = vec![
+            KeyEvent::new(Key::P),
+            KeyEvent::new(Key::R),
+            KeyEvent::new(Key::I),
+            KeyEvent::new(Key::N),
+            KeyEvent::new(Key::T),
+            quote,
+            KeyEvent::new(Key::H),
+            KeyEvent::new(Key::I),
+            quote,
+            KeyEvent::new(Key::Return),
+        ];
+        let mut events = Vec::new();
+        while keyboard.has_events() {
+            let (key_event, _, _) = keyboard.queue[0];
+            let delay = keyboard.drain_event();
+            let shift = keyboard.is_pressed(Key::LShift);
+            if keyboard.is_pressed(key_event.keycode) {
+                assert_eq!(KEY_DOWN_CYCLES, delay);
+                assert_eq!(key_event.modifier.is_some(), shift);
+                events.push(key_event);
+            } else {
+                assert!(!shift);
+            }
+        }
+        assert_eq!(expected, events);
+        assert_eq!(0xff, keyboard.get_row(0));
+    }
+
+    #[test]
+    fn key_repeat() {
+        let matrix = new_shared([0; 16]);
+        let mut keyboard = Keyboard::new(matrix);
+        keyboard.reset();
+        keyboard.set_repeat(Some(KeyRepeat { delay: 3, rate: 2 }));
+        keyboard.on_key_down(KeyEvent::new(Key::S));
+        let mut pressed = Vec::new();
+        for _ in 0..10 {
+            keyboard.process_vsync();
+            pressed.push(keyboard.is_pressed(Key::S));
+        }
+        assert_eq!(
+            vec![true, true, false, true, true, false, true, true, false, true],
+            pressed
+        );
+        keyboard.on_key_up(KeyEvent::new(Key::S));
+        keyboard.process_vsync();
+        assert!(!keyboard.is_pressed(Key::S));
+    }
+
+    #[test]
+    fn key_repeat_ignores_modifiers() {
+        let matrix = new_shared([0; 16]);
+        let mut keyboard = Keyboard::new(matrix);
+        keyboard.reset();
+        keyboard.set_repeat(Some(KeyRepeat { delay: 1, rate: 1 }));
+        keyboard.on_key_down(KeyEvent::new(Key::LShift));
+        for _ in 0..4 {
+            keyboard.process_vsync();
+            assert!(keyboard.is_pressed(Key::LShift));
+        }
+    }
+
     #[test]
     fn emulate_key_press() {
         let matrix = new_shared([0; 16]);
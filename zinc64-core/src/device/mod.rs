@@ -4,10 +4,21 @@
 
 pub mod cartridge;
 mod datassette;
+pub mod flash;
+mod georam;
 pub mod joystick;
 pub mod keyboard;
+pub mod mouse;
+pub mod paddle;
+mod reu;
+mod rs232;
 
 pub use self::cartridge::Cartridge;
 pub use self::datassette::Datassette;
+pub use self::georam::GeoRam;
 pub use self::joystick::Joystick;
 pub use self::keyboard::{Key, KeyEvent, Keyboard};
+pub use self::mouse::Mouse;
+pub use self::paddle::Paddle;
+pub use self::reu::Reu;
+pub use self::rs232::Rs232;
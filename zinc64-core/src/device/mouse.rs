@@ -0,0 +1,257 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::string::String;
+use bit_field::BitField;
+
+use super::joystick::Button;
+use crate::util::{SharedCell, Snapshot, SnapshotReader};
+
+// SPEC: https://www.c64-wiki.com/wiki/Mouse_1351
+
+// Design:
+//   In proportional mode the 1351 keeps a 6 bit counter per axis and reports it in bits
+//   1-6 of the POT lines, so software reads the motion as the difference between two
+//   measurements. Bit 0 is noise and reads as 0 here. The left button is read as the
+//   joystick fire button and the right button as joystick up. In joystick mode motion
+//   is reported as joystick directions and the right button pulls POTX instead.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Proportional,
+    Joystick,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MouseButton {
+    Left,
+    Right,
+}
+
+const POT_IDLE: u8 = 0xff;
+
+pub struct Mouse {
+    // Configuration
+    mode: Mode,
+    port: u8,
+    // Dependencies
+    pot_x: SharedCell<u8>,
+    pot_y: SharedCell<u8>,
+    port_select: SharedCell<u8>,
+    // State
+    port_pot_x: SharedCell<u8>,
+    port_pot_y: SharedCell<u8>,
+    state: SharedCell<u8>,
+    x: u8,
+    y: u8,
+    right_button: bool,
+}
+
+impl Mouse {
+    /// Constructs mouse on control port `port` (1 or 2). `port_pot_x` and `port_pot_y` hold
+    /// the values on the POT lines of the port, `pot_x` and `pot_y` are the SID inputs they
+    /// are switched to while `port_select` matches the port.
+    pub fn new(
+        mode: Mode,
+        port: u8,
+        port_pot_x: SharedCell<u8>,
+        port_pot_y: SharedCell<u8>,
+        state: SharedCell<u8>,
+        pot_x: SharedCell<u8>,
+        pot_y: SharedCell<u8>,
+        port_select: SharedCell<u8>,
+    ) -> Self {
+        #![cfg_attr(feature = "cargo-clippy", allow(clippy::too_many_arguments))]
+        let mut mouse = Self {
+            mode,
+            port,
+            pot_x,
+            pot_y,
+            port_select,
+            port_pot_x,
+            port_pot_y,
+            state,
+            x: 0,
+            y: 0,
+            right_button: false,
+        };
+        mouse.update_pots();
+        mouse
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.x = 0;
+        self.y = 0;
+        self.right_button = false;
+        let mut new_state = self.state.get();
+        for button in &[
+            Button::Up,
+            Button::Down,
+            Button::Left,
+            Button::Right,
+            Button::Fire,
+        ] {
+            new_state.set_bit(button.bit(), false);
+        }
+        self.state.set(new_state);
+        self.update_pots();
+    }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.x);
+        snapshot.write_u8(self.y);
+        snapshot.write_bool(self.right_button);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.x = snapshot.read_u8()?;
+        self.y = snapshot.read_u8()?;
+        self.right_button = snapshot.read_bool()?;
+        self.update_pots();
+        Ok(())
+    }
+
+    fn set_state(&mut self, button: Button, value: bool) {
+        let mut new_state = self.state.get();
+        new_state.set_bit(button.bit(), value);
+        self.state.set(new_state);
+    }
+
+    fn update_pots(&mut self) {
+        let (pot_x, pot_y) = match self.mode {
+            Mode::Proportional => ((self.x & 0x3f) << 1, (self.y & 0x3f) << 1),
+            Mode::Joystick if self.right_button => (0x00, POT_IDLE),
+            Mode::Joystick => (POT_IDLE, POT_IDLE),
+        };
+        self.port_pot_x.set(pot_x);
+        self.port_pot_y.set(pot_y);
+        if self.port_select.get() == self.port {
+            self.pot_x.set(pot_x);
+            self.pot_y.set(pot_y);
+        }
+    }
+
+    // -- Event Handlers
+
+    /// Relative host mouse motion, `dy` grows downwards as on screen.
+    pub fn on_motion(&mut self, dx: i32, dy: i32) {
+        match self.mode {
+            Mode::Proportional => {
+                self.x = self.x.wrapping_add(dx as u8);
+                self.y = self.y.wrapping_sub(dy as u8);
+                self.update_pots();
+            }
+            Mode::Joystick => {
+                self.set_state(Button::Left, dx < 0);
+                self.set_state(Button::Right, dx > 0);
+                self.set_state(Button::Up, dy < 0);
+                self.set_state(Button::Down, dy > 0);
+            }
+        }
+    }
+
+    pub fn on_button_down(&mut self, button: MouseButton) {
+        self.set_button(button, true);
+    }
+
+    pub fn on_button_up(&mut self, button: MouseButton) {
+        self.set_button(button, false);
+    }
+
+    fn set_button(&mut self, button: MouseButton, pressed: bool) {
+        match (button, self.mode) {
+            (MouseButton::Left, _) => self.set_state(Button::Fire, pressed),
+            (MouseButton::Right, Mode::Proportional) => self.set_state(Button::Up, pressed),
+            (MouseButton::Right, Mode::Joystick) => {
+                self.right_button = pressed;
+                self.update_pots();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::new_shared_cell;
+
+    struct Setup {
+        mouse: Mouse,
+        pot_x: SharedCell<u8>,
+        pot_y: SharedCell<u8>,
+        state: SharedCell<u8>,
+    }
+
+    fn setup_mouse(mode: Mode) -> Setup {
+        let pot_x = new_shared_cell(0xffu8);
+        let pot_y = new_shared_cell(0xffu8);
+        let state = new_shared_cell(0u8);
+        let mouse = Mouse::new(
+            mode,
+            1,
+            new_shared_cell(0xff),
+            new_shared_cell(0xff),
+            state.clone(),
+            pot_x.clone(),
+            pot_y.clone(),
+            new_shared_cell(1),
+        );
+        Setup {
+            mouse,
+            pot_x,
+            pot_y,
+            state,
+        }
+    }
+
+    #[test]
+    fn proportional_motion_wraps() {
+        let mut setup = setup_mouse(Mode::Proportional);
+        assert_eq!(0x00, setup.pot_x.get());
+        setup.mouse.on_motion(5, 0);
+        assert_eq!(0x0a, setup.pot_x.get());
+        setup.mouse.on_motion(60, 0);
+        assert_eq!(0x02, setup.pot_x.get());
+        setup.mouse.on_motion(-3, 0);
+        assert_eq!(0x7c, setup.pot_x.get());
+        setup.mouse.on_motion(0, -2);
+        assert_eq!(0x04, setup.pot_y.get());
+    }
+
+    #[test]
+    fn proportional_buttons() {
+        let mut setup = setup_mouse(Mode::Proportional);
+        setup.mouse.on_button_down(MouseButton::Left);
+        assert_eq!(0x10, setup.state.get());
+        setup.mouse.on_button_down(MouseButton::Right);
+        assert_eq!(0x11, setup.state.get());
+        setup.mouse.on_button_up(MouseButton::Left);
+        setup.mouse.on_button_up(MouseButton::Right);
+        assert_eq!(0x00, setup.state.get());
+    }
+
+    #[test]
+    fn joystick_mode() {
+        let mut setup = setup_mouse(Mode::Joystick);
+        assert_eq!(0xff, setup.pot_x.get());
+        setup.mouse.on_motion(3, -1);
+        assert_eq!(0x09, setup.state.get());
+        setup.mouse.on_motion(0, 0);
+        assert_eq!(0x00, setup.state.get());
+        setup.mouse.on_button_down(MouseButton::Right);
+        assert_eq!(0x00, setup.pot_x.get());
+        assert_eq!(0xff, setup.pot_y.get());
+        setup.mouse.on_button_up(MouseButton::Right);
+        assert_eq!(0xff, setup.pot_x.get());
+    }
+}
@@ -0,0 +1,175 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::string::String;
+use bit_field::BitField;
+
+use super::joystick::Button;
+use crate::util::{SharedCell, Snapshot, SnapshotReader};
+
+// SPEC: https://www.c64-wiki.com/wiki/Paddle
+
+// Design:
+//   Paddles come in pairs, the pair on a control port shares its POTX and POTY lines
+//   with the SID through an analog switch selected by PA6/PA7 of CIA 1. Each paddle is
+//   a 470K potentiometer charging a 1nF capacitor, so the count measured by the SID is
+//   linear in the paddle position. The fire buttons use the joystick left and right
+//   lines of the same port.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+impl Axis {
+    fn fire_button(self) -> Button {
+        match self {
+            Axis::X => Button::Left,
+            Axis::Y => Button::Right,
+        }
+    }
+}
+
+pub struct Paddle {
+    // Configuration
+    axis: Axis,
+    port: u8,
+    // Dependencies
+    pot: SharedCell<u8>,
+    port_select: SharedCell<u8>,
+    // State
+    position: SharedCell<u8>,
+    state: SharedCell<u8>,
+}
+
+impl Paddle {
+    /// Constructs paddle on control port `port` (1 or 2) connected to `pot`, the SID POT
+    /// input for `axis`, while `port_select` matches the port.
+    pub fn new(
+        port: u8,
+        axis: Axis,
+        position: SharedCell<u8>,
+        state: SharedCell<u8>,
+        pot: SharedCell<u8>,
+        port_select: SharedCell<u8>,
+    ) -> Self {
+        Self {
+            axis,
+            port,
+            pot,
+            port_select,
+            position,
+            state,
+        }
+    }
+
+    pub fn get_axis(&self) -> Axis {
+        self.axis
+    }
+
+    pub fn get_port(&self) -> u8 {
+        self.port
+    }
+
+    pub fn get_position(&self) -> u8 {
+        self.position.get()
+    }
+
+    /// Set paddle position, 0 is turned fully clockwise and 255 fully counter-clockwise.
+    pub fn set_position(&mut self, position: u8) {
+        self.position.set(position);
+        if self.port_select.get() == self.port {
+            self.pot.set(position);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.set_fire(false);
+    }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.position.get());
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        let position = snapshot.read_u8()?;
+        self.set_position(position);
+        Ok(())
+    }
+
+    fn set_fire(&mut self, pressed: bool) {
+        let mut new_state = self.state.get();
+        new_state.set_bit(self.axis.fire_button().bit(), pressed);
+        self.state.set(new_state);
+    }
+
+    // -- Event Handlers
+
+    pub fn on_axis_motion(&mut self, value: i16) {
+        let position = 255 - ((value as i32 + 32768) >> 8) as u8;
+        self.set_position(position);
+    }
+
+    pub fn on_button_down(&mut self) {
+        self.set_fire(true);
+    }
+
+    pub fn on_button_up(&mut self) {
+        self.set_fire(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::new_shared_cell;
+
+    fn setup_paddle(axis: Axis) -> (Paddle, SharedCell<u8>, SharedCell<u8>, SharedCell<u8>) {
+        let state = new_shared_cell(0u8);
+        let pot = new_shared_cell(0xffu8);
+        let port_select = new_shared_cell(1u8);
+        let paddle = Paddle::new(
+            1,
+            axis,
+            new_shared_cell(0x80),
+            state.clone(),
+            pot.clone(),
+            port_select.clone(),
+        );
+        (paddle, state, pot, port_select)
+    }
+
+    #[test]
+    fn position_selected_port() {
+        let (mut paddle, _, pot, port_select) = setup_paddle(Axis::X);
+        paddle.set_position(0x40);
+        assert_eq!(0x40, pot.get());
+        port_select.set(2);
+        paddle.set_position(0x20);
+        assert_eq!(0x40, pot.get());
+        assert_eq!(0x20, paddle.get_position());
+    }
+
+    #[test]
+    fn axis_motion() {
+        let (mut paddle, _, _, _) = setup_paddle(Axis::X);
+        paddle.on_axis_motion(i16::min_value());
+        assert_eq!(0xff, paddle.get_position());
+        paddle.on_axis_motion(i16::max_value());
+        assert_eq!(0x00, paddle.get_position());
+    }
+
+    #[test]
+    fn fire_buttons() {
+        let (mut paddle_x, state, _, _) = setup_paddle(Axis::X);
+        paddle_x.on_button_down();
+        assert_eq!(0x04, state.get());
+        paddle_x.on_button_up();
+        assert_eq!(0x00, state.get());
+        let (mut paddle_y, state, _, _) = setup_paddle(Axis::Y);
+        paddle_y.on_button_down();
+        assert_eq!(0x08, state.get());
+    }
+}
@@ -0,0 +1,461 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::{vec, vec::Vec};
+use bit_field::BitField;
+use log::{log, trace};
+
+use crate::factory::Peripheral;
+use crate::util::{Ram, Shared, Snapshot, SnapshotReader};
+
+// SPEC: Commodore 1700/1764/1750 RAM Expansion Module User's Guide
+
+// Design:
+//   DMA transfers move all bytes as soon as the command is written, then hold the DMA
+//   line for one cycle per byte so the CPU is stalled for the duration of the transfer.
+//   Transfers see the system RAM only, so I/O and ROM are never the source or target of
+//   a transfer. The $FF00 trigger is treated like an immediate start.
+
+mod reg {
+    pub const STATUS: u8 = 0x00;
+    pub const COMMAND: u8 = 0x01;
+    pub const C64_ADDR_LO: u8 = 0x02;
+    pub const C64_ADDR_HI: u8 = 0x03;
+    pub const REU_ADDR_LO: u8 = 0x04;
+    pub const REU_ADDR_HI: u8 = 0x05;
+    pub const REU_BANK: u8 = 0x06;
+    pub const LENGTH_LO: u8 = 0x07;
+    pub const LENGTH_HI: u8 = 0x08;
+    pub const IRQ_MASK: u8 = 0x09;
+    pub const ADDR_CONTROL: u8 = 0x0a;
+}
+
+mod status {
+    pub const IRQ: usize = 7;
+    pub const END_OF_BLOCK: usize = 6;
+    pub const VERIFY_ERROR: usize = 5;
+    pub const SIZE: usize = 4;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transfer {
+    Stash,
+    Fetch,
+    Swap,
+    Verify,
+}
+
+impl Transfer {
+    pub fn from(command: u8) -> Transfer {
+        match command & 0x03 {
+            0x00 => Transfer::Stash,
+            0x01 => Transfer::Fetch,
+            0x02 => Transfer::Swap,
+            _ => Transfer::Verify,
+        }
+    }
+}
+
+/// RAM Expansion Unit with its DMA controller mapped at $DF00. Supported sizes are
+/// 128KB (1700), 256KB (1764) and 512KB (1750).
+pub struct Reu {
+    // Dependencies
+    ram: Shared<Ram>,
+    // Runtime state
+    data: Vec<u8>,
+    dma_cycles: u32,
+    status: u8,
+    command: u8,
+    c64_address: u16,
+    reu_address: u32,
+    length: u16,
+    irq_mask: u8,
+    address_control: u8,
+    // Autoload
+    c64_address_shadow: u16,
+    reu_address_shadow: u32,
+    length_shadow: u16,
+}
+
+impl Reu {
+    pub fn new(size: usize, ram: Shared<Ram>) -> Result<Self, String> {
+        Self::check_size(size)?;
+        Ok(Reu {
+            ram,
+            data: vec![0; size],
+            dma_cycles: 0,
+            status: 0,
+            command: 0x10,
+            c64_address: 0,
+            reu_address: 0,
+            length: 0xffff,
+            irq_mask: 0,
+            address_control: 0,
+            c64_address_shadow: 0,
+            reu_address_shadow: 0,
+            length_shadow: 0xffff,
+        })
+    }
+
+    /// Check that `size` is one of the supported expansion sizes.
+    pub fn check_size(size: usize) -> Result<(), String> {
+        match size {
+            0x20000 | 0x40000 | 0x80000 => Ok(()),
+            _ => Err(format!("invalid reu size {}", size)),
+        }
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn execute(&mut self) {
+        let transfer = Transfer::from(self.command);
+        let fix_c64 = self.address_control.get_bit(7);
+        let fix_reu = self.address_control.get_bit(6);
+        let reu_mask = self.data.len() as u32 - 1;
+        trace!(target: "reu", "Transfer {:?} c64 0x{:04x} reu 0x{:05x} length {}",
+               transfer, self.c64_address, self.reu_address, self.length);
+        let mut c64_address = self.c64_address;
+        let mut reu_address = self.reu_address & reu_mask;
+        let mut length = self.length;
+        let mut transferred = 0u32;
+        loop {
+            transferred += 1;
+            let mut verify_error = false;
+            match transfer {
+                Transfer::Stash => {
+                    self.data[reu_address as usize] = self.ram.borrow().read(c64_address);
+                }
+                Transfer::Fetch => {
+                    self.ram
+                        .borrow_mut()
+                        .write(c64_address, self.data[reu_address as usize]);
+                }
+                Transfer::Swap => {
+                    let value = self.ram.borrow().read(c64_address);
+                    self.ram
+                        .borrow_mut()
+                        .write(c64_address, self.data[reu_address as usize]);
+                    self.data[reu_address as usize] = value;
+                }
+                Transfer::Verify => {
+                    verify_error =
+                        self.ram.borrow().read(c64_address) != self.data[reu_address as usize];
+                }
+            }
+            if !fix_c64 {
+                c64_address = c64_address.wrapping_add(1);
+            }
+            if !fix_reu {
+                reu_address = (reu_address + 1) & reu_mask;
+            }
+            if length == 1 {
+                self.status.set_bit(status::END_OF_BLOCK, true);
+                self.status.set_bit(status::VERIFY_ERROR, verify_error);
+                break;
+            }
+            length = length.wrapping_sub(1);
+            if verify_error {
+                self.status.set_bit(status::VERIFY_ERROR, true);
+                break;
+            }
+        }
+        if self.command.get_bit(5) {
+            self.c64_address = self.c64_address_shadow;
+            self.reu_address = self.reu_address_shadow;
+            self.length = self.length_shadow;
+        } else {
+            self.c64_address = c64_address;
+            self.reu_address = reu_address;
+            self.length = length;
+        }
+        self.command.set_bit(7, false);
+        self.command.set_bit(4, true);
+        self.dma_cycles = transferred;
+        self.update_irq();
+    }
+
+    fn update_irq(&mut self) {
+        if self.irq_mask.get_bit(7) && (self.irq_mask & self.status & 0x60) != 0 {
+            self.status.set_bit(status::IRQ, true);
+        }
+    }
+
+    fn read_reg(&mut self, reg: u8) -> u8 {
+        let value = self.peek_reg(reg);
+        // Reading status acknowledges the interrupt and clears the flags
+        if reg == reg::STATUS {
+            self.status = 0;
+        }
+        value
+    }
+
+    fn peek_reg(&self, reg: u8) -> u8 {
+        match reg {
+            reg::STATUS => {
+                let mut value = self.status;
+                value.set_bit(status::SIZE, self.data.len() > 0x20000);
+                value
+            }
+            reg::COMMAND => self.command,
+            reg::C64_ADDR_LO => self.c64_address as u8,
+            reg::C64_ADDR_HI => (self.c64_address >> 8) as u8,
+            reg::REU_ADDR_LO => self.reu_address as u8,
+            reg::REU_ADDR_HI => (self.reu_address >> 8) as u8,
+            reg::REU_BANK => (self.reu_address >> 16) as u8 | 0xf8,
+            reg::LENGTH_LO => self.length as u8,
+            reg::LENGTH_HI => (self.length >> 8) as u8,
+            reg::IRQ_MASK => self.irq_mask | 0x1f,
+            reg::ADDR_CONTROL => self.address_control | 0x3f,
+            _ => 0xff,
+        }
+    }
+
+    fn write_reg(&mut self, reg: u8, value: u8) {
+        // Address and length writes also load the autoload registers
+        match reg {
+            reg::COMMAND => {
+                self.command = value;
+                if value.get_bit(7) {
+                    self.execute();
+                }
+            }
+            reg::C64_ADDR_LO => {
+                self.c64_address = (self.c64_address & 0xff00) | value as u16;
+                self.c64_address_shadow = self.c64_address;
+            }
+            reg::C64_ADDR_HI => {
+                self.c64_address = (self.c64_address & 0x00ff) | (value as u16) << 8;
+                self.c64_address_shadow = self.c64_address;
+            }
+            reg::REU_ADDR_LO => {
+                self.reu_address = (self.reu_address & 0x7ff00) | value as u32;
+                self.reu_address_shadow = self.reu_address;
+            }
+            reg::REU_ADDR_HI => {
+                self.reu_address = (self.reu_address & 0x700ff) | (value as u32) << 8;
+                self.reu_address_shadow = self.reu_address;
+            }
+            reg::REU_BANK => {
+                self.reu_address = (self.reu_address & 0x0ffff) | ((value & 0x07) as u32) << 16;
+                self.reu_address_shadow = self.reu_address;
+            }
+            reg::LENGTH_LO => {
+                self.length = (self.length & 0xff00) | value as u16;
+                self.length_shadow = self.length;
+            }
+            reg::LENGTH_HI => {
+                self.length = (self.length & 0x00ff) | (value as u16) << 8;
+                self.length_shadow = self.length;
+            }
+            reg::IRQ_MASK => {
+                self.irq_mask = value & 0xe0;
+                self.update_irq();
+            }
+            reg::ADDR_CONTROL => self.address_control = value & 0xc0,
+            _ => {}
+        }
+    }
+}
+
+impl Peripheral for Reu {
+    fn clock(&mut self) {
+        if self.dma_cycles > 0 {
+            self.dma_cycles -= 1;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.dma_cycles = 0;
+        self.status = 0;
+        self.command = 0x10;
+        self.c64_address = 0;
+        self.reu_address = 0;
+        self.length = 0xffff;
+        self.irq_mask = 0;
+        self.address_control = 0;
+        self.c64_address_shadow = 0;
+        self.reu_address_shadow = 0;
+        self.length_shadow = 0xffff;
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // Registers are mirrored every 32 bytes
+            0xdf00..=0xdfff => Some(self.read_reg((address & 0x1f) as u8)),
+            _ => None,
+        }
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0xdf00..=0xdfff => Some(self.peek_reg((address & 0x1f) as u8)),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if let 0xdf00..=0xdfff = address {
+            self.write_reg((address & 0x1f) as u8, value);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.status.get_bit(status::IRQ)
+    }
+
+    fn dma(&self) -> bool {
+        self.dma_cycles > 0
+    }
+
+    fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bytes(&self.data);
+        snapshot.write_u32(self.dma_cycles);
+        snapshot.write_u8(self.status);
+        snapshot.write_u8(self.command);
+        snapshot.write_u16(self.c64_address);
+        snapshot.write_u32(self.reu_address);
+        snapshot.write_u16(self.length);
+        snapshot.write_u8(self.irq_mask);
+        snapshot.write_u8(self.address_control);
+        snapshot.write_u16(self.c64_address_shadow);
+        snapshot.write_u32(self.reu_address_shadow);
+        snapshot.write_u16(self.length_shadow);
+    }
+
+    fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        snapshot.read_into(&mut self.data)?;
+        self.dma_cycles = snapshot.read_u32()?;
+        self.status = snapshot.read_u8()?;
+        self.command = snapshot.read_u8()?;
+        self.c64_address = snapshot.read_u16()?;
+        self.reu_address = snapshot.read_u32()?;
+        self.length = snapshot.read_u16()?;
+        self.irq_mask = snapshot.read_u8()?;
+        self.address_control = snapshot.read_u8()?;
+        self.c64_address_shadow = snapshot.read_u16()?;
+        self.reu_address_shadow = snapshot.read_u32()?;
+        self.length_shadow = snapshot.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::new_shared;
+
+    fn setup_reu(size: usize) -> (Reu, Shared<Ram>) {
+        let ram = new_shared(Ram::new(0x10000));
+        let reu = Reu::new(size, ram.clone()).unwrap();
+        (reu, ram)
+    }
+
+    fn setup_transfer(reu: &mut Reu, c64_address: u16, reu_address: u32, length: u16) {
+        reu.write(0xdf02, c64_address as u8);
+        reu.write(0xdf03, (c64_address >> 8) as u8);
+        reu.write(0xdf04, reu_address as u8);
+        reu.write(0xdf05, (reu_address >> 8) as u8);
+        reu.write(0xdf06, (reu_address >> 16) as u8);
+        reu.write(0xdf07, length as u8);
+        reu.write(0xdf08, (length >> 8) as u8);
+    }
+
+    #[test]
+    fn stash_and_fetch() {
+        let (mut reu, ram) = setup_reu(0x40000);
+        for i in 0..0x100u16 {
+            ram.borrow_mut().write(0x1000 + i, i as u8);
+        }
+        setup_transfer(&mut reu, 0x1000, 0x31234, 0x100);
+        reu.write(0xdf01, 0xb0); // stash with autoload
+        assert_eq!(0x7f, reu.get_data()[0x31234 + 0x7f]);
+        assert_eq!(0x50, reu.read(0xdf00).unwrap() & 0x50);
+        // Autoload restored the registers
+        assert_eq!(0x10, reu.read(0xdf03).unwrap());
+        assert_eq!(0xfb, reu.read(0xdf06).unwrap());
+        for i in 0..0x100u16 {
+            ram.borrow_mut().write(0x1000 + i, 0);
+        }
+        setup_transfer(&mut reu, 0x2000, 0x31234, 0x100);
+        reu.write(0xdf01, 0x91); // fetch
+        for i in 0..0x100u16 {
+            assert_eq!(i as u8, ram.borrow().read(0x2000 + i));
+        }
+        // Registers point past the transfer without autoload
+        assert_eq!(0x21, reu.read(0xdf03).unwrap());
+        assert_eq!(0x01, reu.read(0xdf07).unwrap());
+    }
+
+    #[test]
+    fn swap() {
+        let (mut reu, ram) = setup_reu(0x20000);
+        ram.borrow_mut().write(0x4000, 0xaa);
+        setup_transfer(&mut reu, 0x4000, 0x0000, 1);
+        reu.write(0xdf01, 0x90);
+        ram.borrow_mut().write(0x4000, 0x55);
+        setup_transfer(&mut reu, 0x4000, 0x0000, 1);
+        reu.write(0xdf01, 0x92);
+        assert_eq!(0xaa, ram.borrow().read(0x4000));
+        assert_eq!(0x55, reu.get_data()[0]);
+    }
+
+    #[test]
+    fn verify_error() {
+        let (mut reu, ram) = setup_reu(0x80000);
+        setup_transfer(&mut reu, 0x1000, 0x70000, 0x10);
+        reu.write(0xdf01, 0x90);
+        setup_transfer(&mut reu, 0x1000, 0x70000, 0x10);
+        reu.write(0xdf01, 0x93);
+        assert_eq!(0x40, reu.read(0xdf00).unwrap() & 0xe0);
+        ram.borrow_mut().write(0x1004, 0xff);
+        reu.write(0xdf09, 0xa0); // irq on verify error
+        setup_transfer(&mut reu, 0x1000, 0x70000, 0x10);
+        reu.write(0xdf01, 0x93);
+        assert!(reu.irq());
+        // Transfer stops past the mismatched byte
+        assert_eq!(0x05, reu.read(0xdf02).unwrap());
+        assert_eq!(0xb0, reu.read(0xdf00).unwrap() & 0xf0);
+        assert!(!reu.irq());
+        assert_eq!(0x00, reu.read(0xdf00).unwrap() & 0xe0);
+    }
+
+    #[test]
+    fn peek_leaves_status_unchanged() {
+        let (mut reu, _) = setup_reu(0x20000);
+        reu.write(0xdf09, 0xc0); // irq on end of block
+        setup_transfer(&mut reu, 0x1000, 0x0000, 0x10);
+        reu.write(0xdf01, 0x90);
+        assert_eq!(Some(0xc0), reu.peek(0xdf00).map(|value| value & 0xe0));
+        assert!(reu.irq());
+        assert_eq!(0xc0, reu.read(0xdf00).unwrap() & 0xe0);
+        assert!(!reu.irq());
+        assert_eq!(None, reu.peek(0xde00));
+    }
+
+    #[test]
+    fn dma_stalls_for_transfer_length() {
+        let (mut reu, _) = setup_reu(0x20000);
+        assert!(!reu.dma());
+        setup_transfer(&mut reu, 0x1000, 0x0000, 0x10);
+        reu.write(0xdf01, 0x90);
+        for _ in 0..0x10 {
+            assert!(reu.dma());
+            reu.clock();
+        }
+        assert!(!reu.dma());
+    }
+
+    #[test]
+    fn registers_mirrored() {
+        let (mut reu, _) = setup_reu(0x20000);
+        reu.write(0xdf22, 0x34);
+        assert_eq!(0x34, reu.read(0xdf02).unwrap());
+        assert_eq!(0xff, reu.read(0xdf1f).unwrap());
+        assert_eq!(0x00, reu.read(0xdf00).unwrap() & 0x10);
+    }
+}
@@ -0,0 +1,304 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use bit_field::BitField;
+use log::{log, trace};
+
+use crate::factory::SerialLink;
+use crate::util::{IoPort, Pin, Shared, Snapshot, SnapshotReader};
+
+// SPEC: https://www.c64-wiki.com/wiki/RS-232
+// SPEC: Commodore 64 Programmer's Reference Guide, Chapter 6, RS-232 Interface
+
+// Design:
+//   Rs232 models a user port interface wired the way the kernal expects it. TXD is
+//   output on PA2 of CIA 2 and RXD is input on PB0 and the FLAG pin, so the start
+//   bit of an incoming byte raises the NMI that starts the kernal receive routine.
+//   The kernal bit-bangs both directions from CIA 2 timer NMIs, timed for the baud
+//   rate selected when the channel is opened. The interface side runs a UART at the
+//   same configured rate with 8 data bits, no parity and 1 stop bit. The CTS, DCD and
+//   DSR inputs are left high so the remote end always appears ready.
+
+enum UserPortLine {
+    RxdIn = 0,
+    TxdOut = 2,
+}
+
+impl UserPortLine {
+    pub fn bit(self) -> usize {
+        self as usize
+    }
+}
+
+const FRAME_BITS: u8 = 10;
+
+pub struct Rs232 {
+    // Configuration
+    cycles_per_bit: u32,
+    // Dependencies
+    port_a: Shared<IoPort>,
+    port_b: Shared<IoPort>,
+    flag_pin: Shared<Pin>,
+    link: Option<Box<dyn SerialLink>>,
+    // Runtime State
+    rx_bit: u8,
+    rx_frame: u16,
+    rx_timer: u32,
+    tx_active: bool,
+    tx_bit: u8,
+    tx_data: u8,
+    tx_last: bool,
+    tx_timer: u32,
+}
+
+impl Rs232 {
+    pub fn new(port_a: Shared<IoPort>, port_b: Shared<IoPort>, flag_pin: Shared<Pin>) -> Self {
+        Self {
+            cycles_per_bit: 1,
+            port_a,
+            port_b,
+            flag_pin,
+            link: None,
+            rx_bit: 0,
+            rx_frame: 0,
+            rx_timer: 0,
+            tx_active: false,
+            tx_bit: 0,
+            tx_data: 0,
+            tx_last: true,
+            tx_timer: 0,
+        }
+    }
+
+    /// Connect the interface to `link` running at `baud_rate` for a cpu clocked at `cpu_freq`.
+    pub fn attach(&mut self, link: Box<dyn SerialLink>, baud_rate: u32, cpu_freq: u32) {
+        self.cycles_per_bit = (cpu_freq / baud_rate.max(1)).max(1);
+        self.link = Some(link);
+        self.reset();
+    }
+
+    pub fn detach(&mut self) {
+        self.reset();
+        self.link = None;
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.link.is_some()
+    }
+
+    pub fn clock(&mut self) {
+        if self.link.is_none() {
+            return;
+        }
+        self.clock_transmit();
+        self.clock_receive();
+    }
+
+    pub fn reset(&mut self) {
+        self.rx_bit = 0;
+        self.rx_frame = 0;
+        self.rx_timer = 0;
+        self.tx_active = false;
+        self.tx_bit = 0;
+        self.tx_data = 0;
+        self.tx_last = true;
+        self.tx_timer = 0;
+        if self.link.is_some() {
+            self.set_rxd(true);
+        }
+    }
+
+    /// The link itself is host side and not part of the snapshot, only the state of
+    /// the frames in transit is.
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.rx_bit);
+        snapshot.write_u16(self.rx_frame);
+        snapshot.write_u32(self.rx_timer);
+        snapshot.write_bool(self.tx_active);
+        snapshot.write_u8(self.tx_bit);
+        snapshot.write_u8(self.tx_data);
+        snapshot.write_bool(self.tx_last);
+        snapshot.write_u32(self.tx_timer);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.rx_bit = snapshot.read_u8()?;
+        self.rx_frame = snapshot.read_u16()?;
+        self.rx_timer = snapshot.read_u32()?;
+        self.tx_active = snapshot.read_bool()?;
+        self.tx_bit = snapshot.read_u8()?;
+        self.tx_data = snapshot.read_u8()?;
+        self.tx_last = snapshot.read_bool()?;
+        self.tx_timer = snapshot.read_u32()?;
+        Ok(())
+    }
+
+    // -- Internal Ops
+
+    // Shift the frame sent by the computer on TXD, sampling each bit at its center.
+    fn clock_transmit(&mut self) {
+        let txd = self.port_a.borrow().get_value().get_bit(UserPortLine::TxdOut.bit());
+        if !self.tx_active {
+            if self.tx_last && !txd {
+                self.tx_active = true;
+                self.tx_bit = 0;
+                self.tx_data = 0;
+                self.tx_timer = self.cycles_per_bit + self.cycles_per_bit / 2;
+            }
+        } else {
+            self.tx_timer -= 1;
+            if self.tx_timer == 0 {
+                if self.tx_bit < 8 {
+                    self.tx_data.set_bit(self.tx_bit as usize, txd);
+                    self.tx_bit += 1;
+                    self.tx_timer = self.cycles_per_bit;
+                } else {
+                    if txd {
+                        let value = self.tx_data;
+                        if let Some(ref mut link) = self.link {
+                            link.send(value);
+                        }
+                    } else {
+                        trace!(target: "rs232", "Framing error");
+                    }
+                    self.tx_active = false;
+                }
+            }
+        }
+        self.tx_last = txd;
+    }
+
+    // Shift the frame received from the link onto RXD, polling the link once per bit time.
+    fn clock_receive(&mut self) {
+        if self.rx_timer > 0 {
+            self.rx_timer -= 1;
+            return;
+        }
+        self.rx_timer = self.cycles_per_bit - 1;
+        if self.rx_bit == 0 {
+            let received = match self.link {
+                Some(ref mut link) => link.receive(),
+                None => None,
+            };
+            match received {
+                Some(value) => {
+                    // Start bit, 8 data bits and the stop bit, LSB first
+                    self.rx_frame = ((value as u16) << 1) | 0x200;
+                    self.rx_bit = FRAME_BITS;
+                }
+                None => return,
+            }
+        }
+        let level = self.rx_frame.get_bit(0);
+        self.rx_frame >>= 1;
+        self.rx_bit -= 1;
+        self.set_rxd(level);
+    }
+
+    fn set_rxd(&mut self, level: bool) {
+        self.port_b
+            .borrow_mut()
+            .set_input_bit(UserPortLine::RxdIn.bit(), level);
+        self.flag_pin.borrow_mut().set_active(level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::new_shared;
+    use alloc::collections::VecDeque;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    struct Loopback {
+        buffer: Rc<RefCell<VecDeque<u8>>>,
+    }
+
+    impl SerialLink for Loopback {
+        fn receive(&mut self) -> Option<u8> {
+            self.buffer.borrow_mut().pop_front()
+        }
+
+        fn send(&mut self, value: u8) {
+            self.buffer.borrow_mut().push_back(value);
+        }
+    }
+
+    const CYCLES_PER_BIT: u32 = 100;
+
+    fn setup_rs232() -> (Rs232, Shared<IoPort>, Shared<IoPort>, Shared<Pin>) {
+        let port_a = new_shared(IoPort::new(0x04, 0xff));
+        let port_b = new_shared(IoPort::new(0x00, 0xff));
+        let flag_pin = new_shared(Pin::new_high());
+        let mut rs232 = Rs232::new(port_a.clone(), port_b.clone(), flag_pin.clone());
+        let link = Loopback {
+            buffer: Rc::new(RefCell::new(VecDeque::new())),
+        };
+        rs232.attach(Box::new(link), 10_000, 10_000 * CYCLES_PER_BIT);
+        port_a.borrow_mut().set_value(0x04);
+        (rs232, port_a, port_b, flag_pin)
+    }
+
+    fn run(rs232: &mut Rs232, cycles: u32) {
+        for _ in 0..cycles {
+            rs232.clock();
+        }
+    }
+
+    fn transmit(rs232: &mut Rs232, port_a: &Shared<IoPort>, value: u8) {
+        let frame = ((value as u16) << 1) | 0x200;
+        for i in 0..FRAME_BITS {
+            let level = frame.get_bit(i as usize);
+            port_a.borrow_mut().set_value(if level { 0x04 } else { 0x00 });
+            run(rs232, CYCLES_PER_BIT);
+        }
+    }
+
+    #[test]
+    fn idle_lines() {
+        let (mut rs232, _, port_b, flag_pin) = setup_rs232();
+        run(&mut rs232, 10 * CYCLES_PER_BIT);
+        assert!(port_b.borrow().get_value().get_bit(0));
+        assert!(flag_pin.borrow().is_high());
+    }
+
+    #[test]
+    fn loopback_byte() {
+        let (mut rs232, port_a, port_b, flag_pin) = setup_rs232();
+        run(&mut rs232, CYCLES_PER_BIT);
+        transmit(&mut rs232, &port_a, 0xa5);
+        // Wait for the start bit to come back on RXD and FLAG
+        let mut cycles = 0;
+        while port_b.borrow().get_value().get_bit(0) {
+            rs232.clock();
+            cycles += 1;
+            assert!(cycles <= 2 * CYCLES_PER_BIT);
+        }
+        assert!(flag_pin.borrow().is_low());
+        run(&mut rs232, CYCLES_PER_BIT / 2);
+        let mut value = 0u8;
+        for i in 0..8 {
+            run(&mut rs232, CYCLES_PER_BIT);
+            value.set_bit(i, port_b.borrow().get_value().get_bit(0));
+        }
+        run(&mut rs232, CYCLES_PER_BIT);
+        assert!(port_b.borrow().get_value().get_bit(0));
+        assert_eq!(0xa5, value);
+    }
+
+    #[test]
+    fn framing_error() {
+        let (mut rs232, port_a, port_b, _) = setup_rs232();
+        run(&mut rs232, CYCLES_PER_BIT);
+        // Break, TXD held low through the stop bit
+        port_a.borrow_mut().set_value(0x00);
+        run(&mut rs232, 12 * CYCLES_PER_BIT);
+        port_a.borrow_mut().set_value(0x04);
+        run(&mut rs232, 12 * CYCLES_PER_BIT);
+        assert!(port_b.borrow().get_value().get_bit(0));
+    }
+}
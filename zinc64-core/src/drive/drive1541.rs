@@ -0,0 +1,292 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bit_field::BitField;
+
+use crate::cpu::Cpu6510;
+use crate::factory::{Addressable, Cpu, Register, TickFn};
+use crate::io::{IecBus, IecLine};
+use crate::util::{
+    new_shared, new_shared_cell, IoPort, IrqLine, Pin, Ram, Shared, SharedCell, Snapshot,
+    SnapshotReader,
+};
+
+use super::gcr::GcrDisk;
+use super::mechanism::Mechanism;
+use super::via::Via;
+
+// SPEC: http://www.unusedino.de/ec64/technical/aay/c1541/
+
+// Design:
+//   The drive runs its own 6502 with 2KB of RAM, the 16KB DOS ROM and two VIAs. VIA 1
+//   connects to the serial bus and VIA 2 to the disk mechanism, which spins a D64 image
+//   encoded to GCR under the head. BYTE READY sets the overflow flag of the CPU through
+//   its SO input while VIA 2 holds CA2 high, the way the DOS waits for disk data with
+//   BVC loops. The drive is clocked in lockstep with the computer, one cycle per host
+//   cycle. Disks are read only.
+
+const RAM_SIZE: usize = 0x0800;
+const ROM_SIZE: usize = 0x4000;
+
+const VIA_1_IRQ: usize = 0;
+const VIA_2_IRQ: usize = 1;
+
+// VIA 1 port B serial bus lines, inputs are inverted so a set bit means the line is low
+enum SerialPort {
+    DataIn = 0,
+    DataOut = 1,
+    ClkIn = 2,
+    ClkOut = 3,
+    AtnAck = 4,
+    AtnIn = 7,
+}
+
+impl SerialPort {
+    pub fn bit(self) -> usize {
+        self as usize
+    }
+}
+
+struct DriveMemory {
+    ram: Ram,
+    rom: Vec<u8>,
+    via_1: Shared<Via>,
+    via_2: Shared<Via>,
+}
+
+impl DriveMemory {
+    fn peek(&self, address: u16) -> u8 {
+        if address >= 0x8000 {
+            return self.rom[address as usize & (ROM_SIZE - 1)];
+        }
+        match address & 0x1fff {
+            0x0000..=0x07ff => self.ram.read(address & 0x07ff),
+            0x1800..=0x1bff => self.via_1.borrow().peek(address as u8),
+            0x1c00..=0x1fff => self.via_2.borrow().peek(address as u8),
+            _ => (address >> 8) as u8,
+        }
+    }
+}
+
+impl Addressable for DriveMemory {
+    fn read(&self, address: u16) -> u8 {
+        if address >= 0x8000 {
+            return self.rom[address as usize & (ROM_SIZE - 1)];
+        }
+        match address & 0x1fff {
+            0x0000..=0x07ff => self.ram.read(address & 0x07ff),
+            0x1800..=0x1bff => self.via_1.borrow_mut().read(address as u8),
+            0x1c00..=0x1fff => self.via_2.borrow_mut().read(address as u8),
+            _ => (address >> 8) as u8,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            return;
+        }
+        match address & 0x1fff {
+            0x0000..=0x07ff => self.ram.write(address & 0x07ff, value),
+            0x1800..=0x1bff => self.via_1.borrow_mut().write(address as u8, value),
+            0x1c00..=0x1fff => self.via_2.borrow_mut().write(address as u8, value),
+            _ => {}
+        }
+    }
+}
+
+pub struct Drive1541 {
+    // Dependencies
+    device: usize,
+    iec_bus: Shared<IecBus>,
+    // Chipset
+    cpu: Cpu6510,
+    mem: Shared<DriveMemory>,
+    via_1: Shared<Via>,
+    via_2: Shared<Via>,
+    mechanism: Shared<Mechanism>,
+    // Runtime State
+    byte_ready: SharedCell<bool>,
+    tick_fn: TickFn,
+}
+
+impl Drive1541 {
+    /// Constructs a drive with the given bus address (8-11) running the 16KB DOS `rom`.
+    pub fn new(device: usize, rom: Vec<u8>, iec_bus: Shared<IecBus>) -> Self {
+        if !(8..=11).contains(&device) {
+            panic!("invalid drive device number {}", device);
+        }
+        if rom.len() != ROM_SIZE {
+            panic!("invalid drive rom size {}", rom.len());
+        }
+        let irq_line = new_shared(IrqLine::new("drive irq"));
+        let via_1_port_b = new_shared(IoPort::new(0x00, 0xff));
+        let via_1 = new_shared(Via::new(
+            VIA_1_IRQ,
+            new_shared(IoPort::new(0x00, 0xff)),
+            via_1_port_b.clone(),
+            irq_line.clone(),
+        ));
+        let via_2_port_a = new_shared(IoPort::new(0x00, 0xff));
+        let via_2_port_b = new_shared(IoPort::new(0x00, 0xff));
+        let via_2 = new_shared(Via::new(
+            VIA_2_IRQ,
+            via_2_port_a.clone(),
+            via_2_port_b.clone(),
+            irq_line.clone(),
+        ));
+        let mechanism = new_shared(Mechanism::new());
+        let byte_ready = new_shared_cell(false);
+        let mem = new_shared(DriveMemory {
+            ram: Ram::new(RAM_SIZE),
+            rom,
+            via_1: via_1.clone(),
+            via_2: via_2.clone(),
+        });
+        let mut cpu = Cpu6510::new(
+            mem.clone(),
+            new_shared(IoPort::new(0x00, 0xff)),
+            new_shared(Pin::new_high()),
+            irq_line,
+            new_shared(IrqLine::new("drive nmi")),
+        );
+        cpu.set_io_port_enabled(false);
+        let tick_fn: TickFn = {
+            let iec_bus_clone = iec_bus.clone();
+            let via_1_clone = via_1.clone();
+            let via_2_clone = via_2.clone();
+            let mechanism_clone = mechanism.clone();
+            let byte_ready_clone = byte_ready.clone();
+            Rc::new(move || {
+                via_1_clone.borrow_mut().clock();
+                via_2_clone.borrow_mut().clock();
+                Self::sync_iec_bus(device, &iec_bus_clone, &via_1_clone, &via_1_port_b);
+                let ready = mechanism_clone
+                    .borrow_mut()
+                    .clock(&mut via_2_port_b.borrow_mut(), &mut via_2_port_a.borrow_mut());
+                let mut via_2 = via_2_clone.borrow_mut();
+                via_2.set_ca1(!ready);
+                if ready && via_2.is_ca2_high() {
+                    byte_ready_clone.set(true);
+                }
+            })
+        };
+        Self {
+            device,
+            iec_bus,
+            cpu,
+            mem,
+            via_1,
+            via_2,
+            mechanism,
+            byte_ready,
+            tick_fn,
+        }
+    }
+
+    /// Insert `disk` with the head over the start of its tracks.
+    pub fn attach_disk(&mut self, disk: GcrDisk) {
+        self.mechanism.borrow_mut().attach(disk);
+    }
+
+    pub fn detach_disk(&mut self) {
+        self.mechanism.borrow_mut().detach();
+    }
+
+    pub fn has_disk(&self) -> bool {
+        self.mechanism.borrow().has_disk()
+    }
+
+    /// Head position in half tracks, 0 is track 1.
+    pub fn get_half_track(&self) -> u8 {
+        self.mechanism.borrow().get_half_track()
+    }
+
+    pub fn get_cpu(&self) -> &dyn Cpu {
+        &self.cpu
+    }
+
+    pub fn get_device(&self) -> usize {
+        self.device
+    }
+
+    pub fn clock(&mut self) {
+        let tick_fn = self.tick_fn.clone();
+        self.cpu.step_cycle(&tick_fn);
+        if self.byte_ready.get() {
+            self.byte_ready.set(false);
+            let p = self.cpu.get_register(Register::P);
+            self.cpu.set_register(Register::P, p | 0x40);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.iec_bus.borrow_mut().release(self.device);
+        self.via_1.borrow_mut().reset();
+        self.via_2.borrow_mut().reset();
+        self.mechanism.borrow_mut().reset();
+        self.byte_ready.set(false);
+        self.cpu.reset();
+    }
+
+    /// Read drive memory as seen by the drive CPU without side effects on the VIAs.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.mem.borrow().peek(address)
+    }
+
+    /// The DOS ROM and the disk are not part of the snapshot, the drive must run the
+    /// same ones.
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        self.cpu.save_state(snapshot);
+        self.mem.borrow().ram.save_state(snapshot);
+        self.via_1.borrow().save_state(snapshot);
+        self.via_2.borrow().save_state(snapshot);
+        self.mechanism.borrow().save_state(snapshot);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.cpu.load_state(snapshot)?;
+        self.mem.borrow_mut().ram.load_state(snapshot)?;
+        self.via_1.borrow_mut().load_state(snapshot)?;
+        self.via_2.borrow_mut().load_state(snapshot)?;
+        self.mechanism.borrow_mut().load_state(snapshot)?;
+        self.byte_ready.set(false);
+        Ok(())
+    }
+
+    fn sync_iec_bus(
+        device: usize,
+        iec_bus: &Shared<IecBus>,
+        via_1: &Shared<Via>,
+        port_b: &Shared<IoPort>,
+    ) {
+        let output = {
+            let port_b = port_b.borrow();
+            port_b.get_value() & port_b.get_direction()
+        };
+        let mut iec_bus = iec_bus.borrow_mut();
+        let atn = iec_bus.is_low(IecLine::Atn);
+        /*
+        The ATN acknowledge logic pulls DATA low in hardware whenever ATN and the ATNA
+        output disagree, so the drive answers ATN even before the DOS gets to it.
+        */
+        let atn_ack = output.get_bit(SerialPort::AtnAck.bit());
+        let data_out = output.get_bit(SerialPort::DataOut.bit()) || atn != atn_ack;
+        iec_bus.set_low(device, IecLine::Data, data_out);
+        iec_bus.set_low(
+            device,
+            IecLine::Clk,
+            output.get_bit(SerialPort::ClkOut.bit()),
+        );
+        // Device address jumpers on PB5/PB6
+        let mut input = ((device - 8) as u8) << 5;
+        input.set_bit(SerialPort::DataIn.bit(), iec_bus.is_low(IecLine::Data));
+        input.set_bit(SerialPort::ClkIn.bit(), iec_bus.is_low(IecLine::Clk));
+        input.set_bit(SerialPort::AtnIn.bit(), atn);
+        port_b.borrow_mut().set_input(input);
+        via_1.borrow_mut().set_ca1(atn);
+    }
+}
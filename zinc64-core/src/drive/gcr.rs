@@ -0,0 +1,209 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// SPEC: http://www.unusedino.de/ec64/technical/formats/g64.html
+// SPEC: http://www.unusedino.de/ec64/technical/formats/d64.html
+
+// Design:
+//   A D64 image holds only sector data, so each track is encoded to the bit stream the
+//   DOS formats on a real disk. Every sector is written as a sync mark, the GCR encoded
+//   header block, a header gap, another sync mark and the GCR encoded data block,
+//   followed by a gap of $55 bytes padding the track to the length a disk spinning at
+//   300 rpm holds in its speed zone. The error info block of extended images is ignored.
+
+const SECTOR_SIZE: usize = 256;
+const SYNC_LENGTH: usize = 5;
+const HEADER_GAP_LENGTH: usize = 9;
+const GAP_BYTE: u8 = 0x55;
+const BAM_TRACK: u8 = 18;
+const BAM_ID_OFFSET: usize = 0xa2;
+
+const HEADER_BLOCK_ID: u8 = 0x08;
+const DATA_BLOCK_ID: u8 = 0x07;
+
+static GCR_CODES: [u8; 16] = [
+    0x0a, 0x0b, 0x12, 0x13, 0x0e, 0x0f, 0x16, 0x17, 0x09, 0x19, 0x1a, 0x1b, 0x0d, 0x1d, 0x1e,
+    0x15,
+];
+
+/// Disk in the 1541 format encoded as GCR tracks.
+pub struct GcrDisk {
+    tracks: Vec<Vec<u8>>,
+}
+
+impl GcrDisk {
+    /// Encode a D64 image with 35 or 40 tracks, with or without error info.
+    pub fn from_d64(data: &[u8]) -> Result<Self, String> {
+        let track_count = match data.len() {
+            174_848 | 175_531 => 35,
+            196_608 | 197_376 => 40,
+            _ => return Err(format!("invalid d64 image size {}", data.len())),
+        };
+        let bam = Self::sector_offset(BAM_TRACK, 0) + BAM_ID_OFFSET;
+        let id = [data[bam], data[bam + 1]];
+        let mut tracks = Vec::new();
+        for track in 1..=track_count {
+            let mut encoded = Vec::with_capacity(Self::track_size(track));
+            for sector in 0..Self::sectors_per_track(track) {
+                let offset = Self::sector_offset(track, sector);
+                Self::encode_sector(
+                    &mut encoded,
+                    track,
+                    sector,
+                    id,
+                    &data[offset..offset + SECTOR_SIZE],
+                );
+                encoded.resize(
+                    (sector as usize + 1) * Self::track_size(track)
+                        / Self::sectors_per_track(track) as usize,
+                    GAP_BYTE,
+                );
+            }
+            tracks.push(encoded);
+        }
+        Ok(Self { tracks })
+    }
+
+    /// Number of tracks on the disk.
+    pub fn get_track_count(&self) -> u8 {
+        self.tracks.len() as u8
+    }
+
+    /// GCR bytes of a track, numbered from 1.
+    pub fn get_track(&self, track: u8) -> Option<&[u8]> {
+        match track {
+            0 => None,
+            _ => self.tracks.get(track as usize - 1).map(|data| data.as_slice()),
+        }
+    }
+
+    /// Speed zone the DOS selects for the track, 3 is the fastest on the outer tracks.
+    pub fn speed_zone(track: u8) -> u8 {
+        match track {
+            0..=17 => 3,
+            18..=24 => 2,
+            25..=30 => 1,
+            _ => 0,
+        }
+    }
+
+    fn sectors_per_track(track: u8) -> u8 {
+        match Self::speed_zone(track) {
+            3 => 21,
+            2 => 19,
+            1 => 18,
+            _ => 17,
+        }
+    }
+
+    fn track_size(track: u8) -> usize {
+        match Self::speed_zone(track) {
+            3 => 7692,
+            2 => 7142,
+            1 => 6666,
+            _ => 6250,
+        }
+    }
+
+    fn sector_offset(track: u8, sector: u8) -> usize {
+        let blocks: usize = (1..track)
+            .map(|track| Self::sectors_per_track(track) as usize)
+            .sum();
+        (blocks + sector as usize) * SECTOR_SIZE
+    }
+
+    fn encode_sector(encoded: &mut Vec<u8>, track: u8, sector: u8, id: [u8; 2], data: &[u8]) {
+        let header = [
+            HEADER_BLOCK_ID,
+            sector ^ track ^ id[1] ^ id[0],
+            sector,
+            track,
+            id[1],
+            id[0],
+            0x0f,
+            0x0f,
+        ];
+        encoded.extend_from_slice(&[0xff; SYNC_LENGTH]);
+        encode_block(encoded, &header);
+        encoded.extend_from_slice(&[GAP_BYTE; HEADER_GAP_LENGTH]);
+        let mut block = Vec::with_capacity(SECTOR_SIZE + 4);
+        block.push(DATA_BLOCK_ID);
+        block.extend_from_slice(data);
+        block.push(data.iter().fold(0, |checksum, value| checksum ^ value));
+        block.extend_from_slice(&[0x00, 0x00]);
+        encoded.extend_from_slice(&[0xff; SYNC_LENGTH]);
+        encode_block(encoded, &block);
+    }
+}
+
+/// Encode bytes in groups of four, each nibble becomes a 5-bit GCR code.
+pub fn encode_block(encoded: &mut Vec<u8>, data: &[u8]) {
+    for group in data.chunks(4) {
+        let mut bits = 0u64;
+        for i in 0..4 {
+            let value = group.get(i).cloned().unwrap_or(0);
+            bits = (bits << 10)
+                | ((GCR_CODES[(value >> 4) as usize] as u64) << 5)
+                | GCR_CODES[(value & 0x0f) as usize] as u64;
+        }
+        for i in (0..5).rev() {
+            encoded.push((bits >> (i * 8)) as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn build_d64() -> Vec<u8> {
+        let mut data = vec![0u8; 174_848];
+        let bam = GcrDisk::sector_offset(18, 0);
+        data[bam + BAM_ID_OFFSET] = b'Z';
+        data[bam + BAM_ID_OFFSET + 1] = b'6';
+        data
+    }
+
+    #[test]
+    fn encode_gcr_group() {
+        let mut encoded = Vec::new();
+        encode_block(&mut encoded, &[0x00, 0x00, 0x00, 0x00, 0x08, 0x0f]);
+        assert_eq!(
+            vec![0x52, 0x94, 0xa5, 0x29, 0x4a, 0x52, 0x55, 0x55, 0x29, 0x4a],
+            encoded
+        );
+    }
+
+    #[test]
+    fn track_layout() {
+        let disk = GcrDisk::from_d64(&build_d64()).unwrap();
+        assert_eq!(35, disk.get_track_count());
+        assert_eq!(None, disk.get_track(0));
+        assert_eq!(None, disk.get_track(36));
+        for (track, size) in [(1u8, 7692usize), (18, 7142), (25, 6666), (35, 6250)].iter() {
+            let data = disk.get_track(*track).unwrap();
+            assert_eq!(*size, data.len());
+            assert_eq!(&[0xff; SYNC_LENGTH], &data[..SYNC_LENGTH]);
+        }
+        let mut header = Vec::new();
+        encode_block(&mut header, &[0x08, 18 ^ b'6' ^ b'Z', 0, 18, b'6', b'Z', 0x0f, 0x0f]);
+        let track = disk.get_track(18).unwrap();
+        assert_eq!(&header[..], &track[SYNC_LENGTH..SYNC_LENGTH + 10]);
+    }
+
+    #[test]
+    fn reject_invalid_size() {
+        assert!(GcrDisk::from_d64(&[0u8; 1000]).is_err());
+        assert!(GcrDisk::from_d64(&vec![0u8; 175_531]).is_ok());
+        assert_eq!(
+            40,
+            GcrDisk::from_d64(&vec![0u8; 196_608]).unwrap().get_track_count()
+        );
+    }
+}
@@ -0,0 +1,192 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::format;
+use alloc::string::String;
+use bit_field::BitField;
+
+use crate::util::{IoPort, Snapshot, SnapshotReader};
+
+use super::gcr::GcrDisk;
+
+// SPEC: http://www.unusedino.de/ec64/technical/aay/c1541/
+
+// Design:
+//   The disk is read a byte at a time instead of a bit at a time. The head moves to
+//   the next byte of the track every 26 to 32 cycles depending on the density selected
+//   by the DOS. Two $ff bytes in a row are ten or more one bits, so SYNC is reported
+//   while the head is over the second and later bytes of a sync mark. Every other byte
+//   is latched to VIA 2 port A and signals BYTE READY. Writing is not supported, the
+//   disk always reports write protection.
+
+const HALF_TRACKS: u8 = 84;
+const RESET_HALF_TRACK: u8 = 34;
+
+// Cycles per byte at each density, density 3 is used on the outer tracks
+static BYTE_CYCLES: [u8; 4] = [32, 30, 28, 26];
+
+// VIA 2 port B disk controller lines
+enum ControlPort {
+    Motor = 2,
+    WriteProtect = 4,
+    Sync = 7,
+}
+
+impl ControlPort {
+    pub fn bit(self) -> usize {
+        self as usize
+    }
+}
+
+pub struct Mechanism {
+    // Media
+    disk: Option<GcrDisk>,
+    // Runtime State
+    half_track: u8,
+    motor_on: bool,
+    density: u8,
+    position: usize,
+    cycles: u8,
+    last_byte: u8,
+    data: u8,
+    sync: bool,
+}
+
+impl Mechanism {
+    pub fn new() -> Self {
+        Self {
+            disk: None,
+            half_track: RESET_HALF_TRACK,
+            motor_on: false,
+            density: 0,
+            position: 0,
+            cycles: BYTE_CYCLES[0],
+            last_byte: 0,
+            data: 0,
+            sync: false,
+        }
+    }
+
+    pub fn attach(&mut self, disk: GcrDisk) {
+        self.disk = Some(disk);
+        self.position = 0;
+    }
+
+    pub fn detach(&mut self) {
+        self.disk = None;
+    }
+
+    pub fn has_disk(&self) -> bool {
+        self.disk.is_some()
+    }
+
+    /// Head position in half tracks, 0 is track 1.
+    pub fn get_half_track(&self) -> u8 {
+        self.half_track
+    }
+
+    /// Follow the stepper, motor and density outputs on `control` and rotate the disk.
+    /// Returns true when a byte was latched to `data_port`.
+    pub fn clock(&mut self, control: &mut IoPort, data_port: &mut IoPort) -> bool {
+        let output = control.get_value() & control.get_direction();
+        self.step(output & 0x03);
+        self.motor_on = output.get_bit(ControlPort::Motor.bit());
+        self.density = (output >> 5) & 0x03;
+        let byte_ready = self.motor_on && self.rotate();
+        let mut input = 0xff;
+        input.set_bit(ControlPort::WriteProtect.bit(), false);
+        input.set_bit(ControlPort::Sync.bit(), !self.sync);
+        control.set_input(input);
+        if byte_ready {
+            data_port.set_input(self.data);
+        }
+        byte_ready
+    }
+
+    pub fn reset(&mut self) {
+        self.motor_on = false;
+        self.density = 0;
+        self.cycles = BYTE_CYCLES[0];
+        self.last_byte = 0;
+        self.data = 0;
+        self.sync = false;
+    }
+
+    fn rotate(&mut self) -> bool {
+        self.cycles -= 1;
+        if self.cycles != 0 {
+            return false;
+        }
+        self.cycles = BYTE_CYCLES[self.density as usize];
+        let track = if self.half_track & 0x01 == 0 {
+            self.disk
+                .as_ref()
+                .and_then(|disk| disk.get_track(self.half_track / 2 + 1))
+        } else {
+            None
+        };
+        // A missing disk or track reads as a blank surface without any flux changes
+        let value = match track {
+            Some(track) => {
+                self.position %= track.len();
+                let value = track[self.position];
+                self.position += 1;
+                value
+            }
+            None => {
+                self.sync = false;
+                self.last_byte = 0x00;
+                return false;
+            }
+        };
+        self.sync = value == 0xff && self.last_byte == 0xff;
+        self.last_byte = value;
+        if !self.sync {
+            self.data = value;
+        }
+        !self.sync
+    }
+
+    // The energized stepper phase pulls the head half a track in or out when it is
+    // next to the one the head rests on.
+    fn step(&mut self, phase: u8) {
+        if phase == (self.half_track + 1) & 0x03 && self.half_track < HALF_TRACKS - 1 {
+            self.half_track += 1;
+        } else if phase == self.half_track.wrapping_sub(1) & 0x03 && self.half_track > 0 {
+            self.half_track -= 1;
+        }
+    }
+
+    /// The disk is not part of the snapshot, the same one must be attached.
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bool(self.disk.is_some());
+        snapshot.write_u8(self.half_track);
+        snapshot.write_bool(self.motor_on);
+        snapshot.write_u8(self.density);
+        snapshot.write_u32(self.position as u32);
+        snapshot.write_u8(self.cycles);
+        snapshot.write_u8(self.last_byte);
+        snapshot.write_u8(self.data);
+        snapshot.write_bool(self.sync);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        if snapshot.read_bool()? != self.disk.is_some() {
+            return Err(String::from("snapshot disk does not match drive"));
+        }
+        let half_track = snapshot.read_u8()?;
+        if half_track >= HALF_TRACKS {
+            return Err(format!("invalid head position {}", half_track));
+        }
+        self.half_track = half_track;
+        self.motor_on = snapshot.read_bool()?;
+        self.density = snapshot.read_u8()? & 0x03;
+        self.position = snapshot.read_u32()? as usize;
+        self.cycles = snapshot.read_u8()?.max(1);
+        self.last_byte = snapshot.read_u8()?;
+        self.data = snapshot.read_u8()?;
+        self.sync = snapshot.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,12 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+mod drive1541;
+pub mod gcr;
+mod mechanism;
+pub mod via;
+
+pub use self::drive1541::Drive1541;
+pub use self::gcr::GcrDisk;
+pub use self::via::Via;
@@ -0,0 +1,427 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::string::String;
+use bit_field::BitField;
+
+use crate::util::{IoPort, IrqLine, Shared, Snapshot, SnapshotReader};
+
+// Spec: R6522 VERSATILE INTERFACE ADAPTER (VIA) Datasheet
+
+// Design:
+//   Timers decrement once per cycle and reload from their latch on underflow. The shift
+//   register and the CB1/CB2 handshake lines are not implemented, their registers only
+//   store the written value.
+
+pub mod reg {
+    pub const ORB: u8 = 0x00;
+    pub const ORA: u8 = 0x01;
+    pub const DDRB: u8 = 0x02;
+    pub const DDRA: u8 = 0x03;
+    pub const T1CL: u8 = 0x04;
+    pub const T1CH: u8 = 0x05;
+    pub const T1LL: u8 = 0x06;
+    pub const T1LH: u8 = 0x07;
+    pub const T2CL: u8 = 0x08;
+    pub const T2CH: u8 = 0x09;
+    pub const SR: u8 = 0x0a;
+    pub const ACR: u8 = 0x0b;
+    pub const PCR: u8 = 0x0c;
+    pub const IFR: u8 = 0x0d;
+    pub const IER: u8 = 0x0e;
+    pub const ORA_NH: u8 = 0x0f;
+}
+
+pub enum Interrupt {
+    Ca2 = 1,
+    Ca1 = 1 << 1,
+    ShiftRegister = 1 << 2,
+    Cb2 = 1 << 3,
+    Cb1 = 1 << 4,
+    Timer2 = 1 << 5,
+    Timer1 = 1 << 6,
+}
+
+pub struct Via {
+    // Dependencies
+    irq_source: usize,
+    // Functional Units
+    t1_counter: u16,
+    t1_latch: u16,
+    t1_armed: bool,
+    t2_counter: u16,
+    t2_latch_lo: u8,
+    t2_armed: bool,
+    sr: u8,
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+    ca1: bool,
+    // I/O
+    irq_line: Shared<IrqLine>,
+    port_a: Shared<IoPort>,
+    port_b: Shared<IoPort>,
+}
+
+impl Via {
+    pub fn new(
+        irq_source: usize,
+        port_a: Shared<IoPort>,
+        port_b: Shared<IoPort>,
+        irq_line: Shared<IrqLine>,
+    ) -> Self {
+        Self {
+            irq_source,
+            t1_counter: 0,
+            t1_latch: 0,
+            t1_armed: false,
+            t2_counter: 0,
+            t2_latch_lo: 0,
+            t2_armed: false,
+            sr: 0,
+            acr: 0,
+            pcr: 0,
+            ifr: 0,
+            ier: 0,
+            ca1: false,
+            irq_line,
+            port_a,
+            port_b,
+        }
+    }
+
+    pub fn clock(&mut self) {
+        let (counter, underflow) = self.t1_counter.overflowing_sub(1);
+        if underflow {
+            if self.t1_armed {
+                self.set_interrupt(Interrupt::Timer1);
+            }
+            if self.acr.get_bit(6) {
+                self.t1_counter = self.t1_latch;
+            } else {
+                self.t1_armed = false;
+                self.t1_counter = counter;
+            }
+        } else {
+            self.t1_counter = counter;
+        }
+        // Pulse counting mode is not supported, timer 2 only counts cycles
+        if !self.acr.get_bit(5) {
+            let (counter, underflow) = self.t2_counter.overflowing_sub(1);
+            if underflow && self.t2_armed {
+                self.t2_armed = false;
+                self.set_interrupt(Interrupt::Timer2);
+            }
+            self.t2_counter = counter;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.t1_counter = 0;
+        self.t1_latch = 0;
+        self.t1_armed = false;
+        self.t2_counter = 0;
+        self.t2_latch_lo = 0;
+        self.t2_armed = false;
+        self.sr = 0;
+        self.acr = 0;
+        self.pcr = 0;
+        self.ifr = 0;
+        self.ier = 0;
+        self.ca1 = false;
+        self.port_a.borrow_mut().reset();
+        self.port_b.borrow_mut().reset();
+        self.update_irq();
+    }
+
+    /// Drive the CA1 input, PCR bit 0 selects the active edge.
+    pub fn set_ca1(&mut self, value: bool) {
+        if self.ca1 != value {
+            let positive_edge = self.pcr.get_bit(0);
+            if value == positive_edge {
+                self.set_interrupt(Interrupt::Ca1);
+            }
+            self.ca1 = value;
+        }
+    }
+
+    /// Check if CA2 is driven high in manual output mode.
+    pub fn is_ca2_high(&self) -> bool {
+        (self.pcr >> 1) & 0x07 == 0x07
+    }
+
+    /// Read register without clearing interrupt flags.
+    pub fn peek(&self, reg: u8) -> u8 {
+        match reg & 0x0f {
+            reg::ORB => self.port_b.borrow().get_value(),
+            reg::ORA | reg::ORA_NH => self.port_a.borrow().get_value(),
+            reg::DDRB => self.port_b.borrow().get_direction(),
+            reg::DDRA => self.port_a.borrow().get_direction(),
+            reg::T1CL => self.t1_counter as u8,
+            reg::T1CH => (self.t1_counter >> 8) as u8,
+            reg::T1LL => self.t1_latch as u8,
+            reg::T1LH => (self.t1_latch >> 8) as u8,
+            reg::T2CL => self.t2_counter as u8,
+            reg::T2CH => (self.t2_counter >> 8) as u8,
+            reg::SR => self.sr,
+            reg::ACR => self.acr,
+            reg::PCR => self.pcr,
+            reg::IFR => {
+                let mut value = self.ifr;
+                value.set_bit(7, self.ifr & self.ier != 0);
+                value
+            }
+            reg::IER => self.ier | 0x80,
+            _ => panic!("invalid reg {}", reg),
+        }
+    }
+
+    pub fn read(&mut self, reg: u8) -> u8 {
+        match reg & 0x0f {
+            reg::ORB => self.port_b.borrow().get_value(),
+            reg::ORA => {
+                self.clear_interrupt(Interrupt::Ca1 as u8 | Interrupt::Ca2 as u8);
+                self.port_a.borrow().get_value()
+            }
+            reg::DDRB => self.port_b.borrow().get_direction(),
+            reg::DDRA => self.port_a.borrow().get_direction(),
+            reg::T1CL => {
+                self.clear_interrupt(Interrupt::Timer1 as u8);
+                self.t1_counter as u8
+            }
+            reg::T1CH => (self.t1_counter >> 8) as u8,
+            reg::T1LL => self.t1_latch as u8,
+            reg::T1LH => (self.t1_latch >> 8) as u8,
+            reg::T2CL => {
+                self.clear_interrupt(Interrupt::Timer2 as u8);
+                self.t2_counter as u8
+            }
+            reg::T2CH => (self.t2_counter >> 8) as u8,
+            reg::SR => self.sr,
+            reg::ACR => self.acr,
+            reg::PCR => self.pcr,
+            reg::IFR => {
+                let mut value = self.ifr;
+                value.set_bit(7, self.ifr & self.ier != 0);
+                value
+            }
+            reg::IER => self.ier | 0x80,
+            reg::ORA_NH => self.port_a.borrow().get_value(),
+            _ => panic!("invalid reg {}", reg),
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, value: u8) {
+        match reg & 0x0f {
+            reg::ORB => self.port_b.borrow_mut().set_value(value),
+            reg::ORA => {
+                self.clear_interrupt(Interrupt::Ca1 as u8 | Interrupt::Ca2 as u8);
+                self.port_a.borrow_mut().set_value(value);
+            }
+            reg::DDRB => self.port_b.borrow_mut().set_direction(value),
+            reg::DDRA => self.port_a.borrow_mut().set_direction(value),
+            reg::T1CL | reg::T1LL => {
+                self.t1_latch = (self.t1_latch & 0xff00) | value as u16;
+            }
+            reg::T1CH => {
+                self.t1_latch = (self.t1_latch & 0x00ff) | ((value as u16) << 8);
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.clear_interrupt(Interrupt::Timer1 as u8);
+            }
+            reg::T1LH => {
+                self.t1_latch = (self.t1_latch & 0x00ff) | ((value as u16) << 8);
+                self.clear_interrupt(Interrupt::Timer1 as u8);
+            }
+            reg::T2CL => self.t2_latch_lo = value,
+            reg::T2CH => {
+                self.t2_counter = ((value as u16) << 8) | self.t2_latch_lo as u16;
+                self.t2_armed = true;
+                self.clear_interrupt(Interrupt::Timer2 as u8);
+            }
+            reg::SR => self.sr = value,
+            reg::ACR => self.acr = value,
+            reg::PCR => self.pcr = value,
+            reg::IFR => self.clear_interrupt(value & 0x7f),
+            reg::IER => {
+                if value.get_bit(7) {
+                    self.ier |= value & 0x7f;
+                } else {
+                    self.ier &= !value & 0x7f;
+                }
+                self.update_irq();
+            }
+            reg::ORA_NH => self.port_a.borrow_mut().set_value(value),
+            _ => panic!("invalid reg {}", reg),
+        }
+    }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u16(self.t1_counter);
+        snapshot.write_u16(self.t1_latch);
+        snapshot.write_bool(self.t1_armed);
+        snapshot.write_u16(self.t2_counter);
+        snapshot.write_u8(self.t2_latch_lo);
+        snapshot.write_bool(self.t2_armed);
+        snapshot.write_u8(self.sr);
+        snapshot.write_u8(self.acr);
+        snapshot.write_u8(self.pcr);
+        snapshot.write_u8(self.ifr);
+        snapshot.write_u8(self.ier);
+        snapshot.write_bool(self.ca1);
+        self.port_a.borrow().save_state(snapshot);
+        self.port_b.borrow().save_state(snapshot);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.t1_counter = snapshot.read_u16()?;
+        self.t1_latch = snapshot.read_u16()?;
+        self.t1_armed = snapshot.read_bool()?;
+        self.t2_counter = snapshot.read_u16()?;
+        self.t2_latch_lo = snapshot.read_u8()?;
+        self.t2_armed = snapshot.read_bool()?;
+        self.sr = snapshot.read_u8()?;
+        self.acr = snapshot.read_u8()?;
+        self.pcr = snapshot.read_u8()?;
+        self.ifr = snapshot.read_u8()?;
+        self.ier = snapshot.read_u8()?;
+        self.ca1 = snapshot.read_bool()?;
+        self.port_a.borrow_mut().load_state(snapshot)?;
+        self.port_b.borrow_mut().load_state(snapshot)?;
+        self.update_irq();
+        Ok(())
+    }
+
+    fn clear_interrupt(&mut self, mask: u8) {
+        self.ifr &= !mask;
+        self.update_irq();
+    }
+
+    fn set_interrupt(&mut self, interrupt: Interrupt) {
+        self.ifr |= interrupt as u8;
+        self.update_irq();
+    }
+
+    fn update_irq(&mut self) {
+        self.irq_line
+            .borrow_mut()
+            .set_low(self.irq_source, self.ifr & self.ier != 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::new_shared;
+
+    fn setup_via() -> (Via, Shared<IrqLine>) {
+        let irq_line = new_shared(IrqLine::new("irq"));
+        let via = Via::new(
+            0,
+            new_shared(IoPort::new(0x00, 0xff)),
+            new_shared(IoPort::new(0x00, 0xff)),
+            irq_line.clone(),
+        );
+        (via, irq_line)
+    }
+
+    #[test]
+    fn port_direction() {
+        let (mut via, _) = setup_via();
+        via.write(reg::DDRB, 0x0f);
+        via.write(reg::ORB, 0x00);
+        assert_eq!(0xf0, via.read(reg::ORB));
+        via.write(reg::ORB, 0x05);
+        assert_eq!(0xf5, via.read(reg::ORB));
+    }
+
+    #[test]
+    fn timer1_one_shot() {
+        let (mut via, irq_line) = setup_via();
+        via.write(reg::IER, 0x80 | Interrupt::Timer1 as u8);
+        via.write(reg::T1CL, 0x02);
+        via.write(reg::T1CH, 0x00);
+        via.clock();
+        via.clock();
+        assert!(!irq_line.borrow().is_low());
+        via.clock();
+        assert!(irq_line.borrow().is_low());
+        assert_eq!(0xc0, via.read(reg::IFR));
+        via.read(reg::T1CL);
+        assert!(!irq_line.borrow().is_low());
+        for _ in 0..0x10000 {
+            via.clock();
+        }
+        assert!(!irq_line.borrow().is_low());
+    }
+
+    #[test]
+    fn timer1_free_running() {
+        let (mut via, irq_line) = setup_via();
+        via.write(reg::ACR, 0x40);
+        via.write(reg::IER, 0x80 | Interrupt::Timer1 as u8);
+        via.write(reg::T1CL, 0x01);
+        via.write(reg::T1CH, 0x00);
+        via.clock();
+        via.clock();
+        assert!(irq_line.borrow().is_low());
+        via.write(reg::IFR, Interrupt::Timer1 as u8);
+        assert!(!irq_line.borrow().is_low());
+        via.clock();
+        via.clock();
+        assert!(irq_line.borrow().is_low());
+    }
+
+    #[test]
+    fn timer2_one_shot() {
+        let (mut via, irq_line) = setup_via();
+        via.write(reg::IER, 0x80 | Interrupt::Timer2 as u8);
+        via.write(reg::T2CL, 0x00);
+        via.write(reg::T2CH, 0x00);
+        via.clock();
+        assert!(irq_line.borrow().is_low());
+        assert_eq!(0xff, via.read(reg::T2CH));
+        via.read(reg::T2CL);
+        assert!(!irq_line.borrow().is_low());
+    }
+
+    #[test]
+    fn ca1_edge() {
+        let (mut via, irq_line) = setup_via();
+        via.write(reg::IER, 0x80 | Interrupt::Ca1 as u8);
+        via.set_ca1(true);
+        assert!(!irq_line.borrow().is_low());
+        via.set_ca1(false);
+        assert!(irq_line.borrow().is_low());
+        via.read(reg::ORA);
+        assert!(!irq_line.borrow().is_low());
+        via.write(reg::PCR, 0x01);
+        via.set_ca1(true);
+        assert!(irq_line.borrow().is_low());
+    }
+
+    #[test]
+    fn peek_keeps_interrupts() {
+        let (mut via, irq_line) = setup_via();
+        via.write(reg::IER, 0x80 | Interrupt::Timer2 as u8);
+        via.write(reg::T2CL, 0x00);
+        via.write(reg::T2CH, 0x00);
+        via.clock();
+        assert_eq!(0xff, via.peek(reg::T2CL));
+        assert_eq!(0xa0, via.peek(reg::IFR));
+        assert!(irq_line.borrow().is_low());
+        via.read(reg::T2CL);
+        assert_eq!(0x00, via.peek(reg::IFR));
+    }
+
+    #[test]
+    fn interrupt_enable() {
+        let (mut via, _) = setup_via();
+        via.write(reg::IER, 0x80 | 0x42);
+        assert_eq!(0xc2, via.read(reg::IER));
+        via.write(reg::IER, 0x02);
+        assert_eq!(0xc0, via.read(reg::IER));
+    }
+}
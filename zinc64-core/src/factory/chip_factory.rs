@@ -8,9 +8,10 @@ use alloc::boxed::Box;
 use alloc::rc::Rc;
 use alloc::sync::Arc;
 
-use crate::factory::system_model::{SidModel, VicModel};
+use crate::factory::system_model::{CiaModel, SidModel, VicModel};
 use crate::factory::types::*;
-use crate::util::{Clock, IoPort, IrqLine, Pin, Ram, Rom, Shared, SharedCell};
+use crate::io::IecBus;
+use crate::util::{Clock, EventBus, IoPort, IrqLine, Pin, Ram, Rom, Shared, SharedCell};
 
 /// ChipFactory serves as the foundation of an extensible emulator architecture and
 /// provides an interface to construct each chip/component within the system.
@@ -54,6 +55,8 @@ pub trait ChipFactory {
     /// Keyboard matrix is also connected to CIA 1 port B.
     ///
     /// # Dependencies
+    /// `chip_model` - choose either 6526 or 6526A
+    /// `events` - event bus for device state changes
     /// `joystick_1` - joystick 1 state
     /// `joystick_2` - joystick 2 state
     /// `keyboard_matrix` - keyboard state
@@ -65,6 +68,8 @@ pub trait ChipFactory {
     /// `irq_line` - interrupt request output
     fn new_cia_1(
         &self,
+        chip_model: CiaModel,
+        events: Rc<EventBus>,
         joystick_1: SharedCell<u8>,
         joystick_2: SharedCell<u8>,
         keyboard_matrix: Shared<[u8; 16]>,
@@ -76,16 +81,29 @@ pub trait ChipFactory {
 
     /// Constructs CIA 2 chip.
     ///
+    /// CIA 2 port B is connected to the user port which may host a 4-player adapter.
+    ///
+    /// # Dependencies
+    /// `chip_model` - choose either 6526 or 6526A
+    /// `events` - event bus for device state changes
+    /// `joystick_3` - joystick 3 state
+    /// `joystick_4` - joystick 4 state
     /// # I/O
     /// `port_a` - I/O port A
     /// `port_b` - I/O port B
+    /// `iec_bus` - serial bus driven by port A
     /// # Signals
     /// `flag_pin` - flag input pin
     /// `nmi_line` - interrupt request output
     fn new_cia_2(
         &self,
+        chip_model: CiaModel,
+        events: Rc<EventBus>,
+        joystick_3: SharedCell<u8>,
+        joystick_4: SharedCell<u8>,
         port_a: Shared<IoPort>,
         port_b: Shared<IoPort>,
+        iec_bus: Shared<IecBus>,
         flag_pin: Shared<Pin>,
         nmi_line: Shared<IrqLine>,
     ) -> Shared<dyn Chip>;
@@ -103,11 +121,18 @@ pub trait ChipFactory {
     /// `system_clock` - system clock
     /// # I/O
     /// `sound_buffer` - output for generated 16-bit sound samples
+    /// `pot_x` - POTX input
+    /// `pot_y` - POTY input
+    /// # Configuration
+    /// `noise_seed` - seed for the initial state of the noise generators
     fn new_sid(
         &self,
         chip_model: SidModel,
         system_clock: Rc<Clock>,
         sound_buffer: Arc<dyn SoundOutput>,
+        pot_x: SharedCell<u8>,
+        pot_y: SharedCell<u8>,
+        noise_seed: Option<u64>,
     ) -> Shared<dyn Chip>;
 
     /// Constructs VIC chip.
@@ -125,6 +150,7 @@ pub trait ChipFactory {
     /// `ram` - 64KB main memory
     /// `rom_charset` - 4KB character generator ROM
     /// `vic_base_address` - memory base address as defined by CIA 2 port A bits 0 and 1
+    /// `events` - event bus for device state changes
     /// # I/O
     /// `frame_buffer` - pixel color information is written here
     /// `vsync_flag` - set when vsync condition is reached
@@ -138,6 +164,7 @@ pub trait ChipFactory {
         ram: Shared<Ram>,
         rom_charset: Shared<Rom>,
         vic_base_address: SharedCell<u16>,
+        events: Rc<EventBus>,
         frame_buffer: Shared<dyn VideoOutput>,
         vsync_flag: SharedCell<bool>,
         ba_line: Shared<Pin>,
@@ -151,7 +178,8 @@ pub trait ChipFactory {
     /// Memory is used by the CPU to access banks of memory accessible at
     /// any given time. Bank switching is controlled through 5 latch bits that control
     /// the memory management unit (LORAM, HIRAM, CHAREN, GAME, EXROM) that does address
-    /// translation.
+    /// translation. An optional second SID is decoded at `sid_2_address` ahead of
+    /// the chips normally mapped there.
     fn new_memory(
         &self,
         mmu: Shared<dyn Mmu>,
@@ -164,6 +192,7 @@ pub trait ChipFactory {
         rom_charset: Shared<Rom>,
         rom_kernal: Shared<Rom>,
         sid: Shared<dyn Chip>,
+        sid_2: Option<(u16, Shared<dyn Chip>)>,
         vic: Shared<dyn Chip>,
     ) -> Shared<dyn Addressable>;
 
@@ -7,5 +7,5 @@ mod system_model;
 mod types;
 
 pub use self::chip_factory::ChipFactory;
-pub use self::system_model::{SidModel, SystemModel, VicModel};
+pub use self::system_model::{CiaModel, SidModel, SystemModel, VicModel};
 pub use self::types::*;
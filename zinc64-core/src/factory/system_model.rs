@@ -19,25 +19,36 @@
    6569   |  300   |   15   |  404 ($194)  | 480 ($1e0) | 380 ($17c)
 */
 
-#[derive(Clone, Copy)]
+/// CIA revision, the 6526 found in early machines asserts IRQ one cycle after an
+/// interrupt event while the later 6526A (8521) asserts it in the same cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CiaModel {
+    Mos6526,  // old
+    Mos6526A, // new
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SidModel {
     Mos6581,
     Mos8580,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum VicModel {
-    Mos6567, // NTSC
-    Mos6569, // PAL
+    Mos6567,     // NTSC
+    Mos6567R56A, // NTSC (old)
+    Mos6569,     // PAL
 }
 
 pub struct SystemModel {
+    pub cia_model: CiaModel,
     pub color_ram: usize,
     pub cpu_freq: u32,
     pub cycles_per_frame: u16,
     pub frame_buffer_size: (u32, u32),
     pub memory_size: usize,
     pub refresh_rate: f32,
+    pub screen_offset: (u32, u32),
     pub sid_model: SidModel,
     pub vic_model: VicModel,
     pub viewport_offset: (u32, u32),
@@ -48,8 +59,10 @@ impl SystemModel {
     pub fn from(model: &str) -> SystemModel {
         match model {
             "ntsc" => SystemModel::c64_ntsc(),
+            "ntsc-old" => SystemModel::c64_ntsc_old(),
             "pal" => SystemModel::c64_pal(),
             "c64-ntsc" => SystemModel::c64_ntsc(),
+            "c64-ntsc-old" => SystemModel::c64_ntsc_old(),
             "c64-pal" => SystemModel::c64_pal(),
             _ => panic!("invalid model {}", model),
         }
@@ -57,12 +70,14 @@ impl SystemModel {
 
     pub fn c64_ntsc() -> SystemModel {
         SystemModel {
+            cia_model: CiaModel::Mos6526,
             color_ram: 1024,
             cpu_freq: 1_022_727,
             cycles_per_frame: 17095,
             frame_buffer_size: (512, 263),
             memory_size: 65536,
             refresh_rate: 59.826,
+            screen_offset: (0x7c, 51),
             sid_model: SidModel::Mos6581,
             vic_model: VicModel::Mos6567,
             viewport_offset: (77, 16),
@@ -70,14 +85,33 @@ impl SystemModel {
         }
     }
 
+    pub fn c64_ntsc_old() -> SystemModel {
+        SystemModel {
+            cia_model: CiaModel::Mos6526,
+            color_ram: 1024,
+            cpu_freq: 1_022_727,
+            cycles_per_frame: 16768,
+            frame_buffer_size: (512, 262),
+            memory_size: 65536,
+            refresh_rate: 60.99,
+            screen_offset: (0x7c, 51),
+            sid_model: SidModel::Mos6581,
+            vic_model: VicModel::Mos6567R56A,
+            viewport_offset: (77, 16),
+            viewport_size: (411, 234),
+        }
+    }
+
     pub fn c64_pal() -> SystemModel {
         SystemModel {
+            cia_model: CiaModel::Mos6526,
             color_ram: 1024,
             cpu_freq: 985_248,
             cycles_per_frame: 19656,
             frame_buffer_size: (504, 312),
             memory_size: 65536,
             refresh_rate: 50.125,
+            screen_offset: (0x7c, 51),
             sid_model: SidModel::Mos6581,
             vic_model: VicModel::Mos6569,
             viewport_offset: (76, 16),
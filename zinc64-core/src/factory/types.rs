@@ -2,10 +2,16 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::boxed::Box;
 use alloc::rc::Rc;
-use core::ops::Fn;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Fn, FnMut};
 use core::option::Option::{self, Some, None};
 
+use crate::util::{Snapshot, SnapshotReader};
+
 /// A tick represents a callback invoked by the cpu for each clock cycle
 /// during instruction execution.
 pub type TickFn = Rc<dyn Fn()>;
@@ -14,24 +20,83 @@ pub fn make_noop() -> TickFn {
     Rc::new(|| {})
 }
 
+/// A trace callback receives a record for each instruction fetched by the cpu.
+pub type TraceFn = Box<dyn FnMut(&TraceRecord)>;
+
+/// A watch callback is notified of each watched access with the address, kind of
+/// access and value transferred. Returning true halts execution after the current
+/// instruction.
+pub type WatchFn = Box<dyn FnMut(u16, Access, u8) -> bool>;
+
+/// Cpu state captured when an instruction is fetched, before it executes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub disassembly: String,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub p: u8,
+    /// Clock cycles elapsed since reset.
+    pub cycles: u64,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, ".{:04x} ", self.pc)?;
+        for i in 0..3 {
+            match self.bytes.get(i) {
+                Some(byte) => write!(f, "{:02x} ", byte)?,
+                None => write!(f, "   ")?,
+            }
+        }
+        write!(
+            f,
+            "{:<12} a:{:02x} x:{:02x} y:{:02x} sp:{:02x} ",
+            self.disassembly, self.a, self.x, self.y, self.sp
+        )?;
+        for (i, flag) in "NV-BDIZC".chars().enumerate() {
+            let set = self.p & (0x80 >> i) != 0;
+            let flag = if set { flag } else { flag.to_ascii_lowercase() };
+            write!(f, "{}", flag)?;
+        }
+        write!(f, " {}", self.cycles)
+    }
+}
+
 /// Addressable represents a bank of memory.
 pub trait Addressable {
     /// Read byte from the specified address.
     fn read(&self, address: u16) -> u8;
     /// Write byte to the specified address.
     fn write(&mut self, address: u16, value: u8);
+    /// Read byte from the specified address without I/O side effects.
+    fn peek(&self, address: u16) -> u8 {
+        self.read(address)
+    }
+    /// Write byte to the specified address without I/O side effects.
+    fn poke(&mut self, address: u16, value: u8) {
+        self.write(address, value)
+    }
 }
 
 /// Addressable represents a bank of memory that may be faded by RAM.
 pub trait AddressableFaded {
     /// Read byte from the specified address.
     fn read(&mut self, address: u16) -> Option<u8>;
+    /// Read byte from the specified address without side effects. Banks that cannot
+    /// be peeked can rely on the default, which leaves the address to RAM.
+    fn peek(&self, _address: u16) -> Option<u8> {
+        None
+    }
     /// Write byte to the specified address.
     fn write(&mut self, address: u16, value: u8);
 }
 
 /// Memory bank type used with MMU to determine how to map a memory address
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Bank {
     Basic,
     Charset,
@@ -53,13 +118,42 @@ pub trait Chip {
     fn process_vsync(&mut self);
     /// Handle reset signal.
     fn reset(&mut self);
+    // -- State
+    /// Write chip state to the snapshot. Chips without state can rely on the default,
+    /// which saves nothing.
+    fn save_state(&self, _snapshot: &mut Snapshot) {}
+    /// Restore chip state previously written by `save_state`.
+    fn load_state(&mut self, _snapshot: &mut SnapshotReader) -> Result<(), String> {
+        Ok(())
+    }
     // I/O
+    /// Read value from the specified register without side effects such as clearing
+    /// interrupt flags. Chips whose registers cannot be peeked can rely on the
+    /// default, which reads 0.
+    fn peek(&self, _reg: u8) -> u8 {
+        0
+    }
     /// Read value from the specified register.
     fn read(&mut self, reg: u8) -> u8;
     /// Write value to the specified register.
     fn write(&mut self, reg: u8, value: u8);
 }
 
+/// Kind of memory access observed by a watchpoint.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Execution state of the CPU.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CpuState {
+    Running,
+    /// Halted by a JAM opcode, only a reset resumes execution.
+    Jammed,
+}
+
 #[derive(Copy, Clone)]
 pub enum Register {
     A,
@@ -77,13 +171,67 @@ pub trait Cpu {
     fn get_register(&self, reg: Register) -> u8;
     fn set_register(&mut self, reg: Register, value: u8);
     fn get_pc(&self) -> u16;
+    /// Address of the instruction being executed, which stays put until the next
+    /// opcode fetch while stepping by cycle.
+    fn get_opcode_pc(&self) -> u16 {
+        self.get_pc()
+    }
     fn set_pc(&mut self, value: u16);
     fn is_cpu_jam(&self) -> bool;
+    fn get_state(&self) -> CpuState {
+        CpuState::Running
+    }
+    /// Check if the CPU halted on a JAM opcode.
+    fn is_jammed(&self) -> bool {
+        self.get_state() == CpuState::Jammed
+    }
     /// The core method of the cpu, decodes and executes one instruction. Tick callback is invoked
     /// for each elapsed clock cycle.
     fn step(&mut self, tick_fn: &TickFn);
+    /// Advance by a single clock cycle. Cores that are not cycle-exact execute
+    /// the whole instruction.
+    fn step_cycle(&mut self, tick_fn: &TickFn) {
+        self.step(tick_fn);
+    }
+    /// Number of clock cycles until the current instruction completes. The count
+    /// may drop by one when an indexed access does not cross a page boundary.
+    fn remaining_cycles(&self) -> u8 {
+        1
+    }
+    /// Check if the next cycle completes the current instruction.
+    fn is_last_cycle(&self) -> bool {
+        self.remaining_cycles() == 1
+    }
+    // -- Debugging
+    /// Watch CPU accesses of the specified kind to an address.
+    fn add_watch(&mut self, _address: u16, _access: Access) {}
+    /// Remove a watch previously added with `add_watch`.
+    fn remove_watch(&mut self, _address: u16, _access: Access) {}
+    /// Check if any watches are set.
+    fn has_watches(&self) -> bool {
+        false
+    }
+    /// Get the first watched access made by the last executed instruction
+    /// that halted execution.
+    fn get_watch_hit(&self) -> Option<(u16, Access)> {
+        None
+    }
+    /// Install or remove the callback notified of watched accesses. Without a
+    /// callback every watched access halts execution.
+    fn set_watch_handler(&mut self, _handler: Option<WatchFn>) {}
+    /// Install or remove the callback invoked for each executed instruction.
+    /// Tracing costs nothing while no callback is installed.
+    fn set_trace(&mut self, _trace: Option<TraceFn>) {}
     /// Reset chip.
     fn reset(&mut self);
+    // -- State
+    /// Write cpu state to the snapshot. The default saves nothing, so a cpu relying
+    /// on it resumes from its current state after a restore.
+    fn save_state(&self, _snapshot: &mut Snapshot) {}
+    /// Restore cpu state previously written by `save_state`.
+    fn load_state(&mut self, _snapshot: &mut SnapshotReader) -> Result<(), String> {
+        Ok(())
+    }
     // I/O
     /// Read byte from the specified address.
     fn read(&self, address: u16) -> u8;
@@ -105,6 +253,14 @@ pub trait SoundOutput {
     fn reset(&self);
     /// Write generated sample to the output buffer.
     fn write(&self, samples: &[i16]);
+    /// Number of interleaved channels in each frame written to the output.
+    fn get_channels(&self) -> usize {
+        1
+    }
+    /// Whether samples are discarded, so generating them can be skipped.
+    fn is_muted(&self) -> bool {
+        false
+    }
 }
 
 /// Video output used by VIC chip.
@@ -116,9 +272,105 @@ pub trait VideoOutput {
     /// Write pixel color to the specified location. Index is computed from raster x, y coordinates:
     /// index = y * pitch + x.
     fn write(&mut self, index: usize, color: u8);
+    /// Called when the raster wraps around and the frame is complete.
+    fn end_frame(&mut self) {}
+    /// Get RGBA pixels of the last complete frame. Outputs that do not keep the pixels
+    /// return an empty slice.
+    fn get_pixel_data(&self) -> &[u8] {
+        &[]
+    }
+}
+
+/// Peripheral represents a custom device attached to the expansion port or the user
+/// port. On the expansion port it is mapped into I/O 1/2 ($de00-$dfff) and ROML/ROMH
+/// when no cartridge claims these addresses, and its IRQ, NMI and DMA lines are polled
+/// every cycle. On the user port it is connected to the data lines PB0-PB7 and PA2 of
+/// CIA 2 through `set_user_port_lines` and `get_user_port_lines` instead.
+pub trait Peripheral {
+    /// Emulate one clock cycle of the device.
+    fn clock(&mut self);
+    /// Handle reset signal.
+    fn reset(&mut self);
+    /// Read byte from the specified address or None if the device does not decode it.
+    fn read(&mut self, address: u16) -> Option<u8>;
+    /// Read byte from the specified address without side effects, e.g. for the debugger.
+    /// Devices that cannot be peeked can rely on the default, which decodes nothing.
+    fn peek(&self, _address: u16) -> Option<u8> {
+        None
+    }
+    /// Write byte to the specified address.
+    fn write(&mut self, address: u16, value: u8);
+    /// Check if the device pulls the IRQ line low.
+    fn irq(&self) -> bool {
+        false
+    }
+    /// Check if the device pulls the NMI line low.
+    fn nmi(&self) -> bool {
+        false
+    }
+    /// Check if the device pulls the DMA line low, which stalls the CPU.
+    fn dma(&self) -> bool {
+        false
+    }
+    /// Receive the levels of the user port lines PB0-PB7 and PA2 when the computer
+    /// changes them. Lines set as input read high.
+    fn set_user_port_lines(&mut self, _port_b: u8, _pa2: bool) {}
+    /// User port lines PB0-PB7 driven by the device as a mask of the lines and their
+    /// levels. Lines outside the mask are left to the computer and other devices.
+    fn get_user_port_lines(&self) -> (u8, u8) {
+        (0x00, 0xff)
+    }
+    /// Save device state to the snapshot. Devices without state other than their
+    /// configuration can rely on the default, which saves nothing.
+    fn save_state(&self, _snapshot: &mut Snapshot) {}
+    /// Restore device state saved by `save_state`.
+    fn load_state(&mut self, _snapshot: &mut SnapshotReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Disk represents a mounted disk image that serves files to the kernal load trap.
+/// Files opened with OPEN are not served from it.
+pub trait Disk {
+    /// Directory listing as a BASIC program, starting with its load address.
+    fn read_directory(&self) -> Vec<u8>;
+    /// Content of the first file matching the PETSCII `pattern`, starting with its
+    /// load address.
+    fn read_file(&self, pattern: &[u8]) -> Option<Vec<u8>>;
+    /// Track and sector of each block read by `read_file`, in the order they are read.
+    /// Only used to report disk activity, images without a sector layout return none.
+    fn read_file_sectors(&self, _pattern: &[u8]) -> Vec<(u8, u8)> {
+        Vec::new()
+    }
+}
+
+/// IecDevice represents a device on the serial bus implemented at the protocol level.
+/// It receives commands and data already decoded from the bus handshake, see
+/// `io::IecProtocol`. Channel is the secondary address used with OPEN.
+pub trait IecDevice {
+    /// Handle OPEN for the channel, the file name follows as data.
+    fn open(&mut self, channel: u8);
+    /// Handle CLOSE for the channel.
+    fn close(&mut self, channel: u8);
+    /// Receive byte sent to the channel while listening, `eoi` marks the last byte.
+    fn write(&mut self, channel: u8, value: u8, eoi: bool);
+    /// Next byte to send from the channel while talking and whether it is the last one.
+    fn read(&mut self, channel: u8) -> Option<(u8, bool)>;
+    /// Handle reset signal.
+    fn reset(&mut self);
+}
+
+/// SerialLink is the far end of the RS-232 interface, typically a host socket or terminal.
+/// Both calls must not block the emulation.
+pub trait SerialLink {
+    /// Next byte received from the remote end if one is available.
+    fn receive(&mut self) -> Option<u8>;
+    /// Send byte to the remote end.
+    fn send(&mut self, value: u8);
 }
 
 pub trait Tape {
+    fn get_pos(&self) -> usize;
     fn read_pulse(&mut self) -> Option<u32>;
     fn seek(&mut self, pos: usize) -> bool;
 }
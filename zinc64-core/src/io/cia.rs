@@ -2,15 +2,21 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::rc::Rc;
+use alloc::string::String;
 use core::option::Option::{self, Some, None};
 use bit_field::BitField;
 use log::LogLevel;
 use log::{log_enabled, log, info, trace, debug};
 
-use crate::factory::Chip;
-use crate::util::{new_shared, IoPort, IrqControl, IrqLine, Pin, Shared, SharedCell};
+use crate::factory::{Chip, CiaModel};
+use crate::util::{
+    new_shared, Event, EventBus, IoPort, IrqControl, IrqLine, Pin, Shared, SharedCell, Snapshot,
+    SnapshotReader,
+};
 
 use super::cycle_counter::CycleCounter;
+use super::iec_bus::{IecBus, IecLine};
 use super::rtc::Rtc;
 use super::timer;
 use super::timer::Timer;
@@ -58,9 +64,12 @@ pub mod reg {
 pub struct Cia {
     // Dependencies
     mode: Mode,
+    chip_model: CiaModel,
     joystick_1: Option<SharedCell<u8>>,
     joystick_2: Option<SharedCell<u8>>,
     keyboard_matrix: Option<Shared<[u8; 16]>>,
+    four_player_adapter: Option<(SharedCell<u8>, SharedCell<u8>)>,
+    events: Rc<EventBus>,
     // Functional Units
     irq_control: IrqControl,
     irq_delay: CycleCounter,
@@ -69,9 +78,18 @@ pub struct Cia {
     tod_alarm: Rtc,
     tod_clock: Rtc,
     tod_set_alarm: bool,
+    sdr: u8,
+    sdr_loaded: bool,
+    sdr_output: bool,
+    sdr_shift: u8,
+    sdr_shift_count: u8,
     // I/O
     cnt_pin: Shared<Pin>,
+    cnt_last: bool,
     flag_pin: Shared<Pin>,
+    flag_last: bool,
+    sp_pin: Shared<Pin>,
+    iec_bus: Option<Shared<IecBus>>,
     irq_line: Shared<IrqLine>,
     port_a: Shared<IoPort>,
     port_b: Shared<IoPort>,
@@ -81,20 +99,26 @@ impl Cia {
     #![cfg_attr(feature = "cargo-clippy", allow(clippy::too_many_arguments))]
     pub fn new(
         mode: Mode,
+        chip_model: CiaModel,
         joystick_1: Option<SharedCell<u8>>,
         joystick_2: Option<SharedCell<u8>>,
         keyboard_matrix: Option<Shared<[u8; 16]>>,
         port_a: Shared<IoPort>,
         port_b: Shared<IoPort>,
+        iec_bus: Option<Shared<IecBus>>,
         flag_pin: Shared<Pin>,
         irq_line: Shared<IrqLine>,
+        events: Rc<EventBus>,
     ) -> Self {
         let cnt_pin = new_shared(Pin::new_high());
         Self {
             mode,
+            chip_model,
             joystick_1,
             joystick_2,
             keyboard_matrix,
+            four_player_adapter: None,
+            events,
             irq_control: IrqControl::default(),
             irq_delay: CycleCounter::new(0xffff),
             timer_a: Timer::new(timer::Mode::TimerA, cnt_pin.clone()),
@@ -102,14 +126,94 @@ impl Cia {
             tod_alarm: Rtc::new(),
             tod_clock: Rtc::new(),
             tod_set_alarm: false,
+            sdr: 0,
+            sdr_loaded: false,
+            sdr_output: false,
+            sdr_shift: 0,
+            sdr_shift_count: 0,
             cnt_pin: cnt_pin.clone(),
+            cnt_last: true,
             flag_pin,
+            flag_last: false,
+            sp_pin: new_shared(Pin::new_high()),
+            iec_bus,
             irq_line,
             port_a,
             port_b,
         }
     }
 
+    /// Connect joysticks 3 and 4 through a 4-player adapter on the user port, which
+    /// is read through port B of CIA 2.
+    pub fn set_four_player_adapter(
+        &mut self,
+        joysticks: Option<(SharedCell<u8>, SharedCell<u8>)>,
+    ) {
+        self.four_player_adapter = joysticks;
+    }
+
+    /// CNT line of the serial port, also usable as timer input.
+    pub fn get_cnt_pin(&self) -> Shared<Pin> {
+        self.cnt_pin.clone()
+    }
+
+    /// SP data line of the serial port.
+    pub fn get_sp_pin(&self) -> Shared<Pin> {
+        self.sp_pin.clone()
+    }
+
+    /*
+    The serial port is a buffered, 8-bit synchronous shift register. In input mode,
+    data on the SP pin is shifted into the shift register on the rising edge of the
+    signal applied to the CNT pin. After 8 CNT pulses, the data in the shift register
+    is dumped into the Serial Data Register and an interrupt is generated. In output
+    mode, TIMER A is used for the baud rate generator. Data is shifted out on the SP
+    pin at 1/2 the underflow rate of TIMER A.
+    */
+
+    fn clock_serial_input(&mut self, cnt_rising: bool) -> bool {
+        if !cnt_rising {
+            return false;
+        }
+        let bit = self.sp_pin.borrow().is_high() as u8;
+        self.sdr_shift = (self.sdr_shift << 1) | bit;
+        self.sdr_shift_count += 1;
+        if self.sdr_shift_count == 8 {
+            self.sdr = self.sdr_shift;
+            self.sdr_shift_count = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn clock_serial_output(&mut self, timer_a_output: bool) -> bool {
+        if !timer_a_output {
+            return false;
+        }
+        if self.sdr_shift_count == 0 {
+            if !self.sdr_loaded {
+                return false;
+            }
+            self.sdr_shift = self.sdr;
+            self.sdr_shift_count = 8;
+            self.sdr_loaded = false;
+        }
+        let mut cnt_pin = self.cnt_pin.borrow_mut();
+        if cnt_pin.is_high() {
+            self.sp_pin
+                .borrow_mut()
+                .set_active(self.sdr_shift.get_bit(7));
+            self.sdr_shift <<= 1;
+            cnt_pin.set_active(false);
+            false
+        } else {
+            cnt_pin.set_active(true);
+            self.sdr_shift_count -= 1;
+            self.sdr_shift_count == 0
+        }
+    }
+
     fn read_cia1_port_a(&self) -> u8 {
         let active_columns = self.port_b.borrow().get_value();
         let keyboard_state = self.scan_keyboard_active_cols(active_columns);
@@ -139,12 +243,45 @@ impl Cia {
     }
 
     fn read_cia2_port_a(&self) -> u8 {
-        // iec inputs
-        self.port_a.borrow().get_value()
+        let port_a = self.port_a.borrow();
+        match self.iec_bus {
+            Some(ref iec_bus) => {
+                // PA6 and PA7 read back CLK and DATA
+                let iec_bus = iec_bus.borrow();
+                let mut input = port_a.get_value_2(0xff);
+                input.set_bit(6, iec_bus.is_high(IecLine::Clk));
+                input.set_bit(7, iec_bus.is_high(IecLine::Data));
+                port_a.get_value_2(input)
+            }
+            None => port_a.get_value(),
+        }
+    }
+
+    fn update_iec_bus(&self) {
+        if let Some(ref iec_bus) = self.iec_bus {
+            // PA3-PA5 drive ATN, CLK and DATA through inverters
+            let output = self.port_a.borrow().get_value_2(0x00);
+            let mut iec_bus = iec_bus.borrow_mut();
+            iec_bus.set_low(IecBus::HOST, IecLine::Atn, output.get_bit(3));
+            iec_bus.set_low(IecBus::HOST, IecLine::Clk, output.get_bit(4));
+            iec_bus.set_low(IecBus::HOST, IecLine::Data, output.get_bit(5));
+        }
     }
 
     fn read_cia2_port_b(&self) -> u8 {
         let mut result = self.port_b.borrow().get_value();
+        /*
+        4-player adapter on the user port: PB7 selects joystick 3 (high) or
+        joystick 4 (low) whose directions and fire button appear on PB0-PB4.
+        */
+        if let Some((ref joystick_3, ref joystick_4)) = self.four_player_adapter {
+            let state = if result.get_bit(7) {
+                joystick_3.get()
+            } else {
+                joystick_4.get()
+            };
+            result &= !(state & 0x1f) | 0xe0;
+        }
         if self.timer_a.is_pb_on() {
             result.set_bit(6, self.timer_a.get_pb_output());
         }
@@ -156,35 +293,48 @@ impl Cia {
 
     fn scan_joystick(&self, joystick: &Option<SharedCell<u8>>) -> u8 {
         if let Some(ref state) = *joystick {
-            !state.get()
+            !(state.get() & 0x1f)
         } else {
             0xff
         }
     }
 
     fn scan_keyboard_active_cols(&self, active_columns: u8) -> u8 {
-        if let Some(matrix) = self.keyboard_matrix.as_ref() {
-            let mut result = 0xff;
-            for col in 0..8 as usize {
-                if active_columns.get_bit(col) == false {
-                    result &= matrix.borrow()[8 + col];
-                }
-            }
-            result
-        } else {
-            0xff
-        }
+        self.scan_keyboard(active_columns, 8, 0)
     }
 
     fn scan_keyboard_active_rows(&self, active_rows: u8) -> u8 {
+        self.scan_keyboard(active_rows, 0, 8)
+    }
+
+    // Lines driven low are connected to the other side of the matrix through each pressed
+    // key, which in turn pulls down every line sharing a pressed key with those. So three
+    // keys on the corners of a rectangle also report the key on the fourth corner.
+    fn scan_keyboard(&self, active_lines: u8, driven_offset: usize, sensed_offset: usize) -> u8 {
         if let Some(matrix) = self.keyboard_matrix.as_ref() {
-            let mut result = 0xff;
-            for row in 0..8 as usize {
-                if active_rows.get_bit(row) == false {
-                    result &= matrix.borrow()[row];
+            let matrix = matrix.borrow();
+            let mut driven = !active_lines;
+            let mut sensed = 0u8;
+            loop {
+                let mut next_sensed = sensed;
+                for line in 0..8 as usize {
+                    if driven.get_bit(line) {
+                        next_sensed |= !matrix[driven_offset + line];
+                    }
                 }
+                let mut next_driven = driven;
+                for line in 0..8 as usize {
+                    if next_sensed.get_bit(line) {
+                        next_driven |= !matrix[sensed_offset + line];
+                    }
+                }
+                if next_sensed == sensed && next_driven == driven {
+                    break;
+                }
+                sensed = next_sensed;
+                driven = next_driven;
             }
-            result
+            !sensed
         } else {
             0xff
         }
@@ -210,20 +360,43 @@ impl Chip for Cia {
         let mut irq_event = false;
         if timer_a_output {
             self.irq_control.set_event(0);
+            self.events.emit(Event::CiaTimerUnderflow(self.mode as u8 + 1, 0));
             irq_event = true;
         }
         if timer_b_output {
             self.irq_control.set_event(1);
+            self.events.emit(Event::CiaTimerUnderflow(self.mode as u8 + 1, 1));
+            irq_event = true;
+        }
+        let cnt_high = self.cnt_pin.borrow().is_high();
+        let serial_event = if self.sdr_output {
+            self.clock_serial_output(timer_a_output)
+        } else {
+            self.clock_serial_input(cnt_high && !self.cnt_last)
+        };
+        self.cnt_last = self.cnt_pin.borrow().is_high();
+        if serial_event {
+            self.irq_control.set_event(3);
             irq_event = true;
         }
-        if self.flag_pin.borrow().is_falling() {
+        let flag_high = self.flag_pin.borrow().is_high();
+        if self.flag_last && !flag_high {
             self.irq_control.set_event(4);
             irq_event = true;
         }
+        self.flag_last = flag_high;
         if irq_event && self.irq_control.is_triggered() {
             self.irq_delay.feed(IntDelay::Interrupt0 as u16);
         }
-        if self.irq_delay.has_cycle(IntDelay::Interrupt1 as u16) {
+        /*
+        The 6526 brings the IRQ pin low one cycle after the interrupt event, the
+        6526A does so in the same cycle.
+        */
+        let irq_stage = match self.chip_model {
+            CiaModel::Mos6526 => IntDelay::Interrupt1,
+            CiaModel::Mos6526A => IntDelay::Interrupt0,
+        };
+        if self.irq_delay.has_cycle(irq_stage as u16) {
             self.irq_line
                 .borrow_mut()
                 .set_low(self.mode.irq_source(), true);
@@ -265,16 +438,70 @@ impl Chip for Cia {
         self.timer_a.reset();
         self.timer_b.reset();
         self.tod_set_alarm = false;
+        self.sdr = 0;
+        self.sdr_loaded = false;
+        self.sdr_output = false;
+        self.sdr_shift = 0;
+        self.sdr_shift_count = 0;
         self.cnt_pin.borrow_mut().set_active(true);
+        self.cnt_last = true;
         self.flag_pin.borrow_mut().set_active(false);
+        self.flag_last = false;
+        self.sp_pin.borrow_mut().set_active(true);
         self.port_a.borrow_mut().reset();
         self.port_b.borrow_mut().reset();
+        self.update_iec_bus();
+    }
+
+    fn save_state(&self, snapshot: &mut Snapshot) {
+        self.irq_control.save_state(snapshot);
+        self.irq_delay.save_state(snapshot);
+        self.timer_a.save_state(snapshot);
+        self.timer_b.save_state(snapshot);
+        self.tod_alarm.save_state(snapshot);
+        self.tod_clock.save_state(snapshot);
+        snapshot.write_bool(self.tod_set_alarm);
+        snapshot.write_u8(self.sdr);
+        snapshot.write_bool(self.sdr_loaded);
+        snapshot.write_bool(self.sdr_output);
+        snapshot.write_u8(self.sdr_shift);
+        snapshot.write_u8(self.sdr_shift_count);
+        self.cnt_pin.borrow().save_state(snapshot);
+        snapshot.write_bool(self.cnt_last);
+        self.flag_pin.borrow().save_state(snapshot);
+        snapshot.write_bool(self.flag_last);
+        self.sp_pin.borrow().save_state(snapshot);
+        self.port_a.borrow().save_state(snapshot);
+        self.port_b.borrow().save_state(snapshot);
+    }
+
+    fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.irq_control.load_state(snapshot)?;
+        self.irq_delay.load_state(snapshot)?;
+        self.timer_a.load_state(snapshot)?;
+        self.timer_b.load_state(snapshot)?;
+        self.tod_alarm.load_state(snapshot)?;
+        self.tod_clock.load_state(snapshot)?;
+        self.tod_set_alarm = snapshot.read_bool()?;
+        self.sdr = snapshot.read_u8()?;
+        self.sdr_loaded = snapshot.read_bool()?;
+        self.sdr_output = snapshot.read_bool()?;
+        self.sdr_shift = snapshot.read_u8()?;
+        self.sdr_shift_count = snapshot.read_u8()?;
+        self.cnt_pin.borrow_mut().load_state(snapshot)?;
+        self.cnt_last = snapshot.read_bool()?;
+        self.flag_pin.borrow_mut().load_state(snapshot)?;
+        self.flag_last = snapshot.read_bool()?;
+        self.sp_pin.borrow_mut().load_state(snapshot)?;
+        self.port_a.borrow_mut().load_state(snapshot)?;
+        self.port_b.borrow_mut().load_state(snapshot)?;
+        Ok(())
     }
 
     // I/O
 
-    fn read(&mut self, reg: u8) -> u8 {
-        let value = match reg {
+    fn peek(&self, reg: u8) -> u8 {
+        match reg {
             reg::PRA => match self.mode {
                 Mode::Cia1 => self.read_cia1_port_a(),
                 Mode::Cia2 => self.read_cia2_port_a(),
@@ -289,10 +516,7 @@ impl Chip for Cia {
             reg::TAHI => self.timer_a.get_counter_hi(),
             reg::TBLO => self.timer_b.get_counter_lo(),
             reg::TBHI => self.timer_b.get_counter_hi(),
-            reg::TODTS => {
-                self.tod_clock.set_enabled(true);
-                to_bcd(self.tod_clock.get_tenth())
-            }
+            reg::TODTS => to_bcd(self.tod_clock.get_tenth()),
             reg::TODSEC => to_bcd(self.tod_clock.get_seconds()),
             reg::TODMIN => to_bcd(self.tod_clock.get_minutes()),
             reg::TODHR => {
@@ -300,7 +524,28 @@ impl Chip for Cia {
                 result.set_bit(7, self.tod_clock.get_pm());
                 result
             }
-            reg::SDR => 0,
+            reg::SDR => self.sdr,
+            reg::ICR => self.irq_control.get_data(),
+            reg::CRA => {
+                let mut config = self.timer_a.get_config();
+                config.set_bit(6, self.sdr_output);
+                config
+            }
+            reg::CRB => {
+                let mut config = self.timer_b.get_config();
+                config.set_bit(7, self.tod_set_alarm);
+                config
+            }
+            _ => panic!("invalid reg {}", reg),
+        }
+    }
+
+    fn read(&mut self, reg: u8) -> u8 {
+        let value = self.peek(reg);
+        match reg {
+            reg::TODTS => {
+                self.tod_clock.set_enabled(true);
+            }
             reg::ICR => {
                 /*
                 In a multi-chip system, the IR bit can be polled to detect which chip has generated
@@ -308,22 +553,14 @@ impl Chip for Cia {
                 is cleared and the IRQ line returns high following a
                 read of the DATA register.
                 */
-                let data = self.irq_control.get_data();
                 self.irq_control.clear();
                 self.irq_delay.reset();
                 self.irq_line
                     .borrow_mut()
                     .set_low(self.mode.irq_source(), false);
-                data
-            }
-            reg::CRA => self.timer_a.get_config(),
-            reg::CRB => {
-                let mut config = self.timer_b.get_config();
-                config.set_bit(7, self.tod_set_alarm);
-                config
             }
-            _ => panic!("invalid reg {}", reg),
-        };
+            _ => {}
+        }
         if log_enabled!(LogLevel::Trace) {
             trace!(target: "cia::reg", "Read 0x{:02x} = 0x{:02x}", reg, value);
         }
@@ -337,12 +574,14 @@ impl Chip for Cia {
         match reg {
             reg::PRA => {
                 self.port_a.borrow_mut().set_value(value);
+                self.update_iec_bus();
             }
             reg::PRB => {
                 self.port_b.borrow_mut().set_value(value);
             }
             reg::DDRA => {
                 self.port_a.borrow_mut().set_direction(value);
+                self.update_iec_bus();
             }
             reg::DDRB => {
                 self.port_b.borrow_mut().set_direction(value);
@@ -393,7 +632,12 @@ impl Chip for Cia {
                 tod.set_hours(from_bcd(value & 0x7f));
                 tod.set_pm(value.get_bit(7));
             }
-            reg::SDR => {}
+            reg::SDR => {
+                self.sdr = value;
+                if self.sdr_output {
+                    self.sdr_loaded = true;
+                }
+            }
             reg::ICR => {
                 /*
                 The MASK register provides convenient control of
@@ -414,6 +658,14 @@ impl Chip for Cia {
             }
             reg::CRA => {
                 self.timer_a.set_config(value);
+                let sdr_output = value.get_bit(6);
+                if sdr_output != self.sdr_output {
+                    self.sdr_output = sdr_output;
+                    self.sdr_loaded = false;
+                    self.sdr_shift_count = 0;
+                    self.cnt_pin.borrow_mut().set_active(true);
+                    self.cnt_last = true;
+                }
             }
             reg::CRB => {
                 self.timer_b.set_config(value);
@@ -435,7 +687,7 @@ fn to_bcd(num: u8) -> u8 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::util::new_shared;
+    use crate::util::{new_shared, Clock};
 
     fn setup_cia() -> Cia {
         let cia_flag = new_shared(Pin::new_low());
@@ -445,19 +697,21 @@ mod tests {
         let keyboard_matrix = new_shared([0xff; 16]);
         let mut cia = Cia::new(
             Mode::Cia1,
+            CiaModel::Mos6526,
             None,
             None,
             Some(keyboard_matrix),
             cia_port_a,
             cia_port_b,
+            None,
             cia_flag,
             cpu_irq,
+            Rc::new(EventBus::new(Rc::new(Clock::default()))),
         );
         cia.reset();
         cia
     }
 
-    #[allow(dead_code)]
     fn setup_cia_with_keyboard(keyboard_matrix: Shared<[u8; 16]>) -> Cia {
         let cia_flag = new_shared(Pin::new_low());
         let cia_port_a = new_shared(IoPort::new(0x00, 0xff));
@@ -465,18 +719,62 @@ mod tests {
         let cpu_irq = new_shared(IrqLine::new("irq"));
         let mut cia = Cia::new(
             Mode::Cia1,
+            CiaModel::Mos6526,
             None,
             None,
             Some(keyboard_matrix),
             cia_port_a,
             cia_port_b,
+            None,
             cia_flag,
             cpu_irq,
+            Rc::new(EventBus::new(Rc::new(Clock::default()))),
         );
         cia.reset();
         cia
     }
 
+    fn press_key(keyboard_matrix: &Shared<[u8; 16]>, row: usize, col: usize) {
+        let mut matrix = keyboard_matrix.borrow_mut();
+        matrix[row].set_bit(col, false);
+        matrix[8 + col].set_bit(row, false);
+    }
+
+    #[test]
+    fn keyboard_two_key_chord() {
+        let keyboard_matrix = new_shared([0xff; 16]);
+        let mut cia = setup_cia_with_keyboard(keyboard_matrix.clone());
+        press_key(&keyboard_matrix, 1, 2); // A
+        press_key(&keyboard_matrix, 7, 6); // Q
+        cia.write(reg::DDRA, 0xff);
+        cia.write(reg::PRA, !0x02);
+        assert_eq!(!0x04, cia.read(reg::PRB));
+        cia.write(reg::PRA, !0x80);
+        assert_eq!(!0x40, cia.read(reg::PRB));
+        cia.write(reg::PRA, !0x82);
+        assert_eq!(!0x44, cia.read(reg::PRB));
+        cia.write(reg::PRA, !0x01);
+        assert_eq!(0xff, cia.read(reg::PRB));
+    }
+
+    #[test]
+    fn keyboard_ghosting() {
+        let keyboard_matrix = new_shared([0xff; 16]);
+        let mut cia = setup_cia_with_keyboard(keyboard_matrix.clone());
+        press_key(&keyboard_matrix, 1, 2); // A
+        press_key(&keyboard_matrix, 1, 5); // S
+        press_key(&keyboard_matrix, 2, 2); // D
+        cia.write(reg::DDRA, 0xff);
+        // F at row 2, col 5 is read through S and A
+        cia.write(reg::PRA, !0x04);
+        assert_eq!(!0x24, cia.read(reg::PRB));
+        // Reverse scan sees the phantom key as well
+        cia.write(reg::DDRA, 0x00);
+        cia.write(reg::DDRB, 0xff);
+        cia.write(reg::PRB, !0x20);
+        assert_eq!(!0x06, cia.read(reg::PRA));
+    }
+
     #[test]
     fn read_regs() {
         let mut cia = setup_cia();
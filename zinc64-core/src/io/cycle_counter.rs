@@ -2,6 +2,10 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
+
+use crate::util::{Snapshot, SnapshotReader};
+
 pub struct CycleCounter {
     // Configuration
     mask: u16,
@@ -48,6 +52,17 @@ impl CycleCounter {
         self.cycles &= !mask;
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u16(self.cycles);
+        snapshot.write_u16(self.feed);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.cycles = snapshot.read_u16()?;
+        self.feed = snapshot.read_u16()?;
+        Ok(())
+    }
+
     #[inline(always)]
     pub fn reset(&mut self) {
         self.cycles = 0;
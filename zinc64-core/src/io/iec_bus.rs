@@ -0,0 +1,112 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::string::String;
+use bit_field::BitField;
+
+use crate::util::{Snapshot, SnapshotReader};
+
+// Spec: https://www.c64-wiki.com/wiki/Serial_Port
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IecLine {
+    Atn = 0,
+    Clk = 1,
+    Data = 2,
+}
+
+/// Serial bus with open-collector ATN, CLK and DATA lines.
+///
+/// Each participant is identified by a device number (the computer uses 0, drives their
+/// bus address). A line is high only when no participant pulls it low.
+pub struct IecBus {
+    lines: [u32; 3],
+}
+
+impl IecBus {
+    pub const HOST: usize = 0;
+
+    pub fn new() -> Self {
+        Self { lines: [0; 3] }
+    }
+
+    pub fn is_high(&self, line: IecLine) -> bool {
+        self.lines[line as usize] == 0
+    }
+
+    pub fn is_low(&self, line: IecLine) -> bool {
+        !self.is_high(line)
+    }
+
+    pub fn release(&mut self, device: usize) {
+        for line in self.lines.iter_mut() {
+            line.set_bit(device, false);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.lines = [0; 3];
+    }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        for line in self.lines.iter() {
+            snapshot.write_u32(*line);
+        }
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        for line in self.lines.iter_mut() {
+            *line = snapshot.read_u32()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_low(&mut self, device: usize, line: IecLine, value: bool) {
+        self.lines[line as usize].set_bit(device, value);
+    }
+}
+
+impl Default for IecBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_lines_are_high() {
+        let bus = IecBus::new();
+        assert!(bus.is_high(IecLine::Atn));
+        assert!(bus.is_high(IecLine::Clk));
+        assert!(bus.is_high(IecLine::Data));
+    }
+
+    #[test]
+    fn any_device_pulls_line_low() {
+        let mut bus = IecBus::new();
+        bus.set_low(IecBus::HOST, IecLine::Data, false);
+        bus.set_low(8, IecLine::Data, true);
+        assert!(bus.is_low(IecLine::Data));
+        assert!(bus.is_high(IecLine::Clk));
+        bus.set_low(IecBus::HOST, IecLine::Data, true);
+        bus.set_low(8, IecLine::Data, false);
+        assert!(bus.is_low(IecLine::Data));
+        bus.set_low(IecBus::HOST, IecLine::Data, false);
+        assert!(bus.is_high(IecLine::Data));
+    }
+
+    #[test]
+    fn release_device() {
+        let mut bus = IecBus::new();
+        bus.set_low(8, IecLine::Clk, true);
+        bus.set_low(9, IecLine::Clk, true);
+        bus.release(8);
+        assert!(bus.is_low(IecLine::Clk));
+        bus.release(9);
+        assert!(bus.is_high(IecLine::Clk));
+    }
+}
@@ -0,0 +1,456 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bit_field::BitField;
+use log::{log, trace};
+
+use crate::factory::IecDevice;
+use crate::util::Shared;
+
+use super::iec_bus::{IecBus, IecLine};
+
+// Spec: https://www.pagetable.com/?p=1135 Commodore Peripheral Bus: Part 4: Standard Serial
+
+// Design:
+//   IecProtocol takes the part of all attached protocol level devices on the shared bus.
+//   It runs the listener and talker side of the byte handshake one cycle at a time and
+//   dispatches the decoded command bytes sent under ATN. Timing constants are in cycles,
+//   which at 1MHz match the microseconds given by the spec.
+
+const EOI_TIMEOUT: u32 = 200;
+const EOI_ACK: u32 = 60;
+const NON_EOI_RESPONSE: u32 = 40;
+const BIT_SETUP: u32 = 70;
+const BIT_VALID: u32 = 60;
+const FRAME_TIMEOUT: u32 = 1000;
+const BYTE_DELAY: u32 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IecCommand {
+    Listen(u8),
+    Unlisten,
+    Talk(u8),
+    Untalk,
+    Secondary(u8),
+    Close(u8),
+    Open(u8),
+}
+
+impl IecCommand {
+    pub fn decode(value: u8) -> Option<IecCommand> {
+        match value {
+            0x3f => Some(IecCommand::Unlisten),
+            0x20..=0x3e => Some(IecCommand::Listen(value & 0x1f)),
+            0x5f => Some(IecCommand::Untalk),
+            0x40..=0x5e => Some(IecCommand::Talk(value & 0x1f)),
+            0x60..=0x6f => Some(IecCommand::Secondary(value & 0x0f)),
+            0xe0..=0xef => Some(IecCommand::Close(value & 0x0f)),
+            0xf0..=0xff => Some(IecCommand::Open(value & 0x0f)),
+            _ => None,
+        }
+    }
+
+    pub fn encode(self) -> u8 {
+        match self {
+            IecCommand::Listen(device) => 0x20 | device,
+            IecCommand::Unlisten => 0x3f,
+            IecCommand::Talk(device) => 0x40 | device,
+            IecCommand::Untalk => 0x5f,
+            IecCommand::Secondary(channel) => 0x60 | channel,
+            IecCommand::Close(channel) => 0xe0 | channel,
+            IecCommand::Open(channel) => 0xf0 | channel,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    // Listener, DATA is held low until the talker is ready to send
+    ReceiveAtn,
+    ReceiveReady,
+    ReceiveStart,
+    ReceiveEoiAck,
+    ReceiveBit,
+    ReceiveBitValid,
+    // Talker
+    Turnaround,
+    SendPrepare,
+    SendReady,
+    SendStart,
+    SendEoi,
+    SendEoiAck,
+    SendBitSetup,
+    SendBitValid,
+    SendFrameAck,
+}
+
+pub struct IecProtocol {
+    // Dependencies
+    iec_bus: Shared<IecBus>,
+    devices: Vec<(usize, Box<dyn IecDevice>)>,
+    // Runtime State
+    atn: bool,
+    bit: u8,
+    byte: u8,
+    channel: u8,
+    eoi: bool,
+    listener: Option<usize>,
+    state: State,
+    talker: Option<usize>,
+    timer: u32,
+}
+
+impl IecProtocol {
+    pub fn new(iec_bus: Shared<IecBus>) -> Self {
+        Self {
+            iec_bus,
+            devices: Vec::new(),
+            atn: false,
+            bit: 0,
+            byte: 0,
+            channel: 0,
+            eoi: false,
+            listener: None,
+            state: State::Idle,
+            talker: None,
+            timer: 0,
+        }
+    }
+
+    /// Attach device with a bus address between 4 and 11.
+    pub fn attach(&mut self, number: usize, device: Box<dyn IecDevice>) -> Result<(), String> {
+        if !(4..=11).contains(&number) {
+            return Err(format!("invalid serial device number {}", number));
+        }
+        if self.has_device(number) {
+            return Err(format!("serial device {} already attached", number));
+        }
+        self.devices.push((number, device));
+        Ok(())
+    }
+
+    pub fn detach(&mut self, number: usize) {
+        if self.has_device(number) {
+            self.iec_bus.borrow_mut().release(number);
+            self.devices.retain(|(n, _)| *n != number);
+            if self.listener == Some(number) || self.talker == Some(number) {
+                self.release();
+            }
+        }
+    }
+
+    pub fn has_device(&self, number: usize) -> bool {
+        self.devices.iter().any(|(n, _)| *n == number)
+    }
+
+    pub fn has_devices(&self) -> bool {
+        !self.devices.is_empty()
+    }
+
+    pub fn clock(&mut self) {
+        if self.devices.is_empty() {
+            return;
+        }
+        let atn = self.iec_bus.borrow().is_low(IecLine::Atn);
+        if atn != self.atn {
+            self.atn = atn;
+            if atn {
+                // Every device acknowledges ATN and listens to the command bytes
+                self.set_line(IecLine::Clk, false);
+                self.set_line(IecLine::Data, true);
+                self.eoi = false;
+                if self.is_low(IecLine::Clk) {
+                    self.enter(State::ReceiveReady);
+                } else {
+                    self.enter(State::ReceiveAtn);
+                }
+            } else {
+                // Only the addressed device stays on the bus
+                self.release();
+                if self.listener.is_some() {
+                    self.set_line(IecLine::Data, true);
+                    self.state = State::ReceiveReady;
+                } else if self.talker.is_some() {
+                    self.state = State::Turnaround;
+                }
+            }
+            return;
+        }
+        self.timer += 1;
+        match self.state {
+            State::Idle => {}
+            State::ReceiveAtn => {
+                // The controller may pull CLK after ATN, wait for it before
+                // watching for ready to send
+                if self.is_low(IecLine::Clk) {
+                    self.enter(State::ReceiveReady);
+                }
+            }
+            State::ReceiveReady => {
+                if self.is_high(IecLine::Clk) {
+                    self.set_line(IecLine::Data, false);
+                    self.enter(State::ReceiveStart);
+                }
+            }
+            State::ReceiveStart => {
+                if self.is_low(IecLine::Clk) {
+                    self.bit = 0;
+                    self.byte = 0;
+                    self.enter(State::ReceiveBit);
+                } else if !self.eoi && self.timer >= EOI_TIMEOUT {
+                    self.eoi = true;
+                    self.set_line(IecLine::Data, true);
+                    self.enter(State::ReceiveEoiAck);
+                }
+            }
+            State::ReceiveEoiAck => {
+                if self.timer >= EOI_ACK {
+                    self.set_line(IecLine::Data, false);
+                    self.enter(State::ReceiveStart);
+                }
+            }
+            State::ReceiveBit => {
+                if self.is_high(IecLine::Clk) {
+                    let value = self.is_high(IecLine::Data);
+                    self.byte.set_bit(self.bit as usize, value);
+                    self.enter(State::ReceiveBitValid);
+                }
+            }
+            State::ReceiveBitValid => {
+                if self.is_low(IecLine::Clk) {
+                    self.bit += 1;
+                    if self.bit < 8 {
+                        self.enter(State::ReceiveBit);
+                    } else {
+                        self.set_line(IecLine::Data, true);
+                        self.enter(State::ReceiveReady);
+                        self.receive_byte(self.byte, self.eoi);
+                        self.eoi = false;
+                    }
+                }
+            }
+            State::Turnaround => {
+                if self.is_high(IecLine::Clk) {
+                    self.set_line(IecLine::Data, false);
+                    self.set_line(IecLine::Clk, true);
+                    self.enter(State::SendPrepare);
+                }
+            }
+            State::SendPrepare => {
+                if self.timer >= BYTE_DELAY {
+                    let channel = self.channel;
+                    let next = match self.talker {
+                        Some(number) => self
+                            .device(number)
+                            .and_then(|device| device.read(channel)),
+                        None => None,
+                    };
+                    match next {
+                        Some((value, eoi)) => {
+                            self.byte = value;
+                            self.eoi = eoi;
+                            self.set_line(IecLine::Clk, false);
+                            self.enter(State::SendReady);
+                        }
+                        None => {
+                            // Nothing to send, the controller times out
+                            self.release();
+                        }
+                    }
+                }
+            }
+            State::SendReady => {
+                if self.is_high(IecLine::Data) {
+                    if self.eoi {
+                        self.enter(State::SendEoi);
+                    } else {
+                        self.enter(State::SendStart);
+                    }
+                }
+            }
+            State::SendStart => {
+                // The listener needs time to start polling CLK after it released DATA
+                if self.timer >= NON_EOI_RESPONSE {
+                    self.start_send();
+                }
+            }
+            State::SendEoi => {
+                if self.is_low(IecLine::Data) {
+                    self.enter(State::SendEoiAck);
+                }
+            }
+            State::SendEoiAck => {
+                if self.is_high(IecLine::Data) {
+                    self.enter(State::SendStart);
+                }
+            }
+            State::SendBitSetup => {
+                let value = self.byte.get_bit(self.bit as usize);
+                self.set_line(IecLine::Data, !value);
+                if self.timer >= BIT_SETUP {
+                    self.set_line(IecLine::Clk, false);
+                    self.enter(State::SendBitValid);
+                }
+            }
+            State::SendBitValid => {
+                if self.timer >= BIT_VALID {
+                    self.set_line(IecLine::Clk, true);
+                    self.set_line(IecLine::Data, false);
+                    self.bit += 1;
+                    if self.bit < 8 {
+                        self.enter(State::SendBitSetup);
+                    } else {
+                        self.enter(State::SendFrameAck);
+                    }
+                }
+            }
+            State::SendFrameAck => {
+                if self.is_low(IecLine::Data) {
+                    if self.eoi {
+                        self.set_line(IecLine::Clk, false);
+                        self.enter(State::Idle);
+                    } else {
+                        self.enter(State::SendPrepare);
+                    }
+                } else if self.timer >= FRAME_TIMEOUT {
+                    trace!(target: "iec", "Frame not acknowledged");
+                    self.release();
+                }
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.release();
+        self.atn = false;
+        self.channel = 0;
+        self.listener = None;
+        self.talker = None;
+        for (_, device) in self.devices.iter_mut() {
+            device.reset();
+        }
+    }
+
+    fn device(&mut self, number: usize) -> Option<&mut Box<dyn IecDevice>> {
+        self.devices
+            .iter_mut()
+            .find(|(n, _)| *n == number)
+            .map(|(_, device)| device)
+    }
+
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.timer = 0;
+    }
+
+    fn is_high(&self, line: IecLine) -> bool {
+        self.iec_bus.borrow().is_high(line)
+    }
+
+    fn is_low(&self, line: IecLine) -> bool {
+        self.iec_bus.borrow().is_low(line)
+    }
+
+    fn receive_byte(&mut self, value: u8, eoi: bool) {
+        if self.atn {
+            let command = IecCommand::decode(value);
+            trace!(target: "iec", "Command {:?}", command);
+            match command {
+                Some(IecCommand::Listen(number)) => {
+                    self.listener = Some(number as usize).filter(|n| self.has_device(*n));
+                }
+                Some(IecCommand::Unlisten) => self.listener = None,
+                Some(IecCommand::Talk(number)) => {
+                    self.talker = Some(number as usize).filter(|n| self.has_device(*n));
+                }
+                Some(IecCommand::Untalk) => self.talker = None,
+                Some(IecCommand::Secondary(channel)) => self.channel = channel,
+                Some(IecCommand::Open(channel)) => {
+                    self.channel = channel;
+                    if let Some(device) = self.listener.and_then(|number| self.device(number)) {
+                        device.open(channel);
+                    }
+                }
+                Some(IecCommand::Close(channel)) => {
+                    if let Some(device) = self.listener.and_then(|number| self.device(number)) {
+                        device.close(channel);
+                    }
+                }
+                None => {}
+            }
+        } else {
+            let channel = self.channel;
+            if let Some(device) = self.listener.and_then(|number| self.device(number)) {
+                device.write(channel, value, eoi);
+            }
+        }
+    }
+
+    fn release(&mut self) {
+        let mut iec_bus = self.iec_bus.borrow_mut();
+        for (number, _) in self.devices.iter() {
+            iec_bus.release(*number);
+        }
+        self.state = State::Idle;
+        self.timer = 0;
+    }
+
+    fn set_line(&mut self, line: IecLine, low: bool) {
+        /*
+        Lines are driven under the number of every attached device while they all
+        respond to ATN, otherwise under the number of the addressed device.
+        */
+        let active = self.talker.or(self.listener);
+        let atn = self.atn;
+        let mut iec_bus = self.iec_bus.borrow_mut();
+        for (number, _) in self.devices.iter() {
+            let drive = match active {
+                Some(active) if !atn => *number == active,
+                _ => true,
+            };
+            iec_bus.set_low(*number, line, low && drive);
+        }
+    }
+
+    fn start_send(&mut self) {
+        self.set_line(IecLine::Clk, true);
+        self.bit = 0;
+        self.enter(State::SendBitSetup);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_commands() {
+        assert_eq!(Some(IecCommand::Listen(8)), IecCommand::decode(0x28));
+        assert_eq!(Some(IecCommand::Unlisten), IecCommand::decode(0x3f));
+        assert_eq!(Some(IecCommand::Talk(4)), IecCommand::decode(0x44));
+        assert_eq!(Some(IecCommand::Untalk), IecCommand::decode(0x5f));
+        assert_eq!(Some(IecCommand::Secondary(2)), IecCommand::decode(0x62));
+        assert_eq!(Some(IecCommand::Close(15)), IecCommand::decode(0xef));
+        assert_eq!(Some(IecCommand::Open(1)), IecCommand::decode(0xf1));
+        assert_eq!(None, IecCommand::decode(0x10));
+        assert_eq!(None, IecCommand::decode(0x80));
+    }
+
+    #[test]
+    fn encode_commands() {
+        for value in 0x20..=0x6f {
+            let command = IecCommand::decode(value).unwrap();
+            assert_eq!(value, command.encode());
+        }
+        for value in 0xe0..=0xff {
+            let command = IecCommand::decode(value).unwrap();
+            assert_eq!(value, command.encode());
+        }
+    }
+}
@@ -4,7 +4,13 @@
 
 pub mod cia;
 mod cycle_counter;
+mod iec_bus;
+mod iec_protocol;
 mod rtc;
 mod timer;
+mod user_port;
 
 pub use self::cia::Cia;
+pub use self::iec_bus::{IecBus, IecLine};
+pub use self::iec_protocol::{IecCommand, IecProtocol};
+pub use self::user_port::UserPort;
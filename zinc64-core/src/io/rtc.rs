@@ -2,8 +2,11 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
 use core::cmp::PartialEq;
 
+use crate::util::{Snapshot, SnapshotReader};
+
 pub struct Rtc {
     enabled: bool,
     hours: u8,
@@ -45,6 +48,25 @@ impl Rtc {
         self.pm
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bool(self.enabled);
+        snapshot.write_u8(self.hours);
+        snapshot.write_u8(self.minutes);
+        snapshot.write_u8(self.seconds);
+        snapshot.write_u8(self.tenth);
+        snapshot.write_bool(self.pm);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.enabled = snapshot.read_bool()?;
+        self.hours = snapshot.read_u8()?;
+        self.minutes = snapshot.read_u8()?;
+        self.seconds = snapshot.read_u8()?;
+        self.tenth = snapshot.read_u8()?;
+        self.pm = snapshot.read_bool()?;
+        Ok(())
+    }
+
     pub fn set_enabled(&mut self, enabled: bool) {
         self.enabled = enabled;
     }
@@ -4,9 +4,10 @@
 
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
 
+use alloc::string::String;
 use bit_field::BitField;
 
-use crate::util::{Pin, Shared};
+use crate::util::{Pin, Shared, Snapshot, SnapshotReader};
 
 use super::cycle_counter::CycleCounter;
 
@@ -274,6 +275,43 @@ impl Timer {
         }
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.get_config());
+        snapshot.write_u16(self.counter);
+        self.delay.save_state(snapshot);
+        snapshot.write_u16(self.latch);
+        snapshot.write_bool(self.pb_output);
+        snapshot.write_bool(self.pb_toggle);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        let config = snapshot.read_u8()?;
+        self.enabled = config.get_bit(0);
+        self.pb_on = config.get_bit(1);
+        self.output_mode = if config.get_bit(2) {
+            OutputMode::Toggle
+        } else {
+            OutputMode::Pulse
+        };
+        self.run_mode = if config.get_bit(3) {
+            RunMode::OneShot
+        } else {
+            RunMode::Continuous
+        };
+        self.input_mode = match (config >> 5) & 0x03 {
+            0 => InputMode::SystemClock,
+            1 => InputMode::External,
+            2 => InputMode::TimerA,
+            _ => InputMode::TimerAWithCNT,
+        };
+        self.counter = snapshot.read_u16()?;
+        self.delay.load_state(snapshot)?;
+        self.latch = snapshot.read_u16()?;
+        self.pb_output = snapshot.read_bool()?;
+        self.pb_toggle = snapshot.read_bool()?;
+        Ok(())
+    }
+
     pub fn reset(&mut self) {
         self.enabled = false;
         self.input_mode = InputMode::SystemClock;
@@ -0,0 +1,211 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bit_field::BitField;
+
+use crate::factory::Peripheral;
+use crate::util::{IoPort, Shared, Snapshot, SnapshotReader};
+
+// Design:
+//   UserPort connects peripherals to the data lines of the user port, PB0-PB7 and PA2
+//   of CIA 2. Peripherals are told the levels of the lines when they change, with
+//   lines set as input reading high, and report the PB lines they drive every cycle.
+//   Driven lines are open collector, so a line is low if any peripheral pulls it low.
+//   Only the lines driven by peripherals are merged into port B input, leaving the
+//   other lines to devices wired to the port directly such as the RS-232 interface.
+
+const PA2: usize = 2;
+
+pub struct UserPort {
+    // Dependencies
+    port_a: Shared<IoPort>,
+    port_b: Shared<IoPort>,
+    peripherals: Vec<Box<dyn Peripheral>>,
+    // Runtime State
+    driven: (u8, u8),
+    last_value: Option<(u8, bool)>,
+}
+
+impl UserPort {
+    pub fn new(port_a: Shared<IoPort>, port_b: Shared<IoPort>) -> Self {
+        Self {
+            port_a,
+            port_b,
+            peripherals: Vec::new(),
+            driven: (0x00, 0xff),
+            last_value: None,
+        }
+    }
+
+    pub fn add_peripheral(&mut self, peripheral: Box<dyn Peripheral>) {
+        self.peripherals.push(peripheral);
+        self.last_value = None;
+    }
+
+    pub fn clock(&mut self) {
+        if self.peripherals.is_empty() {
+            return;
+        }
+        let value = (
+            self.port_b.borrow().get_value_2(0xff),
+            self.port_a.borrow().get_value_2(0xff).get_bit(PA2),
+        );
+        let changed = self.last_value.replace(value) != Some(value);
+        let (mut mask, mut levels) = (0x00, 0xff);
+        for peripheral in self.peripherals.iter_mut() {
+            peripheral.clock();
+            if changed {
+                peripheral.set_user_port_lines(value.0, value.1);
+            }
+            let (lines, lines_levels) = peripheral.get_user_port_lines();
+            mask |= lines;
+            levels &= lines_levels | !lines;
+        }
+        self.drive(mask, levels);
+    }
+
+    pub fn reset(&mut self) {
+        self.driven = (0x00, 0xff);
+        self.last_value = None;
+        for peripheral in self.peripherals.iter_mut() {
+            peripheral.reset();
+        }
+    }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.driven.0);
+        snapshot.write_u8(self.driven.1);
+        snapshot.write_bool(self.last_value.is_some());
+        if let Some((port_b, pa2)) = self.last_value {
+            snapshot.write_u8(port_b);
+            snapshot.write_bool(pa2);
+        }
+        snapshot.write_u32(self.peripherals.len() as u32);
+        for peripheral in self.peripherals.iter() {
+            peripheral.save_state(snapshot);
+        }
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.driven = (snapshot.read_u8()?, snapshot.read_u8()?);
+        self.last_value = if snapshot.read_bool()? {
+            Some((snapshot.read_u8()?, snapshot.read_bool()?))
+        } else {
+            None
+        };
+        if snapshot.read_u32()? != self.peripherals.len() as u32 {
+            return Err(String::from("snapshot peripherals do not match user port"));
+        }
+        for peripheral in self.peripherals.iter_mut() {
+            peripheral.load_state(snapshot)?;
+        }
+        Ok(())
+    }
+
+    // Lines no longer driven are released to float high.
+    fn drive(&mut self, mask: u8, levels: u8) {
+        if (mask, levels) != self.driven {
+            let released = self.driven.0 & !mask;
+            self.port_b
+                .borrow_mut()
+                .set_input_masked(mask | released, levels);
+            self.driven = (mask, levels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::new_shared;
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use core::cell::RefCell;
+
+    type LineLog = Rc<RefCell<Vec<(u8, bool)>>>;
+
+    // Drives PB4-PB7 with the given levels
+    struct NibblePeripheral {
+        levels: u8,
+        lines: LineLog,
+    }
+
+    impl Peripheral for NibblePeripheral {
+        fn clock(&mut self) {}
+
+        fn reset(&mut self) {}
+
+        fn read(&mut self, _address: u16) -> Option<u8> {
+            None
+        }
+
+        fn write(&mut self, _address: u16, _value: u8) {}
+
+        fn set_user_port_lines(&mut self, port_b: u8, pa2: bool) {
+            self.lines.borrow_mut().push((port_b, pa2));
+        }
+
+        fn get_user_port_lines(&self) -> (u8, u8) {
+            (0xf0, self.levels)
+        }
+    }
+
+    fn setup_user_port(levels: u8) -> (UserPort, Shared<IoPort>, Shared<IoPort>, LineLog) {
+        let port_a = new_shared(IoPort::new(0x00, 0xff));
+        let port_b = new_shared(IoPort::new(0x00, 0xff));
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let mut user_port = UserPort::new(port_a.clone(), port_b.clone());
+        user_port.add_peripheral(Box::new(NibblePeripheral {
+            levels,
+            lines: lines.clone(),
+        }));
+        (user_port, port_a, port_b, lines)
+    }
+
+    #[test]
+    fn drive_port_b_input() {
+        let (mut user_port, _, port_b, _) = setup_user_port(0x5a);
+        user_port.clock();
+        assert_eq!(0x5f, port_b.borrow().get_value());
+    }
+
+    #[test]
+    fn keep_lines_not_driven() {
+        let (mut user_port, _, port_b, _) = setup_user_port(0x5a);
+        // RXD of the RS-232 interface
+        port_b.borrow_mut().set_input_bit(0, false);
+        user_port.clock();
+        user_port.clock();
+        assert_eq!(0x5e, port_b.borrow().get_value());
+    }
+
+    #[test]
+    fn pull_lines_low_from_any_device() {
+        let (mut user_port, _, port_b, lines) = setup_user_port(0x5a);
+        user_port.add_peripheral(Box::new(NibblePeripheral {
+            levels: 0xa5,
+            lines,
+        }));
+        user_port.clock();
+        assert_eq!(0x0f, port_b.borrow().get_value());
+    }
+
+    #[test]
+    fn report_line_changes() {
+        let (mut user_port, port_a, port_b, lines) = setup_user_port(0xff);
+        user_port.clock();
+        user_port.clock();
+        assert_eq!(vec![(0xff, true)], *lines.borrow());
+        lines.borrow_mut().clear();
+        port_b.borrow_mut().set_direction(0xff);
+        port_b.borrow_mut().set_value(0x0f);
+        user_port.clock();
+        port_a.borrow_mut().set_direction(0x04);
+        user_port.clock();
+        assert_eq!(vec![(0x0f, true), (0x0f, false)], *lines.borrow());
+    }
+}
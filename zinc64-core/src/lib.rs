@@ -9,6 +9,8 @@ extern crate alloc;
 pub mod cpu;
 pub mod cpu_gen1;
 pub mod device;
+#[cfg(feature = "drive")]
+pub mod drive;
 pub mod factory;
 pub mod io;
 pub mod mem;
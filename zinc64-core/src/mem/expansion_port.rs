@@ -4,9 +4,12 @@
 
 use core::option::Option::{self, Some, None};
 
-use crate::factory::AddressableFaded;
-use crate::util::{IoPort, Shared};
+use crate::factory::{AddressableFaded, Peripheral};
+use crate::util::{EventBus, IoPort, IrqLine, Pin, Shared, Snapshot, SnapshotReader};
 use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 use bit_field::BitField;
 
@@ -26,17 +29,44 @@ impl IoLine {
     }
 }
 
+pub enum IrqSource {
+    ExpansionPort = 3,
+    Peripheral = 5,
+}
+
+impl IrqSource {
+    pub fn value(self) -> usize {
+        self as usize
+    }
+}
+
 pub struct ExpansionPort {
     cartridge: Option<Cartridge>,
+    events: Rc<EventBus>,
+    peripherals: Vec<Box<dyn Peripheral>>,
     // I/O
+    ba_line: Shared<Pin>,
     io_line: Shared<IoPort>,
+    irq_line: Shared<IrqLine>,
+    nmi_line: Shared<IrqLine>,
 }
 
 impl ExpansionPort {
-    pub fn new(io_line: Shared<IoPort>) -> Self {
+    pub fn new(
+        io_line: Shared<IoPort>,
+        ba_line: Shared<Pin>,
+        irq_line: Shared<IrqLine>,
+        nmi_line: Shared<IrqLine>,
+        events: Rc<EventBus>,
+    ) -> Self {
         Self {
             cartridge: None,
+            events,
+            peripherals: Vec::new(),
+            ba_line,
             io_line,
+            irq_line,
+            nmi_line,
         }
     }
 
@@ -48,36 +78,161 @@ impl ExpansionPort {
             io_value.set_bit(IoLine::Exrom.value(), config.exrom);
             io_line_clone.borrow_mut().set_value(io_value);
         })));
+        cartridge.set_events(Some(self.events.clone()));
+        cartridge.reset();
         self.cartridge = Some(cartridge);
     }
 
+    pub fn add_peripheral(&mut self, peripheral: Box<dyn Peripheral>) {
+        self.peripherals.push(peripheral);
+    }
+
+    pub fn clock(&mut self) {
+        if self.peripherals.is_empty() {
+            return;
+        }
+        let (mut irq, mut nmi, mut dma) = (false, false, false);
+        for peripheral in self.peripherals.iter_mut() {
+            peripheral.clock();
+            irq |= peripheral.irq();
+            nmi |= peripheral.nmi();
+            dma |= peripheral.dma();
+        }
+        self.irq_line
+            .borrow_mut()
+            .set_low(IrqSource::Peripheral.value(), irq);
+        self.nmi_line
+            .borrow_mut()
+            .set_low(IrqSource::Peripheral.value(), nmi);
+        // BA is driven by the VIC every cycle before the expansion port is clocked
+        if dma {
+            self.ba_line.borrow_mut().set_active(false);
+        }
+    }
+
     pub fn detach(&mut self) {
         let mut cartridge = self.cartridge.take();
         if let Some(ref mut cartridge) = cartridge {
             cartridge.set_io_observer(None);
+            cartridge.set_events(None);
+        }
+        self.release_io_lines();
+        self.release_nmi();
+    }
+
+    /// Press the freeze button of the attached cartridge. The cartridge holds NMI
+    /// low until its freeze routine acknowledges it by writing the control
+    /// register. Returns false if there is no cartridge that can be frozen.
+    pub fn freeze(&mut self) -> bool {
+        let frozen = match self.cartridge {
+            Some(ref mut cartridge) => cartridge.freeze(),
+            None => false,
+        };
+        if frozen {
+            self.nmi_line
+                .borrow_mut()
+                .set_low(IrqSource::ExpansionPort.value(), true);
         }
+        frozen
+    }
+
+    pub fn get_cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.cartridge.is_some()
     }
 
     pub fn reset(&mut self) {
+        self.release_nmi();
         if let Some(ref mut cartridge) = self.cartridge {
             cartridge.reset();
         } else {
-            let mut io_value = 0u8;
-            io_value.set_bit(IoLine::Game.value(), true);
-            io_value.set_bit(IoLine::Exrom.value(), true);
-            self.io_line.borrow_mut().set_value(io_value);
+            self.release_io_lines();
+        }
+        for peripheral in self.peripherals.iter_mut() {
+            peripheral.reset();
         }
     }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bool(self.cartridge.is_some());
+        if let Some(ref cartridge) = self.cartridge {
+            cartridge.save_state(snapshot);
+        }
+        snapshot.write_u32(self.peripherals.len() as u32);
+        for peripheral in self.peripherals.iter() {
+            peripheral.save_state(snapshot);
+        }
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        if snapshot.read_bool()? != self.cartridge.is_some() {
+            return Err(String::from("snapshot cartridge attachment does not match expansion port"));
+        }
+        if let Some(ref mut cartridge) = self.cartridge {
+            cartridge.load_state(snapshot)?;
+        }
+        if snapshot.read_u32()? != self.peripherals.len() as u32 {
+            return Err(String::from("snapshot peripherals do not match expansion port"));
+        }
+        for peripheral in self.peripherals.iter_mut() {
+            peripheral.load_state(snapshot)?;
+        }
+        Ok(())
+    }
+
+    fn release_nmi(&self) {
+        self.nmi_line
+            .borrow_mut()
+            .set_low(IrqSource::ExpansionPort.value(), false);
+    }
+
+    fn release_io_lines(&self) {
+        let mut io_value = 0u8;
+        io_value.set_bit(IoLine::Game.value(), true);
+        io_value.set_bit(IoLine::Exrom.value(), true);
+        self.io_line.borrow_mut().set_value(io_value);
+    }
 }
 
 impl AddressableFaded for ExpansionPort {
     fn read(&mut self, address: u16) -> Option<u8> {
-        self.cartridge.as_mut().and_then(|crt| crt.read(address))
+        let result = self.cartridge.as_mut().and_then(|crt| crt.read(address));
+        if result.is_some() {
+            return result;
+        }
+        for peripheral in self.peripherals.iter_mut() {
+            let result = peripheral.read(address);
+            if result.is_some() {
+                return result;
+            }
+        }
+        None
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        let result = self.cartridge.as_ref().and_then(|crt| crt.peek(address));
+        if result.is_some() {
+            return result;
+        }
+        self.peripherals
+            .iter()
+            .find_map(|peripheral| peripheral.peek(address))
     }
 
     fn write(&mut self, address: u16, value: u8) {
         if let Some(ref mut cartridge) = self.cartridge {
             cartridge.write(address, value)
         }
+        if (0xde00..=0xdeff).contains(&address) {
+            self.release_nmi();
+        }
+        if (0xde00..=0xdfff).contains(&address) {
+            for peripheral in self.peripherals.iter_mut() {
+                peripheral.write(address, value);
+            }
+        }
     }
 }
@@ -2,6 +2,8 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
+
 use crate::factory::{Addressable, AddressableFaded, Bank, Mmu};
 use crate::mem::{BaseAddr, Mmio};
 use crate::util::{Ram, Rom, Shared};
@@ -43,6 +45,22 @@ impl Memory {
             kernal: rom_kernal,
         }
     }
+
+    /// Let a device claim the address range from `start` to `end` inclusive within the
+    /// I/O area at $D000-$DFFF. Accesses to the range are dispatched to the device
+    /// whenever I/O is banked in.
+    pub fn register_io(
+        &mut self,
+        start: u16,
+        end: u16,
+        device: Shared<dyn Addressable>,
+    ) -> Result<(), String> {
+        self.io.register(start, end, device)
+    }
+
+    pub fn unregister_io(&mut self, start: u16) -> Result<(), String> {
+        self.io.unregister(start)
+    }
 }
 
 impl Addressable for Memory {
@@ -78,12 +96,37 @@ impl Addressable for Memory {
             Bank::Basic => self.ram.borrow_mut().write(address, value),
             Bank::Charset => self.ram.borrow_mut().write(address, value),
             Bank::Kernal => self.ram.borrow_mut().write(address, value),
-            Bank::RomL => self.ram.borrow_mut().write(address, value),
-            Bank::RomH => self.ram.borrow_mut().write(address, value),
+            Bank::RomL | Bank::RomH => {
+                // Cartridges with RAM can claim writes to the ROM window
+                self.expansion_port.borrow_mut().write(address, value);
+                self.ram.borrow_mut().write(address, value);
+            }
             Bank::Io => self.io.write(address, value),
             Bank::Disabled => {}
         }
     }
+
+    fn peek(&self, address: u16) -> u8 {
+        let bank = self.mmu.borrow().map(address);
+        match bank {
+            Bank::RomL | Bank::RomH => self
+                .expansion_port
+                .borrow()
+                .peek(address)
+                .unwrap_or_else(|| self.ram.borrow().read(address)),
+            Bank::Io => self.io.peek(address),
+            _ => self.read(address),
+        }
+    }
+
+    fn poke(&mut self, address: u16, value: u8) {
+        let bank = self.mmu.borrow().map(address);
+        match bank {
+            Bank::RomL | Bank::RomH => self.ram.borrow_mut().write(address, value),
+            Bank::Io => self.io.poke(address, value),
+            _ => self.write(address, value),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -2,9 +2,47 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
-use crate::factory::{AddressableFaded, Chip};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::factory::{Addressable, AddressableFaded, Chip};
 use crate::util::{Ram, Shared};
 
+/// Device claiming a range of the I/O area.
+struct IoDevice {
+    start: u16,
+    end: u16,
+    device: Shared<dyn Addressable>,
+}
+
+/// Adapter to register a chip as an I/O device. The chip decodes the address bits
+/// selected by `mask` and is mirrored across the rest of its range.
+pub struct ChipIo {
+    chip: Shared<dyn Chip>,
+    mask: u16,
+}
+
+impl ChipIo {
+    pub fn new(chip: Shared<dyn Chip>, mask: u16) -> Self {
+        Self { chip, mask }
+    }
+}
+
+impl Addressable for ChipIo {
+    fn read(&self, address: u16) -> u8 {
+        self.chip.borrow_mut().read((address & self.mask) as u8)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.chip.borrow_mut().write((address & self.mask) as u8, value);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.chip.borrow().peek((address & self.mask) as u8)
+    }
+}
+
 pub struct Mmio {
     cia_1: Shared<dyn Chip>,
     cia_2: Shared<dyn Chip>,
@@ -12,6 +50,9 @@ pub struct Mmio {
     expansion_port: Shared<dyn AddressableFaded>,
     sid: Shared<dyn Chip>,
     vic: Shared<dyn Chip>,
+    devices: Vec<IoDevice>,
+    // Configuration
+    color_ram_high: u8,
 }
 
 impl Mmio {
@@ -30,14 +71,99 @@ impl Mmio {
             expansion_port,
             sid,
             vic,
+            devices: Vec::new(),
+            color_ram_high: 0xf0,
+        }
+    }
+
+    /// Set the value of the upper nibble of color RAM reads. Color RAM is only 4 bits wide
+    /// and the upper bits float on real hardware, usually reading back the last byte seen
+    /// on the VIC bus.
+    pub fn set_color_ram_high_nibble(&mut self, value: u8) {
+        self.color_ram_high = value << 4;
+    }
+
+    /// Register a device to handle reads and writes to the address range from `start` to
+    /// `end` inclusive. Registered devices take priority over the chips and the expansion
+    /// port normally mapped there and receive the full address.
+    pub fn register(
+        &mut self,
+        start: u16,
+        end: u16,
+        device: Shared<dyn Addressable>,
+    ) -> Result<(), String> {
+        if start < 0xd000 || end > 0xdfff || end < start {
+            return Err(format!("invalid I/O range ${:04x}-${:04x}", start, end));
+        }
+        if let Some(other) = self
+            .devices
+            .iter()
+            .find(|other| start <= other.end && other.start <= end)
+        {
+            return Err(format!(
+                "I/O range ${:04x}-${:04x} overlaps ${:04x}-${:04x}",
+                start, end, other.start, other.end
+            ));
+        }
+        self.devices.push(IoDevice { start, end, device });
+        Ok(())
+    }
+
+    /// Remove the device registered at the specified start address.
+    pub fn unregister(&mut self, start: u16) -> Result<(), String> {
+        match self.devices.iter().position(|device| device.start == start) {
+            Some(index) => {
+                self.devices.remove(index);
+                Ok(())
+            }
+            None => Err(format!("no I/O device registered at ${:04x}", start)),
+        }
+    }
+
+    fn map_device(&self, address: u16) -> Option<&Shared<dyn Addressable>> {
+        self.devices
+            .iter()
+            .find(|device| address >= device.start && address <= device.end)
+            .map(|device| &device.device)
+    }
+
+    /// Read I/O register without side effects.
+    pub fn peek(&self, address: u16) -> u8 {
+        if let Some(device) = self.map_device(address) {
+            return device.borrow().peek(address);
+        }
+        match address {
+            0xd000..=0xd3ff => self.vic.borrow().peek((address & 0x003f) as u8),
+            0xd400..=0xd7ff => self.sid.borrow().peek((address & 0x001f) as u8),
+            0xd800..=0xdbff => {
+                (self.color_ram.borrow().read(address - 0xd800) & 0x0f) | self.color_ram_high
+            }
+            0xdc00..=0xdcff => self.cia_1.borrow().peek((address & 0x000f) as u8),
+            0xdd00..=0xddff => self.cia_2.borrow().peek((address & 0x000f) as u8),
+            0xde00..=0xdfff => self.expansion_port.borrow().peek(address).unwrap_or(0),
+            _ => panic!("invalid address 0x{:x}", address),
+        }
+    }
+
+    /// Write color RAM without side effects, chip and device registers are left untouched.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        if (0xd800..=0xdbff).contains(&address) {
+            self.color_ram
+                .borrow_mut()
+                .write(address - 0xd800, value & 0x0f);
         }
     }
 
     pub fn read(&self, address: u16) -> u8 {
+        if let Some(device) = self.map_device(address) {
+            return device.borrow().read(address);
+        }
         match address {
             0xd000..=0xd3ff => self.vic.borrow_mut().read((address & 0x003f) as u8),
             0xd400..=0xd7ff => self.sid.borrow_mut().read((address & 0x001f) as u8),
-            0xd800..=0xdbff => self.color_ram.borrow().read(address - 0xd800),
+            0xd800..=0xdbff => {
+                (self.color_ram.borrow().read(address - 0xd800) & 0x0f) | self.color_ram_high
+            }
             0xdc00..=0xdcff => self.cia_1.borrow_mut().read((address & 0x000f) as u8),
             0xdd00..=0xddff => self.cia_2.borrow_mut().read((address & 0x000f) as u8),
             0xde00..=0xdfff => self.expansion_port.borrow_mut().read(address).unwrap_or(0),
@@ -46,10 +172,17 @@ impl Mmio {
     }
 
     pub fn write(&mut self, address: u16, value: u8) {
+        if let Some(device) = self.map_device(address) {
+            device.borrow_mut().write(address, value);
+            return;
+        }
         match address {
             0xd000..=0xd3ff => self.vic.borrow_mut().write((address & 0x003f) as u8, value),
             0xd400..=0xd7ff => self.sid.borrow_mut().write((address & 0x001f) as u8, value),
-            0xd800..=0xdbff => self.color_ram.borrow_mut().write(address - 0xd800, value),
+            0xd800..=0xdbff => self
+                .color_ram
+                .borrow_mut()
+                .write(address - 0xd800, value & 0x0f),
             0xdc00..=0xdcff => self
                 .cia_1
                 .borrow_mut()
@@ -9,7 +9,7 @@ mod pla;
 
 pub use self::expansion_port::ExpansionPort;
 pub use self::memory::Memory;
-pub use self::mmio::Mmio;
+pub use self::mmio::{ChipIo, Mmio};
 pub use self::pla::Pla;
 
 #[allow(dead_code)]
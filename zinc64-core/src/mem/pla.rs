@@ -275,3 +275,88 @@ impl MemoryMap {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bit_field::BitField;
+
+    // Bank selection as described by the PLA logic equations.
+    fn expected_bank(mode: u8, address: u16) -> Bank {
+        let loram = mode.get_bit(0);
+        let hiram = mode.get_bit(1);
+        let charen = mode.get_bit(2);
+        let game = mode.get_bit(3);
+        let exrom = mode.get_bit(4);
+        if !game && exrom {
+            return match address {
+                0x0000..=0x0fff => Bank::Ram,
+                0x8000..=0x9fff => Bank::RomL,
+                0xd000..=0xdfff => Bank::Io,
+                0xe000..=0xffff => Bank::RomH,
+                _ => Bank::Disabled,
+            };
+        }
+        match address {
+            0x8000..=0x9fff if loram && hiram && !exrom => Bank::RomL,
+            0xa000..=0xbfff if !game && !exrom => {
+                if hiram {
+                    Bank::RomH
+                } else {
+                    Bank::Ram
+                }
+            }
+            0xa000..=0xbfff if loram && hiram => Bank::Basic,
+            0xd000..=0xdfff if !loram && !hiram => Bank::Ram,
+            // 16K mode without KERNAL leaves no room for the character ROM
+            0xd000..=0xdfff if !game && !exrom && !hiram && !charen => Bank::Ram,
+            0xd000..=0xdfff if charen => Bank::Io,
+            0xd000..=0xdfff => Bank::Charset,
+            0xe000..=0xffff if hiram => Bank::Kernal,
+            _ => Bank::Ram,
+        }
+    }
+
+    #[test]
+    fn truth_table() {
+        let mut pla = Pla::new();
+        for mode in 0..32u8 {
+            pla.switch_banks(mode);
+            for zone in 0..0x10u16 {
+                let address = zone << 12;
+                assert_eq!(
+                    expected_bank(mode, address),
+                    pla.map(address),
+                    "mode {} address {:04x}",
+                    mode,
+                    address
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn standard_modes() {
+        let mut pla = Pla::new();
+        // BASIC, KERNAL and I/O
+        pla.switch_banks(31);
+        assert_eq!(Bank::Basic, pla.map(0xa000));
+        assert_eq!(Bank::Io, pla.map(0xd000));
+        assert_eq!(Bank::Kernal, pla.map(0xe000));
+        // All RAM
+        pla.switch_banks(24);
+        for zone in 0..0x10u16 {
+            assert_eq!(Bank::Ram, pla.map(zone << 12));
+        }
+        // Character ROM
+        pla.switch_banks(27);
+        assert_eq!(Bank::Charset, pla.map(0xd000));
+        // Ultimax
+        pla.switch_banks(16 | 7);
+        assert_eq!(Bank::Disabled, pla.map(0x1000));
+        assert_eq!(Bank::RomL, pla.map(0x8000));
+        assert_eq!(Bank::Disabled, pla.map(0xa000));
+        assert_eq!(Bank::Io, pla.map(0xd000));
+        assert_eq!(Bank::RomH, pla.map(0xe000));
+    }
+}
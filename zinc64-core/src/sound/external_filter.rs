@@ -0,0 +1,80 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Portions (c) 2004 Dag Lem <resid@nimrod.no>
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::string::String;
+
+use crate::util::{Snapshot, SnapshotReader};
+
+// Design:
+//   Port of the resid external filter. It is kept here rather than in resid so its
+//   state can be part of a snapshot, the one inside resid is always disabled.
+
+// Low-pass:  R = 10kOhm, C = 1000pF; w0l = 1/RC = 1/(1e4*1e-9) = 100000
+// High-pass: R =  1kOhm, C =   10uF; w0h = 1/RC = 1/(1e3*1e-5) =    100
+// Multiply with 1.048576 to facilitate division by 1 000 000 by right-shifting 20 times.
+const W0_LP: i32 = 104_858;
+const W0_HP: i32 = 105;
+
+/// The audio output stage of a C64, a 16kHz low-pass filter followed by a 16Hz
+/// high-pass filter.
+#[derive(Clone, Copy, Default)]
+pub struct ExternalFilter {
+    // Runtime State
+    vlp: i32,
+    vhp: i32,
+    vo: i32,
+}
+
+impl ExternalFilter {
+    pub fn clock(&mut self, vi: i32) {
+        // Vo  = Vlp - Vhp;
+        // Vlp = Vlp + w0lp*(Vi - Vlp)*delta_t;
+        // Vhp = Vhp + w0hp*(Vlp - Vhp)*delta_t;
+        let dvlp = ((W0_LP >> 8) * (vi - self.vlp)) >> 12;
+        let dvhp = (W0_HP * (self.vlp - self.vhp)) >> 20;
+        self.vo = self.vlp - self.vhp;
+        self.vlp += dvlp;
+        self.vhp += dvhp;
+    }
+
+    pub fn clock_delta(&mut self, mut delta: u32, vi: i32) {
+        // The filter works satisfactorily up to about 8 cycles at a time
+        let mut delta_flt = 8;
+        while delta != 0 {
+            if delta < delta_flt {
+                delta_flt = delta;
+            }
+            let dvlp = (((W0_LP * delta_flt as i32) >> 8) * (vi - self.vlp)) >> 12;
+            let dvhp = (W0_HP * delta_flt as i32 * (self.vlp - self.vhp)) >> 20;
+            self.vo = self.vlp - self.vhp;
+            self.vlp += dvlp;
+            self.vhp += dvhp;
+            delta -= delta_flt;
+        }
+    }
+
+    pub fn output(&self) -> i32 {
+        self.vo
+    }
+
+    pub fn reset(&mut self) {
+        self.vlp = 0;
+        self.vhp = 0;
+        self.vo = 0;
+    }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u32(self.vlp as u32);
+        snapshot.write_u32(self.vhp as u32);
+        snapshot.write_u32(self.vo as u32);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.vlp = snapshot.read_u32()? as i32;
+        self.vhp = snapshot.read_u32()? as i32;
+        self.vo = snapshot.read_u32()? as i32;
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::cell::RefCell;
+
+use crate::factory::SoundOutput;
+
+/// Map one frame of interleaved samples onto a frame with a different channel count.
+///
+/// Downmixing to mono averages all input channels so hard-panned sources are kept at
+/// half level instead of being summed and clipped. Upmixing repeats input channels.
+pub fn mix_frame(input: &[i16], output: &mut [i16]) {
+    if input.is_empty() {
+        for sample in output.iter_mut() {
+            *sample = 0;
+        }
+    } else if output.len() == 1 && input.len() > 1 {
+        let sum: i32 = input.iter().map(|sample| *sample as i32).sum();
+        output[0] = (sum / input.len() as i32) as i16;
+    } else {
+        for (i, sample) in output.iter_mut().enumerate() {
+            *sample = input[i % input.len()];
+        }
+    }
+}
+
+/// Interleaves the mono output of two sound sources into stereo frames. Each source
+/// writes through its own channel as it is synced, so samples are queued until the
+/// other channel has caught up. Samples written to the mixer itself go to both
+/// channels. Frames are mapped onto the channel count of the output with `mix_frame`.
+pub struct StereoMixer {
+    output: Arc<dyn SoundOutput>,
+    queues: RefCell<[VecDeque<i16>; 2]>,
+}
+
+impl StereoMixer {
+    pub fn new(output: Arc<dyn SoundOutput>) -> Self {
+        StereoMixer {
+            output,
+            queues: RefCell::new([VecDeque::new(), VecDeque::new()]),
+        }
+    }
+
+    /// Sound output feeding the left (0) or right (1) channel.
+    pub fn channel(mixer: &Arc<StereoMixer>, index: usize) -> Arc<dyn SoundOutput> {
+        assert!(index < 2);
+        Arc::new(MixerChannel {
+            mixer: mixer.clone(),
+            index,
+        })
+    }
+
+    fn push(&self, index: usize, samples: &[i16]) {
+        self.queues.borrow_mut()[index].extend(samples.iter());
+        self.flush();
+    }
+
+    fn flush(&self) {
+        let channels = self.output.get_channels();
+        let mut queues = self.queues.borrow_mut();
+        let mut buffer = [0i16; 512];
+        let mut frames = queues[0].len().min(queues[1].len());
+        while frames > 0 {
+            let count = frames.min(buffer.len() / channels);
+            for frame in buffer[0..count * channels].chunks_mut(channels) {
+                let input = [
+                    queues[0].pop_front().unwrap(),
+                    queues[1].pop_front().unwrap(),
+                ];
+                mix_frame(&input, frame);
+            }
+            self.output.write(&buffer[0..count * channels]);
+            frames -= count;
+        }
+    }
+}
+
+impl SoundOutput for StereoMixer {
+    fn reset(&self) {
+        let mut queues = self.queues.borrow_mut();
+        queues[0].clear();
+        queues[1].clear();
+        self.output.reset();
+    }
+
+    fn write(&self, samples: &[i16]) {
+        {
+            let mut queues = self.queues.borrow_mut();
+            queues[0].extend(samples.iter());
+            queues[1].extend(samples.iter());
+        }
+        self.flush();
+    }
+
+    fn is_muted(&self) -> bool {
+        self.output.is_muted()
+    }
+}
+
+struct MixerChannel {
+    mixer: Arc<StereoMixer>,
+    index: usize,
+}
+
+impl SoundOutput for MixerChannel {
+    fn reset(&self) {
+        self.mixer.queues.borrow_mut()[self.index].clear();
+    }
+
+    fn write(&self, samples: &[i16]) {
+        self.mixer.push(self.index, samples);
+    }
+
+    fn is_muted(&self) -> bool {
+        self.mixer.is_muted()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn downmix_hard_panned_stereo() {
+        let mut output = [0i16; 1];
+        mix_frame(&[1000, 0], &mut output);
+        assert_eq!(500, output[0]);
+        mix_frame(&[0, -1000], &mut output);
+        assert_eq!(-500, output[0]);
+        mix_frame(&[1000, 3000], &mut output);
+        assert_eq!(2000, output[0]);
+    }
+
+    #[test]
+    fn downmix_does_not_clip() {
+        let mut output = [0i16; 1];
+        mix_frame(&[i16::max_value(), i16::max_value()], &mut output);
+        assert_eq!(i16::max_value(), output[0]);
+    }
+
+    #[test]
+    fn upmix_mono() {
+        let mut output = [0i16; 2];
+        mix_frame(&[1234], &mut output);
+        assert_eq!([1234, 1234], output);
+    }
+
+    #[test]
+    fn stereo_passthrough() {
+        let mut output = [0i16; 2];
+        mix_frame(&[100, -200], &mut output);
+        assert_eq!([100, -200], output);
+    }
+
+    struct VecOutput {
+        buffer: RefCell<Vec<i16>>,
+        channels: usize,
+    }
+
+    impl VecOutput {
+        fn new(channels: usize) -> Self {
+            VecOutput {
+                buffer: RefCell::new(Vec::new()),
+                channels,
+            }
+        }
+    }
+
+    impl SoundOutput for VecOutput {
+        fn reset(&self) {
+            self.buffer.borrow_mut().clear();
+        }
+
+        fn write(&self, samples: &[i16]) {
+            self.buffer.borrow_mut().extend_from_slice(samples);
+        }
+
+        fn get_channels(&self) -> usize {
+            self.channels
+        }
+    }
+
+    #[test]
+    fn stereo_mixer_interleaves_channels() {
+        let output = Arc::new(VecOutput::new(2));
+        let mixer = Arc::new(StereoMixer::new(output.clone()));
+        let left = StereoMixer::channel(&mixer, 0);
+        let right = StereoMixer::channel(&mixer, 1);
+        left.write(&[1, 2, 3]);
+        assert!(output.buffer.borrow().is_empty());
+        right.write(&[-1, -2]);
+        assert_eq!(&[1, -1, 2, -2], &output.buffer.borrow()[..]);
+        right.write(&[-3, -4]);
+        left.write(&[4]);
+        assert_eq!(&[1, -1, 2, -2, 3, -3, 4, -4], &output.buffer.borrow()[..]);
+    }
+
+    #[test]
+    fn stereo_mixer_downmixes_to_mono_output() {
+        let output = Arc::new(VecOutput::new(1));
+        let mixer = Arc::new(StereoMixer::new(output.clone()));
+        let left = StereoMixer::channel(&mixer, 0);
+        let right = StereoMixer::channel(&mixer, 1);
+        left.write(&[1000, 2000]);
+        right.write(&[0, 1000]);
+        assert_eq!(&[500, 1500], &output.buffer.borrow()[..]);
+    }
+}
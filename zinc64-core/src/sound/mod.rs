@@ -2,6 +2,11 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+mod external_filter;
+pub mod mixer;
+mod sample_queue;
+mod sampler;
 pub mod sid;
 
+pub use self::sample_queue::SampleQueue;
 pub use self::sid::Sid;
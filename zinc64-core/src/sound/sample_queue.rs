@@ -0,0 +1,152 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+// Design:
+//   Single producer, single consumer ring buffer so the emulation thread never blocks on
+//   the audio callback. Head and tail count positions modulo twice the capacity so a
+//   full queue can be told apart from an empty one. The producer only advances tail
+//   and the consumer only advances head. When the queue is full new
+//   samples are dropped rather than overwriting ones the consumer may be reading.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicI16, AtomicUsize, Ordering};
+
+use crate::factory::SoundOutput;
+
+/// Thread-safe sample queue between the emulation thread writing samples and the
+/// audio thread playing them. Samples dropped because the queue was full are counted
+/// as overruns, silence substituted because the queue ran dry is counted as underruns.
+pub struct SampleQueue {
+    buffer: Vec<AtomicI16>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    flush: AtomicBool,
+    flush_pos: AtomicUsize,
+    overruns: AtomicUsize,
+    underruns: AtomicUsize,
+}
+
+impl SampleQueue {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        let mut buffer = Vec::with_capacity(capacity);
+        buffer.resize_with(capacity, || AtomicI16::new(0));
+        SampleQueue {
+            buffer,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            flush: AtomicBool::new(false),
+            flush_pos: AtomicUsize::new(0),
+            overruns: AtomicUsize::new(0),
+            underruns: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Number of samples queued and not yet consumed.
+    pub fn samples_available(&self) -> usize {
+        let head = if self.flush.load(Ordering::Acquire) {
+            self.flush_pos.load(Ordering::Relaxed)
+        } else {
+            self.head.load(Ordering::Acquire)
+        };
+        self.distance(head, self.tail.load(Ordering::Acquire))
+    }
+
+    /// Number of samples dropped because the queue was full.
+    pub fn get_overruns(&self) -> usize {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Number of samples replaced with silence because the queue was empty.
+    pub fn get_underruns(&self) -> usize {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_counters(&self) {
+        self.overruns.store(0, Ordering::Relaxed);
+        self.underruns.store(0, Ordering::Relaxed);
+    }
+
+    /// Fill output with queued samples. Only to be called from the consumer thread.
+    /// Returns the number of queued samples copied, the remainder is filled with silence.
+    pub fn pop(&self, output: &mut [i16]) -> usize {
+        let mut head = self.head.load(Ordering::Relaxed);
+        let flush = self.flush.swap(false, Ordering::AcqRel);
+        let tail = self.tail.load(Ordering::Acquire);
+        if flush {
+            // Never move head back over samples already consumed
+            let flush_pos = self.flush_pos.load(Ordering::Relaxed);
+            if self.distance(head, flush_pos) <= self.distance(head, tail) {
+                head = flush_pos;
+            }
+        }
+        let count = output.len().min(self.distance(head, tail));
+        for sample in output[0..count].iter_mut() {
+            *sample = self.buffer[head % self.buffer.len()].load(Ordering::Relaxed);
+            head = self.advance(head);
+        }
+        for sample in output[count..].iter_mut() {
+            *sample = 0;
+        }
+        self.head.store(head, Ordering::Release);
+        if count < output.len() {
+            self.underruns
+                .fetch_add(output.len() - count, Ordering::Relaxed);
+        }
+        count
+    }
+
+    /// Queue samples. Only to be called from the producer thread. Returns the number
+    /// of samples queued, samples that do not fit are dropped.
+    pub fn push(&self, samples: &[i16]) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        let free = self.buffer.len() - self.distance(head, tail);
+        let count = samples.len().min(free);
+        for sample in samples[0..count].iter() {
+            self.buffer[tail % self.buffer.len()].store(*sample, Ordering::Relaxed);
+            tail = self.advance(tail);
+        }
+        self.tail.store(tail, Ordering::Release);
+        if count < samples.len() {
+            self.overruns
+                .fetch_add(samples.len() - count, Ordering::Relaxed);
+        }
+        count
+    }
+
+    fn advance(&self, pos: usize) -> usize {
+        if pos + 1 == self.buffer.len() * 2 {
+            0
+        } else {
+            pos + 1
+        }
+    }
+
+    fn distance(&self, head: usize, tail: usize) -> usize {
+        if tail >= head {
+            tail - head
+        } else {
+            self.buffer.len() * 2 - head + tail
+        }
+    }
+}
+
+impl SoundOutput for SampleQueue {
+    /// Discard queued samples. The consumer drops them on its next pop since only it
+    /// may advance the head.
+    fn reset(&self) {
+        self.flush_pos
+            .store(self.tail.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.flush.store(true, Ordering::Release);
+    }
+
+    fn write(&self, samples: &[i16]) {
+        self.push(samples);
+    }
+}
@@ -0,0 +1,441 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Portions (c) 2004 Dag Lem <resid@nimrod.no>
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use resid::synth::Synth;
+
+use super::external_filter::ExternalFilter;
+use super::sid::SamplingMethod;
+use crate::util::{Snapshot, SnapshotReader};
+
+// Design:
+//   Port of the resid sampler that also drives the external filter. Both are kept
+//   here rather than in resid so their state, including the samples the resampling
+//   filter is still working on, can be part of a snapshot.
+
+// Resampling constants.
+// The error in interpolated lookup is bounded by 1.234/L^2, for a resolution of
+// 16 bits this yields L >= 285.
+const FIR_RES: i32 = 285;
+const FIR_SHIFT: i32 = 15;
+const RING_SIZE: usize = 16384;
+
+const FIXP_SHIFT: i32 = 16;
+const FIXP_MASK: i32 = 0xffff;
+
+const OUTPUT_RANGE: u32 = 1 << 16;
+const OUTPUT_HALF: i32 = (OUTPUT_RANGE >> 1) as i32;
+const SAMPLES_PER_OUTPUT: u32 = ((4095 * 255) >> 7) * 3 * 15 * 2 / OUTPUT_RANGE;
+
+struct Fir {
+    data: Vec<i16>,
+    n: i32,
+    res: i32,
+}
+
+pub struct Sampler {
+    // Functional Units
+    pub synth: Synth,
+    ext_filter: ExternalFilter,
+    // Configuration
+    cycles_per_sample: u32,
+    fir: Fir,
+    sampling_method: SamplingMethod,
+    // Runtime State
+    buffer: Vec<i16>,
+    index: usize,
+    offset: i32,
+    prev_sample: i16,
+}
+
+impl Sampler {
+    pub fn new(mut synth: Synth) -> Self {
+        synth.ext_filter.set_enabled(false);
+        Sampler {
+            synth,
+            ext_filter: ExternalFilter::default(),
+            cycles_per_sample: 0,
+            fir: Fir {
+                data: Vec::new(),
+                n: 0,
+                res: 0,
+            },
+            sampling_method: SamplingMethod::Fast,
+            buffer: vec![0; RING_SIZE * 2],
+            index: 0,
+            offset: 0,
+            prev_sample: 0,
+        }
+    }
+
+    pub fn set_parameters(&mut self, method: SamplingMethod, clock_freq: u32, sample_freq: u32) {
+        self.cycles_per_sample =
+            (clock_freq as f64 / sample_freq as f64 * (1 << FIXP_SHIFT) as f64 + 0.5) as u32;
+        self.sampling_method = method;
+        if self.sampling_method == SamplingMethod::Resample {
+            self.init_fir(clock_freq as f64, sample_freq as f64, -1.0, 0.97);
+        } else {
+            self.fir.data.clear();
+            self.fir.n = 0;
+            self.fir.res = 0;
+        }
+        for sample in self.buffer.iter_mut() {
+            *sample = 0;
+        }
+        self.index = 0;
+        self.offset = 0;
+        self.prev_sample = 0;
+    }
+
+    pub fn clock(&mut self) {
+        self.synth.clock();
+        self.ext_filter.clock(self.synth.filter.output());
+    }
+
+    pub fn clock_delta(&mut self, delta: u32) {
+        self.synth.clock_delta(delta);
+        self.ext_filter
+            .clock_delta(delta, self.synth.filter.output());
+    }
+
+    pub fn output(&self) -> i16 {
+        let sample = self.ext_filter.output() / SAMPLES_PER_OUTPUT as i32;
+        if sample >= OUTPUT_HALF {
+            (OUTPUT_HALF - 1) as i16
+        } else if sample < -OUTPUT_HALF {
+            (-OUTPUT_HALF) as i16
+        } else {
+            sample as i16
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.synth.reset();
+        self.ext_filter.reset();
+        self.index = 0;
+        self.offset = 0;
+        self.prev_sample = 0;
+    }
+
+    /// Clock the SID `delta` cycles and write the samples produced every `interleave`
+    /// slots of `buffer`. Returns the number of samples written and the cycles left
+    /// when the buffer filled up.
+    pub fn sample(&mut self, delta: u32, buffer: &mut [i16], interleave: usize) -> (usize, u32) {
+        match self.sampling_method {
+            SamplingMethod::Fast => self.sample_fast(delta, buffer, interleave),
+            SamplingMethod::Interpolate => self.sample_interpolate(delta, buffer, interleave),
+            SamplingMethod::Resample => self.sample_resample(delta, buffer, interleave),
+        }
+    }
+
+    /// Picks the nearest cycle for each sample.
+    fn sample_fast(
+        &mut self,
+        mut delta: u32,
+        buffer: &mut [i16],
+        interleave: usize,
+    ) -> (usize, u32) {
+        let mut index = 0;
+        loop {
+            let next_sample_offset = self.get_next_sample_offset();
+            let delta_sample = (next_sample_offset >> FIXP_SHIFT) as u32;
+            if delta_sample > delta || index >= buffer.len() {
+                break;
+            }
+            self.clock_delta(delta_sample);
+            delta -= delta_sample;
+            buffer[index * interleave] = self.output();
+            index += 1;
+            self.update_sample_offset(next_sample_offset);
+        }
+        if delta > 0 && index < buffer.len() {
+            self.clock_delta(delta);
+            self.offset -= (delta as i32) << FIXP_SHIFT;
+            (index, 0)
+        } else {
+            (index, delta)
+        }
+    }
+
+    /// Interpolates linearly between the two cycles closest to each sample.
+    fn sample_interpolate(
+        &mut self,
+        mut delta: u32,
+        buffer: &mut [i16],
+        interleave: usize,
+    ) -> (usize, u32) {
+        let mut index = 0;
+        loop {
+            let next_sample_offset = self.get_next_sample_offset();
+            let delta_sample = (next_sample_offset >> FIXP_SHIFT) as u32;
+            if delta_sample > delta || index >= buffer.len() {
+                break;
+            }
+            for _i in 0..(delta_sample - 1) {
+                self.prev_sample = self.output();
+                self.clock();
+            }
+            delta -= delta_sample;
+            let sample_now = self.output();
+            buffer[index * interleave] = self.prev_sample
+                + ((self.offset * (sample_now - self.prev_sample) as i32) >> FIXP_SHIFT) as i16;
+            index += 1;
+            self.prev_sample = sample_now;
+            self.update_sample_offset(next_sample_offset);
+        }
+        if delta > 0 && index < buffer.len() {
+            for _i in 0..(delta - 1) {
+                self.clock();
+            }
+            self.offset -= (delta as i32) << FIXP_SHIFT;
+            (index, 0)
+        } else {
+            (index, delta)
+        }
+    }
+
+    /// Runs every cycle through a windowed sinc low-pass filter, as described in
+    /// "A Flexible Sampling-Rate Conversion Method" by J. O. Smith and P. Gosset. The
+    /// filter is tabulated at `fir.res` offsets between cycles and interpolated
+    /// linearly between the two nearest tables.
+    fn sample_resample(
+        &mut self,
+        mut delta: u32,
+        buffer: &mut [i16],
+        interleave: usize,
+    ) -> (usize, u32) {
+        let mut index = 0;
+        let half = 1i32 << 15;
+        loop {
+            let next_sample_offset = self.offset + self.cycles_per_sample as i32;
+            let delta_sample = (next_sample_offset >> FIXP_SHIFT) as u32;
+            if delta_sample > delta || index >= buffer.len() {
+                break;
+            }
+            for _i in 0..delta_sample {
+                self.clock_ring();
+            }
+            delta -= delta_sample;
+            self.offset = next_sample_offset & FIXP_MASK;
+
+            let fir_offset_1 = (self.offset * self.fir.res) >> FIXP_SHIFT;
+            let fir_offset_rmd = (self.offset * self.fir.res) & FIXP_MASK;
+            let sample_start_1 = (self.index as i32 - self.fir.n + RING_SIZE as i32) as usize;
+            let v1 = self.convolve(sample_start_1, fir_offset_1);
+
+            // Use next FIR table, wrap around to first FIR table using previous sample
+            let mut fir_offset_2 = fir_offset_1 + 1;
+            let mut sample_start_2 = sample_start_1;
+            if fir_offset_2 == self.fir.res {
+                fir_offset_2 = 0;
+                sample_start_2 -= 1;
+            }
+            let v2 = self.convolve(sample_start_2, fir_offset_2);
+
+            // Linear interpolation, the remainder is the same for all samples so it is
+            // factored out of the sums
+            let mut v = v1 + ((fir_offset_rmd as i64 * (v2 - v1) as i64) >> FIXP_SHIFT) as i32;
+            v >>= FIR_SHIFT;
+
+            // Saturated arithmetics to guard against 16 bit sample overflow
+            if v >= half {
+                v = half - 1;
+            } else if v < -half {
+                v = -half;
+            }
+
+            buffer[index * interleave] = v as i16;
+            index += 1;
+        }
+        if delta > 0 && index < buffer.len() {
+            for _i in 0..delta {
+                self.clock_ring();
+            }
+            self.offset -= (delta as i32) << FIXP_SHIFT;
+            (index, 0)
+        } else {
+            (index, delta)
+        }
+    }
+
+    // The ring buffer is stored twice in a row so a filter window never wraps.
+    fn clock_ring(&mut self) {
+        self.clock();
+        let output = self.output();
+        self.buffer[self.index] = output;
+        self.buffer[self.index + RING_SIZE] = output;
+        self.index = (self.index + 1) & (RING_SIZE - 1);
+    }
+
+    fn convolve(&self, sample_start: usize, fir_offset: i32) -> i32 {
+        let n = self.fir.n as usize;
+        let fir_start = fir_offset as usize * n;
+        self.buffer[sample_start..sample_start + n]
+            .iter()
+            .zip(self.fir.data[fir_start..fir_start + n].iter())
+            .fold(0, |sum, (&sample, &fir)| sum + sample as i32 * fir as i32)
+    }
+
+    fn get_next_sample_offset(&self) -> i32 {
+        self.offset + self.cycles_per_sample as i32 + (1 << (FIXP_SHIFT - 1))
+    }
+
+    fn update_sample_offset(&mut self, next_sample_offset: i32) {
+        self.offset = (next_sample_offset & FIXP_MASK) - (1 << (FIXP_SHIFT - 1));
+    }
+
+    fn init_fir(
+        &mut self,
+        clock_freq: f64,
+        sample_freq: f64,
+        mut pass_freq: f64,
+        filter_scale: f64,
+    ) {
+        let pi = core::f64::consts::PI;
+        let samples_per_cycle = sample_freq / clock_freq;
+        let cycles_per_sample = clock_freq / sample_freq;
+
+        // The default passband limit is 0.9*sample_freq/2 for sample frequencies
+        // below ~ 44.1kHz, and 20kHz for higher sample frequencies.
+        if pass_freq < 0.0 {
+            pass_freq = 20000.0;
+            if 2.0 * pass_freq / sample_freq >= 0.9 {
+                pass_freq = 0.9 * sample_freq / 2.0;
+            }
+        }
+
+        // 16 bits -> -96dB stopband attenuation.
+        let atten = -20.0f64 * libm::log10(1.0 / (1i32 << 16) as f64);
+        // A fraction of the bandwidth is allocated to the transition band.
+        let dw = (1.0f64 - 2.0 * pass_freq / sample_freq) * pi;
+        // The cutoff frequency is midway through the transition band.
+        let wc = (2.0f64 * pass_freq / sample_freq + 1.0) * pi / 2.0;
+
+        // For calculation of beta and N see the reference for the kaiserord function
+        // in the MATLAB Signal Processing Toolbox.
+        let beta = 0.1102f64 * (atten - 8.7);
+        let io_beta = i0(beta);
+
+        // The filter order is equal to the number of zero crossings, i.e. it should
+        // be an even number (sinc is symmetric about x = 0).
+        let mut n_cap = ((atten - 7.95) / (2.285 * dw) + 0.5) as i32;
+        n_cap += n_cap & 1;
+
+        // The filter length is equal to the filter order + 1 and must be an odd
+        // number (sinc is symmetric about x = 0).
+        self.fir.n = (n_cap as f64 * cycles_per_sample) as i32 + 1;
+        self.fir.n |= 1;
+
+        // The filter table resolution is clamped to 2^n, making the fixpoint
+        // sample offset a whole multiple of the filter table resolution.
+        let n = libm::ceil(libm::log(FIR_RES as f64 / cycles_per_sample) / libm::log(2.0)) as i32;
+        self.fir.res = 1 << n;
+
+        self.fir.data.clear();
+        self.fir
+            .data
+            .resize((self.fir.n * self.fir.res) as usize, 0);
+
+        // Calculate fir.res FIR tables for linear interpolation.
+        for i in 0..self.fir.res {
+            let fir_offset = i * self.fir.n + self.fir.n / 2;
+            let j_offset = i as f64 / self.fir.res as f64;
+            // Calculate FIR table. This is the sinc function, weighted by the Kaiser
+            // window.
+            let fir_n_div2 = self.fir.n / 2;
+            for j in -fir_n_div2..=fir_n_div2 {
+                let jx = j as f64 - j_offset;
+                let wt = wc * jx / cycles_per_sample;
+                let temp = jx / fir_n_div2 as f64;
+                let kaiser = if libm::fabs(temp) <= 1.0 {
+                    i0(beta * libm::sqrt(1.0 - temp * temp)) / io_beta
+                } else {
+                    0f64
+                };
+                let sincwt = if libm::fabs(wt) >= 1e-6 {
+                    libm::sin(wt) / wt
+                } else {
+                    1.0
+                };
+                let val = (1i32 << FIR_SHIFT) as f64 * filter_scale * samples_per_cycle * wc / pi
+                    * sincwt
+                    * kaiser;
+                self.fir.data[(fir_offset + j) as usize] = (val + 0.5) as i16;
+            }
+        }
+    }
+
+    /// The samples in the resampling filter window are part of the state, the sampling
+    /// parameters are not and must match when loading.
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        self.ext_filter.save_state(snapshot);
+        let window = self.get_window_size();
+        snapshot.write_u32(window as u32);
+        let start = self.index + RING_SIZE - window;
+        for sample in self.buffer[start..start + window].iter() {
+            snapshot.write_u16(*sample as u16);
+        }
+        snapshot.write_u32(self.offset as u32);
+        snapshot.write_u16(self.prev_sample as u16);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.ext_filter.load_state(snapshot)?;
+        let window = snapshot.read_u32()? as usize;
+        if window != self.get_window_size() {
+            return Err(format!(
+                "snapshot resampling window {} does not match {}",
+                window,
+                self.get_window_size()
+            ));
+        }
+        for sample in self.buffer.iter_mut() {
+            *sample = 0;
+        }
+        for i in 0..window {
+            let sample = snapshot.read_u16()? as i16;
+            self.buffer[i] = sample;
+            self.buffer[i + RING_SIZE] = sample;
+        }
+        self.index = window;
+        self.offset = snapshot.read_u32()? as i32;
+        self.prev_sample = snapshot.read_u16()? as i16;
+        Ok(())
+    }
+
+    // Number of past samples the resampling filter reads, wrapping around to the
+    // first table takes one more.
+    fn get_window_size(&self) -> usize {
+        match self.fir.n {
+            0 => 0,
+            n => n as usize + 1,
+        }
+    }
+}
+
+fn i0(x: f64) -> f64 {
+    // Max error acceptable in I0.
+    let i0e = 1e-6;
+    let halfx = x / 2.0;
+    let mut sum = 1.0;
+    let mut u = 1.0;
+    let mut n = 1;
+    loop {
+        let temp = halfx / n as f64;
+        n += 1;
+        u *= temp * temp;
+        sum += u;
+        if u < i0e * sum {
+            break;
+        }
+    }
+    sum
+}
@@ -7,34 +7,62 @@
 use log::{log_enabled, log, info, trace, debug};
 
 
+use alloc::format;
 use alloc::rc::Rc;
+use alloc::string::String;
 use alloc::sync::Arc;
 
 use crate::factory::{Chip, SidModel, SoundOutput};
-use crate::util::Clock;
+use crate::util::{Clock, Rng, SharedCell, Snapshot, SnapshotReader};
 use log::LogLevel;
 
-use resid;
+use resid::envelope::State as EnvelopeState;
+use resid::synth::Synth;
+
+use super::sampler::Sampler;
 
 // TODO sound: add sid output sample rate test cases
 
-#[derive(Clone, Copy, PartialEq)]
+/// Conversion from the SID clock to the output sample rate. `Fast` picks the nearest
+/// cycle and `Interpolate` blends the two closest cycles, both of which alias any
+/// content above half the sample rate. `Resample` runs a windowed-sinc low pass
+/// filter across every cycle, which is slower but suppresses aliasing.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SamplingMethod {
     Fast,
     Interpolate,
     Resample,
-    ResampleFast,
 }
 
+// The POT inputs are measured once every 512 cycles. The capacitor is discharged for the
+// first 256 cycles, then the SID counts the cycles it takes to charge past the threshold.
+const POT_PERIOD: u32 = 512;
+
+// The noise waveform is generated by a 23-bit LFSR.
+const NOISE_LFSR_MASK: u32 = 0x7f_ffff;
+
+// Cycles a value written to the SID stays on its data bus and reads back from the
+// write only registers.
+const BUS_VALUE_TTL: u32 = 0x2000;
+
 pub struct Sid {
     // Dependencies
     system_clock: Rc<Clock>,
     sound_buffer: Arc<dyn SoundOutput>,
+    // Configuration
+    noise_seed: Option<u64>,
+    pot_x: SharedCell<u8>,
+    pot_y: SharedCell<u8>,
     // Functional Units
-    resid: resid::Sid,
+    sampler: Sampler,
     // Runtime State
     buffer: [i16; 8192],
+    bus_value: u8,
+    bus_value_ttl: u32,
     cycles: u64,
+    pot_cycles: u32,
+    pot_x_value: u8,
+    pot_y_value: u8,
 }
 
 impl Sid {
@@ -42,24 +70,42 @@ impl Sid {
         chip_model: SidModel,
         system_clock: Rc<Clock>,
         sound_buffer: Arc<dyn SoundOutput>,
+        pot_x: SharedCell<u8>,
+        pot_y: SharedCell<u8>,
     ) -> Self {
         info!(target: "sound", "Initializing SID");
         let resid_model = match chip_model {
             SidModel::Mos6581 => resid::ChipModel::Mos6581,
             SidModel::Mos8580 => resid::ChipModel::Mos8580,
         };
-        let resid = resid::Sid::new(resid_model);
+        let mut sampler = Sampler::new(Synth::new(resid_model));
+        sampler.set_parameters(SamplingMethod::Fast, 985_248, 44100);
         Sid {
             system_clock,
             sound_buffer,
-            resid,
+            noise_seed: None,
+            pot_x,
+            pot_y,
+            sampler,
             buffer: [0i16; 8192],
+            bus_value: 0,
+            bus_value_ttl: 0,
             cycles: 0,
+            pot_cycles: 0,
+            pot_x_value: 0xff,
+            pot_y_value: 0xff,
         }
     }
 
+    /// Start the noise generators of all voices from a state derived from `seed`
+    /// on every reset instead of the fixed reset value.
+    pub fn set_noise_seed(&mut self, seed: Option<u64>) {
+        self.noise_seed = seed;
+        self.seed_noise();
+    }
+
     pub fn enable_filter(&mut self, enabled: bool) {
-        self.resid.enable_filter(enabled);
+        self.sampler.synth.filter.set_enabled(enabled);
     }
 
     pub fn set_sampling_parameters(
@@ -68,14 +114,37 @@ impl Sid {
         clock_freq: u32,
         sample_freq: u32,
     ) {
-        let resid_sampling_method = match sampling_method {
-            SamplingMethod::Fast => resid::SamplingMethod::Fast,
-            SamplingMethod::Interpolate => resid::SamplingMethod::Interpolate,
-            SamplingMethod::Resample => resid::SamplingMethod::Resample,
-            SamplingMethod::ResampleFast => resid::SamplingMethod::ResampleFast,
-        };
-        self.resid
-            .set_sampling_parameters(resid_sampling_method, clock_freq, sample_freq);
+        self.sampler
+            .set_parameters(sampling_method, clock_freq, sample_freq);
+    }
+
+    fn seed_noise(&mut self) {
+        if let Some(seed) = self.noise_seed {
+            let mut rng = Rng::new(seed);
+            for voice in self.sampler.synth.voices.iter_mut() {
+                // An all-zero register never leaves that state
+                voice.wave.shift = (rng.next_u32() & NOISE_LFSR_MASK).max(1);
+            }
+        }
+    }
+
+    // The value last written fades from the data bus after a while.
+    fn clock_bus(&mut self, delta: u32) {
+        if self.bus_value_ttl > delta {
+            self.bus_value_ttl -= delta;
+        } else {
+            self.bus_value_ttl = 0;
+            self.bus_value = 0;
+        }
+    }
+
+    fn clock_pots(&mut self, delta: u32) {
+        self.pot_cycles += delta;
+        if self.pot_cycles >= POT_PERIOD {
+            self.pot_cycles %= POT_PERIOD;
+            self.pot_x_value = self.pot_x.get();
+            self.pot_y_value = self.pot_y.get();
+        }
     }
 
     fn sync(&mut self) {
@@ -88,16 +157,35 @@ impl Sid {
 
 impl Chip for Sid {
     fn clock(&mut self) {
-        self.resid.clock();
+        self.clock_bus(1);
+        self.sampler.clock();
+        self.clock_pots(1);
         self.cycles = self.cycles.wrapping_add(1);
     }
 
     fn clock_delta(&mut self, delta: u32) {
-        if delta > 0 {
+        self.clock_bus(delta);
+        self.clock_pots(delta);
+        if self.sound_buffer.is_muted() {
+            // Leave the resampler as is so output resumes where it left off
+            self.sampler.clock_delta(delta);
+        } else if delta > 0 {
+            let channels = self.sound_buffer.get_channels();
+            let frames = self.buffer.len() / channels;
             let mut delta = delta;
             while delta > 0 {
-                let (samples, next_delta) = self.resid.sample(delta, &mut self.buffer[..], 1);
-                self.sound_buffer.write(&self.buffer[0..samples]);
+                let (samples, next_delta) =
+                    self.sampler.sample(delta, &mut self.buffer[..frames], 1);
+                // Expand in place from the back so no sample is overwritten before it is read
+                if channels > 1 {
+                    for i in (0..samples).rev() {
+                        let sample = self.buffer[i];
+                        for slot in self.buffer[i * channels..(i + 1) * channels].iter_mut() {
+                            *slot = sample;
+                        }
+                    }
+                }
+                self.sound_buffer.write(&self.buffer[0..samples * channels]);
                 delta = next_delta;
             }
         }
@@ -109,15 +197,131 @@ impl Chip for Sid {
     }
 
     fn reset(&mut self) {
-        self.resid.reset();
+        self.sampler.reset();
+        self.bus_value = 0;
+        self.bus_value_ttl = 0;
+        self.seed_noise();
         self.cycles = self.system_clock.get();
+        self.pot_cycles = 0;
+        self.pot_x_value = self.pot_x.get();
+        self.pot_y_value = self.pot_y.get();
+    }
+
+    fn save_state(&self, snapshot: &mut Snapshot) {
+        let synth = &self.sampler.synth;
+        let mut registers = [0u8; 0x19];
+        for (i, voice) in synth.voices.iter().enumerate() {
+            let wave = &voice.wave;
+            let envelope = &voice.envelope;
+            registers[i * 7..i * 7 + 7].copy_from_slice(&[
+                wave.get_frequency_lo(),
+                wave.get_frequency_hi(),
+                wave.get_pulse_width_lo(),
+                wave.get_pulse_width_hi(),
+                wave.get_control() | envelope.get_control(),
+                envelope.get_attack_decay(),
+                envelope.get_sustain_release(),
+            ]);
+        }
+        registers[0x15] = synth.filter.get_fc_lo();
+        registers[0x16] = synth.filter.get_fc_hi();
+        registers[0x17] = synth.filter.get_res_filt();
+        registers[0x18] = synth.filter.get_mode_vol();
+        snapshot.write_bytes(&registers);
+        snapshot.write_u8(self.bus_value);
+        snapshot.write_u32(self.bus_value_ttl);
+        snapshot.write_u32(synth.ext_in as u32);
+        for voice in synth.voices.iter() {
+            let envelope = &voice.envelope;
+            snapshot.write_u32(voice.wave.acc);
+            snapshot.write_u32(voice.wave.shift);
+            snapshot.write_u16(envelope.rate_counter);
+            snapshot.write_u16(envelope.rate_counter_period);
+            snapshot.write_u8(envelope.exponential_counter);
+            snapshot.write_u8(envelope.exponential_counter_period);
+            snapshot.write_u8(envelope.envelope_counter);
+            snapshot.write_u8(envelope.state as u8);
+            snapshot.write_bool(envelope.hold_zero);
+        }
+        snapshot.write_u32(synth.filter.vhp as u32);
+        snapshot.write_u32(synth.filter.vbp as u32);
+        snapshot.write_u32(synth.filter.vlp as u32);
+        snapshot.write_u32(synth.filter.vnf as u32);
+        self.sampler.save_state(snapshot);
+        snapshot.write_u64(self.cycles);
+        snapshot.write_u32(self.pot_cycles);
+        snapshot.write_u8(self.pot_x_value);
+        snapshot.write_u8(self.pot_y_value);
+    }
+
+    fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        let mut registers = [0u8; 0x19];
+        snapshot.read_into(&mut registers)?;
+        let synth = &mut self.sampler.synth;
+        for (reg, value) in registers.iter().enumerate() {
+            synth.write(reg as u8, *value);
+        }
+        self.bus_value = snapshot.read_u8()?;
+        self.bus_value_ttl = snapshot.read_u32()?;
+        synth.ext_in = snapshot.read_u32()? as i32;
+        for voice in synth.voices.iter_mut() {
+            let envelope = &mut voice.envelope;
+            voice.wave.acc = snapshot.read_u32()?;
+            voice.wave.shift = snapshot.read_u32()?;
+            envelope.rate_counter = snapshot.read_u16()?;
+            envelope.rate_counter_period = snapshot.read_u16()?;
+            envelope.exponential_counter = snapshot.read_u8()?;
+            envelope.exponential_counter_period = snapshot.read_u8()?;
+            envelope.envelope_counter = snapshot.read_u8()?;
+            envelope.state = match snapshot.read_u8()? {
+                0 => EnvelopeState::Attack,
+                1 => EnvelopeState::DecaySustain,
+                2 => EnvelopeState::Release,
+                state => return Err(format!("invalid envelope state {}", state)),
+            };
+            envelope.hold_zero = snapshot.read_bool()?;
+        }
+        synth.filter.vhp = snapshot.read_u32()? as i32;
+        synth.filter.vbp = snapshot.read_u32()? as i32;
+        synth.filter.vlp = snapshot.read_u32()? as i32;
+        synth.filter.vnf = snapshot.read_u32()? as i32;
+        self.sampler.load_state(snapshot)?;
+        self.cycles = snapshot.read_u64()?;
+        self.pot_cycles = snapshot.read_u32()?;
+        self.pot_x_value = snapshot.read_u8()?;
+        self.pot_y_value = snapshot.read_u8()?;
+        Ok(())
     }
 
     // I/O
 
+    fn peek(&self, reg: u8) -> u8 {
+        match reg {
+            // Reg::POTX
+            0x19 => self.pot_x_value,
+            // Reg::POTY
+            0x1a => self.pot_y_value,
+            _ => self.sampler.synth.read(reg, self.bus_value),
+        }
+    }
+
     fn read(&mut self, reg: u8) -> u8 {
-        self.sync();
-        self.resid.read(reg)
+        match reg {
+            // Reg::POTX
+            0x19 => {
+                self.sync();
+                self.pot_x_value
+            }
+            // Reg::POTY
+            0x1a => {
+                self.sync();
+                self.pot_y_value
+            }
+            _ => {
+                self.sync();
+                self.sampler.synth.read(reg, self.bus_value)
+            }
+        }
     }
 
     fn write(&mut self, reg: u8, value: u8) {
@@ -125,7 +329,9 @@ impl Chip for Sid {
             trace!(target: "sid::reg", "Write 0x{:02x} = 0x{:02x}", reg, value);
         }
         self.sync();
-        self.resid.write(reg, value)
+        self.bus_value = value;
+        self.bus_value_ttl = BUS_VALUE_TTL;
+        self.sampler.synth.write(reg, value);
     }
 }
 
@@ -2,8 +2,11 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
 use core::cell::Cell;
 
+use super::{Snapshot, SnapshotReader};
+
 #[derive(Default)]
 pub struct Clock {
     counter: Cell<u64>,
@@ -22,6 +25,15 @@ impl Clock {
         self.counter.set(0);
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u64(self.counter.get());
+    }
+
+    pub fn load_state(&self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.counter.set(snapshot.read_u64()?);
+        Ok(())
+    }
+
     pub fn tick(&self) {
         let result = self.counter.get().wrapping_add(1);
         self.counter.set(result);
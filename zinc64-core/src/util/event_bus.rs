@@ -0,0 +1,86 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+use super::Clock;
+
+/// Device state changes reported through the event bus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// Raster interrupt triggered at the specified line.
+    RasterIrq(u16),
+    /// CIA timer underflow, identified by CIA number (1 or 2) and timer (0 = A, 1 = B).
+    CiaTimerUnderflow(u8, u8),
+    /// Cartridge switched to the specified bank.
+    CartridgeBankSwitched(u8),
+    /// Datassette started a new pulse of the specified length in cycles.
+    TapePulse(u32),
+    /// Disk sector read, identified by track and sector.
+    DiskSectorRead(u8, u8),
+}
+
+/// Listener is invoked with the current cycle and the event.
+pub type EventFn = Box<dyn Fn(u64, &Event)>;
+
+/// EventBus provides an opt-in way to observe device state changes. Events are dropped
+/// unless a listener is attached.
+pub struct EventBus {
+    clock: Rc<Clock>,
+    listener: RefCell<Option<EventFn>>,
+}
+
+impl EventBus {
+    pub fn new(clock: Rc<Clock>) -> Self {
+        Self {
+            clock,
+            listener: RefCell::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.listener.borrow().is_some()
+    }
+
+    pub fn set_listener(&self, listener: Option<EventFn>) {
+        *self.listener.borrow_mut() = listener;
+    }
+
+    #[inline]
+    pub fn emit(&self, event: Event) {
+        if let Some(ref listener) = *self.listener.borrow() {
+            listener(self.clock.get(), &event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn emit_without_listener() {
+        let bus = EventBus::new(Rc::new(Clock::default()));
+        assert_eq!(false, bus.is_enabled());
+        bus.emit(Event::RasterIrq(0x30));
+    }
+
+    #[test]
+    fn emit_with_listener() {
+        let clock = Rc::new(Clock::default());
+        let bus = EventBus::new(clock.clone());
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let log_clone = log.clone();
+        bus.set_listener(Some(Box::new(move |cycle, event| {
+            log_clone.borrow_mut().push((cycle, *event));
+        })));
+        clock.tick_delta(10);
+        bus.emit(Event::TapePulse(400));
+        assert_eq!(1, log.borrow().len());
+        assert_eq!((10, Event::TapePulse(400)), log.borrow()[0]);
+    }
+}
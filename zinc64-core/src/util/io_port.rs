@@ -5,8 +5,11 @@
 use core::option::Option::{self, Some, None};
 use core::ops::Fn;
 use alloc::boxed::Box;
+use alloc::string::String;
 use bit_field::BitField;
 
+use super::{Snapshot, SnapshotReader};
+
 pub type Observer = Box<dyn Fn(u8)>;
 
 // direction - (where 1 is an output, and 0 is an input).
@@ -55,6 +58,11 @@ impl IoPort {
         self.notify_observer();
     }
 
+    pub fn set_input_masked(&mut self, mask: u8, value: u8) {
+        self.input = (self.input & !mask) | (value & mask);
+        self.notify_observer();
+    }
+
     pub fn set_observer(&mut self, observer: Observer) {
         self.observer = Some(observer);
     }
@@ -71,6 +79,20 @@ impl IoPort {
         self.notify_observer();
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.direction);
+        snapshot.write_u8(self.input);
+        snapshot.write_u8(self.output);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.direction = snapshot.read_u8()?;
+        self.input = snapshot.read_u8()?;
+        self.output = snapshot.read_u8()?;
+        self.notify_observer();
+        Ok(())
+    }
+
     fn notify_observer(&self) {
         if let Some(ref observer) = self.observer {
             observer(self.get_value());
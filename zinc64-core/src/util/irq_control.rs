@@ -2,8 +2,11 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
 use bit_field::BitField;
 
+use super::{Snapshot, SnapshotReader};
+
 #[derive(Default)]
 pub struct IrqControl {
     data: u8,
@@ -43,6 +46,17 @@ impl IrqControl {
         self.mask = 0;
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.data);
+        snapshot.write_u8(self.mask);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.data = snapshot.read_u8()?;
+        self.mask = snapshot.read_u8()?;
+        Ok(())
+    }
+
     pub fn set_event(&mut self, bit: usize) {
         self.data.set_bit(bit, true);
     }
@@ -2,7 +2,10 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
 use bit_field::BitField;
+
+use super::{Snapshot, SnapshotReader};
 // use log::LogLevel;
 
 pub struct IrqLine {
@@ -24,6 +27,15 @@ impl IrqLine {
         self.signal = 0;
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.signal);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.signal = snapshot.read_u8()?;
+        Ok(())
+    }
+
     pub fn set_low(&mut self, source: usize, value: bool) {
         /* if log_enabled!(LogLevel::Trace) {
             trace!(
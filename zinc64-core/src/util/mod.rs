@@ -3,19 +3,25 @@
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
 mod clock;
+mod event_bus;
 mod io_port;
 mod irq_control;
 mod irq_line;
 mod pin;
 mod ram;
+mod rng;
 mod rom;
 mod shared;
+mod snapshot;
 
 pub use self::clock::Clock;
+pub use self::event_bus::{Event, EventBus, EventFn};
 pub use self::io_port::IoPort;
 pub use self::irq_control::IrqControl;
 pub use self::irq_line::IrqLine;
 pub use self::pin::Pin;
 pub use self::ram::Ram;
+pub use self::rng::Rng;
 pub use self::rom::Rom;
 pub use self::shared::{new_shared, new_shared_cell, Shared, SharedCell};
+pub use self::snapshot::{Snapshot, SnapshotReader};
@@ -2,6 +2,10 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
+
+use super::{Snapshot, SnapshotReader};
+
 #[derive(Clone, Copy, PartialEq)]
 enum State {
     High,
@@ -45,6 +49,17 @@ impl Pin {
         self.last == State::Low && self.state == State::High
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bool(self.state == State::High);
+        snapshot.write_bool(self.last == State::High);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.state = if snapshot.read_bool()? { State::High } else { State::Low };
+        self.last = if snapshot.read_bool()? { State::High } else { State::Low };
+        Ok(())
+    }
+
     pub fn set_active(&mut self, active: bool) {
         if active {
             self.set(State::High);
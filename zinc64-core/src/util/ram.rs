@@ -2,8 +2,11 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
 use alloc::{vec, vec::Vec};
 
+use super::{Snapshot, SnapshotReader};
+
 pub struct Ram {
     data: Vec<u8>,
 }
@@ -33,6 +36,14 @@ impl Ram {
         self.data[address as usize]
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bytes(&self.data);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        snapshot.read_into(&mut self.data)
+    }
+
     pub fn write(&mut self, address: u16, value: u8) {
         self.data[address as usize] = value
     }
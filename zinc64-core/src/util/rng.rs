@@ -0,0 +1,60 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+const SEED_MIX: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Small xorshift64* generator for state that is undefined on real hardware. The
+/// sequence depends only on the seed so runs can be reproduced.
+#[derive(Clone, Copy)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift, so the seed is mixed with a constant
+        let state = seed ^ SEED_MIX;
+        Rng {
+            state: if state != 0 { state } else { SEED_MIX },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut rng_1 = Rng::new(42);
+        let mut rng_2 = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(rng_1.next_u64(), rng_2.next_u64());
+        }
+    }
+
+    #[test]
+    fn different_seed_different_sequence() {
+        let mut rng_1 = Rng::new(42);
+        let mut rng_2 = Rng::new(43);
+        assert_ne!(rng_1.next_u64(), rng_2.next_u64());
+    }
+
+    #[test]
+    fn any_seed_produces_output() {
+        let mut rng = Rng::new(SEED_MIX);
+        assert_ne!(0, rng.next_u64());
+    }
+}
@@ -0,0 +1,236 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+const MAGIC: [u8; 4] = *b"Z64S";
+#[cfg(feature = "std")]
+const VERSION: u16 = 2;
+
+/// Snapshot holds the machine state as a binary blob. Components append their state
+/// in a fixed order and read it back in the same order through `SnapshotReader`.
+/// Values are stored little endian.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    data: Vec<u8>,
+}
+
+// Stored form of a snapshot, encoded with bincode.
+#[cfg(feature = "std")]
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    magic: [u8; 4],
+    version: u16,
+    snapshot: Snapshot,
+}
+
+impl Snapshot {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    /// Load snapshot from bytes previously obtained with `to_bytes`.
+    #[cfg(feature = "std")]
+    pub fn from_bytes(data: &[u8]) -> Result<Snapshot, String> {
+        let file: SnapshotFile =
+            bincode::deserialize(data).map_err(|err| format!("invalid snapshot, {}", err))?;
+        if file.magic != MAGIC {
+            return Err(String::from("invalid snapshot header"));
+        }
+        if file.version != VERSION {
+            return Err(format!("unsupported snapshot version {}", file.version));
+        }
+        Ok(file.snapshot)
+    }
+
+    /// Encode snapshot with a header identifying the format version.
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let file = SnapshotFile {
+            magic: MAGIC,
+            version: VERSION,
+            snapshot: self.clone(),
+        };
+        bincode::serialize(&file).map_err(|err| format!("failed to encode snapshot, {}", err))
+    }
+
+    pub fn reader(&self) -> SnapshotReader {
+        SnapshotReader {
+            data: &self.data,
+            pos: 0,
+        }
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write a length prefixed block of bytes.
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    /// Write another snapshot as a nested block.
+    pub fn write_snapshot(&mut self, value: &Snapshot) {
+        self.write_bytes(&value.data);
+    }
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct SnapshotReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(format!("invalid snapshot bool {}", value)),
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn read_snapshot(&mut self) -> Result<Snapshot, String> {
+        Ok(Snapshot {
+            data: self.read_bytes()?.to_vec(),
+        })
+    }
+
+    /// Read a length prefixed block of bytes into a buffer of the expected size.
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), String> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(format!(
+                "snapshot block size mismatch, expected {} got {}",
+                buffer.len(),
+                bytes.len()
+            ));
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.pos + len > self.data.len() {
+            return Err(String::from("unexpected end of snapshot"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_values() {
+        let mut snapshot = Snapshot::new();
+        snapshot.write_bool(true);
+        snapshot.write_u8(0x12);
+        snapshot.write_u16(0x3456);
+        snapshot.write_u32(0x789a_bcde);
+        snapshot.write_u64(0x0102_0304_0506_0708);
+        snapshot.write_bytes(&[1, 2, 3]);
+        let mut reader = snapshot.reader();
+        assert_eq!(Ok(true), reader.read_bool());
+        assert_eq!(Ok(0x12), reader.read_u8());
+        assert_eq!(Ok(0x3456), reader.read_u16());
+        assert_eq!(Ok(0x789a_bcde), reader.read_u32());
+        assert_eq!(Ok(0x0102_0304_0506_0708), reader.read_u64());
+        assert_eq!(Ok(&[1u8, 2, 3][..]), reader.read_bytes());
+        assert!(reader.is_empty());
+        assert!(reader.read_u8().is_err());
+    }
+
+    #[test]
+    fn nested_snapshot() {
+        let mut inner = Snapshot::new();
+        inner.write_u16(0x1234);
+        let mut snapshot = Snapshot::new();
+        snapshot.write_snapshot(&inner);
+        snapshot.write_u8(0x56);
+        let mut reader = snapshot.reader();
+        assert!(inner == reader.read_snapshot().unwrap());
+        assert_eq!(Ok(0x56), reader.read_u8());
+        assert!(reader.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_bytes_checks_header() {
+        let mut snapshot = Snapshot::new();
+        snapshot.write_u8(0x42);
+        let bytes = snapshot.to_bytes().unwrap();
+        let copy = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(Ok(0x42), copy.reader().read_u8());
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'A';
+        assert!(Snapshot::from_bytes(&bad_magic).is_err());
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 0x01;
+        assert!(Snapshot::from_bytes(&bad_version).is_err());
+    }
+}
@@ -4,6 +4,10 @@
 
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::collapsible_if))]
 
+use alloc::string::String;
+
+use crate::util::{Snapshot, SnapshotReader};
+
 pub struct Config {
     pub border_color: u8,
     pub csel: bool,
@@ -55,6 +59,23 @@ impl BorderUnit {
         self.vertical_flop = false;
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.config.border_color);
+        snapshot.write_bool(self.config.csel);
+        snapshot.write_bool(self.config.rsel);
+        snapshot.write_bool(self.main_flop);
+        snapshot.write_bool(self.vertical_flop);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.config.border_color = snapshot.read_u8()?;
+        self.config.csel = snapshot.read_bool()?;
+        self.config.rsel = snapshot.read_bool()?;
+        self.main_flop = snapshot.read_bool()?;
+        self.vertical_flop = snapshot.read_bool()?;
+        Ok(())
+    }
+
     /*
            |   CSEL=0   |   CSEL=1
      ------+------------+-----------
@@ -2,8 +2,11 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
 use bit_field::BitField;
 
+use crate::util::{Snapshot, SnapshotReader};
+
 #[derive(Copy, Clone)]
 pub enum Mode {
     // (ECM/BMM/MCM=0/0/0)
@@ -134,6 +137,34 @@ impl GfxSequencer {
         self.output = (0, false);
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u8(self.config.mode.value());
+        for color in self.config.bg_color.iter() {
+            snapshot.write_u8(*color);
+        }
+        snapshot.write_u8(self.c_data);
+        snapshot.write_u8(self.c_color);
+        snapshot.write_u8(self.g_data);
+        snapshot.write_u8(self.data);
+        snapshot.write_bool(self.mc_cycle);
+        snapshot.write_u8(self.output.0);
+        snapshot.write_bool(self.output.1);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.config.mode = Mode::from(snapshot.read_u8()? & 0x07);
+        for color in self.config.bg_color.iter_mut() {
+            *color = snapshot.read_u8()?;
+        }
+        self.c_data = snapshot.read_u8()?;
+        self.c_color = snapshot.read_u8()?;
+        self.g_data = snapshot.read_u8()?;
+        self.data = snapshot.read_u8()?;
+        self.mc_cycle = snapshot.read_bool()?;
+        self.output = (snapshot.read_u8()?, snapshot.read_bool()?);
+        Ok(())
+    }
+
     /*
      +----+----+----+----+----+----+----+----+
      |  7 |  6 |  5 |  4 |  3 |  2 |  1 |  0 |
@@ -5,10 +5,14 @@
 mod border_unit;
 mod gfx_sequencer;
 mod mux_unit;
+mod palette;
+mod render_target;
 mod spec;
 mod sprite_sequencer;
 mod vic;
 mod vic_memory;
 
+pub use self::palette::Palette;
+pub use self::render_target::RenderTarget;
 pub use self::vic::Vic;
 pub use self::vic_memory::VicMemory;
@@ -2,10 +2,13 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
+use alloc::string::String;
 use bit_field::BitField;
 use core::option::Option::{self, Some, None};
 use core::iter::Iterator;
 
+use crate::util::{Snapshot, SnapshotReader};
+
 const PRIO_SCREEN_BORDER: u8 = 0;
 const PRIO_FG_SPRITE: u8 = 1;
 const PRIO_FG_GRAPHICS: u8 = 2;
@@ -115,6 +118,31 @@ impl MuxUnit {
         self.output_priority = 0;
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        for priority in self.config.data_priority.iter() {
+            snapshot.write_bool(*priority);
+        }
+        snapshot.write_u8(self.mb_collision);
+        snapshot.write_bool(self.mb_interrupt);
+        snapshot.write_u8(self.mm_collision);
+        snapshot.write_bool(self.mm_interrupt);
+        snapshot.write_u8(self.output);
+        snapshot.write_u8(self.output_priority);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        for priority in self.config.data_priority.iter_mut() {
+            *priority = snapshot.read_bool()?;
+        }
+        self.mb_collision = snapshot.read_u8()?;
+        self.mb_interrupt = snapshot.read_bool()?;
+        self.mm_collision = snapshot.read_u8()?;
+        self.mm_interrupt = snapshot.read_bool()?;
+        self.output = snapshot.read_u8()?;
+        self.output_priority = snapshot.read_u8()?;
+        Ok(())
+    }
+
     fn output_pixel(&mut self, pixel: u8, priority: u8) {
         self.output = pixel;
         self.output_priority = priority;
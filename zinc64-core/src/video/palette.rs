@@ -0,0 +1,159 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+// Spec: http://unusedino.de/ec64/technical/misc/vic656x/colors/
+//       https://www.pepto.de/projects/colorvic/
+
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::unreadable_literal))]
+
+use alloc::format;
+use alloc::string::String;
+
+static PEPTO: [u32; 16] = [
+    0x000000, // Black
+    0xffffff, // White
+    0x68372b, // Red
+    0x70a4b2, // Cyan
+    0x6f3d86, // Purple
+    0x588d43, // Green
+    0x352879, // Blue
+    0xb8c76f, // Yellow
+    0x6f4f25, // Orange
+    0x433900, // Brown
+    0x9a6759, // LightRed
+    0x444444, // DarkGray
+    0x6c6c6c, // MediumGray
+    0x9ad284, // LightGreen
+    0x6c5eb5, // LightBlue
+    0x959595, // LightGray
+];
+
+static COLODORE: [u32; 16] = [
+    0x000000, // Black
+    0xffffff, // White
+    0x813338, // Red
+    0x75cec8, // Cyan
+    0x8e3c97, // Purple
+    0x56ac4d, // Green
+    0x2e2c9b, // Blue
+    0xedf171, // Yellow
+    0x8e5029, // Orange
+    0x553800, // Brown
+    0xc46c71, // LightRed
+    0x4a4a4a, // DarkGray
+    0x7b7b7b, // MediumGray
+    0xa9ff9f, // LightGreen
+    0x706deb, // LightBlue
+    0xb2b2b2, // LightGray
+];
+
+// Early VIC-II chips only have five luminance levels, so the grays share their
+// luminance with other colors. The colors are computed from these levels and
+// Pepto's chroma angles at the default saturation.
+static ORIGINAL: [u32; 16] = [
+    0x000000, // Black
+    0xffffff, // White
+    0x662f33, // Red
+    0x9ad1cd, // Cyan
+    0xa068a6, // Purple
+    0x60985a, // Green
+    0x383784, // Blue
+    0xc8c97c, // Yellow
+    0xa0775a, // Orange
+    0x564007, // Brown
+    0xa66f73, // LightRed
+    0x404040, // DarkGray
+    0x808080, // MediumGray
+    0xa0d89a, // LightGreen
+    0x7877c4, // LightBlue
+    0xc0c0c0, // LightGray
+];
+
+/// Palette maps VIC colors to RGB values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Palette {
+    /// Pepto's original measurements of a PAL C64, the muted colors most users know.
+    Pepto,
+    /// Colodore palette based on Pepto's later, more accurate, measurements.
+    Colodore,
+    /// Grey-ish colors of the first VIC-II revision with five luminance levels.
+    Original,
+    /// User supplied colors in 0xRRGGBB format.
+    Custom([u32; 16]),
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::Pepto
+    }
+}
+
+impl Palette {
+    pub fn from(name: &str) -> Result<Palette, String> {
+        match name {
+            "pepto" => Ok(Palette::Pepto),
+            "colodore" => Ok(Palette::Colodore),
+            "original" => Ok(Palette::Original),
+            _ => Err(format!("invalid palette {}", name)),
+        }
+    }
+
+    /// Get the RGB components of the specified VIC color.
+    pub fn get_rgb(&self, color: u8) -> (u8, u8, u8) {
+        let value = self.colors()[(color & 0x0f) as usize];
+        ((value >> 16) as u8, (value >> 8) as u8, value as u8)
+    }
+
+    /// Get colors in 0xRRGGBB format.
+    pub fn to_rgb(&self) -> [u32; 16] {
+        *self.colors()
+    }
+
+    /// Get opaque colors packed as 0xAABBGGRR, which is RGBA byte order in memory.
+    pub fn to_rgba(&self) -> [u32; 16] {
+        let mut colors = [0; 16];
+        for (i, color) in colors.iter_mut().enumerate() {
+            let (r, g, b) = self.get_rgb(i as u8);
+            *color = u32::from_le_bytes([r, g, b, 0xff]);
+        }
+        colors
+    }
+
+    fn colors(&self) -> &[u32; 16] {
+        match self {
+            Palette::Pepto => &PEPTO,
+            Palette::Colodore => &COLODORE,
+            Palette::Original => &ORIGINAL,
+            Palette::Custom(colors) => colors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb_components() {
+        assert_eq!((0x6c, 0x5e, 0xb5), Palette::Pepto.get_rgb(14));
+        assert_eq!((0x70, 0x6d, 0xeb), Palette::Colodore.get_rgb(14));
+        assert_eq!((0x80, 0x80, 0x80), Palette::Original.get_rgb(12));
+        assert_eq!((0x12, 0x34, 0x56), Palette::Custom([0x123456; 16]).get_rgb(3));
+    }
+
+    #[test]
+    fn rgba_packing() {
+        let colors = Palette::Pepto.to_rgba();
+        assert_eq!(0xff000000, colors[0]);
+        assert_eq!(0xff2b3768, colors[2]);
+        assert_eq!([0x68, 0x37, 0x2b, 0xff], colors[2].to_le_bytes());
+    }
+
+    #[test]
+    fn from_name() {
+        assert_eq!(Ok(Palette::Colodore), Palette::from("colodore"));
+        assert_eq!(Ok(Palette::Original), Palette::from("original"));
+        assert!(Palette::from("unknown").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use crate::factory::VideoOutput;
+
+/// Frame buffer with capacity fixed at compile time so frontends without a heap can
+/// render, e.g. by placing it in a static. Pixels are stored as palette colors, one row
+/// of `WIDTH` pixels per raster line.
+pub struct RenderTarget<const WIDTH: usize, const HEIGHT: usize> {
+    palette: [u32; 16],
+    pixels: [[u32; WIDTH]; HEIGHT],
+    frames: u32,
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> RenderTarget<WIDTH, HEIGHT> {
+    pub const fn new(palette: [u32; 16]) -> Self {
+        RenderTarget {
+            palette,
+            pixels: [[0; WIDTH]; HEIGHT],
+            frames: 0,
+        }
+    }
+
+    /// Get number of frames completed since reset.
+    pub fn get_frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y][x]
+    }
+
+    pub fn get_row(&self, y: usize) -> &[u32; WIDTH] {
+        &self.pixels[y]
+    }
+
+    pub fn rows(&self) -> &[[u32; WIDTH]; HEIGHT] {
+        &self.pixels
+    }
+
+    pub fn set_palette(&mut self, palette: [u32; 16]) {
+        self.palette = palette;
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> VideoOutput for RenderTarget<WIDTH, HEIGHT> {
+    fn get_dimension(&self) -> (usize, usize) {
+        (WIDTH, HEIGHT)
+    }
+
+    fn reset(&mut self) {
+        for row in self.pixels.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel = 0;
+            }
+        }
+        self.frames = 0;
+    }
+
+    fn write(&mut self, index: usize, color: u8) {
+        self.pixels[index / WIDTH][index % WIDTH] = self.palette[(color & 0x0f) as usize];
+    }
+
+    fn end_frame(&mut self) {
+        self.frames = self.frames.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::video::Palette;
+
+    const PALETTE: [u32; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    static TARGET: RenderTarget<8, 4> = RenderTarget::new(PALETTE);
+
+    #[test]
+    fn write_and_read_back() {
+        let mut target = RenderTarget::<8, 4>::new(PALETTE);
+        assert_eq!((8, 4), target.get_dimension());
+        target.write(0, 1);
+        target.write(7, 2);
+        target.write(8, 3);
+        target.write(3 * 8 + 5, 15);
+        assert_eq!(1, target.get_pixel(0, 0));
+        assert_eq!(2, target.get_pixel(7, 0));
+        assert_eq!(3, target.get_pixel(0, 1));
+        assert_eq!(15, target.get_pixel(5, 3));
+        assert_eq!(&[3, 0, 0, 0, 0, 0, 0, 0], target.get_row(1));
+    }
+
+    #[test]
+    fn maps_colors_through_palette() {
+        let mut target = RenderTarget::<4, 2>::new(Palette::Pepto.to_rgb());
+        target.write(5, 2);
+        assert_eq!(0x68372b, target.get_pixel(1, 1));
+        target.set_palette(Palette::Colodore.to_rgb());
+        target.write(5, 2);
+        assert_eq!(0x813338, target.get_pixel(1, 1));
+    }
+
+    #[test]
+    fn reset_clears_pixels_and_frames() {
+        let mut target = RenderTarget::<4, 2>::new(PALETTE);
+        target.write(6, 9);
+        target.end_frame();
+        assert_eq!(1, target.get_frames());
+        target.reset();
+        assert_eq!(0, target.get_frames());
+        assert!(target
+            .rows()
+            .iter()
+            .all(|row| row.iter().all(|pixel| *pixel == 0)));
+    }
+
+    #[test]
+    fn static_target() {
+        assert_eq!((8, 4), TARGET.get_dimension());
+        assert_eq!(0, TARGET.get_pixel(7, 3));
+    }
+}
@@ -32,6 +32,7 @@ impl Spec {
     pub fn new(chip_model: VicModel) -> Spec {
         match chip_model {
             VicModel::Mos6567 => Spec::ntsc(),
+            VicModel::Mos6567R56A => Spec::ntsc_old(),
             VicModel::Mos6569 => Spec::pal(),
         }
     }
@@ -44,6 +45,14 @@ impl Spec {
         }
     }
 
+    fn ntsc_old() -> Spec {
+        Spec {
+            raster_lines: 262,
+            cycles_per_raster: 64,
+            first_x_coord: 0x19c,
+        }
+    }
+
     fn pal() -> Spec {
         Spec {
             raster_lines: 312,
@@ -4,10 +4,13 @@
 
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
 
+use alloc::string::String;
 use core::option::Option::{self, Some, None};
 
 use bit_field::BitField;
 
+use crate::util::{Snapshot, SnapshotReader};
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum Mode {
     Standard = 0,
@@ -100,10 +103,12 @@ impl SpriteSequencer {
     pub fn clock(&mut self, x: u16) {
         if self.display {
             if self.delay_cycles == 0 {
+                // Once X matches, the shift register keeps running regardless of later
+                // writes to the X register.
                 if x == self.config.x_screen && self.counter == 0 {
                     self.counter = 0xffff_ff00;
                 }
-                if x >= self.config.x_screen && self.counter != 0 {
+                if self.counter != 0 {
                     match self.config.mode {
                         Mode::Standard => {
                             self.output = self.output_pixel();
@@ -145,6 +150,52 @@ impl SpriteSequencer {
         self.output = None;
     }
 
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bool(self.config.mode == Mode::Multicolor);
+        snapshot.write_u8(self.config.color);
+        snapshot.write_bool(self.config.enabled);
+        snapshot.write_bool(self.config.expand_x);
+        snapshot.write_bool(self.config.expand_y);
+        snapshot.write_u8(self.config.multicolor[0]);
+        snapshot.write_u8(self.config.multicolor[1]);
+        snapshot.write_u16(self.config.x);
+        snapshot.write_u16(self.config.x_screen);
+        snapshot.write_u8(self.config.y);
+        snapshot.write_u32(self.counter);
+        snapshot.write_u32(self.data);
+        snapshot.write_u8(self.delay_cycles);
+        snapshot.write_bool(self.display);
+        snapshot.write_bool(self.expansion_flop);
+        snapshot.write_bool(self.output.is_some());
+        snapshot.write_u8(self.output.unwrap_or(0));
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.config.mode = if snapshot.read_bool()? {
+            Mode::Multicolor
+        } else {
+            Mode::Standard
+        };
+        self.config.color = snapshot.read_u8()?;
+        self.config.enabled = snapshot.read_bool()?;
+        self.config.expand_x = snapshot.read_bool()?;
+        self.config.expand_y = snapshot.read_bool()?;
+        self.config.multicolor[0] = snapshot.read_u8()?;
+        self.config.multicolor[1] = snapshot.read_u8()?;
+        self.config.x = snapshot.read_u16()?;
+        self.config.x_screen = snapshot.read_u16()?;
+        self.config.y = snapshot.read_u8()?;
+        self.counter = snapshot.read_u32()?;
+        self.data = snapshot.read_u32()?;
+        self.delay_cycles = snapshot.read_u8()?;
+        self.display = snapshot.read_bool()?;
+        self.expansion_flop = snapshot.read_bool()?;
+        let has_output = snapshot.read_bool()?;
+        let output = snapshot.read_u8()?;
+        self.output = if has_output { Some(output) } else { None };
+        Ok(())
+    }
+
     fn output_pixel(&self) -> Option<u8> {
         if self.data.get_bit(31) {
             Some(self.config.color)
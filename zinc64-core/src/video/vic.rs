@@ -5,6 +5,8 @@
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::cyclomatic_complexity))]
 
+use alloc::rc::Rc;
+use alloc::string::String;
 use core::option::Option;
 use crate::factory::{Chip, VicModel, VideoOutput};
 use crate::util::*;
@@ -127,6 +129,47 @@ impl RasterUnit {
         }
         self.sprites_on = false;
     }
+
+    pub fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_bool(self.display_on);
+        snapshot.write_bool(self.display_state);
+        snapshot.write_bool(self.is_bad_line);
+        snapshot.write_u8(self.rc);
+        snapshot.write_u16(self.vc);
+        snapshot.write_u16(self.vc_base);
+        snapshot.write_u16(self.vmli as u16);
+        snapshot.write_bytes(&self.vm_color_line);
+        snapshot.write_bytes(&self.vm_data_line);
+        // Sprites
+        snapshot.write_bytes(&self.mc);
+        snapshot.write_bytes(&self.mc_base);
+        for i in 0..8 {
+            snapshot.write_bool(self.sprite_dma[i]);
+            snapshot.write_u16(self.sprite_ptrs[i]);
+        }
+        snapshot.write_bool(self.sprites_on);
+    }
+
+    pub fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.display_on = snapshot.read_bool()?;
+        self.display_state = snapshot.read_bool()?;
+        self.is_bad_line = snapshot.read_bool()?;
+        self.rc = snapshot.read_u8()?;
+        self.vc = snapshot.read_u16()?;
+        self.vc_base = snapshot.read_u16()?;
+        self.vmli = snapshot.read_u16()? as usize;
+        snapshot.read_into(&mut self.vm_color_line)?;
+        snapshot.read_into(&mut self.vm_data_line)?;
+        // Sprites
+        snapshot.read_into(&mut self.mc)?;
+        snapshot.read_into(&mut self.mc_base)?;
+        for i in 0..8 {
+            self.sprite_dma[i] = snapshot.read_bool()?;
+            self.sprite_ptrs[i] = snapshot.read_u16()?;
+        }
+        self.sprites_on = snapshot.read_bool()?;
+        Ok(())
+    }
 }
 
 pub struct Vic {
@@ -134,6 +177,7 @@ pub struct Vic {
     spec: Spec,
     color_ram: Shared<Ram>,
     mem: VicMemory,
+    events: Rc<EventBus>,
     // Functional Units
     border_unit: BorderUnit,
     gfx_seq: GfxSequencer,
@@ -151,6 +195,9 @@ pub struct Vic {
     video_matrix: u16,
     // Runtime State
     cycle: u16,
+    frame_count: u32,
+    light_pen_pos: [u8; 2],
+    light_pen_triggered: bool,
     y: u16,
     // I/O
     ba_line: Shared<Pin>,
@@ -168,6 +215,7 @@ impl Vic {
         vsync_flag: SharedCell<bool>,
         ba_line: Shared<Pin>,
         irq_line: Shared<IrqLine>,
+        events: Rc<EventBus>,
     ) -> Vic {
         info!(target: "video", "Initializing VIC");
         let spec = Spec::new(chip_model);
@@ -187,6 +235,7 @@ impl Vic {
             spec,
             color_ram,
             mem,
+            events,
             // Functional Units
             border_unit: BorderUnit::new(),
             gfx_seq: GfxSequencer::new(),
@@ -204,6 +253,9 @@ impl Vic {
             video_matrix: 0,
             // Runtime State
             cycle: 1,
+            frame_count: 0,
+            light_pen_pos: [0; 2],
+            light_pen_triggered: false,
             y: 0,
             // I/O
             frame_buffer,
@@ -213,6 +265,58 @@ impl Vic {
         }
     }
 
+    /// Returns the number of frames completed since reset.
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    /// Selects the 16KB bank used for all video matrix, character and sprite fetches.
+    /// Bank 0 starts at $0000 and bank 3 at $C000.
+    pub fn set_bank(&mut self, bank: u8) {
+        self.mem.set_bank(bank);
+    }
+
+    /// Latches the current beam position into LPX/LPY and raises the light pen
+    /// interrupt. Only the first trigger within a frame is latched.
+    pub fn trigger_light_pen(&mut self) {
+        if !self.light_pen_triggered {
+            self.light_pen_triggered = true;
+            let screen_x = (self.cycle - 1) << 3;
+            let x = if screen_x >= 0x64 {
+                screen_x - 0x64
+            } else {
+                screen_x + self.spec.first_x_coord
+            };
+            self.light_pen_pos[0] = (x >> 1) as u8;
+            self.light_pen_pos[1] = (self.y & 0x00ff) as u8;
+            self.trigger_irq(3);
+        }
+    }
+
+    /// Returns the 8-byte bitmaps of all 256 characters as seen by the VIC through
+    /// the current bank and character base, i.e. from either character ROM or RAM.
+    pub fn char_set(&self) -> [[u8; 8]; 256] {
+        let mut char_set = [[0u8; 8]; 256];
+        for (i, glyph) in char_set.iter_mut().enumerate() {
+            for (row, value) in glyph.iter_mut().enumerate() {
+                let address = self.char_base | ((i as u16) << 3) | row as u16;
+                *value = self.mem.read(address);
+            }
+        }
+        char_set
+    }
+
+    /// Returns the absolute addresses of the sprite data blocks as selected by the
+    /// sprite pointers at the end of the current video matrix.
+    pub fn sprite_pointers(&self) -> [u16; 8] {
+        let mut pointers = [0u16; 8];
+        for (n, pointer) in pointers.iter_mut().enumerate() {
+            let address = self.video_matrix | 0x03f8 | n as u16;
+            *pointer = self.mem.get_base_address() | (u16::from(self.mem.read(address)) << 6);
+        }
+        pointers
+    }
+
     fn draw(&mut self) {
         let x_start = (self.cycle << 3) - 12;
         let x_scroll_start = x_start + self.x_scroll as u16;
@@ -348,6 +452,9 @@ impl Vic {
     fn trigger_irq(&mut self, source: usize) {
         self.irq_control.set_event(source);
         if self.irq_control.is_triggered() {
+            if source == 0 && self.irq_control.get_mask() & 0x01 != 0 {
+                self.events.emit(Event::RasterIrq(self.y));
+            }
             if log_enabled!(LogLevel::Trace) {
                 trace!(target: "vic::reg", "Irq data = {:02x}, mask = {:02x}, source: {}",
                        self.irq_control.get_data(),
@@ -725,8 +832,9 @@ impl Chip for Vic {
             }
             55 => {
                 self.draw_cycle_17_56();
-                self.update_sprite_dma_on();
+                // The flip flop is inverted before DMA turned on in this cycle resets it
                 self.update_sprite_expansion_ff();
+                self.update_sprite_dma_on();
                 let sprite_dma = self.raster_unit.sprite_dma[0];
                 self.set_ba(sprite_dma);
                 self.g_access();
@@ -837,6 +945,7 @@ impl Chip for Vic {
             self.y += 1;
             if self.y >= self.spec.raster_lines {
                 self.y = 0;
+                self.light_pen_triggered = false;
                 self.raster_unit.display_on = false;
                 /*
                 Section: 3.7.2. VC and RC
@@ -846,6 +955,8 @@ impl Chip for Vic {
                    and is irrelevant.
                 */
                 self.raster_unit.vc_base = 0;
+                self.frame_count = self.frame_count.wrapping_add(1);
+                self.frame_buffer.borrow_mut().end_frame();
                 self.vsync_flag.set(true);
             }
         }
@@ -879,13 +990,73 @@ impl Chip for Vic {
         self.video_matrix = 0x0400;
         // Runtime State
         self.cycle = 1;
+        self.frame_count = 0;
+        self.light_pen_pos = [0; 2];
+        self.light_pen_triggered = false;
         self.y = 0x0100;
     }
 
+    fn save_state(&self, snapshot: &mut Snapshot) {
+        // Functional Units
+        self.border_unit.save_state(snapshot);
+        self.gfx_seq.save_state(snapshot);
+        self.irq_control.save_state(snapshot);
+        self.mux_unit.save_state(snapshot);
+        self.raster_unit.save_state(snapshot);
+        for sprite_unit in self.sprite_units.iter() {
+            sprite_unit.save_state(snapshot);
+        }
+        // Configuration
+        snapshot.write_u16(self.char_base);
+        snapshot.write_bool(self.den);
+        snapshot.write_u16(self.raster_compare);
+        snapshot.write_u8(self.x_scroll);
+        snapshot.write_u8(self.y_scroll);
+        snapshot.write_u16(self.video_matrix);
+        snapshot.write_u16(self.mem.get_base_address());
+        // Runtime State
+        snapshot.write_u16(self.cycle);
+        snapshot.write_u32(self.frame_count);
+        snapshot.write_u8(self.light_pen_pos[0]);
+        snapshot.write_u8(self.light_pen_pos[1]);
+        snapshot.write_bool(self.light_pen_triggered);
+        snapshot.write_u16(self.y);
+        self.ba_line.borrow().save_state(snapshot);
+    }
+
+    fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        // Functional Units
+        self.border_unit.load_state(snapshot)?;
+        self.gfx_seq.load_state(snapshot)?;
+        self.irq_control.load_state(snapshot)?;
+        self.mux_unit.load_state(snapshot)?;
+        self.raster_unit.load_state(snapshot)?;
+        for sprite_unit in self.sprite_units.iter_mut() {
+            sprite_unit.load_state(snapshot)?;
+        }
+        // Configuration
+        self.char_base = snapshot.read_u16()?;
+        self.den = snapshot.read_bool()?;
+        self.raster_compare = snapshot.read_u16()?;
+        self.x_scroll = snapshot.read_u8()?;
+        self.y_scroll = snapshot.read_u8()?;
+        self.video_matrix = snapshot.read_u16()?;
+        self.mem.set_bank((snapshot.read_u16()? >> 14) as u8);
+        // Runtime State
+        self.cycle = snapshot.read_u16()?;
+        self.frame_count = snapshot.read_u32()?;
+        self.light_pen_pos[0] = snapshot.read_u8()?;
+        self.light_pen_pos[1] = snapshot.read_u8()?;
+        self.light_pen_triggered = snapshot.read_bool()?;
+        self.y = snapshot.read_u16()?;
+        self.ba_line.borrow_mut().load_state(snapshot)?;
+        Ok(())
+    }
+
     // I/O
 
-    fn read(&mut self, reg: u8) -> u8 {
-        let value = match reg {
+    fn peek(&self, reg: u8) -> u8 {
+        match reg {
             // Reg::M0X - Reg::M7X
             0x00 | 0x02 | 0x04 | 0x06 | 0x08 | 0x0a | 0x0c | 0x0e => {
                 (self.sprite_units[(reg >> 1) as usize].config.x & 0x00ff) as u8
@@ -916,9 +1087,9 @@ impl Chip for Vic {
             // Reg::RASTER
             0x12 => (self.y & 0x00ff) as u8,
             // Reg::LPX
-            0x13 => 0,
+            0x13 => self.light_pen_pos[0],
             // Reg::LPY
-            0x14 => 0,
+            0x14 => self.light_pen_pos[1],
             // Reg::ME
             0x15 => {
                 let mut result = 0;
@@ -982,17 +1153,9 @@ impl Chip for Vic {
                 result
             }
             // Reg::MM
-            0x1e => {
-                let result = self.mux_unit.mm_collision;
-                self.mux_unit.mm_collision = 0;
-                result
-            }
+            0x1e => self.mux_unit.mm_collision,
             // Reg::MD
-            0x1f => {
-                let result = self.mux_unit.mb_collision;
-                self.mux_unit.mb_collision = 0;
-                result
-            }
+            0x1f => self.mux_unit.mb_collision,
             // Reg::EC
             0x20 => self.border_unit.config.border_color | 0xf0,
             // Reg::B0C - Reg::B3C
@@ -1002,7 +1165,18 @@ impl Chip for Vic {
             // Reg::M0C - Reg::M7C
             0x27..=0x2e => self.sprite_units[(reg - 0x27) as usize].config.color | 0xf0,
             _ => 0xff,
-        };
+        }
+    }
+
+    fn read(&mut self, reg: u8) -> u8 {
+        let value = self.peek(reg);
+        match reg {
+            // Reg::MM
+            0x1e => self.mux_unit.mm_collision = 0,
+            // Reg::MD
+            0x1f => self.mux_unit.mb_collision = 0,
+            _ => {}
+        }
         if log_enabled!(LogLevel::Trace) {
             trace!(target: "vic::reg", "Read 0x{:02x} = 0x{:02x}", reg, value);
         }
@@ -1086,7 +1260,9 @@ impl Chip for Vic {
                     1. The expansion flip flip is set as long as the bit in MxYE in register
                        $d017 corresponding to the sprite is cleared.
                     */
-                    self.sprite_units[i].expansion_flop = !self.sprite_units[i].config.expand_y;
+                    if !self.sprite_units[i].config.expand_y {
+                        self.sprite_units[i].expansion_flop = true;
+                    }
                 }
             }
             // Reg::MEMPTR
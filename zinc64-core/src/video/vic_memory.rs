@@ -19,6 +19,15 @@ impl VicMemory {
         }
     }
 
+    pub fn get_base_address(&self) -> u16 {
+        self.base_address.get()
+    }
+
+    /// Selects one of the four 16KB banks visible to the VIC.
+    pub fn set_bank(&mut self, bank: u8) {
+        self.base_address.set(u16::from(bank & 0x03) << 14);
+    }
+
     pub fn read(&self, address: u16) -> u8 {
         let full_address = self.base_address.get() | address;
         let zone = full_address >> 12;
@@ -5,26 +5,32 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use zinc64_core::factory::Chip;
+use zinc64_core::factory::{Chip, CiaModel};
 use zinc64_core::io::cia::{reg, Mode};
 use zinc64_core::io::Cia;
-use zinc64_core::util::{IoPort, IrqLine, Pin};
+use zinc64_core::util::{Clock, EventBus, IoPort, IrqLine, Pin, Shared};
 
 fn setup_cia() -> Cia {
+    setup_cia_with_model(CiaModel::Mos6526, Rc::new(RefCell::new(IrqLine::new("irq"))))
+}
+
+fn setup_cia_with_model(chip_model: CiaModel, cpu_irq: Shared<IrqLine>) -> Cia {
     let cia_flag = Rc::new(RefCell::new(Pin::new_low()));
     let cia_port_a = Rc::new(RefCell::new(IoPort::new(0x00, 0xff)));
     let cia_port_b = Rc::new(RefCell::new(IoPort::new(0x00, 0xff)));
-    let cpu_irq = Rc::new(RefCell::new(IrqLine::new("irq")));
     let keyboard_matrix = Rc::new(RefCell::new([0xff; 16]));
     let mut cia = Cia::new(
         Mode::Cia1,
+        chip_model,
         None,
         None,
         Some(keyboard_matrix),
         cia_port_a,
         cia_port_b,
+        None,
         cia_flag,
         cpu_irq,
+        Rc::new(EventBus::new(Rc::new(Clock::default()))),
     );
     cia.reset();
     cia
@@ -214,3 +220,209 @@ fn cia1_tb123_01_00() {
     assert_eq!(0x05, cia.read(reg::TBLO));
     cia.clock();
 }
+
+fn cycles_to_irq(chip_model: CiaModel) -> u32 {
+    let cpu_irq = Rc::new(RefCell::new(IrqLine::new("irq")));
+    let mut cia = setup_cia_with_model(chip_model, cpu_irq.clone());
+    cia.write(reg::TALO, 0x04);
+    cia.write(reg::TAHI, 0x00);
+    cia.write(reg::ICR, 0x81);
+    cia.write(reg::CRA, 0x11);
+    let mut cycles = 0;
+    while !cpu_irq.borrow().is_low() {
+        cia.clock();
+        cycles += 1;
+    }
+    cycles
+}
+
+#[test]
+fn cia_6526a_irq_one_cycle_earlier() {
+    let old_cycles = cycles_to_irq(CiaModel::Mos6526);
+    let new_cycles = cycles_to_irq(CiaModel::Mos6526A);
+    assert_eq!(old_cycles, new_cycles + 1);
+}
+
+/// Cycles within `cycles` at which the ICR reports any of the `mask` flags. Reading
+/// the ICR clears it so each underflow is reported once.
+fn icr_flag_cycles(cia: &mut Cia, mask: u8, cycles: u32) -> Vec<u32> {
+    let mut result = Vec::new();
+    for cycle in 0..cycles {
+        cia.clock();
+        if cia.read(reg::ICR) & mask != 0 {
+            result.push(cycle);
+        }
+    }
+    result
+}
+
+fn intervals(cycles: &[u32]) -> Vec<u32> {
+    cycles.windows(2).map(|pair| pair[1] - pair[0]).collect()
+}
+
+#[test]
+fn timer_a_one_shot_expires_once() {
+    let mut cia = setup_cia();
+    cia.write(reg::TALO, 0x05);
+    cia.write(reg::TAHI, 0x00);
+    cia.write(reg::CRA, 0x19); // START | RUNMODE | LOAD
+    let underflows = icr_flag_cycles(&mut cia, 0x01, 100);
+    assert_eq!(1, underflows.len());
+    assert_eq!(0x00, cia.read(reg::CRA) & 0x01);
+    assert_eq!(0x05, cia.read(reg::TALO));
+    assert_eq!(0x00, cia.read(reg::TAHI));
+}
+
+#[test]
+fn timer_a_continuous_reloads_from_latch() {
+    let mut cia = setup_cia();
+    cia.write(reg::TALO, 0x09);
+    cia.write(reg::TAHI, 0x00);
+    cia.write(reg::CRA, 0x11); // START | LOAD
+    let underflows = icr_flag_cycles(&mut cia, 0x01, 100);
+    assert!(underflows.len() >= 8);
+    assert!(intervals(&underflows).iter().all(|interval| *interval == 10));
+    assert_eq!(0x01, cia.read(reg::CRA) & 0x01);
+}
+
+#[test]
+fn timer_b_counts_timer_a_underflows() {
+    let mut cia = setup_cia();
+    cia.write(reg::TALO, 0x03);
+    cia.write(reg::TAHI, 0x00);
+    cia.write(reg::TBLO, 0x02);
+    cia.write(reg::TBHI, 0x00);
+    cia.write(reg::CRB, 0x51); // START | LOAD | INMODE=TA
+    cia.write(reg::CRA, 0x11); // START | LOAD
+    let mut ta_underflows = Vec::new();
+    let mut tb_underflows = Vec::new();
+    for cycle in 0..100 {
+        cia.clock();
+        let icr = cia.read(reg::ICR);
+        if icr & 0x01 != 0 {
+            ta_underflows.push(cycle);
+        }
+        if icr & 0x02 != 0 {
+            tb_underflows.push(cycle);
+        }
+    }
+    assert!(intervals(&ta_underflows).iter().all(|interval| *interval == 4));
+    assert!(tb_underflows.len() >= 6);
+    assert!(intervals(&tb_underflows).iter().all(|interval| *interval == 12));
+}
+
+#[test]
+fn timer_interrupt_masked_by_icr() {
+    let cpu_irq = Rc::new(RefCell::new(IrqLine::new("irq")));
+    let mut cia = setup_cia_with_model(CiaModel::Mos6526, cpu_irq.clone());
+    cia.write(reg::TALO, 0x07);
+    cia.write(reg::TAHI, 0x00);
+    cia.write(reg::ICR, 0x7f);
+    cia.write(reg::CRA, 0x11); // START | LOAD
+    let mut flagged = false;
+    for _i in 0..20 {
+        cia.clock();
+        assert!(!cpu_irq.borrow().is_low());
+        if cia.read(reg::ICR) == 0x01 {
+            flagged = true;
+        }
+    }
+    assert!(flagged);
+    cia.write(reg::ICR, 0x81);
+    let mut cycles = 0;
+    while !cpu_irq.borrow().is_low() {
+        cia.clock();
+        cycles += 1;
+        assert!(cycles <= 12);
+    }
+    assert_eq!(0x81, cia.read(reg::ICR));
+    assert!(!cpu_irq.borrow().is_low());
+}
+
+#[test]
+fn serial_input_shifts_byte_on_cnt() {
+    let cpu_irq = Rc::new(RefCell::new(IrqLine::new("irq")));
+    let mut cia = setup_cia_with_model(CiaModel::Mos6526, cpu_irq.clone());
+    let cnt = cia.get_cnt_pin();
+    let sp = cia.get_sp_pin();
+    cia.write(reg::ICR, 0x88);
+    let value = 0xa5u8;
+    for bit in (0..8).rev() {
+        sp.borrow_mut().set_active(value & (1 << bit) != 0);
+        cnt.borrow_mut().set_active(false);
+        cia.clock();
+        cnt.borrow_mut().set_active(true);
+        cia.clock();
+        if bit > 0 {
+            assert!(!cpu_irq.borrow().is_low());
+        }
+    }
+    cia.clock();
+    assert!(cpu_irq.borrow().is_low());
+    assert_eq!(value, cia.read(reg::SDR));
+    assert_eq!(0x88, cia.read(reg::ICR));
+}
+
+#[test]
+fn serial_output_shifts_byte_at_half_timer_a_rate() {
+    let mut cia = setup_cia();
+    let cnt = cia.get_cnt_pin();
+    let sp = cia.get_sp_pin();
+    cia.write(reg::TALO, 0x03);
+    cia.write(reg::TAHI, 0x00);
+    cia.write(reg::CRA, 0x51); // START | LOAD | SPMODE
+    cia.write(reg::SDR, 0x5a);
+    let mut received = 0u8;
+    let mut bits = 0;
+    let mut cnt_last = true;
+    let mut sp_irq = false;
+    for _i in 0..100 {
+        cia.clock();
+        let cnt_high = cnt.borrow().is_high();
+        if cnt_high && !cnt_last {
+            received = (received << 1) | sp.borrow().is_high() as u8;
+            bits += 1;
+        }
+        cnt_last = cnt_high;
+        if cia.read(reg::ICR) & 0x08 != 0 {
+            sp_irq = true;
+            break;
+        }
+    }
+    assert_eq!(8, bits);
+    assert_eq!(0x5a, received);
+    assert!(sp_irq);
+}
+
+#[test]
+fn flag_interrupt_on_falling_edge() {
+    let cpu_irq = Rc::new(RefCell::new(IrqLine::new("irq")));
+    let cia_flag = Rc::new(RefCell::new(Pin::new_low()));
+    let mut cia = Cia::new(
+        Mode::Cia1,
+        CiaModel::Mos6526,
+        None,
+        None,
+        None,
+        Rc::new(RefCell::new(IoPort::new(0x00, 0xff))),
+        Rc::new(RefCell::new(IoPort::new(0x00, 0xff))),
+        None,
+        cia_flag.clone(),
+        cpu_irq.clone(),
+        Rc::new(EventBus::new(Rc::new(Clock::default()))),
+    );
+    cia.reset();
+    cia.write(reg::ICR, 0x90);
+    cia_flag.borrow_mut().set_active(true);
+    cia.clock();
+    cia.clock();
+    assert_eq!(0x00, cia.read(reg::ICR));
+    cia_flag.borrow_mut().set_active(false);
+    cia.clock();
+    cia.clock();
+    assert!(cpu_irq.borrow().is_low());
+    assert_eq!(0x90, cia.read(reg::ICR));
+    cia.clock();
+    cia.clock();
+    assert_eq!(0x00, cia.read(reg::ICR));
+}
@@ -0,0 +1,355 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use zinc64_core::cpu::Cpu6510;
+use zinc64_core::factory::{Addressable, Cpu, CpuState, Register, TickFn, TraceRecord};
+use zinc64_core::util::{IoPort, IrqLine, Pin, Ram};
+
+const C: u8 = 1;
+const Z: u8 = 1 << 1;
+const D: u8 = 1 << 3;
+const V: u8 = 1 << 6;
+const N: u8 = 1 << 7;
+
+struct MockMemory {
+    ram: Ram,
+}
+
+impl Addressable for MockMemory {
+    fn read(&self, address: u16) -> u8 {
+        self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.ram.write(address, value);
+    }
+}
+
+fn setup_cpu() -> Cpu6510 {
+    let ba_line = Rc::new(RefCell::new(Pin::new_high()));
+    let cpu_io_port = Rc::new(RefCell::new(IoPort::new(0x00, 0xff)));
+    let cpu_irq = Rc::new(RefCell::new(IrqLine::new("irq")));
+    let cpu_nmi = Rc::new(RefCell::new(IrqLine::new("nmi")));
+    let mem = Rc::new(RefCell::new(MockMemory {
+        ram: Ram::new(0x10000),
+    }));
+    Cpu6510::new(mem, cpu_io_port, ba_line, cpu_irq, cpu_nmi)
+}
+
+/// Execute a single instruction placed at $1000.
+fn run(cpu: &mut Cpu6510, program: &[u8]) {
+    let tick_fn: TickFn = Rc::new(|| {});
+    for (i, byte) in program.iter().enumerate() {
+        cpu.write_mem(0x1000 + i as u16, *byte);
+    }
+    cpu.set_pc(0x1000);
+    cpu.clock();
+    cpu.step(&tick_fn);
+}
+
+/// Execute a single instruction at $1000 with the specified accumulator and status
+/// register and return the resulting accumulator and status register.
+fn execute(cpu: &mut Cpu6510, program: &[u8], a: u8, p: u8) -> (u8, u8) {
+    cpu.set_register(Register::A, a);
+    cpu.set_register(Register::P, p);
+    run(cpu, program);
+    (cpu.get_register(Register::A), cpu.get_register(Register::P))
+}
+
+#[test]
+fn adc_decimal() {
+    // (a, operand, carry in, result, flags out)
+    let vectors: [(u8, u8, u8, u8, u8); 7] = [
+        (0x00, 0x00, 0, 0x00, Z),
+        (0x09, 0x01, 0, 0x10, 0),
+        (0x12, 0x34, 1, 0x47, 0),
+        (0x58, 0x46, 1, 0x05, C | N | V),
+        (0x99, 0x01, 0, 0x00, C | N),
+        (0x50, 0x50, 0, 0x00, C | N | V),
+        (0x81, 0x92, 0, 0x73, C | V),
+    ];
+    let mut cpu = setup_cpu();
+    for &(a, operand, carry, result, flags) in vectors.iter() {
+        let (a_out, p_out) = execute(&mut cpu, &[0x69, operand], a, D | carry);
+        assert_eq!(result, a_out, "{:02x} + {:02x} + {}", a, operand, carry);
+        assert_eq!(
+            flags,
+            p_out & (N | V | Z | C),
+            "{:02x} + {:02x} + {}",
+            a,
+            operand,
+            carry
+        );
+    }
+}
+
+#[test]
+fn sbc_decimal() {
+    // (a, operand, carry in, result, flags out)
+    let vectors: [(u8, u8, u8, u8, u8); 6] = [
+        (0x46, 0x12, 1, 0x34, C),
+        (0x40, 0x13, 1, 0x27, C),
+        (0x32, 0x02, 0, 0x29, C),
+        (0x00, 0x01, 1, 0x99, N),
+        (0x80, 0x01, 1, 0x79, C | V),
+        (0x21, 0x21, 1, 0x00, C | Z),
+    ];
+    let mut cpu = setup_cpu();
+    for &(a, operand, carry, result, flags) in vectors.iter() {
+        let (a_out, p_out) = execute(&mut cpu, &[0xe9, operand], a, D | carry);
+        assert_eq!(result, a_out, "{:02x} - {:02x} - {}", a, operand, 1 - carry);
+        assert_eq!(
+            flags,
+            p_out & (N | V | Z | C),
+            "{:02x} - {:02x} - {}",
+            a,
+            operand,
+            1 - carry
+        );
+    }
+}
+
+#[test]
+fn adc_sbc_binary() {
+    let mut cpu = setup_cpu();
+    assert_eq!((0x80, N | V), execute(&mut cpu, &[0x69, 0x01], 0x7f, 0));
+    assert_eq!((0x00, C | Z), execute(&mut cpu, &[0x69, 0x01], 0xff, 0));
+    assert_eq!((0x7f, C | V), execute(&mut cpu, &[0xe9, 0x01], 0x80, C));
+    assert_eq!((0xff, N), execute(&mut cpu, &[0xe9, 0x01], 0x00, C));
+}
+
+#[test]
+fn rmw_illegal_opcodes() {
+    // (opcode, a, x, memory, carry in, result a, result memory, flags out)
+    let vectors: [(u8, u8, u8, u8, u8, u8, u8, u8); 7] = [
+        (0x07, 0x02, 0x00, 0x81, 0, 0x02, 0x02, C),     // SLO
+        (0x27, 0x0f, 0x00, 0x81, 1, 0x03, 0x03, C),     // RLA
+        (0x47, 0xff, 0x00, 0x03, 0, 0xfe, 0x01, N | C), // SRE
+        (0x67, 0x10, 0x00, 0x02, 1, 0x91, 0x81, N),     // RRA
+        (0xc7, 0x10, 0x00, 0x11, 0, 0x10, 0x10, Z | C), // DCP
+        (0xe7, 0x20, 0x00, 0x0f, 1, 0x10, 0x10, C),     // ISC
+        (0x87, 0xf0, 0x3c, 0x00, 0, 0xf0, 0x30, 0),     // SAX
+    ];
+    let mut cpu = setup_cpu();
+    for &(opcode, a, x, memory, carry, result, result_memory, flags) in vectors.iter() {
+        cpu.write_mem(0x0080, memory);
+        cpu.set_register(Register::X, x);
+        let (a_out, p_out) = execute(&mut cpu, &[opcode, 0x80], a, carry);
+        assert_eq!(result, a_out, "opcode {:02x}", opcode);
+        assert_eq!(result_memory, cpu.read_mem(0x0080), "opcode {:02x}", opcode);
+        assert_eq!(flags, p_out & (N | V | Z | C), "opcode {:02x}", opcode);
+    }
+}
+
+#[test]
+fn rmw_illegal_addressing_modes() {
+    let mut cpu = setup_cpu();
+    cpu.set_register(Register::X, 0x04);
+    cpu.set_register(Register::Y, 0x01);
+    // DCP $20ff,Y crosses into the next page
+    cpu.write_mem(0x2100, 0x05);
+    run(&mut cpu, &[0xdb, 0xff, 0x20]);
+    assert_eq!(0x04, cpu.read_mem(0x2100));
+    // ISC $20ff,X
+    cpu.write_mem(0x2103, 0x05);
+    run(&mut cpu, &[0xff, 0xff, 0x20]);
+    assert_eq!(0x06, cpu.read_mem(0x2103));
+    // SLO ($40,X)
+    cpu.write_mem(0x0044, 0x00);
+    cpu.write_mem(0x0045, 0x30);
+    cpu.write_mem(0x3000, 0x21);
+    run(&mut cpu, &[0x03, 0x40]);
+    assert_eq!(0x42, cpu.read_mem(0x3000));
+    // SRE ($50),Y crosses into the next page
+    cpu.write_mem(0x0050, 0xff);
+    cpu.write_mem(0x0051, 0x30);
+    cpu.write_mem(0x3100, 0x42);
+    run(&mut cpu, &[0x53, 0x50]);
+    assert_eq!(0x21, cpu.read_mem(0x3100));
+    // SAX $60,Y
+    cpu.set_register(Register::A, 0x0f);
+    cpu.set_register(Register::X, 0x3c);
+    run(&mut cpu, &[0x97, 0x60]);
+    assert_eq!(0x0c, cpu.read_mem(0x0061));
+}
+
+#[test]
+fn immediate_illegal_opcodes() {
+    let mut cpu = setup_cpu();
+    // ANC
+    assert_eq!((0x80, N | C), execute(&mut cpu, &[0x0b, 0xff], 0x80, 0));
+    assert_eq!((0x00, Z), execute(&mut cpu, &[0x2b, 0x7f], 0x80, C));
+    // ALR
+    assert_eq!((0x01, C), execute(&mut cpu, &[0x4b, 0x03], 0xff, 0));
+    // ARR
+    assert_eq!((0x60, C), execute(&mut cpu, &[0x6b, 0xc0], 0xff, 0));
+    assert_eq!((0xc0, N | V | C), execute(&mut cpu, &[0x6b, 0x80], 0xff, C));
+    // SBX
+    cpu.set_register(Register::X, 0x3c);
+    assert_eq!((0xf0, C), execute(&mut cpu, &[0xcb, 0x10], 0xf0, 0));
+    assert_eq!(0x20, cpu.get_register(Register::X));
+    // SBC
+    assert_eq!((0x34, C), execute(&mut cpu, &[0xeb, 0x12], 0x46, C));
+}
+
+#[test]
+fn unstable_opcodes_use_magic_constant() {
+    let mut cpu = setup_cpu();
+    // LXA
+    assert_eq!((0x4a, 0), execute(&mut cpu, &[0xab, 0x5a], 0x00, 0));
+    assert_eq!(0x4a, cpu.get_register(Register::X));
+    cpu.set_magic_constant(0xff);
+    assert_eq!((0x5a, 0), execute(&mut cpu, &[0xab, 0x5a], 0x00, 0));
+    assert_eq!(0x5a, cpu.get_register(Register::X));
+    // ANE
+    cpu.set_register(Register::X, 0x0f);
+    assert_eq!((0x0c, 0), execute(&mut cpu, &[0x8b, 0x3c], 0x00, 0));
+    cpu.set_magic_constant(0x00);
+    assert_eq!((0x00, Z), execute(&mut cpu, &[0x8b, 0x3c], 0x00, 0));
+}
+
+#[test]
+fn unstable_stores_and_with_high_byte() {
+    let mut cpu = setup_cpu();
+    cpu.set_register(Register::A, 0xff);
+    cpu.set_register(Register::X, 0xf7);
+    cpu.set_register(Register::Y, 0x01);
+    // SHA $1200,Y
+    run(&mut cpu, &[0x9f, 0x00, 0x12]);
+    assert_eq!(0x13, cpu.read_mem(0x1201));
+    // SHX $1200,Y
+    run(&mut cpu, &[0x9e, 0x00, 0x12]);
+    assert_eq!(0x13, cpu.read_mem(0x1201));
+    // SHY $1200,X
+    cpu.set_register(Register::Y, 0x7e);
+    run(&mut cpu, &[0x9c, 0x00, 0x12]);
+    assert_eq!(0x12, cpu.read_mem(0x12f7));
+    // SHA ($50),Y
+    cpu.set_register(Register::Y, 0x02);
+    cpu.write_mem(0x0050, 0x00);
+    cpu.write_mem(0x0051, 0x34);
+    run(&mut cpu, &[0x93, 0x50]);
+    assert_eq!(0x35, cpu.read_mem(0x3402));
+    // TAS $1200,Y
+    cpu.set_register(Register::A, 0x3f);
+    run(&mut cpu, &[0x9b, 0x00, 0x12]);
+    assert_eq!(0x37, cpu.get_register(Register::SP));
+    assert_eq!(0x13, cpu.read_mem(0x1202));
+}
+
+#[test]
+fn unstable_stores_replace_high_byte_on_page_cross() {
+    let mut cpu = setup_cpu();
+    cpu.set_register(Register::A, 0xff);
+    cpu.set_register(Register::X, 0x0f);
+    cpu.set_register(Register::Y, 0x10);
+    // SHX $12f8,Y lands on $0308 instead of $1308
+    run(&mut cpu, &[0x9e, 0xf8, 0x12]);
+    assert_eq!(0x03, cpu.read_mem(0x0308));
+    assert_eq!(0x00, cpu.read_mem(0x1308));
+}
+
+#[test]
+fn las_ands_memory_with_stack_pointer() {
+    let mut cpu = setup_cpu();
+    cpu.set_register(Register::SP, 0xf0);
+    cpu.set_register(Register::Y, 0x01);
+    cpu.write_mem(0x2001, 0x9c);
+    assert_eq!((0x90, N), execute(&mut cpu, &[0xbb, 0x00, 0x20], 0x00, 0));
+    assert_eq!(0x90, cpu.get_register(Register::X));
+    assert_eq!(0x90, cpu.get_register(Register::SP));
+}
+
+#[test]
+fn lax_loads_a_and_x() {
+    let mut cpu = setup_cpu();
+    cpu.write_mem(0x2000, 0x99);
+    assert_eq!((0x99, N), execute(&mut cpu, &[0xaf, 0x00, 0x20], 0x00, 0));
+    assert_eq!(0x99, cpu.get_register(Register::X));
+}
+
+#[test]
+fn illegal_nops_skip_operands() {
+    let mut cpu = setup_cpu();
+    for &(opcode, length) in [
+        (0x1a, 1u16),
+        (0x80, 2),
+        (0x89, 2),
+        (0x04, 2),
+        (0x14, 2),
+        (0x0c, 3),
+        (0x1c, 3),
+    ]
+    .iter()
+    {
+        let (a, p) = execute(&mut cpu, &[opcode, 0x00, 0x20], 0x42, C);
+        assert_eq!((0x42, C), (a, p & (N | V | Z | C)), "opcode {:02x}", opcode);
+        assert_eq!(0x1000 + length, cpu.get_pc(), "opcode {:02x}", opcode);
+    }
+}
+
+#[test]
+fn jam_halts_cpu() {
+    let tick_fn: TickFn = Rc::new(|| {});
+    let mut cpu = setup_cpu();
+    assert_eq!(CpuState::Running, cpu.get_state());
+    run(&mut cpu, &[0xa9, 0x42, 0x02, 0xea]);
+    assert!(!cpu.is_jammed());
+    cpu.step(&tick_fn);
+    assert!(cpu.is_jammed());
+    assert_eq!(CpuState::Jammed, cpu.get_state());
+    for _i in 0..10 {
+        cpu.step(&tick_fn);
+        assert_eq!(0x1002, cpu.get_opcode_pc());
+    }
+    assert_eq!(0x42, cpu.get_register(Register::A));
+    cpu.reset();
+    assert!(!cpu.is_jammed());
+}
+
+#[test]
+fn trace_records_instructions() {
+    let tick_fn: TickFn = Rc::new(|| {});
+    let mut cpu = setup_cpu();
+    let records = Rc::new(RefCell::new(Vec::<TraceRecord>::new()));
+    let records_clone = records.clone();
+    cpu.set_trace(Some(Box::new(move |record: &TraceRecord| {
+        records_clone.borrow_mut().push(record.clone());
+    })));
+    run(&mut cpu, &[0xa9, 0x80, 0xaa, 0xe8, 0xd0, 0xfa]);
+    cpu.step(&tick_fn);
+    cpu.step(&tick_fn);
+    let records = records.borrow();
+    assert_eq!(4, records.len());
+    assert_eq!(0x1000, records[0].pc);
+    assert_eq!(vec![0xa9, 0x80], records[0].bytes);
+    assert_eq!("LDA #$80", records[0].disassembly);
+    assert_eq!(0x00, records[0].a);
+    assert_eq!(0x1002, records[1].pc);
+    assert_eq!("TAX", records[1].disassembly);
+    assert_eq!(0x80, records[1].a);
+    assert_eq!(N, records[1].p & N);
+    assert_eq!(2, records[1].cycles - records[0].cycles);
+    assert_eq!("INX", records[2].disassembly);
+    assert_eq!(0x80, records[2].x);
+    assert_eq!("BNE $1000", records[3].disassembly);
+    assert_eq!(0x81, records[3].x);
+    assert_eq!(N, records[3].p & (N | Z));
+}
+
+#[test]
+fn trace_disabled() {
+    let mut cpu = setup_cpu();
+    let records = Rc::new(RefCell::new(0));
+    let records_clone = records.clone();
+    cpu.set_trace(Some(Box::new(move |_record: &TraceRecord| {
+        *records_clone.borrow_mut() += 1;
+    })));
+    cpu.set_trace(None);
+    run(&mut cpu, &[0xa9, 0x01]);
+    assert_eq!(0, *records.borrow());
+}
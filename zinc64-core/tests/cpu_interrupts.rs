@@ -0,0 +1,156 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use zinc64_core::cpu::Cpu6510;
+use zinc64_core::factory::{Addressable, Cpu, Register, TickFn};
+use zinc64_core::util::{IoPort, IrqLine, Pin, Ram, Shared};
+
+struct MockMemory {
+    ram: Ram,
+}
+
+impl Addressable for MockMemory {
+    fn read(&self, address: u16) -> u8 {
+        self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.ram.write(address, value);
+    }
+}
+
+struct Setup {
+    cpu: Cpu6510,
+    irq_line: Shared<IrqLine>,
+    nmi_line: Shared<IrqLine>,
+    tick_fn: TickFn,
+}
+
+/// Setup cpu with NOPs at $1000, the IRQ handler at $2000 and the NMI handler at $3000.
+fn setup_cpu() -> Setup {
+    let ba_line = Rc::new(RefCell::new(Pin::new_high()));
+    let cpu_io_port = Rc::new(RefCell::new(IoPort::new(0x00, 0xff)));
+    let irq_line = Rc::new(RefCell::new(IrqLine::new("irq")));
+    let nmi_line = Rc::new(RefCell::new(IrqLine::new("nmi")));
+    let mem = Rc::new(RefCell::new(MockMemory {
+        ram: Ram::new(0x10000),
+    }));
+    let mut cpu = Cpu6510::new(mem, cpu_io_port, ba_line, irq_line.clone(), nmi_line.clone());
+    for i in 0..16 {
+        cpu.write_mem(0x1000 + i, 0xea);
+        cpu.write_mem(0x2000 + i, 0xea);
+        cpu.write_mem(0x3000 + i, 0xea);
+    }
+    cpu.write_mem(0xfffa, 0x00);
+    cpu.write_mem(0xfffb, 0x30);
+    cpu.write_mem(0xfffe, 0x00);
+    cpu.write_mem(0xffff, 0x20);
+    cpu.set_register(Register::SP, 0xff);
+    Setup {
+        cpu,
+        irq_line,
+        nmi_line,
+        tick_fn: Rc::new(|| {}),
+    }
+}
+
+fn return_address(cpu: &Cpu6510) -> u16 {
+    (u16::from(cpu.read_mem(0x01ff)) << 8) | u16::from(cpu.read_mem(0x01fe))
+}
+
+#[test]
+fn irq_delayed_after_cli() {
+    let mut setup = setup_cpu();
+    setup.cpu.write_mem(0x1000, 0x58); // CLI
+    setup.cpu.set_register(Register::P, 0x04);
+    setup.irq_line.borrow_mut().set_low(0, true);
+    setup.cpu.set_pc(0x1000);
+    setup.cpu.clock();
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x1001, setup.cpu.get_pc());
+    setup.cpu.step(&setup.tick_fn);
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x2000, setup.cpu.get_pc());
+    assert_eq!(0x1002, return_address(&setup.cpu));
+}
+
+#[test]
+fn irq_after_sei() {
+    let mut setup = setup_cpu();
+    setup.cpu.write_mem(0x1000, 0x78); // SEI
+    setup.cpu.set_register(Register::P, 0x00);
+    setup.cpu.set_pc(0x1000);
+    setup.cpu.clock();
+    setup.irq_line.borrow_mut().set_low(0, true);
+    setup.cpu.step(&setup.tick_fn);
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x2000, setup.cpu.get_pc());
+    assert_eq!(0x1001, return_address(&setup.cpu));
+}
+
+#[test]
+fn irq_is_level_sensitive() {
+    let mut setup = setup_cpu();
+    setup.cpu.write_mem(0x2000, 0x40); // RTI
+    setup.cpu.set_register(Register::P, 0x00);
+    setup.cpu.set_pc(0x1000);
+    setup.cpu.clock();
+    setup.irq_line.borrow_mut().set_low(0, true);
+    setup.cpu.step(&setup.tick_fn);
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x2000, setup.cpu.get_pc());
+    // RTI returns with the line still low and the IRQ is taken again
+    setup.cpu.step(&setup.tick_fn);
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x2000, setup.cpu.get_pc());
+    setup.irq_line.borrow_mut().set_low(0, false);
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x1001, setup.cpu.get_pc());
+}
+
+#[test]
+fn nmi_is_edge_triggered() {
+    let mut setup = setup_cpu();
+    setup.cpu.set_register(Register::P, 0x04);
+    setup.cpu.set_pc(0x1000);
+    setup.cpu.clock();
+    setup.nmi_line.borrow_mut().set_low(0, true);
+    setup.cpu.step(&setup.tick_fn);
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x3000, setup.cpu.get_pc());
+    for pc in 0x3001..0x3005 {
+        setup.cpu.step(&setup.tick_fn);
+        assert_eq!(pc, setup.cpu.get_pc());
+    }
+    setup.nmi_line.borrow_mut().set_low(0, false);
+    setup.cpu.step(&setup.tick_fn);
+    setup.nmi_line.borrow_mut().set_low(0, true);
+    setup.cpu.step(&setup.tick_fn);
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x3000, setup.cpu.get_pc());
+}
+
+#[test]
+fn nmi_hijacks_pending_irq() {
+    let mut setup = setup_cpu();
+    setup.cpu.set_register(Register::P, 0x00);
+    setup.cpu.set_pc(0x1000);
+    setup.cpu.clock();
+    setup.irq_line.borrow_mut().set_low(0, true);
+    setup.cpu.step(&setup.tick_fn);
+    // IRQ sequence started, NMI arrives while the return address is pushed
+    setup.cpu.clock();
+    setup.cpu.clock();
+    setup.nmi_line.borrow_mut().set_low(0, true);
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x3000, setup.cpu.get_pc());
+    assert_eq!(0x1001, return_address(&setup.cpu));
+    assert_eq!(0x00, setup.cpu.read_mem(0x01fd) & 0x10);
+    // NMI was consumed by the hijacked sequence
+    setup.cpu.step(&setup.tick_fn);
+    assert_eq!(0x3001, setup.cpu.get_pc());
+}
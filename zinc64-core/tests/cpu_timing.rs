@@ -6,7 +6,7 @@ use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 use zinc64_core::cpu::Cpu6510;
-use zinc64_core::factory::{Addressable, Cpu, TickFn};
+use zinc64_core::factory::{Addressable, Cpu, Register, TickFn};
 use zinc64_core::util::{IoPort, IrqLine, Pin, Ram};
 
 struct MockMemory {
@@ -44,75 +44,75 @@ const OPCODE_TIMING: [u8; 256] = [
     7, // 00 BRK #$ab
     6, // 01 ORA ($ab,X)
     0, // 02 HLT*
-    0, // 03 ASO* ($ab,X)
-    0, // 04 SKB* $ab
+    8, // 03 ASO* ($ab,X)
+    3, // 04 SKB* $ab
     3, // 05 ORA $ab
     5, // 06 ASL $ab
-    0, // 07 ASO* $ab
+    5, // 07 ASO* $ab
     3, // 08 PHP
     2, // 09 ORA #$ab
     2, // 0A ASL A
-    0, // 0B ANC* #$ab
-    0, // 0C SKW* $abcd
+    2, // 0B ANC* #$ab
+    4, // 0C SKW* $abcd
     4, // 0D ORA $abcd
     6, // 0E ASL $abcd
-    0, // 0F ASO* $abcd
+    6, // 0F ASO* $abcd
     3, // 10 BPL nearlabel
     5, // 11 ORA ($ab),Y
     0, // 12 HLT*
-    0, // 13 ASO* ($ab),Y
-    0, // 14 SKB* $ab,X
+    8, // 13 ASO* ($ab),Y
+    4, // 14 SKB* $ab,X
     4, // 15 ORA $ab,X
     6, // 16 ASL $ab,X
-    0, // 17 ASO* $ab,X
+    6, // 17 ASO* $ab,X
     2, // 18 CLC
     4, // 19 ORA $abcd,Y
-    0, // 1A NOP*
-    0, // 1B ASO* $abcd,Y
-    0, // 1C SKW* $abcd,X
+    2, // 1A NOP*
+    7, // 1B ASO* $abcd,Y
+    4, // 1C SKW* $abcd,X
     4, // 1D ORA $abcd,X
     7, // 1E ASL $abcd,X
-    0, // 1F ASO* $abcd,X
+    7, // 1F ASO* $abcd,X
     6, // 20 JSR $abcd
     6, // 21 AND ($ab,X)
     0, // 22 HLT*
-    0, // 23 RLA* ($ab,X)
+    8, // 23 RLA* ($ab,X)
     3, // 24 BIT $ab
     3, // 25 AND $ab
     5, // 26 ROL $ab
-    0, // 27 RLA* $ab
+    5, // 27 RLA* $ab
     4, // 28 PLP
     2, // 29 AND #$ab
     2, // 2A ROL A
-    0, // 2B ANC* #$ab
+    2, // 2B ANC* #$ab
     4, // 2C BIT $abcd
     4, // 2D AND $abcd
     6, // 2E ROL $abcd
-    0, // 2F RLA* $abcd
+    6, // 2F RLA* $abcd
     2, // 30 BMI nearlabel
     5, // 31 AND ($ab),Y
     0, // 32 HLT*
-    0, // 33 RLA* ($ab),Y
-    0, // 34 SKB* $ab,X
+    8, // 33 RLA* ($ab),Y
+    4, // 34 SKB* $ab,X
     4, // 35 AND $ab,X
     6, // 36 ROL $ab,X
-    0, // 37 RLA* $ab,X
+    6, // 37 RLA* $ab,X
     2, // 38 SEC
     4, // 39 AND $abcd,Y
-    0, // 3A NOP*
-    0, // 3B RLA* $abcd,Y
-    0, // 3C SKW* $abcd,X
+    2, // 3A NOP*
+    7, // 3B RLA* $abcd,Y
+    4, // 3C SKW* $abcd,X
     4, // 3D AND $abcd,X
     7, // 3E ROL $abcd,X
-    0, // 3F RLA* $abcd,X
+    7, // 3F RLA* $abcd,X
     6, // 40 RTI
     6, // 41 EOR ($ab,X)
     0, // 42 HLT*
-    0, // FIXME 8, // 43 LSE* ($ab,X)
-    0, // 44 SKB* $ab
+    8, // 43 LSE* ($ab,X)
+    3, // 44 SKB* $ab
     3, // 45 EOR $ab
     5, // 46 LSR $ab
-    0, // FIXME 5, // 47 LSE* $ab
+    5, // 47 LSE* $ab
     3, // 48 PHA
     2, // 49 EOR #$ab
     2, // 4A LSR A
@@ -120,87 +120,87 @@ const OPCODE_TIMING: [u8; 256] = [
     3, // 4C JMP $abcd
     4, // 4D EOR $abcd
     6, // 4E LSR $abcd
-    0, // FIXME 6, // 4F LSE* $abcd
+    6, // 4F LSE* $abcd
     3, // 50 BVC nearlabel
     5, // 51 EOR ($ab),Y
     0, // 52 HLT*
-    0, // FIXME 8, // 53 LSE* ($ab),Y
-    0, // 54 SKB* $ab,X
+    8, // 53 LSE* ($ab),Y
+    4, // 54 SKB* $ab,X
     4, // 55 EOR $ab,X
     6, // 56 LSR $ab,X
-    0, // FIXME 6, // 57 LSE* $ab,X
+    6, // 57 LSE* $ab,X
     2, // 58 CLI
     4, // 59 EOR $abcd,Y
-    0, // 5A NOP*
-    0, // FIXME 7, // 5B LSE* $abcd,Y
-    0, // 5C SKW* $abcd,X
+    2, // 5A NOP*
+    7, // 5B LSE* $abcd,Y
+    4, // 5C SKW* $abcd,X
     4, // 5D EOR $abcd,X
     7, // 5E LSR $abcd,X
-    0, // FIXME 7, // 5F LSE* $abcd,X
+    7, // 5F LSE* $abcd,X
     6, // 60 RTS
     6, // 61 ADC ($ab,X)
     0, // 62 HLT*
-    0, // 63 RRA* ($ab,X)
-    0, // 64 SKB* $ab
+    8, // 63 RRA* ($ab,X)
+    3, // 64 SKB* $ab
     3, // 65 ADC $ab
     5, // 66 ROR $ab
-    0, // 67 RRA* $ab
+    5, // 67 RRA* $ab
     4, // 68 PLA
     2, // 69 ADC #$ab
     2, // 6A ROR A
-    0, // 6B ARR* #$ab
+    2, // 6B ARR* #$ab
     5, // 6C JMP ($abcd)
     4, // 6D ADC $abcd
     6, // 6E ROR $abcd
-    0, // 6F RRA* $abcd
+    6, // 6F RRA* $abcd
     2, // 70 BVS nearlabel
     5, // 71 ADC ($ab),Y
     0, // 72 HLT*
-    0, // 73 RRA* ($ab),Y
-    0, // 74 SKB* $ab,X
+    8, // 73 RRA* ($ab),Y
+    4, // 74 SKB* $ab,X
     4, // 75 ADC $ab,X
     6, // 76 ROR $ab,X
-    0, // 77 RRA* $ab,X
+    6, // 77 RRA* $ab,X
     2, // 78 SEI
     4, // 79 ADC $abcd,Y
-    0, // 7A NOP*
-    0, // 7B RRA* $abcd,Y
-    0, // 7C SKW* $abcd,X
+    2, // 7A NOP*
+    7, // 7B RRA* $abcd,Y
+    4, // 7C SKW* $abcd,X
     4, // 7D ADC $abcd,X
     7, // 7E ROR $abcd,X
-    0, // 7F RRA* $abcd,X
-    0, // FIXME 3, // FIXME 80 SKB* #$ab
+    7, // 7F RRA* $abcd,X
+    2, // 80 SKB* #$ab
     6, // 81 STA ($ab,X)
-    0, // 82 SKB* #$ab
-    0, // 83 SAX* ($ab,X)
+    2, // 82 SKB* #$ab
+    6, // 83 SAX* ($ab,X)
     3, // 84 STY $ab
     3, // 85 STA $ab
     3, // 86 STX $ab
-    0, // 87 SAX* $ab
+    3, // 87 SAX* $ab
     2, // 88 DEY
-    0, // 89 SKB* #$ab
+    2, // 89 SKB* #$ab
     2, // 8A TXA
     2, // 8B ANE* #$ab
     4, // 8C STY $abcd
     4, // 8D STA $abcd
     4, // 8E STX $abcd
-    0, // 8F SAX* $abcd
+    4, // 8F SAX* $abcd
     3, // 90 BCC nearlabel
     6, // 91 STA ($ab),Y
     0, // 92 HLT*
-    0, // 93 SHA* ($ab),Y
+    6, // 93 SHA* ($ab),Y
     4, // 94 STY $ab,X
     4, // 95 STA $ab,X
     4, // 96 STX $ab,Y
-    0, // 97 SAX* $ab,Y
+    4, // 97 SAX* $ab,Y
     2, // 98 TYA
     5, // 99 STA $abcd,Y
     2, // 9A TXS
-    0, // 9B SHS* $abcd,Y
-    0, // 9C SHY* $abcd,X
+    5, // 9B SHS* $abcd,Y
+    5, // 9C SHY* $abcd,X
     5, // 9D STA $abcd,X
-    0, // 9E SHX* $abcd,Y
-    0, // 9F SHA* $abcd,Y
+    5, // 9E SHX* $abcd,Y
+    5, // 9F SHA* $abcd,Y
     2, // A0 LDY #$ab
     6, // A1 LDA ($ab,X)
     2, // A2 LDX #$ab
@@ -228,19 +228,19 @@ const OPCODE_TIMING: [u8; 256] = [
     2, // B8 CLV
     4, // B9 LDA $abcd,Y
     2, // BA TSX
-    0, // BB LAS* $abcd,Y
+    4, // BB LAS* $abcd,Y
     4, // BC LDY $abcd,X
     4, // BD LDA $abcd,X
     4, // BE LDX $abcd,Y
     4, // BF LAX* $abcd,Y
     2, // C0 CPY #$ab
     6, // C1 CMP ($ab,X)
-    0, // C2 SKB* #$ab
-    0, // C3 DCM* ($ab,X)
+    2, // C2 SKB* #$ab
+    8, // C3 DCM* ($ab,X)
     3, // C4 CPY $ab
     3, // C5 CMP $ab
     5, // C6 DEC $ab
-    0, // C7 DCM* $ab
+    5, // C7 DCM* $ab
     2, // C8 INY
     2, // C9 CMP #$ab
     2, // CA DEX
@@ -248,55 +248,55 @@ const OPCODE_TIMING: [u8; 256] = [
     4, // CC CPY $abcd
     4, // CD CMP $abcd
     6, // CE DEC $abcd
-    0, // CF DCM* $abcd
+    6, // CF DCM* $abcd
     3, // D0 BNE nearlabel
     5, // D1 CMP ($ab),Y
     0, // D2 HLT*
-    0, // D3 DCM* ($ab),Y
-    0, // D4 SKB* $ab,X
+    8, // D3 DCM* ($ab),Y
+    4, // D4 SKB* $ab,X
     4, // D5 CMP $ab,X
     6, // D6 DEC $ab,X
-    0, // D7 DCM* $ab,X
+    6, // D7 DCM* $ab,X
     2, // D8 CLD
     4, // D9 CMP $abcd,Y
-    0, // DA NOP*
-    0, // DB DCM* $abcd,Y
-    0, // DC SKW* $abcd,X
+    2, // DA NOP*
+    7, // DB DCM* $abcd,Y
+    4, // DC SKW* $abcd,X
     4, // DD CMP $abcd,X
     7, // DE DEC $abcd,X
-    0, // DF DCM* $abcd,X
+    7, // DF DCM* $abcd,X
     2, // E0 CPX #$ab
     6, // E1 SBC ($ab,X)
-    0, // E2 SKB* #$ab
-    0, // E3 INS* ($ab,X)
+    2, // E2 SKB* #$ab
+    8, // E3 INS* ($ab,X)
     3, // E4 CPX $ab
     3, // E5 SBC $ab
     5, // E6 INC $ab
-    0, // E7 INS* $ab
+    5, // E7 INS* $ab
     2, // E8 INX
     2, // E9 SBC #$ab
     2, // EA NOP
-    0, // EB SBC* #$ab
+    2, // EB SBC* #$ab
     4, // EC CPX $abcd
     4, // ED SBC $abcd
     6, // EE INC $abcd
-    0, // EF INS* $abcd
+    6, // EF INS* $abcd
     2, // F0 BEQ nearlabel
     5, // F1 SBC ($ab),Y
     0, // F2 HLT*
-    0, // F3 INS* ($ab),Y
-    0, // F4 SKB* $ab,X
+    8, // F3 INS* ($ab),Y
+    4, // F4 SKB* $ab,X
     4, // F5 SBC $ab,X
     6, // F6 INC $ab,X
-    0, // F7 INS* $ab,X
+    6, // F7 INS* $ab,X
     2, // F8 SED
     4, // F9 SBC $abcd,Y
-    0, // FA NOP*
-    0, // FB INS* $abcd,Y
-    0, // FC SKW* $abcd,X
+    2, // FA NOP*
+    7, // FB INS* $abcd,Y
+    4, // FC SKW* $abcd,X
     4, // FD SBC $abcd,X
     7, // FE INC $abcd,X
-    0, // FF INS* $abcd,X
+    7, // FF INS* $abcd,X
 ];
 
 #[test]
@@ -344,3 +344,53 @@ fn opcode_timing() {
         }
     }
 }
+
+fn step_cycles(cpu: &mut Cpu6510, program: &[u8]) -> Vec<(u16, u8)> {
+    let tick_fn: TickFn = Rc::new(|| {});
+    for (i, byte) in program.iter().enumerate() {
+        cpu.write_mem(0x1000 + i as u16, *byte);
+    }
+    cpu.set_pc(0x1000);
+    cpu.clock();
+    let mut trace = Vec::new();
+    loop {
+        let is_last_cycle = cpu.is_last_cycle();
+        cpu.step_cycle(&tick_fn);
+        trace.push((cpu.get_opcode_pc(), cpu.remaining_cycles()));
+        if is_last_cycle {
+            break;
+        }
+    }
+    trace
+}
+
+#[test]
+fn step_cycle_page_cross() {
+    let mut cpu = setup_cpu();
+    cpu.write_mem(0x2100, 0x42);
+    cpu.set_register(Register::X, 0x01);
+    // LDA $20ff,X
+    let trace = step_cycles(&mut cpu, &[0xbd, 0xff, 0x20, 0xea]);
+    assert_eq!(5, trace.len());
+    for &(pc, _) in trace[..4].iter() {
+        assert_eq!(0x1000, pc);
+    }
+    assert_eq!(0x1003, trace[4].0);
+    assert_eq!(0x42, cpu.get_register(Register::A));
+}
+
+#[test]
+fn step_cycle_no_page_cross() {
+    let mut cpu = setup_cpu();
+    cpu.write_mem(0x2001, 0x42);
+    cpu.set_register(Register::X, 0x01);
+    // LDA $2000,X
+    let trace = step_cycles(&mut cpu, &[0xbd, 0x00, 0x20, 0xea]);
+    assert_eq!(4, trace.len());
+    for &(pc, _) in trace[..3].iter() {
+        assert_eq!(0x1000, pc);
+    }
+    assert_eq!(1, trace[2].1);
+    assert_eq!(0x1003, trace[3].0);
+    assert_eq!(0x42, cpu.get_register(Register::A));
+}
@@ -0,0 +1,196 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#![cfg(feature = "drive")]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use zinc64_core::drive::{Drive1541, GcrDisk};
+use zinc64_core::io::IecBus;
+
+// Reads a block the way the DOS does: wait for SYNC, then take each byte from VIA 2
+// port A once BYTE READY sets the overflow flag. The header block following the first
+// sync goes to $0300, the data block following the next sync to $0400. Before reading,
+// the control values listed at $C0F0 are written to VIA 2 port B to start the motor
+// and step the head.
+static READ_BLOCK_CODE: [u8; 0x58] = [
+    0x78, // SEI
+    0xa9, 0xee, // LDA #$EE
+    0x8d, 0x0c, 0x1c, // STA $1C0C
+    0xa9, 0x6f, // LDA #$6F
+    0x8d, 0x02, 0x1c, // STA $1C02
+    0xa9, 0x00, // LDA #$00
+    0x8d, 0x03, 0x1c, // STA $1C03
+    0xa2, 0x00, // LDX #$00
+    0xbd, 0xf0, 0xc0, // control: LDA $C0F0,X
+    0xf0, 0x06, // BEQ wait_header
+    0x8d, 0x00, 0x1c, // STA $1C00
+    0xe8, // INX
+    0xd0, 0xf5, // BNE control
+    0x2c, 0x00, 0x1c, // wait_header: BIT $1C00
+    0x30, 0xfb, // BMI wait_header
+    0xb8, // CLV
+    0xa0, 0x00, // LDY #$00
+    0x50, 0xfe, // header: BVC header
+    0xb8, // CLV
+    0xad, 0x01, 0x1c, // LDA $1C01
+    0x99, 0x00, 0x03, // STA $0300,Y
+    0xc8, // INY
+    0xc0, 0x0a, // CPY #$0A
+    0xd0, 0xf2, // BNE header
+    0x2c, 0x00, 0x1c, // wait_data: BIT $1C00
+    0x30, 0xfb, // BMI wait_data
+    0xb8, // CLV
+    0xa0, 0x00, // LDY #$00
+    0x50, 0xfe, // data_1: BVC data_1
+    0xb8, // CLV
+    0xad, 0x01, 0x1c, // LDA $1C01
+    0x99, 0x00, 0x04, // STA $0400,Y
+    0xc8, // INY
+    0xd0, 0xf4, // BNE data_1
+    0x50, 0xfe, // data_2: BVC data_2
+    0xb8, // CLV
+    0xad, 0x01, 0x1c, // LDA $1C01
+    0x99, 0x00, 0x05, // STA $0500,Y
+    0xc8, // INY
+    0xc0, 0x45, // CPY #$45
+    0xd0, 0xf2, // BNE data_2
+    0x4c, 0x55, 0xc0, // done: JMP done
+];
+
+const DONE: u16 = 0xc055;
+const WAIT_HEADER: u16 = 0xc01d;
+
+static GCR_DECODE: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x08, 0x00, 0x01, 0xff, 0x0c, 0x04,
+    0x05, 0xff, 0xff, 0x02, 0x03, 0xff, 0x0f, 0x06, 0x07, 0xff, 0x09, 0x0a, 0x0b, 0xff, 0x0d,
+    0x0e, 0xff,
+];
+
+fn sectors_per_track(track: u8) -> usize {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+fn sector_offset(track: u8, sector: u8) -> usize {
+    let blocks: usize = (1..track).map(sectors_per_track).sum();
+    (blocks + sector as usize) * 256
+}
+
+fn build_d64() -> Vec<u8> {
+    let mut data = vec![0u8; 174_848];
+    for track in 1..=35u8 {
+        for sector in 0..sectors_per_track(track) as u8 {
+            let offset = sector_offset(track, sector);
+            for i in 0..256 {
+                data[offset + i] = (i as u8) ^ track.wrapping_mul(16) ^ sector;
+            }
+        }
+    }
+    let bam = sector_offset(18, 0);
+    data[bam + 0xa2] = b'Z';
+    data[bam + 0xa3] = b'6';
+    data
+}
+
+fn decode_gcr(encoded: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    for group in encoded.chunks(5) {
+        let bits = group.iter().fold(0u64, |bits, value| (bits << 8) | *value as u64);
+        for i in (0..8).rev() {
+            let code = GCR_DECODE[((bits >> (i * 5)) & 0x1f) as usize];
+            assert_ne!(0xff, code, "invalid gcr code");
+            if i % 2 == 1 {
+                decoded.push(code << 4);
+            } else {
+                *decoded.last_mut().unwrap() |= code;
+            }
+        }
+    }
+    decoded
+}
+
+fn setup_drive(control: &[u8]) -> Drive1541 {
+    let mut rom = vec![0xeau8; 0x4000];
+    rom[..READ_BLOCK_CODE.len()].copy_from_slice(&READ_BLOCK_CODE);
+    rom[0xf0..0xf0 + control.len()].copy_from_slice(control);
+    rom[0xf0 + control.len()] = 0x00;
+    rom[0x3ffc] = 0x00;
+    rom[0x3ffd] = 0xc0;
+    let iec_bus = Rc::new(RefCell::new(IecBus::new()));
+    let mut drive = Drive1541::new(8, rom, iec_bus);
+    drive.reset();
+    drive
+}
+
+fn run_until(drive: &mut Drive1541, pc: u16, max_cycles: usize) {
+    let mut cycles = 0;
+    while drive.get_cpu().get_opcode_pc() != pc {
+        drive.clock();
+        cycles += 1;
+        assert!(cycles < max_cycles, "pc 0x{:04x}", drive.get_cpu().get_pc());
+    }
+}
+
+fn read_blocks(drive: &Drive1541) -> (Vec<u8>, Vec<u8>) {
+    let header: Vec<u8> = (0x0300..0x030a).map(|address| drive.peek(address)).collect();
+    let data: Vec<u8> = (0x0400..0x0545).map(|address| drive.peek(address)).collect();
+    (decode_gcr(&header), decode_gcr(&data))
+}
+
+fn assert_sector(header: &[u8], data: &[u8], d64: &[u8], track: u8) {
+    let sector = header[2];
+    assert_eq!(0x08, header[0]);
+    assert_eq!(sector ^ track ^ b'6' ^ b'Z', header[1]);
+    assert_eq!(track, header[3]);
+    assert_eq!(&[b'6', b'Z', 0x0f, 0x0f], &header[4..]);
+    let offset = sector_offset(track, sector);
+    let expected = &d64[offset..offset + 256];
+    assert_eq!(0x07, data[0]);
+    assert_eq!(expected, &data[1..257]);
+    assert_eq!(expected.iter().fold(0, |sum, value| sum ^ value), data[257]);
+}
+
+#[test]
+fn read_sector_from_d64() {
+    let d64 = build_d64();
+    // Motor and LED on, density 2, stepper phase matching track 18
+    let mut drive = setup_drive(&[0x4e]);
+    drive.attach_disk(GcrDisk::from_d64(&d64).unwrap());
+    assert!(drive.has_disk());
+    run_until(&mut drive, DONE, 100_000);
+    let (header, data) = read_blocks(&drive);
+    assert_sector(&header, &data, &d64, 18);
+    assert_eq!(0, header[2]);
+}
+
+#[test]
+fn step_head_to_next_track() {
+    let d64 = build_d64();
+    // Two half steps out to track 17 and switch to density 3
+    let mut drive = setup_drive(&[0x4e, 0x6d, 0x6c]);
+    drive.attach_disk(GcrDisk::from_d64(&d64).unwrap());
+    run_until(&mut drive, DONE, 100_000);
+    assert_eq!(32, drive.get_half_track());
+    let (header, data) = read_blocks(&drive);
+    assert_sector(&header, &data, &d64, 17);
+}
+
+#[test]
+fn no_sync_without_disk() {
+    let mut drive = setup_drive(&[0x4e]);
+    assert!(!drive.has_disk());
+    for _ in 0..10_000 {
+        drive.clock();
+    }
+    let pc = drive.get_cpu().get_pc();
+    assert!((WAIT_HEADER..WAIT_HEADER + 5).contains(&pc), "pc 0x{:04x}", pc);
+    // SYNC inactive and write protection on
+    assert_eq!(0x80, drive.peek(0x1c00) & 0x90);
+}
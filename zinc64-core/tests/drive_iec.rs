@@ -0,0 +1,100 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#![cfg(feature = "drive")]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use zinc64_core::drive::Drive1541;
+use zinc64_core::io::{IecBus, IecLine};
+use zinc64_core::util::Shared;
+
+// Minimal DOS that answers ATN the way the stock ROM does: once ATN is seen it takes
+// over DATA from the hardware acknowledge and releases it when the host pulls CLK.
+static DOS_CODE: [u8; 0x31] = [
+    0x78, // SEI
+    0xa2, 0xff, // LDX #$FF
+    0x9a, // TXS
+    0xa9, 0x1a, // LDA #$1A
+    0x8d, 0x02, 0x18, // STA $1802
+    0xa9, 0x00, // LDA #$00
+    0x8d, 0x00, 0x18, // STA $1800
+    0xad, 0x00, 0x18, // wait_atn: LDA $1800
+    0x10, 0xfb, // BPL wait_atn
+    0xa9, 0x12, // LDA #$12
+    0x8d, 0x00, 0x18, // STA $1800
+    0xad, 0x00, 0x18, // wait_clk: LDA $1800
+    0x29, 0x04, // AND #$04
+    0xf0, 0xf9, // BEQ wait_clk
+    0xa9, 0x10, // LDA #$10
+    0x8d, 0x00, 0x18, // STA $1800
+    0xad, 0x00, 0x18, // wait_release: LDA $1800
+    0x30, 0xfb, // BMI wait_release
+    0xa9, 0x00, // LDA #$00
+    0x8d, 0x00, 0x18, // STA $1800
+    0x4c, 0x0e, 0xc0, // JMP wait_atn
+];
+
+fn setup_drive() -> (Drive1541, Shared<IecBus>) {
+    let mut rom = vec![0xeau8; 0x4000];
+    rom[..DOS_CODE.len()].copy_from_slice(&DOS_CODE);
+    rom[0x3ffc] = 0x00;
+    rom[0x3ffd] = 0xc0;
+    let iec_bus = Rc::new(RefCell::new(IecBus::new()));
+    let mut drive = Drive1541::new(8, rom, iec_bus.clone());
+    drive.reset();
+    (drive, iec_bus)
+}
+
+fn run(drive: &mut Drive1541, cycles: usize) {
+    for _ in 0..cycles {
+        drive.clock();
+    }
+}
+
+#[test]
+fn boot_drive_rom() {
+    let (mut drive, iec_bus) = setup_drive();
+    run(&mut drive, 100);
+    let pc = drive.get_cpu().get_pc();
+    assert!((0xc00e..=0xc013).contains(&pc), "pc 0x{:x}", pc);
+    assert_eq!(0x1a, drive.peek(0x1802));
+    assert!(iec_bus.borrow().is_high(IecLine::Data));
+    assert!(iec_bus.borrow().is_high(IecLine::Clk));
+}
+
+#[test]
+fn atn_handshake() {
+    let (mut drive, iec_bus) = setup_drive();
+    run(&mut drive, 100);
+    // Host asserts ATN, the hardware acknowledge pulls DATA low right away
+    iec_bus.borrow_mut().set_low(IecBus::HOST, IecLine::Atn, true);
+    run(&mut drive, 1);
+    assert!(iec_bus.borrow().is_low(IecLine::Data));
+    run(&mut drive, 100);
+    assert!(iec_bus.borrow().is_low(IecLine::Data));
+    // Host pulls CLK to signal it is ready to talk, DOS releases DATA
+    iec_bus.borrow_mut().set_low(IecBus::HOST, IecLine::Clk, true);
+    run(&mut drive, 100);
+    assert!(iec_bus.borrow().is_high(IecLine::Data));
+    // Host releases the bus, DOS drops ATNA and goes back to waiting
+    iec_bus.borrow_mut().set_low(IecBus::HOST, IecLine::Atn, false);
+    iec_bus.borrow_mut().set_low(IecBus::HOST, IecLine::Clk, false);
+    run(&mut drive, 100);
+    assert!(iec_bus.borrow().is_high(IecLine::Data));
+    assert_eq!(0x00, drive.peek(0x1800) & 0x1a);
+}
+
+#[test]
+fn device_address_jumpers() {
+    let (mut drive, _) = setup_drive();
+    run(&mut drive, 100);
+    assert_eq!(0x00, drive.peek(0x1800) & 0x60);
+    let iec_bus = Rc::new(RefCell::new(IecBus::new()));
+    let mut drive = Drive1541::new(9, vec![0xea; 0x4000], iec_bus);
+    drive.reset();
+    run(&mut drive, 1);
+    assert_eq!(0x20, drive.peek(0x1800) & 0x60);
+}
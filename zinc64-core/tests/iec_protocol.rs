@@ -0,0 +1,271 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use zinc64_core::factory::IecDevice;
+use zinc64_core::io::{IecBus, IecCommand, IecLine, IecProtocol};
+use zinc64_core::util::Shared;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Open(u8),
+    Close(u8),
+    Write(u8, u8, bool),
+    Read(u8),
+}
+
+struct TestDevice {
+    events: Shared<Vec<Event>>,
+    output: Vec<u8>,
+}
+
+impl IecDevice for TestDevice {
+    fn open(&mut self, channel: u8) {
+        self.events.borrow_mut().push(Event::Open(channel));
+    }
+
+    fn close(&mut self, channel: u8) {
+        self.events.borrow_mut().push(Event::Close(channel));
+    }
+
+    fn write(&mut self, channel: u8, value: u8, eoi: bool) {
+        self.events
+            .borrow_mut()
+            .push(Event::Write(channel, value, eoi));
+    }
+
+    fn read(&mut self, channel: u8) -> Option<(u8, bool)> {
+        self.events.borrow_mut().push(Event::Read(channel));
+        if self.output.is_empty() {
+            None
+        } else {
+            let value = self.output.remove(0);
+            Some((value, self.output.is_empty()))
+        }
+    }
+
+    fn reset(&mut self) {}
+}
+
+// Host side of the serial handshake as done by the kernal, driven one cycle at a time.
+struct Host {
+    iec_bus: Shared<IecBus>,
+    protocol: IecProtocol,
+}
+
+impl Host {
+    fn is_low(&self, line: IecLine) -> bool {
+        self.iec_bus.borrow().is_low(line)
+    }
+
+    fn set_low(&mut self, line: IecLine, low: bool) {
+        self.iec_bus.borrow_mut().set_low(IecBus::HOST, line, low);
+    }
+
+    fn run(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.protocol.clock();
+        }
+    }
+
+    fn wait_for(&mut self, line: IecLine, low: bool) -> usize {
+        for cycles in 0..1000 {
+            if self.is_low(line) == low {
+                return cycles;
+            }
+            self.protocol.clock();
+        }
+        panic!("timeout waiting for {:?} low={}", line, low);
+    }
+
+    fn attention(&mut self) {
+        self.set_low(IecLine::Atn, true);
+        self.set_low(IecLine::Clk, true);
+        self.set_low(IecLine::Data, false);
+        self.run(1);
+    }
+
+    fn send_byte(&mut self, value: u8, eoi: bool) {
+        // Ready to send, listener answers with ready for data
+        self.set_low(IecLine::Clk, false);
+        self.wait_for(IecLine::Data, false);
+        if eoi {
+            let eoi_wait = self.wait_for(IecLine::Data, true);
+            assert!((195..=205).contains(&eoi_wait), "eoi wait {}", eoi_wait);
+            let eoi_ack = self.wait_for(IecLine::Data, false);
+            assert!((55..=65).contains(&eoi_ack), "eoi ack {}", eoi_ack);
+        }
+        self.set_low(IecLine::Clk, true);
+        for i in 0..8 {
+            self.set_low(IecLine::Data, (value >> i) & 0x01 == 0);
+            self.run(20);
+            self.set_low(IecLine::Clk, false);
+            self.run(20);
+            self.set_low(IecLine::Clk, true);
+            self.set_low(IecLine::Data, false);
+        }
+        // Frame handshake
+        let frame_ack = self.wait_for(IecLine::Data, true);
+        assert!(frame_ack < 10, "frame ack {}", frame_ack);
+    }
+
+    fn receive_byte(&mut self) -> (u8, bool) {
+        // Talker is ready to send, signal ready for data
+        self.wait_for(IecLine::Clk, false);
+        self.set_low(IecLine::Data, false);
+        let mut eoi = false;
+        let mut cycles = 0;
+        while !self.is_low(IecLine::Clk) {
+            if cycles == 200 {
+                eoi = true;
+                self.set_low(IecLine::Data, true);
+                self.run(60);
+                self.set_low(IecLine::Data, false);
+            }
+            self.run(1);
+            cycles += 1;
+            assert!(cycles < 1000, "timeout waiting for talker");
+        }
+        assert!(eoi || cycles >= 40, "talker response {}", cycles);
+        let mut value = 0u8;
+        for i in 0..8 {
+            self.wait_for(IecLine::Clk, false);
+            if !self.is_low(IecLine::Data) {
+                value |= 1 << i;
+            }
+            self.wait_for(IecLine::Clk, true);
+        }
+        // Frame handshake
+        self.set_low(IecLine::Data, true);
+        (value, eoi)
+    }
+}
+
+fn setup_host(number: usize, output: &[u8]) -> (Host, Shared<Vec<Event>>) {
+    let iec_bus = Rc::new(RefCell::new(IecBus::new()));
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut protocol = IecProtocol::new(iec_bus.clone());
+    let device = TestDevice {
+        events: events.clone(),
+        output: output.to_vec(),
+    };
+    protocol.attach(number, Box::new(device)).unwrap();
+    (Host { iec_bus, protocol }, events)
+}
+
+#[test]
+fn attach_device_number() {
+    let iec_bus = Rc::new(RefCell::new(IecBus::new()));
+    let mut protocol = IecProtocol::new(iec_bus);
+    let new_device = || {
+        Box::new(TestDevice {
+            events: Rc::new(RefCell::new(Vec::new())),
+            output: Vec::new(),
+        })
+    };
+    assert!(protocol.attach(3, new_device()).is_err());
+    assert!(protocol.attach(12, new_device()).is_err());
+    assert!(protocol.attach(4, new_device()).is_ok());
+    assert!(protocol.attach(4, new_device()).is_err());
+    assert!(protocol.attach(8, new_device()).is_ok());
+    protocol.detach(4);
+    assert!(!protocol.has_device(4));
+    assert!(protocol.has_device(8));
+}
+
+#[test]
+fn listen_open_byte() {
+    let (mut host, events) = setup_host(4, &[]);
+    host.run(10);
+    assert!(!host.is_low(IecLine::Data));
+    // Device acknowledges ATN by pulling DATA
+    host.attention();
+    assert!(host.is_low(IecLine::Data));
+    host.send_byte(IecCommand::Listen(4).encode(), false);
+    host.send_byte(IecCommand::Open(2).encode(), false);
+    assert_eq!(vec![Event::Open(2)], *events.borrow());
+    // Listener keeps DATA low after ATN is released
+    host.set_low(IecLine::Atn, false);
+    host.run(1);
+    assert!(host.is_low(IecLine::Data));
+    assert!(host.is_low(IecLine::Clk));
+    host.send_byte(0x41, false);
+    host.send_byte(0x42, true);
+    host.attention();
+    host.send_byte(IecCommand::Unlisten.encode(), false);
+    host.set_low(IecLine::Atn, false);
+    host.set_low(IecLine::Clk, false);
+    host.run(10);
+    assert!(!host.is_low(IecLine::Data));
+    assert!(!host.is_low(IecLine::Clk));
+    assert_eq!(
+        vec![
+            Event::Open(2),
+            Event::Write(2, 0x41, false),
+            Event::Write(2, 0x42, true),
+        ],
+        *events.borrow()
+    );
+}
+
+#[test]
+fn listen_with_late_clk() {
+    let (mut host, events) = setup_host(4, &[]);
+    // CLK is still released when ATN goes low, as the kernal does on TALK
+    host.set_low(IecLine::Atn, true);
+    host.run(20);
+    assert!(host.is_low(IecLine::Data));
+    host.set_low(IecLine::Clk, true);
+    host.run(20);
+    host.send_byte(IecCommand::Listen(4).encode(), false);
+    host.send_byte(IecCommand::Open(2).encode(), false);
+    assert_eq!(vec![Event::Open(2)], *events.borrow());
+}
+
+#[test]
+fn listen_close() {
+    let (mut host, events) = setup_host(4, &[]);
+    host.attention();
+    host.send_byte(IecCommand::Listen(4).encode(), false);
+    host.send_byte(IecCommand::Close(2).encode(), false);
+    host.send_byte(IecCommand::Unlisten.encode(), false);
+    host.set_low(IecLine::Atn, false);
+    host.set_low(IecLine::Clk, false);
+    host.run(10);
+    assert!(!host.is_low(IecLine::Data));
+    assert_eq!(vec![Event::Close(2)], *events.borrow());
+}
+
+#[test]
+fn device_not_present() {
+    let (mut host, events) = setup_host(4, &[]);
+    host.attention();
+    assert!(host.is_low(IecLine::Data));
+    host.send_byte(IecCommand::Listen(8).encode(), false);
+    host.send_byte(IecCommand::Open(2).encode(), false);
+    // Nobody holds DATA once ATN is released
+    host.set_low(IecLine::Atn, false);
+    host.run(100);
+    assert!(!host.is_low(IecLine::Data));
+    assert!(events.borrow().is_empty());
+}
+
+#[test]
+fn talk_byte() {
+    let (mut host, events) = setup_host(4, b"HI");
+    host.attention();
+    host.send_byte(IecCommand::Talk(4).encode(), false);
+    host.send_byte(IecCommand::Secondary(2).encode(), false);
+    // Turnaround, host becomes listener and device takes over CLK
+    host.set_low(IecLine::Data, true);
+    host.set_low(IecLine::Clk, false);
+    host.set_low(IecLine::Atn, false);
+    let turnaround = host.wait_for(IecLine::Clk, true);
+    assert!(turnaround < 10, "turnaround {}", turnaround);
+    assert_eq!((b'H', false), host.receive_byte());
+    assert_eq!((b'I', true), host.receive_byte());
+    assert_eq!(vec![Event::Read(2), Event::Read(2)], *events.borrow());
+}
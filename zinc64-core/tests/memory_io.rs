@@ -0,0 +1,182 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use std::cell::Cell;
+
+use zinc64_core::factory::{Addressable, AddressableFaded, Chip, Mmu};
+use zinc64_core::mem::{Memory, Mmio, Pla};
+use zinc64_core::util::{new_shared, Ram, Rom, Shared};
+
+struct MockChip {
+    value: u8,
+}
+
+impl Chip for MockChip {
+    fn clock(&mut self) {}
+    fn clock_delta(&mut self, _delta: u32) {}
+    fn process_vsync(&mut self) {}
+    fn reset(&mut self) {}
+    fn peek(&self, _reg: u8) -> u8 {
+        self.value
+    }
+    fn read(&mut self, _reg: u8) -> u8 {
+        self.value
+    }
+    fn write(&mut self, _reg: u8, value: u8) {
+        self.value = value;
+    }
+}
+
+struct MockExpansionPort {
+    writes: u32,
+}
+
+impl AddressableFaded for MockExpansionPort {
+    fn read(&mut self, _address: u16) -> Option<u8> {
+        Some(0xee)
+    }
+
+    fn peek(&self, _address: u16) -> Option<u8> {
+        Some(0xee)
+    }
+
+    fn write(&mut self, _address: u16, _value: u8) {
+        self.writes += 1;
+    }
+}
+
+struct MockDevice {
+    regs: [u8; 0x100],
+    reads: Cell<u32>,
+}
+
+impl MockDevice {
+    pub fn new() -> Self {
+        MockDevice {
+            regs: [0; 0x100],
+            reads: Cell::new(0),
+        }
+    }
+}
+
+impl Addressable for MockDevice {
+    fn read(&self, address: u16) -> u8 {
+        self.reads.set(self.reads.get() + 1);
+        self.regs[(address & 0xff) as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.regs[(address & 0xff) as usize] = value;
+    }
+}
+
+struct Setup {
+    mem: Memory,
+    mmu: Shared<Pla>,
+    expansion_port: Shared<MockExpansionPort>,
+    ram: Shared<Ram>,
+}
+
+fn setup_memory() -> Setup {
+    let mmu = new_shared(Pla::new());
+    mmu.borrow_mut().switch_banks(31);
+    let chip = || -> Shared<dyn Chip> { new_shared(MockChip { value: 0 }) };
+    let expansion_port = new_shared(MockExpansionPort { writes: 0 });
+    let ram = new_shared(Ram::new(0x10000));
+    let io = Mmio::new(
+        chip(),
+        chip(),
+        new_shared(Ram::new(0x400)),
+        expansion_port.clone(),
+        chip(),
+        chip(),
+    );
+    let mem = Memory::new(
+        mmu.clone(),
+        expansion_port.clone(),
+        io,
+        ram.clone(),
+        new_shared(Rom::new(0x2000, 0xa000, 0x10)),
+        new_shared(Rom::new(0x1000, 0xd000, 0x11)),
+        new_shared(Rom::new(0x2000, 0xe000, 0x12)),
+    );
+    Setup {
+        mem,
+        mmu,
+        expansion_port,
+        ram,
+    }
+}
+
+#[test]
+fn device_handles_registered_range() {
+    let mut setup = setup_memory();
+    let device = new_shared(MockDevice::new());
+    setup
+        .mem
+        .register_io(0xde00, 0xdeff, device.clone())
+        .unwrap();
+    setup.mem.write(0xde10, 0x42);
+    assert_eq!(0x42, device.borrow().regs[0x10]);
+    assert_eq!(0x42, setup.mem.read(0xde10));
+    assert_eq!(1, device.borrow().reads.get());
+    assert_eq!(0x00, setup.ram.borrow().read(0xde10));
+    assert_eq!(0, setup.expansion_port.borrow().writes);
+}
+
+#[test]
+fn unclaimed_addresses_use_default_decoding() {
+    let mut setup = setup_memory();
+    let device = new_shared(MockDevice::new());
+    setup
+        .mem
+        .register_io(0xde00, 0xdeff, device.clone())
+        .unwrap();
+    assert_eq!(0xee, setup.mem.read(0xdf00));
+    setup.mem.write(0xdf00, 0x01);
+    assert_eq!(1, setup.expansion_port.borrow().writes);
+    assert_eq!(0, device.borrow().reads.get());
+}
+
+#[test]
+fn ram_banked_in_bypasses_device() {
+    let mut setup = setup_memory();
+    let device = new_shared(MockDevice::new());
+    setup
+        .mem
+        .register_io(0xde00, 0xdeff, device.clone())
+        .unwrap();
+    setup.mmu.borrow_mut().switch_banks(24);
+    setup.mem.write(0xde10, 0x42);
+    assert_eq!(0x42, setup.ram.borrow().read(0xde10));
+    assert_eq!(0x00, device.borrow().regs[0x10]);
+}
+
+#[test]
+fn rejects_invalid_ranges() {
+    let mut setup = setup_memory();
+    let device = new_shared(MockDevice::new());
+    assert!(setup
+        .mem
+        .register_io(0xc000, 0xc0ff, device.clone())
+        .is_err());
+    assert!(setup
+        .mem
+        .register_io(0xdf00, 0xe000, device.clone())
+        .is_err());
+    assert!(setup
+        .mem
+        .register_io(0xde00, 0xde7f, device.clone())
+        .is_ok());
+    assert!(setup
+        .mem
+        .register_io(0xde40, 0xdeff, device.clone())
+        .is_err());
+    assert!(setup.mem.unregister_io(0xde00).is_ok());
+    assert!(setup
+        .mem
+        .register_io(0xde40, 0xdeff, device.clone())
+        .is_ok());
+    assert!(setup.mem.unregister_io(0xde00).is_err());
+}
@@ -0,0 +1,117 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use std::sync::Arc;
+use std::thread;
+
+use zinc64_core::factory::SoundOutput;
+use zinc64_core::sound::SampleQueue;
+
+fn samples(start: i16, count: usize) -> Vec<i16> {
+    (0..count as i16).map(|i| start + i).collect()
+}
+
+#[test]
+fn push_and_pop_in_order() {
+    let queue = SampleQueue::new(8);
+    assert_eq!(5, queue.push(&samples(1, 5)));
+    assert_eq!(5, queue.samples_available());
+    let mut output = [0i16; 3];
+    assert_eq!(3, queue.pop(&mut output));
+    assert_eq!([1, 2, 3], output);
+    assert_eq!(2, queue.samples_available());
+    assert_eq!(0, queue.get_overruns());
+    assert_eq!(0, queue.get_underruns());
+}
+
+#[test]
+fn slow_consumer_counts_overruns() {
+    let queue = SampleQueue::new(8);
+    assert_eq!(6, queue.push(&samples(1, 6)));
+    assert_eq!(2, queue.push(&samples(7, 6)));
+    assert_eq!(8, queue.samples_available());
+    assert_eq!(4, queue.get_overruns());
+    let mut output = [0i16; 8];
+    assert_eq!(8, queue.pop(&mut output));
+    assert_eq!([1, 2, 3, 4, 5, 6, 7, 8], output);
+}
+
+#[test]
+fn fast_consumer_counts_underruns() {
+    let queue = SampleQueue::new(8);
+    queue.push(&samples(1, 3));
+    let mut output = [-1i16; 5];
+    assert_eq!(3, queue.pop(&mut output));
+    assert_eq!([1, 2, 3, 0, 0], output);
+    assert_eq!(2, queue.get_underruns());
+    assert_eq!(0, queue.samples_available());
+    queue.reset_counters();
+    assert_eq!(0, queue.get_underruns());
+}
+
+#[test]
+fn wraps_around_capacity() {
+    let queue = SampleQueue::new(5);
+    let mut output = [0i16; 3];
+    let mut next = 0;
+    for _ in 0..20 {
+        assert_eq!(3, queue.push(&samples(next, 3)));
+        assert_eq!(3, queue.pop(&mut output));
+        assert_eq!(&samples(next, 3)[..], &output[..]);
+        next += 3;
+    }
+    assert_eq!(0, queue.get_overruns());
+    assert_eq!(0, queue.get_underruns());
+}
+
+#[test]
+fn reset_discards_queued_samples() {
+    let queue = SampleQueue::new(8);
+    queue.push(&samples(1, 4));
+    queue.reset();
+    assert_eq!(0, queue.samples_available());
+    queue.write(&samples(10, 2));
+    let mut output = [0i16; 2];
+    assert_eq!(2, queue.pop(&mut output));
+    assert_eq!([10, 11], output);
+}
+
+#[test]
+fn producer_and_consumer_threads() {
+    const TOTAL: usize = 20_000;
+    let queue = Arc::new(SampleQueue::new(64));
+    let producer = {
+        let queue = queue.clone();
+        thread::spawn(move || {
+            let mut accepted = 0;
+            for chunk in (0..TOTAL).collect::<Vec<usize>>().chunks(16) {
+                let values = chunk.iter().map(|i| *i as i16).collect::<Vec<i16>>();
+                accepted += queue.push(&values);
+                if chunk[0] % 256 == 0 {
+                    thread::yield_now();
+                }
+            }
+            accepted
+        })
+    };
+    let mut consumed = Vec::new();
+    let mut output = [0i16; 24];
+    loop {
+        let count = queue.pop(&mut output);
+        consumed.extend_from_slice(&output[0..count]);
+        if count == 0 && consumed.len() + queue.get_overruns() == TOTAL {
+            break;
+        }
+        if count == 0 {
+            thread::yield_now();
+        }
+    }
+    let accepted = producer.join().unwrap();
+    assert_eq!(accepted, consumed.len());
+    assert_eq!(TOTAL, accepted + queue.get_overruns());
+    // Dropped samples leave gaps but nothing is repeated or reordered
+    for pair in consumed.windows(2) {
+        assert!(pair[1] > pair[0]);
+    }
+}
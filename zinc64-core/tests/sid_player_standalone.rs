@@ -73,7 +73,7 @@ fn exec_sid_player() {
         sound_buffer.clone(),
     )));
     sid.borrow_mut().set_sampling_parameters(
-        sid::SamplingMethod::ResampleFast,
+        sid::SamplingMethod::Resample,
         model.cpu_freq,
         44100,
     );
@@ -0,0 +1,743 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use zinc64_core::factory::{Chip, SystemModel, VicModel, VideoOutput};
+use zinc64_core::util::{Clock, EventBus, IrqLine, Pin, Ram, Rom, Shared, SharedCell};
+use zinc64_core::video::{Vic, VicMemory};
+
+const FRAME_WIDTH: usize = 504;
+const FRAME_HEIGHT: usize = 312;
+
+struct MockVideoOutput {
+    buffer: Vec<u8>,
+    frames: u32,
+}
+
+impl MockVideoOutput {
+    pub fn new() -> Self {
+        MockVideoOutput {
+            buffer: vec![0; FRAME_WIDTH * FRAME_HEIGHT],
+            frames: 0,
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.buffer[y * FRAME_WIDTH + x]
+    }
+}
+
+impl VideoOutput for MockVideoOutput {
+    fn get_dimension(&self) -> (usize, usize) {
+        (FRAME_WIDTH, FRAME_HEIGHT)
+    }
+
+    fn reset(&mut self) {
+        for pixel in self.buffer.iter_mut() {
+            *pixel = 0;
+        }
+    }
+
+    fn write(&mut self, index: usize, color: u8) {
+        self.buffer[index] = color;
+    }
+
+    fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+}
+
+struct Setup {
+    vic: Vic,
+    ba_line: Shared<Pin>,
+    base_address: SharedCell<u16>,
+    color_ram: Shared<Ram>,
+    ram: Shared<Ram>,
+    video: Shared<MockVideoOutput>,
+}
+
+fn setup_vic() -> Setup {
+    setup_vic_with_model(VicModel::Mos6569)
+}
+
+fn setup_vic_with_model(chip_model: VicModel) -> Setup {
+    new_setup(chip_model, Rom::new(0x1000, 0, 0x00))
+}
+
+fn new_setup(chip_model: VicModel, charset: Rom) -> Setup {
+    let ba_line = Rc::new(RefCell::new(Pin::new_high()));
+    let irq_line = Rc::new(RefCell::new(IrqLine::new("irq")));
+    let vsync_flag = Rc::new(Cell::new(false));
+    let base_address = Rc::new(Cell::new(0));
+    let color_ram = Rc::new(RefCell::new(Ram::new(1024)));
+    let ram = Rc::new(RefCell::new(Ram::new(0x10000)));
+    let charset = Rc::new(RefCell::new(charset));
+    let video = Rc::new(RefCell::new(MockVideoOutput::new()));
+    let frame_buffer: Shared<dyn VideoOutput> = video.clone();
+    let mem = VicMemory::new(base_address.clone(), charset, ram.clone());
+    let mut vic = Vic::new(
+        chip_model,
+        color_ram.clone(),
+        mem,
+        frame_buffer,
+        vsync_flag,
+        ba_line.clone(),
+        irq_line,
+        Rc::new(EventBus::new(Rc::new(Clock::default()))),
+    );
+    vic.reset();
+    Setup {
+        vic,
+        ba_line,
+        base_address,
+        color_ram,
+        ram,
+        video,
+    }
+}
+
+fn raster(vic: &mut Vic) -> u16 {
+    (((vic.read(0x11) & 0x80) as u16) << 1) | vic.read(0x12) as u16
+}
+
+/// Clock the vic until it is about to execute the specified cycle of the raster line.
+fn clock_to(vic: &mut Vic, line: u16, cycle: u16) {
+    while raster(vic) == line {
+        vic.clock();
+    }
+    while raster(vic) != line {
+        vic.clock();
+    }
+    for _i in 1..cycle {
+        vic.clock();
+    }
+}
+
+fn setup_sprite_0(setup: &mut Setup, x: u8, y: u8) {
+    setup.ram.borrow_mut().write(0x07f8, 0x20);
+    for i in 0..63 {
+        setup.ram.borrow_mut().write(0x0800 + i, 0xff);
+    }
+    setup.vic.write(0x00, x); // M0X
+    setup.vic.write(0x01, y); // M0Y
+    setup.vic.write(0x27, 0x01); // M0C
+    setup.vic.write(0x15, 0x01); // ME
+}
+
+fn sprite_x_to_screen(x: u16) -> usize {
+    (x + 0x64) as usize
+}
+
+#[test]
+fn sprite_x_latched_before_draw_position() {
+    let mut setup = setup_vic();
+    setup_sprite_0(&mut setup, 0x80, 0x64);
+    // Sprite would start at screen x 228 in cycle 30, move it right before then
+    clock_to(&mut setup.vic, 0x65, 20);
+    setup.vic.write(0x00, 0xa0);
+    clock_to(&mut setup.vic, 0x66, 1);
+    let video = setup.video.borrow();
+    assert_ne!(0x01, video.pixel(sprite_x_to_screen(0x80), 0x65));
+    assert_eq!(0x01, video.pixel(sprite_x_to_screen(0xa0), 0x65));
+    assert_eq!(0x01, video.pixel(sprite_x_to_screen(0xa0) + 23, 0x65));
+    assert_ne!(0x01, video.pixel(sprite_x_to_screen(0xa0) + 24, 0x65));
+}
+
+#[test]
+fn sprite_x_moved_behind_raster_is_skipped() {
+    let mut setup = setup_vic();
+    setup_sprite_0(&mut setup, 0x80, 0x64);
+    // By cycle 25 the beam has passed screen x 188, so the new position never matches
+    clock_to(&mut setup.vic, 0x65, 25);
+    setup.vic.write(0x00, 0x4c);
+    clock_to(&mut setup.vic, 0x67, 1);
+    let video = setup.video.borrow();
+    for x in sprite_x_to_screen(0x4c)..sprite_x_to_screen(0x80) + 24 {
+        assert_ne!(0x01, video.pixel(x, 0x65));
+    }
+    assert_eq!(0x01, video.pixel(sprite_x_to_screen(0x4c), 0x66));
+}
+
+#[test]
+fn sprite_x_change_after_start_keeps_drawing() {
+    let mut setup = setup_vic();
+    setup_sprite_0(&mut setup, 0x80, 0x64);
+    // Sprite starts in cycle 30, once started the shift register runs to completion
+    clock_to(&mut setup.vic, 0x65, 31);
+    setup.vic.write(0x00, 0xa0);
+    clock_to(&mut setup.vic, 0x66, 1);
+    let video = setup.video.borrow();
+    for x in sprite_x_to_screen(0x80)..sprite_x_to_screen(0x80) + 24 {
+        assert_eq!(0x01, video.pixel(x, 0x65));
+    }
+}
+
+#[test]
+fn char_set_from_ram() {
+    let mut setup = setup_vic();
+    for i in 0..8 {
+        setup.ram.borrow_mut().write(0x2000 + 8 + i, 0x10 + i as u8);
+        setup.ram.borrow_mut().write(0x27f8 + i, 0x80 | i as u8);
+    }
+    setup.vic.write(0x18, 0x18); // MEMPTR
+    let char_set = setup.vic.char_set();
+    assert_eq!([0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17], char_set[1]);
+    assert_eq!([0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87], char_set[255]);
+    assert_eq!([0; 8], char_set[0]);
+}
+
+#[test]
+fn char_set_from_rom_shadow() {
+    let mut setup = setup_vic();
+    setup.ram.borrow_mut().write(0x1008, 0xff);
+    setup.vic.write(0x18, 0x14); // MEMPTR
+    let char_set = setup.vic.char_set();
+    assert_eq!([0; 8], char_set[1]);
+}
+
+#[test]
+fn sprite_pointers_default_bank() {
+    let mut setup = setup_vic();
+    for i in 0..8 {
+        setup.ram.borrow_mut().write(0x07f8 + i, 0x20 + i as u8);
+    }
+    setup.vic.write(0x18, 0x14); // MEMPTR
+    let pointers = setup.vic.sprite_pointers();
+    assert_eq!(0x0800, pointers[0]);
+    assert_eq!(0x09c0, pointers[7]);
+}
+
+#[test]
+fn sprite_pointers_follow_bank() {
+    let mut setup = setup_vic();
+    setup.base_address.set(0x4000);
+    setup.ram.borrow_mut().write(0x4c00 + 0x03f8, 0x01);
+    setup.ram.borrow_mut().write(0x4c00 + 0x03ff, 0xff);
+    setup.vic.write(0x18, 0x34); // MEMPTR
+    let pointers = setup.vic.sprite_pointers();
+    assert_eq!(0x4040, pointers[0]);
+    assert_eq!(0x7fc0, pointers[7]);
+}
+
+#[test]
+fn memptr_read_back() {
+    let mut setup = setup_vic();
+    setup.vic.write(0x18, 0x3e); // MEMPTR
+    assert_eq!(0x3f, setup.vic.read(0x18));
+    setup.vic.write(0x18, 0xf0);
+    assert_eq!(0xf1, setup.vic.read(0x18));
+    setup.vic.write(0x18, 0x14);
+    assert_eq!(0x15, setup.vic.read(0x18));
+}
+
+#[test]
+fn raster_wraps_at_frame_end() {
+    let mut setup = setup_vic();
+    clock_to(&mut setup.vic, 0, 1);
+    let mut expected = 0;
+    for _line in 0..312 {
+        assert_eq!(expected, raster(&mut setup.vic));
+        for _cycle in 0..63 {
+            setup.vic.clock();
+        }
+        expected = (expected + 1) % 312;
+    }
+    assert_eq!(0, raster(&mut setup.vic));
+}
+
+const DISPLAY_X: usize = 24 + 0x64;
+const DISPLAY_Y: usize = 0x33;
+
+static GLYPH: [u8; 8] = [0xff, 0x81, 0x42, 0x24, 0x18, 0x00, 0xaa, 0x55];
+
+fn setup_text_screen(setup: &mut Setup, screen_code: u8, color: u8) {
+    for i in 0..8 {
+        setup.ram.borrow_mut().write(0x2000 + ((screen_code as u16) << 3) + i, GLYPH[i as usize]);
+    }
+    for i in 0..1000 {
+        setup.ram.borrow_mut().write(0x0400 + i, screen_code);
+        setup.color_ram.borrow_mut().write(i, color);
+    }
+    setup.vic.write(0x11, 0x1b); // CR1
+    setup.vic.write(0x16, 0x08); // CR2
+    setup.vic.write(0x18, 0x18); // MEMPTR
+    setup.vic.write(0x20, 0x0e); // EC
+    setup.vic.write(0x21, 0x06); // B0C
+}
+
+// The vertical border flip flop is first set at the bottom of the frame after reset,
+// so render the second frame.
+fn render_frame(setup: &mut Setup) {
+    clock_to(&mut setup.vic, 0, 1);
+    clock_to(&mut setup.vic, 0, 1);
+    clock_to(&mut setup.vic, DISPLAY_Y as u16 + 201, 1);
+}
+
+#[test]
+fn render_text_mode() {
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x05);
+    render_frame(&mut setup);
+    let mut expected = vec![0u8; 320 * 200];
+    for y in 0..200 {
+        for x in 0..320 {
+            let bit = GLYPH[y % 8] & (0x80 >> (x % 8));
+            expected[y * 320 + x] = if bit != 0 { 0x05 } else { 0x06 };
+        }
+    }
+    let video = setup.video.borrow();
+    let mut actual = vec![0u8; 320 * 200];
+    for y in 0..200 {
+        for x in 0..320 {
+            actual[y * 320 + x] = video.pixel(DISPLAY_X + x, DISPLAY_Y + y) & 0x0f;
+        }
+    }
+    assert!(expected == actual, "text mode frame mismatch");
+    assert_eq!(0x0e, video.pixel(DISPLAY_X - 1, DISPLAY_Y) & 0x0f);
+    assert_eq!(0x0e, video.pixel(DISPLAY_X + 320, DISPLAY_Y) & 0x0f);
+    assert_eq!(0x0e, video.pixel(DISPLAY_X, DISPLAY_Y - 1) & 0x0f);
+    assert_eq!(0x0e, video.pixel(DISPLAY_X, DISPLAY_Y + 200) & 0x0f);
+}
+
+fn display_row(setup: &Setup, y: usize, x: usize, len: usize) -> Vec<u8> {
+    let video = setup.video.borrow();
+    (0..len)
+        .map(|i| video.pixel(DISPLAY_X + x + i, DISPLAY_Y + y) & 0x0f)
+        .collect()
+}
+
+#[test]
+fn render_multicolor_text_mode() {
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x0d);
+    for i in 0..8 {
+        setup.ram.borrow_mut().write(0x2008 + i, 0x1b);
+    }
+    setup.color_ram.borrow_mut().write(1, 0x02);
+    setup.vic.write(0x16, 0x18); // CR2
+    setup.vic.write(0x22, 0x01); // B1C
+    setup.vic.write(0x23, 0x02); // B2C
+    render_frame(&mut setup);
+    // Color bit 3 set selects double-wide pixels from B0C, B1C, B2C and color bits 0-2
+    assert_eq!(vec![6, 6, 1, 1, 2, 2, 5, 5], display_row(&setup, 0, 0, 8));
+    // Color bit 3 clear renders as hires
+    assert_eq!(vec![6, 6, 6, 2, 2, 6, 2, 2], display_row(&setup, 0, 8, 8));
+}
+
+fn bitmap_pattern(cell: usize, row: usize) -> u8 {
+    ((cell * 7 + row * 13) & 0xff) as u8
+}
+
+fn setup_bitmap_screen(setup: &mut Setup, screen_data: u8, color: u8) {
+    for cell in 0..1000 {
+        for row in 0..8 {
+            let address = 0x2000 + (cell * 8 + row) as u16;
+            setup.ram.borrow_mut().write(address, bitmap_pattern(cell, row));
+        }
+        setup.ram.borrow_mut().write(0x0400 + cell as u16, screen_data);
+        setup.color_ram.borrow_mut().write(cell as u16, color);
+    }
+    setup.vic.write(0x11, 0x3b); // CR1
+    setup.vic.write(0x16, 0x08); // CR2
+    setup.vic.write(0x18, 0x18); // MEMPTR
+    setup.vic.write(0x20, 0x0e); // EC
+    setup.vic.write(0x21, 0x06); // B0C
+}
+
+#[test]
+fn render_bitmap_mode() {
+    let mut setup = setup_vic();
+    setup_bitmap_screen(&mut setup, 0x51, 0x0f);
+    render_frame(&mut setup);
+    for &y in [0usize, 7, 100, 199].iter() {
+        let expected: Vec<u8> = (0..320)
+            .map(|x| {
+                let data = bitmap_pattern((y / 8) * 40 + x / 8, y % 8);
+                if data & (0x80 >> (x % 8)) != 0 {
+                    0x05
+                } else {
+                    0x01
+                }
+            })
+            .collect();
+        assert_eq!(expected, display_row(&setup, y, 0, 320), "scanline {}", y);
+    }
+}
+
+#[test]
+fn render_multicolor_bitmap_mode() {
+    let mut setup = setup_vic();
+    setup_bitmap_screen(&mut setup, 0x51, 0x0c);
+    setup.ram.borrow_mut().write(0x2000, 0x1b);
+    setup.vic.write(0x16, 0x18); // CR2
+    render_frame(&mut setup);
+    // 00: B0C, 01: screen upper nibble, 10: screen lower nibble, 11: color ram
+    assert_eq!(vec![6, 6, 5, 5, 1, 1, 12, 12], display_row(&setup, 0, 0, 8));
+}
+
+#[test]
+fn render_extended_color_text_mode() {
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x05);
+    setup.ram.borrow_mut().write(0x2008, 0xf0);
+    for (i, screen_code) in [0x01u8, 0x41, 0x81, 0xc1].iter().enumerate() {
+        setup.ram.borrow_mut().write(0x0400 + i as u16, *screen_code);
+    }
+    setup.vic.write(0x11, 0x5b); // CR1
+    setup.vic.write(0x22, 0x01); // B1C
+    setup.vic.write(0x23, 0x02); // B2C
+    setup.vic.write(0x24, 0x03); // B3C
+    render_frame(&mut setup);
+    for (i, bg_color) in [0x06u8, 0x01, 0x02, 0x03].iter().enumerate() {
+        let c = *bg_color;
+        assert_eq!(vec![5, 5, 5, 5, c, c, c, c], display_row(&setup, 0, i * 8, 8));
+    }
+}
+
+#[test]
+fn render_invalid_modes_blank() {
+    // ECM/MCM, ECM/BMM and ECM/BMM/MCM
+    for &(cr1, cr2) in [(0x5bu8, 0x18u8), (0x7b, 0x08), (0x7b, 0x18)].iter() {
+        let mut setup = setup_vic();
+        setup_text_screen(&mut setup, 0x01, 0x05);
+        setup_sprite_0(&mut setup, 0x80, 0x64);
+        setup.vic.write(0x11, cr1); // CR1
+        setup.vic.write(0x16, cr2); // CR2
+        render_frame(&mut setup);
+        let sprite_x = sprite_x_to_screen(0x80);
+        let video = setup.video.borrow();
+        for y in DISPLAY_Y..DISPLAY_Y + 200 {
+            for x in DISPLAY_X..DISPLAY_X + 320 {
+                let in_sprite = x >= sprite_x && x < sprite_x + 24 && y >= 0x65 && y < 0x65 + 21;
+                if !in_sprite {
+                    assert_eq!(0x00, video.pixel(x, y), "mode {:02x}/{:02x}", cr1, cr2);
+                }
+            }
+        }
+        assert_eq!(0x01, video.pixel(sprite_x, 0x65) & 0x0f);
+        assert_eq!(0x0e, video.pixel(DISPLAY_X - 1, DISPLAY_Y) & 0x0f);
+    }
+}
+
+fn setup_sprite(setup: &mut Setup, n: u8, x: u8, y: u8, color: u8) {
+    let data = 0x0800 + ((n as u16) << 6);
+    setup.ram.borrow_mut().write(0x07f8 + n as u16, (data >> 6) as u8);
+    for i in 0..63 {
+        setup.ram.borrow_mut().write(data + i, 0xff);
+    }
+    setup.vic.write(n << 1, x); // MnX
+    setup.vic.write((n << 1) + 1, y); // MnY
+    setup.vic.write(0x27 + n, color); // MnC
+    let enabled = setup.vic.read(0x15);
+    setup.vic.write(0x15, enabled | (1 << n)); // ME
+}
+
+fn screen_row(setup: &Setup, x: usize, y: usize, len: usize) -> Vec<u8> {
+    let video = setup.video.borrow();
+    (0..len).map(|i| video.pixel(x + i, y) & 0x0f).collect()
+}
+
+#[test]
+fn render_expanded_multicolor_sprite() {
+    let mut setup = setup_vic();
+    setup_sprite(&mut setup, 0, 0x80, 0x64, 0x01);
+    setup.ram.borrow_mut().write(0x0800, 0x1b);
+    setup.ram.borrow_mut().write(0x0803, 0x00);
+    setup.vic.write(0x1c, 0x01); // MMC
+    setup.vic.write(0x1d, 0x01); // MXE
+    setup.vic.write(0x17, 0x01); // MYE
+    setup.vic.write(0x25, 0x02); // MM0
+    setup.vic.write(0x26, 0x03); // MM1
+    clock_to(&mut setup.vic, 0x68, 1);
+    let x = sprite_x_to_screen(0x80);
+    let bg = setup.video.borrow().pixel(x - 1, 0x65) & 0x0f;
+    let expected = vec![bg, bg, bg, bg, 2, 2, 2, 2, 1, 1, 1, 1, 3, 3, 3, 3];
+    assert_eq!(expected, screen_row(&setup, x, 0x65, 16));
+    assert_eq!(expected, screen_row(&setup, x, 0x66, 16));
+    assert_eq!(vec![bg; 16], screen_row(&setup, x, 0x67, 16));
+    assert_eq!(vec![3; 32], screen_row(&setup, x + 16, 0x65, 32));
+}
+
+#[test]
+fn render_overlapping_sprites_priority() {
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x05);
+    setup_sprite(&mut setup, 0, 0x80, 0x64, 0x01);
+    setup_sprite(&mut setup, 1, 0x80, 0x64, 0x02);
+    setup_sprite(&mut setup, 2, 0x98, 0x64, 0x03);
+    setup.vic.write(0x1b, 0x04); // MDP
+    render_frame(&mut setup);
+    let x = sprite_x_to_screen(0x80);
+    // Lower numbered sprite is drawn on top
+    assert_eq!(vec![1; 24], screen_row(&setup, x, 0x65, 24));
+    // Sprite 2 is behind foreground pixels but in front of background
+    let x = sprite_x_to_screen(0x98);
+    let glyph = GLYPH[(0x65 - DISPLAY_Y) % 8];
+    let column = (x - DISPLAY_X) % 8;
+    let expected: Vec<u8> = (0..24)
+        .map(|i| {
+            if glyph & (0x80 >> ((column + i) % 8)) != 0 {
+                0x05
+            } else {
+                0x03
+            }
+        })
+        .collect();
+    assert_eq!(expected, screen_row(&setup, x, 0x65, 24));
+}
+
+#[test]
+fn sprite_sprite_collision() {
+    let mut setup = setup_vic();
+    setup_sprite(&mut setup, 2, 0x80, 0x64, 0x01);
+    setup_sprite(&mut setup, 5, 0x90, 0x64, 0x02);
+    setup_sprite(&mut setup, 6, 0xc0, 0x64, 0x03);
+    setup.vic.write(0x1a, 0x04); // IMR
+    clock_to(&mut setup.vic, 0x67, 1);
+    assert_eq!(0x04, setup.vic.read(0x19) & 0x04); // IRR
+    assert_eq!(0x24, setup.vic.read(0x1e)); // MM
+    assert_eq!(0x00, setup.vic.read(0x1e));
+}
+
+/// Place a single character at row 10, column 20 of an otherwise blank screen and
+/// sprites 0 and 1 over a blank area and over the character respectively.
+fn sprite_data_collision(glyph_row: u8, color: u8, cr2: u8) -> u8 {
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x20, color);
+    for i in 0..8 {
+        setup.ram.borrow_mut().write(0x2100 + i, 0x00);
+        setup.ram.borrow_mut().write(0x2008 + i, glyph_row);
+    }
+    setup.ram.borrow_mut().write(0x0400 + 10 * 40 + 20, 0x01);
+    setup.vic.write(0x16, cr2); // CR2
+    setup_sprite(&mut setup, 0, 0x30, 0x82, 0x01);
+    setup_sprite(&mut setup, 1, 0xb8, 0x82, 0x02);
+    render_frame(&mut setup);
+    let collision = setup.vic.read(0x1f); // MD
+    assert_eq!(0x00, setup.vic.read(0x1f));
+    collision
+}
+
+#[test]
+fn sprite_data_collision_hires() {
+    assert_eq!(0x00, sprite_data_collision(0x00, 0x05, 0x08));
+    assert_eq!(0x02, sprite_data_collision(0x18, 0x05, 0x08));
+}
+
+#[test]
+fn sprite_data_collision_multicolor() {
+    // Bit pairs 00 and 01 are background
+    assert_eq!(0x00, sprite_data_collision(0x55, 0x0d, 0x18));
+    // Bit pairs 10 and 11 are foreground
+    assert_eq!(0x02, sprite_data_collision(0xa0, 0x0d, 0x18));
+    assert_eq!(0x02, sprite_data_collision(0x0f, 0x0d, 0x18));
+}
+
+fn is_border(setup: &Setup, x: usize, y: usize) -> bool {
+    setup.video.borrow().pixel(x, y) & 0x0f == 0x0e
+}
+
+#[test]
+fn display_window_rsel_csel() {
+    // 40 columns from x 24 to 343, 25 rows from line 51 to 250
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x05);
+    render_frame(&mut setup);
+    assert!(is_border(&setup, sprite_x_to_screen(23), 100));
+    assert!(!is_border(&setup, sprite_x_to_screen(24), 100));
+    assert!(!is_border(&setup, sprite_x_to_screen(343), 100));
+    assert!(is_border(&setup, sprite_x_to_screen(344), 100));
+    assert!(is_border(&setup, 200, 50));
+    assert!(!is_border(&setup, 200, 51));
+    assert!(!is_border(&setup, 200, 250));
+    assert!(is_border(&setup, 200, 251));
+    // 38 columns from x 31 to 334, 24 rows from line 55 to 246
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x05);
+    setup.vic.write(0x11, 0x13); // CR1
+    setup.vic.write(0x16, 0x00); // CR2
+    render_frame(&mut setup);
+    assert!(is_border(&setup, sprite_x_to_screen(30), 100));
+    assert!(!is_border(&setup, sprite_x_to_screen(31), 100));
+    assert!(!is_border(&setup, sprite_x_to_screen(334), 100));
+    assert!(is_border(&setup, sprite_x_to_screen(335), 100));
+    assert!(is_border(&setup, 200, 54));
+    assert!(!is_border(&setup, 200, 55));
+    assert!(!is_border(&setup, 200, 246));
+    assert!(is_border(&setup, 200, 247));
+}
+
+fn render_bottom_border_sprite(open_border: bool) -> Setup {
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x05);
+    setup_sprite(&mut setup, 0, 0x80, 0xfa, 0x01);
+    clock_to(&mut setup.vic, 0, 1);
+    if open_border {
+        // Switch to 24 rows after line 247 so the bottom comparison never matches
+        clock_to(&mut setup.vic, 249, 10);
+        setup.vic.write(0x11, 0x13); // CR1
+        clock_to(&mut setup.vic, 253, 10);
+        setup.vic.write(0x11, 0x1b); // CR1
+    }
+    clock_to(&mut setup.vic, 0x110, 1);
+    setup
+}
+
+#[test]
+fn open_bottom_border_shows_sprite() {
+    let x = sprite_x_to_screen(0x80);
+    let setup = render_bottom_border_sprite(false);
+    assert!(is_border(&setup, x, 0x100));
+    let setup = render_bottom_border_sprite(true);
+    assert_eq!(vec![1; 24], screen_row(&setup, x, 0x100, 24));
+    assert!(!is_border(&setup, 200, 0x100));
+}
+
+#[test]
+fn bad_lines_assert_ba() {
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x05);
+    setup.vic.write(0x11, 0x1d); // CR1
+    clock_to(&mut setup.vic, 0, 1);
+    for line in 0..312u16 {
+        let mut ba_cycles = 0;
+        for _cycle in 0..63 {
+            setup.vic.clock();
+            if setup.ba_line.borrow().is_low() {
+                ba_cycles += 1;
+            }
+        }
+        let bad_line = line >= 0x30 && line <= 0xf7 && line & 0x07 == 5;
+        assert_eq!(if bad_line { 43 } else { 0 }, ba_cycles, "line {}", line);
+    }
+}
+
+#[test]
+fn frame_timing_variants() {
+    for model in [
+        SystemModel::c64_pal(),
+        SystemModel::c64_ntsc(),
+        SystemModel::c64_ntsc_old(),
+    ]
+    .iter()
+    {
+        let mut setup = setup_vic_with_model(model.vic_model);
+        clock_to(&mut setup.vic, 0, 1);
+        let mut cycles = 0u32;
+        let mut last_line = 0;
+        loop {
+            setup.vic.clock();
+            cycles += 1;
+            let line = raster(&mut setup.vic);
+            if line == 0 && last_line != 0 {
+                break;
+            }
+            last_line = line;
+        }
+        assert_eq!(model.cycles_per_frame as u32, cycles, "{:?}", model.vic_model);
+        let expected_last_line = match model.vic_model {
+            VicModel::Mos6569 => 311,
+            VicModel::Mos6567 => 262,
+            VicModel::Mos6567R56A => 261,
+        };
+        assert_eq!(expected_last_line, last_line, "{:?}", model.vic_model);
+    }
+}
+
+#[test]
+fn cr2_read_back() {
+    let mut setup = setup_vic();
+    for &value in [0x00u8, 0x07, 0x08, 0x10, 0x1f, 0x15].iter() {
+        setup.vic.write(0x16, value); // CR2
+        assert_eq!(0xe0 | value, setup.vic.read(0x16));
+    }
+    setup.vic.write(0x16, 0xff);
+    assert_eq!(0xff, setup.vic.read(0x16));
+    setup.vic.write(0x16, 0xc0);
+    assert_eq!(0xe0, setup.vic.read(0x16));
+}
+
+#[test]
+fn light_pen_latch() {
+    let mut setup = setup_vic();
+    setup.vic.write(0x1a, 0x08); // IMR
+    clock_to(&mut setup.vic, 0x80, 30);
+    setup.vic.trigger_light_pen();
+    assert_eq!(((29 * 8 - 0x64) / 2) as u8, setup.vic.read(0x13)); // LPX
+    assert_eq!(0x80, setup.vic.read(0x14)); // LPY
+    assert_eq!(0x88, setup.vic.read(0x19) & 0x88); // IRR
+    clock_to(&mut setup.vic, 0x90, 10);
+    setup.vic.trigger_light_pen();
+    assert_eq!(0x80, setup.vic.read(0x14));
+    clock_to(&mut setup.vic, 0x90, 10);
+    setup.vic.trigger_light_pen();
+    assert_eq!(((9 * 8 + 0x194) / 2) as u8, setup.vic.read(0x13));
+    assert_eq!(0x90, setup.vic.read(0x14));
+}
+
+#[test]
+fn bank_3_fetches() {
+    let mut setup = setup_vic();
+    setup_text_screen(&mut setup, 0x01, 0x05);
+    for i in 0..8 {
+        setup.ram.borrow_mut().write(0x2008 + i, 0x00);
+        setup.ram.borrow_mut().write(0xe008 + i, GLYPH[i as usize]);
+    }
+    for i in 0..1000 {
+        setup.ram.borrow_mut().write(0xc400 + i, 0x01);
+    }
+    setup.ram.borrow_mut().write(0xc400 + 0x03f8, 0x02);
+    setup.vic.set_bank(3);
+    assert_eq!(0xc000, setup.base_address.get());
+    assert_eq!(0xc080, setup.vic.sprite_pointers()[0]);
+    assert_eq!(GLYPH, setup.vic.char_set()[1]);
+    render_frame(&mut setup);
+    for y in 0..8 {
+        let expected: Vec<u8> = (0..8)
+            .map(|x| if GLYPH[y] & (0x80 >> x) != 0 { 0x05 } else { 0x06 })
+            .collect();
+        assert_eq!(expected, display_row(&setup, y, 0, 8));
+    }
+}
+
+#[test]
+fn render_char_rom_shadow() {
+    let mut charset = vec![0u8; 0x1000];
+    charset[8..16].copy_from_slice(&GLYPH);
+    for &bank in [0u8, 2].iter() {
+        let mut setup = new_setup(VicModel::Mos6569, Rom::new_with_data(&charset, 0));
+        setup_text_screen(&mut setup, 0x01, 0x05);
+        let base = u16::from(bank) << 14;
+        for i in 0..0x1000 {
+            setup.ram.borrow_mut().write(base + 0x1000 + i, 0x5a);
+        }
+        for i in 0..1000 {
+            setup.ram.borrow_mut().write(base + 0x0400 + i, 0x01);
+        }
+        setup.vic.write(0x18, 0x14); // MEMPTR
+        setup.vic.set_bank(bank);
+        render_frame(&mut setup);
+        for y in 0..8 {
+            let expected: Vec<u8> = (0..8)
+                .map(|x| if GLYPH[y] & (0x80 >> x) != 0 { 0x05 } else { 0x06 })
+                .collect();
+            assert_eq!(expected, display_row(&setup, y, 0, 8), "bank {}", bank);
+        }
+    }
+}
+
+#[test]
+fn frame_complete_signal() {
+    let mut setup = setup_vic();
+    clock_to(&mut setup.vic, 0, 1);
+    assert_eq!(1, setup.vic.frame_count());
+    assert_eq!(1, setup.video.borrow().frames);
+    for _i in 0..2 * 312 * 63 {
+        setup.vic.clock();
+    }
+    assert_eq!(3, setup.vic.frame_count());
+    assert_eq!(3, setup.video.borrow().frames);
+}
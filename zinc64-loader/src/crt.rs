@@ -19,6 +19,7 @@ use crate::io::{self, ReadBytesExt, Reader};
 
 static HEADER_SIG: &'static str = "C64 CARTRIDGE   ";
 static CHIP_SIG: &'static str = "CHIP";
+const MAX_BANKS: u16 = 64;
 
 struct Header {
     signature: [u8; 16],
@@ -48,13 +49,59 @@ struct CrtImage {
 impl Image for CrtImage {
     fn mount(&mut self, c64: &mut C64) {
         info!(target: "loader", "Mounting CRT image");
-        c64.attach_cartridge(self.cartridge.take().unwrap());
+        c64.attach_cartridge(self.cartridge.take().unwrap(), false);
     }
     fn unmount(&mut self, c64: &mut C64) {
-        c64.detach_cartridge();
+        c64.detach_cartridge(true);
     }
 }
 
+/// Serialize the cartridge in CRT format. Flash based cartridges are written with
+/// their current content, erased banks are left out.
+pub fn build_crt_image(cartridge: &cartridge::Cartridge, name: &str) -> Vec<u8> {
+    let mut image = Vec::new();
+    image.extend_from_slice(HEADER_SIG.as_bytes());
+    image.extend_from_slice(&0x40u32.to_be_bytes());
+    image.extend_from_slice(&cartridge.get_version().to_be_bytes());
+    image.extend_from_slice(&cartridge.get_hw_type().value().to_be_bytes());
+    image.push(cartridge.get_exrom() as u8);
+    image.push(cartridge.get_game() as u8);
+    image.extend_from_slice(&[0u8; 6]);
+    let mut name_field = [0u8; 32];
+    let len = name.len().min(32);
+    name_field[..len].copy_from_slice(&name.as_bytes()[..len]);
+    image.extend_from_slice(&name_field);
+    let mut write_chip = |chip_type: u16, bank_number: u16, load_address: u16, data: &[u8]| {
+        image.extend_from_slice(CHIP_SIG.as_bytes());
+        image.extend_from_slice(&(0x10 + data.len() as u32).to_be_bytes());
+        image.extend_from_slice(&chip_type.to_be_bytes());
+        image.extend_from_slice(&bank_number.to_be_bytes());
+        image.extend_from_slice(&load_address.to_be_bytes());
+        image.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        image.extend_from_slice(data);
+    };
+    if cartridge.get_flash(0).is_some() {
+        for bank in 0..MAX_BANKS {
+            for (index, load_address) in [0x8000u16, 0xa000].iter().enumerate() {
+                let flash = cartridge.get_flash(index).unwrap().get_data();
+                let offset = bank as usize * 0x2000;
+                let data = &flash[offset..offset + 0x2000];
+                if data.iter().any(|byte| *byte != 0xff) {
+                    let chip_type = cartridge::ChipType::FlashRom.value();
+                    write_chip(chip_type, bank, *load_address, data);
+                }
+            }
+        }
+    } else {
+        for bank in 0..MAX_BANKS {
+            if let Some(chip) = cartridge.get_chip(bank as u8) {
+                write_chip(chip.chip_type.value(), bank, chip.offset, &chip.data);
+            }
+        }
+    }
+    image
+}
+
 pub struct CrtLoader;
 
 impl CrtLoader {
@@ -62,23 +109,61 @@ impl CrtLoader {
         Self {}
     }
 
-    fn build_cartridge(&self, header: &Header) -> cartridge::Cartridge {
-        cartridge::Cartridge::new(
+    fn build_cartridge(&self, header: &Header) -> io::Result<cartridge::Cartridge> {
+        Ok(cartridge::Cartridge::new(
             header.version,
-            cartridge::HwType::from(header.hw_type as u8),
+            cartridge::HwType::from(header.hw_type)?,
             header.exrom_line != 0,
             header.game_line != 0,
-        )
+        ))
     }
 
-    fn build_chip(&self, header: &ChipHeader, data: Vec<u8>) -> cartridge::Chip {
-        cartridge::Chip {
-            chip_type: cartridge::ChipType::from(header.chip_type),
+    fn build_chip(&self, header: &ChipHeader, data: Vec<u8>) -> io::Result<cartridge::Chip> {
+        Ok(cartridge::Chip {
+            chip_type: cartridge::ChipType::from(header.chip_type)?,
             bank_number: header.bank_number as u8,
             offset: header.load_address,
             size: header.image_size,
             data,
+        })
+    }
+
+    /// Parse the cartridge header and its CHIP packets.
+    pub fn read_cartridge(&self, reader: &mut dyn Reader) -> io::Result<cartridge::Cartridge> {
+        let header = self
+            .read_header(reader)
+            .map_err(|_| "invalid cartridge header".to_owned())?;
+        info!(target: "loader", "Found cartridge {}, version {}.{}, type {}",
+              str::from_utf8(&header.name).unwrap_or(""),
+              header.version >> 8,
+              header.version & 0xff,
+              header.hw_type);
+        self.validate_header(&header)?;
+        reader.consume((header.header_length - 0x40) as usize);
+        let mut cartridge = self.build_cartridge(&header)?;
+        loop {
+            let chip_header_opt = self
+                .read_chip_header(reader)
+                .map_err(|_| "invalid cartridge chip header".to_owned())?;
+            match chip_header_opt {
+                Some(chip_header) => {
+                    self.validate_chip_header(&chip_header)?;
+                    info!(target: "loader", "Found chip {}, type {}, offset 0x{:x}, size {}",
+                          chip_header.bank_number, chip_header.chip_type, chip_header.load_address, chip_header.length - 0x10);
+                    let chip_data = self
+                        .read_data(reader, (chip_header.length - 0x10) as usize)
+                        .map_err(|_| {
+                            format!("invalid cartridge chip {} data", chip_header.bank_number)
+                        })?;
+                    let chip = self.build_chip(&chip_header, chip_data)?;
+                    cartridge.add(chip);
+                }
+                None => {
+                    break;
+                }
+            }
         }
+        Ok(cartridge)
     }
 
     fn read_chip_header(&self, rdr: &mut dyn Reader) -> io::Result<Option<ChipHeader>> {
@@ -135,20 +220,28 @@ impl CrtLoader {
     fn validate_chip_header(&self, header: &ChipHeader) -> io::Result<()> {
         let sig =
             str::from_utf8(&header.signature).map_err(|_| "invalid chip signature".to_owned())?;
-        if sig == CHIP_SIG {
-            Ok(())
-        } else {
+        if sig != CHIP_SIG {
             Err("invalid chip signature".to_owned())
+        } else if header.length < 0x10 {
+            Err(format!("invalid chip {} length {}", header.bank_number, header.length))
+        } else if header.bank_number >= MAX_BANKS {
+            Err(format!("invalid chip bank {}", header.bank_number))
+        } else if ![0x8000, 0xa000, 0xe000, 0xf000].contains(&header.load_address) {
+            Err(format!("invalid chip load address 0x{:x}", header.load_address))
+        } else {
+            Ok(())
         }
     }
 
     fn validate_header(&self, header: &Header) -> io::Result<()> {
         let sig = str::from_utf8(&header.signature)
             .map_err(|_| "invalid cartridge signature".to_owned())?;
-        if sig == HEADER_SIG {
-            Ok(())
-        } else {
+        if sig != HEADER_SIG {
             Err("invalid cartridge signature".to_owned())
+        } else if header.header_length < 0x40 {
+            Err(format!("invalid cartridge header length {}", header.header_length))
+        } else {
+            Ok(())
         }
     }
 }
@@ -161,41 +254,110 @@ impl Loader for CrtLoader {
 
     fn load(&self, reader: &mut dyn Reader) -> io::Result<Box<dyn Image>> {
         info!(target: "loader", "Loading CRT");
-        let header = self
-            .read_header(reader)
-            .map_err(|_| "invalid cartridge header".to_owned())?;
-        info!(target: "loader", "Found cartridge {}, version {}.{}, type {}",
-              str::from_utf8(&header.name).unwrap_or(""),
-              header.version >> 8,
-              header.version & 0xff,
-              header.hw_type);
-        self.validate_header(&header)?;
-        reader.consume((header.header_length - 0x40) as usize);
-        let mut cartridge = self.build_cartridge(&header);
-        loop {
-            let chip_header_opt = self
-                .read_chip_header(reader)
-                .map_err(|_| "invalid cartridge chip header".to_owned())?;
-            match chip_header_opt {
-                Some(chip_header) => {
-                    info!(target: "loader", "Found chip {}, type {}, offset 0x{:x}, size {}",
-                          chip_header.bank_number, chip_header.chip_type, chip_header.load_address, chip_header.length - 0x10);
-                    self.validate_chip_header(&chip_header)?;
-                    let chip_data = self
-                        .read_data(reader, (chip_header.length - 0x10) as usize)
-                        .map_err(|_| {
-                            format!("invalid cartridge chip {} data", chip_header.bank_number)
-                        })?;
-                    let chip = self.build_chip(&chip_header, chip_data);
-                    cartridge.add(chip);
-                }
-                None => {
-                    break;
-                }
-            }
-        }
+        let cartridge = self.read_cartridge(reader)?;
         Ok(Box::new(CrtImage {
             cartridge: Some(cartridge),
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::SliceReader;
+    use zinc64_core::device::cartridge::{Cartridge, Chip, ChipType, HwType};
+
+    fn build_chip(bank_number: u16, fill: u8) -> Vec<u8> {
+        let mut chip = Vec::new();
+        chip.extend_from_slice(b"CHIP");
+        chip.extend_from_slice(&(0x10u32 + 0x2000).to_be_bytes());
+        chip.extend_from_slice(&0u16.to_be_bytes()); // ROM
+        chip.extend_from_slice(&bank_number.to_be_bytes());
+        chip.extend_from_slice(&0x8000u16.to_be_bytes());
+        chip.extend_from_slice(&0x2000u16.to_be_bytes());
+        chip.extend(vec![fill; 0x2000]);
+        chip
+    }
+
+    fn build_crt(hw_type: u16) -> Vec<u8> {
+        let mut image = Vec::new();
+        image.extend_from_slice(HEADER_SIG.as_bytes());
+        image.extend_from_slice(&0x40u32.to_be_bytes());
+        image.extend_from_slice(&0x0100u16.to_be_bytes());
+        image.extend_from_slice(&hw_type.to_be_bytes());
+        image.push(0); // EXROM
+        image.push(1); // GAME
+        image.extend_from_slice(&[0u8; 6]);
+        let mut name = [0u8; 32];
+        name[..4].copy_from_slice(b"TEST");
+        image.extend_from_slice(&name);
+        image.extend(build_chip(0, 0xaa));
+        image.extend(build_chip(1, 0x55));
+        image
+    }
+
+    #[test]
+    fn read_two_banks() {
+        let image = build_crt(19);
+        let loader = CrtLoader {};
+        let mut cartridge = loader
+            .read_cartridge(&mut SliceReader::new(&image))
+            .unwrap();
+        assert_eq!(HwType::MagicDesk, cartridge.get_hw_type());
+        assert_eq!(false, cartridge.get_exrom());
+        assert_eq!(true, cartridge.get_game());
+        assert_eq!(2, cartridge.get_chip_count());
+        for bank in 0..2 {
+            let chip = cartridge.get_chip(bank).unwrap();
+            assert_eq!(ChipType::Rom, chip.chip_type);
+            assert_eq!(bank, chip.bank_number);
+            assert_eq!(0x8000, chip.offset);
+            assert_eq!(0x2000, chip.size);
+            assert_eq!(0x2000, chip.data.len());
+        }
+        cartridge.reset();
+        assert_eq!(Some(0xaa), cartridge.read(0x8000));
+        cartridge.write(0xde00, 0x01);
+        assert_eq!(Some(0x55), cartridge.read(0x9fff));
+    }
+
+    #[test]
+    fn write_easyflash_image() {
+        let mut cartridge = Cartridge::new(0x0100, HwType::EasyFlash, true, false);
+        cartridge.add(Chip {
+            chip_type: ChipType::FlashRom,
+            bank_number: 2,
+            offset: 0xa000,
+            size: 0x2000,
+            data: vec![0x22; 0x2000],
+        });
+        let image = build_crt_image(&cartridge, "EASYFLASH");
+        let loader = CrtLoader {};
+        let cartridge = loader
+            .read_cartridge(&mut SliceReader::new(&image))
+            .unwrap();
+        assert_eq!(HwType::EasyFlash, cartridge.get_hw_type());
+        let flash = cartridge.get_flash(1).unwrap().get_data();
+        assert_eq!(0xff, flash[0x3fff]);
+        assert!(flash[0x4000..0x6000].iter().all(|byte| *byte == 0x22));
+        assert_eq!(0xff, flash[0x6000]);
+        assert!(cartridge.get_flash(0).unwrap().get_data().iter().all(|byte| *byte == 0xff));
+        // Only the programmed bank is written
+        assert_eq!(0x40 + 0x10 + 0x2000, image.len());
+    }
+
+    #[test]
+    fn reject_unsupported_hw_type() {
+        let image = build_crt(99);
+        let loader = CrtLoader {};
+        assert!(loader.read_cartridge(&mut SliceReader::new(&image)).is_err());
+    }
+
+    #[test]
+    fn reject_truncated_chip() {
+        let mut image = build_crt(0);
+        image.truncate(image.len() - 1);
+        let loader = CrtLoader {};
+        assert!(loader.read_cartridge(&mut SliceReader::new(&image)).is_err());
+    }
+}
@@ -0,0 +1,706 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, string::String, vec::Vec};
+#[cfg(feature = "drive")]
+use zinc64_core::drive::GcrDisk;
+use zinc64_core::factory::Disk;
+use zinc64_system::autostart;
+use zinc64_system::{Autostart, AutostartMethod, Image, C64};
+
+use super::Loader;
+use crate::io::{self, Reader};
+
+// SPEC: http://ist.uwaterloo.ca/~schepers/formats/D64.TXT
+// SPEC: http://ist.uwaterloo.ca/~schepers/formats/D71.TXT
+// SPEC: http://ist.uwaterloo.ca/~schepers/formats/D81.TXT
+
+const SECTOR_SIZE: usize = 256;
+const ENTRY_SIZE: usize = 0x20;
+const PADDING: u8 = 0xa0;
+const DIRECTORY_ADDRESS: u16 = 0x0401;
+
+/// Disk image formats, all of them share the directory entry and sector chain layout
+/// but differ in geometry and where the header and BAM are stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiskFormat {
+    /// 1541 single sided 5.25" disk with 35 or 40 tracks.
+    D64,
+    /// 1571 double sided 5.25" disk with 70 tracks.
+    D71,
+    /// 1581 3.5" disk with 80 tracks of 40 sectors.
+    D81,
+}
+
+impl DiskFormat {
+    /// Detect format and track count from the image size, with or without error info.
+    pub fn detect(size: usize) -> Option<(DiskFormat, u8)> {
+        [
+            (DiskFormat::D64, 35),
+            (DiskFormat::D64, 40),
+            (DiskFormat::D71, 70),
+            (DiskFormat::D81, 80),
+        ]
+        .iter()
+        .cloned()
+        .find(|(format, tracks)| {
+            let sectors = format.total_sectors(*tracks);
+            size == sectors * SECTOR_SIZE || size == sectors * (SECTOR_SIZE + 1)
+        })
+    }
+
+    pub fn sectors_per_track(self, track: u8) -> u8 {
+        match self {
+            DiskFormat::D64 => zone_sectors(track),
+            DiskFormat::D71 if track > 35 => zone_sectors(track - 35),
+            DiskFormat::D71 => zone_sectors(track),
+            DiskFormat::D81 => 40,
+        }
+    }
+
+    fn total_sectors(self, tracks: u8) -> usize {
+        (1..=tracks)
+            .map(|track| self.sectors_per_track(track) as usize)
+            .sum()
+    }
+
+    // Location of the sector holding the disk name and the directory link
+    fn header(self) -> (u8, u8) {
+        match self {
+            DiskFormat::D64 | DiskFormat::D71 => (18, 0),
+            DiskFormat::D81 => (40, 0),
+        }
+    }
+
+    fn name_offset(self) -> usize {
+        match self {
+            DiskFormat::D64 | DiskFormat::D71 => 0x90,
+            DiskFormat::D81 => 0x04,
+        }
+    }
+
+    fn id_offset(self) -> usize {
+        match self {
+            DiskFormat::D64 | DiskFormat::D71 => 0xa2,
+            DiskFormat::D81 => 0x16,
+        }
+    }
+}
+
+fn zone_sectors(track: u8) -> u8 {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+/// File stored in the disk directory. Name is in PETSCII without the padding.
+pub struct DiskEntry {
+    pub name: Vec<u8>,
+    pub file_type: u8,
+    pub blocks: u16,
+    track: u8,
+    sector: u8,
+}
+
+impl DiskEntry {
+    pub fn is_closed(&self) -> bool {
+        self.file_type & 0x80 != 0
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.file_type & 0x40 != 0
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.file_type & 0x07 {
+            0 => "DEL",
+            1 => "SEQ",
+            2 => "PRG",
+            3 => "USR",
+            4 => "REL",
+            _ => "???",
+        }
+    }
+}
+
+/// Commodore DOS disk image in any of the supported formats.
+pub struct CbmDisk {
+    data: Vec<u8>,
+    format: DiskFormat,
+    tracks: u8,
+}
+
+impl CbmDisk {
+    /// Parse a disk image, the format is detected from its size.
+    pub fn parse(data: Vec<u8>) -> io::Result<CbmDisk> {
+        let (format, tracks) = DiskFormat::detect(data.len())
+            .ok_or_else(|| format!("invalid disk image size {}", data.len()))?;
+        let disk = CbmDisk {
+            data,
+            format,
+            tracks,
+        };
+        disk.get_entries()?;
+        Ok(disk)
+    }
+
+    pub fn get_format(&self) -> DiskFormat {
+        self.format
+    }
+
+    /// Disk name in PETSCII without the padding.
+    pub fn get_name(&self) -> Vec<u8> {
+        let offset = self.format.name_offset();
+        strip_padding(&self.header()[offset..offset + 16])
+    }
+
+    pub fn get_blocks_free(&self) -> u16 {
+        match self.format {
+            DiskFormat::D64 | DiskFormat::D71 => {
+                let bam = self.header();
+                let side_1 = (1..=35usize)
+                    .filter(|track| *track != 18)
+                    .map(|track| bam[track * 4] as u16)
+                    .sum::<u16>();
+                // Free counts of the second side are kept after the first side BAM
+                let side_2 = match self.format {
+                    DiskFormat::D71 => (36..=70usize)
+                        .filter(|track| *track != 53)
+                        .map(|track| bam[0xdd + track - 36] as u16)
+                        .sum::<u16>(),
+                    _ => 0,
+                };
+                side_1 + side_2
+            }
+            DiskFormat::D81 => (1..=80u8)
+                .filter(|track| *track != 40)
+                .map(|track| {
+                    let (sector, index) = if track <= 40 {
+                        (1, track - 1)
+                    } else {
+                        (2, track - 41)
+                    };
+                    let bam = self.sector(40, sector).unwrap();
+                    bam[0x10 + index as usize * 6] as u16
+                })
+                .sum(),
+        }
+    }
+
+    pub fn get_entries(&self) -> io::Result<Vec<DiskEntry>> {
+        let mut entries = Vec::new();
+        let header = self.header();
+        let mut next = (header[0], header[1]);
+        let mut visited = 0;
+        while next.0 != 0 {
+            visited += 1;
+            if visited > self.sector_count() {
+                return Err("directory chain is looped".to_owned());
+            }
+            let sector = self.sector(next.0, next.1)?;
+            for record in sector.chunks(ENTRY_SIZE) {
+                if record[2] == 0 {
+                    continue;
+                }
+                entries.push(DiskEntry {
+                    name: strip_padding(&record[0x05..0x15]),
+                    file_type: record[2],
+                    blocks: record[0x1e] as u16 | (record[0x1f] as u16) << 8,
+                    track: record[3],
+                    sector: record[4],
+                });
+            }
+            next = (sector[0], sector[1]);
+        }
+        Ok(entries)
+    }
+
+    /// Find the first closed file whose name matches `pattern`, supporting the DOS
+    /// wildcards `*` and `?` and an optional drive prefix.
+    pub fn find_file(&self, pattern: &[u8]) -> Option<DiskEntry> {
+        let pattern = match pattern.iter().position(|c| *c == b':') {
+            Some(index) => &pattern[index + 1..],
+            None => pattern,
+        };
+        self.get_entries()
+            .ok()?
+            .into_iter()
+            .filter(|entry| entry.is_closed() && entry.file_type & 0x07 != 0)
+            .find(|entry| matches_pattern(&entry.name, pattern))
+    }
+
+    /// Read file content by following its sector chain.
+    pub fn read_entry(&self, entry: &DiskEntry) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for (track, sector) in self.get_sector_chain(entry)? {
+            let sector = self.sector(track, sector)?;
+            if sector[0] == 0 {
+                // Last sector, the link holds the index of the last used byte
+                let last = (sector[1] as usize).max(1);
+                data.extend_from_slice(&sector[2..=last]);
+            } else {
+                data.extend_from_slice(&sector[2..]);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Track and sector of each block of the file in chain order.
+    pub fn get_sector_chain(&self, entry: &DiskEntry) -> io::Result<Vec<(u8, u8)>> {
+        let mut chain = Vec::new();
+        let mut next = (entry.track, entry.sector);
+        loop {
+            if chain.len() >= self.sector_count() {
+                return Err(format!(
+                    "sector chain of {} is looped",
+                    String::from_utf8_lossy(&entry.name)
+                ));
+            }
+            let sector = self.sector(next.0, next.1)?;
+            chain.push(next);
+            if sector[0] == 0 {
+                break;
+            }
+            next = (sector[0], sector[1]);
+        }
+        Ok(chain)
+    }
+
+    fn build_directory(&self) -> Vec<u8> {
+        let mut lines = Vec::new();
+        let header = self.header();
+        let name_offset = self.format.name_offset();
+        let id_offset = self.format.id_offset();
+        let mut title = vec![0x12, b'"'];
+        title.extend(header[name_offset..name_offset + 16].iter().map(|c| unpad(*c)));
+        title.extend_from_slice(&[b'"', b' ']);
+        title.extend(header[id_offset..id_offset + 5].iter().map(|c| unpad(*c)));
+        lines.push((0, title));
+        for entry in self.get_entries().unwrap_or_default() {
+            let mut text = Vec::new();
+            let indent = match entry.blocks {
+                0..=9 => 3,
+                10..=99 => 2,
+                _ => 1,
+            };
+            text.extend(core::iter::repeat(b' ').take(indent));
+            text.push(b'"');
+            text.extend_from_slice(&entry.name);
+            text.push(b'"');
+            text.extend(core::iter::repeat(b' ').take(16 - entry.name.len().min(16)));
+            text.push(if entry.is_closed() { b' ' } else { b'*' });
+            text.extend_from_slice(entry.type_name().as_bytes());
+            text.push(if entry.is_locked() { b'<' } else { b' ' });
+            lines.push((entry.blocks, text));
+        }
+        lines.push((self.get_blocks_free(), b"BLOCKS FREE.".to_vec()));
+        let mut program = vec![DIRECTORY_ADDRESS as u8, (DIRECTORY_ADDRESS >> 8) as u8];
+        let mut address = DIRECTORY_ADDRESS;
+        for (number, text) in lines {
+            address += 4 + text.len() as u16 + 1;
+            program.extend_from_slice(&address.to_le_bytes());
+            program.extend_from_slice(&number.to_le_bytes());
+            program.extend_from_slice(&text);
+            program.push(0);
+        }
+        program.extend_from_slice(&[0, 0]);
+        program
+    }
+
+    fn header(&self) -> &[u8] {
+        let (track, sector) = self.format.header();
+        self.sector(track, sector).unwrap()
+    }
+
+    fn sector(&self, track: u8, sector: u8) -> io::Result<&[u8]> {
+        if track == 0 || track > self.tracks || sector >= self.format.sectors_per_track(track) {
+            return Err(format!("invalid track {} sector {}", track, sector));
+        }
+        let index = self.format.total_sectors(track - 1) + sector as usize;
+        let offset = index * SECTOR_SIZE;
+        Ok(&self.data[offset..offset + SECTOR_SIZE])
+    }
+
+    fn sector_count(&self) -> usize {
+        self.format.total_sectors(self.tracks)
+    }
+}
+
+impl Disk for CbmDisk {
+    fn read_directory(&self) -> Vec<u8> {
+        self.build_directory()
+    }
+
+    fn read_file(&self, pattern: &[u8]) -> Option<Vec<u8>> {
+        let entry = self.find_file(pattern)?;
+        self.read_entry(&entry).ok()
+    }
+
+    fn read_file_sectors(&self, pattern: &[u8]) -> Vec<(u8, u8)> {
+        self.find_file(pattern)
+            .and_then(|entry| self.get_sector_chain(&entry).ok())
+            .unwrap_or_default()
+    }
+}
+
+fn matches_pattern(name: &[u8], pattern: &[u8]) -> bool {
+    let mut name = name.iter();
+    for c in pattern {
+        match *c {
+            b'*' => return true,
+            b'?' => {
+                if name.next().is_none() {
+                    return false;
+                }
+            }
+            c => {
+                if name.next() != Some(&c) {
+                    return false;
+                }
+            }
+        }
+    }
+    name.next().is_none()
+}
+
+fn strip_padding(data: &[u8]) -> Vec<u8> {
+    let len = data.iter().position(|c| *c == PADDING).unwrap_or(data.len());
+    data[..len].to_vec()
+}
+
+fn unpad(c: u8) -> u8 {
+    if c == PADDING {
+        b' '
+    } else {
+        c
+    }
+}
+
+struct DiskImage {
+    disk: Option<CbmDisk>,
+}
+
+impl Image for DiskImage {
+    fn mount(&mut self, c64: &mut C64) {
+        info!(target: "loader", "Mounting disk image");
+        if let Some(disk) = self.disk.take() {
+            #[cfg(feature = "drive")]
+            Self::insert_into_drive(&disk, c64);
+            c64.attach_disk(Box::new(disk));
+        }
+    }
+
+    fn unmount(&mut self, c64: &mut C64) {
+        #[cfg(feature = "drive")]
+        {
+            if let Some(drive) = c64.get_drive() {
+                drive.borrow_mut().detach_disk();
+            }
+        }
+        c64.detach_disk();
+    }
+}
+
+impl DiskImage {
+    /// The emulated 1541 gets the D64 image as GCR media, LOAD is still served by
+    /// the kernal trap while everything else goes over the bus to the drive.
+    #[cfg(feature = "drive")]
+    fn insert_into_drive(disk: &CbmDisk, c64: &mut C64) {
+        if let Some(drive) = c64.get_drive() {
+            if disk.format == DiskFormat::D64 {
+                match GcrDisk::from_d64(&disk.data) {
+                    Ok(gcr_disk) => drive.borrow_mut().attach_disk(gcr_disk),
+                    Err(err) => {
+                        warn!(target: "loader", "Cannot insert disk into drive: {}", err)
+                    }
+                }
+            } else {
+                warn!(target: "loader", "Drive 1541 only takes D64 images");
+            }
+        }
+    }
+}
+
+/// Loader for D64, D71 and D81 images, the format is detected from the image size.
+pub struct DiskLoader;
+
+impl DiskLoader {
+    pub fn new() -> impl Loader {
+        Self {}
+    }
+}
+
+impl Loader for DiskLoader {
+    fn autostart(&self, reader: &mut dyn Reader) -> io::Result<AutostartMethod> {
+        let image = self.load(reader)?;
+        let autostart = Autostart::new(autostart::Mode::LoadDiskAndRun, image);
+        Ok(AutostartMethod::WithAutostart(Some(autostart)))
+    }
+
+    fn load(&self, reader: &mut dyn Reader) -> io::Result<Box<dyn Image>> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let disk = CbmDisk::parse(data)?;
+        info!(target: "loader", "Loading {:?}", disk.get_format());
+        for entry in disk.get_entries()? {
+            info!(
+                target: "loader",
+                "Found file {}, type {}, blocks {}",
+                String::from_utf8_lossy(&entry.name),
+                entry.type_name(),
+                entry.blocks
+            );
+        }
+        Ok(Box::new(DiskImage { disk: Some(disk) }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Layout {
+        format: DiskFormat,
+        tracks: u8,
+        header: (u8, u8),
+        directory: (u8, u8),
+        chain: [(u8, u8); 2],
+    }
+
+    static D64_LAYOUT: Layout = Layout {
+        format: DiskFormat::D64,
+        tracks: 35,
+        header: (18, 0),
+        directory: (18, 1),
+        chain: [(17, 0), (17, 10)],
+    };
+
+    static D71_LAYOUT: Layout = Layout {
+        format: DiskFormat::D71,
+        tracks: 70,
+        header: (18, 0),
+        directory: (18, 1),
+        chain: [(17, 0), (36, 20)],
+    };
+
+    static D81_LAYOUT: Layout = Layout {
+        format: DiskFormat::D81,
+        tracks: 80,
+        header: (40, 0),
+        directory: (40, 3),
+        chain: [(39, 0), (41, 39)],
+    };
+
+    fn offset(layout: &Layout, track: u8, sector: u8) -> usize {
+        (layout.format.total_sectors(track - 1) + sector as usize) * SECTOR_SIZE
+    }
+
+    fn padded(name: &[u8]) -> Vec<u8> {
+        let mut data = name.to_vec();
+        data.resize(16, PADDING);
+        data
+    }
+
+    fn build_program() -> Vec<u8> {
+        let mut program = vec![0x00, 0xc0];
+        program.extend((0..300).map(|i| i as u8));
+        program
+    }
+
+    fn build_image(layout: &Layout) -> Vec<u8> {
+        let mut image = vec![0u8; layout.format.total_sectors(layout.tracks) * SECTOR_SIZE];
+        // Header and BAM
+        let header = offset(layout, layout.header.0, layout.header.1);
+        image[header] = layout.directory.0;
+        image[header + 1] = layout.directory.1;
+        match layout.format {
+            DiskFormat::D64 | DiskFormat::D71 => {
+                image[header + 0x90..header + 0xa0].copy_from_slice(&padded(b"TEST DISK"));
+                image[header + 0xa2..header + 0xa7].copy_from_slice(b"ZC\xa02A");
+                for track in 1..=35 {
+                    image[header + track * 4] = if track == 18 { 2 } else { 10 };
+                }
+                if layout.format == DiskFormat::D71 {
+                    image[header + 3] = 0x80;
+                    for track in 36..=70 {
+                        image[header + 0xdd + track - 36] = if track == 53 { 0 } else { 5 };
+                    }
+                }
+            }
+            DiskFormat::D81 => {
+                image[header + 0x04..header + 0x14].copy_from_slice(&padded(b"TEST DISK"));
+                image[header + 0x16..header + 0x1b].copy_from_slice(b"ZC\xa03D");
+                for (sector, first) in [(1u8, 1usize), (2, 41)].iter() {
+                    let bam = offset(layout, 40, *sector);
+                    for track in *first..*first + 40 {
+                        let free = if track == 40 { 36 } else { 20 };
+                        image[bam + 0x10 + (track - first) * 6] = free;
+                    }
+                }
+            }
+        }
+        // Directory
+        let dir = offset(layout, layout.directory.0, layout.directory.1);
+        image[dir..dir + 2].copy_from_slice(&[0x00, 0xff]);
+        let mut entry = |index: usize, file_type: u8, name: &[u8], ts: (u8, u8), blocks: u8| {
+            let base = dir + index * ENTRY_SIZE;
+            image[base + 2] = file_type;
+            image[base + 3] = ts.0;
+            image[base + 4] = ts.1;
+            image[base + 5..base + 0x15].copy_from_slice(&padded(name));
+            image[base + 0x1e] = blocks;
+        };
+        entry(0, 0x00, b"SCRATCHED", (19, 5), 1);
+        entry(1, 0x82, b"HELLO", layout.chain[0], 2);
+        entry(2, 0x81, b"NOTES", (19, 0), 1);
+        // HELLO spans two sectors
+        let program = build_program();
+        let first = offset(layout, layout.chain[0].0, layout.chain[0].1);
+        image[first..first + 2].copy_from_slice(&[layout.chain[1].0, layout.chain[1].1]);
+        image[first + 2..first + SECTOR_SIZE].copy_from_slice(&program[..254]);
+        let second = offset(layout, layout.chain[1].0, layout.chain[1].1);
+        let rest = &program[254..];
+        image[second..second + 2].copy_from_slice(&[0, 1 + rest.len() as u8]);
+        image[second + 2..second + 2 + rest.len()].copy_from_slice(rest);
+        // NOTES
+        let notes = offset(layout, 19, 0);
+        image[notes..notes + 5].copy_from_slice(&[0, 4, b'A', b'B', b'C']);
+        image
+    }
+
+    fn assert_directory(disk: &CbmDisk) {
+        assert_eq!(b"TEST DISK".to_vec(), disk.get_name());
+        let entries = disk.get_entries().unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(b"HELLO".to_vec(), entries[0].name);
+        assert_eq!("PRG", entries[0].type_name());
+        assert_eq!(2, entries[0].blocks);
+        assert_eq!(b"NOTES".to_vec(), entries[1].name);
+        assert_eq!("SEQ", entries[1].type_name());
+    }
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(Some((DiskFormat::D64, 35)), DiskFormat::detect(174_848));
+        assert_eq!(Some((DiskFormat::D64, 35)), DiskFormat::detect(175_531));
+        assert_eq!(Some((DiskFormat::D64, 40)), DiskFormat::detect(196_608));
+        assert_eq!(Some((DiskFormat::D71, 70)), DiskFormat::detect(349_696));
+        assert_eq!(Some((DiskFormat::D71, 70)), DiskFormat::detect(351_062));
+        assert_eq!(Some((DiskFormat::D81, 80)), DiskFormat::detect(819_200));
+        assert_eq!(Some((DiskFormat::D81, 80)), DiskFormat::detect(822_400));
+        assert_eq!(None, DiskFormat::detect(1000));
+    }
+
+    #[test]
+    fn parse_d64_directory() {
+        let disk = CbmDisk::parse(build_image(&D64_LAYOUT)).unwrap();
+        assert_eq!(DiskFormat::D64, disk.get_format());
+        assert_directory(&disk);
+        assert_eq!(34 * 10, disk.get_blocks_free());
+    }
+
+    #[test]
+    fn parse_d71_directory() {
+        let disk = CbmDisk::parse(build_image(&D71_LAYOUT)).unwrap();
+        assert_eq!(DiskFormat::D71, disk.get_format());
+        assert_directory(&disk);
+        assert_eq!(34 * 10 + 34 * 5, disk.get_blocks_free());
+    }
+
+    #[test]
+    fn parse_d81_directory() {
+        let disk = CbmDisk::parse(build_image(&D81_LAYOUT)).unwrap();
+        assert_eq!(DiskFormat::D81, disk.get_format());
+        assert_directory(&disk);
+        assert_eq!(79 * 20, disk.get_blocks_free());
+    }
+
+    #[test]
+    fn read_file() {
+        let disk = CbmDisk::parse(build_image(&D64_LAYOUT)).unwrap();
+        assert_eq!(Some(build_program()), disk.read_file(b"HELLO"));
+        assert_eq!(Some(build_program()), disk.read_file(b"0:HEL*"));
+        assert_eq!(Some(build_program()), disk.read_file(b"*"));
+        assert_eq!(Some(b"ABC".to_vec()), disk.read_file(b"N?TES"));
+        assert_eq!(None, disk.read_file(b"HELL"));
+        assert_eq!(None, disk.read_file(b"SCRATCHED"));
+    }
+
+    #[test]
+    fn read_file_sectors() {
+        let disk = CbmDisk::parse(build_image(&D64_LAYOUT)).unwrap();
+        assert_eq!(D64_LAYOUT.chain.to_vec(), disk.read_file_sectors(b"HELLO"));
+        assert_eq!(vec![(19, 0)], disk.read_file_sectors(b"NOTES"));
+        assert!(disk.read_file_sectors(b"HELL").is_empty());
+    }
+
+    #[test]
+    fn read_file_from_second_side() {
+        let disk = CbmDisk::parse(build_image(&D71_LAYOUT)).unwrap();
+        assert_eq!(Some(build_program()), disk.read_file(b"HELLO"));
+        assert_eq!(Some(b"ABC".to_vec()), disk.read_file(b"NOTES"));
+    }
+
+    #[test]
+    fn read_file_from_d81() {
+        let disk = CbmDisk::parse(build_image(&D81_LAYOUT)).unwrap();
+        assert_eq!(Some(build_program()), disk.read_file(b"HELLO"));
+        assert_eq!(Some(b"ABC".to_vec()), disk.read_file(b"NOTES"));
+    }
+
+    #[test]
+    fn read_directory_listing() {
+        let disk = CbmDisk::parse(build_image(&D64_LAYOUT)).unwrap();
+        let listing = disk.read_directory();
+        assert_eq!(&[0x01, 0x04], &listing[..2]);
+        // First line links to the second and is numbered 0
+        let header = b"\x12\"TEST DISK       \" ZC 2A";
+        let link = listing[2] as u16 | (listing[3] as u16) << 8;
+        assert_eq!(0x0401 + 4 + header.len() as u16 + 1, link);
+        assert_eq!(&[0, 0], &listing[4..6]);
+        assert_eq!(&header[..], &listing[6..6 + header.len()]);
+        let text = String::from_utf8_lossy(&listing);
+        assert!(text.contains("   \"HELLO\"            PRG "));
+        assert!(text.contains("BLOCKS FREE."));
+        assert_eq!(&[0, 0, 0], &listing[listing.len() - 3..]);
+    }
+
+    #[test]
+    fn read_d81_directory_listing() {
+        let disk = CbmDisk::parse(build_image(&D81_LAYOUT)).unwrap();
+        let listing = disk.read_directory();
+        let header = b"\x12\"TEST DISK       \" ZC 3D";
+        assert_eq!(&header[..], &listing[6..6 + header.len()]);
+    }
+
+    #[test]
+    fn parse_40_tracks_with_error_info() {
+        let layout = Layout {
+            tracks: 40,
+            ..D64_LAYOUT
+        };
+        let mut image = build_image(&layout);
+        image.extend(vec![0x01; DiskFormat::D64.total_sectors(40)]);
+        let disk = CbmDisk::parse(image).unwrap();
+        assert!(disk.sector(40, 16).is_ok());
+        assert!(disk.sector(40, 17).is_err());
+        assert!(disk.sector(41, 0).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_image() {
+        assert!(CbmDisk::parse(vec![0u8; 1000]).is_err());
+        let mut image = build_image(&D64_LAYOUT);
+        // Directory sector linking to itself
+        let dir = offset(&D64_LAYOUT, 18, 1);
+        image[dir..dir + 2].copy_from_slice(&[18, 1]);
+        assert!(CbmDisk::parse(image).is_err());
+    }
+}
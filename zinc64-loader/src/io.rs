@@ -40,3 +40,43 @@ pub trait ReadBytesExt: Reader {
 }
 
 impl<R: Reader + ?Sized> ReadBytesExt for R {}
+
+/// Reader over an in-memory image.
+pub struct SliceReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+}
+
+impl<'a> Reader for SliceReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let count = buf.len().min(self.data.len() - self.pos);
+        buf[..count].copy_from_slice(&self.data[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let count = self.data.len() - self.pos;
+        buf.extend_from_slice(&self.data[self.pos..]);
+        self.pos += count;
+        Ok(count)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        if self.read(buf)? == buf.len() {
+            Ok(())
+        } else {
+            Err(String::from("unexpected end of image"))
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.data.len());
+    }
+}
@@ -14,23 +14,38 @@ extern crate log;
 
 mod bin;
 mod crt;
+mod disk;
 mod io;
 mod p00;
 mod prg;
+mod rom;
+mod t64;
 mod tap;
+#[cfg(test)]
+mod test_util;
 
 #[cfg(not(feature = "std"))]
 use alloc::boxed::Box;
-use zinc64_emu::system::{AutostartMethod, Image};
+use zinc64_system::{AutostartMethod, Image, C64};
 
 pub use crate::bin::BinLoader;
-pub use crate::io::{Reader, Result};
+pub use crate::crt::build_crt_image;
+pub use crate::disk::{CbmDisk, DiskEntry, DiskFormat, DiskLoader};
+pub use crate::io::{Reader, Result, SliceReader};
+pub use crate::rom::RomLoader;
+pub use crate::t64::{T64Archive, T64Entry, T64Loader};
+pub use crate::tap::build_tap_image;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Format {
     Bin,
     Crt,
+    D64,
+    D71,
+    D81,
     P00,
     Prg,
+    T64,
     Tap,
 }
 
@@ -39,13 +54,32 @@ impl Format {
         match ext {
             Some("bin") => Some(Format::Bin),
             Some("crt") => Some(Format::Crt),
+            Some("d64") => Some(Format::D64),
+            Some("d71") => Some(Format::D71),
+            Some("d81") => Some(Format::D81),
             Some("p00") => Some(Format::P00),
             Some("P00") => Some(Format::P00),
             Some("prg") => Some(Format::Prg),
+            Some("t64") => Some(Format::T64),
             Some("tap") => Some(Format::Tap),
             _ => None,
         }
     }
+
+    /// Detect formats that carry a signature in their header.
+    pub fn from_magic(data: &[u8]) -> Option<Format> {
+        if data.starts_with(b"C64 CARTRIDGE   ") {
+            Some(Format::Crt)
+        } else if data.starts_with(b"C64File\0") {
+            Some(Format::P00)
+        } else if data.starts_with(b"C64-TAPE-RAW") {
+            Some(Format::Tap)
+        } else if data.starts_with(b"C64S tape") || data.starts_with(b"C64 tape image") {
+            Some(Format::T64)
+        } else {
+            None
+        }
+    }
 }
 
 pub trait Loader {
@@ -60,8 +94,10 @@ impl Loaders {
         match kind {
             Format::Bin => Box::new(bin::BinLoader::new(1024)),
             Format::Crt => Box::new(crt::CrtLoader::new()),
+            Format::D64 | Format::D71 | Format::D81 => Box::new(disk::DiskLoader::new()),
             Format::P00 => Box::new(p00::P00Loader::new()),
             Format::Prg => Box::new(prg::PrgLoader::new()),
+            Format::T64 => Box::new(t64::T64Loader::new()),
             Format::Tap => Box::new(tap::TapLoader::new()),
         }
     }
@@ -74,3 +110,112 @@ impl Loaders {
         }
     }
 }
+
+/// Attach or load any supported image and autostart it, the single entry point a
+/// front-end needs for e.g. drag and drop.
+///
+/// The format is taken from `format` if specified, otherwise it is detected from the
+/// header signature and then from the filename extension. Images without a signature,
+/// like raw binaries that could pass for a PRG, can be disambiguated with `format`.
+///
+/// SID tunes and G64 images are not supported since there is no SID player and the
+/// drive only takes D64 images, they are rejected with a descriptive error.
+pub trait LoadAny {
+    fn load_any(&mut self, data: &[u8], filename: &str, format: Option<Format>) -> Result<()>;
+}
+
+impl LoadAny for C64 {
+    fn load_any(&mut self, data: &[u8], filename: &str, format: Option<Format>) -> Result<()> {
+        let ext = filename
+            .rsplit('.')
+            .next()
+            .filter(|ext| ext.len() < filename.len())
+            .map(|ext| ext.to_ascii_lowercase());
+        let ext = ext.as_ref().map(|ext| ext.as_str());
+        if format.is_none() {
+            if let Some(kind) = unsupported_format(data, ext) {
+                return Err(format!("{} are not supported, cannot load {}", kind, filename));
+            }
+        }
+        let kind = format
+            .or_else(|| Format::from_magic(data))
+            .or_else(|| Format::from_ext(ext))
+            .ok_or_else(|| match ext {
+                Some(ext) => format!("Unsupported image format {} for {}", ext, filename),
+                None => format!("Unrecognized image format for {}", filename),
+            })?;
+        let loader = Loaders::from(kind);
+        let mut reader = SliceReader::new(data);
+        let mut autostart = loader.autostart(&mut reader)?;
+        autostart.execute(self);
+        Ok(())
+    }
+}
+
+fn unsupported_format(data: &[u8], ext: Option<&str>) -> Option<&'static str> {
+    if data.starts_with(b"PSID") || data.starts_with(b"RSID") || ext == Some("sid") {
+        Some("SID tunes")
+    } else if data.starts_with(b"GCR-1541") || ext == Some("g64") {
+        Some("G64 images")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::setup_c64_with_roms;
+    use zinc64_system::ResetKind;
+
+    #[test]
+    fn load_any_prg_runs_program() {
+        let mut c64 = setup_c64_with_roms();
+        c64.reset(ResetKind::Hard);
+        // 10 POKE49152,42
+        let prg = [
+            0x01, 0x08, 0x0f, 0x08, 0x0a, 0x00, 0x97, 0x34, 0x39, 0x31, 0x35, 0x32, 0x2c,
+            0x34, 0x32, 0x00, 0x00, 0x00,
+        ];
+        c64.load_any(&prg, "POKE.PRG", None).unwrap();
+        for _ in 0..300 {
+            // Type the queued RUN command one key event per frame
+            if c64.get_keyboard().has_events() {
+                c64.get_keyboard().drain_event();
+            }
+            c64.run_frame();
+            c64.reset_vsync();
+        }
+        assert_eq!(0x97, c64.get_cpu().read(0x0805));
+        assert_eq!(42, c64.get_cpu().read(0xc000));
+    }
+
+    #[test]
+    fn load_any_rejects_unsupported_formats() {
+        let mut c64 = setup_c64_with_roms();
+        let err = c64.load_any(b"PSID\x00\x02", "tune.bin", None).unwrap_err();
+        assert!(err.contains("SID tunes"), "{}", err);
+        let err = c64.load_any(&[0u8; 16], "disk.g64", None).unwrap_err();
+        assert!(err.contains("G64 images"), "{}", err);
+        let err = c64.load_any(&[0u8; 16], "notes.txt", None).unwrap_err();
+        assert!(err.contains("txt"), "{}", err);
+    }
+
+    #[test]
+    fn detect_from_magic() {
+        assert_eq!(Some(Format::Crt), Format::from_magic(b"C64 CARTRIDGE   \0\0\0\x40"));
+        assert_eq!(Some(Format::P00), Format::from_magic(b"C64File\0GAME"));
+        assert_eq!(Some(Format::Tap), Format::from_magic(b"C64-TAPE-RAW\x01"));
+        assert_eq!(Some(Format::T64), Format::from_magic(b"C64S tape image file"));
+        assert_eq!(None, Format::from_magic(&[0x01, 0x08, 0x0b, 0x08]));
+    }
+
+    #[test]
+    fn slice_reader() {
+        let mut reader = SliceReader::new(&[1, 2, 3, 4]);
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!([1, 2, 3], buf);
+        assert!(reader.read_exact(&mut buf).is_err());
+    }
+}
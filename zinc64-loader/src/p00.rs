@@ -34,7 +34,9 @@ struct P00Image {
 impl Image for P00Image {
     fn mount(&mut self, c64: &mut C64) {
         info!(target: "loader", "Mounting P00 image");
-        c64.load(&self.data, self.offset);
+        if let Err(err) = c64.load_program(&self.data, self.offset) {
+            error!(target: "loader", "Failed to load program, error: {}", err);
+        }
     }
 
     fn unmount(&mut self, _c64: &mut C64) {}
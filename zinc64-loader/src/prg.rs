@@ -11,15 +11,23 @@ use zinc64_system::{Autostart, AutostartMethod, Image, C64};
 use super::Loader;
 use crate::io::{self, ReadBytesExt, Reader};
 
-struct PrgImage {
+pub(crate) struct PrgImage {
     data: Vec<u8>,
     offset: u16,
 }
 
+impl PrgImage {
+    pub fn new(data: Vec<u8>, offset: u16) -> Self {
+        PrgImage { data, offset }
+    }
+}
+
 impl Image for PrgImage {
     fn mount(&mut self, c64: &mut C64) {
         info!(target: "loader", "Mounting PRG image");
-        c64.load(&self.data, self.offset);
+        if let Err(err) = c64.load_program(&self.data, self.offset) {
+            error!(target: "loader", "Failed to load program, error: {}", err);
+        }
     }
 
     fn unmount(&mut self, _c64: &mut C64) {}
@@ -46,6 +54,6 @@ impl Loader for PrgLoader {
         let mut data = Vec::new();
         reader.read_to_end(&mut data)?;
         info!(target: "loader", "Program offset 0x{:x}, size {}", offset, data.len());
-        Ok(Box::new(PrgImage { data, offset }))
+        Ok(Box::new(PrgImage::new(data, offset)))
     }
 }
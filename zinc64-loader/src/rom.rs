@@ -0,0 +1,168 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#[cfg(not(feature = "std"))]
+use alloc::{borrow::ToOwned, boxed::Box, vec::Vec};
+use zinc64_core::device::cartridge::{Cartridge, Chip, ChipType, HwType};
+use zinc64_system::{AutostartMethod, Image, C64};
+
+use super::Loader;
+use crate::io::{self, Reader};
+
+static CBM80_SIG: [u8; 5] = [0xc3, 0xc2, 0xcd, 0x38, 0x30];
+
+struct RomImage {
+    cartridge: Option<Cartridge>,
+}
+
+impl Image for RomImage {
+    fn mount(&mut self, c64: &mut C64) {
+        info!(target: "loader", "Mounting ROM image");
+        c64.attach_cartridge(self.cartridge.take().unwrap(), false);
+    }
+
+    fn unmount(&mut self, c64: &mut C64) {
+        c64.detach_cartridge(true);
+    }
+}
+
+/// Loader for raw cartridge ROM dumps without a CRT header. The dump is mapped at
+/// `address` as a generic cartridge, 8K or 16K at $8000 or 8K Ultimax at $e000.
+pub struct RomLoader {
+    address: u16,
+}
+
+impl RomLoader {
+    pub fn new(address: u16) -> Self {
+        Self { address }
+    }
+
+    /// Build a generic cartridge holding the dump.
+    pub fn build_cartridge(&self, data: Vec<u8>) -> io::Result<Cartridge> {
+        let (exrom, game) = match (self.address, data.len()) {
+            (0x8000, 0x2000) => (false, true),
+            (0x8000, 0x4000) => (false, false),
+            (0xe000, 0x2000) => (true, false),
+            (0x8000, size) | (0xe000, size) => {
+                return Err(format!(
+                    "invalid rom size {} for address 0x{:04x}",
+                    size, self.address
+                ));
+            }
+            (address, _) => return Err(format!("invalid rom address 0x{:04x}", address)),
+        };
+        let mut cartridge = Cartridge::new(0x0100, HwType::Normal, exrom, game);
+        cartridge.add(Chip {
+            chip_type: ChipType::Rom,
+            bank_number: 0,
+            offset: self.address,
+            size: data.len() as u16,
+            data,
+        });
+        Ok(cartridge)
+    }
+
+    fn read_data(&self, reader: &mut dyn Reader) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Cartridges at $8000 are started by the kernal only if they carry the CBM80
+    /// signature, Ultimax cartridges replace the kernal and its reset vector.
+    fn validate_autostart(&self, data: &[u8]) -> io::Result<()> {
+        if self.address == 0x8000 && data.get(4..9) != Some(&CBM80_SIG[..]) {
+            Err("rom has no CBM80 autostart signature".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Loader for RomLoader {
+    fn autostart(&self, reader: &mut dyn Reader) -> io::Result<AutostartMethod> {
+        info!(target: "loader", "Loading ROM at 0x{:04x}", self.address);
+        let data = self.read_data(reader)?;
+        self.validate_autostart(&data)?;
+        let cartridge = self.build_cartridge(data)?;
+        Ok(AutostartMethod::WithImage(Box::new(RomImage {
+            cartridge: Some(cartridge),
+        })))
+    }
+
+    fn load(&self, reader: &mut dyn Reader) -> io::Result<Box<dyn Image>> {
+        info!(target: "loader", "Loading ROM at 0x{:04x}", self.address);
+        let data = self.read_data(reader)?;
+        let cartridge = self.build_cartridge(data)?;
+        Ok(Box::new(RomImage {
+            cartridge: Some(cartridge),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::SliceReader;
+    use crate::test_util::setup_c64_with_roms;
+
+    // Cold and warm start vectors point past the signature to JMP *
+    fn build_rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0xff; size];
+        rom[0..4].copy_from_slice(&[0x09, 0x80, 0x09, 0x80]);
+        rom[4..9].copy_from_slice(&CBM80_SIG);
+        rom[9..12].copy_from_slice(&[0x4c, 0x09, 0x80]);
+        rom
+    }
+
+    #[test]
+    fn autostart_8k_rom() {
+        let mut c64 = setup_c64_with_roms();
+        let rom = build_rom(0x2000);
+        let loader = RomLoader::new(0x8000);
+        let mut autostart = loader.autostart(&mut SliceReader::new(&rom)).unwrap();
+        autostart.execute(&mut c64);
+        assert_eq!(0x09, c64.get_cpu().read(0x8000));
+        // BASIC stays visible in 8K mode
+        assert_eq!(0x94, c64.get_cpu().read(0xa000));
+        for _ in 0..100 {
+            c64.step();
+        }
+        assert_eq!(0x8009, c64.get_cpu().get_pc());
+    }
+
+    #[test]
+    fn map_16k_rom() {
+        let mut rom = build_rom(0x4000);
+        rom[0x2000] = 0xaa;
+        let loader = RomLoader::new(0x8000);
+        let mut cartridge = loader.build_cartridge(rom).unwrap();
+        assert!(!cartridge.get_exrom());
+        assert!(!cartridge.get_game());
+        cartridge.reset();
+        assert_eq!(Some(0xaa), cartridge.read(0xa000));
+    }
+
+    #[test]
+    fn reject_invalid_size_or_address() {
+        assert!(RomLoader::new(0x8000)
+            .build_cartridge(vec![0; 0x1000])
+            .is_err());
+        assert!(RomLoader::new(0xe000)
+            .build_cartridge(vec![0; 0x4000])
+            .is_err());
+        assert!(RomLoader::new(0x9000)
+            .build_cartridge(vec![0; 0x2000])
+            .is_err());
+    }
+
+    #[test]
+    fn reject_autostart_without_signature() {
+        let mut rom = build_rom(0x2000);
+        rom[8] = 0x00;
+        let loader = RomLoader::new(0x8000);
+        assert!(loader.autostart(&mut SliceReader::new(&rom)).is_err());
+        assert!(loader.load(&mut SliceReader::new(&rom)).is_ok());
+    }
+}
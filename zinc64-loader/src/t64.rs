@@ -0,0 +1,227 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, string::String, vec::Vec};
+use byteorder::{ByteOrder, LittleEndian};
+use zinc64_system::autostart;
+use zinc64_system::{Autostart, AutostartMethod, Image};
+
+use super::Loader;
+use crate::io::{self, Reader};
+use crate::prg::PrgImage;
+
+// SPEC: http://ist.uwaterloo.ca/~schepers/formats/T64.TXT
+
+const HEADER_SIZE: usize = 0x40;
+const ENTRY_SIZE: usize = 0x20;
+
+/// File stored in a T64 archive.
+pub struct T64Entry {
+    pub name: String,
+    pub start_address: u16,
+    pub end_address: u16,
+    offset: usize,
+    size: usize,
+}
+
+/// T64 archives are a container of program files rather than a recording of tape
+/// pulses, so entries are loaded straight into memory like a PRG.
+pub struct T64Archive {
+    data: Vec<u8>,
+    entries: Vec<T64Entry>,
+}
+
+impl T64Archive {
+    pub fn parse(data: Vec<u8>) -> io::Result<T64Archive> {
+        if data.len() < HEADER_SIZE || !data.starts_with(b"C64") {
+            return Err("invalid tape archive signature".to_owned());
+        }
+        let max_entries = LittleEndian::read_u16(&data[0x22..0x24]) as usize;
+        let mut entries = Vec::new();
+        for i in 0..max_entries {
+            let base = HEADER_SIZE + i * ENTRY_SIZE;
+            if base + ENTRY_SIZE > data.len() {
+                break;
+            }
+            let record = &data[base..base + ENTRY_SIZE];
+            // Entry type 0 marks a free slot
+            if record[0] == 0 {
+                continue;
+            }
+            entries.push(T64Entry {
+                name: petscii_to_string(&record[0x10..0x20]),
+                start_address: LittleEndian::read_u16(&record[0x02..0x04]),
+                end_address: LittleEndian::read_u16(&record[0x04..0x06]),
+                offset: LittleEndian::read_u32(&record[0x08..0x0c]) as usize,
+                size: 0,
+            });
+        }
+        if entries.is_empty() {
+            return Err("tape archive has no files".to_owned());
+        }
+        /*
+        Many archives were written with a bogus end address, so the size is limited
+        to the data available before the next entry or the end of the file.
+        */
+        let mut offsets = entries
+            .iter()
+            .map(|entry| entry.offset)
+            .collect::<Vec<usize>>();
+        offsets.push(data.len());
+        offsets.sort();
+        for entry in entries.iter_mut() {
+            if entry.offset > data.len() {
+                return Err(format!("invalid offset for {}", entry.name));
+            }
+            let available = offsets
+                .iter()
+                .find(|offset| **offset > entry.offset)
+                .map_or(0, |next| next - entry.offset);
+            let declared = entry.end_address.wrapping_sub(entry.start_address) as usize;
+            entry.size = declared.min(available);
+        }
+        Ok(T64Archive { data, entries })
+    }
+
+    pub fn get_entries(&self) -> &[T64Entry] {
+        &self.entries
+    }
+
+    /// Program data of the entry at `index` along with its load address.
+    pub fn extract(&self, index: usize) -> io::Result<(u16, Vec<u8>)> {
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or_else(|| format!("invalid tape archive entry {}", index))?;
+        let data = self.data[entry.offset..entry.offset + entry.size].to_vec();
+        Ok((entry.start_address, data))
+    }
+}
+
+fn petscii_to_string(data: &[u8]) -> String {
+    let name = data
+        .iter()
+        .map(|byte| match *byte {
+            0x20..=0x7e => *byte as char,
+            _ => '?',
+        })
+        .collect::<String>();
+    name.trim_end_matches(|c| c == ' ' || c == '?').to_owned()
+}
+
+pub struct T64Loader {
+    entry: usize,
+}
+
+impl T64Loader {
+    pub fn new() -> impl Loader {
+        Self { entry: 0 }
+    }
+
+    /// Loader that extracts the entry at `index` instead of the first one.
+    pub fn with_entry(index: usize) -> impl Loader {
+        Self { entry: index }
+    }
+}
+
+impl Loader for T64Loader {
+    fn autostart(&self, reader: &mut dyn Reader) -> io::Result<AutostartMethod> {
+        let image = self.load(reader)?;
+        let autostart = Autostart::new(autostart::Mode::Run, image);
+        Ok(AutostartMethod::WithAutostart(Some(autostart)))
+    }
+
+    fn load(&self, reader: &mut dyn Reader) -> io::Result<Box<dyn Image>> {
+        info!(target: "loader", "Loading T64");
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let archive = T64Archive::parse(data)?;
+        for entry in archive.get_entries() {
+            info!(
+                target: "loader",
+                "Found file {}, address 0x{:x}, size {}",
+                entry.name,
+                entry.start_address,
+                entry.size
+            );
+        }
+        let (offset, data) = archive.extract(self.entry)?;
+        Ok(Box::new(PrgImage::new(data, offset)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::SliceReader;
+
+    fn build_entry(name: &str, start: u16, end: u16, offset: u32) -> Vec<u8> {
+        let mut entry = vec![0x01, 0x82];
+        entry.extend_from_slice(&start.to_le_bytes());
+        entry.extend_from_slice(&end.to_le_bytes());
+        entry.extend_from_slice(&[0, 0]);
+        entry.extend_from_slice(&offset.to_le_bytes());
+        entry.extend_from_slice(&[0, 0, 0, 0]);
+        let mut filename = [0x20u8; 16];
+        filename[..name.len()].copy_from_slice(name.as_bytes());
+        entry.extend_from_slice(&filename);
+        entry
+    }
+
+    fn build_t64() -> Vec<u8> {
+        let mut image = vec![0u8; 0x40];
+        image[..20].copy_from_slice(b"C64S tape image file");
+        image[0x20] = 0x01;
+        image[0x21] = 0x01;
+        image[0x22] = 0x03; // max entries
+        image[0x24] = 0x02; // used entries
+        image.extend(build_entry("FIRST", 0x0801, 0x0804, 0xa0));
+        image.extend(build_entry("SECOND", 0xc000, 0xc004, 0xa3));
+        image.extend(vec![0u8; 0x20]); // free slot
+        image.extend_from_slice(&[0x01, 0x02, 0x03]);
+        // End address is one byte past the data that is really there
+        image.extend_from_slice(&[0xa9, 0x01, 0x60]);
+        image
+    }
+
+    #[test]
+    fn parse_directory() {
+        let archive = T64Archive::parse(build_t64()).unwrap();
+        let entries = archive.get_entries();
+        assert_eq!(2, entries.len());
+        assert_eq!("FIRST", entries[0].name);
+        assert_eq!(0x0801, entries[0].start_address);
+        assert_eq!("SECOND", entries[1].name);
+        assert_eq!(0xc000, entries[1].start_address);
+    }
+
+    #[test]
+    fn extract_second_entry() {
+        let archive = T64Archive::parse(build_t64()).unwrap();
+        assert_eq!(Ok((0xc000, vec![0xa9, 0x01, 0x60])), archive.extract(1));
+        assert_eq!(Ok((0x0801, vec![0x01, 0x02, 0x03])), archive.extract(0));
+        assert!(archive.extract(2).is_err());
+    }
+
+    #[test]
+    fn load_selected_entry() {
+        let image = build_t64();
+        let loader = T64Loader::with_entry(1);
+        assert!(loader.load(&mut SliceReader::new(&image)).is_ok());
+        let loader = T64Loader::with_entry(5);
+        assert!(loader.load(&mut SliceReader::new(&image)).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_archive() {
+        assert!(T64Archive::parse(b"C64 CARTRIDGE".to_vec()).is_err());
+        let mut image = build_t64();
+        image[0x40] = 0;
+        image[0x60] = 0;
+        assert!(T64Archive::parse(image).is_err());
+    }
+}
@@ -20,6 +20,17 @@ use crate::io::{self, ReadBytesExt, Reader};
 
 static HEADER_SIG: &'static str = "C64-TAPE-RAW";
 
+/// Build a version 1 TAP image from recorded pulse data.
+pub fn build_tap_image(data: &[u8]) -> Vec<u8> {
+    let mut image = Vec::with_capacity(20 + data.len());
+    image.extend_from_slice(HEADER_SIG.as_bytes());
+    image.push(1);
+    image.extend_from_slice(&[0, 0, 0]);
+    image.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    image.extend_from_slice(data);
+    image
+}
+
 struct Header {
     signature: [u8; 12],
     version: u8,
@@ -68,13 +79,32 @@ impl TapLoader {
         Ok(header)
     }
 
+    fn read_tape(&self, reader: &mut dyn Reader) -> io::Result<TapTape> {
+        let header = self
+            .read_header(reader)
+            .map_err(|_| "invalid tape header".to_owned())?;
+        info!(target: "loader", "Found tape, version {}, size {}", header.version, header.size);
+        self.validate_header(&header)?;
+        let mut data = vec![0; header.size as usize];
+        reader
+            .read_exact(&mut data)
+            .map_err(|_| "tape data shorter than header size".to_owned())?;
+        Ok(TapTape {
+            version: header.version,
+            data,
+            pos: 0,
+        })
+    }
+
     fn validate_header(&self, header: &Header) -> io::Result<()> {
         let sig = str::from_utf8(&header.signature)
-            .map_err(|_| "invalid cartridge signature".to_owned())?;
-        if sig == HEADER_SIG {
-            Ok(())
-        } else {
-            Err("invalid cartridge signature".to_owned())
+            .map_err(|_| "invalid tape signature".to_owned())?;
+        if sig != HEADER_SIG {
+            return Err("invalid tape signature".to_owned());
+        }
+        match header.version {
+            0 | 1 => Ok(()),
+            version => Err(format!("unsupported tape version {}", version)),
         }
     }
 }
@@ -88,18 +118,7 @@ impl Loader for TapLoader {
 
     fn load(&self, reader: &mut dyn Reader) -> io::Result<Box<dyn Image>> {
         info!(target: "loader", "Loading TAP");
-        let header = self
-            .read_header(reader)
-            .map_err(|_| "invalid tape header".to_owned())?;
-        info!(target: "loader", "Found tape, version {}, size {}", header.version, header.size);
-        self.validate_header(&header)?;
-        let mut data = vec![0; header.size as usize];
-        reader.read_exact(&mut data)?;
-        let tape = TapTape {
-            version: header.version,
-            data,
-            pos: 0,
-        };
+        let tape = self.read_tape(reader)?;
         Ok(Box::new(TapImage {
             tape: Some(Box::new(tape)),
         }))
@@ -113,33 +132,40 @@ struct TapTape {
 }
 
 impl Tape for TapTape {
+    /*
+    Each data byte is a pulse length in units of 8 cycles. A zero byte marks an
+    overflow: in version 0 it stands for a pulse longer than 255 units, in version 1
+    it is followed by the exact pulse length in cycles as a 24-bit little endian value.
+    */
     fn read_pulse(&mut self) -> Option<u32> {
-        if self.pos < self.data.len() {
-            let value = self.data[self.pos] as u32;
-            self.pos += 1;
-            if value != 0 {
-                Some(value << 3)
-            } else {
-                let pulse = match self.version {
-                    0 => 256 << 3,
-                    1 => {
-                        let byte1 = self.data[self.pos] as u32;
-                        let byte2 = self.data[self.pos + 1] as u32;
-                        let byte3 = self.data[self.pos + 2] as u32;
-                        self.pos += 3;
-                        (byte3 << 16) | (byte2 << 8) | byte1
-                    }
-                    _ => panic!("invalid version {}", self.version),
-                };
-                Some(pulse)
-            }
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let value = self.data[self.pos] as u32;
+        self.pos += 1;
+        if value != 0 {
+            Some(value << 3)
+        } else if self.version == 0 {
+            Some(256 << 3)
+        } else if self.pos + 3 <= self.data.len() {
+            let byte1 = self.data[self.pos] as u32;
+            let byte2 = self.data[self.pos + 1] as u32;
+            let byte3 = self.data[self.pos + 2] as u32;
+            self.pos += 3;
+            Some((byte3 << 16) | (byte2 << 8) | byte1)
         } else {
+            warn!(target: "loader", "Truncated tape pulse at {}", self.pos - 1);
+            self.pos = self.data.len();
             None
         }
     }
 
+    fn get_pos(&self) -> usize {
+        self.pos
+    }
+
     fn seek(&mut self, pos: usize) -> bool {
-        if pos < self.data.len() {
+        if pos <= self.data.len() {
             self.pos = pos;
             true
         } else {
@@ -147,3 +173,100 @@ impl Tape for TapTape {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::SliceReader;
+    use std::rc::Rc;
+    use zinc64_core::device::Datassette;
+    use zinc64_core::util::{new_shared, Clock, EventBus, IoPort, Pin};
+
+    fn build_tap(version: u8, data: &[u8]) -> Vec<u8> {
+        let mut image = HEADER_SIG.as_bytes().to_vec();
+        image.push(version);
+        image.extend_from_slice(&[0, 0, 0]);
+        image.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        image.extend_from_slice(data);
+        image
+    }
+
+    fn read_tape(image: &[u8]) -> io::Result<TapTape> {
+        TapLoader {}.read_tape(&mut SliceReader::new(image))
+    }
+
+    #[test]
+    fn read_short_pulses() {
+        let mut tape = read_tape(&build_tap(1, &[0x30, 0x42, 0x56])).unwrap();
+        assert_eq!(Some(0x30 << 3), tape.read_pulse());
+        assert_eq!(Some(0x42 << 3), tape.read_pulse());
+        assert_eq!(Some(0x56 << 3), tape.read_pulse());
+        assert_eq!(None, tape.read_pulse());
+        assert_eq!(3, tape.get_pos());
+    }
+
+    #[test]
+    fn read_extended_pulse_v1() {
+        let mut tape = read_tape(&build_tap(1, &[0x30, 0x00, 0x20, 0x4e, 0x01, 0x2f])).unwrap();
+        assert_eq!(Some(0x30 << 3), tape.read_pulse());
+        assert_eq!(Some(0x014e20), tape.read_pulse());
+        assert_eq!(Some(0x2f << 3), tape.read_pulse());
+        assert_eq!(None, tape.read_pulse());
+    }
+
+    #[test]
+    fn read_overflow_pulse_v0() {
+        let mut tape = read_tape(&build_tap(0, &[0x00, 0x2f])).unwrap();
+        assert_eq!(Some(256 << 3), tape.read_pulse());
+        assert_eq!(Some(0x2f << 3), tape.read_pulse());
+    }
+
+    #[test]
+    fn truncated_extended_pulse_ends_tape() {
+        let mut tape = read_tape(&build_tap(1, &[0x30, 0x00, 0x20])).unwrap();
+        assert_eq!(Some(0x30 << 3), tape.read_pulse());
+        assert_eq!(None, tape.read_pulse());
+        assert_eq!(None, tape.read_pulse());
+    }
+
+    #[test]
+    fn build_image_round_trip() {
+        let mut tape = read_tape(&build_tap_image(&[0x30, 0x00, 0x20, 0x4e, 0x01])).unwrap();
+        assert_eq!(Some(0x30 << 3), tape.read_pulse());
+        assert_eq!(Some(0x014e20), tape.read_pulse());
+        assert_eq!(None, tape.read_pulse());
+    }
+
+    #[test]
+    fn reject_invalid_header() {
+        let mut image = build_tap(1, &[0x30]);
+        image[0] = b'X';
+        assert!(read_tape(&image).is_err());
+        assert!(read_tape(&build_tap(2, &[0x30])).is_err());
+        let mut image = build_tap(1, &[0x30, 0x31]);
+        image.pop();
+        assert!(read_tape(&image).is_err());
+    }
+
+    #[test]
+    fn datassette_stops_at_end_of_tape() {
+        let tape = read_tape(&build_tap(1, &[0x10, 0x00, 0x00, 0x01, 0x00, 0x10])).unwrap();
+        let cpu_io_port = new_shared(IoPort::new(0x00, 0xff));
+        cpu_io_port.borrow_mut().set_direction(0x20);
+        cpu_io_port.borrow_mut().set_value(0x00); // motor on
+        let events = Rc::new(EventBus::new(Rc::new(Clock::default())));
+        let mut datassette =
+            Datassette::new(new_shared(Pin::new_low()), cpu_io_port.clone(), events);
+        datassette.attach(Box::new(tape));
+        datassette.play();
+        assert!(datassette.is_playing());
+        let mut cycles = 0;
+        while datassette.is_playing() {
+            datassette.clock();
+            cycles += 1;
+            assert!(cycles < 1000);
+        }
+        assert!(cycles >= (0x10 << 3) * 2 + 0x100);
+        assert!(cpu_io_port.borrow().get_value() & 0x10 != 0);
+    }
+}
@@ -7,7 +7,7 @@ use alloc::rc::Rc;
 use alloc::sync::Arc;
 use core::result::Result;
 use zinc64_core::{new_shared, SystemModel};
-use zinc64_system::system::{C64Factory, Config, C64};
+use zinc64_system::system::{C64Factory, Config, ResetKind, C64};
 use zinc64_loader::Loaders;
 use zorio::cursor::Cursor;
 
@@ -19,7 +19,6 @@ use crate::device::fat32::{self, Fat32};
 use crate::device::gpio::GPIO;
 use crate::device::mbox::Mbox;
 use crate::memory;
-use crate::palette::Palette;
 use crate::sound_buffer::SoundBuffer;
 use crate::util::reader::ImageReader;
 use crate::video_buffer::VideoBuffer;
@@ -88,7 +87,7 @@ impl<'a> App<'a> {
         let video_buffer = new_shared(VideoBuffer::new(
             config.model.frame_buffer_size.0,
             config.model.frame_buffer_size.1,
-            Palette::default(),
+            config.palette.to_rgb(),
         ));
         let chip_factory = Box::new(C64Factory::new(config.clone()));
         let mut c64 = C64::build(
@@ -97,7 +96,7 @@ impl<'a> App<'a> {
             video_buffer.clone(),
             sound_buffer.clone(),
         );
-        c64.reset(false);
+        c64.reset(ResetKind::Soft);
         // Initialize audio
         let audio_engine = AudioEngine::build(
             gpio,
@@ -179,8 +178,8 @@ impl<'a> App<'a> {
     fn handle_events(&mut self) {
         if self.c64.get_keyboard().has_events() && self.c64.get_cycles() >= self.next_keyboard_event
         {
-            self.c64.get_keyboard().drain_event();
-            self.next_keyboard_event = self.c64.get_cycles().wrapping_add(20000);
+            let delay = self.c64.get_keyboard().drain_event();
+            self.next_keyboard_event = self.c64.get_cycles().wrapping_add(u64::from(delay));
         }
     }
 
@@ -25,7 +25,6 @@ mod exception;
 mod macros;
 mod memory;
 mod null_output;
-mod palette;
 mod sound_buffer;
 mod util;
 mod video_buffer;
@@ -33,8 +33,12 @@ impl SoundOutput for SoundBuffer {
         self.buffer.lock(|buf| {
             for sample in samples {
                 buf.push(*sample);
-                buf.push(*sample);
             }
         });
     }
+
+    // The PWM audio output is always stereo
+    fn get_channels(&self) -> usize {
+        2
+    }
 }
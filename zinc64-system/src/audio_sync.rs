@@ -0,0 +1,141 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+// Design:
+//   Frames are paced by the host timer while the sound device drains samples off its own
+//   clock, so the two drift apart until the sound buffer runs dry or overflows. Instead of
+//   resampling, the frame rate is scaled by a small factor derived from the buffer fill
+//   level. A proportional term reacts to the current error and an integral term locks
+//   onto the long term clock difference, much like a PLL.
+
+// Gains are relative to the maximum adjustment and tuned for a damped response
+const PROPORTIONAL_GAIN: f64 = 2.0;
+const INTEGRAL_GAIN: f64 = 0.002;
+
+/// Speed adjustment which keeps the sound buffer around a target fill level.
+pub struct AudioSync {
+    target_level: usize,
+    max_adjust: f64,
+    integral: f64,
+    ratio: f64,
+}
+
+impl AudioSync {
+    /// Create adjustment for the target level in sample frames. The speed is never
+    /// changed by more than `max_adjust`, e.g. 0.005 for 0.5%, so pitch shift stays
+    /// inaudible.
+    pub fn new(target_level: usize, max_adjust: f64) -> Self {
+        assert!(target_level > 0);
+        AudioSync {
+            target_level,
+            max_adjust,
+            integral: 0.0,
+            ratio: 1.0,
+        }
+    }
+
+    /// Get the speed factor to apply to the nominal frame rate.
+    pub fn get_ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    pub fn get_target_level(&self) -> usize {
+        self.target_level
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.ratio = 1.0;
+    }
+
+    /// Update adjustment with the number of sample frames currently buffered and
+    /// return the new speed factor.
+    pub fn update(&mut self, level: usize) -> f64 {
+        let error = clamp(
+            (self.target_level as f64 - level as f64) / self.target_level as f64,
+            1.0,
+        );
+        self.integral = clamp(self.integral + error * INTEGRAL_GAIN, 1.0);
+        let adjust = clamp(error * PROPORTIONAL_GAIN + self.integral, 1.0);
+        self.ratio = 1.0 + adjust * self.max_adjust;
+        self.ratio
+    }
+}
+
+fn clamp(value: f64, limit: f64) -> f64 {
+    if value > limit {
+        limit
+    } else if value < -limit {
+        -limit
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_SAMPLES: f64 = 882.0;
+    const TARGET: usize = 4096;
+
+    // Run emulated frames producing samples at the adjusted rate against a consumer
+    // draining at a fixed rate and return the final fill level.
+    fn simulate(sync: &mut AudioSync, level: f64, drain: f64, frames: usize) -> f64 {
+        let mut level = level;
+        for _ in 0..frames {
+            let ratio = sync.update(level as usize);
+            level += FRAME_SAMPLES * ratio - drain;
+            if level < 0.0 {
+                level = 0.0;
+            }
+        }
+        level
+    }
+
+    #[test]
+    fn steady_at_target() {
+        let mut sync = AudioSync::new(TARGET, 0.005);
+        let level = simulate(&mut sync, TARGET as f64, FRAME_SAMPLES, 100);
+        assert_eq!(TARGET as f64, level);
+        assert_eq!(1.0, sync.get_ratio());
+    }
+
+    #[test]
+    fn speeds_up_when_draining() {
+        let mut sync = AudioSync::new(TARGET, 0.005);
+        // Sound device clock runs 0.3% fast
+        let drain = FRAME_SAMPLES * 1.003;
+        let unadjusted = TARGET as f64 - (drain - FRAME_SAMPLES) * 5000.0;
+        assert!(unadjusted < 0.0);
+        let level = simulate(&mut sync, TARGET as f64, drain, 5000);
+        assert!(sync.get_ratio() > 1.0);
+        assert!(sync.get_ratio() <= 1.005);
+        assert!((level - TARGET as f64).abs() < TARGET as f64 * 0.05);
+    }
+
+    #[test]
+    fn slows_down_when_filling() {
+        let mut sync = AudioSync::new(TARGET, 0.005);
+        let drain = FRAME_SAMPLES * 0.998;
+        let level = simulate(&mut sync, TARGET as f64, drain, 5000);
+        assert!(sync.get_ratio() < 1.0);
+        assert!((level - TARGET as f64).abs() < TARGET as f64 * 0.05);
+    }
+
+    #[test]
+    fn adjustment_is_bounded() {
+        let mut sync = AudioSync::new(TARGET, 0.005);
+        for _ in 0..1000 {
+            sync.update(0);
+        }
+        assert!((sync.get_ratio() - 1.005).abs() < 1e-9);
+        for _ in 0..2000 {
+            sync.update(TARGET * 10);
+        }
+        assert!((sync.get_ratio() - 0.995).abs() < 1e-9);
+        sync.reset();
+        assert_eq!(1.0, sync.get_ratio());
+    }
+}
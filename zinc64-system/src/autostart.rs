@@ -7,7 +7,7 @@ use alloc::boxed::Box;
 #[cfg(not(feature = "std"))]
 use alloc::string::ToString;
 
-use super::C64;
+use super::{ResetKind, C64};
 
 pub trait Image {
     fn mount(&mut self, c64: &mut C64);
@@ -16,6 +16,8 @@ pub trait Image {
 
 pub enum Mode {
     Run,
+    /// Load the first file from the disk in device 8 and run it.
+    LoadDiskAndRun,
 }
 
 pub struct Autostart {
@@ -38,6 +40,7 @@ impl Autostart {
     fn get_command(&self) -> &str {
         match self.mode {
             Mode::Run => "RUN",
+            Mode::LoadDiskAndRun => "LOAD\"*\",8,1\nRUN",
         }
     }
 }
@@ -53,14 +56,14 @@ impl AutostartMethod {
         match *self {
             AutostartMethod::WithImage(ref mut image) => {
                 image.mount(c64);
-                c64.reset(false);
+                c64.reset(ResetKind::Soft);
             }
             AutostartMethod::WithBinImage(ref mut image) => {
                 image.mount(c64);
             }
             AutostartMethod::WithAutostart(ref mut autostart) => {
                 c64.set_autostart(autostart.take());
-                c64.reset(false);
+                c64.reset(ResetKind::Soft);
             }
         }
     }
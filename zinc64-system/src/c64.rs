@@ -9,20 +9,40 @@ use alloc::boxed::Box;
 #[cfg(not(feature = "std"))]
 use alloc::rc::Rc;
 #[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
 use alloc::sync::Arc;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::cell::Ref;
+use core::mem;
 #[cfg(feature = "std")]
 use std::rc::Rc;
 #[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
 use std::sync::Arc;
 use zinc64_core::factory::*;
 use zinc64_core::util::*;
 
 use super::breakpoint::BreakpointManager;
+use super::config::DiskTiming;
+#[cfg(feature = "std")]
+use super::frame_buffer::{self, Crop};
+use super::input_recording::{InputLog, InputPorts, InputRecording};
+use super::memory_map::{self, MemRegion};
+use super::sound_gate::SoundGate;
 use super::{Autostart, Config};
-use zinc64_core::device::joystick;
-use zinc64_core::device::{Cartridge, Datassette, Joystick, Keyboard};
+use zinc64_core::device::{joystick, paddle};
+use zinc64_core::device::{
+    Cartridge, Datassette, GeoRam, Joystick, Keyboard, Mouse, Paddle, Reu, Rs232,
+};
+#[cfg(feature = "drive")]
+use zinc64_core::drive::Drive1541;
 use zinc64_core::factory::Tape;
+use zinc64_core::io::{IecBus, IecProtocol, UserPort};
 use zinc64_core::mem::{ExpansionPort, Pla};
+use zinc64_core::sound::mixer::StereoMixer;
 
 // Design:
 //   C64 represents the machine itself and all of its components. Connections between different
@@ -31,10 +51,15 @@ use zinc64_core::mem::{ExpansionPort, Pla};
 #[allow(dead_code)]
 #[derive(Copy, Clone)]
 enum BaseAddr {
+    BasicText = 0x0801,
     Basic = 0xa000,
     BootComplete = 0xa65c,
     Charset = 0xd000,
     Kernal = 0xe000,
+    KernalLoad = 0xf4a5,
+    RamTest = 0xfd6c,
+    RamTestDone = 0xfd88,
+    LoadJump = 0xffd5,
 }
 
 impl BaseAddr {
@@ -43,34 +68,108 @@ impl BaseAddr {
     }
 }
 
+// Power-on RAM holds alternating blocks of $00 and $ff bytes, with noise added as
+// set by `Config::ram_noise`.
+const RAM_POWER_ON_BLOCK: usize = 64;
+const RAM_POWER_ON_NOISE_RANGE: u32 = 0x1000;
+
+/// Kind of reset performed by `C64::reset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetKind {
+    /// Power cycle, RAM is filled with its power-on pattern.
+    Hard,
+    /// Reset line pulled low, RAM is preserved.
+    Soft,
+}
+
+// Access times of a 1541 for loads with realistic timing. Finding a file takes a seek
+// to the directory track and back, plus rotational latency. Sectors of a file follow
+// each other with an interleave of 10, so each takes about half a revolution at 300 rpm.
+const DISK_SEEK_US: u64 = 200_000;
+const DISK_SECTOR_US: u64 = 100_000;
+const DISK_SECTOR_DATA: usize = 254;
+
+// NMI line source of the RESTORE key, which is wired to the NMI input through a
+// monostable rather than to the keyboard matrix.
+const RESTORE_NMI_SOURCE: usize = 4;
+
+// Zero page pointers maintained by BASIC and the kernal LOAD routine.
+#[derive(Copy, Clone)]
+enum BasicPtr {
+    TextStart = 0x2b,
+    VarStart = 0x2d,
+    ArrayStart = 0x2f,
+    ArrayEnd = 0x31,
+    LoadEnd = 0xae,
+}
+
+impl BasicPtr {
+    pub fn addr(self) -> u16 {
+        self as u16
+    }
+}
+
 pub struct C64 {
     // Dependencies
     config: Rc<Config>,
+    kernal_type: KernalType,
     // Chipset
     cpu: Box<dyn Cpu>,
     cia_1: Shared<dyn Chip>,
     cia_2: Shared<dyn Chip>,
     sid: Shared<dyn Chip>,
+    sid_2: Option<Shared<dyn Chip>>,
     vic: Shared<dyn Chip>,
     // Memory
     color_ram: Shared<Ram>,
     expansion_port: Shared<ExpansionPort>,
+    mem: Shared<dyn Addressable>,
+    mmu: Shared<Pla>,
     ram: Shared<Ram>,
+    // I/O Lines
+    iec_bus: Shared<IecBus>,
+    iec_protocol: Shared<IecProtocol>,
+    irq_line: Shared<IrqLine>,
+    input_ports: InputPorts,
+    joystick_1_state: SharedCell<u8>,
+    joystick_2_state: SharedCell<u8>,
+    nmi_line: Shared<IrqLine>,
+    pot_switch: PotSwitch,
     // Peripherals
     datassette: Shared<Datassette>,
+    disk: Option<Box<dyn Disk>>,
+    disk_busy: u32,
+    #[cfg(feature = "drive")]
+    drive: Option<Shared<Drive1541>>,
     joystick_1: Option<Joystick>,
     joystick_2: Option<Joystick>,
+    joystick_3: Option<Joystick>,
+    joystick_4: Option<Joystick>,
     keyboard: Keyboard,
+    mouse: Option<Mouse>,
+    paddle_1: Option<Paddle>,
+    paddle_2: Option<Paddle>,
+    paddle_3: Option<Paddle>,
+    paddle_4: Option<Paddle>,
+    rs232: Shared<Rs232>,
+    user_port: Shared<UserPort>,
     // Buffers
     frame_buffer: Shared<dyn VideoOutput>,
     sound_buffer: Arc<dyn SoundOutput>,
+    sound_gate: Arc<SoundGate>,
     // Runtime State
     autostart: Option<Autostart>,
     breakpoints: BreakpointManager,
     clock: Rc<Clock>,
+    events: Rc<EventBus>,
     frame_count: u32,
+    input_frame: Option<u32>,
+    input_log: InputLog,
+    reset_vector_override: Option<u16>,
+    run_ahead: u32,
     tick_fn: TickFn,
     vsync_flag: SharedCell<bool>,
+    warp_mode: bool,
 }
 
 impl C64 {
@@ -83,9 +182,16 @@ impl C64 {
         info!(target: "c64", "Initializing system");
         // Buffers
         let clock = Rc::new(Clock::default());
+        let events = Rc::new(EventBus::new(clock.clone()));
         let joystick_1_state = new_shared_cell(0u8);
         let joystick_2_state = new_shared_cell(0u8);
+        let joystick_3_state = new_shared_cell(0u8);
+        let joystick_4_state = new_shared_cell(0u8);
         let keyboard_matrix = new_shared([0; 16]);
+        let port_1_pot_x = new_shared_cell(0xffu8);
+        let port_1_pot_y = new_shared_cell(0xffu8);
+        let port_2_pot_x = new_shared_cell(0xffu8);
+        let port_2_pot_y = new_shared_cell(0xffu8);
         let vsync_flag = new_shared_cell(false);
         let vic_base_address = new_shared_cell(0u16);
 
@@ -99,18 +205,24 @@ impl C64 {
         let cia_2_port_a = new_shared(IoPort::new(0x00, 0xff));
         let cia_2_port_b = new_shared(IoPort::new(0x00, 0xff));
         let exp_io_line = new_shared(IoPort::new(0xff, 0xff));
+        let iec_bus = new_shared(IecBus::new());
         let irq_line = new_shared(IrqLine::new("irq"));
         let nmi_line = new_shared(IrqLine::new("nmi"));
+        let pot_x = new_shared_cell(0xffu8);
+        let pot_y = new_shared_cell(0xffu8);
+        let pot_port_select = new_shared_cell(0u8);
 
         // Memory
         let color_ram = factory.new_ram(config.model.color_ram);
         let ram = factory.new_ram(config.model.memory_size);
-        let rom_basic = factory.new_rom(config.roms.basic.as_slice(), BaseAddr::Basic.addr());
-        let rom_charset = factory.new_rom(config.roms.charset.as_slice(), 0);
-        let rom_kernal = factory.new_rom(config.roms.kernal.as_slice(), BaseAddr::Kernal.addr());
+        let rom_basic = factory.new_rom(config.roms.basic(), BaseAddr::Basic.addr());
+        let rom_charset = factory.new_rom(config.roms.charset(), 0);
+        let rom_kernal = factory.new_rom(config.roms.kernal(), BaseAddr::Kernal.addr());
 
         // Chipset
         let cia_1 = factory.new_cia_1(
+            config.model.cia_model,
+            events.clone(),
             joystick_1_state.clone(),
             joystick_2_state.clone(),
             keyboard_matrix.clone(),
@@ -120,18 +232,58 @@ impl C64 {
             irq_line.clone(),
         );
         let cia_2 = factory.new_cia_2(
+            config.model.cia_model,
+            events.clone(),
+            joystick_3_state.clone(),
+            joystick_4_state.clone(),
             cia_2_port_a.clone(),
             cia_2_port_b.clone(),
+            iec_bus.clone(),
             cia_2_flag_pin.clone(),
             nmi_line.clone(),
         );
-        let sid = factory.new_sid(config.model.sid_model, clock.clone(), sound_buffer.clone());
+        let sound_gate = Arc::new(SoundGate::new(sound_buffer));
+        let host_output: Arc<dyn SoundOutput> = sound_gate.clone();
+        let sound_mixer = config
+            .sound
+            .sid_2_address
+            .map(|_| Arc::new(StereoMixer::new(host_output.clone())));
+        // The noise generators of a second SID must not run in step with the first
+        let mut noise_seeds = config.seed.map(Rng::new);
+        let mut next_noise_seed = || noise_seeds.as_mut().map(|rng| rng.next_u64());
+        let sid = factory.new_sid(
+            config.model.sid_model,
+            clock.clone(),
+            match sound_mixer {
+                Some(ref mixer) => StereoMixer::channel(mixer, 0),
+                None => host_output.clone(),
+            },
+            pot_x.clone(),
+            pot_y.clone(),
+            next_noise_seed(),
+        );
+        let sid_2 = match sound_mixer {
+            Some(ref mixer) => Some(factory.new_sid(
+                config.model.sid_model,
+                clock.clone(),
+                StereoMixer::channel(mixer, 1),
+                new_shared_cell(0xffu8),
+                new_shared_cell(0xffu8),
+                next_noise_seed(),
+            )),
+            None => None,
+        };
+        let sound_buffer: Arc<dyn SoundOutput> = match sound_mixer {
+            Some(mixer) => mixer,
+            None => host_output,
+        };
         let vic = factory.new_vic(
             config.model.vic_model,
             color_ram.clone(),
             ram.clone(),
             rom_charset.clone(),
             vic_base_address.clone(),
+            events.clone(),
             frame_buffer.clone(),
             vsync_flag.clone(),
             ba_line.clone(),
@@ -139,7 +291,26 @@ impl C64 {
         );
 
         // Memory Controller and Processor
-        let expansion_port = new_shared(ExpansionPort::new(exp_io_line.clone()));
+        let expansion_port = new_shared(ExpansionPort::new(
+            exp_io_line.clone(),
+            ba_line.clone(),
+            irq_line.clone(),
+            nmi_line.clone(),
+            events.clone(),
+        ));
+        if let Some(size) = config.reu_size() {
+            match Reu::new(size, ram.clone()) {
+                Ok(reu) => expansion_port.borrow_mut().add_peripheral(Box::new(reu)),
+                Err(err) => warn!(target: "c64", "Failed to attach REU: {}", err),
+            }
+        }
+        if let Some(size) = config.georam_size() {
+            let blocks = (0..size / 0x4000).map(|_| factory.new_ram(0x4000)).collect();
+            match GeoRam::new(blocks) {
+                Ok(georam) => expansion_port.borrow_mut().add_peripheral(Box::new(georam)),
+                Err(err) => warn!(target: "c64", "Failed to attach GeoRAM: {}", err),
+            }
+        }
         let mmu = new_shared(Pla::new());
         let mem = factory.new_memory(
             mmu.clone(),
@@ -152,6 +323,10 @@ impl C64 {
             rom_charset.clone(),
             rom_kernal.clone(),
             sid.clone(),
+            config
+                .sound
+                .sid_2_address
+                .and_then(|address| sid_2.clone().map(|sid_2| (address, sid_2))),
             vic.clone(),
         );
         let cpu = factory.new_cpu(
@@ -163,26 +338,128 @@ impl C64 {
         );
 
         // Peripherals
-        let datassette = new_shared(Datassette::new(cia_1_flag_pin.clone(), cpu_io_port.clone()));
+        let datassette = new_shared(Datassette::new(
+            cia_1_flag_pin.clone(),
+            cpu_io_port.clone(),
+            events.clone(),
+        ));
         let joystick1 = if config.joystick.joystick_1 != joystick::Mode::None {
-            Some(Joystick::new(
+            let mut joystick = Joystick::new(
                 config.joystick.joystick_1,
                 config.joystick.axis_motion_threshold,
                 joystick_1_state.clone(),
-            ))
+            );
+            joystick.set_key_map(config.joystick.key_map_1, keyboard_matrix.clone());
+            Some(joystick)
         } else {
             None
         };
         let joystick2 = if config.joystick.joystick_2 != joystick::Mode::None {
-            Some(Joystick::new(
+            let mut joystick = Joystick::new(
                 config.joystick.joystick_2,
                 config.joystick.axis_motion_threshold,
                 joystick_2_state.clone(),
+            );
+            joystick.set_key_map(config.joystick.key_map_2, keyboard_matrix.clone());
+            Some(joystick)
+        } else {
+            None
+        };
+        let joystick3 = if config.joystick.joystick_3 != joystick::Mode::None {
+            Some(Joystick::new(
+                config.joystick.joystick_3,
+                config.joystick.axis_motion_threshold,
+                joystick_3_state.clone(),
+            ))
+        } else {
+            None
+        };
+        let joystick4 = if config.joystick.joystick_4 != joystick::Mode::None {
+            Some(Joystick::new(
+                config.joystick.joystick_4,
+                config.joystick.axis_motion_threshold,
+                joystick_4_state.clone(),
             ))
         } else {
             None
         };
-        let keyboard = Keyboard::new(keyboard_matrix.clone());
+        let mut keyboard = Keyboard::new(keyboard_matrix.clone());
+        keyboard.set_repeat(config.key_repeat);
+        let new_paddle = |port: u8, axis: paddle::Axis, position: &SharedCell<u8>| {
+            let state = if port == 1 {
+                &joystick_1_state
+            } else {
+                &joystick_2_state
+            };
+            let pot = match axis {
+                paddle::Axis::X => &pot_x,
+                paddle::Axis::Y => &pot_y,
+            };
+            position.set(0x80);
+            Paddle::new(
+                port,
+                axis,
+                position.clone(),
+                state.clone(),
+                pot.clone(),
+                pot_port_select.clone(),
+            )
+        };
+        let mouse = config.joystick.mouse.map(|mode| {
+            Mouse::new(
+                mode,
+                1,
+                port_1_pot_x.clone(),
+                port_1_pot_y.clone(),
+                joystick_1_state.clone(),
+                pot_x.clone(),
+                pot_y.clone(),
+                pot_port_select.clone(),
+            )
+        });
+        let (paddle1, paddle2) = if config.joystick.paddles_1 && mouse.is_none() {
+            (
+                Some(new_paddle(1, paddle::Axis::X, &port_1_pot_x)),
+                Some(new_paddle(1, paddle::Axis::Y, &port_1_pot_y)),
+            )
+        } else {
+            (None, None)
+        };
+        let (paddle3, paddle4) = if config.joystick.paddles_2 {
+            (
+                Some(new_paddle(2, paddle::Axis::X, &port_2_pot_x)),
+                Some(new_paddle(2, paddle::Axis::Y, &port_2_pot_y)),
+            )
+        } else {
+            (None, None)
+        };
+        let rs232 = new_shared(Rs232::new(
+            cia_2_port_a.clone(),
+            cia_2_port_b.clone(),
+            cia_2_flag_pin.clone(),
+        ));
+        let iec_protocol = new_shared(IecProtocol::new(iec_bus.clone()));
+        let user_port = new_shared(UserPort::new(cia_2_port_a.clone(), cia_2_port_b.clone()));
+        #[cfg(feature = "drive")]
+        let drive = config
+            .roms
+            .drive()
+            .map(|rom| new_shared(Drive1541::new(8, rom.to_vec(), iec_bus.clone())));
+        let input_ports = InputPorts {
+            keyboard_matrix: keyboard_matrix.clone(),
+            joysticks: [
+                joystick_1_state.clone(),
+                joystick_2_state.clone(),
+                joystick_3_state,
+                joystick_4_state,
+            ],
+            pots: [
+                port_1_pot_x.clone(),
+                port_1_pot_y.clone(),
+                port_2_pot_x.clone(),
+                port_2_pot_y.clone(),
+            ],
+        };
 
         // Observers
         let exp_io_line_clone_1 = exp_io_line.clone();
@@ -204,6 +481,21 @@ impl C64 {
                 let mode = cpu_port_io & 0x07 | expansion_port_io & 0x18;
                 mmu_clone_2.borrow_mut().switch_banks(mode);
             }));
+        let pot_switch = PotSwitch {
+            port_select: pot_port_select.clone(),
+            pot_x: pot_x.clone(),
+            pot_y: pot_y.clone(),
+            ports: [
+                (joystick_1_state.clone(), port_1_pot_x, port_1_pot_y),
+                (joystick_2_state.clone(), port_2_pot_x, port_2_pot_y),
+            ],
+        };
+        let pot_switch_clone = pot_switch.clone();
+        cia_1_port_a
+            .borrow_mut()
+            .set_observer(Box::new(move |value| {
+                pot_switch_clone.select(value >> 6);
+            }));
         let vic_base_address_clone = vic_base_address.clone();
         cia_2_port_a
             .borrow_mut()
@@ -216,37 +508,85 @@ impl C64 {
             let cia_2_clone = cia_2.clone();
             let clock_clone = clock.clone();
             let datassette_clone = datassette.clone();
+            let expansion_port_clone = expansion_port.clone();
+            let iec_protocol_clone = iec_protocol.clone();
+            let rs232_clone = rs232.clone();
+            let user_port_clone = user_port.clone();
             let vic_clone = vic.clone();
+            #[cfg(feature = "drive")]
+            let drive_clone = drive.clone();
             Rc::new(move || {
                 vic_clone.borrow_mut().clock();
                 cia_1_clone.borrow_mut().clock();
                 cia_2_clone.borrow_mut().clock();
                 datassette_clone.borrow_mut().clock();
+                expansion_port_clone.borrow_mut().clock();
+                iec_protocol_clone.borrow_mut().clock();
+                rs232_clone.borrow_mut().clock();
+                user_port_clone.borrow_mut().clock();
+                #[cfg(feature = "drive")]
+                {
+                    if let Some(ref drive) = drive_clone {
+                        drive.borrow_mut().clock();
+                    }
+                }
                 clock_clone.tick();
             })
         };
         C64 {
+            kernal_type: KernalType::detect(config.roms.kernal()),
             config,
             cpu,
             cia_1: cia_1.clone(),
             cia_2: cia_2.clone(),
             sid: sid.clone(),
+            sid_2,
             vic: vic.clone(),
             color_ram: color_ram.clone(),
             expansion_port: expansion_port.clone(),
+            mem,
+            mmu: mmu.clone(),
             ram: ram.clone(),
+            iec_bus,
+            iec_protocol,
+            irq_line,
+            input_ports,
+            joystick_1_state,
+            joystick_2_state,
+            nmi_line,
+            pot_switch,
             datassette,
+            disk: None,
+            disk_busy: 0,
+            #[cfg(feature = "drive")]
+            drive,
             joystick_1: joystick1,
             joystick_2: joystick2,
+            joystick_3: joystick3,
+            joystick_4: joystick4,
             keyboard,
+            mouse,
+            paddle_1: paddle1,
+            paddle_2: paddle2,
+            paddle_3: paddle3,
+            paddle_4: paddle4,
+            rs232,
+            user_port,
             frame_buffer: frame_buffer.clone(),
             sound_buffer: sound_buffer.clone(),
+            sound_gate,
             autostart: None,
             breakpoints: BreakpointManager::default(),
             clock,
+            events,
             frame_count: 0,
+            input_frame: None,
+            input_log: InputLog::Idle,
+            reset_vector_override: None,
+            run_ahead: 0,
             tick_fn,
             vsync_flag,
+            warp_mode: false,
         }
     }
 
@@ -290,10 +630,31 @@ impl C64 {
         self.datassette.clone()
     }
 
+    #[cfg(feature = "drive")]
+    pub fn get_drive(&self) -> Option<Shared<Drive1541>> {
+        self.drive.clone()
+    }
+
+    pub fn get_events(&self) -> Rc<EventBus> {
+        self.events.clone()
+    }
+
+    pub fn get_expansion_port(&self) -> Shared<ExpansionPort> {
+        self.expansion_port.clone()
+    }
+
     pub fn get_frame_count(&self) -> u32 {
         self.frame_count
     }
 
+    pub fn get_iec_bus(&self) -> Shared<IecBus> {
+        self.iec_bus.clone()
+    }
+
+    pub fn get_irq_line(&self) -> Shared<IrqLine> {
+        self.irq_line.clone()
+    }
+
     pub fn get_joystick1(&self) -> &Option<Joystick> {
         &self.joystick_1
     }
@@ -310,14 +671,88 @@ impl C64 {
         &mut self.joystick_2
     }
 
+    pub fn get_joystick3(&self) -> &Option<Joystick> {
+        &self.joystick_3
+    }
+
+    pub fn get_joystick3_mut(&mut self) -> &mut Option<Joystick> {
+        &mut self.joystick_3
+    }
+
+    pub fn get_joystick4(&self) -> &Option<Joystick> {
+        &self.joystick_4
+    }
+
+    pub fn get_joystick4_mut(&mut self) -> &mut Option<Joystick> {
+        &mut self.joystick_4
+    }
+
     pub fn get_keyboard(&mut self) -> &mut Keyboard {
         &mut self.keyboard
     }
 
+    pub fn get_mouse(&self) -> &Option<Mouse> {
+        &self.mouse
+    }
+
+    pub fn get_mouse_mut(&mut self) -> &mut Option<Mouse> {
+        &mut self.mouse
+    }
+
+    pub fn get_nmi_line(&self) -> Shared<IrqLine> {
+        self.nmi_line.clone()
+    }
+
+    /// Current raster line of the beam, useful to locate a partially drawn frame.
+    pub fn get_paddle1(&self) -> &Option<Paddle> {
+        &self.paddle_1
+    }
+
+    pub fn get_paddle1_mut(&mut self) -> &mut Option<Paddle> {
+        &mut self.paddle_1
+    }
+
+    pub fn get_paddle2(&self) -> &Option<Paddle> {
+        &self.paddle_2
+    }
+
+    pub fn get_paddle2_mut(&mut self) -> &mut Option<Paddle> {
+        &mut self.paddle_2
+    }
+
+    pub fn get_paddle3(&self) -> &Option<Paddle> {
+        &self.paddle_3
+    }
+
+    pub fn get_paddle3_mut(&mut self) -> &mut Option<Paddle> {
+        &mut self.paddle_3
+    }
+
+    pub fn get_paddle4(&self) -> &Option<Paddle> {
+        &self.paddle_4
+    }
+
+    pub fn get_paddle4_mut(&mut self) -> &mut Option<Paddle> {
+        &mut self.paddle_4
+    }
+
+    pub fn get_raster_line(&self) -> u16 {
+        let mut vic = self.vic.borrow_mut();
+        (u16::from(vic.read(0x11) & 0x80) << 1) | u16::from(vic.read(0x12))
+    }
+
     pub fn get_sid(&self) -> Shared<dyn Chip> {
         self.sid.clone()
     }
 
+    pub fn get_sid_2(&self) -> Option<Shared<dyn Chip>> {
+        self.sid_2.clone()
+    }
+
+    pub fn get_run_ahead(&self) -> u32 {
+        self.run_ahead
+    }
+
     pub fn get_vic(&self) -> Shared<dyn Chip> {
         self.vic.clone()
     }
@@ -326,6 +761,20 @@ impl C64 {
         self.vsync_flag.get()
     }
 
+    pub fn is_warp_mode(&self) -> bool {
+        self.warp_mode
+    }
+
+    /// Frames per second the host should pace emulation at, or None in warp mode
+    /// when frames are run without pacing.
+    pub fn frame_rate(&self) -> Option<f64> {
+        if !self.warp_mode {
+            Some(self.config.model.refresh_rate as f64)
+        } else {
+            None
+        }
+    }
+
     pub fn is_cpu_jam(&self) -> bool {
         self.cpu.is_cpu_jam()
     }
@@ -334,6 +783,75 @@ impl C64 {
         self.autostart = autostart;
     }
 
+    /// Connect joystick of kind `mode` to control port 1 or 2, `Mode::None` disconnects it.
+    /// A key map set on the joystick previously on the port is kept.
+    pub fn set_joystick_port(&mut self, port: u8, mode: joystick::Mode) -> Result<(), String> {
+        let (joystick, state) = match port {
+            1 => (&mut self.joystick_1, &self.joystick_1_state),
+            2 => (&mut self.joystick_2, &self.joystick_2_state),
+            _ => return Err(format!("invalid control port {}", port)),
+        };
+        let key_map = joystick.as_ref().and_then(|joystick| joystick.get_key_map());
+        if let Some(ref mut joystick) = joystick {
+            joystick.reset();
+        }
+        *joystick = if mode != joystick::Mode::None {
+            let mut new_joystick = Joystick::new(
+                mode,
+                self.config.joystick.axis_motion_threshold,
+                state.clone(),
+            );
+            new_joystick.set_key_map(key_map, self.keyboard.get_matrix());
+            Some(new_joystick)
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    /// Map the lines of the joystick on control port 1 or 2 to keyboard keys.
+    pub fn set_joystick_key_map(
+        &mut self,
+        port: u8,
+        key_map: Option<joystick::KeyMap>,
+    ) -> Result<(), String> {
+        let matrix = self.keyboard.get_matrix();
+        let joystick = match port {
+            1 => &mut self.joystick_1,
+            2 => &mut self.joystick_2,
+            _ => return Err(format!("invalid control port {}", port)),
+        };
+        if let Some(ref mut joystick) = joystick {
+            joystick.set_key_map(key_map, matrix);
+        }
+        Ok(())
+    }
+
+    /// Start execution at the specified address instead of the KERNAL reset
+    /// vector on subsequent resets. Use None to restore the default behavior.
+    pub fn set_reset_vector_override(&mut self, address: Option<u16>) {
+        self.reset_vector_override = address;
+    }
+
+    /// Present each frame as it will look the specified number of frames later, so
+    /// input shows up that much sooner. The frames run ahead are discarded and redone
+    /// once the next input is known. Frames are not run ahead while an RS-232 link or
+    /// serial bus devices are attached, as the data they exchange with the host cannot
+    /// be rolled back.
+    pub fn set_run_ahead(&mut self, frames: u32) {
+        self.run_ahead = frames;
+    }
+
+    /// In warp mode the host runs frames as fast as it can. Emulation is unaffected,
+    /// but the sound buffer is drained every frame so it cannot fill up faster than
+    /// it is played back.
+    pub fn set_warp_mode(&mut self, enabled: bool) {
+        if self.warp_mode != enabled {
+            self.warp_mode = enabled;
+            self.sound_buffer.reset();
+        }
+    }
+
     pub fn reset_vsync(&self) {
         self.vsync_flag.set(false)
     }
@@ -342,6 +860,39 @@ impl C64 {
         self.breakpoints.check(&*self.cpu).is_some()
     }
 
+    /// Describe the address space as currently mapped for the CPU.
+    pub fn memory_map(&self) -> Vec<MemRegion> {
+        memory_map::build(&*self.mmu.borrow())
+    }
+
+    /// Read memory as currently banked in without side effects, e.g. peeking CIA or
+    /// VIC interrupt registers leaves pending interrupts untouched.
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000 | 0x0001 => self.cpu.read(address),
+            _ => self.mem.borrow().peek(address),
+        }
+    }
+
+    /// Write memory as currently banked in without side effects. Writes to ROM go to
+    /// the RAM underneath, in the I/O area only color RAM is written.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000 | 0x0001 => self.cpu.write(address, value),
+            _ => self.mem.borrow_mut().poke(address, value),
+        }
+    }
+
+    /// Read memory the way the CPU does, including side effects of I/O registers.
+    pub fn read_io(&self, address: u16) -> u8 {
+        self.cpu.read(address)
+    }
+
+    /// Write memory the way the CPU does, including side effects of I/O registers.
+    pub fn write_io(&mut self, address: u16, value: u8) {
+        self.cpu.write(address, value);
+    }
+
     pub fn load(&mut self, data: &[u8], offset: u16) {
         let mut mem = self.ram.borrow_mut();
         let mut address = offset;
@@ -351,74 +902,405 @@ impl C64 {
         }
     }
 
-    pub fn reset(&mut self, hard: bool) {
-        info!(target: "c64", "Resetting system");
+    /// Load a PRG file, two byte load address followed by the program, and return
+    /// the load address. See `load_program`.
+    pub fn load_prg(&mut self, data: &[u8]) -> Result<u16, String> {
+        if data.len() < 2 {
+            return Err(String::from("invalid program, missing load address"));
+        }
+        let address = u16::from(data[0]) | (u16::from(data[1]) << 8);
+        self.load_program(&data[2..], address)?;
+        Ok(address)
+    }
+
+    /// Inject a program into RAM the way LOAD"*",8,1 does. The end address is stored
+    /// in EAL ($AE/$AF) and, if the program loads at the start of BASIC text, the
+    /// variable and array pointers are moved past it so RUN works without a NEW.
+    pub fn load_program(&mut self, data: &[u8], address: u16) -> Result<(), String> {
+        let end = address as usize + data.len();
+        if end > 0x10000 {
+            return Err(format!(
+                "program at 0x{:04x} with size {} does not fit into memory",
+                address,
+                data.len()
+            ));
+        }
+        self.load(data, address);
+        let mut ram = self.ram.borrow_mut();
+        let read_word = |ram: &Ram, pointer: u16| {
+            u16::from(ram.read(pointer)) | (u16::from(ram.read(pointer + 1)) << 8)
+        };
+        let write_word = |ram: &mut Ram, pointer: u16, value: u16| {
+            ram.write(pointer, value as u8);
+            ram.write(pointer + 1, (value >> 8) as u8);
+        };
+        let end = end as u16;
+        write_word(&mut *ram, BasicPtr::LoadEnd.addr(), end);
+        let basic_start = match read_word(&*ram, BasicPtr::TextStart.addr()) {
+            0 => BaseAddr::BasicText.addr(),
+            value => value,
+        };
+        if address == basic_start {
+            write_word(&mut *ram, BasicPtr::VarStart.addr(), end);
+            write_word(&mut *ram, BasicPtr::ArrayStart.addr(), end);
+            write_word(&mut *ram, BasicPtr::ArrayEnd.addr(), end);
+        }
+        Ok(())
+    }
+
+    pub fn reset(&mut self, kind: ResetKind) {
+        info!(target: "c64", "Resetting system ({:?})", kind);
         self.clock.reset();
         // Memory
-        if hard {
-            for i in 0..self.config.model.memory_size as u16 {
-                self.ram.borrow_mut().write(i, 0x00);
-            }
-            for i in 0..self.config.model.color_ram as u16 {
-                self.color_ram.borrow_mut().write(i, 0x00);
+        if kind == ResetKind::Hard {
+            // Some programs detect a cold start by the pattern left in RAM
+            let mut ram = self.ram.borrow_mut();
+            let noise = self.config.ram_noise as u32;
+            let mut rng = Rng::new(self.config.seed.unwrap_or(0));
+            for address in 0..self.config.model.memory_size {
+                let mut value = if (address / RAM_POWER_ON_BLOCK) % 2 == 0 {
+                    0x00
+                } else {
+                    0xff
+                };
+                if noise != 0 {
+                    let random = rng.next_u32();
+                    if random % RAM_POWER_ON_NOISE_RANGE < noise {
+                        value ^= 1 << ((random >> 16) & 0x07);
+                    }
+                }
+                ram.write(address as u16, value);
             }
+            self.color_ram.borrow_mut().fill(0x00);
         }
         // Chipset
+        // The reset vector is fetched once the CPU is clocked, after the expansion port
+        // has restored the cartridge lines, so the kernal finds a CBM80 signature at
+        // $8004 and Ultimax cartridges supply their own vector.
         self.cpu.reset();
+        if let Some(address) = self.reset_vector_override {
+            self.cpu.set_pc(address);
+        }
         self.cia_1.borrow_mut().reset();
         self.cia_2.borrow_mut().reset();
         self.sid.borrow_mut().reset();
+        if let Some(ref sid_2) = self.sid_2 {
+            sid_2.borrow_mut().reset();
+        }
         self.vic.borrow_mut().reset();
         // I/O
         self.expansion_port.borrow_mut().reset();
+        self.iec_bus.borrow_mut().reset();
+        self.iec_protocol.borrow_mut().reset();
         // Peripherals
         self.datassette.borrow_mut().reset();
+        self.rs232.borrow_mut().reset();
+        self.user_port.borrow_mut().reset();
+        #[cfg(feature = "drive")]
+        {
+            if let Some(ref drive) = self.drive {
+                drive.borrow_mut().reset();
+            }
+        }
         if let Some(ref mut joystick) = self.joystick_1 {
             joystick.reset();
         }
         if let Some(ref mut joystick) = self.joystick_2 {
             joystick.reset();
         }
+        if let Some(ref mut joystick) = self.joystick_3 {
+            joystick.reset();
+        }
+        if let Some(ref mut joystick) = self.joystick_4 {
+            joystick.reset();
+        }
+        if let Some(ref mut mouse) = self.mouse {
+            mouse.reset();
+        }
+        if let Some(ref mut paddle) = self.paddle_1 {
+            paddle.reset();
+        }
+        if let Some(ref mut paddle) = self.paddle_2 {
+            paddle.reset();
+        }
+        if let Some(ref mut paddle) = self.paddle_3 {
+            paddle.reset();
+        }
+        if let Some(ref mut paddle) = self.paddle_4 {
+            paddle.reset();
+        }
         self.keyboard.reset();
         self.frame_buffer.borrow_mut().reset();
         self.sound_buffer.reset();
+        self.disk_busy = 0;
         // Runtime State
         self.frame_count = 0;
         self.vsync_flag.set(false);
     }
 
+    // -- State Ops
+
+    /// Capture the machine state. Host side input state, serial links and the
+    /// media content itself are not part of the snapshot, only whether a cartridge
+    /// or tape is attached and their position.
+    pub fn save_state(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        self.clock.save_state(&mut snapshot);
+        // Chipset
+        self.cpu.save_state(&mut snapshot);
+        self.cia_1.borrow().save_state(&mut snapshot);
+        self.cia_2.borrow().save_state(&mut snapshot);
+        self.sid.borrow().save_state(&mut snapshot);
+        snapshot.write_bool(self.sid_2.is_some());
+        if let Some(ref sid_2) = self.sid_2 {
+            sid_2.borrow().save_state(&mut snapshot);
+        }
+        self.vic.borrow().save_state(&mut snapshot);
+        // Memory
+        self.color_ram.borrow().save_state(&mut snapshot);
+        self.ram.borrow().save_state(&mut snapshot);
+        // I/O
+        self.expansion_port.borrow().save_state(&mut snapshot);
+        self.iec_bus.borrow().save_state(&mut snapshot);
+        self.irq_line.borrow().save_state(&mut snapshot);
+        self.nmi_line.borrow().save_state(&mut snapshot);
+        self.user_port.borrow().save_state(&mut snapshot);
+        self.rs232.borrow().save_state(&mut snapshot);
+        // Peripherals
+        self.datassette.borrow().save_state(&mut snapshot);
+        snapshot.write_u32(self.disk_busy);
+        #[cfg(feature = "drive")]
+        {
+            snapshot.write_bool(self.drive.is_some());
+            if let Some(ref drive) = self.drive {
+                drive.borrow().save_state(&mut snapshot);
+            }
+        }
+        #[cfg(not(feature = "drive"))]
+        {
+            snapshot.write_bool(false);
+        }
+        for paddle in self.paddles() {
+            snapshot.write_bool(paddle.is_some());
+            if let Some(paddle) = paddle {
+                paddle.save_state(&mut snapshot);
+            }
+        }
+        snapshot.write_bool(self.mouse.is_some());
+        if let Some(ref mouse) = self.mouse {
+            mouse.save_state(&mut snapshot);
+        }
+        // Runtime State
+        snapshot.write_u32(self.frame_count);
+        snapshot.write_bool(self.vsync_flag.get());
+        snapshot
+    }
+
+    /// Restore the machine state captured by `save_state`. The snapshot must have been
+    /// taken with the same system model and attached devices.
+    pub fn load_state(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+        info!(target: "c64", "Loading snapshot");
+        self.restore_state(snapshot)
+    }
+
+    fn restore_state(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+        let mut reader = snapshot.reader();
+        self.clock.load_state(&mut reader)?;
+        // Chipset
+        self.cpu.load_state(&mut reader)?;
+        self.cia_1.borrow_mut().load_state(&mut reader)?;
+        self.cia_2.borrow_mut().load_state(&mut reader)?;
+        self.sid.borrow_mut().load_state(&mut reader)?;
+        if reader.read_bool()? != self.sid_2.is_some() {
+            return Err(String::from("snapshot second SID does not match system"));
+        }
+        if let Some(ref sid_2) = self.sid_2 {
+            sid_2.borrow_mut().load_state(&mut reader)?;
+        }
+        self.vic.borrow_mut().load_state(&mut reader)?;
+        // Memory
+        self.color_ram.borrow_mut().load_state(&mut reader)?;
+        self.ram.borrow_mut().load_state(&mut reader)?;
+        // I/O
+        self.expansion_port.borrow_mut().load_state(&mut reader)?;
+        self.iec_bus.borrow_mut().load_state(&mut reader)?;
+        self.irq_line.borrow_mut().load_state(&mut reader)?;
+        self.nmi_line.borrow_mut().load_state(&mut reader)?;
+        self.user_port.borrow_mut().load_state(&mut reader)?;
+        self.rs232.borrow_mut().load_state(&mut reader)?;
+        // Peripherals
+        self.datassette.borrow_mut().load_state(&mut reader)?;
+        self.disk_busy = reader.read_u32()?;
+        #[cfg(feature = "drive")]
+        {
+            if reader.read_bool()? != self.drive.is_some() {
+                return Err(String::from("snapshot drive does not match system"));
+            }
+            if let Some(ref drive) = self.drive {
+                drive.borrow_mut().load_state(&mut reader)?;
+            }
+        }
+        #[cfg(not(feature = "drive"))]
+        {
+            if reader.read_bool()? {
+                return Err(String::from("snapshot drive does not match system"));
+            }
+        }
+        for paddle in self.paddles_mut() {
+            if reader.read_bool()? != paddle.is_some() {
+                return Err(String::from("snapshot paddles do not match system"));
+            }
+            if let Some(paddle) = paddle {
+                paddle.load_state(&mut reader)?;
+            }
+        }
+        if reader.read_bool()? != self.mouse.is_some() {
+            return Err(String::from("snapshot mouse does not match system"));
+        }
+        if let Some(ref mut mouse) = self.mouse {
+            mouse.load_state(&mut reader)?;
+        }
+        // Runtime State
+        self.frame_count = reader.read_u32()?;
+        self.vsync_flag.set(reader.read_bool()?);
+        if !reader.is_empty() {
+            return Err(String::from("unexpected data at the end of snapshot"));
+        }
+        Ok(())
+    }
+
+    fn paddles(&self) -> [&Option<Paddle>; 4] {
+        [&self.paddle_1, &self.paddle_2, &self.paddle_3, &self.paddle_4]
+    }
+
+    fn paddles_mut(&mut self) -> [&mut Option<Paddle>; 4] {
+        [
+            &mut self.paddle_1,
+            &mut self.paddle_2,
+            &mut self.paddle_3,
+            &mut self.paddle_4,
+        ]
+    }
+
     pub fn run_frame(&mut self) -> bool {
+        self.sync_input();
+        let vsync = self.run_to_vsync();
+        if vsync
+            && self.run_ahead > 0
+            && self.autostart.is_none()
+            && !self.breakpoints.is_bp_present()
+            && !self.cpu.has_watches()
+            && !self.has_host_links()
+        {
+            self.run_ahead_frames();
+        }
+        vsync
+    }
+
+    // Serial links and protocol level bus devices exchange data with the host, which
+    // cannot be rolled back with the snapshot.
+    fn has_host_links(&self) -> bool {
+        self.rs232.borrow().is_attached() || self.iec_protocol.borrow().has_devices()
+    }
+
+    fn run_to_vsync(&mut self) -> bool {
         let tick_fn = self.tick_fn.clone();
         let bp_present = self.breakpoints.is_bp_present();
+        let watch_present = self.cpu.has_watches();
         while !self.vsync_flag.get() {
             self.step_internal(&tick_fn);
             if bp_present && self.check_breakpoints() {
                 break;
             }
+            if watch_present && self.cpu.get_watch_hit().is_some() {
+                break;
+            }
         }
         if self.vsync_flag.get() {
-            self.sid.borrow_mut().process_vsync();
-            self.cia_1.borrow_mut().process_vsync();
-            self.cia_2.borrow_mut().process_vsync();
-            self.frame_count = self.frame_count.wrapping_add(1);
+            self.process_vsync();
         }
         self.vsync_flag.get()
     }
 
+    fn run_ahead_frames(&mut self) {
+        /*
+        The frame just completed is the one the input applies to, so its state is kept.
+        The frames after it are run with the same input only to output the last one
+        and are silenced, then the state is rolled back to redo them with the input
+        sampled for the next frame.
+        */
+        let snapshot = self.save_state();
+        self.sound_gate.set_open(false);
+        for _ in 0..self.run_ahead {
+            self.reset_vsync();
+            self.run_to_vsync();
+        }
+        self.sound_gate.set_open(true);
+        if let Err(err) = self.restore_state(&snapshot) {
+            warn!(target: "c64", "Failed to roll back run-ahead frames: {}", err);
+        }
+    }
+
+    /// Run the specified number of complete frames and return the RGBA pixels of the
+    /// last one. Returns None if stopped early by a breakpoint or watch.
+    pub fn run_frames(&mut self, count: u32) -> Option<Ref<'_, [u8]>> {
+        for _ in 0..count {
+            self.reset_vsync();
+            if !self.run_frame() {
+                return None;
+            }
+        }
+        Some(Ref::map(self.frame_buffer.borrow(), |frame_buffer| {
+            frame_buffer.get_pixel_data()
+        }))
+    }
+
+    /// Save the specified area of the last complete frame as a PNG image. The video
+    /// output must keep the pixels it is given, see `VideoOutput::get_pixel_data`.
+    #[cfg(feature = "std")]
+    pub fn screenshot_png(&self, path: &Path, crop: Crop) -> Result<(), String> {
+        let frame_buffer = self.frame_buffer.borrow();
+        let pixels = frame_buffer.get_pixel_data();
+        if pixels.is_empty() {
+            return Err(String::from("video output does not keep pixels"));
+        }
+        let (width, _) = frame_buffer.get_dimension();
+        frame_buffer::save_png(path, pixels, width, crop.rect(&self.config.model))
+    }
+
     pub fn step(&mut self) {
+        self.sync_input();
         let tick_fn = self.tick_fn.clone();
         self.step_internal(&tick_fn);
         if self.vsync_flag.get() {
-            self.sid.borrow_mut().process_vsync();
-            self.cia_1.borrow_mut().process_vsync();
-            self.cia_2.borrow_mut().process_vsync();
-            self.frame_count = self.frame_count.wrapping_add(1);
+            self.process_vsync();
         }
     }
 
     #[inline]
     pub fn step_internal(&mut self, tick_fn: &TickFn) {
+        if self.disk_busy > 0 {
+            // The CPU waits in LOAD for the drive, interrupts are taken once it returns
+            tick_fn();
+            self.disk_busy -= 1;
+            if self.disk_busy == 0 {
+                self.return_from_load();
+            }
+            return;
+        }
         self.cpu.step(&tick_fn);
+        if self.config.fast_boot && self.cpu.get_pc() == BaseAddr::RamTest.addr() {
+            self.skip_ram_test();
+        }
+        if self.disk.is_some() && self.cpu.get_pc() == BaseAddr::KernalLoad.addr() {
+            self.trap_load();
+        }
+        if self.config.jiffydos_trap
+            && self.disk.is_some()
+            && self.cpu.get_pc() == BaseAddr::LoadJump.addr()
+        {
+            self.trap_jiffydos_load();
+        }
         if self.autostart.is_some() && self.cpu.get_pc() == (BaseAddr::BootComplete.addr()) {
             if let Some(mut autostart) = self.autostart.take() {
                 autostart.execute(self);
@@ -426,19 +1308,325 @@ impl C64 {
         }
     }
 
+    // -- Input Recording
+
+    /// Start recording input from the current state, replacing any recording or
+    /// playback in progress.
+    pub fn start_recording(&mut self) {
+        info!(target: "c64", "Recording input");
+        self.input_log = InputLog::Recording(InputRecording::new(self.save_state()));
+        self.input_frame = None;
+    }
+
+    /// Stop recording or playback. Returns the recorded input if recording.
+    pub fn stop(&mut self) -> Option<InputRecording> {
+        match mem::replace(&mut self.input_log, InputLog::Idle) {
+            InputLog::Recording(recording) => {
+                info!(target: "c64", "Recorded {} frames", recording.len());
+                Some(recording)
+            }
+            _ => None,
+        }
+    }
+
+    /// Restore the state the recording starts from and replay its input on the
+    /// following frames. Host input is ignored until playback completes.
+    pub fn play(&mut self, recording: InputRecording) -> Result<(), String> {
+        self.load_state(recording.get_snapshot())?;
+        info!(target: "c64", "Playing {} frames of input", recording.len());
+        self.input_log = InputLog::Playing(recording, 0);
+        self.input_frame = None;
+        Ok(())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.input_log, InputLog::Playing(_, _))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.input_log, InputLog::Recording(_))
+    }
+
+    // Sample or replay input once per frame, before the first instruction of the frame.
+    fn sync_input(&mut self) {
+        self.sync_input_log();
+        // Joysticks may have changed since POTX was last switched by a port A write
+        self.pot_switch.update();
+    }
+
+    fn sync_input_log(&mut self) {
+        if self.input_frame == Some(self.frame_count) {
+            return;
+        }
+        self.input_frame = Some(self.frame_count);
+        let done = match self.input_log {
+            InputLog::Idle => false,
+            InputLog::Recording(ref mut recording) => {
+                recording.push(self.input_ports.capture());
+                false
+            }
+            InputLog::Playing(ref recording, ref mut pos) => {
+                if let Some(frame) = recording.get_frames().get(*pos) {
+                    self.input_ports.apply(frame);
+                }
+                *pos += 1;
+                *pos >= recording.len()
+            }
+        };
+        if done {
+            info!(target: "c64", "Input playback complete");
+            self.input_log = InputLog::Idle;
+        }
+    }
+
+    fn process_vsync(&mut self) {
+        self.sid.borrow_mut().process_vsync();
+        if let Some(ref sid_2) = self.sid_2 {
+            sid_2.borrow_mut().process_vsync();
+        }
+        self.cia_1.borrow_mut().process_vsync();
+        self.cia_2.borrow_mut().process_vsync();
+        self.keyboard.process_vsync();
+        self.frame_count = self.frame_count.wrapping_add(1);
+        if self.warp_mode {
+            self.sound_buffer.reset();
+        }
+    }
+
+    fn skip_ram_test(&mut self) {
+        /*
+        RAMTAS probes each byte from $0400 until the first write that does not read
+        back, which is the BASIC ROM at $A000 on a stock machine and the cartridge ROM
+        at $8000 with an 8K or 16K cartridge attached. The probe does the same reads
+        and writes here without running the loop, then continues at the routine's exit
+        with the address found. If no such address turns up the kernal loop is left to
+        run.
+        */
+        if self.cpu.read(BaseAddr::RamTest.addr()) != 0xe6 {
+            return; // INC $C2, not a stock kernal
+        }
+        let pointer = self.cpu.read(0x00c1) as u16;
+        let mut page = self.cpu.read(0x00c2);
+        let mut y = self.cpu.get_register(Register::Y);
+        for _ in 0..0x100 {
+            page = page.wrapping_add(1);
+            loop {
+                let address = (((page as u16) << 8) | pointer).wrapping_add(y as u16);
+                let value = self.cpu.read(address);
+                // CMP leaves carry set, so ROL turns $55 into $AB
+                for pattern in [0x55, 0xab].iter() {
+                    self.cpu.write(address, *pattern);
+                    if self.cpu.read(address) != *pattern {
+                        self.cpu.write(0x00c2, page);
+                        self.cpu.set_register(Register::Y, y);
+                        self.cpu.set_pc(BaseAddr::RamTestDone.addr());
+                        return;
+                    }
+                }
+                self.cpu.write(address, value);
+                y = y.wrapping_add(1);
+                if y == 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn trap_load(&mut self) {
+        /*
+        The kernal LOAD entry is reached through ILOAD with the verify flag in A, the
+        relocation address in $C3/$C4 and the file set up by SETLFS/SETNAM. Loads from
+        device 8 are served from the attached disk and the routine returns to its
+        caller the way the kernal would, verify and other devices are left alone.
+        */
+        if self.kernal_type != KernalType::Standard
+            || self.cpu.read(BaseAddr::KernalLoad.addr()) != 0x85
+        {
+            return; // STA $93, not a stock kernal or banked out
+        }
+        self.serve_load();
+    }
+
+    fn trap_jiffydos_load(&mut self) {
+        /*
+        JiffyDOS patches the kernal LOAD routine, so its loads are trapped at the LOAD
+        jump table entry instead, before the relocation address in X/Y is stored to
+        $C3/$C4. Programs that hook the ILOAD vector at $0330 keep their own loader.
+        */
+        if self.kernal_type != KernalType::JiffyDos {
+            return;
+        }
+        if self.cpu.read(0x0331) < (BaseAddr::Kernal.addr() >> 8) as u8 {
+            return;
+        }
+        let x = self.cpu.get_register(Register::X);
+        let y = self.cpu.get_register(Register::Y);
+        self.cpu.write(0x00c3, x);
+        self.cpu.write(0x00c4, y);
+        self.serve_load();
+    }
+
+    fn serve_load(&mut self) {
+        if self.cpu.read(0x00ba) != 8 || self.cpu.get_register(Register::A) != 0 {
+            return;
+        }
+        let read_word = |cpu: &dyn Cpu, pointer: u16| {
+            u16::from(cpu.read(pointer)) | (u16::from(cpu.read(pointer + 1)) << 8)
+        };
+        let name_len = self.cpu.read(0x00b7) as u16;
+        let name_ptr = read_word(&*self.cpu, 0x00bb);
+        let name = (0..name_len)
+            .map(|i| self.cpu.read(name_ptr.wrapping_add(i)))
+            .collect::<Vec<u8>>();
+        let data = match self.disk {
+            Some(ref disk) if name.as_slice() == b"$" => Some(disk.read_directory()),
+            Some(ref disk) if !name.is_empty() => disk.read_file(&name),
+            _ => None,
+        };
+        let mut p = self.cpu.get_register(Register::P);
+        match data {
+            Some(ref data) if data.len() >= 2 => {
+                let address = if self.cpu.read(0x00b9) == 0 {
+                    read_word(&*self.cpu, 0x00c3)
+                } else {
+                    u16::from(data[0]) | (u16::from(data[1]) << 8)
+                };
+                let size = (data.len() - 2).min(0x10000 - address as usize);
+                self.load(&data[2..2 + size], address);
+                let end = address.wrapping_add(size as u16);
+                info!(target: "c64", "Loaded file from disk at 0x{:04x}-0x{:04x}", address, end);
+                self.cpu.write(BasicPtr::LoadEnd.addr(), end as u8);
+                self.cpu.write(BasicPtr::LoadEnd.addr() + 1, (end >> 8) as u8);
+                self.cpu.write(0x0090, 0x40);
+                self.cpu.set_register(Register::X, end as u8);
+                self.cpu.set_register(Register::Y, (end >> 8) as u8);
+                p &= !0x01;
+                if self.events.is_enabled() {
+                    if let Some(ref disk) = self.disk {
+                        for (track, sector) in disk.read_file_sectors(&name) {
+                            self.events.emit(Event::DiskSectorRead(track, sector));
+                        }
+                    }
+                }
+                if self.config.disk_timing == DiskTiming::Realistic {
+                    let sectors = data.len().div_ceil(DISK_SECTOR_DATA);
+                    let time = DISK_SEEK_US + sectors as u64 * DISK_SECTOR_US;
+                    let cpu_freq = u64::from(self.config.model.cpu_freq);
+                    self.disk_busy = (time * cpu_freq / 1_000_000) as u32;
+                }
+            }
+            _ => {
+                info!(target: "c64", "File not found on disk");
+                self.cpu.write(0x0090, 0x42);
+                self.cpu.set_register(Register::A, 0x04);
+                p |= 0x01;
+            }
+        }
+        self.cpu.set_register(Register::P, p);
+        if self.disk_busy == 0 {
+            self.return_from_load();
+        }
+    }
+
+    fn return_from_load(&mut self) {
+        // RTS
+        let sp = self.cpu.get_register(Register::SP);
+        let lo = self.cpu.read(0x0100 + sp.wrapping_add(1) as u16);
+        let hi = self.cpu.read(0x0100 + sp.wrapping_add(2) as u16);
+        self.cpu.set_register(Register::SP, sp.wrapping_add(2));
+        self.cpu.set_pc((u16::from(lo) | (u16::from(hi) << 8)).wrapping_add(1));
+    }
+
     // -- Peripherals Ops
 
-    pub fn attach_cartridge(&mut self, cartridge: Cartridge) {
+    /// Attach custom device to the expansion port, see `Peripheral`.
+    pub fn add_peripheral(&mut self, peripheral: Box<dyn Peripheral>) {
+        self.expansion_port.borrow_mut().add_peripheral(peripheral);
+    }
+
+    /// Attach custom device to the user port, see `Peripheral`.
+    pub fn add_user_port_peripheral(&mut self, peripheral: Box<dyn Peripheral>) {
+        self.user_port.borrow_mut().add_peripheral(peripheral);
+    }
+
+    /// Attach cartridge to the expansion port. Since most cartridges are started
+    /// through the reset vector, the system can be reset as part of this operation.
+    pub fn attach_cartridge(&mut self, cartridge: Cartridge, reset: bool) {
         self.expansion_port.borrow_mut().attach(cartridge);
+        if reset {
+            self.reset(ResetKind::Soft);
+        }
+    }
+
+    /// Press the freeze button of the attached cartridge, see `ExpansionPort::freeze`.
+    pub fn freeze_cartridge(&mut self) -> bool {
+        self.expansion_port.borrow_mut().freeze()
+    }
+
+    /// Press or release the RESTORE key. Pressing it pulls the NMI line low, so the
+    /// kernal NMI handler runs and performs a warm start if RUN/STOP is held.
+    pub fn set_restore_key(&mut self, pressed: bool) {
+        self.nmi_line
+            .borrow_mut()
+            .set_low(RESTORE_NMI_SOURCE, pressed);
+    }
+
+    /// Swap joysticks between the two control ports.
+    pub fn swap_joysticks(&mut self) {
+        core::mem::swap(&mut self.joystick_1, &mut self.joystick_2);
+        if let Some(ref mut joystick) = self.joystick_1 {
+            joystick.set_port(self.joystick_1_state.clone());
+        }
+        if let Some(ref mut joystick) = self.joystick_2 {
+            joystick.set_port(self.joystick_2_state.clone());
+        }
+    }
+
+    /// Attach disk image as device 8. Files are served by trapping the kernal LOAD
+    /// routine rather than by emulating the drive, taking as long as configured by
+    /// `Config::disk_timing`. Only LOAD is trapped, OPEN is not: programs that read
+    /// files with OPEN and GET# or send drive commands find no device 8 on the bus
+    /// and need one attached with `attach_iec_device` instead.
+    pub fn attach_disk(&mut self, disk: Box<dyn Disk>) {
+        self.disk = Some(disk);
+    }
+
+    /// Attach protocol level device to the serial bus, see `IecProtocol`.
+    pub fn attach_iec_device(
+        &mut self,
+        number: usize,
+        device: Box<dyn IecDevice>,
+    ) -> Result<(), String> {
+        self.iec_protocol.borrow_mut().attach(number, device)
+    }
+
+    /// Connect `link` to the user port RS-232 interface running at `baud_rate`.
+    pub fn attach_rs232(&mut self, link: Box<dyn SerialLink>, baud_rate: u32) {
+        let cpu_freq = self.config.model.cpu_freq;
+        self.rs232.borrow_mut().attach(link, baud_rate, cpu_freq);
     }
 
     pub fn attach_tape(&mut self, tape: Box<dyn Tape>) {
         self.datassette.borrow_mut().attach(tape);
     }
 
-    pub fn detach_cartridge(&mut self) {
+    pub fn detach_cartridge(&mut self, reset: bool) {
         self.expansion_port.borrow_mut().detach();
-        self.reset(false);
+        if reset {
+            self.reset(ResetKind::Soft);
+        }
+    }
+
+    pub fn detach_disk(&mut self) {
+        self.disk = None;
+    }
+
+    pub fn detach_iec_device(&mut self, number: usize) {
+        self.iec_protocol.borrow_mut().detach(number);
+    }
+
+    pub fn detach_rs232(&mut self) {
+        self.rs232.borrow_mut().detach();
     }
 
     pub fn detach_tape(&mut self) {
@@ -446,6 +1634,68 @@ impl C64 {
     }
 }
 
+// Analog switch connecting POTX and POTY of the SID to the control port selected by
+// PA6/PA7 of CIA 1. The second fire button of a joystick is read through POTX.
+#[derive(Clone)]
+struct PotSwitch {
+    port_select: SharedCell<u8>,
+    pot_x: SharedCell<u8>,
+    pot_y: SharedCell<u8>,
+    // Joystick state and pot inputs of each control port
+    ports: [(SharedCell<u8>, SharedCell<u8>, SharedCell<u8>); 2],
+}
+
+impl PotSwitch {
+    fn select(&self, port: u8) {
+        self.port_select.set(port);
+        self.update();
+    }
+
+    fn update(&self) {
+        let (state, pot_x, pot_y) = match self.port_select.get() {
+            port @ 0x01..=0x02 => {
+                let (ref state, ref pot_x, ref pot_y) = self.ports[port as usize - 1];
+                (state.get(), pot_x.get(), pot_y.get())
+            }
+            _ => (0, 0xff, 0xff),
+        };
+        let pressed = state & (1 << joystick::Button::Fire2.bit()) != 0;
+        self.pot_x.set(if pressed { 0x00 } else { pot_x });
+        self.pot_y.set(pot_y);
+    }
+}
+
+// JiffyDOS kernals announce themselves with name and version, e.g. JIFFYDOS V6.01,
+// in a power-on message that sits in a different place in each release.
+const JIFFYDOS_SIGNATURE: &[u8] = b"JIFFYDOS V";
+
+// The stock LOAD routine starts with STA $93 at $F4A5.
+const STANDARD_LOAD_SIGNATURE: (usize, u8) = (0x14a5, 0x85);
+
+/// Kernal family, the loads of each are trapped differently.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum KernalType {
+    Standard,
+    JiffyDos,
+    Unknown,
+}
+
+impl KernalType {
+    fn detect(kernal: &[u8]) -> KernalType {
+        let (offset, opcode) = STANDARD_LOAD_SIGNATURE;
+        if kernal
+            .windows(JIFFYDOS_SIGNATURE.len())
+            .any(|window| window == JIFFYDOS_SIGNATURE)
+        {
+            KernalType::JiffyDos
+        } else if kernal.get(offset) == Some(&opcode) {
+            KernalType::Standard
+        } else {
+            KernalType::Unknown
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::C64Factory;
@@ -468,11 +1718,24 @@ mod tests {
         let video_output = new_shared(NullVideo {});
         let sound_output = Arc::new(NullSound {});
         let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
-        c64.reset(false);
+        c64.reset(ResetKind::Soft);
         let cpu = c64.get_cpu();
         assert_eq!(0x94, cpu.read(0xa000));
     }
 
+    #[test]
+    fn detect_kernal_type() {
+        let mut kernal = RES_KERNAL_ROM.to_vec();
+        assert_eq!(KernalType::Standard, KernalType::detect(&kernal));
+        kernal[0x14a5] = 0x4c;
+        assert_eq!(KernalType::Unknown, KernalType::detect(&kernal));
+        kernal[0x047e..0x0486].copy_from_slice(b"JIFFYDOS");
+        assert_eq!(KernalType::Unknown, KernalType::detect(&kernal));
+        kernal[0x1fe0..0x1fee].copy_from_slice(b"JIFFYDOS V6.01");
+        assert_eq!(KernalType::JiffyDos, KernalType::detect(&kernal));
+        assert_eq!(KernalType::Unknown, KernalType::detect(&kernal[..0x1fe8]));
+    }
+
     struct NullSound;
     impl SoundOutput for NullSound {
         fn reset(&self) {}
@@ -18,9 +18,8 @@ use zinc64_core::util::*;
 use super::Config;
 use zinc64_core::cpu::Cpu6510;
 use zinc64_core::io::cia;
-use zinc64_core::io::Cia;
-use zinc64_core::mem::{Memory, Mmio};
-use zinc64_core::sound::sid::SamplingMethod;
+use zinc64_core::io::{Cia, IecBus};
+use zinc64_core::mem::{ChipIo, Memory, Mmio};
 use zinc64_core::sound::Sid;
 use zinc64_core::video::{Vic, VicMemory};
 
@@ -50,6 +49,8 @@ impl ChipFactory for C64Factory {
 
     fn new_cia_1(
         &self,
+        chip_model: CiaModel,
+        events: Rc<EventBus>,
         joystick_1: SharedCell<u8>,
         joystick_2: SharedCell<u8>,
         keyboard_matrix: Shared<[u8; 16]>,
@@ -60,33 +61,48 @@ impl ChipFactory for C64Factory {
     ) -> Shared<dyn Chip> {
         new_shared(Cia::new(
             cia::Mode::Cia1,
+            chip_model,
             Some(joystick_1),
             Some(joystick_2),
             Some(keyboard_matrix),
             port_a,
             port_b,
+            None,
             flag_pin,
             irq_line,
+            events,
         ))
     }
 
     fn new_cia_2(
         &self,
+        chip_model: CiaModel,
+        events: Rc<EventBus>,
+        joystick_3: SharedCell<u8>,
+        joystick_4: SharedCell<u8>,
         port_a: Shared<IoPort>,
         port_b: Shared<IoPort>,
+        iec_bus: Shared<IecBus>,
         flag_pin: Shared<Pin>,
         nmi_line: Shared<IrqLine>,
     ) -> Shared<dyn Chip> {
-        new_shared(Cia::new(
+        let mut cia = Cia::new(
             cia::Mode::Cia2,
+            chip_model,
             None,
             None,
             None,
             port_a,
             port_b,
+            Some(iec_bus),
             flag_pin,
             nmi_line,
-        ))
+            events,
+        );
+        if self.config.joystick.four_player_adapter {
+            cia.set_four_player_adapter(Some((joystick_3, joystick_4)));
+        }
+        new_shared(cia)
     }
 
     fn new_sid(
@@ -94,14 +110,18 @@ impl ChipFactory for C64Factory {
         chip_model: SidModel,
         system_clock: Rc<Clock>,
         sound_buffer: Arc<dyn SoundOutput>,
+        pot_x: SharedCell<u8>,
+        pot_y: SharedCell<u8>,
+        noise_seed: Option<u64>,
     ) -> Shared<dyn Chip> {
-        let mut sid = Sid::new(chip_model, system_clock, sound_buffer);
+        let mut sid = Sid::new(chip_model, system_clock, sound_buffer, pot_x, pot_y);
         sid.set_sampling_parameters(
-            SamplingMethod::Fast,
+            self.config.sound.sampling_method,
             self.config.model.cpu_freq,
             self.config.sound.sample_rate,
         );
         sid.enable_filter(self.config.sound.sid_filters);
+        sid.set_noise_seed(noise_seed);
         new_shared(sid)
     }
 
@@ -112,6 +132,7 @@ impl ChipFactory for C64Factory {
         ram: Shared<Ram>,
         rom_charset: Shared<Rom>,
         vic_base_address: SharedCell<u16>,
+        events: Rc<EventBus>,
         frame_buffer: Shared<dyn VideoOutput>,
         vsync_flag: SharedCell<bool>,
         ba_line: Shared<Pin>,
@@ -126,6 +147,7 @@ impl ChipFactory for C64Factory {
             vsync_flag,
             ba_line,
             irq_line,
+            events,
         ))
     }
 
@@ -143,9 +165,18 @@ impl ChipFactory for C64Factory {
         rom_charset: Shared<Rom>,
         rom_kernal: Shared<Rom>,
         sid: Shared<dyn Chip>,
+        sid_2: Option<(u16, Shared<dyn Chip>)>,
         vic: Shared<dyn Chip>,
     ) -> Shared<dyn Addressable> {
-        let io = Mmio::new(cia_1, cia_2, color_ram, expansion_port.clone(), sid, vic);
+        let mut io = Mmio::new(cia_1, cia_2, color_ram, expansion_port.clone(), sid, vic);
+        io.set_color_ram_high_nibble(self.config.color_ram_high_nibble);
+        if let Some((address, sid_2)) = sid_2 {
+            // The second SID decodes 32 registers at its base address
+            let sid_io = new_shared(ChipIo::new(sid_2, 0x001f));
+            if let Err(err) = io.register(address, address.saturating_add(0x1f), sid_io) {
+                warn!(target: "c64", "Failed to map second SID: {}", err);
+            }
+        }
         new_shared(Memory::new(
             mmu,
             expansion_port.clone(),
@@ -2,13 +2,36 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
-use zinc64_core::device::joystick;
+use zinc64_core::device::{joystick, keyboard, mouse, GeoRam, Reu};
 use zinc64_core::factory::SystemModel;
-use alloc::vec::Vec;
+use zinc64_core::sound::sid::SamplingMethod;
+use zinc64_core::video::Palette;
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, vec::Vec};
 
 pub struct Config {
     pub model: SystemModel,
+    pub fast_boot: bool,
+    /// Serve loads of a JiffyDOS kernal from the attached disk, see `C64::attach_disk`.
+    pub jiffydos_trap: bool,
+    /// Time taken by loads served from the attached disk.
+    pub disk_timing: DiskTiming,
+    /// Seed for state left undefined by the hardware, i.e. power-on RAM noise and the
+    /// initial state of the SID noise generators. Each SID is seeded separately.
+    /// Without a seed the noise generators start from their reset value.
+    pub seed: Option<u64>,
+    /// Chance in 4096 that a byte of power-on RAM has a random bit flipped. With zero
+    /// RAM holds the plain pattern of alternating $00 and $ff blocks.
+    pub ram_noise: u16,
+    /// Value read back in the floating upper nibble of color RAM.
+    pub color_ram_high_nibble: u8,
+    reu_size: Option<usize>,
+    georam_size: Option<usize>,
     pub joystick: JoystickConfig,
+    /// Colors used to convert VIC output to pixels.
+    pub palette: Palette,
+    /// Auto-repeat held keys independently of the running software.
+    pub key_repeat: Option<keyboard::KeyRepeat>,
     pub sound: SoundConfig,
     pub roms: RomData,
 }
@@ -17,7 +40,17 @@ impl Config {
     pub fn new(model: SystemModel) -> Config {
         Config {
             model,
+            fast_boot: false,
+            jiffydos_trap: false,
+            disk_timing: DiskTiming::Instant,
+            seed: None,
+            ram_noise: 0,
+            color_ram_high_nibble: 0x0f,
+            reu_size: None,
+            georam_size: None,
             joystick: JoystickConfig::default(),
+            palette: Palette::default(),
+            key_repeat: None,
             sound: SoundConfig::default(),
             roms: RomData::default(),
         }
@@ -31,17 +64,78 @@ impl Config {
     ) -> Config {
         Config {
             model,
+            fast_boot: false,
+            jiffydos_trap: false,
+            disk_timing: DiskTiming::Instant,
+            seed: None,
+            ram_noise: 0,
+            color_ram_high_nibble: 0x0f,
+            reu_size: None,
+            georam_size: None,
             joystick: JoystickConfig::default(),
+            palette: Palette::default(),
+            key_repeat: None,
             sound: SoundConfig::default(),
             roms: RomData::new(basic, charset, kernal),
         }
     }
+
+    /// Size of the RAM Expansion Unit.
+    pub fn reu_size(&self) -> Option<usize> {
+        self.reu_size
+    }
+
+    /// Set the size of the RAM Expansion Unit, 128KB, 256KB or 512KB.
+    pub fn set_reu_size(&mut self, size: Option<usize>) -> Result<(), String> {
+        if let Some(size) = size {
+            Reu::check_size(size)?;
+        }
+        self.reu_size = size;
+        Ok(())
+    }
+
+    /// Size of the GeoRAM expansion.
+    pub fn georam_size(&self) -> Option<usize> {
+        self.georam_size
+    }
+
+    /// Set the size of the GeoRAM expansion, a power of two multiple of 16KB up to 512KB.
+    pub fn set_georam_size(&mut self, size: Option<usize>) -> Result<(), String> {
+        if let Some(size) = size {
+            GeoRam::check_size(size)?;
+        }
+        self.georam_size = size;
+        Ok(())
+    }
+}
+
+/// Timing of loads served from the attached disk, see `C64::attach_disk`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiskTiming {
+    /// Loads complete as soon as LOAD is called.
+    Instant,
+    /// Loads take about as long as a 1541 needs to seek and read the file's sectors,
+    /// while the rest of the system keeps running.
+    Realistic,
 }
 
 pub struct JoystickConfig {
     pub axis_motion_threshold: i16,
     pub joystick_1: joystick::Mode,
     pub joystick_2: joystick::Mode,
+    pub joystick_3: joystick::Mode,
+    pub joystick_4: joystick::Mode,
+    /// Keys pressed in place of the lines of joystick 1.
+    pub key_map_1: Option<joystick::KeyMap>,
+    /// Keys pressed in place of the lines of joystick 2.
+    pub key_map_2: Option<joystick::KeyMap>,
+    pub four_player_adapter: bool,
+    /// Paddle pair connected to control port 1.
+    pub paddles_1: bool,
+    /// Paddle pair connected to control port 2.
+    pub paddles_2: bool,
+    /// 1351 mouse connected to control port 1, takes the place of paddles on that port.
+    pub mouse: Option<mouse::Mode>,
 }
 
 impl JoystickConfig {
@@ -50,14 +144,32 @@ impl JoystickConfig {
             axis_motion_threshold: 3200,
             joystick_1: joystick::Mode::Numpad,
             joystick_2: joystick::Mode::None,
+            joystick_3: joystick::Mode::None,
+            joystick_4: joystick::Mode::None,
+            key_map_1: None,
+            key_map_2: None,
+            four_player_adapter: false,
+            paddles_1: false,
+            paddles_2: false,
+            mouse: None,
         }
     }
 }
 
+const BASIC_ROM_SIZE: usize = 0x2000;
+const CHARSET_ROM_SIZE: usize = 0x1000;
+const KERNAL_ROM_SIZE: usize = 0x2000;
+#[cfg(feature = "drive")]
+const DRIVE_ROM_SIZE: usize = 0x4000;
+
+/// ROM images the chip factory maps into the system. Replacement images, e.g. a
+/// JiffyDOS kernal or a localized charset, are set through the validating setters.
 pub struct RomData {
-    pub basic: Vec<u8>,
-    pub charset: Vec<u8>,
-    pub kernal: Vec<u8>,
+    basic: Vec<u8>,
+    charset: Vec<u8>,
+    kernal: Vec<u8>,
+    #[cfg(feature = "drive")]
+    drive: Option<Vec<u8>>,
 }
 
 impl RomData {
@@ -66,6 +178,8 @@ impl RomData {
             basic: vec![0x00; 0x2000],
             charset: vec![0x00; 0x1000],
             kernal: vec![0x00; 0x2000],
+            #[cfg(feature = "drive")]
+            drive: None,
         }
     }
 
@@ -74,6 +188,60 @@ impl RomData {
             basic: basic.to_vec(),
             charset: charset.to_vec(),
             kernal: kernal.to_vec(),
+            #[cfg(feature = "drive")]
+            drive: None,
+        }
+    }
+
+    pub fn basic(&self) -> &[u8] {
+        &self.basic
+    }
+
+    pub fn charset(&self) -> &[u8] {
+        &self.charset
+    }
+
+    pub fn kernal(&self) -> &[u8] {
+        &self.kernal
+    }
+
+    /// DOS ROM of the 1541 drive, the drive is attached as device 8 when present.
+    #[cfg(feature = "drive")]
+    pub fn drive(&self) -> Option<&[u8]> {
+        self.drive.as_ref().map(|data| data.as_slice())
+    }
+
+    pub fn set_basic(&mut self, data: Vec<u8>) -> Result<(), String> {
+        self.basic = Self::check_size("basic", data, BASIC_ROM_SIZE)?;
+        Ok(())
+    }
+
+    pub fn set_charset(&mut self, data: Vec<u8>) -> Result<(), String> {
+        self.charset = Self::check_size("charset", data, CHARSET_ROM_SIZE)?;
+        Ok(())
+    }
+
+    pub fn set_kernal(&mut self, data: Vec<u8>) -> Result<(), String> {
+        self.kernal = Self::check_size("kernal", data, KERNAL_ROM_SIZE)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "drive")]
+    pub fn set_drive(&mut self, data: Vec<u8>) -> Result<(), String> {
+        self.drive = Some(Self::check_size("drive", data, DRIVE_ROM_SIZE)?);
+        Ok(())
+    }
+
+    fn check_size(name: &str, data: Vec<u8>, size: usize) -> Result<Vec<u8>, String> {
+        if data.len() == size {
+            Ok(data)
+        } else {
+            Err(format!(
+                "Invalid rom: {} is {} bytes, expected {}",
+                name,
+                data.len(),
+                size
+            ))
         }
     }
 }
@@ -81,8 +249,14 @@ impl RomData {
 pub struct SoundConfig {
     pub enable: bool,
     pub buffer_size: usize,
+    pub channels: u8,
     pub sample_rate: u32,
+    pub sampling_method: SamplingMethod,
     pub sid_filters: bool,
+    /// I/O address of a second SID mixed to the right channel, e.g. $D420 or $DE00.
+    pub sid_2_address: Option<u16>,
+    /// Sample frames to keep buffered, emulation speed is adjusted slightly to hold this level.
+    pub target_buffer_level: Option<usize>,
 }
 
 impl SoundConfig {
@@ -90,8 +264,12 @@ impl SoundConfig {
         SoundConfig {
             enable: true,
             buffer_size: 4096,
+            channels: 2,
             sample_rate: 44100,
+            sampling_method: SamplingMethod::Fast,
             sid_filters: true,
+            sid_2_address: None,
+            target_buffer_level: None,
         }
     }
 }
@@ -0,0 +1,170 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{BufWriter, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+use zinc64_core::factory::{SystemModel, VideoOutput};
+use zinc64_core::video::Palette;
+
+#[cfg(feature = "std")]
+use super::png;
+
+// The display window with 25 rows and 40 columns
+const SCREEN_SIZE: (u32, u32) = (320, 200);
+
+/// Area of the frame buffer to capture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crop {
+    /// Visible area including the border.
+    Border,
+    /// Display window without the border.
+    Screen,
+}
+
+impl Crop {
+    /// Get the x, y, width and height of the area within the frame buffer.
+    pub fn rect(self, model: &SystemModel) -> (u32, u32, u32, u32) {
+        let (x, y, width, height) = match self {
+            Crop::Border => (
+                model.viewport_offset.0,
+                model.viewport_offset.1,
+                model.viewport_size.0,
+                model.viewport_size.1,
+            ),
+            Crop::Screen => (
+                model.screen_offset.0,
+                model.screen_offset.1,
+                SCREEN_SIZE.0,
+                SCREEN_SIZE.1,
+            ),
+        };
+        let (max_width, max_height) = model.frame_buffer_size;
+        (x, y, width.min(max_width - x), height.min(max_height - y))
+    }
+}
+
+/// Frame buffer holding RGBA pixels for frontends that render without a window, e.g.
+/// to capture screenshots. The VIC draws into the back buffer which is copied to front
+/// once the frame is complete.
+pub struct FrameBuffer {
+    dim: (usize, usize),
+    palette: [u32; 16],
+    front: Vec<u8>,
+    pixels: Vec<u8>,
+    position: usize,
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32, palette: Palette) -> Self {
+        let size = (width * height * 4) as usize;
+        Self {
+            dim: (width as usize, height as usize),
+            palette: palette.to_rgba(),
+            front: vec![0; size],
+            pixels: vec![0; size],
+            position: 0,
+        }
+    }
+
+    /// Get RGBA color of the pixel at the specified location of the last complete frame.
+    pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
+        let offset = (y * self.dim.0 + x) * 4;
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.front[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Copy RGBA pixels of the specified area from the last complete frame.
+    pub fn get_area(&self, rect: (u32, u32, u32, u32)) -> Vec<u8> {
+        copy_area(&self.front, self.dim.0, rect)
+    }
+
+    /// Copy RGBA pixels of the frame as drawn so far, with the area the beam has not
+    /// reached yet replaced by the specified RGBA fill color. Rows above the current
+    /// raster line are complete.
+    pub fn get_partial_frame(&self, fill: u32) -> Vec<u8> {
+        let mut frame = self.pixels.clone();
+        let fill = fill.to_le_bytes();
+        for pixel in frame[self.position * 4..].chunks_mut(4) {
+            pixel.copy_from_slice(&fill);
+        }
+        frame
+    }
+
+    /// Save the specified area of the last complete frame as a PNG image.
+    #[cfg(feature = "std")]
+    pub fn save_png(&self, path: &Path, rect: (u32, u32, u32, u32)) -> Result<(), String> {
+        save_png(path, &self.front, self.dim.0, rect)
+    }
+
+    /// Get RGBA color used for the specified VIC color.
+    pub fn get_palette_color(&self, color: u8) -> u32 {
+        self.palette[(color & 0x0f) as usize]
+    }
+}
+
+/// Copy the specified area of RGBA pixels from a frame with the given width.
+pub fn copy_area(pixels: &[u8], pitch: usize, rect: (u32, u32, u32, u32)) -> Vec<u8> {
+    let (x, y, width, height) = rect;
+    let mut area = Vec::with_capacity((width * height * 4) as usize);
+    for row in y..y + height {
+        let offset = (row as usize * pitch + x as usize) * 4;
+        area.extend_from_slice(&pixels[offset..offset + width as usize * 4]);
+    }
+    area
+}
+
+/// Save the specified area of RGBA pixels from a frame with the given width as a PNG
+/// image.
+#[cfg(feature = "std")]
+pub fn save_png(
+    path: &Path,
+    pixels: &[u8],
+    pitch: usize,
+    rect: (u32, u32, u32, u32),
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| format!("{}", err))?;
+    let mut writer = BufWriter::new(file);
+    png::write_png(&mut writer, rect.2, rect.3, &copy_area(pixels, pitch, rect))
+        .and_then(|_| writer.flush())
+        .map_err(|err| format!("{}", err))
+}
+
+impl VideoOutput for FrameBuffer {
+    fn get_dimension(&self) -> (usize, usize) {
+        self.dim
+    }
+
+    fn reset(&mut self) {
+        for byte in self.pixels.iter_mut() {
+            *byte = 0;
+        }
+        for byte in self.front.iter_mut() {
+            *byte = 0;
+        }
+        self.position = 0;
+    }
+
+    fn end_frame(&mut self) {
+        self.front.copy_from_slice(&self.pixels);
+        self.position = 0;
+    }
+
+    fn get_pixel_data(&self) -> &[u8] {
+        &self.front
+    }
+
+    fn write(&mut self, index: usize, color: u8) {
+        let offset = index * 4;
+        let rgba = self.palette[color as usize].to_le_bytes();
+        self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+        self.position = index + 1;
+    }
+}
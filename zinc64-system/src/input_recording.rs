@@ -0,0 +1,209 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+// Design:
+//   Emulation is deterministic, so a run is reproduced by its initial state and the
+//   input that reached the machine. Host input is sampled once at the start of each
+//   frame from the lines it drives, i.e. the keyboard matrix, the joystick ports and
+//   the POT lines of both control ports, and written back to them during playback.
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use zinc64_core::util::{Shared, SharedCell, Snapshot};
+
+/// Host input sampled at the start of a frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputFrame {
+    pub keyboard: [u8; 16],
+    /// Lines of joysticks 1 to 4, the last two on the user port adapter.
+    pub joysticks: [u8; 4],
+    /// POT X and Y of control port 1 followed by those of control port 2.
+    pub pots: [u8; 4],
+}
+
+/// Input recorded frame by frame from a saved machine state.
+#[derive(Clone)]
+pub struct InputRecording {
+    snapshot: Snapshot,
+    frames: Vec<InputFrame>,
+}
+
+impl InputRecording {
+    pub fn new(snapshot: Snapshot) -> Self {
+        InputRecording {
+            snapshot,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Load recording written with `to_snapshot`.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<InputRecording, String> {
+        let mut reader = snapshot.reader();
+        let initial_state = reader.read_snapshot()?;
+        let count = reader.read_u32()? as usize;
+        let mut frames = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut frame = InputFrame {
+                keyboard: [0; 16],
+                joysticks: [0; 4],
+                pots: [0; 4],
+            };
+            reader.read_into(&mut frame.keyboard)?;
+            reader.read_into(&mut frame.joysticks)?;
+            reader.read_into(&mut frame.pots)?;
+            frames.push(frame);
+        }
+        if !reader.is_empty() {
+            return Err(String::from("unexpected data at the end of recording"));
+        }
+        Ok(InputRecording {
+            snapshot: initial_state,
+            frames,
+        })
+    }
+
+    pub fn get_frames(&self) -> &[InputFrame] {
+        &self.frames
+    }
+
+    /// Get the machine state the recording starts from.
+    pub fn get_snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn push(&mut self, frame: InputFrame) {
+        self.frames.push(frame);
+    }
+
+    /// Serialize the recording, use `Snapshot::to_bytes` to store it.
+    pub fn to_snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        snapshot.write_snapshot(&self.snapshot);
+        snapshot.write_u32(self.frames.len() as u32);
+        for frame in self.frames.iter() {
+            snapshot.write_bytes(&frame.keyboard);
+            snapshot.write_bytes(&frame.joysticks);
+            snapshot.write_bytes(&frame.pots);
+        }
+        snapshot
+    }
+}
+
+/// Lines through which host input reaches the machine.
+pub struct InputPorts {
+    pub keyboard_matrix: Shared<[u8; 16]>,
+    pub joysticks: [SharedCell<u8>; 4],
+    pub pots: [SharedCell<u8>; 4],
+}
+
+impl InputPorts {
+    pub fn capture(&self) -> InputFrame {
+        InputFrame {
+            keyboard: *self.keyboard_matrix.borrow(),
+            joysticks: [
+                self.joysticks[0].get(),
+                self.joysticks[1].get(),
+                self.joysticks[2].get(),
+                self.joysticks[3].get(),
+            ],
+            pots: [
+                self.pots[0].get(),
+                self.pots[1].get(),
+                self.pots[2].get(),
+                self.pots[3].get(),
+            ],
+        }
+    }
+
+    pub fn apply(&self, frame: &InputFrame) {
+        *self.keyboard_matrix.borrow_mut() = frame.keyboard;
+        for (line, value) in self.joysticks.iter().zip(frame.joysticks.iter()) {
+            line.set(*value);
+        }
+        for (line, value) in self.pots.iter().zip(frame.pots.iter()) {
+            line.set(*value);
+        }
+    }
+}
+
+/// Whether input is being recorded or played back, with the position of the next
+/// frame to play.
+pub enum InputLog {
+    Idle,
+    Recording(InputRecording),
+    Playing(InputRecording, usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zinc64_core::util::{new_shared, new_shared_cell};
+
+    fn build_ports() -> InputPorts {
+        InputPorts {
+            keyboard_matrix: new_shared([0xff; 16]),
+            joysticks: [
+                new_shared_cell(0),
+                new_shared_cell(0),
+                new_shared_cell(0),
+                new_shared_cell(0),
+            ],
+            pots: [
+                new_shared_cell(0xff),
+                new_shared_cell(0xff),
+                new_shared_cell(0xff),
+                new_shared_cell(0xff),
+            ],
+        }
+    }
+
+    #[test]
+    fn capture_and_apply() {
+        let ports = build_ports();
+        ports.keyboard_matrix.borrow_mut()[1] = 0xfb;
+        ports.joysticks[1].set(0x10);
+        ports.pots[2].set(0x80);
+        let frame = ports.capture();
+        let other = build_ports();
+        other.apply(&frame);
+        assert_eq!(frame, other.capture());
+        assert_eq!(0xfb, other.keyboard_matrix.borrow()[1]);
+        assert_eq!(0x10, other.joysticks[1].get());
+        assert_eq!(0x80, other.pots[2].get());
+    }
+
+    #[test]
+    fn round_trip_recording() {
+        let mut initial_state = Snapshot::new();
+        initial_state.write_u32(0x1234_5678);
+        let mut recording = InputRecording::new(initial_state.clone());
+        let ports = build_ports();
+        recording.push(ports.capture());
+        ports.joysticks[0].set(0x01);
+        recording.push(ports.capture());
+        let loaded = InputRecording::from_snapshot(&recording.to_snapshot()).unwrap();
+        assert!(initial_state == *loaded.get_snapshot());
+        assert_eq!(recording.get_frames(), loaded.get_frames());
+        assert_eq!(2, loaded.len());
+    }
+
+    #[test]
+    fn reject_truncated_recording() {
+        let mut truncated = Snapshot::new();
+        truncated.write_snapshot(&Snapshot::new());
+        truncated.write_u32(1);
+        truncated.write_bytes(&build_ports().capture().keyboard);
+        assert!(InputRecording::from_snapshot(&truncated).is_err());
+    }
+}
@@ -12,16 +12,37 @@ extern crate alloc;
 #[macro_use]
 extern crate log;
 
+mod audio_sync;
 pub mod autostart;
 mod breakpoint;
 pub mod c64;
 mod c64_factory;
 mod condition;
 pub mod config;
+mod frame_buffer;
+mod input_recording;
+pub mod memory_map;
+mod monitor;
+#[cfg(feature = "std")]
+mod png;
+#[cfg(feature = "std")]
+mod serial_bridge;
+mod sound_gate;
+#[cfg(feature = "std")]
+mod sound_recorder;
 
+pub use self::audio_sync::AudioSync;
 pub use self::autostart::{Autostart, AutostartMethod, Image};
 pub use self::breakpoint::Breakpoint;
-pub use self::c64::C64;
+pub use self::c64::{ResetKind, C64};
 pub use self::c64_factory::C64Factory;
 pub use self::condition::Condition;
 pub use self::config::Config;
+pub use self::frame_buffer::{Crop, FrameBuffer};
+pub use self::input_recording::{InputFrame, InputRecording};
+pub use self::memory_map::{MemRegion, RegionKind};
+pub use self::monitor::{assemble, Monitor, Reply};
+#[cfg(feature = "std")]
+pub use self::serial_bridge::SerialBridge;
+#[cfg(feature = "std")]
+pub use self::sound_recorder::SoundRecorder;
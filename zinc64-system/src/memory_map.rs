@@ -0,0 +1,75 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use zinc64_core::factory::{Bank, Mmu};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionKind {
+    Ram,
+    BasicRom,
+    KernalRom,
+    CharRom,
+    ColorRam,
+    Vic,
+    Sid,
+    Cia1,
+    Cia2,
+    ExpansionIo,
+    CartridgeLo,
+    CartridgeHi,
+    Unmapped,
+}
+
+/// MemRegion describes an address range as currently seen by the CPU.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemRegion {
+    pub kind: RegionKind,
+    pub start: u16,
+    pub end: u16,
+}
+
+/// Build the list of regions for the current bank configuration. Adjacent zones
+/// of the same kind are merged into a single region.
+pub fn build(mmu: &dyn Mmu) -> Vec<MemRegion> {
+    let mut regions: Vec<MemRegion> = Vec::new();
+    for zone in 0..0x10u16 {
+        let start = zone << 12;
+        match mmu.map(start) {
+            Bank::Io => {
+                append(&mut regions, RegionKind::Vic, 0xd000, 0xd3ff);
+                append(&mut regions, RegionKind::Sid, 0xd400, 0xd7ff);
+                append(&mut regions, RegionKind::ColorRam, 0xd800, 0xdbff);
+                append(&mut regions, RegionKind::Cia1, 0xdc00, 0xdcff);
+                append(&mut regions, RegionKind::Cia2, 0xdd00, 0xddff);
+                append(&mut regions, RegionKind::ExpansionIo, 0xde00, 0xdfff);
+            }
+            bank => {
+                let kind = match bank {
+                    Bank::Ram => RegionKind::Ram,
+                    Bank::Basic => RegionKind::BasicRom,
+                    Bank::Charset => RegionKind::CharRom,
+                    Bank::Kernal => RegionKind::KernalRom,
+                    Bank::RomL => RegionKind::CartridgeLo,
+                    Bank::RomH => RegionKind::CartridgeHi,
+                    Bank::Disabled => RegionKind::Unmapped,
+                    Bank::Io => unreachable!(),
+                };
+                append(&mut regions, kind, start, start | 0x0fff);
+            }
+        }
+    }
+    regions
+}
+
+fn append(regions: &mut Vec<MemRegion>, kind: RegionKind, start: u16, end: u16) {
+    if let Some(last) = regions.last_mut() {
+        if last.kind == kind && last.end.wrapping_add(1) == start {
+            last.end = end;
+            return;
+        }
+    }
+    regions.push(MemRegion { kind, start, end });
+}
@@ -0,0 +1,391 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
+
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, string::ToString, vec::Vec};
+use zinc64_core::cpu::disasm;
+use zinc64_core::factory::Register;
+
+use super::C64;
+
+const DUMP_LENGTH: u32 = 0x80;
+const DUMP_WIDTH: u32 = 16;
+const DISASM_COUNT: usize = 16;
+const HUNT_WIDTH: usize = 8;
+
+/// Reply to a monitor command.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// Text produced by the command.
+    Output(String),
+    /// Leave the monitor and resume emulation.
+    Resume,
+}
+
+/// Machine language monitor with a VICE-like command set. All numbers are hexadecimal
+/// with an optional `$` prefix and address ranges are inclusive.
+///
+/// Memory is accessed through the cpu without side effects on I/O chips.
+pub struct Monitor {
+    next_disasm: u16,
+    next_dump: u16,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self {
+            next_disasm: 0,
+            next_dump: 0,
+        }
+    }
+
+    pub fn help() -> &'static str {
+        "a <address> <instruction>  assemble\n\
+         d [<start> [<end>]]        disassemble\n\
+         f <start> <end> <data>     fill memory\n\
+         g [<address>]              go\n\
+         h <start> <end> <data>     hunt memory\n\
+         m [<start> [<end>]]        memory dump\n\
+         r [<reg>=<value> ...]      registers\n\
+         z [<count>]                step\n\
+         x                          exit\n"
+    }
+
+    pub fn execute(&mut self, c64: &mut C64, input: &str) -> Result<Reply, String> {
+        let mut tokens = input.split_whitespace();
+        let command = match tokens.next() {
+            Some(command) => command.to_lowercase(),
+            None => return Ok(Reply::Output(String::new())),
+        };
+        let args = tokens.collect::<Vec<_>>();
+        match command.as_str() {
+            "a" => self.assemble(c64, &args),
+            "d" => self.disassemble(c64, &args),
+            "f" => self.fill(c64, &args),
+            "g" => self.go(c64, &args),
+            "h" => self.hunt(c64, &args),
+            "m" => self.dump(c64, &args),
+            "r" => self.registers(c64, &args),
+            "z" => self.step(c64, &args),
+            "x" => {
+                ensure_args(&args, 0, 0)?;
+                Ok(Reply::Resume)
+            }
+            _ => Err(format!("Invalid command {}", command)),
+        }
+    }
+
+    // -- Commands
+
+    fn assemble(&mut self, c64: &mut C64, args: &[&str]) -> Result<Reply, String> {
+        if args.len() < 2 {
+            return Err("missing argument".to_string());
+        }
+        let address = parse_num(args[0])?;
+        let bytes = assemble(&args[1..].join(" "), address)?;
+        for (i, byte) in bytes.iter().enumerate() {
+            c64.write_io(address.wrapping_add(i as u16), *byte);
+        }
+        let (line, len) = disasm_line(c64, address);
+        self.next_disasm = address.wrapping_add(len);
+        Ok(Reply::Output(line))
+    }
+
+    fn disassemble(&mut self, c64: &mut C64, args: &[&str]) -> Result<Reply, String> {
+        ensure_args(args, 0, 2)?;
+        let start = match args.first() {
+            Some(value) => parse_num(value)?,
+            None => self.next_disasm,
+        };
+        let end = match args.get(1) {
+            Some(value) => Some(parse_num(value)? as u32),
+            None => None,
+        };
+        let mut output = String::new();
+        let mut address = start as u32;
+        let mut count = 0;
+        loop {
+            let (line, len) = disasm_line(c64, address as u16);
+            output.push_str(&line);
+            address += len as u32;
+            count += 1;
+            let done = match end {
+                Some(end) => address > end,
+                None => count == DISASM_COUNT,
+            };
+            if done || address > 0xffff {
+                break;
+            }
+        }
+        self.next_disasm = address as u16;
+        Ok(Reply::Output(output))
+    }
+
+    fn dump(&mut self, c64: &mut C64, args: &[&str]) -> Result<Reply, String> {
+        ensure_args(args, 0, 2)?;
+        let start = match args.first() {
+            Some(value) => parse_num(value)?,
+            None => self.next_dump,
+        };
+        let end = match args.get(1) {
+            Some(value) => parse_range_end(start, value)?,
+            None => (start as u32 + DUMP_LENGTH - 1).min(0xffff),
+        };
+        let mut output = String::new();
+        let mut address = start as u32;
+        while address <= end {
+            let line_end = (address + DUMP_WIDTH - 1).min(end);
+            let data = (address..=line_end)
+                .map(|a| c64.peek(a as u16))
+                .collect::<Vec<u8>>();
+            output.push_str(&format!(">{:04x} ", address));
+            for i in 0..DUMP_WIDTH as usize {
+                match data.get(i) {
+                    Some(byte) => output.push_str(&format!(" {:02x}", byte)),
+                    None => output.push_str("   "),
+                }
+            }
+            output.push_str("  ");
+            for byte in data.iter() {
+                output.push(if *byte >= 0x20 && *byte < 0x7f {
+                    *byte as char
+                } else {
+                    '.'
+                });
+            }
+            output.push('\n');
+            address = line_end + 1;
+        }
+        self.next_dump = address as u16;
+        Ok(Reply::Output(output))
+    }
+
+    fn fill(&mut self, c64: &mut C64, args: &[&str]) -> Result<Reply, String> {
+        let (start, end, data) = parse_range_data(args)?;
+        for (i, address) in (start as u32..=end).enumerate() {
+            c64.write_io(address as u16, data[i % data.len()]);
+        }
+        Ok(Reply::Output(String::new()))
+    }
+
+    fn go(&mut self, c64: &mut C64, args: &[&str]) -> Result<Reply, String> {
+        ensure_args(args, 0, 1)?;
+        if let Some(value) = args.first() {
+            let address = parse_num(value)?;
+            c64.get_cpu_mut().set_pc(address);
+        }
+        Ok(Reply::Resume)
+    }
+
+    fn hunt(&mut self, c64: &mut C64, args: &[&str]) -> Result<Reply, String> {
+        let (start, end, data) = parse_range_data(args)?;
+        let matches = (start as u32..=end)
+            .filter(|address| {
+                data.iter()
+                    .enumerate()
+                    .all(|(i, byte)| c64.peek((*address as u16).wrapping_add(i as u16)) == *byte)
+            })
+            .map(|address| format!("{:04x}", address))
+            .collect::<Vec<_>>();
+        let mut output = String::new();
+        for chunk in matches.chunks(HUNT_WIDTH) {
+            output.push_str(&chunk.join(" "));
+            output.push('\n');
+        }
+        Ok(Reply::Output(output))
+    }
+
+    fn registers(&mut self, c64: &mut C64, args: &[&str]) -> Result<Reply, String> {
+        for arg in args {
+            let mut parts = arg.splitn(2, '=');
+            let name = parts.next().unwrap_or("").to_lowercase();
+            let value = parts
+                .next()
+                .ok_or_else(|| format!("Invalid register assignment {}", arg))?;
+            let value = parse_num(value)?;
+            let cpu = c64.get_cpu_mut();
+            match name.as_str() {
+                "pc" => cpu.set_pc(value),
+                _ => {
+                    let reg = match name.as_str() {
+                        "a" => Register::A,
+                        "x" => Register::X,
+                        "y" => Register::Y,
+                        "sp" => Register::SP,
+                        "p" => Register::P,
+                        _ => return Err(format!("Invalid register {}", name)),
+                    };
+                    if value > 0xff {
+                        return Err(format!("Invalid value {}", arg));
+                    }
+                    cpu.set_register(reg, value as u8);
+                }
+            }
+        }
+        let mut output = String::from("  pc  a  x  y  sp nv-bdizc\n");
+        output.push_str(&format_registers(c64));
+        output.push('\n');
+        Ok(Reply::Output(output))
+    }
+
+    fn step(&mut self, c64: &mut C64, args: &[&str]) -> Result<Reply, String> {
+        ensure_args(args, 0, 1)?;
+        let count = match args.first() {
+            Some(value) => parse_num(value)?,
+            None => 1,
+        };
+        let mut output = String::new();
+        for _ in 0..count {
+            let cycles = c64.get_cycles();
+            c64.step();
+            // Right after the program counter is changed the cpu only fetches the opcode.
+            if c64.get_cycles().wrapping_sub(cycles) == 1 && !c64.get_cpu().is_jammed() {
+                c64.step();
+            }
+            let pc = c64.get_cpu().get_pc();
+            let (line, len) = disasm_line(c64, pc);
+            output.push_str(&format!("{:<24}{}\n", line.trim_end(), format_registers(c64)));
+            self.next_disasm = pc.wrapping_add(len);
+        }
+        Ok(Reply::Output(output))
+    }
+}
+
+/// Assemble a single instruction at `address`. Operands use the same syntax as
+/// the disassembler output, branch operands are target addresses.
+pub fn assemble(input: &str, address: u16) -> Result<Vec<u8>, String> {
+    let input = input.trim().to_uppercase();
+    let mut tokens = input.splitn(2, char::is_whitespace);
+    let mnemonic = tokens.next().unwrap_or("");
+    let operand = tokens
+        .next()
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    // Prefer the documented opcode over the illegal single byte NOPs.
+    if mnemonic == "NOP" && operand.is_empty() {
+        return Ok(vec![0xea]);
+    }
+    let mut candidates: Vec<(Vec<u8>, String)> = Vec::new();
+    if operand.is_empty() || operand == "A" {
+        let expected = format!("{} A", mnemonic);
+        for opcode in 0..=0xffu8 {
+            candidates.push((vec![opcode], String::from(mnemonic)));
+            candidates.push((vec![opcode], expected.clone()));
+        }
+    } else {
+        let prefix_len = operand.find(|c: char| c != '#' && c != '(').unwrap_or(0);
+        let (prefix, rest) = operand.split_at(prefix_len);
+        let rest = rest.trim_start_matches('$');
+        let digits_len = rest
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(rest.len());
+        let (digits, suffix) = rest.split_at(digits_len);
+        let value = parse_num(digits)?;
+        let [lo, hi] = value.to_le_bytes();
+        let short = format!("{} {}${:02X}{}", mnemonic, prefix, value, suffix);
+        let long = format!("{} {}${:04X}{}", mnemonic, prefix, value, suffix);
+        let offset = value.wrapping_sub(address.wrapping_add(2)) as i16;
+        for opcode in 0..=0xffu8 {
+            if value <= 0xff {
+                candidates.push((vec![opcode, lo], short.clone()));
+            }
+            if (-128..=127).contains(&offset) {
+                candidates.push((vec![opcode, offset as u8], long.clone()));
+            }
+        }
+        for opcode in 0..=0xffu8 {
+            candidates.push((vec![opcode, lo, hi], long.clone()));
+        }
+    }
+    candidates
+        .into_iter()
+        .find(|(bytes, expected)| disasm(bytes, address) == (expected.clone(), bytes.len()))
+        .map(|(bytes, _)| bytes)
+        .ok_or_else(|| format!("Invalid instruction {}", input))
+}
+
+fn disasm_line(c64: &C64, address: u16) -> (String, u16) {
+    let data = [
+        c64.peek(address),
+        c64.peek(address.wrapping_add(1)),
+        c64.peek(address.wrapping_add(2)),
+    ];
+    let (text, len) = disasm(&data, address);
+    let mut line = format!(".{:04x} ", address);
+    for (i, byte) in data.iter().enumerate() {
+        if i < len {
+            line.push_str(&format!(" {:02x}", byte));
+        } else {
+            line.push_str("   ");
+        }
+    }
+    line.push_str(&format!("  {}\n", text));
+    (line, len as u16)
+}
+
+fn format_registers(c64: &C64) -> String {
+    let cpu = c64.get_cpu();
+    format!(
+        ".{:04x} {:02x} {:02x} {:02x} {:02x} {:08b}",
+        cpu.get_pc(),
+        cpu.get_register(Register::A),
+        cpu.get_register(Register::X),
+        cpu.get_register(Register::Y),
+        cpu.get_register(Register::SP),
+        cpu.get_register(Register::P)
+    )
+}
+
+// -- Helpers
+
+fn ensure_args(args: &[&str], min: usize, max: usize) -> Result<(), String> {
+    if args.len() < min {
+        Err("missing argument".to_string())
+    } else if args.len() > max {
+        Err(format!("Unexpected token {}", args[max]))
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_num(value: &str) -> Result<u16, String> {
+    u16::from_str_radix(value.trim_start_matches('$'), 16)
+        .map_err(|_| format!("Invalid number {}", value))
+}
+
+fn parse_range_end(start: u16, value: &str) -> Result<u32, String> {
+    let end = parse_num(value)?;
+    if end < start {
+        Err(format!("Invalid range {:04x}-{:04x}", start, end))
+    } else {
+        Ok(end as u32)
+    }
+}
+
+fn parse_range_data(args: &[&str]) -> Result<(u16, u32, Vec<u8>), String> {
+    if args.len() < 3 {
+        return Err("missing argument".to_string());
+    }
+    let start = parse_num(args[0])?;
+    let end = parse_range_end(start, args[1])?;
+    let mut data = Vec::new();
+    for value in &args[2..] {
+        let byte = parse_num(value)?;
+        if byte > 0xff {
+            return Err(format!("Invalid byte {}", value));
+        }
+        data.push(byte as u8);
+    }
+    Ok((start, end, data))
+}
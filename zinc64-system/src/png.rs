@@ -0,0 +1,111 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+// Spec: https://www.w3.org/TR/PNG/
+// Design:
+//   Screenshots are small so image data is written as uncompressed deflate blocks which
+//   keeps the encoder free of dependencies.
+
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::unreadable_literal))]
+
+use std::io::{Result, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const COLOR_TYPE_RGBA: u8 = 6;
+const MAX_BLOCK_SIZE: usize = 0xffff;
+
+/// Write RGBA pixels as a PNG image.
+pub fn write_png(out: &mut dyn Write, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    assert_eq!((width * height * 4) as usize, rgba.len());
+    out.write_all(&SIGNATURE)?;
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, COLOR_TYPE_RGBA, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+    let mut scanlines = Vec::with_capacity(rgba.len() + height as usize);
+    if width > 0 {
+        for row in rgba.chunks((width * 4) as usize) {
+            scanlines.push(0); // filter type None
+            scanlines.extend_from_slice(row);
+        }
+    }
+    write_chunk(out, b"IDAT", &zlib_store(&scanlines))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(crc32(0xffffffff, kind), data) ^ 0xffffffff;
+    out.write_all(&crc.to_be_bytes())
+}
+
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK_SIZE * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(MAX_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(if last { 0x01 } else { 0x00 });
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xcbf43926, crc32(0xffffffff, b"123456789") ^ 0xffffffff);
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(0x11e60398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn zlib_store_splits_blocks() {
+        let data = vec![0x55; MAX_BLOCK_SIZE + 1];
+        let out = zlib_store(&data);
+        assert_eq!(2 + 5 + MAX_BLOCK_SIZE + 5 + 1 + 4, out.len());
+        assert_eq!(0x00, out[2]);
+        assert_eq!(0x01, out[2 + 5 + MAX_BLOCK_SIZE]);
+    }
+}
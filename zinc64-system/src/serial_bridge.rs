@@ -0,0 +1,96 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use zinc64_core::factory::SerialLink;
+
+/// Serial link that bridges the RS-232 interface to a host TCP socket or terminal
+/// device, e.g. the slave side of a pseudo-terminal. Incoming bytes are read on a
+/// separate thread so the emulation never blocks on the host.
+pub struct SerialBridge {
+    rx: Receiver<u8>,
+    writer: Box<dyn Write>,
+    connected: bool,
+}
+
+impl SerialBridge {
+    /// Connect to a TCP server at `addr`, e.g. a telnet BBS.
+    pub fn connect(addr: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr).map_err(|err| format!("{}", err))?;
+        stream.set_nodelay(true).map_err(|err| format!("{}", err))?;
+        let reader = stream.try_clone().map_err(|err| format!("{}", err))?;
+        Ok(Self::build(Box::new(reader), Box::new(stream)))
+    }
+
+    /// Open terminal device at `path`, e.g. /dev/pts/3.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|err| format!("{}", err))?;
+        let reader = file.try_clone().map_err(|err| format!("{}", err))?;
+        Ok(Self::build(Box::new(reader), Box::new(file)))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn build(mut reader: Box<dyn Read + Send>, writer: Box<dyn Write>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 256];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        for value in &buffer[..n] {
+                            if tx.send(*value).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        SerialBridge {
+            rx,
+            writer,
+            connected: true,
+        }
+    }
+}
+
+impl SerialLink for SerialBridge {
+    fn receive(&mut self) -> Option<u8> {
+        match self.rx.try_recv() {
+            Ok(value) => Some(value),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                if self.connected {
+                    info!(target: "rs232", "Serial link closed by remote end");
+                    self.connected = false;
+                }
+                None
+            }
+        }
+    }
+
+    fn send(&mut self, value: u8) {
+        if !self.connected {
+            return;
+        }
+        if let Err(err) = self.writer.write_all(&[value]) {
+            warn!(target: "rs232", "Serial link write failed: {}", err);
+            self.connected = false;
+        }
+    }
+}
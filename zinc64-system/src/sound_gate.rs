@@ -0,0 +1,50 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+#[cfg(not(feature = "std"))]
+use alloc::sync::Arc;
+use core::cell::Cell;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+use zinc64_core::factory::SoundOutput;
+
+/// Sound output that is muted while closed, used to silence frames that are emulated
+/// but not played such as run-ahead frames.
+pub struct SoundGate {
+    output: Arc<dyn SoundOutput>,
+    open: Cell<bool>,
+}
+
+impl SoundGate {
+    pub fn new(output: Arc<dyn SoundOutput>) -> Self {
+        SoundGate {
+            output,
+            open: Cell::new(true),
+        }
+    }
+
+    pub fn set_open(&self, open: bool) {
+        self.open.set(open);
+    }
+}
+
+impl SoundOutput for SoundGate {
+    fn reset(&self) {
+        self.output.reset();
+    }
+
+    fn write(&self, samples: &[i16]) {
+        if self.open.get() {
+            self.output.write(samples);
+        }
+    }
+
+    fn get_channels(&self) -> usize {
+        self.output.get_channels()
+    }
+
+    fn is_muted(&self) -> bool {
+        !self.open.get() || self.output.is_muted()
+    }
+}
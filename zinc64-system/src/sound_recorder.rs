@@ -0,0 +1,152 @@
+// This file is part of zinc64.
+// Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
+// Licensed under the GPLv3. See LICENSE file in the project root for full license text.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use zinc64_core::factory::SoundOutput;
+
+const HEADER_SIZE: u32 = 44;
+
+/// Sound output that passes samples through to the host output and optionally
+/// captures them to a 16-bit PCM WAV file. Samples are captured as the SID
+/// generates them, so recordings are unaffected by the host output being reset
+/// or drained, e.g. in warp mode. The recording has the channel count of the
+/// host output.
+pub struct SoundRecorder {
+    output: Arc<dyn SoundOutput>,
+    sample_rate: u32,
+    writer: Mutex<Option<WavWriter>>,
+}
+
+impl SoundRecorder {
+    pub fn new(output: Arc<dyn SoundOutput>, sample_rate: u32) -> Self {
+        SoundRecorder {
+            output,
+            sample_rate,
+            writer: Mutex::new(None),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.writer.lock().unwrap().is_some()
+    }
+
+    /// Start capturing samples to a new WAV file. A recording in progress is
+    /// finished first.
+    pub fn start_recording(&self, path: &Path) -> Result<(), String> {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(current) = writer.take() {
+            current.finish()?;
+        }
+        let channels = self.output.get_channels() as u16;
+        *writer = Some(WavWriter::create(path, self.sample_rate, channels)?);
+        Ok(())
+    }
+
+    /// Finish the current recording and return the number of samples written.
+    pub fn stop_recording(&self) -> Result<u32, String> {
+        match self.writer.lock().unwrap().take() {
+            Some(writer) => writer.finish(),
+            None => Err("sound recording not started".to_string()),
+        }
+    }
+}
+
+impl Drop for SoundRecorder {
+    fn drop(&mut self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            if let Some(writer) = writer.take() {
+                if let Err(err) = writer.finish() {
+                    warn!(target: "sound", "Failed to finish recording: {}", err);
+                }
+            }
+        }
+    }
+}
+
+impl SoundOutput for SoundRecorder {
+    fn reset(&self) {
+        self.output.reset();
+    }
+
+    fn write(&self, samples: &[i16]) {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(ref mut wav) = *writer {
+            if let Err(err) = wav.write(samples) {
+                warn!(target: "sound", "Recording stopped: {}", err);
+                *writer = None;
+            }
+        }
+        self.output.write(samples);
+    }
+
+    fn get_channels(&self) -> usize {
+        self.output.get_channels()
+    }
+}
+
+struct WavWriter {
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> Result<Self, String> {
+        let file = File::create(path).map_err(|err| format!("{}", err))?;
+        let mut writer = WavWriter {
+            file: BufWriter::new(file),
+            samples: 0,
+        };
+        writer
+            .write_header(sample_rate, channels)
+            .map_err(|err| format!("{}", err))?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, samples: &[i16]) -> Result<(), String> {
+        for sample in samples {
+            self.file
+                .write_all(&sample.to_le_bytes())
+                .map_err(|err| format!("{}", err))?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    /// Patch chunk sizes now that the data length is known.
+    pub fn finish(mut self) -> Result<u32, String> {
+        let data_size = self.samples * 2;
+        let result = self
+            .file
+            .seek(SeekFrom::Start(4))
+            .and_then(|_| self.file.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes()))
+            .and_then(|_| self.file.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4)))
+            .and_then(|_| self.file.write_all(&data_size.to_le_bytes()))
+            .and_then(|_| self.file.flush());
+        result.map_err(|err| format!("{}", err))?;
+        Ok(self.samples)
+    }
+
+    fn write_header(&mut self, sample_rate: u32, channels: u16) -> std::io::Result<()> {
+        let block_align = channels * 2;
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        self.file.write_all(b"WAVE")?;
+        self.file.write_all(b"fmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&1u16.to_le_bytes())?; // PCM
+        self.file.write_all(&channels.to_le_bytes())?;
+        self.file.write_all(&sample_rate.to_le_bytes())?;
+        self.file
+            .write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        self.file.write_all(&block_align.to_le_bytes())?;
+        self.file.write_all(&16u16.to_le_bytes())?; // bits per sample
+        self.file.write_all(b"data")?;
+        self.file.write_all(&0u32.to_le_bytes())?;
+        Ok(())
+    }
+}
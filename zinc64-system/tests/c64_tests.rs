@@ -2,14 +2,23 @@
 // Copyright (c) 2016-2019 Sebastian Jastrzebski. All rights reserved.
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use zinc64_core::factory::{SoundOutput, SystemModel, TickFn, VideoOutput};
-use zinc64_core::io::cia;
-use zinc64_core::util::new_shared;
-use zinc64_system::{C64Factory, Config, C64};
+use zinc64_core::device::cartridge::{Cartridge, Chip, ChipType, HwType};
+use zinc64_core::device::{joystick, mouse, Key, KeyEvent, Keyboard};
+use zinc64_core::factory::{
+    Access, Disk, IecDevice, Peripheral, Register, SoundOutput, SystemModel, TickFn, VideoOutput,
+};
+use zinc64_core::io::{cia, IecLine};
+use zinc64_core::util::{new_shared, Event, Shared, Snapshot, SnapshotReader};
+use zinc64_core::video::Palette;
+use zinc64_system::config::DiskTiming;
+use zinc64_system::{
+    assemble, C64Factory, Config, Crop, FrameBuffer, MemRegion, Monitor, RegionKind, Reply,
+    ResetKind, SoundRecorder, C64,
+};
 
 /*
 Program CIA1TAB - TA, TB, PB67 and ICR in cascaded mode
@@ -25,66 +34,1582 @@ ICR 00 01 01 01 01 01 01 01 03 83 83 83
 
 static CIA1TAB_PRG: &[u8] = include_bytes!("data/cia1tab.prg");
 
+static RES_BASIC_ROM: &[u8] = include_bytes!("../../res/rom/basic.rom");
+static RES_CHARSET_ROM: &[u8] = include_bytes!("../../res/rom/characters.rom");
+static RES_KERNAL_ROM: &[u8] = include_bytes!("../../res/rom/kernal.rom");
+
 static CIA1TAB_TA: [u8; 12] = [01, 02, 02, 01, 02, 02, 01, 02, 02, 01, 02, 02];
 
-static CIA1TAB_TB: [u8; 12] = [02, 02, 02, 01, 01, 01, 00, 00, 02, 02, 02, 02];
+static CIA1TAB_TB: [u8; 12] = [02, 02, 02, 01, 01, 01, 00, 00, 02, 02, 02, 02];
+
+static CIA1TAB_PB: [u8; 12] = [
+    0x80, 0xC0, 0x80, 0x80, 0xC0, 0x80, 0x80, 0xC0, 0x00, 0x00, 0x40, 0x00,
+];
+
+struct NullSound;
+impl SoundOutput for NullSound {
+    fn reset(&self) {}
+    fn write(&self, _samples: &[i16]) {}
+}
+
+struct BufferSound {
+    buffer: Mutex<Vec<i16>>,
+    channels: usize,
+}
+
+impl BufferSound {
+    fn new(channels: usize) -> Self {
+        BufferSound {
+            buffer: Mutex::new(Vec::new()),
+            channels,
+        }
+    }
+}
+
+impl SoundOutput for BufferSound {
+    fn reset(&self) {
+        self.buffer.lock().unwrap().clear();
+    }
+    fn write(&self, samples: &[i16]) {
+        self.buffer.lock().unwrap().extend_from_slice(samples);
+    }
+    fn get_channels(&self) -> usize {
+        self.channels
+    }
+}
+
+struct NullVideo;
+impl VideoOutput for NullVideo {
+    fn get_dimension(&self) -> (usize, usize) {
+        (0, 0)
+    }
+    fn reset(&mut self) {}
+    fn write(&mut self, _index: usize, _color: u8) {}
+}
+
+#[test]
+fn program_cia1tab() {
+    let config = Rc::new(Config::new(SystemModel::from("pal")));
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(NullSound {});
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
+    c64.reset(ResetKind::Soft);
+    let cia1_clone = c64.get_cia_1();
+    let cia2_clone = c64.get_cia_2();
+    let clock_clone = c64.get_clock();
+    let test_flag = Rc::new(Cell::new(false));
+    let test_flag_clone = test_flag.clone();
+    let test_cycle = Rc::new(Cell::new(0));
+    let test_cycle_clone = test_cycle.clone();
+    let tick_fn: TickFn = Rc::new(move || {
+        cia1_clone.borrow_mut().clock();
+        cia2_clone.borrow_mut().clock();
+        clock_clone.tick();
+        if test_flag_clone.get() {
+            if test_cycle_clone.get() >= 1 && test_cycle_clone.get() < 13 {
+                let i = test_cycle_clone.get() - 1;
+                assert_eq!(cia1_clone.borrow_mut().read(cia::reg::TALO), CIA1TAB_TA[i]);
+                assert_eq!(cia1_clone.borrow_mut().read(cia::reg::TBLO), CIA1TAB_TB[i]);
+                assert_eq!(cia1_clone.borrow_mut().read(cia::reg::PRB), CIA1TAB_PB[i]);
+            }
+            test_cycle_clone.set(test_cycle_clone.get() + 1);
+        }
+    });
+    c64.load(&CIA1TAB_PRG.to_vec()[2..].to_vec(), 0x4000);
+    c64.get_cpu_mut().set_pc(0x4000);
+    while test_cycle.get() < 13 {
+        c64.step_internal(&tick_fn);
+        if c64.get_cpu().get_pc() == 0x402d {
+            test_flag.set(true);
+        }
+    }
+}
+
+fn setup_c64_with_roms() -> C64 {
+    let config = Rc::new(Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    ));
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(NullSound {});
+    C64::build(config.clone(), &*factory, video_output, sound_output)
+}
+
+fn build_16k_cartridge(fill: u8) -> Cartridge {
+    let mut cartridge = Cartridge::new(0x0100, HwType::Normal, false, false);
+    cartridge.add(Chip {
+        chip_type: ChipType::Rom,
+        bank_number: 0,
+        offset: 0x8000,
+        size: 0x4000,
+        data: vec![fill; 0x4000],
+    });
+    cartridge
+}
+
+#[test]
+fn attach_detach_cartridge() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+    c64.attach_cartridge(build_16k_cartridge(0xaa), true);
+    assert_eq!(0xaa, c64.get_cpu().read(0x8000));
+    assert_eq!(0xaa, c64.get_cpu().read(0xa000));
+    c64.detach_cartridge(true);
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+}
+
+#[test]
+fn attach_detach_cartridge_without_reset() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.attach_cartridge(build_16k_cartridge(0x55), false);
+    assert_eq!(0x55, c64.get_cpu().read(0xa000));
+    c64.detach_cartridge(false);
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+}
+
+// Cold and warm start vectors point to JMP * following the signature at $8004
+fn build_autostart_cartridge(signature: &[u8; 5]) -> Cartridge {
+    let mut data = vec![0xff; 0x2000];
+    data[0..4].copy_from_slice(&[0x09, 0x80, 0x09, 0x80]);
+    data[4..9].copy_from_slice(signature);
+    data[9..12].copy_from_slice(&[0x4c, 0x09, 0x80]);
+    let mut cartridge = Cartridge::new(0x0100, HwType::Normal, false, true);
+    cartridge.add(Chip {
+        chip_type: ChipType::Rom,
+        bank_number: 0,
+        offset: 0x8000,
+        size: 0x2000,
+        data,
+    });
+    cartridge
+}
+
+// Run the kernal reset routine until it either enters the cartridge or goes on to
+// initialize the system at $FCEF.
+fn run_reset_routine(c64: &mut C64) -> u16 {
+    for _ in 0..1000 {
+        c64.step();
+        let pc = c64.get_cpu().get_pc();
+        if pc == 0x8009 || pc == 0xfcef {
+            return pc;
+        }
+    }
+    c64.get_cpu().get_pc()
+}
+
+#[test]
+fn cartridge_autostart_on_reset() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Hard);
+    c64.attach_cartridge(build_autostart_cartridge(b"\xc3\xc2\xcd80"), true);
+    assert_eq!(0x8009, run_reset_routine(&mut c64));
+    c64.reset(ResetKind::Hard);
+    assert_eq!(0x8009, run_reset_routine(&mut c64));
+}
+
+#[test]
+fn cartridge_without_signature_boots_basic() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Hard);
+    // ASCII text instead of the PETSCII signature
+    c64.attach_cartridge(build_autostart_cartridge(b"CBM80"), true);
+    assert_eq!(0xfcef, run_reset_routine(&mut c64));
+    c64.detach_cartridge(true);
+    assert_eq!(0xfcef, run_reset_routine(&mut c64));
+}
+
+fn build_ocean_cartridge(banks: u8, game: bool) -> Cartridge {
+    let mut cartridge = Cartridge::new(0x0100, HwType::OceanType1, false, game);
+    for bank in 0..banks {
+        cartridge.add(Chip {
+            chip_type: ChipType::Rom,
+            bank_number: bank,
+            offset: 0x8000,
+            size: 0x2000,
+            data: vec![bank; 0x2000],
+        });
+    }
+    cartridge
+}
+
+#[test]
+fn ocean_cartridge_bank_switching() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.attach_cartridge(build_ocean_cartridge(16, false), false);
+    assert_eq!(0x00, c64.get_cpu().read(0x8000));
+    assert_eq!(0x00, c64.get_cpu().read(0xbfff));
+    for &bank in [0x83u8, 0x8f, 0x81].iter() {
+        c64.get_cpu_mut().write(0xde00, bank);
+        assert_eq!(bank & 0x0f, c64.get_cpu().read(0x8000));
+        assert_eq!(bank & 0x0f, c64.get_cpu().read(0x9fff));
+        assert_eq!(bank & 0x0f, c64.get_cpu().read(0xa000));
+    }
+    // Bank lines above the cartridge size are not decoded
+    c64.get_cpu_mut().write(0xde00, 0x92);
+    assert_eq!(0x02, c64.get_cpu().read(0x8000));
+}
+
+#[test]
+fn ocean_cartridge_8k_mode_keeps_basic() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.attach_cartridge(build_ocean_cartridge(4, true), false);
+    c64.get_cpu_mut().write(0xde00, 0x82);
+    assert_eq!(0x02, c64.get_cpu().read(0x8000));
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+}
+
+fn build_action_replay_cartridge() -> Cartridge {
+    let mut cartridge = Cartridge::new(0x0100, HwType::ActionReplay, false, true);
+    for bank in 0..4u8 {
+        let mut data = vec![0x10 + bank; 0x2000];
+        if bank == 0 {
+            // JMP $8000 and NMI vector at $FFFA in ultimax mode
+            data[..3].copy_from_slice(&[0x4c, 0x00, 0x80]);
+            data[0x1ffa..0x1ffc].copy_from_slice(&[0x00, 0x80]);
+        }
+        cartridge.add(Chip {
+            chip_type: ChipType::Rom,
+            bank_number: bank,
+            offset: 0x8000,
+            size: 0x2000,
+            data,
+        });
+    }
+    cartridge
+}
+
+#[test]
+fn action_replay_freeze_takes_nmi() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.attach_cartridge(build_action_replay_cartridge(), false);
+    for _ in 0..10 {
+        c64.step();
+    }
+    assert!(c64.freeze_cartridge());
+    assert!(c64.get_nmi_line().borrow().is_low());
+    assert_eq!(0x00, c64.get_cpu().read(0xfffa));
+    assert_eq!(0x80, c64.get_cpu().read(0xfffb));
+    for _ in 0..10 {
+        c64.step();
+    }
+    assert!(c64.get_cpu().get_pc() >= 0x8000 && c64.get_cpu().get_pc() < 0x8003);
+    assert_eq!(0x4c, c64.get_cpu().read(0x8000));
+}
+
+#[test]
+fn action_replay_control_register() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.attach_cartridge(build_action_replay_cartridge(), false);
+    assert!(c64.freeze_cartridge());
+    // Bank 1 in 16K mode
+    c64.get_cpu_mut().write(0xde00, 0x09);
+    assert!(!c64.get_nmi_line().borrow().is_low());
+    assert_eq!(0x11, c64.get_cpu().read(0x8000));
+    assert_eq!(0x11, c64.get_cpu().read(0xa000));
+    assert_eq!(0x11, c64.get_cpu().read(0xdf00));
+    // RAM at ROML and I/O 2 in 8K mode
+    c64.get_cpu_mut().write(0xde00, 0x20);
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+    c64.get_cpu_mut().write(0x8000, 0x5a);
+    c64.get_cpu_mut().write(0xdf00, 0x77);
+    assert_eq!(0x5a, c64.get_cpu().read(0x8000));
+    assert_eq!(0x77, c64.get_cpu().read(0x9f00));
+    // Disabled until reset
+    c64.get_cpu_mut().write(0xde00, 0x04);
+    c64.get_cpu_mut().write(0xde00, 0x09);
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+    c64.reset(ResetKind::Soft);
+    assert_eq!(0x10, c64.get_cpu().read(0x8010));
+}
+
+#[test]
+fn freeze_requires_freezer_cartridge() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    assert!(!c64.freeze_cartridge());
+    c64.attach_cartridge(build_16k_cartridge(0xaa), false);
+    assert!(!c64.freeze_cartridge());
+    assert!(!c64.get_nmi_line().borrow().is_low());
+}
+
+fn build_easyflash_cartridge() -> Cartridge {
+    let mut cartridge = Cartridge::new(0x0100, HwType::EasyFlash, true, false);
+    for bank in 0..4u8 {
+        for &(offset, fill) in [(0x8000u16, 0x40u8), (0xa000, 0x80)].iter() {
+            cartridge.add(Chip {
+                chip_type: ChipType::FlashRom,
+                bank_number: bank,
+                offset,
+                size: 0x2000,
+                data: vec![fill + bank; 0x2000],
+            });
+        }
+    }
+    cartridge
+}
+
+fn flash_command(c64: &mut C64, address: u16, command: u8) {
+    c64.get_cpu_mut().write(address | 0x0555, 0xaa);
+    c64.get_cpu_mut().write(address | 0x02aa, 0x55);
+    c64.get_cpu_mut().write(address | 0x0555, command);
+}
+
+#[test]
+fn easyflash_bank_switching() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.attach_cartridge(build_easyflash_cartridge(), false);
+    // Boot jumper starts the cartridge in ultimax mode
+    assert_eq!(0x40, c64.get_cpu().read(0x8000));
+    assert_eq!(0x80, c64.get_cpu().read(0xe000));
+    for bank in [3u8, 1, 2].iter() {
+        c64.get_cpu_mut().write(0xde00, *bank);
+        assert_eq!(0x40 + bank, c64.get_cpu().read(0x9fff));
+        assert_eq!(0x80 + bank, c64.get_cpu().read(0xffff));
+    }
+}
+
+#[test]
+fn easyflash_mode_register() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.attach_cartridge(build_easyflash_cartridge(), false);
+    // 16K
+    c64.get_cpu_mut().write(0xde02, 0x07);
+    assert_eq!(0x40, c64.get_cpu().read(0x8000));
+    assert_eq!(0x80, c64.get_cpu().read(0xa000));
+    assert_eq!(0x85, c64.get_cpu().read(0xe000));
+    // 8K
+    c64.get_cpu_mut().write(0xde02, 0x06);
+    assert_eq!(0x40, c64.get_cpu().read(0x8000));
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+    // Cartridge off
+    c64.get_cpu_mut().write(0xde02, 0x04);
+    c64.get_cpu_mut().write(0x8000, 0x12);
+    assert_eq!(0x12, c64.get_cpu().read(0x8000));
+    // Ultimax with the LED on
+    c64.get_cpu_mut().write(0xde02, 0x85);
+    assert_eq!(0x40, c64.get_cpu().read(0x8000));
+    assert_eq!(0x80, c64.get_cpu().read(0xe000));
+    // RAM at I/O 2
+    c64.get_cpu_mut().write(0xdf10, 0x99);
+    assert_eq!(0x99, c64.get_cpu().read(0xdf10));
+}
+
+#[test]
+fn easyflash_program_and_erase() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.attach_cartridge(build_easyflash_cartridge(), false);
+    c64.get_cpu_mut().write(0xde02, 0x07);
+    c64.get_cpu_mut().write(0xde00, 0x01);
+    // Writes without the unlock sequence are ignored
+    c64.get_cpu_mut().write(0x8100, 0x00);
+    assert_eq!(0x41, c64.get_cpu().read(0x8100));
+    flash_command(&mut c64, 0x8000, 0xa0);
+    c64.get_cpu_mut().write(0x8100, 0x00);
+    assert_eq!(0x00, c64.get_cpu().read(0x8100));
+    assert_eq!(0x41, c64.get_cpu().read(0x8101));
+    // Erase the sector holding banks 0-7 of ROML
+    flash_command(&mut c64, 0x8000, 0x80);
+    c64.get_cpu_mut().write(0x8555, 0xaa);
+    c64.get_cpu_mut().write(0x82aa, 0x55);
+    c64.get_cpu_mut().write(0x8000, 0x30);
+    assert_eq!(0xff, c64.get_cpu().read(0x8100));
+    c64.get_cpu_mut().write(0xde00, 0x00);
+    assert_eq!(0xff, c64.get_cpu().read(0x8000));
+    assert_eq!(0x80, c64.get_cpu().read(0xa000));
+}
+
+fn build_ultimax_cartridge(offset: u16, size: u16) -> Cartridge {
+    let mut cartridge = Cartridge::new(0x0100, HwType::Normal, true, false);
+    let mut data = vec![0xea; size as usize];
+    let end = size as usize;
+    // LDA #$5A; STA $0400; JMP * and reset vector to $E000
+    data[..8].copy_from_slice(&[0xa9, 0x5a, 0x8d, 0x00, 0x04, 0x4c, 0x05, 0xe0]);
+    data[end - 4..end - 2].copy_from_slice(&[0x00, 0xe0]);
+    cartridge.add(Chip {
+        chip_type: ChipType::Rom,
+        bank_number: 0,
+        offset,
+        size,
+        data,
+    });
+    cartridge
+}
+
+#[test]
+fn ultimax_cartridge_reset_vector() {
+    let mut c64 = setup_c64_with_roms();
+    c64.attach_cartridge(build_ultimax_cartridge(0xe000, 0x2000), true);
+    assert_eq!(0x00, c64.get_cpu().read(0xfffc));
+    assert_eq!(0xe0, c64.get_cpu().read(0xfffd));
+    for _ in 0..8 {
+        c64.step();
+    }
+    assert_eq!(0xe005, c64.get_cpu().get_pc());
+    assert_eq!(0x5a, c64.get_cpu().read(0x0400));
+    let regions = c64.memory_map();
+    assert_eq!(RegionKind::Unmapped, regions[1].kind);
+    assert_eq!(0x1000, regions[1].start);
+    assert_eq!(RegionKind::CartridgeHi, regions.last().unwrap().kind);
+    // ROML is not driven by the cartridge
+    c64.get_cpu_mut().write(0x8000, 0x12);
+    assert_eq!(0x12, c64.get_cpu().read(0x8000));
+}
+
+#[test]
+fn ultimax_4k_cartridge_is_mirrored() {
+    let mut c64 = setup_c64_with_roms();
+    c64.attach_cartridge(build_ultimax_cartridge(0xf000, 0x1000), true);
+    assert_eq!(0xa9, c64.get_cpu().read(0xe000));
+    assert_eq!(0xa9, c64.get_cpu().read(0xf000));
+    assert_eq!(0xe0, c64.get_cpu().read(0xfffd));
+    c64.detach_cartridge(true);
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+}
+
+struct CounterPeripheral {
+    cycles: u32,
+    irq: bool,
+}
+
+impl Peripheral for CounterPeripheral {
+    fn clock(&mut self) {
+        self.cycles = self.cycles.wrapping_add(1);
+    }
+
+    fn reset(&mut self) {
+        self.cycles = 0;
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0xde00 => Some(self.cycles as u8),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address == 0xde01 {
+            self.irq = value != 0;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+// Pulls IRQ and NMI as set through $DE02, DMA for the number of cycles written to $DE03
+struct LinePeripheral {
+    lines: u8,
+    dma_cycles: u8,
+}
+
+impl Peripheral for LinePeripheral {
+    fn clock(&mut self) {
+        self.dma_cycles = self.dma_cycles.saturating_sub(1);
+    }
+
+    fn reset(&mut self) {
+        self.lines = 0;
+        self.dma_cycles = 0;
+    }
+
+    fn read(&mut self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xde02 => self.lines = value,
+            0xde03 => self.dma_cycles = value,
+            _ => {}
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.lines & 0x01 != 0
+    }
+
+    fn nmi(&self) -> bool {
+        self.lines & 0x02 != 0
+    }
+
+    fn dma(&self) -> bool {
+        self.dma_cycles != 0
+    }
+}
+
+// Echoes the inverted port B output back as its input
+struct InverterPeripheral {
+    output: Rc<Cell<u8>>,
+}
+
+impl Peripheral for InverterPeripheral {
+    fn clock(&mut self) {}
+
+    fn reset(&mut self) {}
+
+    fn read(&mut self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, _address: u16, _value: u8) {}
+
+    fn set_user_port_lines(&mut self, port_b: u8, _pa2: bool) {
+        self.output.set(port_b);
+    }
+
+    fn get_user_port_lines(&self) -> (u8, u8) {
+        (0xff, !self.output.get())
+    }
+}
+
+#[test]
+fn reu_stash_fetch() {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.set_reu_size(Some(0x20000)).unwrap();
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let mut c64 = C64::build(
+        config.clone(),
+        &*factory,
+        new_shared(NullVideo {}),
+        Arc::new(NullSound {}),
+    );
+    c64.reset(ResetKind::Soft);
+    let cpu = c64.get_cpu_mut();
+    cpu.write(0x3000, 0x42);
+    cpu.write(0xdf02, 0x00); // C64 address
+    cpu.write(0xdf03, 0x30);
+    cpu.write(0xdf06, 0x01); // REU bank
+    cpu.write(0xdf07, 0x01); // length
+    cpu.write(0xdf08, 0x00);
+    cpu.write(0xdf01, 0xb0);
+    cpu.write(0x3000, 0x00);
+    cpu.write(0xdf01, 0xb1);
+    assert_eq!(0x42, cpu.read(0x3000));
+    assert_eq!(0x40, cpu.read(0xdf00));
+}
+
+#[test]
+fn expansion_sizes_are_validated() {
+    let mut config = Config::new(SystemModel::from("pal"));
+    assert!(config.set_reu_size(Some(0x30000)).is_err());
+    assert_eq!(None, config.reu_size());
+    config.set_reu_size(Some(0x40000)).unwrap();
+    assert_eq!(Some(0x40000), config.reu_size());
+    assert!(config.set_georam_size(Some(0x30000)).is_err());
+    assert!(config.set_georam_size(Some(0x100000)).is_err());
+    assert_eq!(None, config.georam_size());
+    config.set_georam_size(Some(0x10000)).unwrap();
+    assert_eq!(Some(0x10000), config.georam_size());
+}
+
+#[test]
+fn snapshot_restores_reu() {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.set_reu_size(Some(0x20000)).unwrap();
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let mut c64 = C64::build(
+        config.clone(),
+        &*factory,
+        new_shared(NullVideo {}),
+        Arc::new(NullSound {}),
+    );
+    c64.reset(ResetKind::Soft);
+    let stash = |c64: &mut C64, value: u8| {
+        let cpu = c64.get_cpu_mut();
+        cpu.write(0x3000, value);
+        cpu.write(0xdf02, 0x00); // C64 address
+        cpu.write(0xdf03, 0x30);
+        cpu.write(0xdf06, 0x01); // REU bank
+        cpu.write(0xdf07, 0x01); // length
+        cpu.write(0xdf08, 0x00);
+        cpu.write(0xdf01, 0x90);
+    };
+    stash(&mut c64, 0x42);
+    let snapshot = c64.save_state();
+    stash(&mut c64, 0x24);
+    c64.load_state(&snapshot).unwrap();
+    let cpu = c64.get_cpu_mut();
+    // Registers point past the first transfer
+    assert_eq!(0x01, cpu.read(0xdf02));
+    cpu.write(0x3000, 0x00);
+    cpu.write(0xdf02, 0x00);
+    cpu.write(0xdf03, 0x30);
+    cpu.write(0xdf04, 0x00);
+    cpu.write(0xdf07, 0x01);
+    cpu.write(0xdf01, 0x91);
+    assert_eq!(0x42, cpu.read(0x3000));
+}
+
+struct TestDisk;
+
+impl Disk for TestDisk {
+    fn read_directory(&self) -> Vec<u8> {
+        vec![0x01, 0x04, 0x00, 0x00]
+    }
+
+    fn read_file(&self, pattern: &[u8]) -> Option<Vec<u8>> {
+        match pattern {
+            b"GAME" => Some(vec![0x00, 0xc1, 0xa9, 0x01, 0x60]),
+            _ => None,
+        }
+    }
+
+    fn read_file_sectors(&self, pattern: &[u8]) -> Vec<(u8, u8)> {
+        match pattern {
+            b"GAME" => vec![(17, 0)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Returns the number of cycles spent in the LOAD call.
+fn kernal_load(c64: &mut C64, name: &[u8], secondary: u8) -> u64 {
+    // SETLFS 1,8,secondary; SETNAM name at $C200; LOAD to $3000
+    let program = [
+        0xa9, 0x01, 0xa2, 0x08, 0xa0, secondary, 0x20, 0xba, 0xff, 0xa9, name.len() as u8,
+        0xa2, 0x00, 0xa0, 0xc2, 0x20, 0xbd, 0xff, 0xa9, 0x00, 0xa2, 0x00, 0xa0, 0x30, 0x20,
+        0xd5, 0xff, 0x4c, 0x1b, 0xc0,
+    ];
+    c64.reset(ResetKind::Soft);
+    while c64.get_cpu().get_pc() != 0xa65c {
+        c64.step();
+    }
+    c64.load(&program, 0xc000);
+    c64.load(name, 0xc200);
+    c64.get_cpu_mut().set_pc(0xc000);
+    let start = c64.get_clock().get();
+    let mut steps = 0;
+    while c64.get_cpu().get_pc() != 0xc01b && steps < 1_000_000 {
+        c64.step();
+        steps += 1;
+    }
+    assert_eq!(0xc01b, c64.get_cpu().get_pc());
+    c64.get_clock().get() - start
+}
+
+#[test]
+fn disk_load_trap() {
+    let mut c64 = setup_c64_with_roms();
+    c64.attach_disk(Box::new(TestDisk {}));
+    kernal_load(&mut c64, b"GAME", 1);
+    let cpu = c64.get_cpu();
+    assert_eq!(0, cpu.get_register(Register::P) & 0x01);
+    assert_eq!(0x03, cpu.get_register(Register::X));
+    assert_eq!(0xc1, cpu.get_register(Register::Y));
+    assert_eq!([0xa9, 0x01, 0x60], [cpu.read(0xc100), cpu.read(0xc101), cpu.read(0xc102)]);
+    assert_eq!(0x03, cpu.read(0x00ae));
+    assert_eq!(0xc1, cpu.read(0x00af));
+}
+
+#[test]
+fn disk_load_trap_relocates() {
+    let mut c64 = setup_c64_with_roms();
+    c64.attach_disk(Box::new(TestDisk {}));
+    kernal_load(&mut c64, b"GAME", 0);
+    let cpu = c64.get_cpu();
+    assert_eq!(0, cpu.get_register(Register::P) & 0x01);
+    assert_eq!([0xa9, 0x01, 0x60], [cpu.read(0x3000), cpu.read(0x3001), cpu.read(0x3002)]);
+    assert_eq!(0x03, cpu.get_register(Register::X));
+    assert_eq!(0x30, cpu.get_register(Register::Y));
+}
+
+#[test]
+fn disk_load_trap_file_not_found() {
+    let mut c64 = setup_c64_with_roms();
+    c64.attach_disk(Box::new(TestDisk {}));
+    kernal_load(&mut c64, b"MISSING", 1);
+    let cpu = c64.get_cpu();
+    assert_eq!(0x01, cpu.get_register(Register::P) & 0x01);
+    assert_eq!(0x04, cpu.get_register(Register::A));
+}
+
+#[test]
+fn disk_load_trap_emits_sector_reads() {
+    let mut c64 = setup_c64_with_roms();
+    c64.attach_disk(Box::new(TestDisk {}));
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let log_clone = log.clone();
+    c64.get_events().set_listener(Some(Box::new(move |_, event| {
+        if let Event::DiskSectorRead(_, _) = *event {
+            log_clone.borrow_mut().push(*event);
+        }
+    })));
+    kernal_load(&mut c64, b"GAME", 1);
+    assert_eq!(vec![Event::DiskSectorRead(17, 0)], *log.borrow());
+}
+
+// Program at $4000 with a byte pattern that shows misplaced or dropped bytes
+fn build_large_program() -> Vec<u8> {
+    let mut program = vec![0x00, 0x40];
+    program.extend((0..512).map(|i| (i * 7 + i / 256) as u8));
+    program
+}
+
+struct ProgramDisk {
+    program: Vec<u8>,
+}
+
+impl Disk for ProgramDisk {
+    fn read_directory(&self) -> Vec<u8> {
+        vec![0x01, 0x04, 0x00, 0x00]
+    }
+
+    fn read_file(&self, pattern: &[u8]) -> Option<Vec<u8>> {
+        match pattern {
+            b"GAME" => Some(self.program.clone()),
+            _ => None,
+        }
+    }
+}
+
+// Drive serving the program on channel 0 through the serial bus handshake
+struct ProgramDevice {
+    program: Vec<u8>,
+    pos: usize,
+}
+
+impl IecDevice for ProgramDevice {
+    fn open(&mut self, _channel: u8) {
+        self.pos = 0;
+    }
+
+    fn close(&mut self, _channel: u8) {}
+
+    fn write(&mut self, _channel: u8, _value: u8, _eoi: bool) {}
+
+    fn read(&mut self, channel: u8) -> Option<(u8, bool)> {
+        if channel != 0 || self.pos >= self.program.len() {
+            return None;
+        }
+        self.pos += 1;
+        Some((self.program[self.pos - 1], self.pos == self.program.len()))
+    }
+
+    fn reset(&mut self) {}
+}
+
+fn assert_program_loaded(c64: &C64, program: &[u8]) {
+    let cpu = c64.get_cpu();
+    for (i, value) in program[2..].iter().enumerate() {
+        assert_eq!(*value, cpu.read(0x4000 + i as u16));
+    }
+    let end = 0x4000 + (program.len() - 2) as u16;
+    assert_eq!(end as u8, cpu.read(0x00ae));
+    assert_eq!((end >> 8) as u8, cpu.read(0x00af));
+}
+
+#[test]
+fn iec_device_kernal_load() {
+    let program = build_large_program();
+    let mut c64 = setup_c64_with_roms();
+    c64.attach_iec_device(
+        8,
+        Box::new(ProgramDevice {
+            program: program.clone(),
+            pos: 0,
+        }),
+    )
+    .unwrap();
+    kernal_load(&mut c64, b"GAME", 1);
+    assert_program_loaded(&c64, &program);
+    assert_eq!(0, c64.get_cpu().get_register(Register::P) & 0x01);
+    assert_eq!(0x40, c64.get_cpu().read(0x0090));
+}
+
+fn setup_c64_with_kernal(kernal: &[u8], jiffydos_trap: bool) -> C64 {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        kernal,
+    );
+    config.jiffydos_trap = jiffydos_trap;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    C64::build(
+        config.clone(),
+        &*factory,
+        new_shared(NullVideo {}),
+        Arc::new(NullSound {}),
+    )
+}
+
+#[test]
+fn jiffydos_load_trap() {
+    let program = build_large_program();
+    // Stand-in for a JiffyDOS kernal with its power-on message in place of the
+    // COMMODORE banner. Its LOAD routine jams the CPU, so the load has to be served
+    // by the trap.
+    let mut kernal = RES_KERNAL_ROM.to_vec();
+    kernal[0x0479..0x0498].copy_from_slice(b"    JIFFYDOS V6.01 (C)1989 CMD ");
+    kernal[0x14a5] = 0x02;
+    let mut c64 = setup_c64_with_kernal(&kernal, true);
+    c64.attach_disk(Box::new(ProgramDisk {
+        program: program.clone(),
+    }));
+    let trap_cycles = kernal_load(&mut c64, b"GAME", 1);
+    assert_program_loaded(&c64, &program);
+    assert_eq!(0, c64.get_cpu().get_register(Register::P) & 0x01);
+    // The standard kernal trap leaves JiffyDOS alone, so without its own trap the
+    // load goes out to the empty serial bus
+    kernal[0x14a5] = 0x85;
+    let mut c64_untrapped = setup_c64_with_kernal(&kernal, false);
+    c64_untrapped.attach_disk(Box::new(ProgramDisk {
+        program: program.clone(),
+    }));
+    kernal_load(&mut c64_untrapped, b"GAME", 1);
+    assert_eq!(0x01, c64_untrapped.get_cpu().get_register(Register::P) & 0x01);
+    // The standard kernal loader takes far longer over the serial bus
+    let mut c64_serial = setup_c64_with_roms();
+    c64_serial
+        .attach_iec_device(
+            8,
+            Box::new(ProgramDevice {
+                program: program.clone(),
+                pos: 0,
+            }),
+        )
+        .unwrap();
+    let serial_cycles = kernal_load(&mut c64_serial, b"GAME", 1);
+    assert!(
+        trap_cycles * 100 < serial_cycles,
+        "trap {} serial {}",
+        trap_cycles,
+        serial_cycles
+    );
+}
+
+#[test]
+fn disk_load_realistic_timing() {
+    let program = build_large_program();
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.disk_timing = DiskTiming::Realistic;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let mut c64 = C64::build(
+        config.clone(),
+        &*factory,
+        new_shared(NullVideo {}),
+        Arc::new(NullSound {}),
+    );
+    c64.attach_disk(Box::new(ProgramDisk {
+        program: program.clone(),
+    }));
+    let cycles = kernal_load(&mut c64, b"GAME", 1);
+    assert_program_loaded(&c64, &program);
+    assert_eq!(0, c64.get_cpu().get_register(Register::P) & 0x01);
+    // Seek plus 3 sectors, 500ms at the PAL clock
+    let delay = config.model.cpu_freq as u64 / 2;
+    assert!(cycles >= delay && cycles < delay + 1000);
+}
+
+#[test]
+fn custom_peripheral() {
+    let mut c64 = setup_c64_with_roms();
+    let peripheral = CounterPeripheral {
+        cycles: 0,
+        irq: false,
+    };
+    c64.add_peripheral(Box::new(peripheral));
+    c64.reset(ResetKind::Soft);
+    assert_eq!(0x00, c64.get_cpu().read(0xde00));
+    c64.step();
+    assert_ne!(0x00, c64.get_cpu().read(0xde00));
+    c64.get_cpu_mut().write(0xde01, 0x01);
+    c64.step();
+    assert!(c64.get_irq_line().borrow().is_low());
+    c64.get_cpu_mut().write(0xde01, 0x00);
+    c64.step();
+    assert!(!c64.get_irq_line().borrow().is_low());
+}
+
+#[test]
+fn custom_peripheral_lines() {
+    let mut c64 = setup_c64_with_roms();
+    c64.add_peripheral(Box::new(LinePeripheral {
+        lines: 0,
+        dma_cycles: 0,
+    }));
+    c64.reset(ResetKind::Soft);
+    // NOP; NOP
+    c64.load(&[0xea, 0xea], 0xc000);
+    c64.get_cpu_mut().set_pc(0xc000);
+    c64.step();
+    let pc = c64.get_cpu().get_pc();
+    let start = c64.get_clock().get();
+    c64.get_cpu_mut().write(0xde03, 200);
+    while c64.get_cpu().get_pc() == pc {
+        c64.step();
+    }
+    assert_eq!(pc + 1, c64.get_cpu().get_pc());
+    assert!(c64.get_clock().get() - start >= 200);
+    c64.get_cpu_mut().write(0xde02, 0x03);
+    c64.step();
+    assert!(c64.get_irq_line().borrow().is_low());
+    assert!(c64.get_nmi_line().borrow().is_low());
+    c64.get_cpu_mut().write(0xde02, 0x00);
+    c64.step();
+    assert!(!c64.get_irq_line().borrow().is_low());
+    assert!(!c64.get_nmi_line().borrow().is_low());
+}
+
+#[test]
+fn user_port_peripheral() {
+    let mut c64 = setup_c64_with_roms();
+    let output = Rc::new(Cell::new(0));
+    c64.add_user_port_peripheral(Box::new(InverterPeripheral {
+        output: output.clone(),
+    }));
+    c64.reset(ResetKind::Soft);
+    c64.get_cpu_mut().write(0xdd03, 0x0f);
+    c64.get_cpu_mut().write(0xdd01, 0x05);
+    c64.step();
+    assert_eq!(0xf5, output.get());
+    assert_eq!(0x05, c64.get_cpu().read(0xdd01) & 0x0f);
+    assert_eq!(0x00, c64.get_cpu().read(0xdd01) & 0xf0);
+}
+
+#[test]
+fn memory_map_default() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    let regions = c64.memory_map();
+    let expected = [
+        (RegionKind::Ram, 0x0000, 0x9fff),
+        (RegionKind::BasicRom, 0xa000, 0xbfff),
+        (RegionKind::Ram, 0xc000, 0xcfff),
+        (RegionKind::Vic, 0xd000, 0xd3ff),
+        (RegionKind::Sid, 0xd400, 0xd7ff),
+        (RegionKind::ColorRam, 0xd800, 0xdbff),
+        (RegionKind::Cia1, 0xdc00, 0xdcff),
+        (RegionKind::Cia2, 0xdd00, 0xddff),
+        (RegionKind::ExpansionIo, 0xde00, 0xdfff),
+        (RegionKind::KernalRom, 0xe000, 0xffff),
+    ];
+    assert_eq!(expected.len(), regions.len());
+    for (region, (kind, start, end)) in regions.iter().zip(expected.iter()) {
+        assert_eq!(
+            MemRegion {
+                kind: *kind,
+                start: *start,
+                end: *end
+            },
+            *region
+        );
+    }
+}
+
+#[test]
+fn cpu_port_selects_memory_source() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    for &address in [0xa000u16, 0xd000, 0xe000].iter() {
+        c64.get_cpu_mut().write(0x0001, 0x34);
+        c64.get_cpu_mut().write(address, 0x5a);
+    }
+    // BASIC, KERNAL and I/O
+    c64.get_cpu_mut().write(0x0001, 0x37);
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+    assert_eq!(0x85, c64.get_cpu().read(0xe000));
+    assert_ne!(0x5a, c64.get_cpu().read(0xd000));
+    // RAM at $A000
+    c64.get_cpu_mut().write(0x0001, 0x36);
+    assert_eq!(0x5a, c64.get_cpu().read(0xa000));
+    assert_eq!(0x85, c64.get_cpu().read(0xe000));
+    // RAM at $A000 and $E000, I/O stays in
+    c64.get_cpu_mut().write(0x0001, 0x35);
+    assert_eq!(0x5a, c64.get_cpu().read(0xa000));
+    assert_eq!(0x5a, c64.get_cpu().read(0xe000));
+    assert_ne!(0x5a, c64.get_cpu().read(0xd000));
+    // Character ROM
+    c64.get_cpu_mut().write(0x0001, 0x33);
+    assert_eq!(0x3c, c64.get_cpu().read(0xd000));
+    assert_eq!(0x94, c64.get_cpu().read(0xa000));
+    // All RAM
+    c64.get_cpu_mut().write(0x0001, 0x34);
+    assert_eq!(0x5a, c64.get_cpu().read(0xd000));
+}
+
+#[test]
+fn memory_map_all_ram() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.get_cpu_mut().write(0x0001, 0x00);
+    let regions = c64.memory_map();
+    assert_eq!(1, regions.len());
+    assert_eq!(RegionKind::Ram, regions[0].kind);
+    assert_eq!(0xffff, regions[0].end);
+}
+
+#[test]
+fn four_player_adapter() {
+    let mut config = Config::new(SystemModel::from("pal"));
+    config.joystick.joystick_3 = joystick::Mode::Joy2;
+    config.joystick.joystick_4 = joystick::Mode::Joy3;
+    config.joystick.four_player_adapter = true;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(NullSound {});
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
+    c64.reset(ResetKind::Soft);
+    if let Some(ref mut joystick) = c64.get_joystick3_mut() {
+        joystick.on_key_down(joystick::Button::Up);
+    }
+    if let Some(ref mut joystick) = c64.get_joystick4_mut() {
+        joystick.on_key_down(joystick::Button::Fire);
+    }
+    c64.get_cpu_mut().write(0xdd03, 0x80);
+    c64.get_cpu_mut().write(0xdd01, 0x80);
+    assert_eq!(0x1e, c64.get_cpu().read(0xdd01) & 0x1f);
+    c64.get_cpu_mut().write(0xdd01, 0x00);
+    assert_eq!(0x0f, c64.get_cpu().read(0xdd01) & 0x1f);
+}
+
+// Park the CPU in a loop at $C000 with interrupts disabled, so the kernal leaves
+// the CIA ports alone.
+fn park_cpu(c64: &mut C64) {
+    c64.load(&[0x4c, 0x00, 0xc0], 0xc000);
+    c64.get_cpu_mut().set_pc(0xc000);
+    let p = c64.get_cpu().get_register(Register::P);
+    c64.get_cpu_mut().set_register(Register::P, p | 0x04);
+}
+
+fn run_cycles(c64: &mut C64, cycles: u64) {
+    let end = c64.get_clock().get() + cycles;
+    while c64.get_clock().get() < end {
+        c64.step();
+    }
+}
+
+#[test]
+fn joystick_second_fire_button() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    park_cpu(&mut c64);
+    if let Some(ref mut joystick) = c64.get_joystick1_mut() {
+        joystick.on_button_down(1);
+    }
+    c64.get_cpu_mut().write(0xdc02, 0xff);
+    c64.get_cpu_mut().write(0xdc00, 0x40);
+    // POT inputs are measured every 512 cycles
+    run_cycles(&mut c64, 512);
+    assert_eq!(0x00, c64.get_cpu().read(0xd419));
+    assert_eq!(0xff, c64.get_cpu().read(0xdc01));
+    c64.get_cpu_mut().write(0xdc00, 0x80);
+    run_cycles(&mut c64, 512);
+    assert_eq!(0xff, c64.get_cpu().read(0xd419));
+}
+
+#[test]
+fn joystick_second_fire_button_without_port_write() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    park_cpu(&mut c64);
+    c64.get_cpu_mut().write(0xdc02, 0xff);
+    c64.get_cpu_mut().write(0xdc00, 0x40);
+    if let Some(ref mut joystick) = c64.get_joystick1_mut() {
+        joystick.on_button_down(1);
+    }
+    run_cycles(&mut c64, 512);
+    assert_eq!(0x00, c64.get_cpu().read(0xd419));
+    if let Some(ref mut joystick) = c64.get_joystick1_mut() {
+        joystick.on_button_up(1);
+    }
+    run_cycles(&mut c64, 512);
+    assert_eq!(0xff, c64.get_cpu().read(0xd419));
+}
+
+#[test]
+fn raster_irq_events_only_when_enabled() {
+    let (mut c64, _frame_buffer) = setup_c64_headless();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let log_clone = log.clone();
+    c64.get_events().set_listener(Some(Box::new(move |_, event| {
+        if let Event::RasterIrq(line) = *event {
+            log_clone.borrow_mut().push(line);
+        }
+    })));
+    c64.reset(ResetKind::Soft);
+    assert!(c64.run_frames(BOOT_FRAMES).is_some());
+    // The kernal sets bit 8 of the raster compare, clear it to compare line $80
+    c64.get_cpu_mut().write(0xd011, 0x1b);
+    c64.get_cpu_mut().write(0xd012, 0x80);
+    assert!(c64.run_frames(2).is_some());
+    assert!(log.borrow().is_empty());
+    c64.get_cpu_mut().write(0xd01a, 0x01);
+    assert!(c64.run_frames(2).is_some());
+    assert_eq!(vec![0x80, 0x80], *log.borrow());
+}
+
+#[test]
+fn restore_key_nmi() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    while c64.get_cpu().get_pc() != 0xa65c {
+        c64.step();
+    }
+    c64.run_frame();
+    c64.set_restore_key(true);
+    let mut steps = 0;
+    while c64.get_cpu().get_pc() != 0xfe43 {
+        c64.step();
+        steps += 1;
+        assert!(steps < 100, "NMI not taken");
+    }
+    c64.set_restore_key(false);
+    // The kernal handler is reached through the NMI vector at $0318
+    c64.step();
+    c64.step();
+    assert_eq!(0xfe47, c64.get_cpu().get_pc());
+    // Without RUN/STOP held the handler returns to the interrupted program
+    for _ in 0..2000 {
+        c64.step();
+        assert_ne!(0xfe66, c64.get_cpu().get_pc());
+    }
+}
+
+#[test]
+fn run_stop_restore_warm_start() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    while c64.get_cpu().get_pc() != 0xa65c {
+        c64.step();
+    }
+    c64.run_frame();
+    c64.get_keyboard().on_key_down(KeyEvent::new(Key::RunStop));
+    c64.set_restore_key(true);
+    let mut steps = 0;
+    while c64.get_cpu().get_pc() != 0xfe66 {
+        c64.step();
+        steps += 1;
+        assert!(steps < 1000, "warm start not reached");
+    }
+    c64.set_restore_key(false);
+    c64.get_keyboard().on_key_up(KeyEvent::new(Key::RunStop));
+}
+
+#[test]
+fn joystick_key_map() {
+    let mut config = Config::new(SystemModel::from("pal"));
+    config.joystick.key_map_1 = Some(joystick::KeyMap {
+        fire: Some(Key::Space),
+        ..Default::default()
+    });
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(NullSound {});
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
+    c64.reset(ResetKind::Soft);
+    if let Some(ref mut joystick) = c64.get_joystick1_mut() {
+        joystick.on_key_down(joystick::Button::Fire);
+        joystick.on_key_down(joystick::Button::Up);
+    }
+    let (row, col) = Keyboard::map_keycode(Key::Space);
+    assert_eq!(!(1u8 << col), c64.get_keyboard().get_row(row as u8));
+    c64.get_cpu_mut().write(0xdc02, 0xff);
+    c64.get_cpu_mut().write(0xdc03, 0x00);
+    c64.get_cpu_mut().write(0xdc00, 0xff);
+    assert_eq!(0xfe, c64.get_cpu().read(0xdc01));
+    c64.get_cpu_mut().write(0xdc00, !(1u8 << row));
+    assert_eq!(!(1u8 << col) & 0xfe, c64.get_cpu().read(0xdc01));
+    if let Some(ref mut joystick) = c64.get_joystick1_mut() {
+        joystick.on_key_up(joystick::Button::Fire);
+    }
+    assert_eq!(0xff, c64.get_keyboard().get_row(row as u8));
+}
+
+#[test]
+fn joystick_swap_ports() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.get_cpu_mut().write(0xdc02, 0xff);
+    c64.get_cpu_mut().write(0xdc03, 0x00);
+    c64.get_cpu_mut().write(0xdc00, 0xff);
+    if let Some(ref mut joystick) = c64.get_joystick1_mut() {
+        joystick.on_key_down(joystick::Button::Fire);
+    }
+    assert_eq!(0xef, c64.get_cpu().read(0xdc01));
+    assert_eq!(0xff, c64.get_cpu().read(0xdc00));
+    c64.swap_joysticks();
+    assert!(c64.get_joystick1().is_none());
+    assert_eq!(0xff, c64.get_cpu().read(0xdc01));
+    if let Some(ref mut joystick) = c64.get_joystick2_mut() {
+        joystick.on_key_down(joystick::Button::Fire);
+    }
+    assert_eq!(0xef, c64.get_cpu().read(0xdc00));
+    assert_eq!(0xff, c64.get_cpu().read(0xdc01));
+}
+
+#[test]
+fn joystick_set_port() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    assert!(c64.set_joystick_port(3, joystick::Mode::Numpad).is_err());
+    c64.set_joystick_port(1, joystick::Mode::None).unwrap();
+    assert!(c64.get_joystick1().is_none());
+    c64.set_joystick_port(2, joystick::Mode::Numpad).unwrap();
+    c64.get_cpu_mut().write(0xdc02, 0xff);
+    c64.get_cpu_mut().write(0xdc00, 0xff);
+    if let Some(ref mut joystick) = c64.get_joystick2_mut() {
+        joystick.on_key_down(joystick::Button::Left);
+    }
+    assert_eq!(0xfb, c64.get_cpu().read(0xdc00));
+}
+
+#[test]
+fn mouse_pot_sequence() {
+    let mut config = Config::new(SystemModel::from("pal"));
+    config.joystick.joystick_1 = joystick::Mode::None;
+    config.joystick.mouse = Some(mouse::Mode::Proportional);
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(NullSound {});
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
+    c64.reset(ResetKind::Soft);
+    c64.get_cpu_mut().write(0xdc02, 0xff);
+    c64.get_cpu_mut().write(0xdc00, 0x40);
+    let mut pots = Vec::new();
+    for (dx, dy) in &[(0, 0), (4, -1), (30, 0), (40, 2), (-8, 0)] {
+        if let Some(ref mut mouse) = c64.get_mouse_mut() {
+            mouse.on_motion(*dx, *dy);
+        }
+        c64.get_clock().tick_delta(512);
+        pots.push((c64.get_cpu().read(0xd419), c64.get_cpu().read(0xd41a)));
+    }
+    assert_eq!(
+        vec![
+            (0x00, 0x00),
+            (0x08, 0x02),
+            (0x44, 0x02),
+            (0x14, 0x7e),
+            (0x04, 0x7e),
+        ],
+        pots
+    );
+    // Left button is fire and right button is up
+    if let Some(ref mut mouse) = c64.get_mouse_mut() {
+        mouse.on_button_down(mouse::MouseButton::Left);
+        mouse.on_button_down(mouse::MouseButton::Right);
+    }
+    assert_eq!(0xee, c64.get_cpu().read(0xdc01));
+}
+
+#[test]
+fn mouse_joystick_mode() {
+    let mut config = Config::new(SystemModel::from("pal"));
+    config.joystick.joystick_1 = joystick::Mode::None;
+    config.joystick.mouse = Some(mouse::Mode::Joystick);
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(NullSound {});
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
+    c64.reset(ResetKind::Soft);
+    c64.get_cpu_mut().write(0xdc02, 0xff);
+    c64.get_cpu_mut().write(0xdc00, 0x40);
+    if let Some(ref mut mouse) = c64.get_mouse_mut() {
+        mouse.on_motion(-2, 3);
+        mouse.on_button_down(mouse::MouseButton::Right);
+    }
+    c64.get_clock().tick_delta(512);
+    assert_eq!(0xf9, c64.get_cpu().read(0xdc01));
+    assert_eq!(0x00, c64.get_cpu().read(0xd419));
+    assert_eq!(0xff, c64.get_cpu().read(0xd41a));
+}
+
+#[test]
+fn paddle_position_and_button() {
+    let mut config = Config::new(SystemModel::from("pal"));
+    config.joystick.joystick_1 = joystick::Mode::None;
+    config.joystick.paddles_1 = true;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(NullSound {});
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
+    c64.reset(ResetKind::Soft);
+    // Select control port 1 for the SID POT inputs
+    c64.get_cpu_mut().write(0xdc02, 0xff);
+    c64.get_cpu_mut().write(0xdc00, 0x40);
+    if let Some(ref mut paddle) = c64.get_paddle1_mut() {
+        paddle.set_position(0x40);
+    }
+    if let Some(ref mut paddle) = c64.get_paddle2_mut() {
+        paddle.set_position(0xc0);
+        paddle.on_button_down();
+    }
+    // Registers keep the last measurement until the next one completes
+    let cycles = c64.get_cycles() % 512;
+    c64.get_clock().tick_delta(511 - cycles);
+    assert_ne!(0x40, c64.get_cpu().read(0xd419));
+    c64.get_clock().tick_delta(1);
+    assert_eq!(0x40, c64.get_cpu().read(0xd419));
+    assert_eq!(0xc0, c64.get_cpu().read(0xd41a));
+    // Paddle fire buttons are read as joystick left and right
+    assert_eq!(0xf7, c64.get_cpu().read(0xdc01));
+    // Other control port selected
+    c64.get_cpu_mut().write(0xdc00, 0x80);
+    c64.get_clock().tick_delta(512);
+    assert_eq!(0xff, c64.get_cpu().read(0xd419));
+    assert_eq!(0xff, c64.get_cpu().read(0xd41a));
+}
+
+#[test]
+fn hard_reset_ram_pattern() {
+    let mut c64 = setup_c64_with_roms();
+    c64.get_cpu_mut().write(0x1234, 0x5a);
+    c64.reset(ResetKind::Hard);
+    for address in 0x0200..0xa000u16 {
+        let expected = if (address / 64) % 2 == 0 { 0x00 } else { 0xff };
+        assert_eq!(expected, c64.get_cpu().read(address), "0x{:04x}", address);
+    }
+}
+
+#[test]
+fn soft_reset_preserves_ram() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Hard);
+    c64.get_cpu_mut().write(0x1234, 0x5a);
+    c64.get_cpu_mut().write(0x9fff, 0xa5);
+    c64.reset(ResetKind::Soft);
+    assert_eq!(0x5a, c64.get_cpu().read(0x1234));
+    assert_eq!(0xa5, c64.get_cpu().read(0x9fff));
+    assert_eq!(0xff, c64.get_cpu().read(0x1240));
+}
+
+#[test]
+fn reset_vector_override() {
+    let mut c64 = setup_c64_with_roms();
+    // LDA #$42, STA $0400, JMP $C005
+    c64.load(&[0xa9, 0x42, 0x8d, 0x00, 0x04, 0x4c, 0x05, 0xc0], 0xc000);
+    c64.set_reset_vector_override(Some(0xc000));
+    c64.reset(ResetKind::Soft);
+    for _i in 0..3 {
+        c64.step();
+    }
+    assert_eq!(0xc005, c64.get_cpu().get_pc());
+    assert_eq!(0x42, c64.get_cpu().read(0x0400));
+    c64.set_reset_vector_override(None);
+    c64.get_cpu_mut().write(0x01fe, 0x00);
+    c64.get_cpu_mut().write(0x01ff, 0x00);
+    c64.reset(ResetKind::Soft);
+    // The reset sequence loads the kernal vector $FCE2 over two steps, then
+    // LDX #$FF, SEI, TXS, CLD, JSR $FD02
+    for _i in 0..7 {
+        c64.step();
+    }
+    assert_eq!(0xfd02, c64.get_cpu().get_pc());
+    assert_eq!(0xfc, c64.get_cpu().read(0x01ff));
+    assert_eq!(0xe9, c64.get_cpu().read(0x01fe));
+}
+
+#[test]
+fn partial_frame_up_to_raster_line() {
+    let (mut c64, frame_buffer) = setup_c64_headless();
+    c64.reset(ResetKind::Soft);
+    assert!(c64.run_frames(BOOT_FRAMES).is_some());
+    // The boot screen is static above the cursor
+    while c64.get_raster_line() != 90 {
+        c64.step();
+    }
+    let fill = 0x1234_5678u32;
+    let frame_buffer = frame_buffer.borrow();
+    let pitch = frame_buffer.get_dimension().0 * 4;
+    let partial = frame_buffer.get_partial_frame(fill);
+    let complete = frame_buffer.get_pixel_data();
+    assert_eq!(&complete[..89 * pitch], &partial[..89 * pitch]);
+    assert!(partial[..89 * pitch]
+        .chunks(4)
+        .all(|pixel| pixel != fill.to_le_bytes()));
+    assert!(partial[91 * pitch..]
+        .chunks(4)
+        .all(|pixel| pixel == fill.to_le_bytes()));
+}
+
+#[test]
+fn raster_line_mid_frame() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    // VIC starts at raster line $100 after reset
+    while c64.get_cycles() < 63 * 20 {
+        c64.step();
+    }
+    let line = c64.get_raster_line();
+    assert!(line >= 0x100 + 19 && line <= 0x100 + 21, "line {}", line);
+}
+
+#[test]
+fn iec_bus_open_collector() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    let iec_bus = c64.get_iec_bus();
+    // Host releases all lines
+    c64.get_cpu_mut().write(0xdd02, 0x3f);
+    c64.get_cpu_mut().write(0xdd00, 0x03);
+    assert_eq!(0xc0, c64.get_cpu().read(0xdd00) & 0xc0);
+    // Drive pulls DATA low
+    iec_bus.borrow_mut().set_low(8, IecLine::Data, true);
+    assert_eq!(0x40, c64.get_cpu().read(0xdd00) & 0xc0);
+    // Host pulls CLK low
+    c64.get_cpu_mut().write(0xdd00, 0x13);
+    assert!(iec_bus.borrow().is_low(IecLine::Clk));
+    assert_eq!(0x00, c64.get_cpu().read(0xdd00) & 0xc0);
+    // DATA stays low until every device releases it
+    c64.get_cpu_mut().write(0xdd00, 0x33);
+    iec_bus.borrow_mut().set_low(8, IecLine::Data, false);
+    assert!(iec_bus.borrow().is_low(IecLine::Data));
+    c64.get_cpu_mut().write(0xdd00, 0x03);
+    assert!(iec_bus.borrow().is_high(IecLine::Data));
+    assert_eq!(0xc0, c64.get_cpu().read(0xdd00) & 0xc0);
+}
+
+fn cycles_to_ready(fast_boot: bool, cartridge: Option<Cartridge>) -> (u64, u16) {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.fast_boot = fast_boot;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(NullSound {});
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
+    if let Some(cartridge) = cartridge {
+        c64.attach_cartridge(cartridge, false);
+    }
+    c64.reset(ResetKind::Soft);
+    while c64.get_cpu().get_pc() != 0xa65c {
+        c64.step();
+    }
+    let cpu = c64.get_cpu();
+    let mem_top = ((cpu.read(0x0284) as u16) << 8) | cpu.read(0x0283) as u16;
+    (c64.get_cycles(), mem_top)
+}
+
+#[test]
+fn fast_boot_skips_ram_test() {
+    let (normal_cycles, normal_mem_top) = cycles_to_ready(false, None);
+    let (fast_cycles, fast_mem_top) = cycles_to_ready(true, None);
+    assert_eq!(0xa000, normal_mem_top);
+    assert_eq!(normal_mem_top, fast_mem_top);
+    assert!(fast_cycles * 2 < normal_cycles, "{} {}", fast_cycles, normal_cycles);
+}
 
-static CIA1TAB_PB: [u8; 12] = [
-    0x80, 0xC0, 0x80, 0x80, 0xC0, 0x80, 0x80, 0xC0, 0x00, 0x00, 0x40, 0x00,
-];
+#[test]
+fn fast_boot_finds_ram_top_below_cartridge() {
+    let (_, normal_mem_top) = cycles_to_ready(false, Some(build_autostart_cartridge(b"NOCBM")));
+    let (_, fast_mem_top) = cycles_to_ready(true, Some(build_autostart_cartridge(b"NOCBM")));
+    assert_eq!(0x8000, normal_mem_top);
+    assert_eq!(normal_mem_top, fast_mem_top);
+}
 
-struct NullSound;
-impl SoundOutput for NullSound {
-    fn reset(&self) {}
-    fn write(&self, _samples: &[i16]) {}
+fn instructions_per_frame(cr1: u8) -> u32 {
+    let mut c64 = setup_c64_with_roms();
+    // LDA #cr1, STA $D011, INC $FB, JMP $C005
+    let program = [0xa9, cr1, 0x8d, 0x11, 0xd0, 0xe6, 0xfb, 0x4c, 0x05, 0xc0];
+    c64.load(&program, 0xc000);
+    c64.set_reset_vector_override(Some(0xc000));
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 1);
+    let mut count = 0;
+    while !c64.get_vsync() {
+        c64.step();
+        count += 1;
+    }
+    c64.reset_vsync();
+    count
 }
 
-struct NullVideo;
-impl VideoOutput for NullVideo {
-    fn get_dimension(&self) -> (usize, usize) {
-        (0, 0)
+#[test]
+fn bad_lines_steal_cpu_cycles() {
+    let display_off = instructions_per_frame(0x0b);
+    let display_on = instructions_per_frame(0x1b);
+    // The loop averages 4 cycles per instruction, 25 bad lines stall the CPU for
+    // 40-43 cycles each
+    let stolen = (display_off - display_on) * 4;
+    assert!(stolen >= 25 * 40 - 8 && stolen <= 25 * 43 + 8, "stolen {}", stolen);
+}
+
+fn run_frames(c64: &mut C64, frames: u32) {
+    for _i in 0..frames {
+        c64.run_frame();
+        c64.reset_vsync();
     }
-    fn reset(&mut self) {}
-    fn write(&mut self, _index: usize, _color: u8) {}
+}
+
+fn read_jiffy_clock(c64: &C64) -> u32 {
+    let cpu = c64.get_cpu();
+    ((cpu.read(0x00a0) as u32) << 16) | ((cpu.read(0x00a1) as u32) << 8) | cpu.read(0x00a2) as u32
 }
 
 #[test]
-fn program_cia1tab() {
-    let config = Rc::new(Config::new(SystemModel::from("pal")));
+fn kernal_jiffy_clock_pal() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 150);
+    let start = read_jiffy_clock(&c64);
+    // One second at 50.125 Hz
+    run_frames(&mut c64, 50);
+    let jiffies = read_jiffy_clock(&c64) - start;
+    assert!(jiffies >= 58 && jiffies <= 61, "jiffies {}", jiffies);
+}
+
+#[test]
+fn kernal_jiffy_clock_ntsc() {
+    let config = Rc::new(Config::new_with_roms(
+        SystemModel::from("ntsc"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    ));
     let factory = Box::new(C64Factory::new(config.clone()));
     let video_output = new_shared(NullVideo {});
     let sound_output = Arc::new(NullSound {});
     let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output);
-    c64.reset(false);
-    let cia1_clone = c64.get_cia_1();
-    let cia2_clone = c64.get_cia_2();
-    let clock_clone = c64.get_clock();
-    let test_flag = Rc::new(Cell::new(false));
-    let test_flag_clone = test_flag.clone();
-    let test_cycle = Rc::new(Cell::new(0));
-    let test_cycle_clone = test_cycle.clone();
-    let tick_fn: TickFn = Rc::new(move || {
-        cia1_clone.borrow_mut().clock();
-        cia2_clone.borrow_mut().clock();
-        clock_clone.tick();
-        if test_flag_clone.get() {
-            if test_cycle_clone.get() >= 1 && test_cycle_clone.get() < 13 {
-                let i = test_cycle_clone.get() - 1;
-                assert_eq!(cia1_clone.borrow_mut().read(cia::reg::TALO), CIA1TAB_TA[i]);
-                assert_eq!(cia1_clone.borrow_mut().read(cia::reg::TBLO), CIA1TAB_TB[i]);
-                assert_eq!(cia1_clone.borrow_mut().read(cia::reg::PRB), CIA1TAB_PB[i]);
-            }
-            test_cycle_clone.set(test_cycle_clone.get() + 1);
-        }
-    });
-    c64.load(&CIA1TAB_PRG.to_vec()[2..].to_vec(), 0x4000);
-    c64.get_cpu_mut().set_pc(0x4000);
-    while test_cycle.get() < 13 {
-        c64.step_internal(&tick_fn);
-        if c64.get_cpu().get_pc() == 0x402d {
-            test_flag.set(true);
-        }
-    }
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 180);
+    let start = read_jiffy_clock(&c64);
+    // One second at 59.826 Hz
+    run_frames(&mut c64, 60);
+    let jiffies = read_jiffy_clock(&c64) - start;
+    assert!(jiffies >= 59 && jiffies <= 62, "jiffies {}", jiffies);
 }
 
 #[test]
@@ -179,3 +1704,1076 @@ loop     lda PRB
 
 ende     rts             ; back to BASIC
 */
+
+#[test]
+fn breakpoint_stops_execution() {
+    let mut c64 = setup_c64_with_roms();
+    // LDA #$42, LDX #$17, INC $D020, JMP $C007
+    c64.load(
+        &[0xa9, 0x42, 0xa2, 0x17, 0xee, 0x20, 0xd0, 0x4c, 0x07, 0xc0],
+        0xc000,
+    );
+    c64.set_reset_vector_override(Some(0xc000));
+    c64.reset(ResetKind::Soft);
+    c64.get_bpm_mut().set(0xc004, false);
+    assert_eq!(false, c64.run_frame());
+    assert_eq!(0xc004, c64.get_cpu().get_pc());
+    assert_eq!(0x42, c64.get_cpu().get_register(Register::A));
+    assert_eq!(0x17, c64.get_cpu().get_register(Register::X));
+}
+
+#[test]
+fn watchpoint_stops_execution() {
+    let mut c64 = setup_c64_with_roms();
+    // LDA #$42, NOP, STA $D020, JMP $C006
+    c64.load(&[0xa9, 0x42, 0xea, 0x8d, 0x20, 0xd0, 0x4c, 0x06, 0xc0], 0xc000);
+    c64.set_reset_vector_override(Some(0xc000));
+    c64.reset(ResetKind::Soft);
+    c64.get_cpu_mut().add_watch(0xd020, Access::Write);
+    assert_eq!(false, c64.run_frame());
+    assert_eq!(Some((0xd020, Access::Write)), c64.get_cpu().get_watch_hit());
+    assert_eq!(0xc006, c64.get_cpu().get_pc());
+    assert_eq!(0x42, c64.get_cpu().get_register(Register::A));
+    c64.get_cpu_mut().remove_watch(0xd020, Access::Write);
+    assert!(!c64.get_cpu().has_watches());
+}
+
+#[test]
+fn watch_handler_decides_when_to_halt() {
+    let mut c64 = setup_c64_with_roms();
+    // INC $C100, LDA $C100, JMP $C000
+    c64.load(
+        &[0xee, 0x00, 0xc1, 0xad, 0x00, 0xc1, 0x4c, 0x00, 0xc0],
+        0xc000,
+    );
+    c64.set_reset_vector_override(Some(0xc000));
+    c64.reset(ResetKind::Soft);
+    let reads = Rc::new(RefCell::new(Vec::new()));
+    let reads_clone = reads.clone();
+    c64.get_cpu_mut().add_watch(0xc100, Access::Read);
+    c64.get_cpu_mut()
+        .set_watch_handler(Some(Box::new(move |address, access, value| {
+            reads_clone.borrow_mut().push((address, access, value));
+            value == 3
+        })));
+    assert_eq!(false, c64.run_frame());
+    assert_eq!(Some((0xc100, Access::Read)), c64.get_cpu().get_watch_hit());
+    assert_eq!(0xc006, c64.get_cpu().get_pc());
+    assert_eq!(0x03, c64.get_cpu().get_register(Register::A));
+    // INC reads the operand, then LDA reads the result
+    let values: Vec<u8> = reads.borrow().iter().map(|read| read.2).collect();
+    assert_eq!(vec![0, 1, 1, 2, 2, 3], values);
+    assert!(reads
+        .borrow()
+        .iter()
+        .all(|read| read.0 == 0xc100 && read.1 == Access::Read));
+}
+
+#[test]
+fn snapshot_restore_replays_identically() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 120);
+    let snapshot = c64.save_state();
+    run_frames(&mut c64, 30);
+    let expected = c64.save_state();
+    let expected_pc = c64.get_cpu().get_pc();
+    c64.load_state(&snapshot).unwrap();
+    assert!(snapshot == c64.save_state());
+    run_frames(&mut c64, 30);
+    assert_eq!(expected_pc, c64.get_cpu().get_pc());
+    assert!(expected == c64.save_state());
+}
+
+#[test]
+fn snapshot_rejects_mismatched_devices() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    let snapshot = c64.save_state();
+    let copy = Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+    assert!(c64.load_state(&copy).is_ok());
+    c64.attach_cartridge(build_16k_cartridge(0xaa), false);
+    assert!(c64.load_state(&snapshot).is_err());
+}
+
+#[test]
+fn warp_mode_matches_normal_execution() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 100);
+    let normal = c64.save_state();
+    assert_eq!(Some(50.125), c64.frame_rate());
+    let mut c64_warp = setup_c64_with_roms();
+    c64_warp.set_warp_mode(true);
+    c64_warp.reset(ResetKind::Soft);
+    run_frames(&mut c64_warp, 100);
+    assert!(c64_warp.is_warp_mode());
+    assert_eq!(None, c64_warp.frame_rate());
+    assert!(normal == c64_warp.save_state());
+}
+
+#[test]
+fn sound_recording_writes_wav() {
+    let config = Rc::new(Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    ));
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let recorder = Arc::new(SoundRecorder::new(
+        Arc::new(NullSound {}),
+        config.sound.sample_rate,
+    ));
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, recorder.clone());
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 10);
+    c64.set_warp_mode(true);
+    let path = std::env::temp_dir().join("zinc64_sound_recording.wav");
+    recorder.start_recording(&path).unwrap();
+    {
+        let cpu = c64.get_cpu_mut();
+        cpu.write(0xd400, 0x00); // FREQLO1
+        cpu.write(0xd401, 0x1c); // FREQHI1
+        cpu.write(0xd405, 0x00); // AD1
+        cpu.write(0xd406, 0xf0); // SR1
+        cpu.write(0xd418, 0x0f); // MODVOL
+        cpu.write(0xd404, 0x11); // CR1
+    }
+    run_frames(&mut c64, 50);
+    let samples = recorder.stop_recording().unwrap();
+    assert!(!recorder.is_recording());
+    let cycles = 50 * config.model.cycles_per_frame as u64;
+    let expected = cycles * config.sound.sample_rate as u64 / config.model.cpu_freq as u64;
+    assert!((samples as i64 - expected as i64).abs() <= 2);
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let read_u16 = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+    assert_eq!(b"RIFF", &data[0..4]);
+    assert_eq!(36 + samples * 2, read_u32(4));
+    assert_eq!(b"WAVE", &data[8..12]);
+    assert_eq!(b"fmt ", &data[12..16]);
+    assert_eq!(1, read_u16(20));
+    assert_eq!(1, read_u16(22));
+    assert_eq!(config.sound.sample_rate, read_u32(24));
+    assert_eq!(16, read_u16(34));
+    assert_eq!(b"data", &data[36..40]);
+    assert_eq!(samples * 2, read_u32(40));
+    assert_eq!(44 + samples as usize * 2, data.len());
+    let pcm = data[44..]
+        .chunks(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect::<Vec<i16>>();
+    let min = pcm.iter().min().unwrap();
+    let max = pcm.iter().max().unwrap();
+    assert!(*max as i32 - *min as i32 > 1000);
+}
+
+fn count_rising_crossings<I: Iterator<Item = i16> + Clone>(samples: I) -> usize {
+    let count = samples.clone().count() as f64;
+    let mean = samples.clone().map(|sample| sample as f64).sum::<f64>() / count;
+    let values = samples.map(|sample| sample as f64).collect::<Vec<f64>>();
+    values
+        .windows(2)
+        .filter(|pair| pair[0] < mean && pair[1] >= mean)
+        .count()
+}
+
+#[test]
+fn dual_sid_outputs_independent_channels() {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.sound.sid_2_address = Some(0xd420);
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(BufferSound::new(2));
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output.clone());
+    c64.reset(ResetKind::Soft);
+    assert!(c64.get_sid_2().is_some());
+    run_frames(&mut c64, 5);
+    {
+        let cpu = c64.get_cpu_mut();
+        // 440Hz on the first SID, 1760Hz on the second
+        for &(base, freq) in &[(0xd400u16, 7492u16), (0xd420, 29970)] {
+            cpu.write(base, (freq & 0xff) as u8); // FREQLO1
+            cpu.write(base + 0x01, (freq >> 8) as u8); // FREQHI1
+            cpu.write(base + 0x05, 0x00); // AD1
+            cpu.write(base + 0x06, 0xf0); // SR1
+            cpu.write(base + 0x18, 0x0f); // MODVOL
+            cpu.write(base + 0x04, 0x11); // CR1
+        }
+    }
+    run_frames(&mut c64, 5);
+    sound_output.reset();
+    run_frames(&mut c64, 25);
+    let samples = sound_output.buffer.lock().unwrap().clone();
+    assert_eq!(0, samples.len() % 2);
+    let left = count_rising_crossings(samples.iter().step_by(2).cloned());
+    let right = count_rising_crossings(samples.iter().skip(1).step_by(2).cloned());
+    let seconds = 25.0 * config.model.cycles_per_frame as f64 / config.model.cpu_freq as f64;
+    assert!((left as f64 / seconds - 440.0).abs() < 10.0, "left {}", left);
+    assert!((right as f64 / seconds - 1760.0).abs() < 10.0, "right {}", right);
+}
+
+#[test]
+fn single_sid_fills_all_output_channels() {
+    let config = Rc::new(Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    ));
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let video_output = new_shared(NullVideo {});
+    let sound_output = Arc::new(BufferSound::new(2));
+    let mut c64 = C64::build(config.clone(), &*factory, video_output, sound_output.clone());
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 5);
+    {
+        let cpu = c64.get_cpu_mut();
+        cpu.write(0xd400, 0x44); // FREQLO1
+        cpu.write(0xd401, 0x1d); // FREQHI1
+        cpu.write(0xd405, 0x00); // AD1
+        cpu.write(0xd406, 0xf0); // SR1
+        cpu.write(0xd418, 0x0f); // MODVOL
+        cpu.write(0xd404, 0x11); // CR1
+    }
+    run_frames(&mut c64, 5);
+    sound_output.reset();
+    run_frames(&mut c64, 10);
+    let samples = sound_output.buffer.lock().unwrap().clone();
+    let cycles = 10 * config.model.cycles_per_frame as u64;
+    let frames = cycles * config.sound.sample_rate as u64 / config.model.cpu_freq as u64;
+    assert!((samples.len() as i64 / 2 - frames as i64).abs() <= 2);
+    assert_eq!(0, samples.len() % 2);
+    assert!(samples.iter().any(|sample| *sample != 0));
+    for frame in samples.chunks(2) {
+        assert_eq!(frame[0], frame[1]);
+    }
+}
+
+fn type_into_keyboard_buffer(c64: &mut C64, text: &[u8]) {
+    let cpu = c64.get_cpu_mut();
+    for (i, c) in text.iter().enumerate() {
+        cpu.write(0x0277 + i as u16, *c);
+    }
+    cpu.write(0x00c6, text.len() as u8);
+}
+
+#[test]
+fn load_prg_basic_program_runs() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 200);
+    // 10 PRINT"HI"
+    let prg = [
+        0x01, 0x08, 0x0c, 0x08, 0x0a, 0x00, 0x99, 0x22, 0x48, 0x49, 0x22, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(Ok(0x0801), c64.load_prg(&prg));
+    let read_word = |c64: &C64, address: u16| {
+        let cpu = c64.get_cpu();
+        cpu.read(address) as u16 | (cpu.read(address + 1) as u16) << 8
+    };
+    for (i, byte) in prg[2..].iter().enumerate() {
+        assert_eq!(*byte, c64.get_cpu().read(0x0801 + i as u16));
+    }
+    assert_eq!(0x080d, read_word(&c64, 0x2d));
+    assert_eq!(0x080d, read_word(&c64, 0x2f));
+    assert_eq!(0x080d, read_word(&c64, 0x31));
+    assert_eq!(0x080d, read_word(&c64, 0xae));
+    type_into_keyboard_buffer(&mut c64, b"RUN\r");
+    run_frames(&mut c64, 20);
+    let cpu = c64.get_cpu();
+    let screen = (0x0400..0x07e8).map(|address| cpu.read(address)).collect::<Vec<u8>>();
+    assert!(screen.windows(2).any(|chars| chars == [0x08, 0x09])); // HI
+}
+
+#[test]
+fn load_prg_absolute_keeps_basic_pointers() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    run_frames(&mut c64, 200);
+    let vartab = c64.get_cpu().read(0x2d);
+    assert_eq!(Ok(0xc000), c64.load_prg(&[0x00, 0xc0, 0xa9, 0x01, 0x60]));
+    assert_eq!(0xa9, c64.get_cpu().read(0xc000));
+    assert_eq!(0x60, c64.get_cpu().read(0xc002));
+    assert_eq!(vartab, c64.get_cpu().read(0x2d));
+    assert_eq!(0x03, c64.get_cpu().read(0xae));
+    assert_eq!(0xc0, c64.get_cpu().read(0xaf));
+    assert!(c64.load_prg(&[0x00]).is_err());
+    assert!(c64.load_prg(&[0xff, 0xff, 0x01, 0x02]).is_err());
+}
+
+fn monitor_output(monitor: &mut Monitor, c64: &mut C64, input: &str) -> String {
+    match monitor.execute(c64, input).unwrap() {
+        Reply::Output(output) => output,
+        Reply::Resume => panic!("unexpected resume"),
+    }
+}
+
+#[test]
+fn monitor_fill_and_dump() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    let mut monitor = Monitor::new();
+    monitor_output(&mut monitor, &mut c64, "f 1000 1017 41 42");
+    assert_eq!(
+        ">1000  41 42 41 42 41 42 41 42 41 42 41 42 41 42 41 42  ABABABABABABABAB\n\
+         >1010  41 42 41 42 41 42 41 42                          ABABABAB\n",
+        monitor_output(&mut monitor, &mut c64, "m 1000 1017")
+    );
+    assert_eq!(0x41, c64.get_cpu().read(0x1016));
+    assert_eq!(
+        "1000 1002 1004 1006 1008 100a 100c 100e\n1010 1012 1014 1016\n",
+        monitor_output(&mut monitor, &mut c64, "h 1000 1017 41 42")
+    );
+}
+
+#[test]
+fn monitor_register_set() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    let mut monitor = Monitor::new();
+    let output = monitor_output(&mut monitor, &mut c64, "r a=12 x=$34 y=56 sp=f0 p=24 pc=c000");
+    assert_eq!("  pc  a  x  y  sp nv-bdizc\n.c000 12 34 56 f0 00100100\n", output);
+    let cpu = c64.get_cpu();
+    assert_eq!(0xc000, cpu.get_pc());
+    assert_eq!(0x12, cpu.get_register(Register::A));
+    assert_eq!(0x34, cpu.get_register(Register::X));
+    assert_eq!(0x56, cpu.get_register(Register::Y));
+    assert_eq!(0xf0, cpu.get_register(Register::SP));
+    assert!(monitor.execute(&mut c64, "r q=01").is_err());
+}
+
+#[test]
+fn monitor_assemble_and_step() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    let mut monitor = Monitor::new();
+    assert_eq!(
+        ".1000  a9 42     LDA #$42\n",
+        monitor_output(&mut monitor, &mut c64, "a 1000 lda #$42")
+    );
+    monitor_output(&mut monitor, &mut c64, "a 1002 tax");
+    monitor_output(&mut monitor, &mut c64, "a 1003 inx");
+    monitor_output(&mut monitor, &mut c64, "a 1004 bne 1000");
+    assert_eq!(
+        ".1000  a9 42     LDA #$42\n\
+         .1002  aa        TAX\n\
+         .1003  e8        INX\n\
+         .1004  d0 fa     BNE $1000\n",
+        monitor_output(&mut monitor, &mut c64, "d 1000 1004")
+    );
+    monitor_output(&mut monitor, &mut c64, "r p=24 pc=1000");
+    let output = monitor_output(&mut monitor, &mut c64, "z");
+    assert!(output.starts_with(".1002  aa        TAX"));
+    assert_eq!(0x1002, c64.get_cpu().get_pc());
+    assert_eq!(0x42, c64.get_cpu().get_register(Register::A));
+    monitor_output(&mut monitor, &mut c64, "z 2");
+    assert_eq!(0x1004, c64.get_cpu().get_pc());
+    assert_eq!(0x43, c64.get_cpu().get_register(Register::X));
+    assert_eq!(Ok(Reply::Resume), monitor.execute(&mut c64, "g 1003"));
+    assert_eq!(0x1003, c64.get_cpu().get_pc());
+}
+
+#[test]
+fn monitor_assemble_addressing_modes() {
+    assert_eq!(Ok(vec![0xea]), assemble("nop", 0x1000));
+    assert_eq!(Ok(vec![0x0a]), assemble("asl", 0x1000));
+    assert_eq!(Ok(vec![0x0a]), assemble("asl a", 0x1000));
+    assert_eq!(Ok(vec![0xa9, 0x01]), assemble("lda #1", 0x1000));
+    assert_eq!(Ok(vec![0xa5, 0x12]), assemble("lda $12", 0x1000));
+    assert_eq!(Ok(vec![0xb6, 0x12]), assemble("ldx $12,y", 0x1000));
+    assert_eq!(Ok(vec![0xbd, 0x34, 0x12]), assemble("lda $1234,x", 0x1000));
+    assert_eq!(Ok(vec![0x6c, 0xfc, 0xff]), assemble("jmp ($fffc)", 0x1000));
+    assert_eq!(Ok(vec![0xa1, 0x12]), assemble("lda ($12,x)", 0x1000));
+    assert_eq!(Ok(vec![0xb1, 0x12]), assemble("lda ($12), y", 0x1000));
+    assert_eq!(Ok(vec![0xd0, 0xfa]), assemble("bne $1000", 0x1004));
+    assert_eq!(Ok(vec![0xa7, 0x12]), assemble("lax $12", 0x1000));
+    assert!(assemble("lda ($1234),y", 0x1000).is_err());
+    assert!(assemble("bne $2000", 0x1000).is_err());
+}
+
+#[test]
+fn color_ram_read_back() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.get_cpu_mut().write(0xd800, 0x35);
+    c64.get_cpu_mut().write(0xdbff, 0xfa);
+    assert_eq!(0xf5, c64.get_cpu().read(0xd800));
+    assert_eq!(0xfa, c64.get_cpu().read(0xdbff));
+    assert_eq!(0x05, c64.get_cpu().read(0xd800) & 0x0f);
+}
+
+#[test]
+fn color_ram_high_nibble_config() {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.color_ram_high_nibble = 0x03;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let mut c64 = C64::build(
+        config.clone(),
+        &*factory,
+        new_shared(NullVideo {}),
+        Arc::new(NullSound {}),
+    );
+    c64.reset(ResetKind::Soft);
+    c64.get_cpu_mut().write(0xd9ab, 0xce);
+    assert_eq!(0x3e, c64.get_cpu().read(0xd9ab));
+}
+
+const BOOT_FRAMES: u32 = 150;
+
+fn setup_c64_headless() -> (C64, Shared<FrameBuffer>) {
+    setup_c64_headless_with_palette(Palette::default())
+}
+
+fn setup_c64_headless_with_palette(palette: Palette) -> (C64, Shared<FrameBuffer>) {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.fast_boot = true;
+    config.palette = palette;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let (width, height) = config.model.frame_buffer_size;
+    let frame_buffer = new_shared(FrameBuffer::new(width, height, config.palette));
+    let sound_output = Arc::new(NullSound {});
+    let c64 = C64::build(config.clone(), &*factory, frame_buffer.clone(), sound_output);
+    (c64, frame_buffer)
+}
+
+// FNV-1a hash of the BASIC ready screen
+const READY_SCREEN_HASH: u64 = 0x598a_21d2_4fc2_5a19;
+
+// FNV-1a is used over the std hasher since its output is stable across releases.
+fn hash_pixels(pixels: &[u8]) -> u64 {
+    pixels.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn hash_frame(frame_buffer: &Shared<FrameBuffer>) -> u64 {
+    hash_pixels(frame_buffer.borrow().get_pixel_data())
+}
+
+#[test]
+fn headless_boot_to_ready_screen() {
+    let (mut c64, frame_buffer) = setup_c64_headless();
+    c64.reset(ResetKind::Soft);
+    let golden = hash_pixels(&c64.run_frames(BOOT_FRAMES).unwrap());
+    assert_eq!(READY_SCREEN_HASH, golden);
+    assert_eq!(BOOT_FRAMES, c64.get_frame_count());
+    {
+        let frame = frame_buffer.borrow();
+        assert_eq!(504 * 312 * 4, frame.get_pixel_data().len());
+        let (x, y) = c64.get_config().model.viewport_offset;
+        let (width, height) = c64.get_config().model.viewport_size;
+        // Light blue border and blue background of the ready screen
+        assert_eq!(
+            frame.get_palette_color(14),
+            frame.get_pixel(x as usize + 2, y as usize + 2)
+        );
+        assert_eq!(
+            frame.get_palette_color(6),
+            frame.get_pixel((x + width / 2) as usize, (y + height / 2) as usize)
+        );
+    }
+    // Screen changes are picked up by the next frame
+    c64.get_cpu_mut().write(0x0400 + 12 * 40 + 20, 0x01);
+    assert_ne!(golden, hash_pixels(&c64.run_frames(1).unwrap()));
+}
+
+// Check whether every pixel of the character cell at column, row has the color.
+fn is_cell_filled(
+    frame_buffer: &FrameBuffer,
+    model: &SystemModel,
+    cell: (u32, u32),
+    color: u8,
+) -> bool {
+    let (x, y, _, _) = Crop::Screen.rect(model);
+    let rgba = frame_buffer.get_palette_color(color);
+    (0..8).all(|row| {
+        (0..8).all(|col| {
+            frame_buffer.get_pixel(
+                (x + cell.0 * 8 + col) as usize,
+                (y + cell.1 * 8 + row) as usize,
+            ) == rgba
+        })
+    })
+}
+
+#[test]
+fn custom_charset_rom() {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    assert!(config.roms.set_charset(vec![0xff; 0x0800]).is_err());
+    assert!(config.roms.set_kernal(vec![0x00; 0x4000]).is_err());
+    assert_eq!(RES_CHARSET_ROM, config.roms.charset());
+    // Replace the @ glyph of the uppercase set with a solid block
+    let mut charset = RES_CHARSET_ROM.to_vec();
+    for byte in charset[0..8].iter_mut() {
+        *byte = 0xff;
+    }
+    config.roms.set_charset(charset).unwrap();
+    config.fast_boot = true;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let (width, height) = config.model.frame_buffer_size;
+    let frame_buffer = new_shared(FrameBuffer::new(width, height, config.palette));
+    let mut c64 = C64::build(
+        config.clone(),
+        &*factory,
+        frame_buffer.clone(),
+        Arc::new(NullSound {}),
+    );
+    c64.reset(ResetKind::Soft);
+    assert!(c64.run_frames(BOOT_FRAMES).is_some());
+    c64.get_cpu_mut().write(0x0400 + 12 * 40 + 20, 0x00);
+    c64.get_cpu_mut().write(0xd800 + 12 * 40 + 20, 0x01);
+    assert!(c64.run_frames(1).is_some());
+    assert!(is_cell_filled(
+        &frame_buffer.borrow(),
+        &config.model,
+        (20, 12),
+        1
+    ));
+    // The stock glyph leaves background pixels
+    let (mut c64_2, frame_buffer_2) = setup_c64_headless();
+    c64_2.reset(ResetKind::Soft);
+    assert!(c64_2.run_frames(BOOT_FRAMES).is_some());
+    c64_2.get_cpu_mut().write(0x0400 + 12 * 40 + 20, 0x00);
+    c64_2.get_cpu_mut().write(0xd800 + 12 * 40 + 20, 0x01);
+    assert!(c64_2.run_frames(1).is_some());
+    assert!(!is_cell_filled(
+        &frame_buffer_2.borrow(),
+        &config.model,
+        (20, 12),
+        1
+    ));
+}
+
+#[test]
+fn input_recording_playback() {
+    let (mut c64, frame_buffer) = setup_c64_headless();
+    c64.reset(ResetKind::Soft);
+    assert!(c64.run_frames(BOOT_FRAMES).is_some());
+    c64.start_recording();
+    assert!(c64.is_recording());
+    let keys = [Key::H, Key::E, Key::L, Key::L, Key::O];
+    for frame in 0..60 {
+        if frame % 6 == 0 && frame / 6 < keys.len() {
+            c64.get_keyboard()
+                .on_key_down(KeyEvent::new(keys[frame / 6]));
+        } else if frame % 6 == 3 && frame / 6 < keys.len() {
+            c64.get_keyboard().on_key_up(KeyEvent::new(keys[frame / 6]));
+        }
+        if let Some(ref mut joystick) = c64.get_joystick1_mut() {
+            if frame == 40 {
+                joystick.on_button_down(0);
+            } else if frame == 45 {
+                joystick.on_button_up(0);
+            }
+        }
+        assert!(c64.run_frames(1).is_some());
+    }
+    let recording = c64.stop().unwrap();
+    assert!(!c64.is_recording());
+    assert_eq!(60, recording.len());
+    let state = c64.save_state();
+    let golden = hash_frame(&frame_buffer);
+    // Replay on another system from the recorded initial state
+    let (mut c64_2, frame_buffer_2) = setup_c64_headless();
+    c64_2.play(recording).unwrap();
+    assert!(c64_2.is_playing());
+    assert!(c64_2.run_frames(60).is_some());
+    assert!(!c64_2.is_playing());
+    assert!(state == c64_2.save_state());
+    assert_eq!(golden, hash_frame(&frame_buffer_2));
+    // HELLO typed below the READY prompt
+    let screen = (0..5)
+        .map(|i| c64_2.get_cpu().read(0x0400 + 6 * 40 + i))
+        .collect::<Vec<u8>>();
+    assert_eq!(vec![0x08, 0x05, 0x0c, 0x0c, 0x0f], screen);
+}
+
+#[test]
+fn run_ahead_presents_later_frames() {
+    let (mut c64, frame_buffer) = setup_c64_headless();
+    let (mut c64_2, frame_buffer_2) = setup_c64_headless();
+    c64.set_run_ahead(2);
+    c64.reset(ResetKind::Soft);
+    c64_2.reset(ResetKind::Soft);
+    assert!(c64.run_frames(BOOT_FRAMES).is_some());
+    assert!(c64_2.run_frames(BOOT_FRAMES).is_some());
+    let mut hashes = Vec::new();
+    let mut hashes_2 = Vec::new();
+    for frame in 0..20 {
+        for system in [&mut c64, &mut c64_2].iter_mut() {
+            if frame == 5 {
+                system.get_keyboard().on_key_down(KeyEvent::new(Key::A));
+            } else if frame == 8 {
+                system.get_keyboard().on_key_up(KeyEvent::new(Key::A));
+            }
+            assert!(system.run_frames(1).is_some());
+        }
+        // Frames run ahead leave no trace in the machine state
+        assert!(c64.save_state() == c64_2.save_state());
+        hashes.push(hash_frame(&frame_buffer));
+        hashes_2.push(hash_frame(&frame_buffer_2));
+    }
+    // Frames shown ahead match as long as the input does not change in between
+    for frame in 0..18 {
+        if ![3, 4, 6, 7].contains(&frame) {
+            assert_eq!(hashes_2[frame + 2], hashes[frame], "frame {}", frame);
+        }
+    }
+    assert_eq!(0x01, c64.get_cpu().read(0x0400 + 6 * 40));
+}
+
+// Counts the cycles it is clocked for
+struct ClockCounter {
+    cycles: Rc<Cell<u64>>,
+}
+
+impl Peripheral for ClockCounter {
+    fn clock(&mut self) {
+        self.cycles.set(self.cycles.get() + 1);
+    }
+
+    fn reset(&mut self) {}
+
+    fn read(&mut self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, _address: u16, _value: u8) {}
+
+    fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.write_u64(self.cycles.get());
+    }
+
+    fn load_state(&mut self, snapshot: &mut SnapshotReader) -> Result<(), String> {
+        self.cycles.set(snapshot.read_u64()?);
+        Ok(())
+    }
+}
+
+#[test]
+fn run_ahead_restores_peripheral_state() {
+    let (mut c64, _frame_buffer) = setup_c64_headless();
+    let cycles = Rc::new(Cell::new(0));
+    c64.add_peripheral(Box::new(ClockCounter {
+        cycles: cycles.clone(),
+    }));
+    c64.set_run_ahead(2);
+    c64.reset(ResetKind::Soft);
+    let start = c64.get_clock().get();
+    cycles.set(0);
+    assert!(c64.run_frames(3).is_some());
+    // Cycles run ahead are rolled back with the machine
+    assert_eq!(c64.get_clock().get() - start, cycles.get());
+}
+
+#[test]
+fn run_ahead_drops_sound_of_frames_ahead() {
+    let build = |run_ahead: u32| {
+        let config = Rc::new(Config::new_with_roms(
+            SystemModel::from("pal"),
+            RES_BASIC_ROM,
+            RES_CHARSET_ROM,
+            RES_KERNAL_ROM,
+        ));
+        let factory = Box::new(C64Factory::new(config.clone()));
+        let sound_output = Arc::new(BufferSound::new(1));
+        let mut c64 = C64::build(
+            config.clone(),
+            &*factory,
+            new_shared(NullVideo {}),
+            sound_output.clone(),
+        );
+        c64.set_run_ahead(run_ahead);
+        c64.reset(ResetKind::Soft);
+        assert!(c64.run_frames(10).is_some());
+        let samples = sound_output.buffer.lock().unwrap().clone();
+        samples
+    };
+    let samples = build(0);
+    assert!(!samples.is_empty());
+    assert_eq!(samples, build(1));
+}
+
+/// Decode a PNG image written with uncompressed deflate blocks into RGBA pixels.
+fn decode_png(data: &[u8]) -> (u32, u32, Vec<u8>) {
+    assert_eq!(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a], &data[0..8]);
+    let read_u32 = |offset: usize| {
+        u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+    };
+    let mut offset = 8;
+    let (mut width, mut height) = (0, 0);
+    let mut zlib = Vec::new();
+    loop {
+        let len = read_u32(offset) as usize;
+        let kind = &data[offset + 4..offset + 8];
+        let chunk = &data[offset + 8..offset + 8 + len];
+        match kind {
+            b"IHDR" => {
+                width = read_u32(offset + 8);
+                height = read_u32(offset + 12);
+                assert_eq!(&[8, 6, 0, 0, 0], &chunk[8..13]);
+            }
+            b"IDAT" => zlib.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => panic!("unexpected chunk"),
+        }
+        offset += 12 + len;
+    }
+    let mut raw = Vec::new();
+    let mut pos = 2;
+    loop {
+        let last = zlib[pos] & 0x01 != 0;
+        let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]) as usize;
+        raw.extend_from_slice(&zlib[pos + 5..pos + 5 + len]);
+        pos += 5 + len;
+        if last {
+            break;
+        }
+    }
+    let mut pixels = Vec::new();
+    for row in raw.chunks(width as usize * 4 + 1) {
+        assert_eq!(0, row[0]);
+        pixels.extend_from_slice(&row[1..]);
+    }
+    assert_eq!((width * height * 4) as usize, pixels.len());
+    (width, height, pixels)
+}
+
+fn rgba_at(pixels: &[u8], width: u32, x: u32, y: u32) -> u32 {
+    let offset = ((y * width + x) * 4) as usize;
+    u32::from_le_bytes([
+        pixels[offset],
+        pixels[offset + 1],
+        pixels[offset + 2],
+        pixels[offset + 3],
+    ])
+}
+
+#[test]
+fn screenshot_png() {
+    let (mut c64, frame_buffer) = setup_c64_headless();
+    c64.reset(ResetKind::Soft);
+    assert!(c64.run_frames(BOOT_FRAMES).is_some());
+    let frame_buffer = frame_buffer.borrow();
+    let light_blue = frame_buffer.get_palette_color(14);
+    let blue = frame_buffer.get_palette_color(6);
+    let path = std::env::temp_dir().join("zinc64_screenshot.png");
+    // Display window only
+    c64.screenshot_png(&path, Crop::Screen).unwrap();
+    let (width, height, pixels) = decode_png(&std::fs::read(&path).unwrap());
+    assert_eq!((320, 200), (width, height));
+    assert_eq!(blue, rgba_at(&pixels, width, 0, 0));
+    assert_eq!(blue, rgba_at(&pixels, width, 319, 199));
+    let rect = Crop::Screen.rect(&c64.get_config().model);
+    assert_eq!(frame_buffer.get_area(rect), pixels);
+    // Visible area with the border
+    c64.screenshot_png(&path, Crop::Border).unwrap();
+    let (width, height, pixels) = decode_png(&std::fs::read(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((403, 284), (width, height));
+    assert_eq!(light_blue, rgba_at(&pixels, width, 0, 0));
+    assert_eq!(blue, rgba_at(&pixels, width, 0x7c - 76, 51 - 16));
+}
+
+#[test]
+fn screen_crop_follows_model() {
+    for model in ["pal", "ntsc", "ntsc-old"].iter() {
+        let mut config = Config::new_with_roms(
+            SystemModel::from(model),
+            RES_BASIC_ROM,
+            RES_CHARSET_ROM,
+            RES_KERNAL_ROM,
+        );
+        config.fast_boot = true;
+        let config = Rc::new(config);
+        let factory = Box::new(C64Factory::new(config.clone()));
+        let (width, height) = config.model.frame_buffer_size;
+        let frame_buffer = new_shared(FrameBuffer::new(width, height, config.palette));
+        let mut c64 = C64::build(
+            config.clone(),
+            &*factory,
+            frame_buffer.clone(),
+            Arc::new(NullSound {}),
+        );
+        c64.reset(ResetKind::Soft);
+        assert!(c64.run_frames(BOOT_FRAMES).is_some());
+        let frame_buffer = frame_buffer.borrow();
+        let light_blue = frame_buffer.get_palette_color(14);
+        let blue = frame_buffer.get_palette_color(6);
+        let (x, y, width, height) = Crop::Screen.rect(&config.model);
+        let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+        assert_eq!(blue, frame_buffer.get_pixel(x, y), "{}", model);
+        assert_eq!(blue, frame_buffer.get_pixel(x + width - 1, y + height - 1), "{}", model);
+        assert_eq!(light_blue, frame_buffer.get_pixel(x - 1, y), "{}", model);
+        assert_eq!(light_blue, frame_buffer.get_pixel(x, y - 1), "{}", model);
+        assert_eq!(light_blue, frame_buffer.get_pixel(x + width, y + height - 1), "{}", model);
+        assert_eq!(light_blue, frame_buffer.get_pixel(x, y + height), "{}", model);
+    }
+}
+
+#[test]
+fn screenshot_needs_pixels() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    let path = std::env::temp_dir().join("zinc64_screenshot_null.png");
+    assert!(c64.screenshot_png(&path, Crop::Screen).is_err());
+}
+
+/// Render a red reverse space in the top left character cell and return the color
+/// of a pixel inside it.
+fn render_red_character(palette: Palette) -> u32 {
+    let (mut c64, frame_buffer) = setup_c64_headless_with_palette(palette);
+    // LDA #$1B, STA $D011, LDA #$A0, STA $0400, LDA #$02, STA $D800, JMP $C00F
+    let program = [
+        0xa9, 0x1b, 0x8d, 0x11, 0xd0, 0xa9, 0xa0, 0x8d, 0x00, 0x04, 0xa9, 0x02, 0x8d, 0x00,
+        0xd8, 0x4c, 0x0f, 0xc0,
+    ];
+    c64.load(&program, 0xc000);
+    c64.set_reset_vector_override(Some(0xc000));
+    c64.reset(ResetKind::Soft);
+    assert!(c64.run_frames(3).is_some());
+    let frame_buffer = frame_buffer.borrow();
+    frame_buffer.get_pixel(0x7c + 3, 51 + 3)
+}
+
+#[test]
+fn palette_selects_output_colors() {
+    let pepto = render_red_character(Palette::Pepto);
+    let colodore = render_red_character(Palette::Colodore);
+    assert_eq!([0x68, 0x37, 0x2b, 0xff], pepto.to_le_bytes());
+    assert_eq!([0x81, 0x33, 0x38, 0xff], colodore.to_le_bytes());
+    assert_eq!(
+        [0x66, 0x2f, 0x33, 0xff],
+        render_red_character(Palette::Original).to_le_bytes()
+    );
+    let mut custom = [0; 16];
+    custom[2] = 0x102030;
+    assert_eq!(
+        [0x10, 0x20, 0x30, 0xff],
+        render_red_character(Palette::Custom(custom)).to_le_bytes()
+    );
+}
+
+#[test]
+fn peek_leaves_interrupt_flags() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    // One-shot timer A with latch 1 sets ICR bit 0 on underflow
+    c64.write_io(0xdc0d, 0x7f);
+    c64.write_io(0xdc04, 0x01);
+    c64.write_io(0xdc05, 0x00);
+    c64.write_io(0xdc0e, 0x19);
+    for _ in 0..10 {
+        c64.get_cia_1().borrow_mut().clock();
+    }
+    assert_eq!(0x01, c64.peek(0xdc0d) & 0x01);
+    assert_eq!(0x01, c64.peek(0xdc0d) & 0x01);
+    assert_eq!(0x01, c64.read_io(0xdc0d) & 0x01);
+    assert_eq!(0x00, c64.peek(0xdc0d));
+    assert_eq!(0x00, c64.read_io(0xdc0d));
+}
+
+#[test]
+fn poke_respects_banking() {
+    let mut c64 = setup_c64_with_roms();
+    c64.reset(ResetKind::Soft);
+    c64.poke(0xa000, 0x12);
+    assert_eq!(0x94, c64.peek(0xa000));
+    c64.poke(0xd800, 0x05);
+    assert_eq!(0x05, c64.peek(0xd800) & 0x0f);
+    let border = c64.peek(0xd020);
+    c64.poke(0xd020, border ^ 0x01);
+    assert_eq!(border, c64.peek(0xd020));
+    c64.poke(0x0001, 0x36);
+    assert_eq!(0x12, c64.peek(0xa000));
+}
+
+#[test]
+fn peek_leaves_cartridge_bank() {
+    let mut c64 = setup_c64_with_roms();
+    let mut cartridge = Cartridge::new(0x0100, HwType::GameSystem, false, true);
+    for bank in 0..2u8 {
+        cartridge.add(Chip {
+            chip_type: ChipType::Rom,
+            bank_number: bank,
+            offset: 0x8000,
+            size: 0x2000,
+            data: vec![0x10 + bank; 0x2000],
+        });
+    }
+    c64.attach_cartridge(cartridge, false);
+    c64.reset(ResetKind::Soft);
+    assert_eq!(0x10, c64.peek(0x8000));
+    // Reading $DE01 selects bank 1
+    c64.peek(0xde01);
+    assert_eq!(0x10, c64.peek(0x8000));
+    c64.read_io(0xde01);
+    assert_eq!(0x11, c64.peek(0x8000));
+}
+
+fn setup_c64_seeded(seed: u64) -> (C64, Shared<FrameBuffer>, Arc<BufferSound>) {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.fast_boot = true;
+    config.seed = Some(seed);
+    config.ram_noise = 0x100;
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let (width, height) = config.model.frame_buffer_size;
+    let frame_buffer = new_shared(FrameBuffer::new(width, height, config.palette));
+    let sound_output = Arc::new(BufferSound::new(1));
+    let c64 = C64::build(
+        config.clone(),
+        &*factory,
+        frame_buffer.clone(),
+        sound_output.clone(),
+    );
+    (c64, frame_buffer, sound_output)
+}
+
+fn run_seeded(seed: u64) -> (Vec<u8>, u64, Vec<i16>) {
+    let (mut c64, frame_buffer, sound_output) = setup_c64_seeded(seed);
+    c64.reset(ResetKind::Hard);
+    let ram = (0x0800..0xa000).map(|address| c64.peek(address)).collect();
+    assert!(c64.run_frames(BOOT_FRAMES).is_some());
+    {
+        let cpu = c64.get_cpu_mut();
+        cpu.write(0xd400, 0x00); // FREQLO1
+        cpu.write(0xd401, 0x20); // FREQHI1
+        cpu.write(0xd405, 0x00); // AD1
+        cpu.write(0xd406, 0xf0); // SR1
+        cpu.write(0xd418, 0x0f); // MODVOL
+        cpu.write(0xd404, 0x81); // CR1
+    }
+    sound_output.reset();
+    let keys = [Key::R, Key::U, Key::N];
+    for frame in 0..30 {
+        if frame % 6 == 0 && frame / 6 < keys.len() {
+            c64.get_keyboard()
+                .on_key_down(KeyEvent::new(keys[frame / 6]));
+        } else if frame % 6 == 3 && frame / 6 < keys.len() {
+            c64.get_keyboard().on_key_up(KeyEvent::new(keys[frame / 6]));
+        }
+        assert!(c64.run_frames(1).is_some());
+    }
+    let samples = sound_output.buffer.lock().unwrap().clone();
+    (ram, hash_frame(&frame_buffer), samples)
+}
+
+#[test]
+fn seeded_systems_are_deterministic() {
+    let (ram_1, frame_1, audio_1) = run_seeded(42);
+    let (ram_2, frame_2, audio_2) = run_seeded(42);
+    assert_eq!(ram_1, ram_2);
+    assert_eq!(frame_1, frame_2);
+    assert!(!audio_1.is_empty());
+    assert_eq!(audio_1, audio_2);
+    // Another seed changes the undefined state but not the output of the program
+    let (ram_3, frame_3, audio_3) = run_seeded(43);
+    assert_ne!(ram_1, ram_3);
+    assert_eq!(frame_1, frame_3);
+    assert_ne!(audio_1, audio_3);
+}
+
+#[test]
+fn power_on_ram_without_noise() {
+    let (mut c64, _, _) = setup_c64_seeded(42);
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.seed = Some(42);
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let mut plain = C64::build(
+        config.clone(),
+        &*factory,
+        new_shared(NullVideo {}),
+        Arc::new(NullSound {}),
+    );
+    c64.reset(ResetKind::Hard);
+    plain.reset(ResetKind::Hard);
+    let mut flipped = 0;
+    for address in 0x0800..0xa000u16 {
+        let expected = if (address / 64) % 2 == 0 { 0x00 } else { 0xff };
+        assert_eq!(expected, plain.peek(address));
+        if c64.peek(address) != expected {
+            flipped += 1;
+        }
+    }
+    assert!(flipped > 0);
+}
+
+fn sid_states(seed: Option<u64>) -> (Snapshot, Snapshot) {
+    let mut config = Config::new_with_roms(
+        SystemModel::from("pal"),
+        RES_BASIC_ROM,
+        RES_CHARSET_ROM,
+        RES_KERNAL_ROM,
+    );
+    config.seed = seed;
+    config.sound.sid_2_address = Some(0xd420);
+    let config = Rc::new(config);
+    let factory = Box::new(C64Factory::new(config.clone()));
+    let mut c64 = C64::build(
+        config.clone(),
+        &*factory,
+        new_shared(NullVideo {}),
+        Arc::new(BufferSound::new(2)),
+    );
+    c64.reset(ResetKind::Hard);
+    let mut state_1 = Snapshot::new();
+    c64.get_sid().borrow().save_state(&mut state_1);
+    let mut state_2 = Snapshot::new();
+    c64.get_sid_2().unwrap().borrow().save_state(&mut state_2);
+    (state_1, state_2)
+}
+
+#[test]
+fn seeded_sids_have_separate_noise() {
+    let (state_1, state_2) = sid_states(None);
+    assert!(state_1 == state_2);
+    let (state_1, state_2) = sid_states(Some(42));
+    assert!(state_1 != state_2);
+}
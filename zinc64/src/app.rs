@@ -3,7 +3,7 @@
 // Licensed under the GPLv3. See LICENSE file in the project root for full license text.
 
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc};
 use std::thread;
 
@@ -11,6 +11,7 @@ use glutin::event::Event;
 use zinc64_core::device::joystick;
 use zinc64_core::util::Shared;
 use zinc64_debug::{Command, Debugger};
+use zinc64_loader::build_crt_image;
 use zinc64_system::C64;
 
 use crate::audio::SoundBuffer;
@@ -19,6 +20,7 @@ use crate::debug::Debug;
 use crate::framework::{Context, State};
 use crate::gfx::Font;
 use crate::ui::{MainScreen, Screen, Transition};
+use crate::util::keymap;
 use crate::video::VideoBuffer;
 
 const CONSOLE_BUFFER: usize = 2048;
@@ -38,10 +40,14 @@ pub struct Options {
     // Controllers
     pub joydev_1: joystick::Mode,
     pub joydev_2: joystick::Mode,
+    pub keyboard_layout: keymap::Layout,
+    pub keyboard_mapping: keymap::Mapping,
     // Debug
     pub debug: bool,
     pub dbg_address: SocketAddr,
     pub rap_address: SocketAddr,
+    // Media
+    pub crt_save: Option<PathBuf>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -71,19 +77,15 @@ pub struct App {
 impl App {
     pub fn build(
         ctx: &mut Context,
-        c64: C64,
+        mut c64: C64,
         sound_buffer: Arc<SoundBuffer>,
         video_buffer: Shared<VideoBuffer>,
         options: Options,
     ) -> Result<App, String> {
         let window_size = ctx.platform.windowed_context.window().inner_size();
         // Initialize fps
-        let fps = if !options.warp_mode {
-            Some(c64.get_config().model.refresh_rate as f64)
-        } else {
-            None
-        };
-        ctx.time.set_fps(fps);
+        c64.set_warp_mode(options.warp_mode);
+        ctx.time.set_fps(c64.frame_rate());
         // Initiliaze console
         let font = Font::load_psf(Path::new("res/font/font.psf"))?;
         let cols = window_size.width / font.get_width();
@@ -132,6 +134,35 @@ impl App {
     }
 }
 
+impl Drop for App {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.state.options.crt_save {
+            if let Err(err) = save_cartridge(&self.state.c64, path) {
+                error!("Failed to save cartridge, error: {}", err);
+            }
+        }
+    }
+}
+
+/// Write the attached cartridge to a CRT file if software changed its flash.
+pub fn save_cartridge(c64: &C64, path: &Path) -> Result<(), String> {
+    let expansion_port = c64.get_expansion_port();
+    let expansion_port = expansion_port.borrow();
+    match expansion_port.get_cartridge() {
+        Some(cartridge) if cartridge.is_flash_modified() => {
+            let name = path
+                .file_stem()
+                .map(|name| name.to_string_lossy().to_uppercase())
+                .unwrap_or_default();
+            std::fs::write(path, build_crt_image(cartridge, &name))
+                .map_err(|err| format!("{}", err))?;
+            info!("Saved cartridge to {}", path.display());
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
 impl State for App {
     fn handle_event(&mut self, ctx: &mut Context, event: Event<()>) -> Result<(), String> {
         match self.screens.last_mut() {
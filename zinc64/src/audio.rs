@@ -10,13 +10,11 @@ use std::thread;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::SampleFormat;
 use zinc64_core::factory::SoundOutput;
-
-use crate::util::CircularBuffer;
-
-// TODO app: audio warp handling
+use zinc64_core::sound::{mixer, SampleQueue};
 
 const SCALER_MAX: i32 = 4096;
 const SCALER_SHIFT: usize = 12;
+const MAX_CHANNELS: usize = 8;
 const VOLUME_MAX: u8 = 100;
 const SAMPLE_FORMAT_PREFERENCE: [cpal::SampleFormat; 3] = [
     cpal::SampleFormat::I16,
@@ -48,7 +46,7 @@ impl AudioRenderer {
         let device = host
             .default_output_device()
             .expect("failed to find a default output device");
-        let config = select_config(&device, freq)?;
+        let config = select_config(&device, freq, channels)?;
         let state = Arc::new(Mutex::new(AudioRendererState {
             mute: false,
             scaler: SCALER_MAX,
@@ -106,6 +104,7 @@ impl AudioRenderer {
 fn select_config(
     device: &cpal::Device,
     freq: i32,
+    channels: u8,
 ) -> Result<cpal::SupportedStreamConfig, anyhow::Error> {
     let output_configs: cpal::SupportedOutputConfigs = device.supported_output_configs()?;
     let all_output_configs: Vec<cpal::SupportedStreamConfigRange> = output_configs.collect();
@@ -114,7 +113,7 @@ fn select_config(
     // https://github.com/RustAudio/cpal/issues/368
     let mut possible_configs = SAMPLE_FORMAT_PREFERENCE.iter().filter_map(|sample_format| {
         let format = cpal::SupportedStreamConfig::new(
-            2,
+            channels as u16,
             cpal::SampleRate(freq as u32),
             cpal::SupportedBufferSize::Unknown,
             *sample_format,
@@ -176,12 +175,15 @@ fn write_data<T>(
     T: cpal::Sample + cpal::FromSample<i16>,
 {
     let state = state.lock().unwrap();
-    let mut input = input.buffer.lock().unwrap();
+    let input_channels = input.channels;
+    let mut in_frame = [0i16; MAX_CHANNELS];
+    let mut out_frame = [0i16; MAX_CHANNELS];
     for frame in output.chunks_mut(channels) {
-        let value = input.pop().unwrap_or(0);
-        for sample in frame.iter_mut() {
+        input.buffer.pop(&mut in_frame[0..input_channels]);
+        mixer::mix_frame(&in_frame[0..input_channels], &mut out_frame[0..frame.len()]);
+        for (sample, value) in frame.iter_mut().zip(out_frame.iter()) {
             if !state.mute {
-                let value = ((value as i32 * state.scaler) >> (SCALER_SHIFT as i32)) as i16;
+                let value = ((*value as i32 * state.scaler) >> (SCALER_SHIFT as i32)) as i16;
                 let formatted_value = T::from_sample(value);
                 *sample = formatted_value;
             } else {
@@ -191,28 +193,36 @@ fn write_data<T>(
     }
 }
 
+/// Interleaved samples with the specified number of channels per frame.
 pub struct SoundBuffer {
-    buffer: Mutex<CircularBuffer<i16>>,
+    buffer: SampleQueue,
+    channels: usize,
 }
 
 impl SoundBuffer {
-    pub fn new(length: usize) -> Self {
+    pub fn new(length: usize, channels: usize) -> Self {
+        assert!(channels >= 1 && channels <= MAX_CHANNELS);
         SoundBuffer {
-            buffer: Mutex::new(CircularBuffer::new(length)),
+            buffer: SampleQueue::new(length),
+            channels,
         }
     }
+
+    pub fn get_queue(&self) -> &SampleQueue {
+        &self.buffer
+    }
 }
 
 impl SoundOutput for SoundBuffer {
     fn reset(&self) {
-        let mut output = self.buffer.lock().unwrap();
-        output.reset();
+        self.buffer.reset();
     }
 
     fn write(&self, samples: &[i16]) {
-        let mut output = self.buffer.lock().unwrap();
-        for sample in samples {
-            output.push(*sample);
-        }
+        self.buffer.write(samples);
+    }
+
+    fn get_channels(&self) -> usize {
+        self.channels
     }
 }
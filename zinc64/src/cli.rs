@@ -9,11 +9,15 @@ use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
 use structopt::StructOpt;
-use zinc64_core::device::joystick;
-use zinc64_core::factory::SystemModel;
-use zinc64_system::{Config, C64};
+use zinc64_core::device::{joystick, keyboard, mouse};
+use zinc64_core::factory::{CiaModel, SidModel, SystemModel};
+use zinc64_core::sound::sid::SamplingMethod;
+use zinc64_core::video::Palette;
+use zinc64_system::config::DiskTiming;
+use zinc64_system::{Config, SerialBridge, C64};
 
 use crate::app::{self, JamAction};
+use crate::util::keymap::{Layout, Mapping};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "zinc64")]
@@ -21,10 +25,26 @@ pub struct Opt {
     /// attach and autostart image
     #[structopt(parse(from_os_str))]
     pub image: Option<PathBuf>,
+    /// save cartridge flash changes to a CRT file on exit
+    #[structopt(long = "crt-save", parse(from_os_str))]
+    pub crt_save: Option<PathBuf>,
+    /// attach image as a raw cartridge ROM at this address, 8000 or e000 (Ultimax)
+    #[structopt(long = "cart-rom", parse(try_from_str = parse_cart_address))]
+    pub cart_address: Option<u16>,
 
-    /// set NTSC or PAL variants
+    /// set NTSC, NTSC-OLD or PAL variants
     #[structopt(long, default_value = "pal")]
     pub model: String,
+    /// set CIA revision, old (6526) or new (6526A)
+    #[structopt(
+        long = "cia-model",
+        default_value = "old",
+        parse(try_from_str = parse_cia_model)
+    )]
+    pub cia_model: CiaModel,
+    /// set SID revision, 6581 or 8580, defaults to the one of the model
+    #[structopt(long = "sid-model", parse(try_from_str = parse_sid_model))]
+    pub sid_model: Option<SidModel>,
     /// start in console mode
     #[structopt(long)]
     pub console: bool,
@@ -34,6 +54,31 @@ pub struct Opt {
     /// enable wrap mode
     #[structopt(long = "warp")]
     pub warp_mode: bool,
+    /// skip the kernal memory test on reset
+    #[structopt(long = "fastboot")]
+    pub fast_boot: bool,
+    /// serve disk loads of a JiffyDOS kernal without emulating the transfer
+    #[structopt(long = "jiffydos-trap")]
+    pub jiffydos_trap: bool,
+    /// set time taken by disk loads, instant or realistic
+    #[structopt(
+        long = "disk-timing",
+        default_value = "instant",
+        parse(try_from_str = parse_disk_timing)
+    )]
+    pub disk_timing: DiskTiming,
+    /// seed power-on RAM and SID noise for reproducible runs
+    #[structopt(long)]
+    pub seed: Option<u64>,
+    /// set chance in 4096 of a bit flip in each byte of power-on RAM
+    #[structopt(long = "ram-noise", default_value = "0")]
+    pub ram_noise: u16,
+    /// attach RAM Expansion Unit with 128, 256 or 512 KB
+    #[structopt(long = "reu", parse(try_from_str = parse_reu_size))]
+    pub reu_size: Option<usize>,
+    /// attach GeoRAM with 64 to 512 KB
+    #[structopt(long = "georam", parse(try_from_str = parse_georam_size))]
+    pub georam_size: Option<usize>,
     /// set cpu jam handling
     #[structopt(
         long = "jamaction",
@@ -53,6 +98,9 @@ pub struct Opt {
     /// enable fullscreen
     #[structopt(short, long)]
     pub fullscreen: bool,
+    /// set color palette, pepto, colodore or original
+    #[structopt(long, default_value = "pepto", parse(try_from_str = parse_palette))]
+    pub palette: Palette,
 
     // -- Devices
     /// set device for joystick 1
@@ -69,6 +117,32 @@ pub struct Opt {
         parse(try_from_str = parse_joy_mode)
     )]
     pub joydev_2: joystick::Mode,
+    /// connect 1351 mouse to control port 1, proportional or joystick mode
+    #[structopt(long, parse(try_from_str = parse_mouse_mode))]
+    pub mouse: Option<mouse::Mode>,
+    /// connect user port RS-232 to a TCP server (host:port) or terminal device path
+    #[structopt(long)]
+    pub rs232: Option<String>,
+    /// set RS-232 baud rate
+    #[structopt(long = "rs232-baud", default_value = "1200")]
+    pub rs232_baud: u32,
+    /// set host keyboard layout, us, uk or de
+    #[structopt(
+        long = "keyboard-layout",
+        default_value = "us",
+        parse(try_from_str = parse_keyboard_layout)
+    )]
+    pub keyboard_layout: Layout,
+    /// map host keys by symbol or by position
+    #[structopt(
+        long = "keyboard-mapping",
+        default_value = "symbolic",
+        parse(try_from_str = parse_keyboard_mapping)
+    )]
+    pub keyboard_mapping: Mapping,
+    /// auto-repeat held keys, delay and rate in frames, e.g. 25,3
+    #[structopt(long = "key-repeat", parse(try_from_str = parse_key_repeat))]
+    pub key_repeat: Option<keyboard::KeyRepeat>,
 
     // -- Roms
     /// filename of the basic ROM
@@ -80,6 +154,10 @@ pub struct Opt {
     /// filename of the kernal ROM
     #[structopt(long, parse(from_os_str), group = "rom")]
     pub kernal: Option<PathBuf>,
+    /// filename of the 1541 DOS ROM, attaches a 1541 drive as device 8
+    #[cfg(feature = "drive")]
+    #[structopt(long = "drive-rom", parse(from_os_str))]
+    pub drive_rom: Option<PathBuf>,
 
     // -- Sound
     /// disable sound playback
@@ -88,12 +166,31 @@ pub struct Opt {
     /// disable SID filters
     #[structopt(long = "nosidfilters")]
     pub no_sid_filters: bool,
+    /// add a second SID at this I/O address, e.g. d420 or de00
+    #[structopt(long = "sid2", parse(try_from_str = parse_sid_address))]
+    pub sid_2_address: Option<u16>,
     /// set sound sample rate in Hz
     #[structopt(long = "sound-rate", default_value = "44100")]
     pub sound_rate: u32,
+    /// set SID resampling, fast, interpolate or resample
+    #[structopt(
+        long = "sound-resampling",
+        default_value = "fast",
+        parse(try_from_str = parse_sampling_method)
+    )]
+    pub sampling_method: SamplingMethod,
+    /// set number of output channels, mono output is downmixed
+    #[structopt(long = "sound-channels", default_value = "2")]
+    pub sound_channels: u8,
     /// set sound buffer size in samples
     #[structopt(long = "sound-samples", default_value = "2048")]
     pub sound_samples: u32,
+    /// keep this many samples buffered by adjusting emulation speed, 0 to disable
+    #[structopt(long = "sound-sync", default_value = "4096")]
+    pub sound_sync: usize,
+    /// record SID output to a WAV file
+    #[structopt(long = "record-wav", parse(from_os_str))]
+    pub record_wav: Option<PathBuf>,
 
     // -- Debug
     /// set breakpoint at this address
@@ -133,17 +230,27 @@ pub fn build_app_options(opt: &Opt) -> Result<app::Options, String> {
         warp_mode: opt.warp_mode,
         joydev_1: opt.joydev_1,
         joydev_2: opt.joydev_2,
+        keyboard_layout: opt.keyboard_layout,
+        keyboard_mapping: opt.keyboard_mapping,
         debug: opt.debug,
         dbg_address: opt.dbg_address,
         rap_address: SocketAddr::from(([127, 0, 0, 1], 9999)), // opt.rap_address,
+        crt_save: opt.crt_save.clone(),
     })
 }
 
 pub fn build_emu_config(opt: &Opt) -> Result<Config, String> {
-    let model = SystemModel::from(opt.model.as_str());
+    let mut model = SystemModel::from(opt.model.as_str());
+    model.cia_model = opt.cia_model;
+    if let Some(sid_model) = opt.sid_model {
+        model.sid_model = sid_model;
+    }
     let mut config = Config::new(model);
     config.joystick.joystick_1 = opt.joydev_1;
     config.joystick.joystick_2 = opt.joydev_2;
+    config.joystick.mouse = opt.mouse;
+    config.key_repeat = opt.key_repeat;
+    config.palette = opt.palette;
     let basic_path = Path::new(
         opt.basic
             .as_ref()
@@ -162,21 +269,65 @@ pub fn build_emu_config(opt: &Opt) -> Result<Config, String> {
             .map(|path| Path::new(path))
             .unwrap_or(Path::new("res/rom/kernal.rom")),
     );
-    config.roms.basic = load_file(basic_path).map_err(|_| format!("Invalid rom: basic"))?;
-    config.roms.charset = load_file(charset_path).map_err(|_| format!("Invalid rom: charset"))?;
-    config.roms.kernal = load_file(kernal_path).map_err(|_| format!("Invalid rom: kernal"))?;
+    config
+        .roms
+        .set_basic(load_file(basic_path).map_err(|_| format!("Invalid rom: basic"))?)?;
+    config
+        .roms
+        .set_charset(load_file(charset_path).map_err(|_| format!("Invalid rom: charset"))?)?;
+    config
+        .roms
+        .set_kernal(load_file(kernal_path).map_err(|_| format!("Invalid rom: kernal"))?)?;
+    #[cfg(feature = "drive")]
+    {
+        if let Some(ref path) = opt.drive_rom {
+            config
+                .roms
+                .set_drive(load_file(path).map_err(|_| format!("Invalid rom: drive"))?)?;
+        }
+    }
+    config.fast_boot = opt.fast_boot;
+    config.jiffydos_trap = opt.jiffydos_trap;
+    config.disk_timing = opt.disk_timing;
+    config.seed = opt.seed;
+    config.ram_noise = opt.ram_noise;
+    config.set_reu_size(opt.reu_size)?;
+    config.set_georam_size(opt.georam_size)?;
     config.sound.enable = !opt.no_sound;
     config.sound.buffer_size = opt.sound_samples as usize;
+    config.sound.channels = opt.sound_channels;
     config.sound.sample_rate = opt.sound_rate;
+    config.sound.sampling_method = opt.sampling_method;
     config.sound.sid_filters = !opt.no_sid_filters;
+    config.sound.sid_2_address = opt.sid_2_address;
+    config.sound.target_buffer_level = if opt.sound_sync > 0 {
+        Some(opt.sound_sync)
+    } else {
+        None
+    };
     Ok(config)
 }
 
 pub fn set_c64_options(c64: &mut C64, opt: &Opt) -> Result<(), String> {
+    set_c64_device_options(c64, opt)?;
     set_c64_debug_options(c64, opt)?;
     Ok(())
 }
 
+fn set_c64_device_options(c64: &mut C64, opt: &Opt) -> Result<(), String> {
+    if let Some(ref target) = opt.rs232 {
+        let path = Path::new(target);
+        let bridge = if path.is_absolute() {
+            SerialBridge::open(path)
+        } else {
+            SerialBridge::connect(target)
+        }
+        .map_err(|err| format!("Invalid rs232 target {}: {}", target, err))?;
+        c64.attach_rs232(Box::new(bridge), opt.rs232_baud);
+    }
+    Ok(())
+}
+
 fn set_c64_debug_options(c64: &mut C64, opt: &Opt) -> Result<(), String> {
     for bp in &opt.bp {
         c64.get_bpm_mut().set(*bp, false);
@@ -191,6 +342,115 @@ fn load_file(path: &Path) -> Result<Vec<u8>, io::Error> {
     Ok(data)
 }
 
+fn parse_cart_address(s: &str) -> Result<u16, Box<dyn Error>> {
+    let digits = s.trim_start_matches('$').trim_start_matches("0x");
+    let address = u16::from_str_radix(digits, 16)?;
+    match address {
+        0x8000 | 0xe000 => Ok(address),
+        _ => Err(Box::<dyn Error>::from("invalid cartridge address".to_string())),
+    }
+}
+
+fn parse_cia_model(s: &str) -> Result<CiaModel, Box<dyn Error>> {
+    match s {
+        "old" | "6526" => Ok(CiaModel::Mos6526),
+        "new" | "6526a" => Ok(CiaModel::Mos6526A),
+        _ => Err(Box::<dyn Error>::from("invalid cia model".to_string())),
+    }
+}
+
+fn parse_disk_timing(s: &str) -> Result<DiskTiming, Box<dyn Error>> {
+    match s {
+        "instant" => Ok(DiskTiming::Instant),
+        "realistic" => Ok(DiskTiming::Realistic),
+        _ => Err(Box::<dyn Error>::from("invalid disk timing".to_string())),
+    }
+}
+
+fn parse_palette(s: &str) -> Result<Palette, Box<dyn Error>> {
+    Palette::from(s).map_err(Box::<dyn Error>::from)
+}
+
+fn parse_sampling_method(s: &str) -> Result<SamplingMethod, Box<dyn Error>> {
+    match s {
+        "fast" => Ok(SamplingMethod::Fast),
+        "interpolate" => Ok(SamplingMethod::Interpolate),
+        "resample" => Ok(SamplingMethod::Resample),
+        _ => Err(Box::<dyn Error>::from("invalid sampling method".to_string())),
+    }
+}
+
+fn parse_georam_size(s: &str) -> Result<usize, Box<dyn Error>> {
+    match s.trim_end_matches(|c| c == 'k' || c == 'K') {
+        "64" => Ok(0x10000),
+        "128" => Ok(0x20000),
+        "256" => Ok(0x40000),
+        "512" => Ok(0x80000),
+        _ => Err(Box::<dyn Error>::from("invalid georam size".to_string())),
+    }
+}
+
+fn parse_key_repeat(s: &str) -> Result<keyboard::KeyRepeat, Box<dyn Error>> {
+    let mut values = s.split(',').map(|value| value.trim().parse::<u32>());
+    match (values.next(), values.next(), values.next()) {
+        (Some(Ok(delay)), Some(Ok(rate)), None) if rate > 0 => {
+            Ok(keyboard::KeyRepeat { delay, rate })
+        }
+        _ => Err(Box::<dyn Error>::from("invalid key repeat".to_string())),
+    }
+}
+
+fn parse_keyboard_layout(s: &str) -> Result<Layout, Box<dyn Error>> {
+    match s {
+        "us" => Ok(Layout::Us),
+        "uk" => Ok(Layout::Uk),
+        "de" => Ok(Layout::De),
+        _ => Err(Box::<dyn Error>::from("invalid keyboard layout".to_string())),
+    }
+}
+
+fn parse_keyboard_mapping(s: &str) -> Result<Mapping, Box<dyn Error>> {
+    match s {
+        "symbolic" => Ok(Mapping::Symbolic),
+        "positional" => Ok(Mapping::Positional),
+        _ => Err(Box::<dyn Error>::from("invalid keyboard mapping".to_string())),
+    }
+}
+
+fn parse_mouse_mode(s: &str) -> Result<mouse::Mode, Box<dyn Error>> {
+    match s {
+        "proportional" => Ok(mouse::Mode::Proportional),
+        "joystick" => Ok(mouse::Mode::Joystick),
+        _ => Err(Box::<dyn Error>::from("invalid mouse mode".to_string())),
+    }
+}
+
+fn parse_reu_size(s: &str) -> Result<usize, Box<dyn Error>> {
+    match s.trim_end_matches(|c| c == 'k' || c == 'K') {
+        "128" => Ok(0x20000),
+        "256" => Ok(0x40000),
+        "512" => Ok(0x80000),
+        _ => Err(Box::<dyn Error>::from("invalid reu size".to_string())),
+    }
+}
+
+fn parse_sid_address(s: &str) -> Result<u16, Box<dyn Error>> {
+    let digits = s.trim_start_matches('$').trim_start_matches("0x");
+    let address = u16::from_str_radix(digits, 16)?;
+    match address {
+        0xd420..=0xd7e0 | 0xde00..=0xdfe0 if address & 0x1f == 0 => Ok(address),
+        _ => Err(Box::<dyn Error>::from("invalid sid address".to_string())),
+    }
+}
+
+fn parse_sid_model(s: &str) -> Result<SidModel, Box<dyn Error>> {
+    match s {
+        "6581" => Ok(SidModel::Mos6581),
+        "8580" => Ok(SidModel::Mos8580),
+        _ => Err(Box::<dyn Error>::from("invalid sid model".to_string())),
+    }
+}
+
 fn parse_jam_action(s: &str) -> Result<JamAction, Box<dyn Error>> {
     match s {
         "continue" => Ok(JamAction::Continue),
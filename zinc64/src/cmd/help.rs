@@ -5,6 +5,8 @@
 use std::io::Write;
 use std::result::Result;
 
+use zinc64_system::Monitor;
+
 use super::load::LoadCommand;
 use super::ls::LsCommand;
 use super::reset::ResetCommand;
@@ -32,6 +34,7 @@ impl HelpCommand {
         let mut buffer = String::new();
         buffer.push_str("load\n");
         buffer.push_str("ls\n");
+        buffer.push_str("monitor\n");
         buffer.push_str("reset\n");
         buffer.push_str("exit (x)\n");
         buffer.push_str("help (?)\n");
@@ -45,6 +48,7 @@ impl Handler for HelpCommand {
             let text = match command.trim().to_lowercase().as_str() {
                 "load" => HelpCommand::format(LoadCommand::help(), ""),
                 "ls" => HelpCommand::format(LsCommand::help(), ""),
+                "monitor" => Monitor::help().to_string(),
                 "reset" => HelpCommand::format(ResetCommand::help(), ""),
                 _ => format!("Invalid command: {}", command),
             };
@@ -11,7 +11,7 @@ mod reset;
 use std::io::Write;
 use std::result::Result;
 
-use zinc64_system::C64;
+use zinc64_system::{Monitor, Reply, C64};
 
 use self::help::HelpCommand;
 use self::load::LoadCommand;
@@ -23,6 +23,7 @@ pub enum Cmd {
     Help(Option<String>),
     Load(String),
     Ls(Option<String>),
+    Monitor(String),
     Reset(bool),
 }
 
@@ -31,24 +32,43 @@ trait Handler {
 }
 
 pub struct Executor {
+    monitor: Monitor,
     parser: Parser,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
+            monitor: Monitor::new(),
             parser: Parser::new(),
         }
     }
 
-    pub fn execute(&self, input: &str, c64: &mut C64, out: &mut dyn Write) -> Result<(), String> {
+    /// Execute the command, returns true if emulation should resume.
+    pub fn execute(
+        &mut self,
+        input: &str,
+        c64: &mut C64,
+        out: &mut dyn Write,
+    ) -> Result<bool, String> {
         let command = self.parser.parse(input)?;
         let mut handler: Box<dyn Handler> = match command {
+            Cmd::Monitor(input) => {
+                return match self.monitor.execute(c64, &input)? {
+                    Reply::Output(output) => {
+                        out.write(output.as_bytes())
+                            .map_err(|err| format!("{}", err))?;
+                        Ok(false)
+                    }
+                    Reply::Resume => Ok(true),
+                };
+            }
             Cmd::Load(path) => Box::new(LoadCommand::new(c64, path)),
             Cmd::Ls(path) => Box::new(LsCommand::new(path)),
             Cmd::Reset(hard) => Box::new(ResetCommand::new(c64, hard)),
             Cmd::Help(command) => Box::new(HelpCommand::new(command)),
         };
-        handler.run(out)
+        handler.run(out)?;
+        Ok(false)
     }
 }
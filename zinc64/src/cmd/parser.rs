@@ -29,6 +29,9 @@ impl Parser {
         let mut tokens = input.split_whitespace();
         if let Some(command) = tokens.next() {
             match command.to_lowercase().as_str() {
+                "a" | "d" | "f" | "g" | "h" | "m" | "r" | "z" => {
+                    Ok(Cmd::Monitor(input.to_string()))
+                }
                 "load" => self.parse_load(&mut tokens),
                 "ls" => self.parse_ls(&mut tokens),
                 "reset" => self.parse_reset(&mut tokens),
@@ -5,7 +5,7 @@
 use std::io::Write;
 use std::result::Result;
 
-use zinc64_system::C64;
+use zinc64_system::{ResetKind, C64};
 
 use super::Handler;
 
@@ -26,7 +26,11 @@ impl<'a> ResetCommand<'a> {
 
 impl<'a> Handler for ResetCommand<'a> {
     fn run(&mut self, out: &mut dyn Write) -> Result<(), String> {
-        self.c64.reset(self.hard);
+        self.c64.reset(if self.hard {
+            ResetKind::Hard
+        } else {
+            ResetKind::Soft
+        });
         out.write("Reset system.\n".as_bytes())
             .map_err(|err| format!("{}", err))?;
         Ok(())
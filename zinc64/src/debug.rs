@@ -12,7 +12,7 @@ use std::time::Duration;
 use byteorder::{BigEndian, WriteBytesExt};
 use zinc64_core::factory::Register;
 use zinc64_debug::{Command, Output, RegData, RegOp};
-use zinc64_system::C64;
+use zinc64_system::{ResetKind, C64};
 
 use crate::app::RuntimeState;
 
@@ -224,11 +224,10 @@ impl Debug {
     }
 
     fn mem_read(&self, c64: &mut C64, start: u16, end: u16) -> Result<CmdResult, String> {
-        let cpu = c64.get_cpu();
         let mut buffer = Vec::new();
         let mut address = start;
         while address < end {
-            buffer.push(cpu.read(address));
+            buffer.push(c64.peek(address));
             address = address.wrapping_add(1);
         }
         CmdResult::ok(Output::Buffer(buffer))
@@ -272,7 +271,7 @@ impl Debug {
     }
 
     fn sys_reset(&self, c64: &mut C64, hard: bool) -> Result<CmdResult, String> {
-        c64.reset(hard);
+        c64.reset(if hard { ResetKind::Hard } else { ResetKind::Soft });
         CmdResult::unit()
     }
 
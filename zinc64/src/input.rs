@@ -4,22 +4,30 @@
 
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::cast_lossless))]
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use glutin::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use glutin::event::{
+    DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+};
 use zinc64_core::device::joystick::Button;
+use zinc64_core::device::keyboard::KeyEvent;
+use zinc64_core::device::mouse;
 use zinc64_system::C64;
 
 use crate::util::keymap::KeyMap;
 
 pub struct InputSystem {
+    keymap: KeyMap,
+    pressed_keys: HashMap<VirtualKeyCode, KeyEvent>,
     _pressed_joy_keys: HashSet<VirtualKeyCode>,
     _pressed_joy_buttons: Vec<Button>,
 }
 
 impl InputSystem {
-    pub fn build() -> Result<InputSystem, String> {
+    pub fn build(keymap: KeyMap) -> Result<InputSystem, String> {
         Ok(InputSystem {
+            keymap,
+            pressed_keys: HashMap::new(),
             _pressed_joy_keys: HashSet::new(),
             _pressed_joy_buttons: Vec::new(),
         })
@@ -38,19 +46,45 @@ impl InputSystem {
                         },
                     ..
                 } => match (virtual_code, state) {
+                    (VirtualKeyCode::PageUp, _) => {
+                        c64.set_restore_key(*state == ElementState::Pressed);
+                    }
                     (_, ElementState::Pressed) => {
-                        if let Some(key_event) = KeyMap::map_key(*virtual_code, *modifiers) {
+                        if let Some(key_event) = self.keymap.map_key(*virtual_code, *modifiers) {
+                            self.pressed_keys.insert(*virtual_code, key_event);
                             c64.get_keyboard().on_key_down(key_event);
                         }
                     }
                     (_, ElementState::Released) => {
-                        if let Some(key_event) = KeyMap::map_key(*virtual_code, *modifiers) {
+                        // Release the keys pressed for the host key even if modifiers changed
+                        if let Some(key_event) = self.pressed_keys.remove(virtual_code) {
                             c64.get_keyboard().on_key_up(key_event);
                         }
                     }
                 },
+                WindowEvent::MouseInput { state, button, .. } => {
+                    let button = match button {
+                        MouseButton::Left => Some(mouse::MouseButton::Left),
+                        MouseButton::Right => Some(mouse::MouseButton::Right),
+                        _ => None,
+                    };
+                    if let (Some(button), Some(ref mut mouse)) = (button, c64.get_mouse_mut()) {
+                        match state {
+                            ElementState::Pressed => mouse.on_button_down(button),
+                            ElementState::Released => mouse.on_button_up(button),
+                        }
+                    }
+                }
                 _ => (),
             },
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                if let Some(ref mut mouse) = c64.get_mouse_mut() {
+                    mouse.on_motion(delta.0 as i32, delta.1 as i32);
+                }
+            }
             _ => (),
         }
         // FIXME self.handle_joystick_event(c64, event);
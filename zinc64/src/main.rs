@@ -14,7 +14,6 @@ mod debug;
 mod framework;
 mod gfx;
 mod input;
-mod palette;
 mod platform;
 mod time;
 mod ui;
@@ -30,13 +29,12 @@ use std::sync::Arc;
 
 use structopt::StructOpt;
 use zinc64_core::util::new_shared;
-use zinc64_loader::Loaders;
-use zinc64_system::{C64Factory, C64};
+use zinc64_loader::{Loader, Loaders, RomLoader};
+use zinc64_system::{C64Factory, ResetKind, SoundRecorder, C64};
 
 use crate::app::App;
 use crate::audio::SoundBuffer;
 use crate::cli::Opt;
-use crate::palette::Palette;
 use crate::util::{FileReader, Logger};
 use crate::video::VideoBuffer;
 
@@ -53,9 +51,11 @@ fn main() {
     };
 }
 
-fn load_image(c64: &mut C64, path: &Path) -> Result<(), String> {
-    let ext = path.extension().map(|s| s.to_str().unwrap());
-    let loader = Loaders::from_ext(ext)?;
+fn load_image(c64: &mut C64, path: &Path, cart_address: Option<u16>) -> Result<(), String> {
+    let loader: Box<dyn Loader> = match cart_address {
+        Some(address) => Box::new(RomLoader::new(address)),
+        None => Loaders::from_ext(path.extension().map(|s| s.to_str().unwrap()))?,
+    };
     let file = File::open(path).map_err(|err| format!("{}", err))?;
     let mut reader = FileReader(BufReader::new(file));
     let mut autostart = loader.autostart(&mut reader)?;
@@ -68,26 +68,40 @@ fn run(opt: &Opt) -> Result<(), String> {
     Logger::enable(logger)?;
     info!("Starting {}", NAME);
     let config = Rc::new(cli::build_emu_config(opt)?);
-    let sound_buffer = Arc::new(SoundBuffer::new(config.sound.buffer_size << 2));
+    let sound_channels = config.sound.channels as usize;
+    let sound_buffer = Arc::new(SoundBuffer::new(
+        (config.sound.buffer_size << 2) * sound_channels,
+        sound_channels,
+    ));
+    let sound_recorder = Arc::new(SoundRecorder::new(
+        sound_buffer.clone(),
+        config.sound.sample_rate,
+    ));
+    if let Some(path) = &opt.record_wav {
+        sound_recorder.start_recording(path)?;
+    }
     let video_buffer = new_shared(VideoBuffer::new(
         config.model.frame_buffer_size.0,
         config.model.frame_buffer_size.1,
-        Palette::default(),
+        config.palette.to_rgba(),
     ));
     let chip_factory = Box::new(C64Factory::new(config.clone()));
     let mut c64 = C64::build(
         config.clone(),
         &*chip_factory,
         video_buffer.clone(),
-        sound_buffer.clone(),
+        sound_recorder.clone(),
     );
     cli::set_c64_options(&mut c64, opt)?;
-    c64.reset(true);
+    c64.reset(ResetKind::Hard);
     if let Some(image_path) = &opt.image {
-        load_image(&mut c64, Path::new(image_path))?;
+        load_image(&mut c64, Path::new(image_path), opt.cart_address)?;
     }
     if opt.console {
         run_console(&mut c64);
+        if let Some(path) = &opt.crt_save {
+            app::save_cartridge(&c64, path)?;
+        }
     } else {
         let app_options = cli::build_app_options(opt)?;
         let fx_options = framework::Options {
@@ -105,6 +119,9 @@ fn run(opt: &Opt) -> Result<(), String> {
             )
         })?;
     }
+    if sound_recorder.is_recording() {
+        sound_recorder.stop_recording()?;
+    }
     Ok(())
 }
 
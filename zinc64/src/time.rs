@@ -24,6 +24,13 @@ impl Time {
         self.timer = fps.map(|v| Timer::new(1.0 / v));
     }
 
+    /// Change frame rate keeping the time accumulated towards the next event.
+    pub fn adjust_fps(&mut self, fps: f64) {
+        if let Some(timer) = self.timer.as_mut() {
+            timer.interval = 1.0 / fps;
+        }
+    }
+
     #[allow(unused)]
     pub fn elapsed(&self) -> Duration {
         self.elapsed
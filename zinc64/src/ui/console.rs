@@ -239,19 +239,23 @@ impl Screen<AppState> for ConsoleScreen {
                             self.handle_input(app_state, virtual_code, state, modifiers)
                         {
                             app_state.console.restore_pos();
-                            match self.cmd_handler.execute(
+                            let resume = match self.cmd_handler.execute(
                                 &input,
                                 &mut app_state.c64,
                                 &mut app_state.console,
                             ) {
-                                Ok(_) => {}
+                                Ok(resume) => resume,
                                 Err(error) => {
                                     app_state.console.print("ERROR: ".as_bytes());
                                     app_state.console.print(error.as_bytes());
                                     app_state.console.print(&['\n' as u8]);
+                                    false
                                 }
-                            }
+                            };
                             app_state.console.save_pos();
+                            if resume {
+                                return Ok(Transition::Pop);
+                            }
                         }
                         Ok(Transition::None)
                     }
@@ -10,7 +10,9 @@ use std::result::Result;
 
 use glutin::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use glutin::window::Fullscreen;
-use zinc64_loader::Loaders;
+use zinc64_core::factory::SoundOutput;
+use zinc64_loader::{build_tap_image, Loaders};
+use zinc64_system::{AudioSync, ResetKind};
 
 use crate::app::{AppState, JamAction, RuntimeState};
 use crate::audio::AudioRenderer;
@@ -18,12 +20,17 @@ use crate::framework::Context;
 use crate::input::InputSystem;
 use crate::ui::console::ConsoleScreen;
 use crate::ui::{Screen, Transition};
+use crate::util::keymap::KeyMap;
 use crate::util::FileReader;
 use crate::video::VideoRenderer;
 
+const TAPE_RECORDING: &str = "recording.tap";
+const AUDIO_SYNC_MAX_ADJUST: f64 = 0.005;
+
 pub struct MainScreen {
     // Components
     audio_device: AudioRenderer,
+    audio_sync: Option<AudioSync>,
     input_system: InputSystem,
     video_renderer: VideoRenderer,
     // Runtime State
@@ -36,18 +43,29 @@ impl MainScreen {
         let mut audio_device = AudioRenderer::build(
             //&audio_sys,
             state.c64.get_config().sound.sample_rate as i32,
-            1,
+            state.c64.get_config().sound.channels,
             state.c64.get_config().sound.buffer_size as u16,
             state.sound_buffer.clone(),
         )
         .map_err(|err| format!("{}", err))?;
         audio_device.start();
+        let audio_sync = state
+            .c64
+            .get_config()
+            .sound
+            .target_buffer_level
+            .map(|level| AudioSync::new(level, AUDIO_SYNC_MAX_ADJUST));
         // Initialize video
         let video_renderer = VideoRenderer::build(ctx, state)?;
         // Initialize input
-        let input_system = InputSystem::build()?;
+        let keymap = KeyMap::new(
+            state.options.keyboard_layout,
+            state.options.keyboard_mapping,
+        );
+        let input_system = InputSystem::build(keymap)?;
         Ok(MainScreen {
             audio_device,
+            audio_sync,
             input_system,
             video_renderer,
             next_keyboard_event: 0,
@@ -91,13 +109,13 @@ impl MainScreen {
         if state.c64.get_keyboard().has_events()
             && state.c64.get_cycles() >= self.next_keyboard_event
         {
-            state.c64.get_keyboard().drain_event();
-            self.next_keyboard_event = state.c64.get_cycles().wrapping_add(20000);
+            let delay = state.c64.get_keyboard().drain_event();
+            self.next_keyboard_event = state.c64.get_cycles().wrapping_add(u64::from(delay));
         }
     }
 
     fn reset(&mut self, state: &mut AppState) {
-        state.c64.reset(false);
+        state.c64.reset(ResetKind::Soft);
         self.next_keyboard_event = 0;
     }
 
@@ -117,6 +135,20 @@ impl MainScreen {
         }
     }
 
+    fn toggle_datassette_record(&mut self, state: &mut AppState) {
+        let datassette = state.c64.get_datasette();
+        if !datassette.borrow().is_record_pressed() {
+            datassette.borrow_mut().record();
+        } else {
+            datassette.borrow_mut().stop();
+            let data = datassette.borrow_mut().take_recording();
+            match std::fs::write(TAPE_RECORDING, build_tap_image(&data)) {
+                Ok(_) => info!("Saved tape recording to {}", TAPE_RECORDING),
+                Err(err) => error!("Failed to save tape recording, error: {}", err),
+            }
+        }
+    }
+
     fn toggle_fullscreen(&mut self, ctx: &mut Context) {
         match ctx.platform.windowed_context.window().fullscreen() {
             None => {
@@ -148,12 +180,21 @@ impl MainScreen {
     fn toggle_warp(&mut self, ctx: &mut Context, state: &mut AppState) {
         let value = state.options.warp_mode;
         state.options.warp_mode = !value;
-        let fps = if !state.options.warp_mode {
-            Some(state.c64.get_config().model.refresh_rate as f64)
-        } else {
-            None
-        };
-        ctx.time.set_fps(fps);
+        state.c64.set_warp_mode(state.options.warp_mode);
+        ctx.time.set_fps(state.c64.frame_rate());
+        if let Some(audio_sync) = self.audio_sync.as_mut() {
+            audio_sync.reset();
+        }
+    }
+
+    fn sync_audio(&mut self, ctx: &mut Context, state: &AppState) {
+        if let Some(audio_sync) = self.audio_sync.as_mut() {
+            let level = state.sound_buffer.get_queue().samples_available()
+                / state.sound_buffer.get_channels();
+            let ratio = audio_sync.update(level);
+            let refresh_rate = state.c64.get_config().model.refresh_rate as f64;
+            ctx.time.adjust_fps(refresh_rate * ratio);
+        }
     }
 
     fn update_audio_state(&mut self, state: &mut AppState) {
@@ -200,6 +241,10 @@ impl Screen<AppState> for MainScreen {
                         self.halt(app_state)?;
                         Ok(Transition::None)
                     }
+                    (VirtualKeyCode::J, ElementState::Pressed) if modifiers.alt() => {
+                        app_state.c64.swap_joysticks();
+                        Ok(Transition::None)
+                    }
                     (VirtualKeyCode::M, ElementState::Pressed) if modifiers.alt() => {
                         self.toggle_mute();
                         Ok(Transition::None)
@@ -220,10 +265,20 @@ impl Screen<AppState> for MainScreen {
                         self.toggle_fullscreen(ctx);
                         Ok(Transition::None)
                     }
+                    (VirtualKeyCode::Z, ElementState::Pressed) if modifiers.alt() => {
+                        if !app_state.c64.freeze_cartridge() {
+                            warn!("Attached cartridge does not support freezing");
+                        }
+                        Ok(Transition::None)
+                    }
                     (VirtualKeyCode::F1, ElementState::Pressed) if modifiers.ctrl() => {
                         self.toggle_datassette_play(app_state);
                         Ok(Transition::None)
                     }
+                    (VirtualKeyCode::F2, ElementState::Pressed) if modifiers.ctrl() => {
+                        self.toggle_datassette_record(app_state);
+                        Ok(Transition::None)
+                    }
                     (VirtualKeyCode::F9, ElementState::Pressed) if modifiers.ctrl() => {
                         self.reset(app_state);
                         Ok(Transition::None)
@@ -248,7 +303,7 @@ impl Screen<AppState> for MainScreen {
 
     fn update(
         &mut self,
-        _ctx: &mut Context,
+        ctx: &mut Context,
         state: &mut AppState,
     ) -> Result<Transition<AppState>, String> {
         self.process_keyboard_events(state);
@@ -270,6 +325,8 @@ impl Screen<AppState> for MainScreen {
                 let vsync = state.c64.run_frame();
                 if !vsync {
                     self.halt(state)?;
+                } else if !state.options.warp_mode {
+                    self.sync_audio(ctx, state);
                 }
                 Ok(Transition::None)
             }
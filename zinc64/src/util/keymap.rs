@@ -5,63 +5,193 @@
 use glutin::event::{ModifiersState, VirtualKeyCode};
 use zinc64_core::device::keyboard::{Key, KeyEvent};
 
-pub struct KeyMap;
+// Design:
+//   Host keys are translated in two steps. The host layout turns a virtual key code into
+//   either the character printed on the key or the position of the key on a US keyboard,
+//   as virtual key codes name letters after the layout but punctuation after the US key
+//   in the same position. Symbolic mapping then presses the C64 key that produces the same
+//   character, pressing or releasing shift as needed, while positional mapping presses the
+//   C64 key in the same position and leaves shift to the user. Keys that do not produce a
+//   character are mapped the same way in both modes.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    Us,
+    Uk,
+    De,
+}
+
+impl Layout {
+    /// Characters produced by the key in US position `position`, unshifted and shifted.
+    fn get_chars(self, position: VirtualKeyCode) -> Option<(char, char)> {
+        match (self, position) {
+            (_, VirtualKeyCode::Key1) => Some(('1', '!')),
+            (_, VirtualKeyCode::Key4) => Some(('4', '$')),
+            (_, VirtualKeyCode::Key5) => Some(('5', '%')),
+            // US
+            (Layout::Us, VirtualKeyCode::Grave) => Some(('`', '~')),
+            (Layout::Us, VirtualKeyCode::Key2) => Some(('2', '@')),
+            (Layout::Us, VirtualKeyCode::Key3) => Some(('3', '#')),
+            (Layout::Us, VirtualKeyCode::Apostrophe) => Some(('\'', '"')),
+            (Layout::Us, VirtualKeyCode::Backslash) => Some(('\\', '|')),
+            // UK
+            (Layout::Uk, VirtualKeyCode::Grave) => Some(('`', '¬')),
+            (Layout::Uk, VirtualKeyCode::Key2) => Some(('2', '"')),
+            (Layout::Uk, VirtualKeyCode::Key3) => Some(('3', '£')),
+            (Layout::Uk, VirtualKeyCode::Apostrophe) => Some(('\'', '@')),
+            (Layout::Uk, VirtualKeyCode::Backslash) => Some(('#', '~')),
+            (Layout::Uk, VirtualKeyCode::OEM102) => Some(('\\', '|')),
+            (Layout::Us, _) | (Layout::Uk, _) => match position {
+                VirtualKeyCode::Key6 => Some(('6', '^')),
+                VirtualKeyCode::Key7 => Some(('7', '&')),
+                VirtualKeyCode::Key8 => Some(('8', '*')),
+                VirtualKeyCode::Key9 => Some(('9', '(')),
+                VirtualKeyCode::Key0 => Some(('0', ')')),
+                VirtualKeyCode::Minus => Some(('-', '_')),
+                VirtualKeyCode::Equals => Some(('=', '+')),
+                VirtualKeyCode::LBracket => Some(('[', '{')),
+                VirtualKeyCode::RBracket => Some((']', '}')),
+                VirtualKeyCode::Semicolon => Some((';', ':')),
+                VirtualKeyCode::Comma => Some((',', '<')),
+                VirtualKeyCode::Period => Some(('.', '>')),
+                VirtualKeyCode::Slash => Some(('/', '?')),
+                _ => None,
+            },
+            // DE
+            (Layout::De, VirtualKeyCode::Grave) => Some(('^', '°')),
+            (Layout::De, VirtualKeyCode::Key2) => Some(('2', '"')),
+            (Layout::De, VirtualKeyCode::Key3) => Some(('3', '§')),
+            (Layout::De, VirtualKeyCode::Key6) => Some(('6', '&')),
+            (Layout::De, VirtualKeyCode::Key7) => Some(('7', '/')),
+            (Layout::De, VirtualKeyCode::Key8) => Some(('8', '(')),
+            (Layout::De, VirtualKeyCode::Key9) => Some(('9', ')')),
+            (Layout::De, VirtualKeyCode::Key0) => Some(('0', '=')),
+            (Layout::De, VirtualKeyCode::Minus) => Some(('ß', '?')),
+            (Layout::De, VirtualKeyCode::Equals) => Some(('´', '`')),
+            (Layout::De, VirtualKeyCode::LBracket) => Some(('ü', 'Ü')),
+            (Layout::De, VirtualKeyCode::RBracket) => Some(('+', '*')),
+            (Layout::De, VirtualKeyCode::Backslash) => Some(('#', '\'')),
+            (Layout::De, VirtualKeyCode::Semicolon) => Some(('ö', 'Ö')),
+            (Layout::De, VirtualKeyCode::Apostrophe) => Some(('ä', 'Ä')),
+            (Layout::De, VirtualKeyCode::Comma) => Some((',', ';')),
+            (Layout::De, VirtualKeyCode::Period) => Some(('.', ':')),
+            (Layout::De, VirtualKeyCode::Slash) => Some(('-', '_')),
+            (Layout::De, VirtualKeyCode::OEM102) => Some(('<', '>')),
+            (Layout::De, _) => None,
+        }
+    }
+
+    /// Position of the key on a US keyboard.
+    fn get_position(self, virtual_code: VirtualKeyCode) -> VirtualKeyCode {
+        match (self, virtual_code) {
+            (Layout::De, VirtualKeyCode::Y) => VirtualKeyCode::Z,
+            (Layout::De, VirtualKeyCode::Z) => VirtualKeyCode::Y,
+            _ => virtual_code,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    Symbolic,
+    Positional,
+}
+
+pub struct KeyMap {
+    layout: Layout,
+    mapping: Mapping,
+}
 
 impl KeyMap {
-    pub fn map_key(virtual_code: VirtualKeyCode, modifiers: ModifiersState) -> Option<KeyEvent> {
-        match virtual_code {
-            // Numerical
-            VirtualKeyCode::Key0 if modifiers.shift() => Some(KeyEvent::new(Key::Num9)),
-            VirtualKeyCode::Key2 if modifiers.shift() => {
-                Some(KeyEvent::with_disabled_shift(Key::At))
-            }
-            VirtualKeyCode::Key4 if modifiers.shift() => {
-                Some(KeyEvent::with_disabled_shift(Key::Dollar))
+    pub fn new(layout: Layout, mapping: Mapping) -> Self {
+        Self { layout, mapping }
+    }
+
+    pub fn map_key(
+        &self,
+        virtual_code: VirtualKeyCode,
+        modifiers: ModifiersState,
+    ) -> Option<KeyEvent> {
+        if let Some(key_event) = Self::map_control_key(virtual_code) {
+            return Some(key_event);
+        }
+        match self.mapping {
+            Mapping::Symbolic => self.map_symbolic(virtual_code, modifiers.shift()),
+            Mapping::Positional => {
+                Self::map_position(self.layout.get_position(virtual_code)).map(KeyEvent::new)
             }
-            VirtualKeyCode::Key6 if modifiers.shift() => Some(KeyEvent::new(Key::Num7)),
-            VirtualKeyCode::Key7 if modifiers.shift() => Some(KeyEvent::new(Key::Num6)),
-            VirtualKeyCode::Key8 if modifiers.shift() => {
-                Some(KeyEvent::with_disabled_shift(Key::Asterisk))
+        }
+    }
+
+    fn map_symbolic(&self, virtual_code: VirtualKeyCode, shift: bool) -> Option<KeyEvent> {
+        let c = match Self::map_letter(virtual_code) {
+            Some(key) => return Some(KeyEvent::new(key)),
+            None => {
+                let (normal, shifted) = self.layout.get_chars(virtual_code)?;
+                if shift {
+                    shifted
+                } else {
+                    normal
+                }
             }
-            VirtualKeyCode::Key9 if modifiers.shift() => Some(KeyEvent::new(Key::Num8)),
-            VirtualKeyCode::Key0 => Some(KeyEvent::new(Key::Num0)),
-            VirtualKeyCode::Key1 => Some(KeyEvent::new(Key::Num1)),
-            VirtualKeyCode::Key2 => Some(KeyEvent::new(Key::Num2)),
-            VirtualKeyCode::Key3 => Some(KeyEvent::new(Key::Num3)),
-            VirtualKeyCode::Key4 => Some(KeyEvent::new(Key::Num4)),
-            VirtualKeyCode::Key5 => Some(KeyEvent::new(Key::Num5)),
-            VirtualKeyCode::Key6 => Some(KeyEvent::new(Key::Num6)),
-            VirtualKeyCode::Key7 => Some(KeyEvent::new(Key::Num7)),
-            VirtualKeyCode::Key8 => Some(KeyEvent::new(Key::Num8)),
-            VirtualKeyCode::Key9 => Some(KeyEvent::new(Key::Num9)),
-            // Alpha
-            VirtualKeyCode::A => Some(KeyEvent::new(Key::A)),
-            VirtualKeyCode::B => Some(KeyEvent::new(Key::B)),
-            VirtualKeyCode::C => Some(KeyEvent::new(Key::C)),
-            VirtualKeyCode::D => Some(KeyEvent::new(Key::D)),
-            VirtualKeyCode::E => Some(KeyEvent::new(Key::E)),
-            VirtualKeyCode::F => Some(KeyEvent::new(Key::F)),
-            VirtualKeyCode::G => Some(KeyEvent::new(Key::G)),
-            VirtualKeyCode::H => Some(KeyEvent::new(Key::H)),
-            VirtualKeyCode::I => Some(KeyEvent::new(Key::I)),
-            VirtualKeyCode::J => Some(KeyEvent::new(Key::J)),
-            VirtualKeyCode::K => Some(KeyEvent::new(Key::K)),
-            VirtualKeyCode::L => Some(KeyEvent::new(Key::L)),
-            VirtualKeyCode::M => Some(KeyEvent::new(Key::M)),
-            VirtualKeyCode::N => Some(KeyEvent::new(Key::N)),
-            VirtualKeyCode::O => Some(KeyEvent::new(Key::O)),
-            VirtualKeyCode::P => Some(KeyEvent::new(Key::P)),
-            VirtualKeyCode::Q => Some(KeyEvent::new(Key::Q)),
-            VirtualKeyCode::R => Some(KeyEvent::new(Key::R)),
-            VirtualKeyCode::S => Some(KeyEvent::new(Key::S)),
-            VirtualKeyCode::T => Some(KeyEvent::new(Key::T)),
-            VirtualKeyCode::U => Some(KeyEvent::new(Key::U)),
-            VirtualKeyCode::V => Some(KeyEvent::new(Key::V)),
-            VirtualKeyCode::W => Some(KeyEvent::new(Key::W)),
-            VirtualKeyCode::X => Some(KeyEvent::new(Key::X)),
-            VirtualKeyCode::Y => Some(KeyEvent::new(Key::Y)),
-            VirtualKeyCode::Z => Some(KeyEvent::new(Key::Z)),
-            // Control
+        };
+        let (key, c64_shift) = Self::map_char(c)?;
+        match (shift, c64_shift) {
+            (false, true) => Some(KeyEvent::with_mod(key, Key::LShift)),
+            (true, false) => Some(KeyEvent::with_disabled_shift(key)),
+            _ => Some(KeyEvent::new(key)),
+        }
+    }
+
+    // -- Mapping Tables
+
+    /// C64 key producing `c` and whether it has to be shifted.
+    fn map_char(c: char) -> Option<(Key, bool)> {
+        match c {
+            '0' => Some((Key::Num0, false)),
+            '1' => Some((Key::Num1, false)),
+            '2' => Some((Key::Num2, false)),
+            '3' => Some((Key::Num3, false)),
+            '4' => Some((Key::Num4, false)),
+            '5' => Some((Key::Num5, false)),
+            '6' => Some((Key::Num6, false)),
+            '7' => Some((Key::Num7, false)),
+            '8' => Some((Key::Num8, false)),
+            '9' => Some((Key::Num9, false)),
+            '!' => Some((Key::Num1, true)),
+            '"' => Some((Key::Num2, true)),
+            '#' => Some((Key::Num3, true)),
+            '$' => Some((Key::Num4, true)),
+            '%' => Some((Key::Num5, true)),
+            '&' => Some((Key::Num6, true)),
+            '\'' => Some((Key::Num7, true)),
+            '(' => Some((Key::Num8, true)),
+            ')' => Some((Key::Num9, true)),
+            '*' => Some((Key::Asterisk, false)),
+            '+' => Some((Key::Plus, false)),
+            ',' => Some((Key::Comma, false)),
+            '-' => Some((Key::Minus, false)),
+            '.' => Some((Key::Period, false)),
+            '/' => Some((Key::Slash, false)),
+            ':' => Some((Key::Colon, false)),
+            ';' => Some((Key::Semicolon, false)),
+            '<' => Some((Key::Comma, true)),
+            '=' => Some((Key::Equals, false)),
+            '>' => Some((Key::Period, true)),
+            '?' => Some((Key::Slash, true)),
+            '@' => Some((Key::At, false)),
+            '[' => Some((Key::Colon, true)),
+            ']' => Some((Key::Semicolon, true)),
+            '^' => Some((Key::Caret, false)),
+            '_' => Some((Key::Left, false)),
+            '£' | '\\' => Some((Key::Dollar, false)),
+            _ => None,
+        }
+    }
+
+    /// Keys that do not produce a character.
+    fn map_control_key(virtual_code: VirtualKeyCode) -> Option<KeyEvent> {
+        match virtual_code {
             VirtualKeyCode::Back => Some(KeyEvent::new(Key::Backspace)),
             VirtualKeyCode::Down => Some(KeyEvent::new(Key::CrsrDown)),
             VirtualKeyCode::Home => Some(KeyEvent::new(Key::Home)),
@@ -73,36 +203,84 @@ impl KeyMap {
             VirtualKeyCode::Return => Some(KeyEvent::new(Key::Return)),
             VirtualKeyCode::Right => Some(KeyEvent::new(Key::CrsrRight)),
             VirtualKeyCode::RShift => Some(KeyEvent::new(Key::RShift)),
+            VirtualKeyCode::Space => Some(KeyEvent::new(Key::Space)),
             VirtualKeyCode::Tab => Some(KeyEvent::new(Key::RunStop)),
             VirtualKeyCode::Up => Some(KeyEvent::with_mod(Key::CrsrDown, Key::LShift)),
             // Function
             VirtualKeyCode::F1 => Some(KeyEvent::new(Key::F1)),
+            VirtualKeyCode::F2 => Some(KeyEvent::with_mod(Key::F1, Key::LShift)),
             VirtualKeyCode::F3 => Some(KeyEvent::new(Key::F3)),
+            VirtualKeyCode::F4 => Some(KeyEvent::with_mod(Key::F3, Key::LShift)),
             VirtualKeyCode::F5 => Some(KeyEvent::new(Key::F5)),
+            VirtualKeyCode::F6 => Some(KeyEvent::with_mod(Key::F5, Key::LShift)),
             VirtualKeyCode::F7 => Some(KeyEvent::new(Key::F7)),
-            // Symbols
-            VirtualKeyCode::At => Some(KeyEvent::new(Key::At)),
-            VirtualKeyCode::Backslash if modifiers.shift() => {
-                Some(KeyEvent::with_mod(Key::Minus, Key::LShift))
-            }
-            VirtualKeyCode::Caret => Some(KeyEvent::new(Key::Caret)),
-            VirtualKeyCode::Comma => Some(KeyEvent::new(Key::Comma)),
-            VirtualKeyCode::Equals if modifiers.shift() => {
-                Some(KeyEvent::with_disabled_shift(Key::Plus))
-            }
-            VirtualKeyCode::Equals => Some(KeyEvent::new(Key::Equals)),
-            VirtualKeyCode::LBracket => Some(KeyEvent::with_mod(Key::Colon, Key::LShift)),
-            VirtualKeyCode::Minus => Some(KeyEvent::new(Key::Minus)),
-            VirtualKeyCode::Period => Some(KeyEvent::new(Key::Period)),
-            VirtualKeyCode::RBracket => Some(KeyEvent::with_mod(Key::Semicolon, Key::LShift)),
-            VirtualKeyCode::Semicolon if modifiers.shift() => {
-                Some(KeyEvent::with_disabled_shift(Key::Colon))
-            }
-            VirtualKeyCode::Semicolon => Some(KeyEvent::new(Key::Semicolon)),
-            VirtualKeyCode::Slash => Some(KeyEvent::new(Key::Slash)),
-            VirtualKeyCode::Space => Some(KeyEvent::new(Key::Space)),
-            // VirtualKeyCode::Quote if modifiers.shift() => Some(KeyEvent::new(Key::Num2))
-            // VirtualKeyCode::Quote => Some(KeyEvent::with_mod(Key::Num7, Key::LShift)),
+            VirtualKeyCode::F8 => Some(KeyEvent::with_mod(Key::F7, Key::LShift)),
+            _ => None,
+        }
+    }
+
+    fn map_letter(virtual_code: VirtualKeyCode) -> Option<Key> {
+        match virtual_code {
+            VirtualKeyCode::A => Some(Key::A),
+            VirtualKeyCode::B => Some(Key::B),
+            VirtualKeyCode::C => Some(Key::C),
+            VirtualKeyCode::D => Some(Key::D),
+            VirtualKeyCode::E => Some(Key::E),
+            VirtualKeyCode::F => Some(Key::F),
+            VirtualKeyCode::G => Some(Key::G),
+            VirtualKeyCode::H => Some(Key::H),
+            VirtualKeyCode::I => Some(Key::I),
+            VirtualKeyCode::J => Some(Key::J),
+            VirtualKeyCode::K => Some(Key::K),
+            VirtualKeyCode::L => Some(Key::L),
+            VirtualKeyCode::M => Some(Key::M),
+            VirtualKeyCode::N => Some(Key::N),
+            VirtualKeyCode::O => Some(Key::O),
+            VirtualKeyCode::P => Some(Key::P),
+            VirtualKeyCode::Q => Some(Key::Q),
+            VirtualKeyCode::R => Some(Key::R),
+            VirtualKeyCode::S => Some(Key::S),
+            VirtualKeyCode::T => Some(Key::T),
+            VirtualKeyCode::U => Some(Key::U),
+            VirtualKeyCode::V => Some(Key::V),
+            VirtualKeyCode::W => Some(Key::W),
+            VirtualKeyCode::X => Some(Key::X),
+            VirtualKeyCode::Y => Some(Key::Y),
+            VirtualKeyCode::Z => Some(Key::Z),
+            _ => None,
+        }
+    }
+
+    /// C64 key in US key position `position`. The pound and equals keys have no
+    /// counterpart on a US keyboard and are mapped to Insert and End.
+    fn map_position(position: VirtualKeyCode) -> Option<Key> {
+        if let Some(key) = Self::map_letter(position) {
+            return Some(key);
+        }
+        match position {
+            VirtualKeyCode::Grave => Some(Key::Left),
+            VirtualKeyCode::Key1 => Some(Key::Num1),
+            VirtualKeyCode::Key2 => Some(Key::Num2),
+            VirtualKeyCode::Key3 => Some(Key::Num3),
+            VirtualKeyCode::Key4 => Some(Key::Num4),
+            VirtualKeyCode::Key5 => Some(Key::Num5),
+            VirtualKeyCode::Key6 => Some(Key::Num6),
+            VirtualKeyCode::Key7 => Some(Key::Num7),
+            VirtualKeyCode::Key8 => Some(Key::Num8),
+            VirtualKeyCode::Key9 => Some(Key::Num9),
+            VirtualKeyCode::Key0 => Some(Key::Num0),
+            VirtualKeyCode::Minus => Some(Key::Plus),
+            VirtualKeyCode::Equals => Some(Key::Minus),
+            VirtualKeyCode::Insert => Some(Key::Dollar),
+            VirtualKeyCode::LBracket => Some(Key::At),
+            VirtualKeyCode::RBracket => Some(Key::Asterisk),
+            VirtualKeyCode::Backslash => Some(Key::Caret),
+            VirtualKeyCode::Semicolon => Some(Key::Colon),
+            VirtualKeyCode::Apostrophe => Some(Key::Semicolon),
+            VirtualKeyCode::End => Some(Key::Equals),
+            VirtualKeyCode::Comma => Some(Key::Comma),
+            VirtualKeyCode::Period => Some(Key::Period),
+            VirtualKeyCode::Slash => Some(Key::Slash),
             _ => None,
         }
     }
@@ -221,3 +399,90 @@ pub fn to_ascii2(virtual_code: VirtualKeyCode, modifiers: ModifiersState) -> cha
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zinc64_core::device::Keyboard;
+    use zinc64_core::util::new_shared;
+
+    fn setup_keyboard() -> Keyboard {
+        let mut keyboard = Keyboard::new(new_shared([0; 16]));
+        keyboard.reset();
+        keyboard
+    }
+
+    fn is_pressed(keyboard: &Keyboard, key: Key) -> bool {
+        let (row, col) = Keyboard::map_keycode(key);
+        keyboard.get_row(row as u8) & (1 << col) == 0
+    }
+
+    fn press(keyboard: &mut Keyboard, keymap: &KeyMap, code: VirtualKeyCode, shift: bool) {
+        let modifiers = if shift {
+            ModifiersState::SHIFT
+        } else {
+            ModifiersState::empty()
+        };
+        keyboard.on_key_down(keymap.map_key(code, modifiers).unwrap());
+    }
+
+    #[test]
+    fn symbolic_releases_shift() {
+        let keymap = KeyMap::new(Layout::Us, Mapping::Symbolic);
+        let mut keyboard = setup_keyboard();
+        press(&mut keyboard, &keymap, VirtualKeyCode::LShift, true);
+        press(&mut keyboard, &keymap, VirtualKeyCode::Key2, true);
+        assert!(is_pressed(&keyboard, Key::At));
+        assert!(!is_pressed(&keyboard, Key::Num2));
+        assert!(!is_pressed(&keyboard, Key::LShift));
+    }
+
+    #[test]
+    fn symbolic_synthesizes_shift() {
+        let keymap = KeyMap::new(Layout::Us, Mapping::Symbolic);
+        let mut keyboard = setup_keyboard();
+        press(&mut keyboard, &keymap, VirtualKeyCode::Apostrophe, false);
+        assert!(is_pressed(&keyboard, Key::Num7));
+        assert!(is_pressed(&keyboard, Key::LShift));
+    }
+
+    #[test]
+    fn symbolic_layouts() {
+        let keymap = KeyMap::new(Layout::De, Mapping::Symbolic);
+        let mut keyboard = setup_keyboard();
+        press(&mut keyboard, &keymap, VirtualKeyCode::LShift, true);
+        press(&mut keyboard, &keymap, VirtualKeyCode::RBracket, true);
+        assert!(is_pressed(&keyboard, Key::Asterisk));
+        assert!(!is_pressed(&keyboard, Key::LShift));
+        let keymap = KeyMap::new(Layout::Uk, Mapping::Symbolic);
+        let mut keyboard = setup_keyboard();
+        press(&mut keyboard, &keymap, VirtualKeyCode::LShift, true);
+        press(&mut keyboard, &keymap, VirtualKeyCode::Key3, true);
+        assert!(is_pressed(&keyboard, Key::Dollar));
+        assert!(!is_pressed(&keyboard, Key::Num3));
+        assert!(!is_pressed(&keyboard, Key::LShift));
+    }
+
+    #[test]
+    fn positional_keeps_shift() {
+        let keymap = KeyMap::new(Layout::Us, Mapping::Positional);
+        let mut keyboard = setup_keyboard();
+        press(&mut keyboard, &keymap, VirtualKeyCode::LShift, true);
+        press(&mut keyboard, &keymap, VirtualKeyCode::Minus, true);
+        assert!(is_pressed(&keyboard, Key::Plus));
+        assert!(is_pressed(&keyboard, Key::LShift));
+        press(&mut keyboard, &keymap, VirtualKeyCode::Key2, true);
+        assert!(is_pressed(&keyboard, Key::Num2));
+        assert!(!is_pressed(&keyboard, Key::At));
+    }
+
+    #[test]
+    fn positional_layouts() {
+        let keymap = KeyMap::new(Layout::De, Mapping::Positional);
+        let mut keyboard = setup_keyboard();
+        press(&mut keyboard, &keymap, VirtualKeyCode::Y, false);
+        assert!(is_pressed(&keyboard, Key::Z));
+        assert!(!is_pressed(&keyboard, Key::Y));
+        assert!(!is_pressed(&keyboard, Key::LShift));
+    }
+}
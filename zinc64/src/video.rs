@@ -17,9 +17,12 @@ use crate::app::AppState;
 use crate::framework::Context;
 use crate::gfx::{gl, sprite, Color, Rect, RectI};
 
+/// Double-buffered frame buffer. The VIC draws into the back buffer which is swapped
+/// to front once the frame is complete, so the renderer always presents whole frames.
 pub struct VideoBuffer {
     dim: (usize, usize),
     palette: [u32; 16],
+    front: Vec<u32>,
     pixels: Vec<u32>,
 }
 
@@ -28,16 +31,10 @@ impl VideoBuffer {
         VideoBuffer {
             dim: (width as usize, height as usize),
             palette,
+            front: vec![0; (width * height) as usize],
             pixels: vec![0; (width * height) as usize],
         }
     }
-
-    pub fn get_pixel_data(&self) -> &[u8] {
-        unsafe {
-            let len = self.pixels.len() * core::mem::size_of::<u32>();
-            core::slice::from_raw_parts(self.pixels.as_ptr() as *const u8, len)
-        }
-    }
 }
 
 impl VideoOutput for VideoBuffer {
@@ -49,6 +46,20 @@ impl VideoOutput for VideoBuffer {
         for pixel in self.pixels.iter_mut() {
             *pixel = 0x00;
         }
+        for pixel in self.front.iter_mut() {
+            *pixel = 0x00;
+        }
+    }
+
+    fn end_frame(&mut self) {
+        core::mem::swap(&mut self.front, &mut self.pixels);
+    }
+
+    fn get_pixel_data(&self) -> &[u8] {
+        unsafe {
+            let len = self.front.len() * core::mem::size_of::<u32>();
+            core::slice::from_raw_parts(self.front.as_ptr() as *const u8, len)
+        }
     }
 
     fn write(&mut self, index: usize, color: u8) {